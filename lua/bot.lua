@@ -2,6 +2,10 @@ bot = bot or {}
 bot.cmds = bot.cmds or {}
 bot.aliases = bot.aliases or {}
 bot.reaction_hooks = {}
+bot.component_hooks = {}
+
+-- Seconds before buttons and select menus stop being handled
+local COMPONENT_HOOK_TIMEOUT = 15 * 60
 
 include("./lib/async.lua")
 include("./lib/hooks.lua")
@@ -13,6 +17,7 @@ include("./lib/string.lua")
 include("./lib/table.lua")
 include("./lib/tags.lua")
 include("./lib/time.lua")
+include("./lib/websocket.lua")
 
 bot.cache = bot.cache or {
     messages = {},
@@ -217,13 +222,17 @@ function bot.parse_args(cmd, args)
         return str:sub(1, #start) == start
     end
 
+    local function opt_display_name(opt)
+        return opt.long and "--" .. opt.long or "-" .. opt.short
+    end
+
     for _, arg in ipairs(args) do
         if starts_with(arg, "--") then
             local opt_name = string.sub(arg, 3)
             local opt = cmd._long_options[opt_name]
 
             if taking_opt_value then
-                return false, 'expected value for "--' .. taking_opt_value .. '", not "--' .. opt_name .. '"'
+                return false, 'expected value for "' .. opt_display_name(taking_opt_value) .. '", not "--' .. opt_name .. '"'
             end
 
             if not opt then
@@ -231,7 +240,7 @@ function bot.parse_args(cmd, args)
             end
 
             if opt.takes_value then
-                taking_opt_value = opt_name
+                taking_opt_value = opt
             else
                 out[opt.key] = true
             end
@@ -240,20 +249,25 @@ function bot.parse_args(cmd, args)
             local opt = cmd._short_options[opt_name]
 
             if taking_opt_value then
-                return false, 'expected value for "--' .. taking_opt_value .. '", not "-' .. opt_name '"'
+                return false, 'expected value for "' .. opt_display_name(taking_opt_value) .. '", not "-' .. opt_name .. '"'
             end
 
             if not opt then
                 return false, 'unknown option "-' .. opt_name .. '"'
             end
 
-            local value = string.sub(arg, 2)
+            local value = string.sub(arg, 3)
 
-            out[arg.key] = value ~= "" and value or true
+            if value ~= "" then
+                out[opt.key] = value
+            elseif opt.takes_value then
+                taking_opt_value = opt
+            else
+                out[opt.key] = true
+            end
         else
             if taking_opt_value then
-                local opt = cmd._long_options[taking_opt_value]
-                out[arg.key] = arg
+                out[taking_opt_value.key] = arg
                 taking_opt_value = nil
             else
                 local argument = cmd._arguments[arg_index]
@@ -268,6 +282,10 @@ function bot.parse_args(cmd, args)
         end
     end
 
+    if taking_opt_value then
+        return false, 'expected value for "' .. opt_display_name(taking_opt_value) .. '"'
+    end
+
     for _, arg in ipairs(cmd._arguments) do
         if arg.required then
             if not out[arg.key] then
@@ -298,7 +316,43 @@ function bot.has_role_or_higher(role, user_role, only_higher)
     return user_role_idx > role_idx
 end
 
-local function exec_command(msg, cmd, args)
+-- Commands can require a permission node like "owner", "admin" or "tags.moderate". Admin
+-- commands can also be granted to other users with their "command.NAME" node
+function bot.has_command_permission(cmd, caller)
+    if not cmd.permission then
+        return true
+    end
+
+    if caller:has_permission(cmd.permission):await() then
+        return true
+    end
+
+    local node = "command." .. get_abs_cmd(cmd):gsub(" ", ".")
+
+    return cmd.permission == "admin" and caller:has_permission(node):await()
+end
+
+-- Arguments with the type "user" or "channel" are resolved from mentions, ids or names
+local function resolve_typed_args(msg, cmd, out)
+    for _, arg in ipairs(cmd.args or {}) do
+        local value = out[arg.key]
+        local resolve = (arg.type == "user" and bot.resolve_user) or (arg.type == "channel" and bot.resolve_channel)
+
+        if resolve and type(value) == "string" then
+            local succ, resolved = pcall(function() return resolve(value, msg.channel):await() end)
+
+            if not succ then
+                return false, 'no ' .. arg.type .. ' "' .. value .. '" was found for "' .. (arg.name or arg.key) .. '"'
+            end
+
+            out[arg.key] = resolved
+        end
+    end
+
+    return true
+end
+
+local function exec_command(msg, cmd, args, caller)
     local has_subcommands = #cmd.sub_commands > 0
 
     if not cmd then
@@ -311,6 +365,19 @@ local function exec_command(msg, cmd, args)
         end
     end
 
+    if not bot.has_command_permission(cmd, caller) then
+        if cmd.permission == "owner" or cmd.permission == "admin" then
+            return msg:reply("permission denied: this command can only be used by bot " .. cmd.permission .. "s."):await()
+        end
+
+        return msg:reply('permission denied: this command needs the "' .. cmd.permission .. '" permission.'):await()
+    end
+
+    -- Permissions the caller needs in the channel on top of the bot role, like "manage_messages"
+    if cmd.service_permission and not msg:author_permissions():await()[cmd.service_permission] then
+        return msg:reply("permission denied: this command requires the " .. cmd.service_permission .. " permission in this channel."):await()
+    end
+
     if has_subcommands then
         local cmd_name = args[1]
         local args = {table.unpack(args, 2, #args)}
@@ -319,7 +386,7 @@ local function exec_command(msg, cmd, args)
             local sub_cmd = cmd._sub_commands[cmd_name]
 
             if sub_cmd then
-                return exec_command(msg, sub_cmd, args)
+                return exec_command(msg, sub_cmd, args, caller)
             end
         end
 
@@ -338,20 +405,51 @@ local function exec_command(msg, cmd, args)
         return msg:reply("argument error: " .. res .. '\nUse "' .. get_abs_cmd(cmd) .. ' --help" for more info.'):await()
     end
 
+    local resolved, err = resolve_typed_args(msg, cmd, res)
+
+    if not resolved then
+        return msg:reply("argument error: " .. err .. '\nUse "' .. get_abs_cmd(cmd) .. ' --help" for more info.'):await()
+    end
+
     return cmd.callback({
         msg = msg,
+        caller = caller,
         args = res,
-        extra_args = extra_args
+        extra_args = extra_args,
+        -- Format a unix timestamp in the invoking user's timezone
+        format_time = function(timestamp, format)
+            return time.format(timestamp, format, msg.author)
+        end,
     })
 end
 
-function bot.on_command(msg, args, edited)
+-- Returns false if no script is installed on the server with the name
+local function run_server_script(msg, name, args)
+    local installed, err, out = server_scripts.run(msg, name, args):await()
+
+    if not installed then
+        return false
+    end
+
+    if err then
+        return true, msg:reply(msg.channel:escape_text("error: " .. err)):await()
+    end
+
+    if out ~= "" then
+        return true, msg:reply(msg.channel:escape_text(out)):await()
+    end
+
+    return true
+end
+
+function bot.on_command(msg, args, edited, caller)
     local cmd_name = args[1]
     local args = {table.unpack(args, 2, #args)}
 
     local cmd = bot.cmds[cmd_name] or bot.aliases[cmd_name]
 
-    if not cmd then
+    -- Fall back to running a server script or tag with the same name
+    if not cmd and not (cmd_name and tags.is_valid_name(cmd_name)) then
         return
     end
 
@@ -371,7 +469,20 @@ function bot.on_command(msg, args, edited)
         end
     end
 
-    local reply = exec_command(msg, cmd, args)
+    local reply
+
+    if cmd then
+        reply = exec_command(msg, cmd, args, caller)
+    else
+        -- Scripts installed on the server take precedence over tags
+        local installed
+        installed, reply = run_server_script(msg, cmd_name, args)
+
+        if not installed then
+            reply = tags.exec_tag_command(msg, cmd_name, args)
+        end
+    end
+
     bot.add_command_history(msg, reply, count)
 end
 
@@ -420,6 +531,47 @@ function bot.on_reaction(msg, reactor, reaction, removed)
     end
 end
 
+-- Registered through msg:on_click(id, fn, timeout), fn is called with (msg, user, values, interaction)
+-- and can answer with interaction:modal({ title, fields }) for a couple of seconds
+function bot.add_component_hook(msg, id, fn, timeout)
+    local hooks = bot.component_hooks[msg.id] or {}
+    hooks[id] = { fn = fn, expires = os.time() + (timeout or COMPONENT_HOOK_TIMEOUT) }
+    bot.component_hooks[msg.id] = hooks
+end
+
+function bot.on_component(msg, user, id, values, interaction)
+    local now = os.time()
+
+    for msg_id, hooks in pairs(bot.component_hooks) do
+        for hook_id, hook in pairs(hooks) do
+            if hook.expires < now then
+                hooks[hook_id] = nil
+            end
+        end
+
+        if next(hooks) == nil then
+            bot.component_hooks[msg_id] = nil
+        end
+    end
+
+    local hook = bot.component_hooks[msg.id] and bot.component_hooks[msg.id][id]
+    if hook then
+        hook.fn(msg, user, values, interaction)
+    end
+end
+
+-- Moderation and configuration changes reported by a service, including ones made outside the bot
+function bot.on_audit(event)
+    hooks.call("audit", event)
+end
+
+-- Called by the github module, resolves to false if a hook wants the event dropped
+function bot.on_github_event(event, payload)
+    return async.future(function(resolve)
+        resolve(hooks.call("github_event", event, payload) ~= true)
+    end)()
+end
+
 function bot.on_loaded()
     hooks.call("loaded")
 end
@@ -428,5 +580,16 @@ function bot.shutdown()
     hooks.call("shutdown")
 end
 
+-- Called before an exec restart, handoff hooks store what they want resumed in the table
+function bot.on_handoff()
+    local state = {}
+    hooks.call("handoff", state)
+    return state
+end
+
+function bot.on_resume(state)
+    hooks.call("resume", state)
+end
+
 include("bot/commands/**/*.lua")
 include("bot/modules/**/*.lua")
@@ -0,0 +1,76 @@
+bot.add_command("audit", {
+    description = "Show the log of privileged actions",
+    args = {
+        {
+            key = "action",
+            long = "action",
+            short = "a",
+            takes_value = true,
+            description = "Only show actions of this kind, e.g. blacklist or setting.lua",
+        },
+        {
+            key = "user",
+            long = "user",
+            short = "u",
+            takes_value = true,
+            description = "Only show actions taken by this user",
+        },
+        {
+            key = "limit",
+            long = "limit",
+            short = "n",
+            takes_value = true,
+            description = "Number of entries to show, defaults to 10",
+        },
+    },
+    callback = function(ctx)
+        local actor
+
+        if ctx.args.user then
+            local user = bot.find_user(ctx.msg.channel, ctx.args.user):await()
+
+            if not user then
+                return ctx.msg:reply("error: no user was found"):await()
+            end
+
+            actor = user.uid
+        end
+
+        local entries = bot.audit_log({
+            action = ctx.args.action,
+            actor = actor,
+            limit = tonumber(ctx.args.limit),
+        }):await()
+
+        if #entries == 0 then
+            return ctx.msg:reply("no audit entries were found"):await()
+        end
+
+        local names = {}
+        local lines = {}
+
+        for _, entry in ipairs(entries) do
+            local actor_name = "bot"
+
+            if entry.actor then
+                if not names[entry.actor] then
+                    local succ, user = pcall(function() return bot.get_user(entry.actor):await() end)
+                    names[entry.actor] = succ and user.name or ("uid " .. entry.actor)
+                end
+
+                actor_name = names[entry.actor]
+            end
+
+            local line = entry.date .. "  " .. actor_name .. "  " .. entry.action .. "  " .. entry.scope
+
+            if entry.before or entry.after then
+                line = line .. "  " .. (entry.before or "-") .. " -> " .. (entry.after or "-")
+            end
+
+            table.insert(lines, line)
+        end
+
+        return ctx.msg:reply(bot.code_block(ctx.msg.channel, table.concat(lines, "\n"))):await()
+    end,
+    permission = "admin",
+})
@@ -0,0 +1,180 @@
+local function blacklist_args(extra)
+    local args = {
+        {
+            key = "user",
+            name = "USER",
+            description = "User to blacklist",
+            required = true,
+        },
+        {
+            key = "global",
+            long = "global",
+            short = "g",
+            description = "Apply to every server, requires root",
+        },
+    }
+
+    for _, arg in ipairs(extra or {}) do
+        table.insert(args, arg)
+    end
+
+    return args
+end
+
+-- Returns the server the entry applies to, or nil for global entries
+local function blacklist_server(ctx)
+    if ctx.args.global then
+        if not bot.has_role_or_higher("root", ctx.msg.author.role) then
+            return false, "error: only root can manage the global blacklist"
+        end
+
+        return true, nil
+    end
+
+    return true, ctx.msg.channel.server
+end
+
+local function format_duration(secs)
+    if secs >= 60 * 60 * 24 then
+        return math.floor(secs / (60 * 60 * 24)) .. "d"
+    elseif secs >= 60 * 60 then
+        return math.floor(secs / (60 * 60)) .. "h"
+    elseif secs >= 60 then
+        return math.floor(secs / 60) .. "m"
+    end
+
+    return secs .. "s"
+end
+
+bot.add_command("blacklist", {
+    description = "Make the bot ignore a user",
+    sub_commands = {
+        bot.sub_command("add", {
+            description = "Add a user to the blacklist",
+            args = blacklist_args({
+                {
+                    key = "duration",
+                    long = "duration",
+                    short = "d",
+                    takes_value = true,
+                    description = "How long the user is blacklisted for, permanent if not set",
+                },
+                {
+                    key = "reason",
+                    long = "reason",
+                    short = "r",
+                    takes_value = true,
+                    description = "Reason for the blacklist",
+                },
+            }),
+            callback = function(ctx)
+                local ok, server = blacklist_server(ctx)
+
+                if not ok then
+                    return ctx.msg:reply(server):await()
+                end
+
+                local user = bot.find_user(ctx.msg.channel, ctx.args.user):await()
+
+                if not user then
+                    return ctx.msg:reply("error: no user was found"):await()
+                end
+
+                if ctx.msg.author.uid == user.uid then
+                    return ctx.msg:reply("error: cannot blacklist yourself"):await()
+                end
+
+                if not bot.has_role_or_higher(user.role, ctx.msg.author.role, true) then
+                    return ctx.msg:reply("error: cannot blacklist someone with a higher role"):await()
+                end
+
+                local duration
+
+                if ctx.args.duration then
+                    duration = time.parse_duration(ctx.args.duration)
+
+                    if duration <= 0 then
+                        return ctx.msg:reply("error: invalid duration"):await()
+                    end
+                end
+
+                bot.blacklist_user(user, server, ctx.msg.author, ctx.args.reason, duration):await()
+
+                local text = "blacklisted " .. user.name
+
+                if duration then
+                    text = text .. " for " .. format_duration(duration)
+                end
+
+                return ctx.msg:reply(ctx.msg.channel:escape_text(text)):await()
+            end,
+        }),
+        bot.sub_command("remove", {
+            description = "Remove a user from the blacklist",
+            args = blacklist_args(),
+            callback = function(ctx)
+                local ok, server = blacklist_server(ctx)
+
+                if not ok then
+                    return ctx.msg:reply(server):await()
+                end
+
+                local user = bot.find_user(ctx.msg.channel, ctx.args.user):await()
+
+                if not user then
+                    return ctx.msg:reply("error: no user was found"):await()
+                end
+
+                if not bot.unblacklist_user(user, server, ctx.msg.author):await() then
+                    return ctx.msg:reply(ctx.msg.channel:escape_text(user.name .. " is not blacklisted")):await()
+                end
+
+                return ctx.msg:reply(ctx.msg.channel:escape_text("unblacklisted " .. user.name)):await()
+            end,
+        }),
+        bot.sub_command("list", {
+            description = "List the blacklisted users",
+            args = {
+                {
+                    key = "global",
+                    long = "global",
+                    short = "g",
+                    description = "List the global blacklist",
+                },
+            },
+            callback = function(ctx)
+                local server = ctx.msg.channel.server
+
+                if ctx.args.global then
+                    server = nil
+                end
+
+                local entries = bot.list_blacklist(server):await()
+
+                if #entries == 0 then
+                    return ctx.msg:reply("the blacklist is empty"):await()
+                end
+
+                local lines = {}
+
+                for _, entry in ipairs(entries) do
+                    local succ, user = pcall(function() return bot.get_user(entry.uid):await() end)
+                    local line = succ and user.name or ("uid " .. entry.uid)
+
+                    if entry.expires_in then
+                        line = line .. "  (" .. format_duration(entry.expires_in) .. " left)"
+                    end
+
+                    if entry.reason then
+                        line = line .. "  " .. entry.reason
+                    end
+
+                    table.insert(lines, line)
+                end
+
+                return ctx.msg:reply(bot.code_block(ctx.msg.channel, table.concat(lines, "\n"))):await()
+            end,
+        }),
+    },
+    permission = "admin",
+})
@@ -0,0 +1,80 @@
+local function upload_args()
+    return {
+        {
+            key = "name",
+            name = "NAME",
+            description = "Name of the new emoji or sticker",
+            required = true,
+        },
+        {
+            key = "image",
+            name = "IMAGE",
+            description = "Url, emoji or user to take the image from, the attachment is used if not set",
+        },
+    }
+end
+
+local function resolve_image(ctx)
+    local succ, img = pcall(function()
+        return image.resolve(ctx.msg, ctx.args.image):await()
+    end)
+
+    if not succ or not img then
+        return nil, "error: no image was found"
+    end
+
+    return img
+end
+
+bot.add_command("emoji", {
+    description = "Upload custom emoji and stickers to the server",
+    permission = "admin",
+    sub_commands = {
+        bot.sub_command("add", {
+            description = "Upload a custom emoji",
+            args = upload_args(),
+            callback = function(ctx)
+                local img, err = resolve_image(ctx)
+
+                if not img then
+                    return ctx.msg:reply(err):await()
+                end
+
+                local succ, res = pcall(function()
+                    return bot.upload_emoji(ctx.msg.channel.server, ctx.args.name, img):await()
+                end)
+
+                if not succ then
+                    return ctx.msg:reply("error: " .. tostring(res)):await()
+                end
+
+                return ctx.msg:reply("added " .. res):await()
+            end,
+        }),
+        bot.sub_command("sticker", {
+            description = "Upload a sticker, the image must be a 320x320 png",
+            args = upload_args(),
+            callback = function(ctx)
+                local img, err = resolve_image(ctx)
+
+                if not img then
+                    return ctx.msg:reply(err):await()
+                end
+
+                local succ, res = pcall(function()
+                    return bot.upload_sticker(ctx.msg.channel.server, ctx.args.name, img, {
+                        description = #ctx.extra_args > 0 and table.concat(ctx.extra_args, " ") or nil,
+                    }):await()
+                end)
+
+                if not succ then
+                    return ctx.msg:reply("error: " .. tostring(res)):await()
+                end
+
+                return ctx.msg:reply("added the sticker " .. bot.icode_block(ctx.msg.channel, ctx.args.name), {
+                    stickers = { res },
+                }):await()
+            end,
+        }),
+    },
+})
@@ -0,0 +1,179 @@
+local function user_args(description, extra)
+    local args = {
+        {
+            key = "user",
+            name = "USER",
+            description = description,
+            type = "user",
+            required = true,
+        },
+    }
+
+    for _, arg in ipairs(extra or {}) do
+        table.insert(args, arg)
+    end
+
+    return args
+end
+
+local reason_arg = {
+    key = "reason",
+    long = "reason",
+    short = "r",
+    takes_value = true,
+    description = "Reason shown in the server audit log",
+}
+
+local function find_target(ctx)
+    local user = ctx.args.user
+
+    if not user then
+        return nil, "error: no user was found"
+    end
+
+    if ctx.msg.author.uid == user.uid then
+        return nil, "error: cannot moderate yourself"
+    end
+
+    return user
+end
+
+-- Returns nil when no duration was given, and false when it couldn't be parsed
+local function parse_duration(ctx)
+    if not ctx.args.duration then
+        return nil
+    end
+
+    local duration = math.floor(time.parse_duration(ctx.args.duration))
+
+    if duration <= 0 then
+        return false
+    end
+
+    return duration
+end
+
+bot.add_command("kick", {
+    description = "Kick a member from the server",
+    args = user_args("Member to kick", { reason_arg }),
+    callback = function(ctx)
+        local user, err = find_target(ctx)
+
+        if not user then
+            return ctx.msg:reply(err):await()
+        end
+
+        mod.kick(ctx.msg, user, ctx.args.reason):await()
+
+        return ctx.msg:reply(ctx.msg.channel:escape_text("kicked " .. user.name)):await()
+    end,
+    permission = "admin",
+})
+
+bot.add_command("ban", {
+    description = "Ban a user from the server",
+    args = user_args("User to ban", {
+        {
+            key = "duration",
+            long = "duration",
+            short = "d",
+            takes_value = true,
+            description = "How long the user is banned for, permanent if not set",
+        },
+        reason_arg,
+    }),
+    callback = function(ctx)
+        local user, err = find_target(ctx)
+
+        if not user then
+            return ctx.msg:reply(err):await()
+        end
+
+        local duration = parse_duration(ctx)
+
+        if duration == false then
+            return ctx.msg:reply("error: invalid duration"):await()
+        end
+
+        mod.ban(ctx.msg, user, ctx.args.reason, duration):await()
+
+        return ctx.msg:reply(ctx.msg.channel:escape_text("banned " .. user.name)):await()
+    end,
+    permission = "admin",
+})
+
+bot.add_command("unban", {
+    description = "Lift the ban of a user",
+    args = user_args("User to unban"),
+    callback = function(ctx)
+        local user, err = find_target(ctx)
+
+        if not user then
+            return ctx.msg:reply(err):await()
+        end
+
+        mod.unban(ctx.msg, user):await()
+
+        return ctx.msg:reply(ctx.msg.channel:escape_text("unbanned " .. user.name)):await()
+    end,
+    permission = "admin",
+})
+
+bot.add_command("timeout", {
+    description = "Stop a member from talking for a while",
+    args = user_args("Member to time out", {
+        {
+            key = "duration",
+            long = "duration",
+            short = "d",
+            takes_value = true,
+            description = "How long the timeout lasts, lifts the timeout if not set",
+        },
+        reason_arg,
+    }),
+    callback = function(ctx)
+        local user, err = find_target(ctx)
+
+        if not user then
+            return ctx.msg:reply(err):await()
+        end
+
+        local duration = parse_duration(ctx)
+
+        if duration == false then
+            return ctx.msg:reply("error: invalid duration"):await()
+        end
+
+        mod.timeout(ctx.msg, user, duration, ctx.args.reason):await()
+
+        if not duration then
+            return ctx.msg:reply(ctx.msg.channel:escape_text("lifted the timeout of " .. user.name)):await()
+        end
+
+        return ctx.msg:reply(ctx.msg.channel:escape_text("timed out " .. user.name)):await()
+    end,
+    permission = "admin",
+})
+
+bot.add_command("purge", {
+    description = "Delete the latest messages in this channel",
+    args = {
+        {
+            key = "count",
+            name = "COUNT",
+            description = "Number of latest messages to delete, including the command",
+            required = true,
+        },
+    },
+    callback = function(ctx)
+        local count = tonumber(ctx.args.count)
+
+        if not count or count < 1 then
+            return ctx.msg:reply("error: invalid message count"):await()
+        end
+
+        mod.purge(ctx.msg, ctx.msg.channel, math.floor(count)):await()
+    end,
+    permission = "admin",
+    service_permission = "manage_messages",
+})
@@ -0,0 +1,103 @@
+-- Bare message ids are taken to be on the same service as the command
+local function message_id(ctx, id)
+    if id:find(":") then
+        return id
+    end
+
+    return ctx.msg.id:match("^(%w+):") .. ":" .. id
+end
+
+local function reaction_role_args(extra)
+    local args = {
+        {
+            key = "message",
+            name = "MESSAGE",
+            description = "Id of the message to react to, in this channel",
+            required = true,
+        },
+        {
+            key = "emoji",
+            name = "EMOJI",
+            description = "Emoji members react with",
+            required = true,
+        },
+    }
+
+    for _, arg in ipairs(extra or {}) do
+        table.insert(args, arg)
+    end
+
+    return args
+end
+
+bot.add_command("reactionrole", {
+    description = "Give members a role when they react to a message",
+    sub_commands = {
+        bot.sub_command("add", {
+            description = "Give a role to members reacting with the emoji",
+            args = reaction_role_args({
+                {
+                    key = "role",
+                    name = "ROLE",
+                    description = "Role name, id or mention",
+                    required = true,
+                },
+                {
+                    key = "exclusive",
+                    long = "exclusive",
+                    short = "e",
+                    description = "Members can only have one of the roles on the message",
+                },
+            }),
+            callback = function(ctx)
+                local role = bot.add_reaction_role(
+                    ctx.msg,
+                    message_id(ctx, ctx.args.message),
+                    ctx.args.emoji,
+                    ctx.args.role,
+                    ctx.args.exclusive ~= nil
+                ):await()
+
+                return ctx.msg:reply(ctx.msg.channel:escape_text("reacting with " .. ctx.args.emoji .. " now gives " .. role)):await()
+            end,
+        }),
+        bot.sub_command("remove", {
+            description = "Stop giving a role for the emoji",
+            args = reaction_role_args(),
+            callback = function(ctx)
+                local role_id = bot.remove_reaction_role(ctx.msg, message_id(ctx, ctx.args.message), ctx.args.emoji):await()
+
+                if not role_id then
+                    return ctx.msg:reply("error: the emoji has no role on that message"):await()
+                end
+
+                return ctx.msg:reply("removed the reaction role"):await()
+            end,
+        }),
+        bot.sub_command("list", {
+            description = "List the reaction roles in this server",
+            callback = function(ctx)
+                local roles = bot.reaction_roles(ctx.msg):await()
+
+                if #roles == 0 then
+                    return ctx.msg:reply("no reaction roles are set up"):await()
+                end
+
+                local lines = {}
+
+                for _, role in ipairs(roles) do
+                    local line = role.message_id .. "  " .. role.emoji .. "  <@&" .. role.role_id .. ">"
+
+                    if role.exclusive then
+                        line = line .. "  (exclusive)"
+                    end
+
+                    table.insert(lines, line)
+                end
+
+                return ctx.msg:reply(table.concat(lines, "\n")):await()
+            end,
+        }),
+    },
+    permission = "admin",
+})
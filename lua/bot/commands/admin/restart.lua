@@ -0,0 +1,8 @@
+bot.add_command("restart", {
+    description = "Restart the bot, handing its state over to the new process",
+    callback = function(ctx)
+        ctx.msg:reply("restarting..."):await()
+        bot.restart(ctx.msg.author)
+    end,
+    permission = "owner",
+})
@@ -0,0 +1,25 @@
+bot.add_command("restartservice", {
+    description = "Reconnect a single service without restarting the bot",
+    args = {
+        {
+            key = "service",
+            name = "SERVICE",
+            description = "Service to reconnect, like discord",
+            required = true,
+        },
+    },
+    callback = function(ctx)
+        ctx.msg:reply("restarting " .. ctx.args.service .. "..."):await()
+
+        local succ, err = pcall(function()
+            return bot.restart_service(ctx.msg.author, ctx.args.service):await()
+        end)
+
+        if not succ then
+            return ctx.msg:reply("error: " .. tostring(err)):await()
+        end
+
+        return ctx.msg:reply(ctx.args.service .. " is connected again"):await()
+    end,
+    permission = "owner",
+})
@@ -5,11 +5,12 @@ bot.add_command("restrict", {
             key = "user",
             name = "USER",
             description = "User to restrict or unrestrict",
+            type = "user",
             required = true,
         }
     },
     callback = function(ctx)
-        local user = bot.find_user(ctx.msg.channel, ctx.args.user):await()
+        local user = ctx.args.user
 
 
         if ctx.msg.author.uid == user.uid then
@@ -25,7 +26,7 @@ bot.add_command("restrict", {
         end
 
         if user.restricted then
-            bot.unrestrict_user(user)
+            bot.unrestrict_user(user, ctx.msg.author):await()
             return ctx.msg:reply("unrestricted " .. user.name):await()
         else
             bot.restrict_user(user, ctx.msg.author):await()
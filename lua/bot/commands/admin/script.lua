@@ -0,0 +1,92 @@
+local script_name_args = {
+    {
+        key = "name",
+        name = "NAME",
+        description = "Name of the script, also the command that runs it",
+        required = true,
+    },
+}
+
+-- The script is taken from the first attachment of the message or downloaded from the link
+local function script_source(ctx)
+    local attachment = ctx.msg.attachments[1]
+
+    if attachment then
+        return attachment:bytes():await()
+    end
+
+    if ctx.args.link then
+        local res = http.fetch(ctx.args.link):await()
+
+        if res.status ~= 200 then
+            error("downloading the script failed with status " .. res.status)
+        end
+
+        return res.body
+    end
+end
+
+bot.add_command("script", {
+    description = "Install lua scripts on the server that run as commands in the sandbox",
+    sub_commands = {
+        bot.sub_command("install", {
+            description = "Install or update a script from an attachment or a link",
+            args = {
+                script_name_args[1],
+                {
+                    key = "link",
+                    name = "LINK",
+                    description = "Link to the script, if it isn't attached",
+                },
+            },
+            callback = function(ctx)
+                local name = ctx.args.name
+
+                if bot.cmds[name] or bot.aliases[name] then
+                    return ctx.msg:reply("error: a command named " .. name .. " already exists"):await()
+                end
+
+                local source = script_source(ctx)
+
+                if not source then
+                    return ctx.msg:reply("error: attach the script or give a link to it"):await()
+                end
+
+                server_scripts.install(ctx.msg.channel.server, name, source, ctx.msg.author):await()
+
+                return ctx.msg:reply("installed script " .. name):await()
+            end,
+        }),
+        bot.sub_command("uninstall", {
+            description = "Uninstall a script and delete its saved data",
+            aliases = { "remove" },
+            args = script_name_args,
+            callback = function(ctx)
+                if not server_scripts.uninstall(ctx.msg.channel.server, ctx.args.name, ctx.msg.author):await() then
+                    return ctx.msg:reply("error: no script named " .. ctx.args.name .. " is installed"):await()
+                end
+
+                return ctx.msg:reply("uninstalled script " .. ctx.args.name):await()
+            end,
+        }),
+        bot.sub_command("list", {
+            description = "List the scripts installed on the server",
+            callback = function(ctx)
+                local scripts = server_scripts.list(ctx.msg.channel.server):await()
+
+                if #scripts == 0 then
+                    return ctx.msg:reply("no scripts are installed"):await()
+                end
+
+                local lines = {}
+
+                for _, script in ipairs(scripts) do
+                    table.insert(lines, script.name .. "  " .. script.size .. " bytes  " .. ctx.format_time(script.timestamp))
+                end
+
+                return ctx.msg:reply(bot.code_block(ctx.msg.channel, table.concat(lines, "\n"))):await()
+            end,
+        }),
+    },
+    permission = "admin",
+})
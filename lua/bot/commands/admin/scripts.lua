@@ -0,0 +1,62 @@
+bot.add_command("scripts", {
+    description = "Manage the loaded bot scripts",
+    sub_commands = {
+        bot.sub_command("sync", {
+            description = "Pull the scripts from the configured git repository and reload them",
+            callback = function(ctx)
+                ctx.msg:reply("syncing scripts..."):await()
+                bot.sync_scripts(ctx.msg.channel, ctx.msg.author)
+            end,
+        }),
+        bot.sub_command("rollback", {
+            description = "Revert to a previous version of the scripts",
+            args = {
+                {
+                    key = "version",
+                    name = "VERSION",
+                    description = "Version hash to revert to, defaults to the previous version",
+                },
+            },
+            callback = function(ctx)
+                ctx.msg:reply("rolling back scripts..."):await()
+                bot.rollback_scripts(ctx.msg.channel, ctx.msg.author, ctx.args.version)
+            end,
+        }),
+        bot.sub_command("reload", {
+            description = "Reload the active scripts from disk, keeping the state they hand off",
+            callback = function(ctx)
+                ctx.msg:reply("reloading scripts..."):await()
+                bot.reload_scripts(ctx.msg.channel, ctx.msg.author)
+            end,
+        }),
+        bot.sub_command("versions", {
+            description = "List the stored script versions",
+            callback = function(ctx)
+                local versions = bot.script_versions()
+
+                if #versions == 0 then
+                    return ctx.msg:reply("no script versions have been stored"):await()
+                end
+
+                local lines = {}
+
+                for _, version in ipairs(versions) do
+                    local line = version.hash .. "  " .. version.date
+
+                    if version.commit then
+                        line = line .. "  " .. version.commit:sub(1, 7)
+                    end
+
+                    if version.active then
+                        line = line .. "  (active)"
+                    end
+
+                    table.insert(lines, line)
+                end
+
+                return ctx.msg:reply(bot.code_block(ctx.msg.channel, table.concat(lines, "\n"))):await()
+            end,
+        }),
+    },
+    permission = "owner",
+})
@@ -5,6 +5,7 @@ bot.add_command("setrole", {
             key = "user",
             name = "USER",
             description = "User to update the role",
+            type = "user",
             required = true,
         },
         {
@@ -15,10 +16,10 @@ bot.add_command("setrole", {
         }
     },
     callback = function(ctx)
-        local user = bot.find_user(ctx.msg.channel, ctx.args.user):await()
+        local user = ctx.args.user
 
         if user then
-            bot.set_role(user, ctx.args.role):await()
+            bot.set_role(user, ctx.args.role, ctx.msg.author):await()
             return ctx.msg:reply("changed role of " .. user.name .. " to "..ctx.args.role):await()
         else
             return ctx.msg:reply("error: no user was found"):await()
@@ -1,3 +1,52 @@
+-- Explain an unknown setting name with the closest registered names and the settings that share its prefix
+local function unknown_setting_message(module_settings, setting)
+    local candidates = {}
+
+    for _, v in ipairs(module_settings) do
+        table.insert(candidates, {name = v.name, dist = string.levenshtein(string.lower(setting), v.name)})
+    end
+
+    table.sort(candidates, function(a, b)
+        return a.dist < b.dist or (a.dist == b.dist and a.name < b.name)
+    end)
+
+    local max_dist = math.max(2, math.floor(#setting / 3))
+    local suggestions = {}
+
+    for _, candidate in ipairs(candidates) do
+        if candidate.dist > max_dist or #suggestions == 3 then
+            break
+        end
+
+        table.insert(suggestions, "\"" .. candidate.name .. "\"")
+    end
+
+    if #suggestions == 0 then
+        return "unknown setting \"" .. setting .. "\""
+    end
+
+    local out = "unknown setting \"" .. setting .. "\", did you mean " .. table.concat(suggestions, ", ") .. "?"
+
+    -- Settings are namespaced by the part before the first underscore, like http_ or sandbox_
+    local namespace = candidates[1].name:match("^([^_]+_)")
+
+    if namespace then
+        local names = {}
+
+        for _, v in ipairs(module_settings) do
+            if string.starts_with(v.name, namespace) then
+                table.insert(names, v.name)
+            end
+        end
+
+        if #names > 1 then
+            out = out .. "\nsettings in " .. namespace .. "*: " .. table.concat(names, ", ")
+        end
+    end
+
+    return out
+end
+
 bot.add_command("settings", {
     description = "Update module settings for the channel or server",
     sub_commands = {
@@ -34,6 +83,54 @@ bot.add_command("settings", {
                 ctx.msg:reply(out)
             end,
         }),
+        bot.sub_command("help", {
+            args = {
+                {
+                    key = "module",
+                    name = "MODULE",
+                    description = "Module of the setting",
+                    required = true,
+                },
+                {
+                    key = "setting",
+                    name = "SETTING",
+                    description = "Setting to explain",
+                    required = true,
+                },
+            },
+            description = "Show the type, default and limits of a setting",
+            callback = function(ctx)
+                local module_settings = bot.list_settings(ctx.args.module)
+
+                if not module_settings then
+                    return ctx.msg:reply("unknown module"):await()
+                end
+
+                for _, v in ipairs(module_settings) do
+                    if v.name == ctx.args.setting then
+                        local lines = {
+                            ctx.args.module .. "/" .. v.name,
+                            v.help,
+                            "",
+                            "type: " .. v.kind,
+                            "default: " .. v.default,
+                        }
+
+                        if v.constraints then
+                            table.insert(lines, "limits: " .. v.constraints)
+                        end
+
+                        if v.server_override then
+                            table.insert(lines, "server values take precedence over channel values")
+                        end
+
+                        return ctx.msg:reply(bot.code_block(ctx.msg.channel, table.concat(lines, "\n"))):await()
+                    end
+                end
+
+                return ctx.msg:reply(unknown_setting_message(module_settings, ctx.args.setting)):await()
+            end,
+        }),
         bot.sub_command("set", {
             args = {
                 {
@@ -65,8 +162,20 @@ bot.add_command("settings", {
                     description = "apply the setting for the current channel"
                 }
             },
-            description = "Update a module setting",
+            description = "Update a module setting, add \"for DURATION\" (like \"for 2h\") to revert it after a while",
             callback = function(ctx)
+                local duration
+
+                if ctx.extra_args[1] == "for" and ctx.extra_args[2] and #ctx.extra_args == 2 then
+                    duration = math.floor(time.parse_duration(ctx.extra_args[2]))
+
+                    if duration <= 0 then
+                        return ctx.msg:reply("argument error: invalid duration \"" .. ctx.extra_args[2] .. "\""):await()
+                    end
+                elseif #ctx.extra_args > 0 then
+                    return ctx.msg:reply("argument error: unexpected \"" .. table.concat(ctx.extra_args, " ") .. "\", expected \"for DURATION\""):await()
+                end
+
                 if not (ctx.args.server or ctx.args.channel) then
                     return ctx.msg:reply("argument error: --channel or --server has to be used"):await()
                 end
@@ -77,7 +186,26 @@ bot.add_command("settings", {
 
                 local server = ctx.args.server ~= nil
 
-                local err, fut = bot.set_setting(ctx.msg, server, ctx.args.module, ctx.args.setting, ctx.args.value)
+                local module_settings = bot.list_settings(ctx.args.module)
+
+                if not module_settings then
+                    return ctx.msg:reply("argument error: unknown module"):await()
+                end
+
+                local known = false
+
+                for _, v in ipairs(module_settings) do
+                    if v.name == ctx.args.setting then
+                        known = true
+                        break
+                    end
+                end
+
+                if not known then
+                    return ctx.msg:reply("argument error: " .. unknown_setting_message(module_settings, ctx.args.setting)):await()
+                end
+
+                local err, fut = bot.set_setting(ctx.msg, server, ctx.args.module, ctx.args.setting, ctx.args.value, duration)
 
                 if err then
                     return ctx.msg:reply("argument error: " .. err):await()
@@ -85,9 +213,15 @@ bot.add_command("settings", {
 
                 fut:await()
 
-                return ctx.msg:reply("Successfully updated \"" .. ctx.args.module .. "/" .. ctx.args.setting .. "\" for the current " .. (server and "server" or "channel")):await()
+                local reply = "Successfully updated \"" .. ctx.args.module .. "/" .. ctx.args.setting .. "\" for the current " .. (server and "server" or "channel")
+
+                if duration then
+                    reply = reply .. ", it will be reverted in " .. ctx.extra_args[2]
+                end
+
+                return ctx.msg:reply(reply):await()
             end,
         })
     },
-    role = "admin",
+    permission = "admin",
 })
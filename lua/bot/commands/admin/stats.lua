@@ -0,0 +1,41 @@
+local function add_counters(lines, title, counters)
+    local keys = {}
+
+    for key in pairs(counters) do
+        table.insert(keys, key)
+    end
+
+    table.sort(keys)
+
+    if #keys == 0 then
+        return
+    end
+
+    table.insert(lines, title .. ":")
+
+    for _, key in ipairs(keys) do
+        table.insert(lines, "  " .. key .. ": " .. counters[key])
+    end
+end
+
+bot.add_command("stats", {
+    description = "Show runtime counters of the sandbox, the lua states and the services since the bot started",
+    callback = function(ctx)
+        local stats = bot.stats(ctx.msg.author)
+        local lines = {
+            "uptime: " .. stats.uptime .. "s",
+            "active sandbox workers: " .. stats.sandbox_active_workers,
+            "abandoned sandbox workers: " .. stats.sandbox_abandoned_workers,
+            "sandbox runs: " .. stats.sandbox_runs,
+            "sandbox instructions: " .. stats.sandbox_instructions,
+        }
+
+        add_counters(lines, "sandbox terminations", stats.sandbox_terminations)
+        add_counters(lines, "sandbox http rejections", stats.http_rejections)
+        add_counters(lines, "async callbacks handled", stats.async_callbacks)
+        add_counters(lines, "messages received", stats.messages_received)
+
+        return ctx.msg:reply(bot.code_block(ctx.msg.channel, table.concat(lines, "\n"))):await()
+    end,
+    permission = "owner",
+})
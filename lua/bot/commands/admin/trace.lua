@@ -0,0 +1,63 @@
+local function reply_output(ctx, header, output, err)
+    if err then
+        return ctx.msg:reply(header .. "\n" .. bot.code_block(ctx.msg.channel, "error: " .. err)):await()
+    end
+
+    if output == "" then
+        output = "(no output)"
+    end
+
+    return ctx.msg:reply(header .. "\n" .. bot.code_block(ctx.msg.channel, output)):await()
+end
+
+bot.add_command("trace", {
+    description = "Record sandbox runs and replay them with the same inputs to debug them",
+    sub_commands = {
+        bot.sub_command("record", {
+            description = "Run code in the sandbox and record everything it receives",
+            args = {
+                {
+                    key = "code",
+                    name = "CODE",
+                    description = "Code to run",
+                    required = true,
+                },
+            },
+            callback = function(ctx)
+                local code = ctx.args.code
+
+                if #ctx.extra_args > 0 then
+                    code = code .. " " .. table.concat(ctx.extra_args, " ")
+                end
+
+                local res = bot.record_sandbox(ctx.msg, code):await()
+
+                return reply_output(ctx, "recorded trace " .. res.trace .. " with " .. res.events .. " events", res.output, res.error)
+            end,
+        }),
+        bot.sub_command("replay", {
+            description = "Run a recording again against the recorded inputs",
+            args = {
+                {
+                    key = "trace",
+                    name = "TRACE",
+                    description = "Trace id of the recording",
+                    required = true,
+                },
+            },
+            callback = function(ctx)
+                local res = bot.replay_sandbox(ctx.msg, ctx.args.trace):await()
+                local header = "replayed trace " .. res.trace
+
+                if res.matches then
+                    header = header .. ", the result matches the recording"
+                else
+                    header = header .. ", the result differs from the recording"
+                end
+
+                return reply_output(ctx, header, res.output, res.error)
+            end,
+        }),
+    },
+    permission = "owner",
+})
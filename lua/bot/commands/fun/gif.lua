@@ -0,0 +1,37 @@
+bot.add_command("gif", {
+    description = "Search for a GIF",
+    rate_limit = { count = 3, period = 30 },
+    args = {
+        {
+            key = "query",
+            name = "QUERY",
+            description = "What to search for",
+            required = true,
+        },
+    },
+    callback = function(ctx)
+        if not gif.available() then
+            return ctx.msg:reply("no GIF provider has been configured"):await()
+        end
+
+        local query = ctx.args.query
+
+        if #ctx.extra_args > 0 then
+            query = query .. " " .. table.concat(ctx.extra_args, " ")
+        end
+
+        local succ, res = pcall(function()
+            return gif.search(query, { limit = 10, channel = ctx.msg.channel }):await()
+        end)
+
+        if not succ then
+            return ctx.msg:reply("error: " .. tostring(res)):await()
+        end
+
+        if #res == 0 then
+            return ctx.msg:reply("no GIFs found"):await()
+        end
+
+        return ctx.msg:reply("", { gif = res[math.random(#res)].url }):await()
+    end,
+})
@@ -0,0 +1,111 @@
+-- Bare message ids are taken to be on the same service as the command
+local function message_id(ctx, id)
+    if id:find(":") then
+        return id
+    end
+
+    return ctx.msg.id:match("^(%w+):") .. ":" .. id
+end
+
+local function results_text(ctx, results)
+    local lines = { bot.bold_block(ctx.msg.channel, results.closed and "Poll results:" or "Poll:") .. " " .. results.title }
+
+    for i, option in ipairs(results.options) do
+        table.insert(lines, i .. ". " .. option.label .. ": " .. option.votes .. "/" .. results.total)
+    end
+
+    if #results.winners > 0 then
+        table.insert(lines, bot.bold_block(ctx.msg.channel, "Winner: ") .. table.list_words(results.winners))
+    end
+
+    return table.concat(lines, "\n")
+end
+
+local poll_id_args = {
+    {
+        key = "message",
+        name = "MESSAGE",
+        description = "Id of the poll message",
+        required = true,
+    },
+}
+
+bot.add_command("poll", {
+    description = "Create polls members vote on with reactions or buttons",
+    sub_commands = {
+        bot.sub_command("create", {
+            description = "Create a poll in the channel",
+            args = {
+                {
+                    key = "title",
+                    name = "TITLE",
+                    description = "Question of the poll",
+                    required = true,
+                },
+                {
+                    key = "first_option",
+                    name = "OPTIONS",
+                    description = "Poll options, at least two",
+                    required = true,
+                },
+                {
+                    key = "duration",
+                    long = "duration",
+                    short = "d",
+                    takes_value = true,
+                    description = "Close the poll automatically after e.g. 1h or 2d",
+                },
+                {
+                    key = "buttons",
+                    long = "buttons",
+                    short = "b",
+                    description = "Vote with buttons instead of reactions",
+                },
+            },
+            callback = function(ctx)
+                local options = ctx.extra_args
+                table.insert(options, 1, ctx.args.first_option)
+
+                local duration
+
+                if ctx.args.duration then
+                    duration = math.floor(time.parse_duration(ctx.args.duration))
+
+                    if duration <= 0 then
+                        return ctx.msg:reply("error: invalid duration"):await()
+                    end
+                end
+
+                polls.create(ctx.msg.channel, ctx.msg.author, {
+                    title = ctx.args.title,
+                    options = options,
+                    duration = duration,
+                    buttons = ctx.args.buttons ~= nil and ctx.msg.channel:supports_feature(bot.FEATURES.Components),
+                }):await()
+            end,
+            role = "trusted",
+        }),
+        bot.sub_command("close", {
+            description = "Close a poll and show the results",
+            aliases = { "end" },
+            args = poll_id_args,
+            callback = function(ctx)
+                local id = message_id(ctx, ctx.args.message)
+                local results = polls.results(id):await()
+
+                if results.created_by ~= ctx.msg.author.uid and not bot.is_admin(ctx.msg.author, ctx.msg.channel):await() then
+                    return ctx.msg:reply("error: only the creator of the poll or an admin can close it"):await()
+                end
+
+                return ctx.msg:reply(results_text(ctx, polls.close(id):await())):await()
+            end,
+        }),
+        bot.sub_command("results", {
+            description = "Show the current votes of a poll",
+            args = poll_id_args,
+            callback = function(ctx)
+                return ctx.msg:reply(results_text(ctx, polls.results(message_id(ctx, ctx.args.message)):await())):await()
+            end,
+        }),
+    },
+})
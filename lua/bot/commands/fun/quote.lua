@@ -0,0 +1,154 @@
+local function quote_text(ctx, quote)
+    local text = bot.bold_block(ctx.msg.channel, "#" .. quote.number) .. " " .. ctx.msg.channel:escape_text(quote.content)
+        .. "\n- " .. ctx.msg.channel:escape_text(quote.author_name)
+
+    if quote.link then
+        text = text .. " <" .. quote.link .. ">"
+    end
+
+    return text
+end
+
+local function reply_quote(ctx, quote)
+    if not quote then
+        return ctx.msg:reply("error: no quote was found"):await()
+    end
+
+    return ctx.msg:reply(quote_text(ctx, quote)):await()
+end
+
+local quote_number_args = {
+    {
+        key = "number",
+        name = "NUMBER",
+        description = "Number of the quote",
+        required = true,
+    },
+}
+
+bot.add_command("quote", {
+    description = "Show a quote by its number",
+    args = {
+        {
+            key = "number",
+            name = "NUMBER",
+            description = "Number of the quote, a random one if not set",
+        },
+    },
+    callback = function(ctx)
+        if not ctx.args.number then
+            return reply_quote(ctx, quotes.random(ctx.msg.channel.server):await())
+        end
+
+        local number = tonumber(ctx.args.number)
+
+        if not number then
+            return ctx.msg:reply("error: invalid quote number"):await()
+        end
+
+        return reply_quote(ctx, quotes.get(ctx.msg.channel.server, number):await())
+    end,
+    sub_commands = {
+        bot.sub_command("add", {
+            description = "Quote the message you are replying to",
+            callback = function(ctx)
+                if not ctx.msg.reply_to then
+                    return ctx.msg:reply("error: reply to the message you want to quote"):await()
+                end
+
+                local msg = bot.message(ctx.msg.channel.id, ctx.msg.reply_to):await()
+
+                if msg.content == "" then
+                    return ctx.msg:reply("error: the message has no text to quote"):await()
+                end
+
+                local number = quotes.add(msg, ctx.msg.author):await()
+
+                if not number then
+                    return ctx.msg:reply("error: the message is already quoted"):await()
+                end
+
+                return ctx.msg:reply("added quote #" .. number):await()
+            end,
+        }),
+        bot.sub_command("random", {
+            description = "Show a random quote",
+            callback = function(ctx)
+                return reply_quote(ctx, quotes.random(ctx.msg.channel.server):await())
+            end,
+        }),
+        bot.sub_command("search", {
+            description = "Browse the quotes containing the text, or all of them",
+            args = {
+                {
+                    key = "text",
+                    name = "TEXT",
+                    description = "Text or author name to search for",
+                },
+                {
+                    key = "page",
+                    long = "page",
+                    short = "p",
+                    takes_value = true,
+                    description = "Page number",
+                },
+            },
+            callback = function(ctx)
+                local text = ctx.args.text
+
+                if text and #ctx.extra_args > 0 then
+                    text = text .. " " .. table.concat(ctx.extra_args, " ")
+                end
+
+                local found = quotes.search(ctx.msg.channel.server, text):await()
+
+                if #found == 0 then
+                    return ctx.msg:reply("error: no quotes were found"):await()
+                end
+
+                return pagination.create(ctx.msg.channel, {
+                    title = "Quotes",
+                    data = found,
+                    render_data = function(_, page)
+                        local lines = {}
+
+                        for _, quote in ipairs(page) do
+                            table.insert(lines, quote_text(ctx, quote))
+                        end
+
+                        return {
+                            content = table.concat(lines, "\n"),
+                        }
+                    end,
+                    page = ctx.args.page,
+                    caller = ctx.msg.author,
+                })
+            end,
+        }),
+        bot.sub_command("remove", {
+            description = "Remove a quote, only the one who added it or an admin can",
+            args = quote_number_args,
+            callback = function(ctx)
+                local number = tonumber(ctx.args.number)
+
+                if not number then
+                    return ctx.msg:reply("error: invalid quote number"):await()
+                end
+
+                local quote = quotes.get(ctx.msg.channel.server, number):await()
+
+                if not quote then
+                    return ctx.msg:reply("error: no quote was found"):await()
+                end
+
+                if quote.added_by ~= ctx.msg.author.uid and not bot.is_admin(ctx.msg.author, ctx.msg.channel):await() then
+                    return ctx.msg:reply("error: only the one who added the quote or an admin can remove it"):await()
+                end
+
+                quotes.remove(ctx.msg.channel.server, number, ctx.msg.author):await()
+
+                return ctx.msg:reply("removed quote #" .. number):await()
+            end,
+        }),
+    },
+})
@@ -1,3 +1,59 @@
+local create_args = {
+    {
+        key = "tag",
+        name = "NAME",
+        description = "Tag name",
+        required = true,
+    },
+    {
+        key = "value",
+        name = "VALUE",
+        description = "Tag value",
+    }
+}
+
+local function create_tag(ctx)
+    if not tags.is_valid_name(ctx.args.tag) then
+        return ctx.msg:reply("error: the tag name must be alphanumeric"):await()
+    end
+
+    if #ctx.args.tag > tags.MAX_NAME_LIMIT then
+        return ctx.msg:reply("error: the tag name cannot be longer than " .. tags.MAX_NAME_LIMIT .. " characters"):await()
+    end
+
+    local value = ctx.args.value or ""
+
+    if #ctx.extra_args > 0 then
+        value = value .. " " .. table.concat(ctx.extra_args, " ")
+    end
+
+    for i, attachment in pairs(ctx.msg.attachments) do
+        if value ~= "" then value = value .. "\n" end
+
+        value = value .. attachment.url
+    end
+
+    if #value > tags.MAX_VALUE_LIMIT then
+        return ctx.msg:reply("error: the tag value cannot be longer than " .. tags.MAX_VALUE_LIMIT .. " characters"):await()
+    end
+
+    if #value == 0 then
+        return ctx.msg:reply("error: the tag value cannot be empty"):await()
+    end
+
+    if tags.count_user_tags(ctx.msg.author):await() > tags.MAX_USER_TAGS then
+        return ctx.msg:reply("error: the max tags owned limit on " .. tags.MAX_USER_TAGS .. " tags has been reached"):await()
+    end
+
+    local error = tags.create_tag(ctx.msg.author, ctx.msg.channel.server, ctx.args.tag, value):await()
+
+    if error then
+        return ctx.msg:reply("error: " .. ctx.msg.channel:escape_text(error)):await()
+    else
+        return ctx.msg:reply("sucessfully created tag \"" .. ctx.msg.channel:escape_text(ctx.args.tag) .. "\""):await()
+    end
+end
+
 bot.add_command("tag", {
     description = "View a tag",
     aliases = { "t" },
@@ -23,61 +79,14 @@ bot.add_command("tag", {
     end,
     sub_commands = {
         bot.sub_command("create", {
-            args = {
-                {
-                    key = "tag",
-                    name = "NAME",
-                    description = "Tag name",
-                    required = true,
-                },
-                {
-                    key = "value",
-                    name = "VALUE",
-                    description = "Tag value",
-                }
-            },
+            args = create_args,
             description = "Create a new tag",
-            callback = function(ctx)
-                if not tags.is_valid_name(ctx.args.tag) then
-                    return ctx.msg:reply("error: the tag name must be alphanumeric"):await()
-                end
-
-                if #ctx.args.tag > tags.MAX_NAME_LIMIT then
-                    return ctx.msg:reply("error: the tag name cannot be longer than " .. tags.MAX_NAME_LIMIT .. " characters"):await()
-                end
-
-                local value = ctx.args.value or ""
-
-                if #ctx.extra_args > 0 then
-                    value = value .. " " .. table.concat(ctx.extra_args, " ")
-                end
-
-                for i, attachment in pairs(ctx.msg.attachments) do
-                    if value ~= "" then value = value .. "\n" end
-
-                    value = value .. attachment.url
-                end
-
-                if #value > tags.MAX_VALUE_LIMIT then
-                    return ctx.msg:reply("error: the tag value cannot be longer than " .. tags.MAX_VALUE_LIMIT .. " characters"):await()
-                end
-
-                if #value == 0 then
-                    return ctx.msg:reply("error: the tag value cannot be empty"):await()
-                end
-
-                if tags.count_user_tags(ctx.msg.author):await() > tags.MAX_USER_TAGS then
-                    return ctx.msg:reply("error: the max tags owned limit on " .. tags.MAX_USER_TAGS .. " tags has been reached"):await()
-                end
-
-                local error = tags.create_tag(ctx.msg.author, ctx.msg.channel.server, ctx.args.tag, value):await()
-
-                if error then
-                    return ctx.msg:reply("error: " .. ctx.msg.channel:escape_text(error)):await()
-                else
-                    return ctx.msg:reply("sucessfully created tag \"" .. ctx.msg.channel:escape_text(ctx.args.tag) .. "\""):await()
-                end
-            end,
+            callback = create_tag,
+        }),
+        bot.sub_command("add", {
+            args = create_args,
+            description = "Create a new tag, the tag can also be run as a command by its name",
+            callback = create_tag,
         }),
         bot.sub_command("delete", {
             args = {
@@ -0,0 +1,205 @@
+local function event_args(extra)
+    local args = {
+        {
+            key = "name",
+            name = "NAME",
+            description = "Name of the event",
+            required = true,
+        },
+        {
+            key = "start",
+            name = "START",
+            description = "When the event starts, e.g. 2d4h or \"next friday 5pm\"",
+            required = true,
+        },
+        {
+            key = "location",
+            long = "location",
+            short = "l",
+            takes_value = true,
+            description = "Where the event takes place, if not in a voice channel",
+        },
+        {
+            key = "duration",
+            long = "duration",
+            short = "d",
+            takes_value = true,
+            description = "How long the event lasts, e.g. 2h",
+        },
+        {
+            key = "channel",
+            long = "channel",
+            short = "c",
+            takes_value = true,
+            description = "Voice channel id to host the event in",
+        },
+        {
+            key = "description",
+            long = "description",
+            takes_value = true,
+            description = "Description of the event",
+        },
+    }
+
+    for _, arg in ipairs(extra or {}) do
+        table.insert(args, arg)
+    end
+
+    return args
+end
+
+-- Builds event settings from the command arguments, the start time continues into the extra arguments
+local function event_settings(ctx)
+    local start_text = ctx.args.start
+
+    if #ctx.extra_args > 0 then
+        start_text = start_text .. " " .. table.concat(ctx.extra_args, " ")
+    end
+
+    local start_time = time.parse_when(start_text, ctx.msg.author)
+
+    if not start_time or start_time <= os.time() then
+        return false, "error: invalid start time \"" .. start_text .. "\""
+    end
+
+    local location = ctx.args.location
+
+    if not ctx.args.channel and not location then
+        return false, "error: events need either a voice channel or a location"
+    end
+
+    return true, {
+        name = ctx.args.name,
+        description = ctx.args.description,
+        start_time = start_time,
+        duration = ctx.args.duration and time.parse_duration(ctx.args.duration),
+        location = location,
+        channel = ctx.args.channel,
+    }
+end
+
+local function check_supported(ctx)
+    if not ctx.msg.channel:supports_feature(bot.FEATURES.ScheduledEvents) then
+        ctx.msg:reply("error: scheduled events are not supported here"):await()
+        return false
+    end
+
+    return true
+end
+
+bot.add_command("event", {
+    description = "List and create scheduled events",
+    sub_commands = {
+        bot.sub_command("create", {
+            description = "Create a scheduled event",
+            role = "admin",
+            args = event_args(),
+            callback = function(ctx)
+                if not check_supported(ctx) then return end
+
+                local ok, settings = event_settings(ctx)
+
+                if not ok then
+                    return ctx.msg:reply(settings):await()
+                end
+
+                local event = bot.create_scheduled_event(ctx.msg.channel.server, {
+                    name = settings.name,
+                    description = settings.description,
+                    start_time = settings.start_time,
+                    end_time = settings.duration and settings.start_time + settings.duration,
+                    location = settings.location,
+                    channel = settings.channel,
+                }):await()
+
+                return ctx.msg:reply(
+                    "created event " .. bot.icode_block(ctx.msg.channel, event.name)
+                        .. " starting " .. bot.icode_block(ctx.msg.channel, ctx.format_time(event.start_time))
+                ):await()
+            end,
+        }),
+        bot.sub_command("weekly", {
+            description = "Create an event every week",
+            role = "admin",
+            args = event_args(),
+            callback = function(ctx)
+                if not check_supported(ctx) then return end
+
+                local ok, settings = event_settings(ctx)
+
+                if not ok then
+                    return ctx.msg:reply(settings):await()
+                end
+
+                local entry = bot.recurring_events.add_weekly(ctx.msg.channel, settings)
+                bot.recurring_events.think()
+
+                return ctx.msg:reply(
+                    "the event " .. bot.icode_block(ctx.msg.channel, entry.name) .. " will be created every week"
+                ):await()
+            end,
+        }),
+        bot.sub_command("recurring", {
+            description = "List the weekly events of this server",
+            callback = function(ctx)
+                local entries = bot.recurring_events.list_weekly(ctx.msg.channel.server)
+
+                if #entries == 0 then
+                    return ctx.msg:reply("there are no weekly events"):await()
+                end
+
+                local lines = {}
+
+                for i, entry in ipairs(entries) do
+                    table.insert(lines, i .. ". " .. entry.name .. ", next on " .. ctx.format_time(entry.next_time))
+                end
+
+                return ctx.msg:reply(bot.code_block(ctx.msg.channel, table.concat(lines, "\n"))):await()
+            end,
+        }),
+        bot.sub_command("unweekly", {
+            description = "Stop creating a weekly event",
+            role = "admin",
+            args = {
+                {
+                    key = "index",
+                    name = "INDEX",
+                    description = "Number of the event as shown by \"event recurring\"",
+                    required = true,
+                },
+            },
+            callback = function(ctx)
+                local index = tonumber(ctx.args.index)
+
+                if not index or not bot.recurring_events.remove_weekly(ctx.msg.channel.server, index) then
+                    return ctx.msg:reply("error: no weekly event with that number"):await()
+                end
+
+                return ctx.msg:reply("removed the weekly event"):await()
+            end,
+        }),
+    },
+    callback = function(ctx)
+        if not check_supported(ctx) then return end
+
+        local events = bot.scheduled_events(ctx.msg.channel.server):await()
+
+        if #events == 0 then
+            return ctx.msg:reply("there are no scheduled events"):await()
+        end
+
+        local lines = {}
+
+        for _, event in ipairs(events) do
+            local line = ctx.format_time(event.start_time) .. "  " .. event.name
+
+            if event.user_count then
+                line = line .. " (" .. event.user_count .. " interested)"
+            end
+
+            table.insert(lines, line)
+        end
+
+        return ctx.msg:reply(bot.code_block(ctx.msg.channel, table.concat(lines, "\n"))):await()
+    end,
+})
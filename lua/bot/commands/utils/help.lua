@@ -15,7 +15,7 @@ bot.add_command("help", {
                 if bot.has_role_or_higher(cmd.role, ctx.msg.author.role) then
                     table.insert(cmds, cmd)
                 end
-            else
+            elseif bot.has_command_permission(cmd, ctx.caller) then
                 table.insert(cmds, cmd)
             end
         end
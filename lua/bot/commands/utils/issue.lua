@@ -0,0 +1,42 @@
+bot.add_command("issue", {
+    description = "Look up a GitHub issue or pull request",
+    aliases = { "gh" },
+    args = {
+        {
+            key = "repo",
+            name = "REPO",
+            description = "Repository in the owner/name format",
+            required = true,
+        },
+        {
+            key = "number",
+            name = "NUMBER",
+            description = "Issue or pull request number",
+            required = true,
+        },
+    },
+    callback = function(ctx)
+        local number = tonumber(ctx.args.number)
+
+        if not number then
+            return ctx.msg:reply("error: expected a number"):await()
+        end
+
+        local succ, res = pcall(function()
+            return gh.issue(ctx.args.repo, math.floor(number)):await()
+        end)
+
+        if not succ then
+            if async.error_kind(res) == "not_found" then
+                return ctx.msg:reply("error: no issue #" .. math.floor(number) .. " was found"):await()
+            end
+
+            return ctx.msg:reply("error: " .. tostring(res)):await()
+        end
+
+        local kind = res.is_pull_request and "Pull request" or "Issue"
+        local out = kind .. " #" .. res.number .. " (" .. res.state .. ") by " .. res.author .. ": " .. res.title
+
+        return ctx.msg:reply(ctx.msg.channel:escape_text(out) .. "\n<" .. res.url .. ">"):await()
+    end,
+})
@@ -0,0 +1,34 @@
+bot.add_command("tex", {
+    description = "Render a LaTeX math expression",
+    aliases = { "latex" },
+    rate_limit = { count = 3, period = 30 },
+    args = {
+        {
+            key = "expr",
+            name = "EXPR",
+            description = "Expression to render",
+            required = true,
+        },
+    },
+    callback = function(ctx)
+        if not latex.available() then
+            return ctx.msg:reply("no latex renderer has been configured"):await()
+        end
+
+        local expr = ctx.args.expr
+
+        if #ctx.extra_args > 0 then
+            expr = expr .. " " .. table.concat(ctx.extra_args, " ")
+        end
+
+        local succ, res = pcall(function()
+            return latex.render(expr):await()
+        end)
+
+        if not succ then
+            return ctx.msg:reply("error: " .. tostring(res)):await()
+        end
+
+        return ctx.msg:reply("", { attachments = { res } }):await()
+    end,
+})
@@ -0,0 +1,55 @@
+bot.add_command("qr", {
+    description = "Create a QR code",
+    args = {
+        {
+            key = "text",
+            name = "TEXT",
+            description = "Text to encode",
+            required = true,
+        },
+    },
+    sub_commands = {
+        bot.sub_command("read", {
+            description = "Read the QR codes of an image",
+            args = {
+                {
+                    key = "image",
+                    name = "IMAGE",
+                    description = "Url of the image, the attachment is used if not set",
+                },
+            },
+            callback = function(ctx)
+                local succ, img = pcall(function()
+                    return image.resolve(ctx.msg, ctx.args.image):await()
+                end)
+
+                if not succ or not img then
+                    return ctx.msg:reply("error: no image was found"):await()
+                end
+
+                local codes = qr.decode(img):await()
+
+                if #codes == 0 then
+                    return ctx.msg:reply("no QR codes found"):await()
+                end
+
+                return ctx.msg:reply(ctx.msg.channel:escape_text(table.concat(codes, "\n"))):await()
+            end,
+        }),
+    },
+    callback = function(ctx)
+        local text = ctx.args.text
+
+        if #ctx.extra_args > 0 then
+            text = text .. " " .. table.concat(ctx.extra_args, " ")
+        end
+
+        local succ, res = pcall(qr.encode, text)
+
+        if not succ then
+            return ctx.msg:reply("error: " .. tostring(res)):await()
+        end
+
+        return ctx.msg:reply("", { attachments = { res } }):await()
+    end,
+})
@@ -0,0 +1,50 @@
+local function format_duration(secs)
+    if secs >= 60 * 60 then
+        return math.floor(secs / (60 * 60)) .. "h"
+    elseif secs >= 60 then
+        return math.floor(secs / 60) .. "m"
+    end
+
+    return secs .. "s"
+end
+
+local function format_bytes(bytes)
+    if bytes >= 1024 then
+        return string.format("%.1f KiB", bytes / 1024)
+    end
+
+    return bytes .. " B"
+end
+
+bot.add_command("quota", {
+    description = "Show your remaining sandbox quota in this channel",
+    callback = function(ctx)
+        local quota = bot.sandbox_quota(ctx.msg.author, ctx.msg.channel):await()
+        local runs, per_run, storage = quota.runs, quota.per_run, quota.storage
+
+        local lines = {}
+
+        if runs.timeout then
+            table.insert(lines, "runs: timed out for " .. format_duration(runs.timeout))
+        elseif runs.limit == 0 then
+            table.insert(lines, "runs: unlimited")
+        else
+            table.insert(lines, string.format(
+                "runs: %d/%d left per %s",
+                runs.left, runs.limit, format_duration(runs.period)
+            ))
+        end
+
+        table.insert(lines, string.format(
+            "%s: %d http calls, %d lines / %d characters of output, %d messages, %d images, %d attachment downloads",
+            per_run.running and "current run" or "per run",
+            per_run.http_calls, per_run.lines, per_run.characters, per_run.messages, per_run.images, per_run.attachment_downloads
+        ))
+        table.insert(lines, string.format(
+            "storage: %s/%s used by sandbox.save()",
+            format_bytes(storage.used), format_bytes(storage.limit)
+        ))
+
+        return ctx.msg:reply(bot.code_block(ctx.msg.channel, table.concat(lines, "\n"))):await()
+    end,
+})
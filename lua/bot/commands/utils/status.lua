@@ -0,0 +1,65 @@
+local function format_duration(secs)
+    local days = math.floor(secs / (60 * 60 * 24))
+    local hours = math.floor(secs / (60 * 60)) % 24
+    local minutes = math.floor(secs / 60) % 60
+
+    if days > 0 then
+        return days .. "d " .. hours .. "h"
+    elseif hours > 0 then
+        return hours .. "h " .. minutes .. "m"
+    elseif minutes > 0 then
+        return minutes .. "m"
+    end
+
+    return secs .. "s"
+end
+
+bot.add_command("status", {
+    description = "Show the connection status of the services and the lua states",
+    callback = function(ctx)
+        local statuses = bot.service_status():await()
+        local lines = {}
+
+        for _, status in ipairs(statuses) do
+            if #lines > 0 then
+                table.insert(lines, "")
+            end
+
+            local state
+
+            if status.uptime then
+                state = "connected for " .. format_duration(status.uptime)
+            elseif status.failed_attempts > 0 then
+                state = "disconnected, " .. status.failed_attempts .. " failed attempts"
+            else
+                state = "disconnected"
+            end
+
+            table.insert(lines, status.name .. ": " .. state)
+            table.insert(lines, "  reconnects: " .. status.reconnects)
+            table.insert(lines, "  queued messages: " .. status.backlog)
+            table.insert(lines, "  sent messages: " .. status.sent)
+
+            for _, shard in ipairs(status.shards) do
+                local latency = shard.latency and (shard.latency .. "ms") or "unknown"
+                table.insert(lines, "  shard " .. shard.id .. ": " .. shard.stage .. ", heartbeat " .. latency)
+            end
+        end
+
+        if #lines == 0 then
+            table.insert(lines, "no services are running")
+        end
+
+        for _, stats in ipairs(bot.lua_stats():await()) do
+            table.insert(lines, "")
+            table.insert(lines, "lua " .. stats.name .. " state:")
+            table.insert(lines, "  memory: " .. string.format("%.1f", stats.used_memory / (1024 * 1024)) .. " MiB")
+            table.insert(lines, "  registry values: " .. stats.registry_size)
+            table.insert(lines, "  pending futures: " .. stats.pending_futures)
+            table.insert(lines, "  queued callbacks: " .. stats.pending_callbacks)
+        end
+
+        return ctx.msg:reply(bot.code_block(ctx.msg.channel, table.concat(lines, "\n"))):await()
+    end,
+    permission = "admin",
+})
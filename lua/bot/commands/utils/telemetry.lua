@@ -0,0 +1,20 @@
+bot.add_command("telemetry", {
+    description = "Show whether anonymous usage counters are sent and what they contain",
+    callback = function(ctx)
+        local telemetry = bot.telemetry()
+
+        if not telemetry.enabled then
+            return ctx.msg:reply("Telemetry is disabled, no usage data is collected or sent"):await()
+        end
+
+        local hours = math.floor(telemetry.interval / (60 * 60))
+        local every = hours > 0 and (hours .. "h") or (math.floor(telemetry.interval / 60) .. "m")
+
+        local lines = {
+            "The owner of this bot enabled anonymous telemetry, every " .. every .. " the counters below are sent to " .. telemetry.endpoint .. ".",
+            "They only count commands run, sandbox terminations by reason and the services in use, never ids, names or message content.",
+        }
+
+        return ctx.msg:reply(table.concat(lines, "\n") .. "\n" .. bot.code_block(ctx.msg.channel, telemetry.report)):await()
+    end,
+})
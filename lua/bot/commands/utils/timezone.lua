@@ -0,0 +1,42 @@
+bot.add_command("timezone", {
+    description = "Show or change your timezone, used when formatting times",
+    aliases = { "tz" },
+    sub_commands = {
+        bot.sub_command("set", {
+            description = "Set your timezone, e.g. Europe/Berlin",
+            args = {
+                {
+                    key = "timezone",
+                    name = "TIMEZONE",
+                    description = "IANA timezone name",
+                    required = true,
+                },
+            },
+            callback = function(ctx)
+                if not time.is_timezone(ctx.args.timezone) then
+                    return ctx.msg:reply(ctx.msg.channel:escape_text("error: unknown timezone \"" .. ctx.args.timezone .. "\"")):await()
+                end
+
+                bot.set_timezone(ctx.msg.author, ctx.args.timezone):await()
+
+                return ctx.msg:reply("your time is now " .. bot.icode_block(ctx.msg.channel, time.format(os.time(), nil, ctx.args.timezone))):await()
+            end,
+        }),
+        bot.sub_command("clear", {
+            description = "Clear your timezone and go back to UTC",
+            callback = function(ctx)
+                bot.set_timezone(ctx.msg.author, nil):await()
+
+                return ctx.msg:reply("cleared your timezone"):await()
+            end,
+        }),
+    },
+    callback = function(ctx)
+        local timezone = ctx.msg.author.timezone or "UTC"
+
+        return ctx.msg:reply(
+            "your timezone is " .. bot.icode_block(ctx.msg.channel, timezone)
+                .. ", the time is " .. bot.icode_block(ctx.msg.channel, ctx.format_time(os.time()))
+        ):await()
+    end,
+})
@@ -0,0 +1,70 @@
+bot.add_command("translate", {
+    description = "Translate text into another language",
+    aliases = { "tr" },
+    rate_limit = { count = 3, period = 30 },
+    args = {
+        {
+            key = "to",
+            long = "to",
+            short = "t",
+            takes_value = true,
+            description = "Language to translate into",
+        },
+        {
+            key = "from",
+            long = "from",
+            short = "f",
+            takes_value = true,
+            description = "Language to translate from, detected if not set",
+        },
+        {
+            key = "detect",
+            long = "detect",
+            short = "d",
+            description = "Only detect the language of the text",
+        },
+        {
+            key = "text",
+            name = "TEXT",
+            description = "Text to translate",
+            required = true,
+        },
+    },
+    callback = function(ctx)
+        local text = ctx.args.text
+
+        if #ctx.extra_args > 0 then
+            text = text .. " " .. table.concat(ctx.extra_args, " ")
+        end
+
+        if ctx.args.detect then
+            local succ, res = pcall(function()
+                return translate.detect(text, ctx.msg.channel):await()
+            end)
+
+            if not succ then
+                return ctx.msg:reply("error: " .. tostring(res)):await()
+            end
+
+            return ctx.msg:reply("detected language: " .. bot.icode_block(ctx.msg.channel, res)):await()
+        end
+
+        local succ, res = pcall(function()
+            return translate.translate(text, {
+                to = ctx.args.to,
+                from = ctx.args.from,
+                channel = ctx.msg.channel,
+            }):await()
+        end)
+
+        if not succ then
+            return ctx.msg:reply("error: " .. tostring(res)):await()
+        end
+
+        local header = (res.source or "?") .. " -> " .. res.target
+
+        return ctx.msg:reply(
+            bot.icode_block(ctx.msg.channel, header) .. " " .. ctx.msg.channel:escape_text(res.text)
+        ):await()
+    end,
+})
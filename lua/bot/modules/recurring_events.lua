@@ -0,0 +1,113 @@
+local WEEK = 60 * 60 * 24 * 7
+-- How far ahead the next occurrence of a recurring event is created
+local CREATE_AHEAD = 60 * 60 * 24 * 6
+
+bot.recurring_events = bot.recurring_events or { entries = {} }
+
+local function next_occurrence(entry)
+    local now = os.time()
+
+    while entry.next_time <= now do
+        entry.next_time = entry.next_time + entry.interval
+    end
+
+    return entry.next_time
+end
+
+local function create_occurrence(entry)
+    local start_time = next_occurrence(entry)
+    local channel = bot.channel(entry.channel_id):await()
+
+    local event = bot.create_scheduled_event(channel.server, {
+        name = entry.name,
+        description = entry.description,
+        start_time = start_time,
+        end_time = entry.duration and start_time + entry.duration,
+        location = entry.location,
+        channel = entry.voice_channel,
+    }):await()
+
+    entry.next_time = start_time + entry.interval
+    bot.recurring_events.save()
+
+    return event
+end
+
+function bot.recurring_events.think()
+    for _, entry in pairs(bot.recurring_events.entries) do
+        if next_occurrence(entry) - os.time() <= CREATE_AHEAD then
+            local succ, err = pcall(create_occurrence, entry)
+
+            if not succ then
+                print("failed to create recurring event \"" .. entry.name .. "\": " .. tostring(err))
+            end
+        end
+    end
+end
+
+function bot.recurring_events.add_weekly(channel, settings)
+    local entry = {
+        channel_id = channel.id,
+        server_id = channel.server.id,
+        name = settings.name,
+        description = settings.description,
+        location = settings.location,
+        voice_channel = settings.channel,
+        duration = settings.duration,
+        next_time = settings.start_time,
+        interval = WEEK,
+    }
+
+    table.insert(bot.recurring_events.entries, entry)
+    bot.recurring_events.save()
+
+    return entry
+end
+
+function bot.recurring_events.list_weekly(server)
+    local entries = {}
+
+    for _, entry in ipairs(bot.recurring_events.entries) do
+        if entry.server_id == server.id then
+            table.insert(entries, entry)
+        end
+    end
+
+    return entries
+end
+
+function bot.recurring_events.remove_weekly(server, index)
+    local entry = bot.recurring_events.list_weekly(server)[index]
+    if not entry then return false end
+
+    for k, v in ipairs(bot.recurring_events.entries) do
+        if v == entry then
+            table.remove(bot.recurring_events.entries, k)
+            break
+        end
+    end
+
+    bot.recurring_events.save()
+
+    return true
+end
+
+function bot.recurring_events.save()
+    bot.set_data("scheduledevents", json.encode(bot.recurring_events.entries)):await()
+end
+
+hooks.add("loaded", "recurring_events", function()
+    local data = bot.get_data("scheduledevents"):await()
+    if data then
+        bot.recurring_events.entries = json.decode(data)
+    end
+
+    if bot.recurring_events.thread then return end
+
+    bot.recurring_events.thread = async.spawn(function()
+        while true do
+            bot.recurring_events.think()
+            async.delay(60):await()
+        end
+    end)
+end)
@@ -46,20 +46,35 @@ function async.FutureMeta:catch(callback)
     return self
 end
 
--- Works only inside of a coroutine pool
+-- Works only inside of a coroutine. Threads resumed by think park until the future settles,
+-- other coroutines check on it every time they're resumed.
 function async.FutureMeta:await()
-    while true do
+    while self.state == async.FUTURE_STATE.Pending or self.state == async.FUTURE_STATE.Executing do
+        local thread_id = async.__park(coroutine.running())
+
+        if thread_id then
+            table.insert(self.waiters, thread_id)
+        end
+
         coroutine.yield()
+    end
 
-        local res = self.results
+    local res = self.results
 
-        if self.state ~= async.FUTURE_STATE.Pending and self.state ~= async.FUTURE_STATE.Executing then
-            if self.state == async.FUTURE_STATE.Resolved then
-                return table.unpack(res, 1, #res)
-            else
-                error(res[1])
-            end
-        end
+    if self.state == async.FUTURE_STATE.Resolved then
+        return table.unpack(res, 1, #res)
+    else
+        -- Raised at the await, so the error points at the code waiting on the future
+        error(res[1], 2)
+    end
+end
+
+local function wake_waiters(future)
+    local waiters = future.waiters
+    future.waiters = {}
+
+    for _, thread_id in ipairs(waiters) do
+        async.__wake(thread_id)
     end
 end
 
@@ -68,6 +83,7 @@ function async.FutureMeta:__handle_resolve(force, ...)
     self.state = async.FUTURE_STATE.Resolved
 
     self.results = {...}
+    wake_waiters(self)
 
     local next_cb = table.remove(self.callbacks, 1)
     local last_res = self.results
@@ -107,6 +123,7 @@ function async.FutureMeta:__handle_reject(force, ...)
     self.state = async.FUTURE_STATE.Rejected
 
     self.results = {...}
+    wake_waiters(self)
     local next_cb = table.remove(self.callbacks, 1)
     local last_res = self.results
 
@@ -159,7 +176,8 @@ end
 function async.__RustFuture()
     local future = setmetatable({
         state = async.FUTURE_STATE.Pending,
-        callbacks = {}
+        callbacks = {},
+        waiters = {}
     }, async.FutureMeta)
 
     return future
@@ -171,7 +189,8 @@ function async.future(future_fn)
     local future = setmetatable({
         future_fn = future_fn,
         state = async.FUTURE_STATE.Pending,
-        callbacks = {}
+        callbacks = {},
+        waiters = {}
     }, async.FutureMeta)
 
     table.insert(next_tick_cbs, future)
@@ -186,11 +205,44 @@ function async.poll()
     end
 end
 
+-- Calls fn every time the job runs, until the job is cancelled
+local function run_job(job, fn)
+    assert(type(fn) == "function")
+
+    local function wait()
+        job:wait():thence(function(ran)
+            if ran then
+                local succ, err = pcall(fn)
+
+                if not succ then
+                    print("scheduled job failed: " .. tostring(err))
+                end
+
+                wait()
+            end
+        end)
+    end
+
+    wait()
+
+    return job
+end
+
+-- Named jobs of the bot state keep their schedule across restarts and catch up on a missed run
+function async.interval(seconds, fn, name)
+    return run_job(async.__interval(seconds, name), fn)
+end
+
+-- Five field cron expressions like "0 9 * * *", in UTC
+function async.cron(expr, fn, name)
+    return run_job(async.__cron(expr, name), fn)
+end
+
 function async.spawn(fn)
     local thread = coroutine.create(fn)
-    local thread_id = async.gen_thread_id()
 
-    debug.getregistry()["__ASYNC_THREADS"][thread_id] = thread
+    -- Spawned threads belong to the same trace as the code spawning them
+    async.__spawn(thread)
 
     return thread
 end
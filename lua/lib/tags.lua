@@ -36,7 +36,9 @@ tags.SCRIPTED_TAGS = {
     },
     lua = {
         fn = function(ctx, code)
-            local err, res = bot.run_sandboxed_lua(ctx.user, ctx.msg, code, {
+            -- Snippets run under the quota of the tag owner rather than the caller
+            local owner = bot.get_user(ctx.tag.uid):await()
+            local err, res = bot.run_sandboxed_lua(owner, ctx.msg, code, {
                 args = ctx.extra_args,
                 user = {
                     name = ctx.user.name,
@@ -119,3 +121,17 @@ function tags.exec_tag(msg, user, channel, tag, extra_args)
 
     return out
 end
+
+function tags.exec_tag_command(msg, name, args)
+    local tag = tags.find_tag(msg.channel.server, name):await()
+
+    if not tag then
+        return
+    end
+
+    local text = tags.exec_tag(msg, msg.author, msg.channel, tag, args)
+
+    if text ~= "" then
+        return msg:reply(msg.channel:escape_text(text)):await()
+    end
+end
@@ -21,3 +21,12 @@ function time.parse_duration(time)
         + math.floor(add_time(time, "M", 60 * 60 * 24 * (365 / 12)))
         + add_time(time, "Y", 60 * 60 * 24 * 365)
 end
+
+-- Accepts durations like 2d4h as well as natural language like "next friday 5pm"
+function time.parse_when(text, zone)
+    if string.match(text, "^%s*%d+[smhdwMY][%dsmhdwMY]*%s*$") then
+        return os.time() + time.parse_duration(text)
+    end
+
+    return time.parse_human(text, zone)
+end
@@ -0,0 +1,59 @@
+local WebSocketMeta = {}
+WebSocketMeta.__index = WebSocketMeta
+
+local function receive(ws)
+    ws.conn:receive():thence(function(msg)
+        if msg == nil then
+            ws.receiving = false
+            return
+        end
+
+        for _, fn in ipairs(ws.message_handlers) do
+            local succ, err = pcall(fn, msg)
+
+            if not succ then
+                print("websocket message handler failed: " .. tostring(err))
+            end
+        end
+
+        receive(ws)
+    end):catch(function(err)
+        ws.receiving = false
+        print("websocket error: " .. tostring(err))
+    end)
+end
+
+function WebSocketMeta:send(data)
+    return self.conn:send(data)
+end
+
+-- Calls fn with every message received until the websocket is closed
+function WebSocketMeta:on_message(fn)
+    assert(type(fn) == "function")
+
+    table.insert(self.message_handlers, fn)
+
+    if not self.receiving then
+        self.receiving = true
+        receive(self)
+    end
+
+    return self
+end
+
+function WebSocketMeta:close(code, reason)
+    return self.conn:close(code, reason)
+end
+
+-- Resolves to the websocket once it's connected
+function http.websocket(url)
+    return async.future(function(resolve, reject)
+        http.__websocket(url):thence(function(conn)
+            resolve(setmetatable({
+                conn = conn,
+                message_handlers = {},
+                receiving = false,
+            }, WebSocketMeta))
+        end):catch(reject)
+    end)
+end
@@ -9,37 +9,12 @@ include("./lib/string.lua")
 include("./sandbox/utils.lua")
 include("./sandbox/env.lua")
 
-local HOOK_EVERY_INSTRUCTION = 32
-
 function sandbox.exec(state, fenv, fn)
-    local instructions_run = state:get_instructions_run()
-    local max_instructions = state:get_instruction_limit()
-
     -- Set the function env
     sandbox.utils.setfenv(fn, fenv)
 
-    -- Create the coroutine thread
+    -- Create the coroutine thread, instructions and time are limited by a hook set from Rust
     local thread = coroutine.create(fn)
-    local timeout = os.clock() + 30
-
-    debug.sethook(
-        thread,
-        function()
-            instructions_run = instructions_run + HOOK_EVERY_INSTRUCTION
-            state:set_instructions_run(instructions_run)
-            if instructions_run >= max_instructions then
-                state:terminate("exec")
-                error("Execution quota exceeded")
-            end
-
-            if os.clock() > timeout then
-                state:terminate("time")
-                error("Execution time limit reached")
-            end
-        end,
-        "",
-        HOOK_EVERY_INSTRUCTION
-    )
 
     return sandbox.run_coroutine(thread)
 end
@@ -95,6 +70,10 @@ local function update_env(fenv, state)
         return state:http_fetch(url, data or {})
     end
     sandbox.utils.setfenv(upd_fenv.http.fetch, fenv)
+    upd_fenv.http.quota_remaining = function()
+        return state:http_quota_remaining()
+    end
+    sandbox.utils.setfenv(upd_fenv.http.quota_remaining, fenv)
 
     upd_fenv.json = {}
     local json = json
@@ -137,6 +116,94 @@ local function update_env(fenv, state)
     end
     sandbox.utils.setfenv(upd_fenv.getmetatable, fenv)
 
+    -- Persistence
+    local rawset = rawset
+    local base_env = sandbox.env.base_env
+    local function persistent_key(k)
+        return type(k) == "string" and base_env[k] == nil and upd_fenv[k] == nil and k ~= "_G" and k ~= "msg"
+    end
+
+    local function plain_data(value, depth)
+        local kind = type(value)
+
+        if kind == "string" or kind == "number" or kind == "boolean" then
+            return value
+        elseif kind ~= "table" or depth > 16 then
+            return nil
+        end
+
+        local out = {}
+
+        for k,v in pairs(value) do
+            local key_kind = type(k)
+
+            if key_kind == "string" or key_kind == "number" then
+                out[k] = plain_data(v, depth + 1)
+            end
+        end
+
+        return out
+    end
+
+    upd_fenv.sandbox = {}
+    upd_fenv.sandbox.save = function()
+        local data = {}
+
+        for k,v in pairs(fenv) do
+            if persistent_key(k) then
+                data[k] = plain_data(v, 1)
+            end
+        end
+
+        state:save_env(json.encode(data)):await()
+    end
+    sandbox.utils.setfenv(upd_fenv.sandbox.save, fenv)
+    upd_fenv.sandbox.restore = function()
+        local data = state:load_env():await()
+
+        if not data then
+            return false
+        end
+
+        for k,v in pairs(json.decode(data)) do
+            if persistent_key(k) then
+                rawset(fenv, k, v)
+            end
+        end
+
+        return true
+    end
+    sandbox.utils.setfenv(upd_fenv.sandbox.restore, fenv)
+    upd_fenv.sandbox.later = function(seconds, text)
+        state:later(seconds, tostring(text))
+    end
+    sandbox.utils.setfenv(upd_fenv.sandbox.later, fenv)
+
+    -- Messaging between scripts and sandbox runs on the same server
+    upd_fenv.ipc = {}
+    upd_fenv.ipc.publish = function(channel, value)
+        state:ipc_publish(tostring(channel), json.encode(plain_data(value, 1)))
+    end
+    sandbox.utils.setfenv(upd_fenv.ipc.publish, fenv)
+    upd_fenv.ipc.subscribe = function(channel)
+        state:ipc_subscribe(tostring(channel))
+    end
+    sandbox.utils.setfenv(upd_fenv.ipc.subscribe, fenv)
+    upd_fenv.ipc.unsubscribe = function(channel)
+        state:ipc_unsubscribe(tostring(channel))
+    end
+    sandbox.utils.setfenv(upd_fenv.ipc.unsubscribe, fenv)
+    upd_fenv.ipc.receive = function()
+        local messages = state:ipc_receive()
+
+        for i = 1, #messages do
+            messages[i].data = json.decode(messages[i].data)
+        end
+
+        return messages
+    end
+    sandbox.utils.setfenv(upd_fenv.ipc.receive, fenv)
+
     -- Update
     local function update_fenv(fenv, upd_fenv)
         for k,v in pairs(upd_fenv) do
@@ -194,7 +261,9 @@ function sandbox.run(state, msg, source, env, main)
         end
     
         if not fn then
-            state:error(tostring(err))
+            if state:error(tostring(err)) then
+                sandbox.reset_env()
+            end
             return
         end
     end
@@ -221,9 +290,13 @@ function sandbox.run(state, msg, source, env, main)
                 local succ, thread, res = sandbox.run_coroutine(thread)
 
                 if not succ then
+                    local out_of_memory = false
                     sandbox.run(state, nil, function()
-                        state:error(tostring(res))
+                        out_of_memory = state:error(tostring(res))
                     end)
+                    if out_of_memory then
+                        sandbox.reset_env()
+                    end
                     return true
                 end
 
@@ -279,12 +352,22 @@ function sandbox.run(state, msg, source, env, main)
         end
     else
         local tostring = tostring
+        local out_of_memory = false
         sandbox.run(state, msg, function()
-            state:error(tostring(res))
+            out_of_memory = state:error(tostring(res))
         end)
+        if out_of_memory then
+            sandbox.reset_env()
+        end
     end
 end
 
+-- Drop the env after a run ran out of memory, whatever it stored there would keep the state at its limit
+function sandbox.reset_env()
+    sandbox.env.env = nil
+    collectgarbage()
+end
+
 function sandbox.think()
     for k,v in pairs(sandbox.tasks) do
         if v() then
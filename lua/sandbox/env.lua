@@ -4,14 +4,24 @@ sandbox.env.base_env = {
     _VERSION = _VERSION,
     os = {
         clock = os.clock,
-        time = os.time
+        clock_monotonic = os.clock_monotonic,
+        time = os.time,
+        uptime = os.uptime,
+        version = os.version,
+        platform = os.platform
     },
     async = async,
     bot = bot,
+    chart = chart,
+    emoji = emoji,
     image = image,
+    latex = latex,
     math = math,
+    qr = qr,
+    storage = storage,
     string = string,
     table = table,
+    time = time,
     -- Iters
     pairs = pairs,
     ipairs = ipairs,
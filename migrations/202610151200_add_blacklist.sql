@@ -0,0 +1,10 @@
+CREATE TABLE blacklist (
+    uid INTEGER NOT NULL,
+    sid INTEGER NOT NULL DEFAULT 0, -- 0 for global entries
+    added_by INTEGER NOT NULL,
+    reason TEXT,
+    expire_time INTEGER, -- unix timestamp, NULL for permanent entries
+    create_time TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
+    FOREIGN KEY(uid) REFERENCES users(uid),
+    PRIMARY KEY (uid, sid)
+);
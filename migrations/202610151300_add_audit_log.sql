@@ -0,0 +1,11 @@
+CREATE TABLE audit_log (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    actor_uid INTEGER, -- NULL for actions taken by the bot itself
+    action TEXT NOT NULL,
+    scope TEXT NOT NULL,
+    before TEXT,
+    after TEXT,
+    time TIMESTAMP DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX audit_log_action ON audit_log(action);
@@ -0,0 +1,6 @@
+CREATE TABLE secrets (
+    name TEXT NOT NULL PRIMARY KEY,
+    value TEXT NOT NULL,
+    version INTEGER NOT NULL DEFAULT 1,
+    update_time TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
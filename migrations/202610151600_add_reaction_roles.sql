@@ -0,0 +1,14 @@
+CREATE TABLE reaction_roles (
+    message_id TEXT NOT NULL, -- short service id, e.g. "d:1234"
+    emoji TEXT NOT NULL,
+    sid INTEGER NOT NULL,
+    channel_id TEXT NOT NULL,
+    role_id TEXT NOT NULL,
+    exclusive INTEGER NOT NULL DEFAULT 0, -- members can only hold one role of the message
+    added_by INTEGER NOT NULL,
+    create_time TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
+    FOREIGN KEY(sid) REFERENCES servers(sid),
+    PRIMARY KEY (message_id, emoji)
+);
+
+CREATE INDEX reaction_roles_sid ON reaction_roles(sid);
@@ -0,0 +1,11 @@
+CREATE TABLE temp_bans (
+    server_id TEXT NOT NULL, -- service id, e.g. "discord:1234"
+    user_id TEXT NOT NULL,
+    added_by INTEGER NOT NULL,
+    reason TEXT,
+    expire_time INTEGER NOT NULL, -- unix timestamp the ban is lifted at
+    create_time TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
+    PRIMARY KEY (server_id, user_id)
+);
+
+CREATE INDEX temp_bans_expire_time ON temp_bans(expire_time);
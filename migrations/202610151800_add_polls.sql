@@ -0,0 +1,21 @@
+CREATE TABLE polls (
+    message_id TEXT PRIMARY KEY NOT NULL, -- short service id, e.g. "d:1234"
+    channel_id TEXT NOT NULL,
+    title TEXT NOT NULL,
+    options TEXT NOT NULL, -- JSON array of the option labels
+    buttons INTEGER NOT NULL DEFAULT 0, -- voted on with buttons instead of reactions
+    created_by INTEGER NOT NULL,
+    close_time INTEGER, -- unix timestamp the poll is closed at, open until closed by hand if null
+    closed INTEGER NOT NULL DEFAULT 0,
+    create_time TIMESTAMP DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX polls_close_time ON polls(closed, close_time);
+
+CREATE TABLE poll_votes (
+    message_id TEXT NOT NULL,
+    user_id TEXT NOT NULL, -- short service id
+    option INTEGER NOT NULL, -- index into the poll options
+    FOREIGN KEY(message_id) REFERENCES polls(message_id),
+    PRIMARY KEY (message_id, user_id)
+);
@@ -0,0 +1,15 @@
+CREATE TABLE quotes (
+    sid INTEGER NOT NULL,
+    number INTEGER NOT NULL, -- counts up per server
+    message_id TEXT NOT NULL, -- short service id of the quoted message
+    author_id TEXT NOT NULL, -- short service id of the quoted user
+    author_name TEXT NOT NULL,
+    content TEXT NOT NULL,
+    link TEXT, -- jumps to the quoted message for context
+    added_by INTEGER NOT NULL,
+    create_time TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
+    FOREIGN KEY(sid) REFERENCES servers(sid),
+    PRIMARY KEY (sid, number)
+);
+
+CREATE UNIQUE INDEX quotes_message_id ON quotes(sid, message_id);
@@ -0,0 +1,9 @@
+CREATE TABLE server_scripts (
+    sid INTEGER NOT NULL,
+    name TEXT NOT NULL, -- also the command that runs the script
+    source TEXT NOT NULL,
+    installed_by INTEGER NOT NULL,
+    create_time TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
+    FOREIGN KEY(sid) REFERENCES servers(sid),
+    PRIMARY KEY (sid, name)
+);
@@ -0,0 +1,11 @@
+CREATE TABLE setting_expiries (
+    kind TEXT NOT NULL, -- "server" or "channel", like the settings tables
+    location TEXT NOT NULL, -- short service id of the server or channel
+    key TEXT NOT NULL,
+    previous TEXT, -- raw value from before the temporary change, NULL reverts to the default
+    announce_channel TEXT, -- short service id of the channel the reversion is announced in
+    expire_time INTEGER NOT NULL, -- unix timestamp the setting is reverted at
+    PRIMARY KEY (kind, location, key)
+);
+
+CREATE INDEX setting_expiries_expire_time ON setting_expiries(expire_time);
@@ -0,0 +1,11 @@
+CREATE TABLE blobs (
+    sid INTEGER NOT NULL,
+    name TEXT NOT NULL,
+    hash TEXT NOT NULL, -- sha256 of the data, also the file name in the blobs directory
+    size INTEGER NOT NULL,
+    create_time TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
+    FOREIGN KEY(sid) REFERENCES servers(sid),
+    PRIMARY KEY (sid, name)
+);
+
+CREATE INDEX blobs_hash ON blobs(hash);
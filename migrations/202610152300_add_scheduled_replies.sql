@@ -0,0 +1,12 @@
+CREATE TABLE scheduled_replies (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    channel_id TEXT NOT NULL, -- service id, e.g. "discord:1234"
+    message_id TEXT NOT NULL, -- service id of the message replied to
+    content TEXT NOT NULL,
+    created_by INTEGER NOT NULL,
+    send_time INTEGER NOT NULL, -- unix timestamp the reply is sent at
+    create_time TIMESTAMP DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX scheduled_replies_send_time ON scheduled_replies(send_time);
+CREATE INDEX scheduled_replies_created_by ON scheduled_replies(created_by);
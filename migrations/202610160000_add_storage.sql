@@ -0,0 +1,7 @@
+CREATE TABLE storage (
+    namespace TEXT NOT NULL, -- "server:<sid>" or "user:<uid>"
+    key TEXT NOT NULL,
+    value TEXT NOT NULL, -- json encoded
+    update_time TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
+    PRIMARY KEY (namespace, key)
+);
@@ -0,0 +1,4 @@
+CREATE TABLE scheduled_jobs (
+    name TEXT PRIMARY KEY NOT NULL, -- given by the script registering the job
+    next_run INTEGER NOT NULL
+);
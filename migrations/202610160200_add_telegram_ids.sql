@@ -0,0 +1,5 @@
+ALTER TABLE users ADD COLUMN telegram_id INTEGER;
+CREATE UNIQUE INDEX users_telegram_id ON users (telegram_id);
+
+ALTER TABLE servers ADD COLUMN telegram_id INTEGER;
+CREATE UNIQUE INDEX servers_telegram_id ON servers (telegram_id);
@@ -3,25 +3,45 @@ use arc_swap::ArcSwapOption;
 use std::{
     path::{Path, PathBuf},
     sync::Arc,
+    time::{Duration, Instant},
 };
 
+pub mod admin;
 pub mod db;
+pub mod events;
+pub mod handoff;
+pub mod metrics;
+pub mod permissions;
 
 use crate::{
     config::Config,
     modules::Modules,
-    services::{ChannelId, Message, MessageId, ServerId, Service, Services, User},
+    server::HttpServer,
+    services::{
+        Channel, ChannelId, InteractionId, Message, MessageId, Server, ServerId, Service,
+        Services, User, UserId,
+    },
 };
 use db::BotDb;
+use events::EventBus;
+use metrics::Metrics;
+use permissions::Permissions;
 
 pub const ROLES: &[&'static str] = &["guest", "trusted", "admin", "root"];
 pub const DEFAULT_ROLE: &'static str = ROLES[0];
 
 pub struct Bot {
     ctx: ArcSwapOption<BotContext>,
+    config: Config,
     db: Arc<BotDb>,
+    vault: Arc<Vault>,
+    events: EventBus,
+    permissions: Permissions,
+    metrics: Metrics,
+    http_server: Arc<HttpServer>,
     data_path: PathBuf,
     share_path: PathBuf,
+    started: Instant,
 }
 
 macro_rules! get_ctx {
@@ -39,14 +59,34 @@ impl Bot {
         share_path: PathBuf,
         config: &Config,
     ) -> Result<Arc<Bot>> {
+        let db = BotDb::new(&data_path, &share_path, config).await?;
+        let vault = Vault::new(db.clone(), &data_path)?;
+
+        // Tokens can be kept out of the config as references to vault secrets
+        let mut config = config.clone();
+        vault.resolve_config(&mut config).await?;
+
+        let permissions = Permissions::from_config(&config)?;
+
         Ok(Arc::new(Bot {
             ctx: ArcSwapOption::default(),
-            db: BotDb::new(&data_path, &share_path, config).await?,
+            config,
+            db,
+            vault,
+            events: EventBus::default(),
+            permissions,
+            metrics: Metrics::default(),
+            http_server: HttpServer::new(),
             data_path,
             share_path,
+            started: Instant::now(),
         }))
     }
 
+    pub fn uptime(&self) -> Duration {
+        self.started.elapsed()
+    }
+
     pub fn data_path(&self) -> &Path {
         &self.data_path
     }
@@ -55,10 +95,34 @@ impl Bot {
         &self.share_path
     }
 
+    pub fn config(&self) -> &Config {
+        &self.config
+    }
+
     pub fn db(&self) -> &Arc<BotDb> {
         &self.db
     }
 
+    pub fn vault(&self) -> &Arc<Vault> {
+        &self.vault
+    }
+
+    pub fn events(&self) -> &EventBus {
+        &self.events
+    }
+
+    pub fn permissions(&self) -> &Permissions {
+        &self.permissions
+    }
+
+    pub fn metrics(&self) -> &Metrics {
+        &self.metrics
+    }
+
+    pub fn http_server(&self) -> &Arc<HttpServer> {
+        &self.http_server
+    }
+
     pub fn set_ctx(&self, ctx: Arc<BotContext>) {
         self.ctx.store(Some(ctx));
     }
@@ -73,9 +137,39 @@ impl Bot {
         }
     }
 
-    pub async fn message(&self, msg: Arc<dyn Message<impl Service>>) {
+    // Blacklisted users are dropped before reaching any module, DMs only check the global blacklist
+    async fn is_blacklisted(
+        &self,
+        user_id: UserId,
+        msg: &Arc<dyn Message<impl Service>>,
+    ) -> Result<bool> {
+        let server_id = match msg.channel().await {
+            Ok(channel) => channel.server().await.ok().map(|server| server.id()),
+            Err(_) => None,
+        };
+
+        self.db.is_blacklisted(user_id, server_id).await
+    }
+
+    async fn should_drop(&self, user_id: UserId, msg: &Arc<dyn Message<impl Service>>) -> bool {
+        match self.is_blacklisted(user_id, msg).await {
+            Ok(blacklisted) => blacklisted,
+            Err(err) => {
+                println!("error checking blacklist: {}", err.to_string());
+                false
+            }
+        }
+    }
+
+    pub async fn message<S: Service>(&self, msg: Arc<dyn Message<S>>) {
         let ctx = get_ctx!(self);
 
+        self.metrics.count_message_received(S::NAME);
+
+        if self.should_drop(msg.author().id(), &msg).await {
+            return;
+        }
+
         ctx.modules().message(msg).await;
     }
 
@@ -86,6 +180,10 @@ impl Bot {
     ) {
         let ctx = get_ctx!(self);
 
+        if self.should_drop(msg.author().id(), &msg).await {
+            return;
+        }
+
         ctx.modules().message_update(msg, old_msg).await;
     }
 
@@ -111,8 +209,42 @@ impl Bot {
     ) {
         let ctx = get_ctx!(self);
 
+        if self.should_drop(reactor.id(), &msg).await {
+            return;
+        }
+
         ctx.modules().reaction(msg, reactor, reaction, remove).await;
     }
+
+    pub async fn member(
+        &self,
+        server_id: ServerId,
+        user: Arc<dyn User<impl Service>>,
+        joined: bool,
+    ) {
+        let ctx = get_ctx!(self);
+
+        ctx.modules().member(server_id, user, joined).await;
+    }
+
+    pub async fn component(
+        &self,
+        msg: Arc<dyn Message<impl Service>>,
+        user: Arc<dyn User<impl Service>>,
+        id: String,
+        values: Vec<String>,
+        interaction_id: InteractionId,
+    ) {
+        let ctx = get_ctx!(self);
+
+        if self.should_drop(user.id(), &msg).await {
+            return;
+        }
+
+        ctx.modules()
+            .component(msg, user, id, values, interaction_id)
+            .await;
+    }
 }
 
 pub struct BotContext {
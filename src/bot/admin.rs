@@ -0,0 +1,166 @@
+use anyhow::Result;
+use hyper::{Body, Request, Response, StatusCode};
+use std::{collections::HashMap, sync::Arc};
+
+use super::Bot;
+use crate::server::{status_response, HttpHandler};
+
+pub const RESTART_PATH: &str = "/admin/restart";
+pub const AUDIT_PATH: &str = "/admin/audit";
+
+const DEFAULT_AUDIT_ENTRIES: i64 = 50;
+const MAX_AUDIT_ENTRIES: i64 = 500;
+
+/// Bearer token of the admin endpoints, if the config sets one
+pub fn admin_token(bot: &Bot) -> Option<String> {
+    bot.config()
+        .http
+        .as_ref()
+        .and_then(|http| http.admin_token.clone())
+        .filter(|token| !token.is_empty())
+}
+
+/// Register the admin endpoints if the config has a token for them
+pub fn register(bot: &Arc<Bot>) {
+    let token = match admin_token(bot) {
+        Some(token) => token,
+        None => return,
+    };
+
+    bot.http_server().register(
+        RESTART_PATH,
+        Arc::new(RestartHandler {
+            bot: bot.clone(),
+            token: token.clone(),
+        }),
+    );
+    bot.http_server().register(
+        AUDIT_PATH,
+        Arc::new(AuditHandler {
+            bot: bot.clone(),
+            token,
+        }),
+    );
+}
+
+// Compares every byte so the time taken doesn't give away how much of the token matched
+fn token_matches(token: &str, given: &str) -> bool {
+    token.len() == given.len()
+        && token
+            .bytes()
+            .zip(given.bytes())
+            .fold(0, |diff, (a, b)| diff | (a ^ b))
+            == 0
+}
+
+pub fn authorized(req: &Request<Body>, token: &str) -> bool {
+    req.headers()
+        .get(hyper::header::AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "))
+        .map(|given| token_matches(token, given))
+        .unwrap_or(false)
+}
+
+fn query_params(req: &Request<Body>) -> HashMap<String, String> {
+    req.uri()
+        .query()
+        .map(|query| {
+            url::form_urlencoded::parse(query.as_bytes())
+                .into_owned()
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// `POST /admin/restart?service=<id>`, reconnects a single service in the background
+struct RestartHandler {
+    bot: Arc<Bot>,
+    token: String,
+}
+
+#[async_trait]
+impl HttpHandler for RestartHandler {
+    async fn handle(&self, req: Request<Body>) -> Result<Response<Body>> {
+        if req.method() != hyper::Method::POST {
+            return Ok(status_response(StatusCode::METHOD_NOT_ALLOWED));
+        }
+
+        if !authorized(&req, &self.token) {
+            return Ok(status_response(StatusCode::UNAUTHORIZED));
+        }
+
+        let query = query_params(&req);
+
+        let service = match query.get("service") {
+            Some(service) if self.bot.get_ctx().services().is_started(service) => service.clone(),
+            _ => return Ok(status_response(StatusCode::NOT_FOUND)),
+        };
+
+        let bot = self.bot.clone();
+        tokio::spawn(async move {
+            if let Err(err) = bot.get_ctx().services().restart(&service).await {
+                println!("error restarting {}: {}", service, err.to_string());
+                return;
+            }
+
+            if let Err(err) = bot
+                .db()
+                .audit(None, "service.restart", &service, None, None)
+                .await
+            {
+                println!("error auditing service restart: {}", err.to_string());
+            }
+        });
+
+        Ok(status_response(StatusCode::ACCEPTED))
+    }
+}
+
+/// `GET /admin/audit?action=<action>&actor=<uid>&scope=<scope>&limit=<n>`, the newest audit log
+/// entries as json
+struct AuditHandler {
+    bot: Arc<Bot>,
+    token: String,
+}
+
+#[async_trait]
+impl HttpHandler for AuditHandler {
+    async fn handle(&self, req: Request<Body>) -> Result<Response<Body>> {
+        if req.method() != hyper::Method::GET {
+            return Ok(status_response(StatusCode::METHOD_NOT_ALLOWED));
+        }
+
+        if !authorized(&req, &self.token) {
+            return Ok(status_response(StatusCode::UNAUTHORIZED));
+        }
+
+        let query = query_params(&req);
+
+        let actor = match query.get("actor").map(|actor| actor.parse()) {
+            Some(Ok(actor)) => Some(actor),
+            Some(Err(_)) => return Ok(status_response(StatusCode::BAD_REQUEST)),
+            None => None,
+        };
+        let limit = match query.get("limit").map(|limit| limit.parse::<i64>()) {
+            Some(Ok(limit)) => limit.clamp(1, MAX_AUDIT_ENTRIES),
+            Some(Err(_)) => return Ok(status_response(StatusCode::BAD_REQUEST)),
+            None => DEFAULT_AUDIT_ENTRIES,
+        };
+
+        let entries = self
+            .bot
+            .db()
+            .list_audit_log(
+                query.get("action").map(|action| action.as_str()),
+                actor,
+                query.get("scope").map(|scope| scope.as_str()),
+                limit,
+            )
+            .await?;
+
+        Ok(Response::builder()
+            .header("Content-Type", "application/json")
+            .body(Body::from(serde_json::to_vec(&entries)?))?)
+    }
+}
@@ -9,7 +9,7 @@ use std::{path::Path, sync::Arc};
 use super::{DEFAULT_ROLE, ROLES};
 use crate::{
     config::Config,
-    services::{ChannelId, ServerId, UserId},
+    services::{ChannelId, MessageId, ServerId, UserId},
 };
 
 pub type Uid = i64;
@@ -46,42 +46,39 @@ impl BotDb {
     }
 
     pub async fn get_user_from_uid(&self, uid: Uid) -> Result<User> {
-        let (role, discord_id): (Option<String>, Option<Vec<u8>>) =
-            sqlx::query_as("SELECT role, discord_id FROM users WHERE uid = ?")
-                .bind(uid)
-                .fetch_one(self.pool())
-                .await?;
-
-        let role = role
-            .filter(|role| ROLES.contains(&role.as_str()))
-            .unwrap_or_else(|| DEFAULT_ROLE.into());
-        let discord_id = discord_id.map(|data| {
-            let mut bytes = [0u8; 8];
-            bytes.clone_from_slice(&data[0..8]);
-            u64::from_le_bytes(bytes)
-        });
+        let (role, discord_id, telegram_id, timezone): (
+            Option<String>,
+            Option<Vec<u8>>,
+            Option<i64>,
+            Option<String>,
+        ) = sqlx::query_as(
+            "SELECT role, discord_id, telegram_id, timezone FROM users WHERE uid = ?",
+        )
+        .bind(uid)
+        .fetch_one(self.pool())
+        .await?;
 
-        Ok(User {
-            uid,
-            role,
-            discord_id,
-        })
+        Ok(User::from_row(uid, role, discord_id, telegram_id, timezone))
     }
 
     pub async fn get_user_from_service_user_id(&self, service_user_id: UserId) -> Result<User> {
-        let res: Result<(Uid, Option<String>, Option<Vec<u8>>), sqlx::Error> =
+        let res: Result<(Uid, Option<String>, Option<Vec<u8>>, Option<i64>, Option<String>), sqlx::Error> =
             match service_user_id {
-                UserId::Discord(discord_id) => {
-                    sqlx::query_as("SELECT uid, role, discord_id FROM users WHERE discord_id = ?")
-                        .bind(discord_id.to_le_bytes().to_vec())
-                }
+                UserId::Discord(discord_id) => sqlx::query_as(
+                    "SELECT uid, role, discord_id, telegram_id, timezone FROM users WHERE discord_id = ?",
+                )
+                .bind(discord_id.to_le_bytes().to_vec()),
+                UserId::Telegram(telegram_id) => sqlx::query_as(
+                    "SELECT uid, role, discord_id, telegram_id, timezone FROM users WHERE telegram_id = ?",
+                )
+                .bind(telegram_id),
             }
             .fetch_one(self.pool())
             .await;
 
-        let (uid, role, discord_id) = match res {
+        let (uid, role, discord_id, telegram_id, timezone) = match res {
             Err(sqlx::Error::RowNotFound) => {
-                let (res, discord_id) = match service_user_id {
+                let (res, discord_id, telegram_id) = match service_user_id {
                     UserId::Discord(discord_id) => (
                         self.pool()
                             .execute(
@@ -90,29 +87,27 @@ impl BotDb {
                             )
                             .await?,
                         Some(discord_id.to_le_bytes().to_vec()),
+                        None,
+                    ),
+                    UserId::Telegram(telegram_id) => (
+                        self.pool()
+                            .execute(
+                                sqlx::query("INSERT INTO users ( telegram_id ) VALUES ( ? )")
+                                    .bind(telegram_id),
+                            )
+                            .await?,
+                        None,
+                        Some(telegram_id),
                     ),
                 };
 
-                (res.last_insert_rowid(), None, discord_id)
+                (res.last_insert_rowid(), None, discord_id, telegram_id, None)
             }
             Err(err) => return Err(err.into()),
             Ok(res) => res,
         };
 
-        let role = role
-            .filter(|role| ROLES.contains(&role.as_str()))
-            .unwrap_or_else(|| DEFAULT_ROLE.into());
-        let discord_id = discord_id.map(|data| {
-            let mut bytes = [0u8; 8];
-            bytes.clone_from_slice(&data[0..8]);
-            u64::from_le_bytes(bytes)
-        });
-
-        Ok(User {
-            uid,
-            role,
-            discord_id,
-        })
+        Ok(User::from_row(uid, role, discord_id, telegram_id, timezone))
     }
 
     pub async fn set_role_for_user(&self, user_id: Uid, role: &str) -> Result<()> {
@@ -131,6 +126,18 @@ impl BotDb {
         Ok(())
     }
 
+    pub async fn set_timezone_for_user(&self, user_id: Uid, timezone: Option<&str>) -> Result<()> {
+        self.pool()
+            .execute(
+                sqlx::query("UPDATE users SET timezone = ? WHERE uid = ?")
+                    .bind(timezone)
+                    .bind(user_id),
+            )
+            .await?;
+
+        Ok(())
+    }
+
     pub async fn restrict_user(&self, user_id: Uid, restrictor_user_id: Uid) -> Result<()> {
         self.pool()
             .execute(
@@ -165,6 +172,260 @@ impl BotDb {
         Ok(restricted)
     }
 
+    // Blacklist
+    pub async fn blacklist_user(
+        &self,
+        uid: Uid,
+        sid: Option<Sid>,
+        actor_uid: Uid,
+        reason: Option<&str>,
+        expire_time: Option<i64>,
+    ) -> Result<()> {
+        let sid = sid.unwrap_or(0);
+        let mut tx = self.pool().begin().await?;
+
+        tx.execute(
+            sqlx::query(
+                "REPLACE INTO blacklist ( uid, sid, added_by, reason, expire_time ) VALUES ( ?, ?, ?, ?, ? )",
+            )
+            .bind(uid)
+            .bind(sid)
+            .bind(actor_uid)
+            .bind(reason)
+            .bind(expire_time),
+        )
+        .await?;
+
+        insert_audit(
+            &mut tx,
+            Some(actor_uid),
+            "blacklist.add",
+            &blacklist_scope(sid),
+            None,
+            Some(
+                &serde_json::json!({ "uid": uid, "reason": reason, "expire_time": expire_time })
+                    .to_string(),
+            ),
+        )
+        .await?;
+
+        tx.commit().await?;
+
+        Ok(())
+    }
+
+    pub async fn unblacklist_user(&self, uid: Uid, sid: Option<Sid>, actor_uid: Uid) -> Result<bool> {
+        let sid = sid.unwrap_or(0);
+        let mut tx = self.pool().begin().await?;
+
+        let res = tx
+            .execute(
+                sqlx::query("DELETE FROM blacklist WHERE uid = ? AND sid = ?")
+                    .bind(uid)
+                    .bind(sid),
+            )
+            .await?;
+
+        if res.rows_affected() == 0 {
+            return Ok(false);
+        }
+
+        insert_audit(
+            &mut tx,
+            Some(actor_uid),
+            "blacklist.remove",
+            &blacklist_scope(sid),
+            Some(&serde_json::json!({ "uid": uid }).to_string()),
+            None,
+        )
+        .await?;
+
+        tx.commit().await?;
+
+        Ok(true)
+    }
+
+    /// Check if the user is blacklisted globally or in the server, ignoring expired entries.
+    /// Looks up the user and server by their service ids in the same query, users and servers
+    /// missing from the db can't be blacklisted
+    pub async fn is_blacklisted(
+        &self,
+        user_id: UserId,
+        server_id: Option<ServerId>,
+    ) -> Result<bool> {
+        let (count,): (i64,) = match user_id {
+            UserId::Discord(discord_id) => sqlx::query_as(
+                "SELECT COUNT(*) FROM blacklist JOIN users ON users.uid = blacklist.uid LEFT JOIN servers ON servers.sid = blacklist.sid WHERE users.discord_id = ? AND (blacklist.sid = 0 OR servers.discord_id = ?) AND (blacklist.expire_time IS NULL OR blacklist.expire_time > ?)",
+            )
+            .bind(discord_id.to_le_bytes().to_vec())
+            .bind(match server_id {
+                Some(ServerId::Discord(discord_id)) => Some(discord_id.to_le_bytes().to_vec()),
+                _ => None,
+            }),
+            UserId::Telegram(telegram_id) => sqlx::query_as(
+                "SELECT COUNT(*) FROM blacklist JOIN users ON users.uid = blacklist.uid LEFT JOIN servers ON servers.sid = blacklist.sid WHERE users.telegram_id = ? AND (blacklist.sid = 0 OR servers.telegram_id = ?) AND (blacklist.expire_time IS NULL OR blacklist.expire_time > ?)",
+            )
+            .bind(telegram_id)
+            .bind(match server_id {
+                Some(ServerId::Telegram(telegram_id)) => Some(telegram_id),
+                _ => None,
+            }),
+        }
+        .bind(chrono::Utc::now().timestamp())
+        .fetch_one(self.pool())
+        .await?;
+
+        Ok(count > 0)
+    }
+
+    pub async fn list_blacklist(&self, sid: Option<Sid>) -> Result<Vec<BlacklistEntry>> {
+        Ok(sqlx::query_as::<_, BlacklistEntry>(
+            "SELECT uid, sid, added_by, reason, expire_time FROM blacklist WHERE sid = ? AND (expire_time IS NULL OR expire_time > ?) ORDER BY create_time",
+        )
+        .bind(sid.unwrap_or(0))
+        .bind(chrono::Utc::now().timestamp())
+        .fetch_all(self.pool())
+        .await?)
+    }
+
+    // Audit log
+    pub async fn audit(
+        &self,
+        actor_uid: Option<Uid>,
+        action: &str,
+        scope: &str,
+        before: Option<&str>,
+        after: Option<&str>,
+    ) -> Result<()> {
+        insert_audit(self.pool(), actor_uid, action, scope, before, after).await
+    }
+
+    /// Fetch the most recent audit entries, optionally filtered by action prefix, actor and scope
+    pub async fn list_audit_log(
+        &self,
+        action: Option<&str>,
+        actor_uid: Option<Uid>,
+        scope: Option<&str>,
+        limit: i64,
+    ) -> Result<Vec<AuditEntry>> {
+        Ok(sqlx::query_as::<_, AuditEntry>(
+            "SELECT id, actor_uid, action, scope, before, after, CAST(strftime('%s', time) AS INTEGER) AS timestamp FROM audit_log
+                WHERE (?1 IS NULL OR action = ?1 OR action LIKE ?1 || '.%')
+                AND (?2 IS NULL OR actor_uid = ?2)
+                AND (?3 IS NULL OR scope = ?3)
+                ORDER BY id DESC LIMIT ?4",
+        )
+        .bind(action)
+        .bind(actor_uid)
+        .bind(scope)
+        .bind(limit)
+        .fetch_all(self.pool())
+        .await?)
+    }
+
+    // Secrets, values are encrypted by the vault before they get here
+    pub async fn get_secret(&self, name: &str) -> Result<Option<String>> {
+        sqlx::query_as("SELECT value FROM secrets WHERE name = ?")
+            .bind(name)
+            .fetch_one(self.pool())
+            .await
+            .map(|val: (String,)| Some(val.0))
+            .or_else(|err| match err {
+                sqlx::Error::RowNotFound => Ok(None),
+                _ => Err(err.into()),
+            })
+    }
+
+    pub async fn save_secret(&self, name: &str, value: &str) -> Result<i64> {
+        let mut tx = self.pool().begin().await?;
+
+        tx.execute(
+            sqlx::query(
+                "INSERT INTO secrets ( name, value ) VALUES ( ?1, ?2 )
+                    ON CONFLICT ( name ) DO UPDATE SET value = ?2, version = version + 1, update_time = CURRENT_TIMESTAMP",
+            )
+            .bind(name)
+            .bind(value),
+        )
+        .await?;
+
+        let (version,): (i64,) = sqlx::query_as("SELECT version FROM secrets WHERE name = ?")
+            .bind(name)
+            .fetch_one(&mut tx)
+            .await?;
+
+        tx.commit().await?;
+
+        Ok(version)
+    }
+
+    pub async fn delete_secret(&self, name: &str) -> Result<bool> {
+        let res = self
+            .pool()
+            .execute(sqlx::query("DELETE FROM secrets WHERE name = ?").bind(name))
+            .await?;
+
+        Ok(res.rows_affected() > 0)
+    }
+
+    pub async fn list_secrets(&self) -> Result<Vec<SecretEntry>> {
+        Ok(sqlx::query_as::<_, SecretEntry>(
+            "SELECT name, version, CAST(strftime('%s', update_time) AS INTEGER) AS update_timestamp FROM secrets ORDER BY name",
+        )
+        .fetch_all(self.pool())
+        .await?)
+    }
+
+    /// Every value encrypted with the vault key, secrets and secret settings, also temporarily replaced ones
+    pub async fn list_encrypted_values(&self) -> Result<Vec<EncryptedValue>> {
+        Ok(sqlx::query_as::<_, EncryptedValue>(
+            "SELECT 'secret' AS kind, name AS id, '' AS key, value FROM secrets
+                UNION ALL SELECT 'server', server_id, key, value FROM settings_server WHERE value LIKE 'enc:%'
+                UNION ALL SELECT 'channel', channel_id, key, value FROM settings_channel WHERE value LIKE 'enc:%'
+                UNION ALL SELECT 'expiry_' || kind, location, key, previous FROM setting_expiries WHERE previous LIKE 'enc:%'",
+        )
+        .fetch_all(self.pool())
+        .await?)
+    }
+
+    pub async fn replace_encrypted_values(&self, values: &[EncryptedValue]) -> Result<()> {
+        let mut tx = self.pool().begin().await?;
+
+        for value in values {
+            let query = match value.kind.as_str() {
+                "secret" => sqlx::query("UPDATE secrets SET value = ? WHERE name = ?")
+                    .bind(&value.value)
+                    .bind(&value.id),
+                "server" => {
+                    sqlx::query("UPDATE settings_server SET value = ? WHERE server_id = ? AND key = ?")
+                        .bind(&value.value)
+                        .bind(&value.id)
+                        .bind(&value.key)
+                }
+                "channel" => {
+                    sqlx::query("UPDATE settings_channel SET value = ? WHERE channel_id = ? AND key = ?")
+                        .bind(&value.value)
+                        .bind(&value.id)
+                        .bind(&value.key)
+                }
+                kind if kind.starts_with("expiry_") => sqlx::query(
+                    "UPDATE setting_expiries SET previous = ? WHERE kind = ? AND location = ? AND key = ?",
+                )
+                .bind(&value.value)
+                .bind(&kind["expiry_".len()..])
+                .bind(&value.id)
+                .bind(&value.key),
+                kind => return Err(anyhow!("unknown encrypted value kind \"{}\"", kind)),
+            };
+
+            tx.execute(query).await?;
+        }
+
+        tx.commit().await?;
+
+        Ok(())
+    }
+
     pub async fn get_channel_setting(
         &self,
         channel_id: ChannelId,
@@ -239,12 +500,84 @@ impl BotDb {
         Ok(())
     }
 
+    pub async fn remove_channel_setting(&self, channel_id: ChannelId, key: &str) -> Result<()> {
+        self.pool()
+            .execute(
+                sqlx::query("DELETE FROM settings_channel WHERE channel_id = ? AND key = ?")
+                    .bind(channel_id.to_short_str())
+                    .bind(key),
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    pub async fn remove_server_setting(&self, server_id: ServerId, key: &str) -> Result<()> {
+        self.pool()
+            .execute(
+                sqlx::query("DELETE FROM settings_server WHERE server_id = ? AND key = ?")
+                    .bind(server_id.to_short_str())
+                    .bind(key),
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    /// Extending a temporary setting keeps the value from before the first change
+    pub async fn add_setting_expiry(&self, expiry: &SettingExpiry) -> Result<()> {
+        self.pool()
+            .execute(
+                sqlx::query(
+                    "INSERT INTO setting_expiries ( kind, location, key, previous, announce_channel, expire_time ) VALUES ( ?, ?, ?, ?, ?, ? )
+                        ON CONFLICT ( kind, location, key ) DO UPDATE SET announce_channel = excluded.announce_channel, expire_time = excluded.expire_time",
+                )
+                .bind(&expiry.kind)
+                .bind(&expiry.location)
+                .bind(&expiry.key)
+                .bind(&expiry.previous)
+                .bind(&expiry.announce_channel)
+                .bind(expiry.expire_time),
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    pub async fn remove_setting_expiry(&self, kind: &str, location: &str, key: &str) -> Result<()> {
+        self.pool()
+            .execute(
+                sqlx::query(
+                    "DELETE FROM setting_expiries WHERE kind = ? AND location = ? AND key = ?",
+                )
+                .bind(kind)
+                .bind(location)
+                .bind(key),
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    /// Temporary settings that should be reverted by now
+    pub async fn expired_settings(&self, now: i64) -> Result<Vec<SettingExpiry>> {
+        Ok(sqlx::query_as::<_, SettingExpiry>(
+            "SELECT kind, location, key, previous, announce_channel, expire_time FROM setting_expiries WHERE expire_time <= ?",
+        )
+        .bind(now)
+        .fetch_all(self.pool())
+        .await?)
+    }
+
     pub async fn get_sid(&self, server_id: ServerId) -> Result<Sid> {
         let res: Result<(Sid,), sqlx::Error> = match server_id {
             ServerId::Discord(discord_id) => {
                 sqlx::query_as("SELECT sid FROM servers WHERE discord_id = ?")
                     .bind(discord_id.to_le_bytes().to_vec())
             }
+            ServerId::Telegram(telegram_id) => {
+                sqlx::query_as("SELECT sid FROM servers WHERE telegram_id = ?").bind(telegram_id)
+            }
         }
         .fetch_one(self.pool())
         .await;
@@ -260,6 +593,14 @@ impl BotDb {
                             )
                             .await?
                     }
+                    ServerId::Telegram(telegram_id) => {
+                        self.pool()
+                            .execute(
+                                sqlx::query("INSERT INTO servers ( telegram_id ) VALUES ( ? )")
+                                    .bind(telegram_id),
+                            )
+                            .await?
+                    }
                 };
 
                 Ok(res.last_insert_rowid())
@@ -396,22 +737,941 @@ impl BotDb {
         Ok(res.into_iter().map(|t| t.key).collect())
     }
 
-    pub fn pool(&self) -> &Pool<Sqlite> {
-        &self.pool
+    // Reaction roles
+    pub async fn add_reaction_role(&self, role: &ReactionRole, actor_uid: Uid) -> Result<()> {
+        let mut tx = self.pool().begin().await?;
+
+        tx.execute(
+            sqlx::query(
+                "INSERT INTO reaction_roles ( message_id, emoji, sid, channel_id, role_id, exclusive, added_by ) VALUES ( ?, ?, ?, ?, ?, ?, ? )",
+            )
+            .bind(&role.message_id)
+            .bind(&role.emoji)
+            .bind(role.sid)
+            .bind(&role.channel_id)
+            .bind(&role.role_id)
+            .bind(role.exclusive)
+            .bind(actor_uid),
+        )
+        .await?;
+
+        // Exclusivity applies to the whole message
+        tx.execute(
+            sqlx::query("UPDATE reaction_roles SET exclusive = ? WHERE message_id = ?")
+                .bind(role.exclusive)
+                .bind(&role.message_id),
+        )
+        .await?;
+
+        insert_audit(
+            &mut tx,
+            Some(actor_uid),
+            "reaction_role.add",
+            &format!("server:{}", role.sid),
+            None,
+            Some(
+                &serde_json::json!({
+                    "message_id": role.message_id,
+                    "emoji": role.emoji,
+                    "role_id": role.role_id,
+                    "exclusive": role.exclusive,
+                })
+                .to_string(),
+            ),
+        )
+        .await?;
+
+        tx.commit().await?;
+
+        Ok(())
     }
-}
 
-#[derive(Clone)]
-pub struct User {
-    pub uid: Uid,
-    pub role: String,
-    pub discord_id: Option<u64>,
-}
+    pub async fn remove_reaction_role(
+        &self,
+        message_id: MessageId,
+        emoji: &str,
+        actor_uid: Uid,
+    ) -> Result<Option<ReactionRole>> {
+        let mut tx = self.pool().begin().await?;
 
-impl User {
-    pub fn service_user_id(&self) -> UserId {
-        if let Some(discord_id) = self.discord_id {
-            return UserId::Discord(discord_id);
+        let role = sqlx::query_as::<_, ReactionRole>(
+            "SELECT message_id, emoji, sid, channel_id, role_id, exclusive FROM reaction_roles WHERE message_id = ? AND emoji = ?",
+        )
+        .bind(message_id.to_short_str())
+        .bind(emoji)
+        .fetch_optional(&mut tx)
+        .await?;
+
+        let role = match role {
+            Some(role) => role,
+            None => return Ok(None),
+        };
+
+        tx.execute(
+            sqlx::query("DELETE FROM reaction_roles WHERE message_id = ? AND emoji = ?")
+                .bind(&role.message_id)
+                .bind(&role.emoji),
+        )
+        .await?;
+
+        insert_audit(
+            &mut tx,
+            Some(actor_uid),
+            "reaction_role.remove",
+            &format!("server:{}", role.sid),
+            Some(
+                &serde_json::json!({
+                    "message_id": role.message_id,
+                    "emoji": role.emoji,
+                    "role_id": role.role_id,
+                })
+                .to_string(),
+            ),
+            None,
+        )
+        .await?;
+
+        tx.commit().await?;
+
+        Ok(Some(role))
+    }
+
+    pub async fn message_reaction_roles(&self, message_id: MessageId) -> Result<Vec<ReactionRole>> {
+        Ok(sqlx::query_as::<_, ReactionRole>(
+            "SELECT message_id, emoji, sid, channel_id, role_id, exclusive FROM reaction_roles WHERE message_id = ? ORDER BY create_time",
+        )
+        .bind(message_id.to_short_str())
+        .fetch_all(self.pool())
+        .await?)
+    }
+
+    pub async fn list_reaction_roles(&self, sid: Sid) -> Result<Vec<ReactionRole>> {
+        Ok(sqlx::query_as::<_, ReactionRole>(
+            "SELECT message_id, emoji, sid, channel_id, role_id, exclusive FROM reaction_roles WHERE sid = ? ORDER BY message_id, create_time",
+        )
+        .bind(sid)
+        .fetch_all(self.pool())
+        .await?)
+    }
+
+    // Temporary bans
+    pub async fn add_temp_ban(
+        &self,
+        server_id: ServerId,
+        user_id: UserId,
+        actor_uid: Uid,
+        reason: Option<&str>,
+        expire_time: i64,
+    ) -> Result<()> {
+        self.pool()
+            .execute(
+                sqlx::query(
+                    "REPLACE INTO temp_bans ( server_id, user_id, added_by, reason, expire_time ) VALUES ( ?, ?, ?, ?, ? )",
+                )
+                .bind(server_id.to_str())
+                .bind(user_id.to_str())
+                .bind(actor_uid)
+                .bind(reason)
+                .bind(expire_time),
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    pub async fn remove_temp_ban(&self, server_id: ServerId, user_id: UserId) -> Result<()> {
+        self.pool()
+            .execute(
+                sqlx::query("DELETE FROM temp_bans WHERE server_id = ? AND user_id = ?")
+                    .bind(server_id.to_str())
+                    .bind(user_id.to_str()),
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    /// Bans that should be lifted by now, as (server id, user id) pairs
+    pub async fn expired_temp_bans(&self, now: i64) -> Result<Vec<(ServerId, UserId)>> {
+        let bans: Vec<(String, String)> =
+            sqlx::query_as("SELECT server_id, user_id FROM temp_bans WHERE expire_time <= ?")
+                .bind(now)
+                .fetch_all(self.pool())
+                .await?;
+
+        bans.into_iter()
+            .map(|(server_id, user_id)| {
+                Ok((ServerId::from_str(&server_id)?, UserId::from_str(&user_id)?))
+            })
+            .collect()
+    }
+
+    // Polls
+    pub async fn add_poll(&self, poll: &Poll) -> Result<()> {
+        self.pool()
+            .execute(
+                sqlx::query(
+                    "INSERT INTO polls ( message_id, channel_id, title, options, buttons, created_by, close_time ) VALUES ( ?, ?, ?, ?, ?, ?, ? )",
+                )
+                .bind(&poll.message_id)
+                .bind(&poll.channel_id)
+                .bind(&poll.title)
+                .bind(&poll.options)
+                .bind(poll.buttons)
+                .bind(poll.created_by)
+                .bind(poll.close_time),
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    pub async fn poll(&self, message_id: MessageId) -> Result<Option<Poll>> {
+        Ok(sqlx::query_as::<_, Poll>(
+            "SELECT message_id, channel_id, title, options, buttons, created_by, close_time, closed FROM polls WHERE message_id = ?",
+        )
+        .bind(message_id.to_short_str())
+        .fetch_optional(self.pool())
+        .await?)
+    }
+
+    /// Open polls whose close time has passed
+    pub async fn due_polls(&self, now: i64) -> Result<Vec<Poll>> {
+        Ok(sqlx::query_as::<_, Poll>(
+            "SELECT message_id, channel_id, title, options, buttons, created_by, close_time, closed FROM polls WHERE closed = 0 AND close_time <= ?",
+        )
+        .bind(now)
+        .fetch_all(self.pool())
+        .await?)
+    }
+
+    /// Mark a poll as closed, false if it already was
+    pub async fn close_poll(&self, message_id: MessageId) -> Result<bool> {
+        let res = self
+            .pool()
+            .execute(
+                sqlx::query("UPDATE polls SET closed = 1 WHERE message_id = ? AND closed = 0")
+                    .bind(message_id.to_short_str()),
+            )
+            .await?;
+
+        Ok(res.rows_affected() > 0)
+    }
+
+    pub async fn set_poll_vote(
+        &self,
+        message_id: MessageId,
+        user_id: UserId,
+        option: i64,
+    ) -> Result<()> {
+        self.pool()
+            .execute(
+                sqlx::query(
+                    "REPLACE INTO poll_votes ( message_id, user_id, option ) VALUES ( ?, ?, ? )",
+                )
+                .bind(message_id.to_short_str())
+                .bind(user_id.to_short_str())
+                .bind(option),
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    /// Remove the vote of a user, only if it is for `option`
+    pub async fn remove_poll_vote(
+        &self,
+        message_id: MessageId,
+        user_id: UserId,
+        option: i64,
+    ) -> Result<()> {
+        self.pool()
+            .execute(
+                sqlx::query(
+                    "DELETE FROM poll_votes WHERE message_id = ? AND user_id = ? AND option = ?",
+                )
+                .bind(message_id.to_short_str())
+                .bind(user_id.to_short_str())
+                .bind(option),
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    /// Vote counts of a poll as (option index, votes) pairs, options without votes are left out
+    pub async fn poll_tally(&self, message_id: MessageId) -> Result<Vec<(i64, i64)>> {
+        Ok(sqlx::query_as(
+            "SELECT option, COUNT(*) FROM poll_votes WHERE message_id = ? GROUP BY option",
+        )
+        .bind(message_id.to_short_str())
+        .fetch_all(self.pool())
+        .await?)
+    }
+
+    // Quotes
+    /// Store a quote, returning its number in the server or None if the message is already quoted
+    pub async fn add_quote(
+        &self,
+        sid: Sid,
+        quote: &NewQuote<'_>,
+        actor_uid: Uid,
+    ) -> Result<Option<i64>> {
+        let mut tx = self.pool().begin().await?;
+
+        let (number,): (i64,) =
+            sqlx::query_as("SELECT COALESCE(MAX(number), 0) + 1 FROM quotes WHERE sid = ?")
+                .bind(sid)
+                .fetch_one(&mut tx)
+                .await?;
+
+        match tx
+            .execute(
+                sqlx::query(
+                    "INSERT INTO quotes ( sid, number, message_id, author_id, author_name, content, link, added_by ) VALUES ( ?, ?, ?, ?, ?, ?, ?, ? )",
+                )
+                .bind(sid)
+                .bind(number)
+                .bind(quote.message_id.to_short_str())
+                .bind(quote.author_id.to_short_str())
+                .bind(quote.author_name)
+                .bind(quote.content)
+                .bind(quote.link)
+                .bind(actor_uid),
+            )
+            .await
+        {
+            Ok(_) => {}
+            Err(sqlx::Error::Database(_)) => return Ok(None),
+            Err(err) => return Err(err.into()),
+        }
+
+        tx.commit().await?;
+
+        Ok(Some(number))
+    }
+
+    pub async fn quote(&self, sid: Sid, number: i64) -> Result<Option<Quote>> {
+        Ok(sqlx::query_as::<_, Quote>(
+            "SELECT number, author_id, author_name, content, link, added_by, CAST(strftime('%s', create_time) AS INTEGER) AS timestamp FROM quotes WHERE sid = ? AND number = ?",
+        )
+        .bind(sid)
+        .bind(number)
+        .fetch_optional(self.pool())
+        .await?)
+    }
+
+    pub async fn random_quote(&self, sid: Sid) -> Result<Option<Quote>> {
+        Ok(sqlx::query_as::<_, Quote>(
+            "SELECT number, author_id, author_name, content, link, added_by, CAST(strftime('%s', create_time) AS INTEGER) AS timestamp FROM quotes WHERE sid = ? ORDER BY RANDOM() LIMIT 1",
+        )
+        .bind(sid)
+        .fetch_optional(self.pool())
+        .await?)
+    }
+
+    /// Quotes whose content or author name contains the text, case insensitive, all if empty
+    pub async fn search_quotes(&self, sid: Sid, text: &str) -> Result<Vec<Quote>> {
+        Ok(sqlx::query_as::<_, Quote>(
+            "SELECT number, author_id, author_name, content, link, added_by, CAST(strftime('%s', create_time) AS INTEGER) AS timestamp FROM quotes
+            WHERE sid = ?1 AND (instr(lower(content), lower(?2)) > 0 OR instr(lower(author_name), lower(?2)) > 0)
+            ORDER BY number",
+        )
+        .bind(sid)
+        .bind(text)
+        .fetch_all(self.pool())
+        .await?)
+    }
+
+    pub async fn remove_quote(&self, sid: Sid, number: i64, actor_uid: Uid) -> Result<bool> {
+        let mut tx = self.pool().begin().await?;
+
+        let res = tx
+            .execute(
+                sqlx::query("DELETE FROM quotes WHERE sid = ? AND number = ?")
+                    .bind(sid)
+                    .bind(number),
+            )
+            .await?;
+
+        if res.rows_affected() == 0 {
+            return Ok(false);
+        }
+
+        insert_audit(
+            &mut tx,
+            Some(actor_uid),
+            "quote.remove",
+            &format!("server:{}", sid),
+            Some(&serde_json::json!({ "number": number }).to_string()),
+            None,
+        )
+        .await?;
+
+        tx.commit().await?;
+
+        Ok(true)
+    }
+
+    // Server scripts
+    /// Install a script, replacing the installed script with the same name
+    pub async fn install_server_script(
+        &self,
+        sid: Sid,
+        name: &str,
+        source: &str,
+        actor_uid: Uid,
+    ) -> Result<()> {
+        let mut tx = self.pool().begin().await?;
+
+        tx.execute(
+            sqlx::query(
+                "REPLACE INTO server_scripts ( sid, name, source, installed_by ) VALUES ( ?, ?, ?, ? )",
+            )
+            .bind(sid)
+            .bind(name)
+            .bind(source)
+            .bind(actor_uid),
+        )
+        .await?;
+
+        insert_audit(
+            &mut tx,
+            Some(actor_uid),
+            "script.install",
+            &format!("server:{}", sid),
+            None,
+            Some(&serde_json::json!({ "name": name }).to_string()),
+        )
+        .await?;
+
+        tx.commit().await?;
+
+        Ok(())
+    }
+
+    pub async fn server_script(&self, sid: Sid, name: &str) -> Result<Option<ServerScript>> {
+        Ok(sqlx::query_as::<_, ServerScript>(
+            "SELECT name, source, installed_by, CAST(strftime('%s', create_time) AS INTEGER) AS timestamp FROM server_scripts WHERE sid = ? AND name = ?",
+        )
+        .bind(sid)
+        .bind(name)
+        .fetch_optional(self.pool())
+        .await?)
+    }
+
+    pub async fn server_scripts(&self, sid: Sid) -> Result<Vec<ServerScript>> {
+        Ok(sqlx::query_as::<_, ServerScript>(
+            "SELECT name, source, installed_by, CAST(strftime('%s', create_time) AS INTEGER) AS timestamp FROM server_scripts WHERE sid = ? ORDER BY name",
+        )
+        .bind(sid)
+        .fetch_all(self.pool())
+        .await?)
+    }
+
+    pub async fn uninstall_server_script(
+        &self,
+        sid: Sid,
+        name: &str,
+        actor_uid: Uid,
+    ) -> Result<bool> {
+        let mut tx = self.pool().begin().await?;
+
+        let res = tx
+            .execute(
+                sqlx::query("DELETE FROM server_scripts WHERE sid = ? AND name = ?")
+                    .bind(sid)
+                    .bind(name),
+            )
+            .await?;
+
+        if res.rows_affected() == 0 {
+            return Ok(false);
+        }
+
+        insert_audit(
+            &mut tx,
+            Some(actor_uid),
+            "script.uninstall",
+            &format!("server:{}", sid),
+            Some(&serde_json::json!({ "name": name }).to_string()),
+            None,
+        )
+        .await?;
+
+        tx.commit().await?;
+
+        Ok(true)
+    }
+
+    // Blobs
+    /// Store a blob under the name, returning the hash of the blob it replaced
+    pub async fn put_blob(
+        &self,
+        sid: Sid,
+        name: &str,
+        hash: &str,
+        size: i64,
+    ) -> Result<Option<String>> {
+        let mut tx = self.pool().begin().await?;
+
+        let old: Option<(String,)> =
+            sqlx::query_as("SELECT hash FROM blobs WHERE sid = ? AND name = ?")
+                .bind(sid)
+                .bind(name)
+                .fetch_optional(&mut tx)
+                .await?;
+
+        tx.execute(
+            sqlx::query("REPLACE INTO blobs ( sid, name, hash, size ) VALUES ( ?, ?, ?, ? )")
+                .bind(sid)
+                .bind(name)
+                .bind(hash)
+                .bind(size),
+        )
+        .await?;
+
+        tx.commit().await?;
+
+        Ok(old.map(|(hash,)| hash))
+    }
+
+    pub async fn blob(&self, sid: Sid, name: &str) -> Result<Option<Blob>> {
+        Ok(sqlx::query_as::<_, Blob>(
+            "SELECT name, hash, size, CAST(strftime('%s', create_time) AS INTEGER) AS timestamp FROM blobs WHERE sid = ? AND name = ?",
+        )
+        .bind(sid)
+        .bind(name)
+        .fetch_optional(self.pool())
+        .await?)
+    }
+
+    pub async fn blobs(&self, sid: Sid) -> Result<Vec<Blob>> {
+        Ok(sqlx::query_as::<_, Blob>(
+            "SELECT name, hash, size, CAST(strftime('%s', create_time) AS INTEGER) AS timestamp FROM blobs WHERE sid = ? ORDER BY name",
+        )
+        .bind(sid)
+        .fetch_all(self.pool())
+        .await?)
+    }
+
+    /// Bytes used by the blobs of a server, leaving out the blob with the name
+    pub async fn blob_usage(&self, sid: Sid, except_name: &str) -> Result<i64> {
+        let (size,) =
+            sqlx::query_as("SELECT COALESCE(SUM(size), 0) FROM blobs WHERE sid = ? AND name != ?")
+                .bind(sid)
+                .bind(except_name)
+                .fetch_one(self.pool())
+                .await?;
+
+        Ok(size)
+    }
+
+    /// Remove a blob, returning its hash if it existed
+    pub async fn delete_blob(&self, sid: Sid, name: &str) -> Result<Option<String>> {
+        let mut tx = self.pool().begin().await?;
+
+        let old: Option<(String,)> =
+            sqlx::query_as("SELECT hash FROM blobs WHERE sid = ? AND name = ?")
+                .bind(sid)
+                .bind(name)
+                .fetch_optional(&mut tx)
+                .await?;
+
+        tx.execute(
+            sqlx::query("DELETE FROM blobs WHERE sid = ? AND name = ?")
+                .bind(sid)
+                .bind(name),
+        )
+        .await?;
+
+        tx.commit().await?;
+
+        Ok(old.map(|(hash,)| hash))
+    }
+
+    pub async fn blob_referenced(&self, hash: &str) -> Result<bool> {
+        let (count,): (i64,) = sqlx::query_as("SELECT COUNT(*) FROM blobs WHERE hash = ?")
+            .bind(hash)
+            .fetch_one(self.pool())
+            .await?;
+
+        Ok(count > 0)
+    }
+
+    pub async fn blob_hashes(&self) -> Result<Vec<String>> {
+        let hashes: Vec<(String,)> = sqlx::query_as("SELECT DISTINCT hash FROM blobs")
+            .fetch_all(self.pool())
+            .await?;
+
+        Ok(hashes.into_iter().map(|(hash,)| hash).collect())
+    }
+
+    // Scheduled replies
+    pub async fn add_scheduled_reply(
+        &self,
+        channel_id: ChannelId,
+        message_id: MessageId,
+        content: &str,
+        created_by: Uid,
+        send_time: i64,
+    ) -> Result<i64> {
+        let res = self
+            .pool()
+            .execute(
+                sqlx::query(
+                    "INSERT INTO scheduled_replies ( channel_id, message_id, content, created_by, send_time ) VALUES ( ?, ?, ?, ?, ? )",
+                )
+                .bind(channel_id.to_str())
+                .bind(message_id.to_str())
+                .bind(content)
+                .bind(created_by)
+                .bind(send_time),
+            )
+            .await?;
+
+        Ok(res.last_insert_rowid())
+    }
+
+    /// Whether the reply was still pending, only whoever removes it gets to send it
+    pub async fn remove_scheduled_reply(&self, id: i64) -> Result<bool> {
+        let res = self
+            .pool()
+            .execute(sqlx::query("DELETE FROM scheduled_replies WHERE id = ?").bind(id))
+            .await?;
+
+        Ok(res.rows_affected() > 0)
+    }
+
+    pub async fn scheduled_reply_count(&self, created_by: Uid) -> Result<i64> {
+        let (count,): (i64,) =
+            sqlx::query_as("SELECT COUNT(*) FROM scheduled_replies WHERE created_by = ?")
+                .bind(created_by)
+                .fetch_one(self.pool())
+                .await?;
+
+        Ok(count)
+    }
+
+    pub async fn due_scheduled_replies(&self, now: i64) -> Result<Vec<ScheduledReply>> {
+        Ok(sqlx::query_as(
+            "SELECT id, channel_id, message_id, content, send_time FROM scheduled_replies WHERE send_time <= ? ORDER BY send_time",
+        )
+        .bind(now)
+        .fetch_all(self.pool())
+        .await?)
+    }
+
+    // Scheduled jobs
+    pub async fn scheduled_job_next_run(&self, name: &str) -> Result<Option<i64>> {
+        let next_run: Option<(i64,)> =
+            sqlx::query_as("SELECT next_run FROM scheduled_jobs WHERE name = ?")
+                .bind(name)
+                .fetch_optional(self.pool())
+                .await?;
+
+        Ok(next_run.map(|(next_run,)| next_run))
+    }
+
+    pub async fn set_scheduled_job_next_run(&self, name: &str, next_run: i64) -> Result<()> {
+        self.pool()
+            .execute(
+                sqlx::query("REPLACE INTO scheduled_jobs ( name, next_run ) VALUES ( ?, ? )")
+                    .bind(name)
+                    .bind(next_run),
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    pub async fn remove_scheduled_job(&self, name: &str) -> Result<()> {
+        self.pool()
+            .execute(sqlx::query("DELETE FROM scheduled_jobs WHERE name = ?").bind(name))
+            .await?;
+
+        Ok(())
+    }
+
+    // Storage
+    pub async fn storage_value(&self, namespace: &str, key: &str) -> Result<Option<String>> {
+        let value: Option<(String,)> =
+            sqlx::query_as("SELECT value FROM storage WHERE namespace = ? AND key = ?")
+                .bind(namespace)
+                .bind(key)
+                .fetch_optional(self.pool())
+                .await?;
+
+        Ok(value.map(|(value,)| value))
+    }
+
+    /// Store a value under the key, unless the values of the namespace would take up more than
+    /// the quota in bytes
+    pub async fn set_storage_value(
+        &self,
+        namespace: &str,
+        key: &str,
+        value: &str,
+        quota: i64,
+    ) -> Result<bool> {
+        let mut tx = self.pool().begin().await?;
+
+        let (used,): (i64,) = sqlx::query_as(
+            "SELECT COALESCE(SUM(LENGTH(CAST(key AS BLOB)) + LENGTH(CAST(value AS BLOB))), 0) FROM storage WHERE namespace = ? AND key != ?",
+        )
+        .bind(namespace)
+        .bind(key)
+        .fetch_one(&mut tx)
+        .await?;
+
+        if used + (key.len() + value.len()) as i64 > quota {
+            return Ok(false);
+        }
+
+        tx.execute(
+            sqlx::query("REPLACE INTO storage ( namespace, key, value ) VALUES ( ?, ?, ? )")
+                .bind(namespace)
+                .bind(key)
+                .bind(value),
+        )
+        .await?;
+
+        tx.commit().await?;
+
+        Ok(true)
+    }
+
+    pub async fn delete_storage_value(&self, namespace: &str, key: &str) -> Result<bool> {
+        let res = self
+            .pool()
+            .execute(
+                sqlx::query("DELETE FROM storage WHERE namespace = ? AND key = ?")
+                    .bind(namespace)
+                    .bind(key),
+            )
+            .await?;
+
+        Ok(res.rows_affected() > 0)
+    }
+
+    pub async fn storage_keys(
+        &self,
+        namespace: &str,
+        prefix: &str,
+        limit: i64,
+    ) -> Result<Vec<String>> {
+        let keys: Vec<(String,)> = sqlx::query_as(
+            "SELECT key FROM storage WHERE namespace = ? AND SUBSTR(key, 1, LENGTH(?)) = ? ORDER BY key LIMIT ?",
+        )
+        .bind(namespace)
+        .bind(prefix)
+        .bind(prefix)
+        .bind(limit)
+        .fetch_all(self.pool())
+        .await?;
+
+        Ok(keys.into_iter().map(|(key,)| key).collect())
+    }
+
+    pub fn pool(&self) -> &Pool<Sqlite> {
+        &self.pool
+    }
+}
+
+async fn insert_audit<'c, E>(
+    executor: E,
+    actor_uid: Option<Uid>,
+    action: &str,
+    scope: &str,
+    before: Option<&str>,
+    after: Option<&str>,
+) -> Result<()>
+where
+    E: Executor<'c, Database = Sqlite>,
+{
+    executor
+        .execute(
+            sqlx::query(
+                "INSERT INTO audit_log ( actor_uid, action, scope, before, after ) VALUES ( ?, ?, ?, ?, ? )",
+            )
+            .bind(actor_uid)
+            .bind(action)
+            .bind(scope)
+            .bind(before)
+            .bind(after),
+        )
+        .await?;
+
+    Ok(())
+}
+
+fn blacklist_scope(sid: Sid) -> String {
+    if sid == 0 {
+        "global".into()
+    } else {
+        format!("server:{}", sid)
+    }
+}
+
+#[derive(Clone, Serialize, sqlx::FromRow)]
+pub struct AuditEntry {
+    pub id: i64,
+    pub actor_uid: Option<Uid>,
+    pub action: String,
+    pub scope: String,
+    pub before: Option<String>,
+    pub after: Option<String>,
+    pub timestamp: i64,
+}
+
+#[derive(Clone, sqlx::FromRow)]
+pub struct SecretEntry {
+    pub name: String,
+    pub version: i64,
+    pub update_timestamp: i64,
+}
+
+/// A setting changed for a while, reverted by the settings scheduler
+#[derive(Clone, sqlx::FromRow)]
+pub struct SettingExpiry {
+    pub kind: String,
+    pub location: String,
+    pub key: String,
+    pub previous: Option<String>,
+    pub announce_channel: Option<String>,
+    pub expire_time: i64,
+}
+
+#[derive(Clone, sqlx::FromRow)]
+pub struct EncryptedValue {
+    pub kind: String,
+    pub id: String,
+    pub key: String,
+    pub value: String,
+}
+
+/// A role given to members reacting to a message with an emoji
+#[derive(Clone, sqlx::FromRow)]
+pub struct ReactionRole {
+    pub message_id: String,
+    /// Unicode emoji, or "name:id" for custom emoji
+    pub emoji: String,
+    pub sid: Sid,
+    pub channel_id: String,
+    pub role_id: String,
+    pub exclusive: bool,
+}
+
+/// A poll voted on with reactions or buttons on its message
+#[derive(Clone, sqlx::FromRow)]
+pub struct Poll {
+    pub message_id: String,
+    pub channel_id: String,
+    pub title: String,
+    /// JSON array of the option labels
+    pub options: String,
+    pub buttons: bool,
+    pub created_by: Uid,
+    pub close_time: Option<i64>,
+    pub closed: bool,
+}
+
+#[derive(Clone, sqlx::FromRow)]
+pub struct Quote {
+    /// Counts up per server
+    pub number: i64,
+    pub author_id: String,
+    pub author_name: String,
+    pub content: String,
+    pub link: Option<String>,
+    pub added_by: Uid,
+    pub timestamp: i64,
+}
+
+pub struct NewQuote<'a> {
+    pub message_id: MessageId,
+    pub author_id: UserId,
+    pub author_name: &'a str,
+    pub content: &'a str,
+    pub link: Option<&'a str>,
+}
+
+#[derive(Clone, sqlx::FromRow)]
+pub struct ServerScript {
+    pub name: String,
+    pub source: String,
+    pub installed_by: Uid,
+    pub timestamp: i64,
+}
+
+#[derive(Clone, sqlx::FromRow)]
+pub struct Blob {
+    pub name: String,
+    pub hash: String,
+    pub size: i64,
+    pub timestamp: i64,
+}
+
+#[derive(Clone, sqlx::FromRow)]
+pub struct ScheduledReply {
+    pub id: i64,
+    pub channel_id: String,
+    pub message_id: String,
+    pub content: String,
+    pub send_time: i64,
+}
+
+#[derive(Clone, sqlx::FromRow)]
+pub struct BlacklistEntry {
+    pub uid: Uid,
+    pub sid: Sid,
+    pub added_by: Uid,
+    pub reason: Option<String>,
+    pub expire_time: Option<i64>,
+}
+
+#[derive(Clone)]
+pub struct User {
+    pub uid: Uid,
+    pub role: String,
+    pub discord_id: Option<u64>,
+    pub telegram_id: Option<i64>,
+    pub timezone: Option<String>,
+}
+
+impl User {
+    fn from_row(
+        uid: Uid,
+        role: Option<String>,
+        discord_id: Option<Vec<u8>>,
+        telegram_id: Option<i64>,
+        timezone: Option<String>,
+    ) -> User {
+        let role = role
+            .filter(|role| ROLES.contains(&role.as_str()))
+            .unwrap_or_else(|| DEFAULT_ROLE.into());
+        let discord_id = discord_id.map(|data| {
+            let mut bytes = [0u8; 8];
+            bytes.clone_from_slice(&data[0..8]);
+            u64::from_le_bytes(bytes)
+        });
+
+        User {
+            uid,
+            role,
+            discord_id,
+            telegram_id,
+            timezone,
+        }
+    }
+
+    pub fn service_user_id(&self) -> UserId {
+        if let Some(discord_id) = self.discord_id {
+            return UserId::Discord(discord_id);
+        }
+
+        if let Some(telegram_id) = self.telegram_id {
+            return UserId::Telegram(telegram_id);
         }
 
         unreachable!("no valid service id for uid {}", self.uid)
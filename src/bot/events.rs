@@ -0,0 +1,82 @@
+use futures::channel::mpsc::{unbounded, UnboundedReceiver, UnboundedSender};
+use serde_json::Value as JsonValue;
+use std::{sync::Mutex, time::Duration};
+
+use super::db::Uid;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ConnectionState {
+    Connected,
+    Reconnecting {
+        attempt: u32,
+        delay: Duration,
+    },
+    /// Published once when reconnecting keeps failing past the alert threshold
+    Unhealthy {
+        attempt: u32,
+    },
+}
+
+/// A moderation or configuration change reported by a service, possibly made outside the bot
+#[derive(Clone, Debug)]
+pub struct ServiceAudit {
+    pub service: &'static str,
+    pub server_id: String,
+    /// e.g. "member_ban_add", "channel_update" or "role_delete"
+    pub action: &'static str,
+    /// Unknown when the bot can't read the audit log of the server
+    pub actor_id: Option<String>,
+    pub target_id: Option<String>,
+    pub reason: Option<String>,
+    /// Changed keys with their old and new values
+    pub changes: Vec<(String, Option<JsonValue>, Option<JsonValue>)>,
+}
+
+/// Data published by a sandbox run on a named channel of a server
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct IpcMessage {
+    pub server_id: String,
+    pub channel: String,
+    /// e.g. "script:prices" or "user:12"
+    pub source: String,
+    /// JSON encoded
+    pub data: String,
+    pub timestamp: i64,
+}
+
+#[derive(Clone, Debug)]
+pub enum BotEvent {
+    Connection {
+        service: &'static str,
+        state: ConnectionState,
+    },
+    /// Asks the process to hand its state off and exec a fresh copy of itself
+    RestartRequested {
+        actor_uid: Option<Uid>,
+    },
+    ServiceAudit(ServiceAudit),
+    Ipc(IpcMessage),
+}
+
+/// Fans bot wide events out to every subscriber
+#[derive(Default)]
+pub struct EventBus {
+    subscribers: Mutex<Vec<UnboundedSender<BotEvent>>>,
+}
+
+impl EventBus {
+    pub fn subscribe(&self) -> UnboundedReceiver<BotEvent> {
+        let (sender, receiver) = unbounded();
+        self.subscribers.lock().unwrap().push(sender);
+
+        receiver
+    }
+
+    pub fn publish(&self, event: BotEvent) {
+        // Dropped receivers are removed on the next publish
+        self.subscribers
+            .lock()
+            .unwrap()
+            .retain(|sender| sender.unbounded_send(event.clone()).is_ok());
+    }
+}
@@ -0,0 +1,67 @@
+use anyhow::Result;
+use std::{
+    collections::HashMap,
+    env, fs,
+    os::unix::{io::RawFd, process::CommandExt},
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+// A handoff older than this is from a restart that never came back up
+const MAX_HANDOFF_AGE: i64 = 120;
+
+/// State written by the old process right before an exec restart
+#[derive(Default, Deserialize, Serialize)]
+pub struct Handoff {
+    pub created: i64,
+    pub modules: HashMap<String, serde_json::Value>,
+}
+
+fn handoff_path(data_path: &Path) -> PathBuf {
+    data_path.join("handoff.json")
+}
+
+pub fn save(data_path: &Path, handoff: &Handoff) -> Result<()> {
+    fs::write(handoff_path(data_path), serde_json::to_vec(handoff)?)?;
+
+    Ok(())
+}
+
+/// Read and remove the handoff left by the previous process, if it is recent enough
+pub fn take(data_path: &Path) -> Result<Option<Handoff>> {
+    let path = handoff_path(data_path);
+
+    let data = match fs::read(&path) {
+        Ok(data) => data,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(err) => return Err(err.into()),
+    };
+    fs::remove_file(&path)?;
+
+    let handoff: Handoff = serde_json::from_slice(&data)?;
+
+    if chrono::Utc::now().timestamp() - handoff.created > MAX_HANDOFF_AGE {
+        println!("Ignoring stale handoff state");
+        return Ok(None);
+    }
+
+    Ok(Some(handoff))
+}
+
+/// Replace the current process with a fresh copy of the binary, keeping `fd` open for it
+pub fn exec_restart(http_fd: Option<RawFd>) -> Result<()> {
+    let mut command = Command::new(env::current_exe()?);
+    command.args(env::args_os().skip(1));
+
+    if let Some(fd) = http_fd {
+        // Sockets are opened close-on-exec, clear it so the listener survives
+        if unsafe { libc::fcntl(fd, libc::F_SETFD, 0) } == -1 {
+            return Err(std::io::Error::last_os_error().into());
+        }
+
+        command.env("KAITO_HTTP_FD", fd.to_string());
+    }
+
+    // exec only returns if it failed
+    Err(command.exec().into())
+}
@@ -0,0 +1,72 @@
+use std::{
+    collections::BTreeMap,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex,
+    },
+};
+
+/// Counters of the bot runtime since it started, for the metrics endpoint and `bot.stats`.
+/// Unlike telemetry they are always collected and never leave the bot.
+#[derive(Default)]
+pub struct Metrics {
+    sandbox_runs: AtomicU64,
+    sandbox_instructions: AtomicU64,
+    sandbox_terminations: Mutex<BTreeMap<&'static str, u64>>,
+    async_callbacks: Mutex<BTreeMap<&'static str, u64>>,
+    http_rejections: Mutex<BTreeMap<&'static str, u64>>,
+    messages_received: Mutex<BTreeMap<&'static str, u64>>,
+}
+
+#[derive(Clone, Serialize)]
+pub struct MetricsSnapshot {
+    pub sandbox_runs: u64,
+    /// Instructions run by finished sandbox runs
+    pub sandbox_instructions: u64,
+    pub sandbox_terminations: BTreeMap<&'static str, u64>,
+    /// Future callbacks handled by think, by state
+    pub async_callbacks: BTreeMap<&'static str, u64>,
+    /// Sandbox http requests refused or cut off by a limit, by reason
+    pub http_rejections: BTreeMap<&'static str, u64>,
+    /// Messages received, by service
+    pub messages_received: BTreeMap<&'static str, u64>,
+}
+
+fn increment(counters: &Mutex<BTreeMap<&'static str, u64>>, key: &'static str) {
+    *counters.lock().unwrap().entry(key).or_default() += 1;
+}
+
+impl Metrics {
+    pub fn count_sandbox_run(&self, instructions: u64) {
+        self.sandbox_runs.fetch_add(1, Ordering::Relaxed);
+        self.sandbox_instructions
+            .fetch_add(instructions, Ordering::Relaxed);
+    }
+
+    pub fn count_sandbox_termination(&self, reason: &'static str) {
+        increment(&self.sandbox_terminations, reason);
+    }
+
+    pub fn count_async_callback(&self, state: &'static str) {
+        increment(&self.async_callbacks, state);
+    }
+
+    pub fn count_http_rejection(&self, reason: &'static str) {
+        increment(&self.http_rejections, reason);
+    }
+
+    pub fn count_message_received(&self, service: &'static str) {
+        increment(&self.messages_received, service);
+    }
+
+    pub fn snapshot(&self) -> MetricsSnapshot {
+        MetricsSnapshot {
+            sandbox_runs: self.sandbox_runs.load(Ordering::Relaxed),
+            sandbox_instructions: self.sandbox_instructions.load(Ordering::Relaxed),
+            sandbox_terminations: self.sandbox_terminations.lock().unwrap().clone(),
+            async_callbacks: self.async_callbacks.lock().unwrap().clone(),
+            http_rejections: self.http_rejections.lock().unwrap().clone(),
+            messages_received: self.messages_received.lock().unwrap().clone(),
+        }
+    }
+}
@@ -0,0 +1,153 @@
+use anyhow::Result;
+use thiserror::Error;
+
+use super::{db::User as DbUser, Bot, ROLES};
+use crate::{
+    config::Config,
+    services::{ChannelId, ServerId},
+    settings::{PermissionGrant, UserList},
+};
+
+// Nodes only owners hold, they can't be granted to anyone. Server admins handing out admin
+// permissions or grants could make anyone an admin
+const OWNER_NODES: &[&str] = &["setting.lua.admins", "setting.lua.permissions"];
+
+/// A user acting in a channel, permission checks look up the server admins and grants there
+#[derive(Clone)]
+pub struct Caller {
+    pub user: DbUser,
+    pub server_id: ServerId,
+    pub channel_id: ChannelId,
+}
+
+impl Caller {
+    pub fn new(user: DbUser, server_id: ServerId, channel_id: ChannelId) -> Caller {
+        Caller {
+            user,
+            server_id,
+            channel_id,
+        }
+    }
+
+    pub fn location(&self) -> (ServerId, ChannelId) {
+        (self.server_id, self.channel_id)
+    }
+}
+
+/// Who may manage the bot, from the configured user lists, roles and the server admins setting
+pub struct Permissions {
+    owners: UserList,
+    admins: UserList,
+}
+
+impl Permissions {
+    pub fn from_config(config: &Config) -> Result<Permissions> {
+        let user_list =
+            |ids: &Option<Vec<String>>| UserList::parse(ids.iter().flatten().map(|id| id.as_str()));
+
+        Ok(Permissions {
+            owners: user_list(&config.owners)?,
+            admins: user_list(&config.admins)?,
+        })
+    }
+
+    pub fn is_owner(&self, user: &DbUser) -> bool {
+        user.role == "root" || self.owners.contains(user.service_user_id())
+    }
+
+    /// Admins of the whole bot, or of the server the channel belongs to
+    pub async fn is_admin(
+        &self,
+        bot: &Bot,
+        user: &DbUser,
+        location: Option<(ServerId, ChannelId)>,
+    ) -> Result<bool> {
+        if self.is_owner(user)
+            || has_role_or_higher(&user.role, "admin")
+            || self.admins.contains(user.service_user_id())
+        {
+            return Ok(true);
+        }
+
+        if let Some((server_id, channel_id)) = location {
+            let settings = bot.get_ctx().modules().lua.module().settings().clone();
+            let admins = settings.admins.value(server_id, channel_id).await?;
+
+            return Ok(admins.contains(user.service_user_id()));
+        }
+
+        Ok(false)
+    }
+
+    /// Whether the user holds the permission node, like "command.tags.delete" or
+    /// "setting.lua.prefix". The nodes "owner" and "admin" check for owners and admins. Owners
+    /// hold every other node and admins every node besides the owner ones, anyone else needs a
+    /// grant from the permissions setting for them or their role
+    pub async fn has_permission(
+        &self,
+        bot: &Bot,
+        user: &DbUser,
+        node: &str,
+        location: Option<(ServerId, ChannelId)>,
+    ) -> Result<bool> {
+        match node {
+            "owner" => return Ok(self.is_owner(user)),
+            "admin" => return self.is_admin(bot, user, location).await,
+            _ => {}
+        }
+
+        if self.is_owner(user) {
+            return Ok(true);
+        }
+
+        if OWNER_NODES.contains(&node) {
+            return Ok(false);
+        }
+
+        if self.is_admin(bot, user, location).await? {
+            return Ok(true);
+        }
+
+        let (server_id, channel_id) = match location {
+            Some(location) => location,
+            None => return Ok(false),
+        };
+
+        let settings = bot.get_ctx().modules().lua.module().settings().clone();
+        let grants = settings.permissions.value(server_id, channel_id).await?;
+
+        Ok(grants
+            .iter()
+            .any(|grant| grant.matches(node) && grant.applies_to(user)))
+    }
+
+    pub async fn check_permission(&self, bot: &Bot, caller: &Caller, node: &str) -> Result<()> {
+        if self
+            .has_permission(bot, &caller.user, node, Some(caller.location()))
+            .await?
+        {
+            Ok(())
+        } else {
+            Err(PermissionError::MissingNode(node.into()).into())
+        }
+    }
+}
+
+pub fn has_role_or_higher(user_role: &str, role: &str) -> bool {
+    let index = |role: &str| ROLES.iter().position(|r| *r == role);
+
+    match (index(user_role), index(role)) {
+        (Some(user_role), Some(role)) => user_role >= role,
+        _ => false,
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum PermissionError {
+    #[error("permission denied: only bot owners can do this")]
+    NotOwner,
+    #[error("permission denied: only admins can do this")]
+    NotAdmin,
+    #[error("permission denied: this needs the \"{}\" permission", _0)]
+    MissingNode(String),
+}
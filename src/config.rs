@@ -1,17 +1,177 @@
 use anyhow::Result;
-use std::{collections::HashMap, fs, path::Path};
+use std::{collections::HashMap, fs, net::SocketAddr, path::Path};
 
-use crate::services::discord::DiscordServiceConfig;
+use crate::{
+    modules::{GithubModuleConfig, TelemetryModuleConfig},
+    services::{discord::DiscordServiceConfig, telegram::TelegramServiceConfig},
+};
 
 #[derive(Clone, Deserialize, Serialize, Debug)]
 pub struct Config {
     pub services: ConfigServices,
     pub user_roles: Option<HashMap<String, String>>,
+    /// Users with full control over the bot, as service user ids like "discord:1234"
+    pub owners: Option<Vec<String>>,
+    /// Users with admin permissions in every server, admins of a single server are a lua setting
+    pub admins: Option<Vec<String>>,
+    pub translate: Option<ConfigTranslate>,
+    pub gif: Option<ConfigGif>,
+    pub latex: Option<ConfigLatex>,
+    pub http: Option<ConfigHttp>,
+    pub github: Option<GithubModuleConfig>,
+    pub scripts: Option<ConfigScripts>,
+    pub proc: Option<ConfigProc>,
+    pub lua: Option<ConfigLua>,
+    /// Anonymous usage counters posted for the maintainers, off unless enabled
+    pub telemetry: Option<TelemetryModuleConfig>,
 }
 
 #[derive(Clone, Deserialize, Serialize, Debug)]
 pub struct ConfigServices {
     pub discord: Option<DiscordServiceConfig>,
+    pub telegram: Option<TelegramServiceConfig>,
+}
+
+#[derive(Clone, Deserialize, Serialize, Debug)]
+pub struct ConfigHttp {
+    pub bind: SocketAddr,
+    /// Url the server is reachable at from outside, share links of blobs are only given out if
+    /// it is set
+    pub public_url: Option<String>,
+    /// Bearer token for the admin endpoints, they are left out unless it is set
+    pub admin_token: Option<String>,
+    /// Serve the lua metrics, they need the admin token as well
+    pub metrics: Option<bool>,
+}
+
+#[derive(Clone, Deserialize, Serialize, Debug)]
+pub struct ConfigScripts {
+    pub git_url: String,
+    pub branch: Option<String>,
+    pub commit: Option<String>,
+    /// Path of the lua directory inside the repository, defaults to "lua"
+    pub path: Option<String>,
+    #[serde(default)]
+    pub sync_on_startup: bool,
+    /// Amount of script versions to keep on disk for rollbacks, defaults to 5
+    pub keep_versions: Option<usize>,
+}
+
+#[derive(Clone, Deserialize, Serialize, Debug)]
+pub struct ConfigLua {
+    /// Extra standard libraries for the bot state, defaults to ["utf8", "math"]
+    pub bot_stdlib: Option<Vec<String>>,
+    /// Extra standard libraries for the sandbox state, io, os and package are rejected
+    pub sandbox_stdlib: Option<Vec<String>>,
+    /// Requests per minute the sandbox may make to a domain and its subdomains, other hosts get
+    /// 60 each
+    pub http_domain_limits: Option<HashMap<String, u32>>,
+    /// Garbage collector tuning of the bot and sandbox states
+    pub gc: Option<ConfigLuaGc>,
+    /// Drive time, random seeds and rate limiters from a fake clock, so tests get the same
+    /// results on every run
+    pub deterministic: Option<ConfigLuaDeterministic>,
+    /// Futures a state may have in flight before new ones overflow, defaults to 4096
+    pub max_pending_futures: Option<usize>,
+    /// What happens to futures over the limit, defaults to reject
+    pub future_overflow: Option<ConfigFutureOverflow>,
+    /// Channel told when the bot state crashed and was rebuilt, as a service channel id like
+    /// "discord:1234"
+    pub operator_channel: Option<String>,
+    /// Providers links in sandbox output and sandbox http fetches are checked with, servers
+    /// choose what happens to unsafe links through the url_scan setting
+    pub url_scan: Option<ConfigUrlScan>,
+    /// Reload the scripts when files under the lua root change, meant for working on scripts,
+    /// defaults to false
+    pub watch_scripts: Option<bool>,
+}
+
+#[derive(Clone, Deserialize, Serialize, Debug)]
+pub struct ConfigUrlScan {
+    /// Google Safe Browsing api key
+    pub safe_browsing_key: Option<String>,
+    /// Files of blocked domains, one per line, subdomains are blocked with them
+    #[serde(default)]
+    pub blocklists: Vec<String>,
+    /// Seconds a scanned url is remembered for, defaults to 30 minutes
+    pub cache_ttl: Option<u64>,
+}
+
+#[derive(Clone, Copy, Deserialize, Serialize, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum ConfigFutureOverflow {
+    /// Creating the future raises a lua error
+    Reject,
+    /// The future is handed out but never started or resolved, and the drop is logged
+    Drop,
+}
+
+#[derive(Clone, Deserialize, Serialize, Debug)]
+pub struct ConfigLuaDeterministic {
+    /// Unix timestamp the fake clock starts at, defaults to 0
+    pub start_time: Option<i64>,
+    /// Seed for math.random, defaults to 0
+    pub seed: Option<i64>,
+}
+
+#[derive(Clone, Deserialize, Serialize, Debug)]
+pub struct ConfigLuaGc {
+    /// Percent memory grows by after a collection before the next cycle starts, defaults to 200
+    pub pause: Option<i32>,
+    /// Speed of the collector relative to allocation in percent, defaults to 100
+    pub step_multiplier: Option<i32>,
+    /// Kilobytes of collection work done on every think, defaults to 64
+    pub step_kbytes: Option<i32>,
+    /// Memory use in MiB over which think does a full collection, defaults to 192 of the
+    /// 256 MiB a state may use
+    pub soft_memory_limit: Option<usize>,
+}
+
+#[derive(Clone, Deserialize, Serialize, Debug)]
+pub struct ConfigProc {
+    #[serde(default)]
+    pub enabled: bool,
+    /// Binaries scripts are allowed to run, looked up through PATH
+    #[serde(default)]
+    pub allowed: Vec<String>,
+    /// Upper bound in seconds before a process is killed, defaults to 10
+    pub timeout: Option<u64>,
+    /// Bytes kept from both stdout and stderr, defaults to 64 KiB
+    pub max_output: Option<usize>,
+}
+
+#[derive(Clone, Deserialize, Serialize, Debug)]
+pub struct ConfigTranslate {
+    pub deepl: Option<ConfigDeepL>,
+    pub libretranslate: Option<ConfigLibreTranslate>,
+}
+
+#[derive(Clone, Deserialize, Serialize, Debug)]
+pub struct ConfigDeepL {
+    pub api_key: String,
+    pub api_url: Option<String>,
+}
+
+#[derive(Clone, Deserialize, Serialize, Debug)]
+pub struct ConfigLibreTranslate {
+    pub url: String,
+    pub api_key: Option<String>,
+}
+
+#[derive(Clone, Deserialize, Serialize, Debug)]
+pub struct ConfigGif {
+    /// Either "tenor" or "giphy"
+    pub provider: String,
+    pub api_key: String,
+    pub api_url: Option<String>,
+    /// Content rating passed to the provider, defaults to "medium" for tenor and "pg-13" for giphy
+    pub rating: Option<String>,
+}
+
+#[derive(Clone, Deserialize, Serialize, Debug)]
+pub struct ConfigLatex {
+    /// Url of the render service returning a png, "{}" is replaced with the url encoded expression
+    pub url: String,
 }
 
 pub fn load_config(path: &Path) -> Result<Config> {
@@ -6,7 +6,14 @@ extern crate bitflags;
 extern crate serde_derive;
 
 use anyhow::Result;
+use futures::StreamExt;
 use std::{env, path::PathBuf};
+use tokio::signal::unix::{signal, SignalKind};
+
+use bot::{
+    events::BotEvent,
+    handoff::{self, Handoff},
+};
 
 #[macro_use]
 mod settings;
@@ -15,8 +22,10 @@ mod bot;
 mod config;
 mod message;
 mod modules;
+mod server;
 mod services;
 mod utils;
+mod vault;
 
 async fn run() -> Result<()> {
     let config_path = env::var("KAITO_CONFIG_FILE")
@@ -37,17 +46,77 @@ async fn run() -> Result<()> {
         std::fs::create_dir_all(&data_path)?;
     }
 
+    let args = env::args().skip(1).collect::<Vec<_>>();
+    if args.first().map(String::as_str) == Some("vault") {
+        return vault::run_cli(&data_path, &share_path, &config, &args[1..]).await;
+    }
+    if args.first().map(String::as_str) == Some("bench") {
+        return modules::run_bench_cli(&data_path, &share_path, &config, &args[1..]).await;
+    }
+    if args.first().map(String::as_str) == Some("settings-docs") {
+        return settings::run_cli(&data_path, &share_path, &config, &args[1..]).await;
+    }
+
     let bot = bot::Bot::init(data_path, share_path, &config).await?;
-    let modules = modules::Modules::init(bot.clone(), &config).await?;
-    let services = services::Services::init(bot.clone(), &config.services).await?;
+    let mut events = bot.events().subscribe();
+
+    let modules = modules::Modules::init(bot.clone(), bot.config()).await?;
+    if let Some(handoff) = handoff::take(bot.data_path())? {
+        modules.resume(handoff.modules).await;
+        println!("Resumed state from the previous process");
+    }
+
+    let services = services::Services::init(bot.clone(), &bot.config().services).await?;
     let ctx = bot::BotContext::new(bot.clone(), modules, services);
     bot.set_ctx(ctx);
+    settings::spawn_expiry_task(bot.clone());
+
+    if let Some(http) = &config.http {
+        bot::admin::register(&bot);
+        bot.http_server().clone().serve(http.bind)?;
+    }
 
     println!("Everything is online");
 
-    tokio::signal::ctrl_c().await?;
-    println!("Exit signal received, shutting down...");
-    bot.get_ctx().shutdown().await?;
+    let mut hangup = signal(SignalKind::hangup())?;
+
+    // None shuts down, Some restarts on behalf of the actor
+    let restart = loop {
+        tokio::select! {
+            res = tokio::signal::ctrl_c() => {
+                res?;
+                break None;
+            }
+            _ = hangup.recv() => break Some(None),
+            event = events.next() => {
+                if let Some(BotEvent::RestartRequested { actor_uid }) = event {
+                    break Some(actor_uid);
+                }
+            }
+        }
+    };
+
+    match restart {
+        None => {
+            println!("Exit signal received, shutting down...");
+            bot.get_ctx().shutdown().await?;
+        }
+        Some(actor_uid) => {
+            println!("Restart requested, handing off state...");
+            bot.db()
+                .audit(actor_uid, "bot.restart", "global", None, None)
+                .await?;
+
+            let handoff = Handoff {
+                created: chrono::Utc::now().timestamp(),
+                modules: bot.get_ctx().modules().handoff().await,
+            };
+
+            bot.get_ctx().shutdown().await?;
+            handoff::save(bot.data_path(), &handoff)?;
+            handoff::exec_restart(bot.http_server().listener_fd())?;
+        }
+    }
 
     Ok(())
 }
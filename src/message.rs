@@ -1,3 +1,5 @@
+pub mod format;
+
 use super::services::{MessageId, UserId};
 
 #[derive(Clone, Default)]
@@ -6,6 +8,40 @@ pub struct MessageSettings {
     pub reply: Option<MessageId>,
     pub reply_user: Option<UserId>,
     pub attachments: Vec<(String, Vec<u8>)>,
+    /// Rows of buttons and select menus
+    pub components: Vec<Vec<MessageComponent>>,
+    /// Url of a GIF shown as the embed image
+    pub gif: Option<String>,
+    /// Service specific sticker ids
+    pub stickers: Vec<String>,
+    pub priority: MessagePriority,
+    pub sanitize: Sanitize,
+}
+
+bitflags! {
+    /// Parts of untrusted content that are neutralized before it is sent
+    #[derive(Default)]
+    pub struct Sanitize: u32 {
+        const EVERYONE = 1;
+        const MENTIONS = 1 << 1;
+        const INVITES = 1 << 2;
+    }
+}
+
+/// Order of messages waiting in the send queue of a channel
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum MessagePriority {
+    /// Feeds and other automated posts
+    Low,
+    Normal,
+    /// Error notices
+    High,
+}
+
+impl Default for MessagePriority {
+    fn default() -> Self {
+        MessagePriority::Normal
+    }
 }
 
 #[derive(Clone, Default)]
@@ -25,6 +61,83 @@ pub struct MessageEmbed {
     pub attachment: Option<String>,
 }
 
+#[derive(Clone, Copy)]
+pub enum ButtonStyle {
+    Primary,
+    Secondary,
+    Success,
+    Danger,
+}
+
+#[derive(Clone)]
+pub struct SelectOption {
+    pub label: String,
+    pub value: String,
+    pub description: Option<String>,
+}
+
+#[derive(Clone)]
+pub enum MessageComponent {
+    Button {
+        id: String,
+        label: String,
+        style: ButtonStyle,
+        disabled: bool,
+    },
+    Select {
+        id: String,
+        placeholder: Option<String>,
+        options: Vec<SelectOption>,
+        min_values: u64,
+        max_values: u64,
+    },
+}
+
+impl MessageComponent {
+    /// Plain text stand-in for services that can't show components
+    pub fn fallback_text(&self) -> String {
+        match self {
+            MessageComponent::Button { label, .. } => format!("[{}]", label),
+            MessageComponent::Select {
+                placeholder,
+                options,
+                ..
+            } => {
+                let options = options
+                    .iter()
+                    .map(|option| option.label.as_str())
+                    .collect::<Vec<_>>()
+                    .join(" | ");
+
+                match placeholder {
+                    Some(placeholder) => format!("{}: {}", placeholder, options),
+                    None => options,
+                }
+            }
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct ModalField {
+    pub id: String,
+    pub label: String,
+    pub placeholder: Option<String>,
+    pub value: Option<String>,
+    /// Multi-line input
+    pub long: bool,
+    pub required: bool,
+    pub min_length: Option<u64>,
+    pub max_length: Option<u64>,
+}
+
+/// A form of text inputs shown in response to an interaction
+#[derive(Clone)]
+pub struct MessageModal {
+    pub title: String,
+    pub fields: Vec<ModalField>,
+}
+
 pub enum MessageContent<'a> {
     String(String),
     Str(&'a str),
@@ -35,6 +148,7 @@ pub struct Attachment {
     pub url: String,
     pub size: Option<u64>,
     pub dimensions: Option<(u64, u64)>,
+    pub content_type: Option<String>,
 }
 
 pub trait ToMessageContent<'a>: Send + Sync {
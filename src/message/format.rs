@@ -0,0 +1,221 @@
+/// Formatted text independent of a service, rendered into the dialect of wherever it is sent
+#[derive(Clone, Debug)]
+pub enum RichText {
+    Text(String),
+    Bold(Vec<RichText>),
+    Italic(Vec<RichText>),
+    Underline(Vec<RichText>),
+    Strikethrough(Vec<RichText>),
+    Code(String),
+    CodeBlock {
+        code: String,
+        language: Option<String>,
+    },
+    Link {
+        text: Vec<RichText>,
+        url: String,
+    },
+}
+
+/// How formatting is written on a service or bridge
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TextDialect {
+    Plain,
+    /// Discord flavored markdown
+    Markdown,
+    /// mIRC control codes
+    Irc,
+    /// The HTML subset understood by Telegram
+    TelegramHtml,
+}
+
+impl TextDialect {
+    pub fn from_str(s: &str) -> Option<TextDialect> {
+        match s {
+            "plain" => Some(TextDialect::Plain),
+            "markdown" => Some(TextDialect::Markdown),
+            "irc" => Some(TextDialect::Irc),
+            "telegram_html" => Some(TextDialect::TelegramHtml),
+            _ => None,
+        }
+    }
+}
+
+const IRC_BOLD: char = '\x02';
+const IRC_ITALIC: char = '\x1d';
+const IRC_UNDERLINE: char = '\x1f';
+const IRC_STRIKETHROUGH: char = '\x1e';
+const IRC_MONOSPACE: char = '\x11';
+
+fn escape_markdown(text: &str, out: &mut String) {
+    for c in text.chars() {
+        if matches!(c, '\\' | '*' | '_' | '~' | '`' | '|' | '>' | '[' | ']') {
+            out.push('\\');
+        }
+        out.push(c);
+    }
+}
+
+fn escape_html(text: &str, out: &mut String) {
+    for c in text.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            c => out.push(c),
+        }
+    }
+}
+
+/// Text can't toggle formatting on IRC by itself, so control codes in it are dropped
+fn escape_irc(text: &str, out: &mut String) {
+    out.extend(
+        text.chars()
+            .filter(|c| !c.is_control() || *c == '\n' || *c == '\t'),
+    );
+}
+
+fn escape(text: &str, dialect: TextDialect, out: &mut String) {
+    match dialect {
+        TextDialect::Plain => out.push_str(text),
+        TextDialect::Markdown => escape_markdown(text, out),
+        TextDialect::Irc => escape_irc(text, out),
+        TextDialect::TelegramHtml => escape_html(text, out),
+    }
+}
+
+/// Wrap children in the markers of a style, as markdown, an IRC control code and an HTML tag
+fn render_wrapped(
+    children: &[RichText],
+    dialect: TextDialect,
+    (markdown, irc, html): (&str, char, &str),
+    out: &mut String,
+) {
+    match dialect {
+        TextDialect::Plain => render_into(children, dialect, out),
+        TextDialect::Markdown => {
+            out.push_str(markdown);
+            render_into(children, dialect, out);
+            out.push_str(markdown);
+        }
+        TextDialect::Irc => {
+            out.push(irc);
+            render_into(children, dialect, out);
+            out.push(irc);
+        }
+        TextDialect::TelegramHtml => {
+            out.push_str(&format!("<{}>", html));
+            render_into(children, dialect, out);
+            out.push_str(&format!("</{}>", html));
+        }
+    }
+}
+
+fn render_into(text: &[RichText], dialect: TextDialect, out: &mut String) {
+    for node in text {
+        match node {
+            RichText::Text(text) => escape(text, dialect, out),
+            RichText::Bold(children) => {
+                render_wrapped(children, dialect, ("**", IRC_BOLD, "b"), out)
+            }
+            RichText::Italic(children) => {
+                render_wrapped(children, dialect, ("*", IRC_ITALIC, "i"), out)
+            }
+            RichText::Underline(children) => {
+                render_wrapped(children, dialect, ("__", IRC_UNDERLINE, "u"), out)
+            }
+            RichText::Strikethrough(children) => {
+                render_wrapped(children, dialect, ("~~", IRC_STRIKETHROUGH, "s"), out)
+            }
+            RichText::Code(code) => match dialect {
+                TextDialect::Plain => out.push_str(code),
+                // Backticks can't be escaped inside of inline code, a double fence allows single ones
+                TextDialect::Markdown if code.contains('`') => {
+                    out.push_str("`` ");
+                    out.push_str(&code.replace("``", "` `"));
+                    out.push_str(" ``");
+                }
+                TextDialect::Markdown => {
+                    out.push('`');
+                    out.push_str(code);
+                    out.push('`');
+                }
+                TextDialect::Irc => {
+                    out.push(IRC_MONOSPACE);
+                    escape_irc(code, out);
+                    out.push(IRC_MONOSPACE);
+                }
+                TextDialect::TelegramHtml => {
+                    out.push_str("<code>");
+                    escape_html(code, out);
+                    out.push_str("</code>");
+                }
+            },
+            RichText::CodeBlock { code, language } => match dialect {
+                TextDialect::Plain => out.push_str(code),
+                TextDialect::Markdown => {
+                    out.push_str("```");
+                    out.push_str(language.as_deref().unwrap_or_default());
+                    out.push('\n');
+                    // A zero width space keeps fences inside of the code from closing the block
+                    out.push_str(&code.replace("```", "`\u{200b}``"));
+                    out.push_str("\n```");
+                }
+                // IRC has no blocks, every line is monospaced on its own
+                TextDialect::Irc => {
+                    for (i, line) in code.lines().enumerate() {
+                        if i > 0 {
+                            out.push('\n');
+                        }
+
+                        out.push(IRC_MONOSPACE);
+                        escape_irc(line, out);
+                        out.push(IRC_MONOSPACE);
+                    }
+                }
+                TextDialect::TelegramHtml => {
+                    match language {
+                        Some(language) => {
+                            out.push_str("<pre><code class=\"language-");
+                            escape_html(language, out);
+                            out.push_str("\">");
+                        }
+                        None => out.push_str("<pre><code>"),
+                    }
+
+                    escape_html(code, out);
+                    out.push_str("</code></pre>");
+                }
+            },
+            RichText::Link { text, url } => match dialect {
+                TextDialect::Markdown => {
+                    out.push('[');
+                    render_into(text, dialect, out);
+                    out.push_str("](<");
+                    out.push_str(&url.replace('>', "%3E"));
+                    out.push_str(">)");
+                }
+                TextDialect::TelegramHtml => {
+                    out.push_str("<a href=\"");
+                    escape_html(url, out);
+                    out.push_str("\">");
+                    render_into(text, dialect, out);
+                    out.push_str("</a>");
+                }
+                TextDialect::Plain | TextDialect::Irc => {
+                    render_into(text, dialect, out);
+                    out.push_str(" (");
+                    escape(url, dialect, out);
+                    out.push(')');
+                }
+            },
+        }
+    }
+}
+
+pub fn render(text: &[RichText], dialect: TextDialect) -> String {
+    let mut out = String::new();
+    render_into(text, dialect, &mut out);
+    out
+}
@@ -1,15 +1,26 @@
 use anyhow::Result;
 use serde::{Deserialize, Serialize};
-use std::sync::Arc;
+use std::{collections::HashMap, sync::Arc};
 
+mod github;
 mod lua;
+mod message_log;
+mod moderation;
+mod polls;
+mod reaction_roles;
+mod telemetry;
 mod utils;
+mod welcome;
+
+pub use github::GithubModuleConfig;
+pub use lua::run_bench_cli;
+pub use telemetry::TelemetryModuleConfig;
 
 use crate::{
     bot::Bot,
     config::Config,
-    services::{ChannelId, Message, MessageId, ServerId, Service, User},
-    settings::Settings,
+    services::{ChannelId, InteractionId, Message, MessageId, ServerId, Service, User},
+    settings::{SettingInfo, Settings},
 };
 
 macro_rules! modules_loader {
@@ -79,6 +90,36 @@ macro_rules! modules_loader {
                 )+
             }
 
+            pub async fn member(&self, server_id: ServerId, user: Arc<dyn User<impl Service>>, joined: bool) {
+                $(
+                    if self.$module_ident.is_enabled() {
+                        if let Err(err) = self.$module_ident.module().member(server_id, user.clone(), joined).await {
+                            println!("error during executing module {}: {}", self.$module_ident.module().name(), err.to_string())
+                        };
+                    }
+                )+
+            }
+
+            #[allow(dead_code)]
+            pub async fn component(&self, msg: Arc<dyn Message<impl Service>>, user: Arc<dyn User<impl Service>>, id: String, values: Vec<String>, interaction_id: InteractionId) {
+                $(
+                    if self.$module_ident.is_enabled() {
+                        if let Err(err) = self.$module_ident.module().component(msg.clone(), user.clone(), id.clone(), values.clone(), interaction_id).await {
+                            println!("error during executing module {}: {}", self.$module_ident.module().name(), err.to_string())
+                        };
+                    }
+                )+
+            }
+
+            /// The settings of every module, without loading the modules
+            pub fn settings_reference(bot: Arc<Bot>) -> Result<Vec<(&'static str, Vec<SettingInfo>)>> {
+                Ok(vec![
+                    $(
+                        (<$module>::ID, <<$module as Module>::ModuleSettings>::create(bot.clone())?.enumerate()),
+                    )+
+                ])
+            }
+
             pub fn get_settings(&self, name: &str) -> Option<Arc<dyn Settings>> {
                 match name {
                     $(
@@ -88,6 +129,33 @@ macro_rules! modules_loader {
                 }
             }
 
+            /// Collect the state every module wants to carry over an exec restart
+            pub async fn handoff(&self) -> HashMap<String, serde_json::Value> {
+                let mut states = HashMap::new();
+
+                $(
+                    match self.$module_ident.module().handoff().await {
+                        Ok(Some(state)) => {
+                            states.insert(<$module>::ID.to_string(), state);
+                        }
+                        Ok(None) => {}
+                        Err(err) => println!("error during handoff of module {}: {}", self.$module_ident.module().name(), err.to_string()),
+                    }
+                )+
+
+                states
+            }
+
+            pub async fn resume(&self, mut states: HashMap<String, serde_json::Value>) {
+                $(
+                    if let Some(state) = states.remove(<$module>::ID) {
+                        if let Err(err) = self.$module_ident.module().resume(state).await {
+                            println!("error resuming module {}: {}", self.$module_ident.module().name(), err.to_string())
+                        }
+                    }
+                )+
+            }
+
             // Join all the unload functions of the modules and return the first error if any
             pub async fn unload(&self) -> Result<()> {
                 let (
@@ -110,7 +178,7 @@ macro_rules! modules_loader {
         <$module>::load($bot, ()).await?
     };
     (__init, $module:ty, $bot:expr, $config:expr, $config_ident:ident) => {
-        <$module>::load($bot, $config.$config_ident).await?
+        <$module>::load($bot, $config.$config_ident.clone()).await?
     };
 }
 
@@ -148,6 +216,25 @@ pub trait Module: 'static + Send + Sync + Sized {
         remove: bool,
     ) -> Result<()>;
 
+    async fn component(
+        &self,
+        msg: Arc<dyn Message<impl Service>>,
+        user: Arc<dyn User<impl Service>>,
+        id: String,
+        values: Vec<String>,
+        interaction_id: InteractionId,
+    ) -> Result<()>;
+
+    /// A user joined or left a server
+    async fn member(
+        &self,
+        _server_id: ServerId,
+        _user: Arc<dyn User<impl Service>>,
+        _joined: bool,
+    ) -> Result<()> {
+        Ok(())
+    }
+
     async fn enabled(&self, server_id: ServerId, channel_id: ChannelId) -> Result<bool>;
 
     fn kind(&self) -> ModuleKind {
@@ -158,6 +245,14 @@ pub trait Module: 'static + Send + Sync + Sized {
     }
 
     fn settings(&self) -> &Arc<Self::ModuleSettings>;
+
+    /// State to carry over an exec restart, handed to `resume` in the new process
+    async fn handoff(&self) -> Result<Option<serde_json::Value>> {
+        Ok(None)
+    }
+    async fn resume(&self, _state: serde_json::Value) -> Result<()> {
+        Ok(())
+    }
 }
 
 pub struct ModuleWrapper<M: Module> {
@@ -179,13 +274,27 @@ impl<M: Module> ModuleWrapper<M> {
 }
 
 pub enum ModuleKind {
+    Github,
     Lua,
+    MessageLog,
+    Moderation,
+    Polls,
+    ReactionRoles,
+    Telemetry,
     Utils,
+    Welcome,
 }
 
 modules_loader! {
     Modules,
 
+    github => (github::GithubModule, github),
     lua => (lua::LuaModule, ()),
-    utils => (utils::UtilsModule, ())
+    message_log => (message_log::MessageLogModule, ()),
+    moderation => (moderation::ModerationModule, ()),
+    polls => (polls::PollsModule, ()),
+    reaction_roles => (reaction_roles::ReactionRolesModule, ()),
+    telemetry => (telemetry::TelemetryModule, telemetry),
+    utils => (utils::UtilsModule, ()),
+    welcome => (welcome::WelcomeModule, ())
 }
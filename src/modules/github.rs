@@ -0,0 +1,482 @@
+use anyhow::Result;
+use hmac::{Hmac, Mac};
+use hyper::{Body, Client, Request, Response, StatusCode};
+use hyper_tls::HttpsConnector;
+use serde_json::Value;
+use sha2::Sha256;
+use std::sync::Arc;
+use thiserror::Error;
+
+use super::{Module, ModuleKind};
+use crate::{
+    bot::Bot,
+    message::{MessageEmbed, MessagePriority, MessageSettings},
+    server::{read_body, status_response, HttpHandler},
+    services::{
+        Channel, ChannelId, InteractionId, Message, MessageId, Server, ServerId, Service, User,
+    },
+    settings::prelude::*,
+};
+
+const GITHUB_API_URL: &'static str = "https://api.github.com";
+const WEBHOOK_PATH: &'static str = "/github";
+const MAX_COMMITS: usize = 5;
+
+const COLOR_PUSH: u32 = 0x7289da;
+const COLOR_OPENED: u32 = 0x2cbe4e;
+const COLOR_CLOSED: u32 = 0xcb2431;
+const COLOR_MERGED: u32 = 0x6f42c1;
+
+#[derive(Clone, Deserialize, Serialize, Debug)]
+pub struct GithubModuleConfig {
+    pub webhook_secret: String,
+    pub token: Option<String>,
+    #[serde(default)]
+    pub routes: Vec<GithubRoute>,
+}
+
+#[derive(Clone, Deserialize, Serialize, Debug)]
+pub struct GithubRoute {
+    /// Repository in the owner/name format, or "*" for any repository
+    pub repo: String,
+    pub channel: String,
+    pub events: Option<Vec<String>>,
+}
+
+impl GithubRoute {
+    fn matches(&self, repo: &str, event: &str) -> bool {
+        (self.repo == "*" || self.repo.eq_ignore_ascii_case(repo))
+            && self
+                .events
+                .as_ref()
+                .map(|events| events.iter().any(|e| e == event))
+                .unwrap_or(true)
+    }
+}
+
+pub struct GithubModule {
+    bot: Arc<Bot>,
+    config: Option<GithubModuleConfig>,
+    settings: Arc<GithubModuleSettings>,
+}
+
+settings! {
+    GithubModuleSettings,
+    GithubModule,
+    {
+        notifications: bool => (true, SettingFlags::SERVER_OVERRIDE, "Post GitHub webhook events routed to the channel", [])
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct GithubIssue {
+    pub number: u64,
+    pub title: String,
+    pub state: String,
+    pub url: String,
+    pub author: String,
+    pub body: Option<String>,
+    pub comments: u64,
+    pub is_pull_request: bool,
+}
+
+#[async_trait]
+impl Module for GithubModule {
+    const KIND: ModuleKind = ModuleKind::Github;
+    const ID: &'static str = "github";
+    const NAME: &'static str = "GitHub";
+
+    type ModuleConfig = Option<GithubModuleConfig>;
+    type ModuleSettings = GithubModuleSettings;
+
+    async fn load(bot: Arc<Bot>, config: Option<GithubModuleConfig>) -> Result<Arc<GithubModule>> {
+        let module = Arc::new(GithubModule {
+            bot: bot.clone(),
+            config,
+            settings: GithubModuleSettings::create(bot.clone())?,
+        });
+
+        if module.config.is_some() {
+            bot.http_server().register(
+                WEBHOOK_PATH,
+                Arc::new(GithubWebhookHandler(module.clone())),
+            );
+        }
+
+        Ok(module)
+    }
+
+    async fn unload(&self) -> Result<()> {
+        self.bot.http_server().unregister(WEBHOOK_PATH);
+
+        Ok(())
+    }
+
+    async fn message(&self, _msg: Arc<dyn Message<impl Service>>) -> Result<()> {
+        Ok(())
+    }
+
+    async fn message_update(
+        &self,
+        _msg: Arc<dyn Message<impl Service>>,
+        _old_msg: Option<Arc<dyn Message<impl Service>>>,
+    ) -> Result<()> {
+        Ok(())
+    }
+
+    async fn message_delete(
+        &self,
+        _server_id: Option<ServerId>,
+        _channel_id: ChannelId,
+        _message_id: MessageId,
+    ) -> Result<()> {
+        Ok(())
+    }
+
+    async fn reaction(
+        &self,
+        _msg: Arc<dyn Message<impl Service>>,
+        _reactor: Arc<dyn User<impl Service>>,
+        _reaction: String,
+        _remove: bool,
+    ) -> Result<()> {
+        Ok(())
+    }
+
+    async fn component(
+        &self,
+        _msg: Arc<dyn Message<impl Service>>,
+        _user: Arc<dyn User<impl Service>>,
+        _id: String,
+        _values: Vec<String>,
+        _interaction_id: InteractionId,
+    ) -> Result<()> {
+        Ok(())
+    }
+
+    async fn enabled(&self, server_id: ServerId, channel_id: ChannelId) -> Result<bool> {
+        self.settings.notifications.value(server_id, channel_id).await
+    }
+
+    fn settings(&self) -> &Arc<GithubModuleSettings> {
+        &self.settings
+    }
+}
+
+// The repo is spliced into an api path sent with the bot's token, so owner and name may only
+// hold the characters github allows in them
+fn is_valid_repo(repo: &str) -> bool {
+    let mut parts = repo.split('/');
+
+    match (parts.next(), parts.next(), parts.next()) {
+        (Some(owner), Some(name), None) => [owner, name].iter().all(|part| {
+            !part.is_empty()
+                && *part != "."
+                && *part != ".."
+                && part
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '.' || c == '-')
+        }),
+        _ => false,
+    }
+}
+
+impl GithubModule {
+    pub async fn issue(&self, repo: &str, number: u64) -> Result<GithubIssue> {
+        if !is_valid_repo(repo) {
+            return Err(GithubError::InvalidRepo(repo.into()).into());
+        }
+
+        let issue = self
+            .api_get(&format!("/repos/{}/issues/{}", repo, number))
+            .await?;
+
+        Ok(GithubIssue {
+            number: issue["number"].as_u64().unwrap_or(number),
+            title: str_field(&issue["title"]),
+            state: str_field(&issue["state"]),
+            url: str_field(&issue["html_url"]),
+            author: str_field(&issue["user"]["login"]),
+            body: issue["body"].as_str().map(|s| s.to_string()),
+            comments: issue["comments"].as_u64().unwrap_or_default(),
+            is_pull_request: !issue["pull_request"].is_null(),
+        })
+    }
+
+    async fn api_get(&self, path: &str) -> Result<Value> {
+        let https = HttpsConnector::new();
+        let client = Client::builder().build::<_, Body>(https);
+
+        let mut req = Request::builder()
+            .method("GET")
+            .uri(format!("{}{}", GITHUB_API_URL, path))
+            .header("Accept", "application/vnd.github+json")
+            .header("User-Agent", "kaito");
+
+        if let Some(token) = self.config.as_ref().and_then(|c| c.token.as_ref()) {
+            req = req.header("Authorization", format!("Bearer {}", token));
+        }
+
+        let res = client.request(req.body(Body::empty())?).await?;
+        let status = res.status();
+        let body = hyper::body::to_bytes(res.into_body()).await?;
+
+        match status {
+            StatusCode::NOT_FOUND => Err(GithubError::NotFound.into()),
+            status if !status.is_success() => Err(GithubError::ApiError(status.as_u16()).into()),
+            _ => Ok(serde_json::from_slice(&body)?),
+        }
+    }
+
+    async fn handle_event(&self, event: &str, payload: Value) -> Result<()> {
+        let config = match self.config.as_ref() {
+            Some(config) => config,
+            None => return Ok(()),
+        };
+
+        let repo = str_field(&payload["repository"]["full_name"]);
+
+        let embed = match format_event(event, &payload) {
+            Some(embed) => embed,
+            None => return Ok(()),
+        };
+
+        let ctx = self.bot.get_ctx();
+
+        // Scripts can drop events through the github_event hook
+        let allowed = ctx
+            .modules()
+            .lua
+            .module()
+            .call_lua("bot.on_github_event", &[Value::from(event), payload.clone()])
+            .await;
+
+        match allowed {
+            Ok(values) if values.first() == Some(&Value::Bool(false)) => return Ok(()),
+            Ok(_) => {}
+            Err(err) => println!("github_event hook failed: {:?}", err),
+        }
+
+        for route in config.routes.iter().filter(|r| r.matches(&repo, event)) {
+            let channel_id = match ChannelId::from_str(&route.channel) {
+                Ok(id) => id,
+                Err(err) => {
+                    println!(
+                        "invalid channel \"{}\" in github route: {}",
+                        route.channel,
+                        err.to_string()
+                    );
+                    continue;
+                }
+            };
+
+            let channel = ctx.services().channel(channel_id).await?;
+            let server = channel.server().await?;
+
+            if !self.enabled(server.id(), channel_id).await? {
+                continue;
+            }
+
+            ctx.services()
+                .send_message(
+                    channel_id,
+                    "",
+                    MessageSettings {
+                        embed: Some(embed.clone()),
+                        priority: MessagePriority::Low,
+                        ..Default::default()
+                    },
+                )
+                .await?;
+        }
+
+        Ok(())
+    }
+}
+
+struct GithubWebhookHandler(Arc<GithubModule>);
+
+#[async_trait]
+impl HttpHandler for GithubWebhookHandler {
+    async fn handle(&self, req: Request<Body>) -> Result<Response<Body>> {
+        let secret = match self.0.config.as_ref() {
+            Some(config) => config.webhook_secret.clone(),
+            None => return Ok(status_response(StatusCode::NOT_FOUND)),
+        };
+
+        if req.method() != hyper::Method::POST {
+            return Ok(status_response(StatusCode::METHOD_NOT_ALLOWED));
+        }
+
+        let (parts, body) = read_body(req).await?;
+
+        let signature = parts
+            .headers
+            .get("X-Hub-Signature-256")
+            .and_then(|v| v.to_str().ok())
+            .unwrap_or_default();
+
+        if !verify_signature(secret.as_bytes(), &body, signature) {
+            return Ok(status_response(StatusCode::UNAUTHORIZED));
+        }
+
+        let event = parts
+            .headers
+            .get("X-GitHub-Event")
+            .and_then(|v| v.to_str().ok())
+            .unwrap_or_default()
+            .to_string();
+
+        let payload: Value = match serde_json::from_slice(&body) {
+            Ok(payload) => payload,
+            Err(_) => return Ok(status_response(StatusCode::BAD_REQUEST)),
+        };
+
+        let module = self.0.clone();
+        tokio::spawn(async move {
+            if let Err(err) = module.handle_event(&event, payload).await {
+                println!("error handling github event {}: {}", event, err.to_string());
+            }
+        });
+
+        Ok(status_response(StatusCode::NO_CONTENT))
+    }
+}
+
+fn verify_signature(secret: &[u8], body: &[u8], signature: &str) -> bool {
+    let signature = match signature
+        .strip_prefix("sha256=")
+        .and_then(|s| hex::decode(s).ok())
+    {
+        Some(signature) => signature,
+        None => return false,
+    };
+
+    let mut mac = match Hmac::<Sha256>::new_from_slice(secret) {
+        Ok(mac) => mac,
+        Err(_) => return false,
+    };
+    mac.update(body);
+
+    mac.verify_slice(&signature).is_ok()
+}
+
+fn str_field(value: &Value) -> String {
+    value.as_str().unwrap_or_default().to_string()
+}
+
+fn truncate(text: &str, max_chars: usize) -> String {
+    if text.chars().count() > max_chars {
+        let mut out: String = text.chars().take(max_chars - 1).collect();
+        out.push('…');
+        out
+    } else {
+        text.to_string()
+    }
+}
+
+fn format_event(event: &str, payload: &Value) -> Option<MessageEmbed> {
+    let repo = str_field(&payload["repository"]["full_name"]);
+
+    let mut embed = MessageEmbed {
+        author_name: Some(str_field(&payload["sender"]["login"])),
+        author_icon_url: payload["sender"]["avatar_url"].as_str().map(|s| s.into()),
+        author_url: payload["sender"]["html_url"].as_str().map(|s| s.into()),
+        footer_text: Some(repo.clone()),
+        timestamp: Some(chrono::Utc::now()),
+        ..Default::default()
+    };
+
+    match event {
+        "push" => {
+            let commits = payload["commits"].as_array()?;
+
+            if commits.is_empty() {
+                return None;
+            }
+
+            let branch = str_field(&payload["ref"]);
+            let branch = branch.trim_start_matches("refs/heads/");
+
+            let mut description = String::new();
+
+            for commit in commits.iter().take(MAX_COMMITS) {
+                let id = str_field(&commit["id"]);
+                let message = str_field(&commit["message"]);
+
+                description.push_str(&format!(
+                    "[`{}`]({}) {}\n",
+                    &id[..id.len().min(7)],
+                    str_field(&commit["url"]),
+                    truncate(message.lines().next().unwrap_or_default(), 64)
+                ));
+            }
+
+            if commits.len() > MAX_COMMITS {
+                description.push_str(&format!("and {} more", commits.len() - MAX_COMMITS));
+            }
+
+            embed.title = Some(format!(
+                "[{}:{}] {} new commit{}",
+                repo,
+                branch,
+                commits.len(),
+                if commits.len() == 1 { "" } else { "s" }
+            ));
+            embed.description = Some(description);
+            embed.color = Some(COLOR_PUSH);
+        }
+        "issues" | "pull_request" => {
+            let action = str_field(&payload["action"]);
+            let (item, kind) = if event == "issues" {
+                (&payload["issue"], "Issue")
+            } else {
+                (&payload["pull_request"], "Pull request")
+            };
+
+            let merged = item["merged"].as_bool().unwrap_or(false);
+
+            let (action, color) = match action.as_str() {
+                "opened" | "reopened" => (action.as_str(), COLOR_OPENED),
+                "closed" if merged => ("merged", COLOR_MERGED),
+                "closed" => ("closed", COLOR_CLOSED),
+                _ => return None,
+            };
+
+            embed.title = Some(format!(
+                "[{}] {} {}: #{} {}",
+                repo,
+                kind,
+                action,
+                item["number"].as_u64().unwrap_or_default(),
+                truncate(&str_field(&item["title"]), 128)
+            ));
+
+            let mut description = str_field(&item["html_url"]);
+
+            if action == "opened" {
+                if let Some(body) = item["body"].as_str().filter(|body| !body.is_empty()) {
+                    description.push_str("\n\n");
+                    description.push_str(&truncate(body, 512));
+                }
+            }
+
+            embed.description = Some(description);
+
+            embed.color = Some(color);
+        }
+        _ => return None,
+    }
+
+    Some(embed)
+}
+
+#[derive(Debug, Error)]
+pub enum GithubError {
+    #[error("invalid repository \"{}\", expected owner/name", _0)]
+    InvalidRepo(String),
+    #[error("not found")]
+    NotFound,
+    #[error("github api error ({})", _0)]
+    ApiError(u16),
+}
@@ -1,33 +1,83 @@
 use anyhow::Result;
+use arc_swap::ArcSwap;
 use async_mutex::{Mutex, MutexGuardArc};
 use crossbeam::channel::TryRecvError;
+use futures::StreamExt;
+use hyper::{Body, Request, Response, StatusCode};
 use lru::LruCache;
+use serde_json::Value as JsonValue;
 use std::{
-    sync::Arc,
+    collections::{BTreeMap, HashMap},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex as StdMutex, Weak},
     time::{Duration, Instant},
 };
 
 #[macro_use]
-mod lib;
+pub mod lib;
+mod bench;
+mod clock;
+mod error;
+mod filter;
 mod http;
+mod intern;
+mod ipc;
+mod limiter;
+mod pool;
+mod replay;
+mod scheduler;
+mod scripts;
 mod state;
+pub mod throttle;
+mod url_scan;
 mod utils;
+mod watch;
 
-use self::lib::bot::BotUser;
+use self::lib::{
+    blob::{self, BlobHandler},
+    bot::{BotInteraction, BotUser},
+    schedule,
+};
+
+pub use bench::run_cli as run_bench_cli;
 
 use super::{Module, ModuleKind};
 use crate::{
-    bot::Bot,
-    message::MessageSettings,
+    bot::{admin, db::Uid, events::BotEvent, permissions::Caller, Bot},
+    message::{MessagePriority, MessageSettings, Sanitize},
+    server::{status_response, HttpHandler},
     services::{
-        Channel, ChannelId, Message, MessageId, Server, ServerId, Service, ServiceFeatures,
-        ServiceKind, User,
+        Channel, ChannelId, InteractionId, Message, MessageId, Server, ServerId, Service,
+        ServiceFeatures, ServiceKind, User, UserId,
     },
     settings::prelude::*,
     utils::{escape_untrusted_text, shell_parser::parse_shell_args},
 };
+use clock::LuaClock;
+use filter::{ContentFilter, FilterError};
+use http::{HttpQuota, HttpRateLimiter};
+use ipc::IpcBroker;
 use lib::bot::BotMessage;
-use state::{LuaState, SandboxMsg, SandboxTerminationReason};
+use limiter::{CommandRateLimiter, OffenderState, RateLimit, RateLimitResult};
+use pool::SandboxPool;
+use scripts::{ScriptManifest, ScriptVersion, ScriptVersions};
+use state::{
+    LuaState, LuaVmStats, SandboxError, SandboxMsg, SandboxOwner, SandboxStateInner,
+    SandboxTerminationReason,
+};
+use throttle::{Throttle, ThrottleQuota};
+use url_scan::{UrlScanAction, UrlScanError, UrlScanner};
+use utils::TraceId;
+use watch::AffectedStates;
+
+// Sandbox output that doesn't fit in this many messages is sent as an attachment
+const MAX_SANDBOX_OUTPUT_PARTS: usize = 2;
+// The bot state is rebuilt after failing to think this many times in a row
+const MAX_THINK_FAILURES: usize = 20;
+// Scripts that take longer to hand off their state on reload start over without it
+const HANDOFF_TIMEOUT: Duration = Duration::from_secs(5);
+
+const METRICS_PATH: &str = "/metrics/lua";
 
 pub type LuaSandboxReplies = Mutex<LruCache<MessageId, (bool, Vec<(ChannelId, MessageId)>)>>;
 
@@ -35,8 +85,19 @@ pub struct LuaModule {
     bot: Arc<Bot>,
     settings: Arc<LuaModuleSettings>,
     bot_state: Arc<Mutex<LuaState>>,
-    sandbox_state: Arc<Mutex<LuaState>>,
+    sandbox_pool: Arc<SandboxPool>,
     lua_sandbox_replies: Arc<LuaSandboxReplies>,
+    lua_root_path: ArcSwap<PathBuf>,
+    script_versions: ScriptVersions,
+    scripts_lock: Mutex<()>,
+    command_limiter: CommandRateLimiter,
+    ipc: Arc<IpcBroker>,
+    throttle: Arc<Throttle>,
+    http_rate_limiter: Arc<HttpRateLimiter>,
+    url_scanner: Arc<UrlScanner>,
+    clock: LuaClock,
+    /// Sandbox runs in flight by the user who started them, the quota command reads their counters
+    sandbox_runs: StdMutex<HashMap<Uid, Weak<SandboxStateInner>>>,
 }
 
 settings! {
@@ -46,7 +107,26 @@ settings! {
         enable: bool => (true, SettingFlags::empty(), "Enable the lua module", []),
         prefix: String => ("&".into(), SettingFlags::empty(), "Set the message prefix for lua commands", [max_len => 8]),
         always_eval: bool => (false, SettingFlags::empty(), "Evaluate all messages in the sandbox", []),
-        lua_prefix: String => ("]".into(), SettingFlags::empty(), "Set the lua prefix for runnning lua code in the sandbox with errors", [max_len => 8])
+        lua_prefix: String => ("]".into(), SettingFlags::empty(), "Set the lua prefix for runnning lua code in the sandbox with errors", [max_len => 8]),
+        command_rate_limit: i64 => (5, SettingFlags::empty(), "Set how many commands a user can run per period, 0 disables the limit", [min => 0 max => 1000]),
+        command_rate_period: i64 => (10, SettingFlags::empty(), "Set the command rate limit period in seconds", [min => 1 max => 3600]),
+        command_channel_rate_limit: i64 => (20, SettingFlags::empty(), "Set how many commands can be run in a channel per period, 0 disables the limit", [min => 0 max => 1000]),
+        http_user_rate_limit: i64 => (10, SettingFlags::empty(), "Set how many sandbox http calls a user can make per minute, 0 disables the limit", [min => 0 max => 600]),
+        http_channel_rate_limit: i64 => (20, SettingFlags::empty(), "Set how many sandbox http calls can be made in a channel per minute, 0 disables the limit", [min => 0 max => 600]),
+        translate_provider: String => ("".into(), SettingFlags::empty(), "Set the translation provider (deepl, libretranslate), defaults to the first configured provider", [max_len => 16]),
+        translate_language: String => ("en".into(), SettingFlags::empty(), "Set the default language to translate into", [max_len => 8]),
+        sandbox_enabled: bool => (true, SettingFlags::SERVER_OVERRIDE, "Allow running lua code in the sandbox", []),
+        gifs_enabled: bool => (true, SettingFlags::empty(), "Allow commands to send GIFs and stickers", []),
+        sanitize_mentions: bool => (true, SettingFlags::empty(), "Escape user and role mentions in sandbox output", []),
+        sanitize_invites: bool => (true, SettingFlags::empty(), "Escape invite links in sandbox output", []),
+        admins: UserList => (UserList::default(), SettingFlags::SERVER_OVERRIDE, "Users with admin permissions in the server, as comma separated user ids", [max_len => 32]),
+        permissions: Vec<PermissionGrant> => (Vec::new(), SettingFlags::SERVER_OVERRIDE, "Permission nodes granted to users or roles, as comma separated entries like command.purge=discord:1234 or setting.lua.*=role:trusted", [max_len => 64]),
+        filter_words: String => ("".into(), SettingFlags::SERVER_OVERRIDE, "Comma separated words that sandbox code and output can't contain, ignoring case", [max_len => 2000]),
+        filter_pattern: String => ("".into(), SettingFlags::SERVER_OVERRIDE, "Regex that sandbox code and output can't match", [max_len => 500]),
+        filter_max_mentions: i64 => (0, SettingFlags::SERVER_OVERRIDE, "Set how many users sandbox output can mention, 0 disables the limit", [min => 0 max => 100]),
+        filter_action: String => ("reject".into(), SettingFlags::SERVER_OVERRIDE, "What happens to sandbox runs breaking the filter (reject, redact, log), code is always rejected", [max_len => 8]),
+        filter_log_channel: String => ("".into(), SettingFlags::SERVER_OVERRIDE, "Channel notified about sandbox runs breaking the filter, as an id like discord:1234", [max_len => 64]),
+        url_scan: String => ("ignore".into(), SettingFlags::SERVER_OVERRIDE, "What happens to unsafe links in sandbox output and sandbox http fetches (ignore, warn, block)", [max_len => 8])
     }
 }
 
@@ -60,42 +140,175 @@ impl Module for LuaModule {
     type ModuleSettings = LuaModuleSettings;
 
     async fn load(bot: Arc<Bot>, _config: ()) -> Result<Arc<LuaModule>> {
+        let script_versions = ScriptVersions::new(&bot);
+        let lua_root_path = initial_lua_root_path(&bot, &script_versions).await;
+
+        let clock = LuaClock::from_config(&bot);
+
         let lua_sandbox_replies = Arc::new(Mutex::new(LruCache::new(64)));
-        let sandbox_state = Arc::new(Mutex::new(LuaState::create_state(&bot, true, None)?));
+        let sandbox_pool = SandboxPool::new(&bot, lua_root_path.clone(), &clock);
         let bot_state = Arc::new(Mutex::new(LuaState::create_state(
             &bot,
             false,
-            Some((sandbox_state.clone(), lua_sandbox_replies.clone())),
+            Some((sandbox_pool.clone(), lua_sandbox_replies.clone())),
+            &lua_root_path,
+            &clock,
         )?));
 
+        let ipc = Arc::new(IpcBroker::new(&clock));
+
         let bot_state2 = bot_state.clone();
-        let sandbox_state2 = sandbox_state.clone();
+        let ipc2 = ipc.clone();
+        let mut events = bot.events().subscribe();
         tokio::spawn(async move {
-            let mut interval = tokio::time::interval(Duration::from_millis(50));
-
-            loop {
-                interval.tick().await;
-                if let Err(err) = bot_state2.lock_arc().await.think() {
-                    println!("error: {}", err.to_string());
-                }
-                if let Err(err) = sandbox_state2.lock_arc().await.think() {
-                    println!("error: {}", err.to_string());
+            while let Some(event) = events.next().await {
+                match event {
+                    BotEvent::ServiceAudit(audit) => {
+                        if let Err(err) = bot_state2.lock_arc().await.run_bot_audit(audit) {
+                            println!("error: {}", err.to_string());
+                        }
+                    }
+                    BotEvent::Ipc(msg) => {
+                        if let Err(err) = ipc2.deliver(msg) {
+                            println!("error delivering ipc message: {}", err.to_string());
+                        }
+                    }
+                    _ => {}
                 }
             }
         });
 
         bot_state.lock_arc().await.on_loaded()?;
 
-        Ok(Arc::new(LuaModule {
+        let url_scanner = Arc::new(UrlScanner::new(
+            bot.config()
+                .lua
+                .as_ref()
+                .and_then(|lua| lua.url_scan.as_ref()),
+        )?);
+
+        let module = Arc::new(LuaModule {
             bot: bot.clone(),
             settings: LuaModuleSettings::create(bot)?,
             bot_state,
-            sandbox_state,
+            sandbox_pool,
             lua_sandbox_replies,
-        }))
+            lua_root_path: ArcSwap::from_pointee(lua_root_path),
+            script_versions,
+            scripts_lock: Mutex::new(()),
+            command_limiter: CommandRateLimiter::new(clock.clone()),
+            ipc,
+            throttle: Arc::new(Throttle::new(&clock)),
+            http_rate_limiter: Arc::new(HttpRateLimiter::new(
+                bot.config()
+                    .lua
+                    .as_ref()
+                    .and_then(|lua| lua.http_domain_limits.as_ref()),
+                &clock,
+            )),
+            url_scanner,
+            clock,
+            sandbox_runs: StdMutex::new(HashMap::new()),
+        });
+
+        let metrics = module
+            .bot
+            .config()
+            .http
+            .as_ref()
+            .and_then(|http| http.metrics)
+            .unwrap_or(false);
+        if metrics {
+            match admin::admin_token(&module.bot) {
+                Some(token) => module.bot.http_server().register(
+                    METRICS_PATH,
+                    Arc::new(LuaMetricsHandler {
+                        module: module.clone(),
+                        token,
+                    }),
+                ),
+                None => println!("lua: the metrics need an admin token, leaving them out"),
+            }
+        }
+        module
+            .bot
+            .http_server()
+            .register(blob::BLOBS_PATH, Arc::new(BlobHandler(module.bot.clone())));
+
+        let bot2 = module.bot.clone();
+        tokio::spawn(async move {
+            match blob::collect_blob_garbage(&bot2).await {
+                Ok(0) => {}
+                Ok(removed) => println!("removed {} unreferenced blob files", removed),
+                Err(err) => println!("error collecting blobs: {}", err.to_string()),
+            }
+        });
+
+        schedule::spawn_delivery_task(module.bot.clone());
+
+        if module
+            .bot
+            .config()
+            .lua
+            .as_ref()
+            .and_then(|lua| lua.watch_scripts)
+            .unwrap_or(false)
+        {
+            if let Err(err) = watch::spawn_script_watcher(module.clone()) {
+                println!("error watching the lua scripts: {}", err.to_string());
+            }
+        }
+
+        let module2 = module.clone();
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(Duration::from_millis(50));
+            let mut think_failures = 0;
+
+            loop {
+                let (crash_reason, waker) = {
+                    let bot_state = module2.bot_state.lock_arc().await;
+
+                    match bot_state.think() {
+                        Ok(()) => think_failures = 0,
+                        Err(err) => {
+                            println!("error: {}", err.to_string());
+                            bot_state.check_crash(&err);
+                            think_failures += 1;
+                        }
+                    }
+
+                    let crash_reason = bot_state.crash_reason().or_else(|| {
+                        (think_failures >= MAX_THINK_FAILURES)
+                            .then(|| format!("failed to think {} times in a row", think_failures))
+                    });
+
+                    (crash_reason, bot_state.async_waker())
+                };
+
+                if let Some(reason) = crash_reason {
+                    think_failures = 0;
+
+                    if let Err(err) = module2.recover_bot_state(&reason).await {
+                        println!("error rebuilding the bot state: {}", err.to_string());
+                    }
+                }
+
+                // Resolved futures are handled as soon as their callback is queued, coroutines
+                // and timers are still resumed every tick
+                tokio::select! {
+                    _ = interval.tick() => {}
+                    _ = waker.woken() => {}
+                }
+            }
+        });
+
+        Ok(module)
     }
 
     async fn unload(&self) -> Result<()> {
+        self.bot.http_server().unregister(METRICS_PATH);
+        self.bot.http_server().unregister(blob::BLOBS_PATH);
+
         while self.bot_state.clone().lock_arc().await.shutdown()? {}
 
         Ok(())
@@ -136,7 +349,9 @@ impl Module for LuaModule {
         match content.strip_prefix(&prefix) {
             Some(rest) => {
                 let text = rest.to_string();
-                return self.on_command(msg, text, false).await;
+                return self
+                    .on_command(msg, user.uid, server.id(), channel.id(), text, false)
+                    .await;
             }
             None => {}
         };
@@ -155,22 +370,48 @@ impl Module for LuaModule {
             .value(server.id(), channel.id())
             .await?;
 
+        let sandbox_enabled = self
+            .settings
+            .sandbox_enabled
+            .value(server.id(), channel.id())
+            .await?;
+
         match content.strip_prefix(&lua_prefix) {
             Some(rest) => {
+                if !sandbox_enabled {
+                    channel
+                        .send(
+                            "Sorry, running lua code has been disabled here.",
+                            MessageSettings::default(),
+                        )
+                        .await?;
+                    return Ok(());
+                }
+
+                let limit = self.default_rate_limit(server.id(), channel.id()).await?;
+
+                if !self
+                    .check_rate_limit(&msg, user.uid, "sandbox", limit)
+                    .await?
+                {
+                    return Ok(());
+                }
+
                 let text = rest.to_string();
-                return self.eval_sandbox(msg, true, text).await;
+                return self.eval_sandbox(msg, user.uid, true, text).await;
             }
             None => {}
         };
 
-        if self
-            .settings
-            .always_eval
-            .value(server.id(), channel.id())
-            .await?
+        if sandbox_enabled
+            && self
+                .settings
+                .always_eval
+                .value(server.id(), channel.id())
+                .await?
         {
             let text = content.to_string();
-            self.eval_sandbox(msg, false, text).await
+            self.eval_sandbox(msg, user.uid, false, text).await
         } else {
             Ok(())
         }
@@ -215,7 +456,8 @@ impl Module for LuaModule {
         match content.strip_prefix(&prefix) {
             Some(rest) => {
                 let text = rest.to_string();
-                self.on_command(msg, text, true).await
+                self.on_command(msg, user.uid, server.id(), channel.id(), text, true)
+                    .await
             }
             None => Ok(()),
         }
@@ -252,6 +494,26 @@ impl Module for LuaModule {
         Ok(())
     }
 
+    async fn component(
+        &self,
+        msg: Arc<dyn Message<impl Service>>,
+        user: Arc<dyn User<impl Service>>,
+        id: String,
+        values: Vec<String>,
+        interaction_id: InteractionId,
+    ) -> Result<()> {
+        let lua_state = self.get_bot_state().await?;
+        let sender = lua_state.async_sender();
+
+        let bot_msg = BotMessage::from_msg(self.bot.clone(), sender.clone(), &msg).await?;
+        let bot_user = BotUser::from_user(self.bot.clone(), &user).await?;
+        let interaction = BotInteraction::new(self.bot.clone(), sender, interaction_id);
+
+        lua_state.run_bot_component(bot_msg, bot_user, id, values, interaction)?;
+
+        Ok(())
+    }
+
     async fn enabled(&self, server_id: ServerId, channel_id: ChannelId) -> Result<bool> {
         self.settings.enable.value(server_id, channel_id).await
     }
@@ -259,12 +521,58 @@ impl Module for LuaModule {
     fn settings(&self) -> &Arc<LuaModuleSettings> {
         &self.settings
     }
+
+    async fn handoff(&self) -> Result<Option<JsonValue>> {
+        let scripts = self
+            .call_lua("bot.on_handoff", &[])
+            .await?
+            .into_iter()
+            .next()
+            .unwrap_or(JsonValue::Null);
+
+        Ok(Some(serde_json::to_value(LuaHandoff {
+            offenders: self.command_limiter.export(),
+            scripts,
+        })?))
+    }
+
+    async fn resume(&self, state: JsonValue) -> Result<()> {
+        let state: LuaHandoff = serde_json::from_value(state)?;
+
+        self.command_limiter.import(state.offenders);
+
+        if !state.scripts.is_null() {
+            self.call_lua("bot.on_resume", &[state.scripts]).await?;
+        }
+
+        Ok(())
+    }
+}
+
+pub struct SandboxQuota {
+    pub limit: RateLimit,
+    pub runs_left: usize,
+    /// Time left until the user is no longer timed out for running too much
+    pub timeout: Option<Duration>,
+    /// Http calls the quotas shared between runs still allow, None without any quotas
+    pub http_quota_left: Option<u32>,
+    /// The run of the user still in flight, its limits are what's left of its budget
+    pub run: Option<Arc<SandboxStateInner>>,
+}
+
+#[derive(Deserialize, Serialize)]
+struct LuaHandoff {
+    offenders: Vec<OffenderState>,
+    scripts: JsonValue,
 }
 
 impl LuaModule {
     async fn on_command(
         &self,
         msg: Arc<dyn Message<impl Service>>,
+        uid: Uid,
+        server_id: ServerId,
+        channel_id: ChannelId,
         rest: String,
         edited: bool,
     ) -> Result<()> {
@@ -275,29 +583,501 @@ impl LuaModule {
             &rest,
         );
 
+        // Unknown commands fall back to running a server script or tag by name, they're only
+        // charged to the tag limit once one of those exists
+        let command = match args.first() {
+            Some(name) => {
+                let command = self.get_bot_state().await?.command_rate_limit(name)?;
+
+                match command {
+                    Some(command) => Some(command),
+                    None if self.has_fallback_command(server_id, name).await? => {
+                        Some(("tag".into(), None))
+                    }
+                    None => None,
+                }
+            }
+            None => None,
+        };
+
+        if let Some((name, limit)) = command {
+            let limit = match limit {
+                Some(limit) => limit,
+                None => self.default_rate_limit(server_id, channel_id).await?,
+            };
+
+            if !self.check_rate_limit(&msg, uid, &name, limit).await? {
+                return Ok(());
+            }
+
+            if !self
+                .check_channel_rate_limit(&msg, server_id, channel_id)
+                .await?
+            {
+                return Ok(());
+            }
+        }
+
         let lua_state = self.get_bot_state().await?;
         let sender = lua_state.async_sender();
         let bot_msg = BotMessage::from_msg(self.bot.clone(), sender, &msg).await?;
 
-        let res = lua_state.run_bot_command(bot_msg, args, edited);
+        let caller = Caller::new(bot_msg.author().db_user().clone(), server_id, channel_id);
+
+        let trace = TraceId::new();
+        let res = lua_state.run_bot_command(bot_msg, args, edited, caller, trace);
         drop(lua_state);
 
+        self.bot
+            .get_ctx()
+            .modules()
+            .telemetry
+            .module()
+            .count_command();
+
         if let Err(err) = res {
+            println!("error running command [trace {}]: {}", trace, err.to_string());
+
             msg.channel()
                 .await?
-                .send(err.to_string(), MessageSettings::default())
+                .send(
+                    format!("{}\ntrace: {}", err.to_string(), trace),
+                    MessageSettings {
+                        priority: MessagePriority::High,
+                        ..Default::default()
+                    },
+                )
                 .await?;
         }
 
         Ok(())
     }
 
+    /// Sandbox runs a user has left in the current rate limit window of a channel, with the
+    /// counters the limits of their runs are enforced with
+    pub async fn sandbox_quota(
+        &self,
+        uid: Uid,
+        user_id: UserId,
+        server_id: ServerId,
+        channel_id: ChannelId,
+    ) -> Result<SandboxQuota> {
+        let limit = self.default_rate_limit(server_id, channel_id).await?;
+        let (runs_left, timeout) = self.command_limiter.remaining(uid, "sandbox", limit);
+        let http_quota_left = self
+            .http_quota_for(user_id, server_id, channel_id)
+            .await?
+            .remaining(&self.throttle);
+
+        let run = {
+            let mut sandbox_runs = self.sandbox_runs.lock().unwrap();
+            sandbox_runs.retain(|_, run| run.strong_count() > 0);
+            sandbox_runs.get(&uid).and_then(|run| run.upgrade())
+        };
+
+        Ok(SandboxQuota {
+            limit,
+            runs_left,
+            timeout,
+            http_quota_left,
+            run,
+        })
+    }
+
+    async fn has_fallback_command(&self, server_id: ServerId, name: &str) -> Result<bool> {
+        let db = self.bot.db();
+
+        if db.find_tag(server_id, name).await?.is_some() {
+            return Ok(true);
+        }
+
+        let sid = db.get_sid(server_id).await?;
+
+        Ok(db.server_script(sid, name).await?.is_some())
+    }
+
+    async fn default_rate_limit(
+        &self,
+        server_id: ServerId,
+        channel_id: ChannelId,
+    ) -> Result<RateLimit> {
+        let count = self
+            .settings
+            .command_rate_limit
+            .value(server_id, channel_id)
+            .await?;
+        let period = self
+            .settings
+            .command_rate_period
+            .value(server_id, channel_id)
+            .await?;
+
+        Ok(RateLimit {
+            count: count.max(0) as usize,
+            period: Duration::from_secs(period.max(1) as u64),
+        })
+    }
+
+    // Returns false if the user is being rate limited
+    async fn check_rate_limit(
+        &self,
+        msg: &Arc<dyn Message<impl Service>>,
+        uid: Uid,
+        key: &str,
+        limit: RateLimit,
+    ) -> Result<bool> {
+        match self.command_limiter.check(uid, key, limit) {
+            RateLimitResult::Allowed => Ok(true),
+            RateLimitResult::Limited(timeout) => {
+                msg.channel()
+                    .await?
+                    .send(
+                        format!(
+                            "you are running commands too quickly, try again in {} seconds",
+                            timeout.as_secs()
+                        ),
+                        MessageSettings {
+                            reply: Some(msg.id()),
+                            ..Default::default()
+                        },
+                    )
+                    .await?;
+
+                Ok(false)
+            }
+            RateLimitResult::TimedOut => Ok(false),
+        }
+    }
+
+    // Returns false if the channel is running too many commands, whoever runs them
+    async fn check_channel_rate_limit(
+        &self,
+        msg: &Arc<dyn Message<impl Service>>,
+        server_id: ServerId,
+        channel_id: ChannelId,
+    ) -> Result<bool> {
+        let count = self
+            .settings
+            .command_channel_rate_limit
+            .value(server_id, channel_id)
+            .await?;
+
+        if count <= 0 {
+            return Ok(true);
+        }
+
+        let period = self
+            .settings
+            .command_rate_period
+            .value(server_id, channel_id)
+            .await?;
+        let quota = ThrottleQuota {
+            count: count as u32,
+            period: Duration::from_secs(period.max(1) as u64),
+        };
+
+        let key = format!("commands:channel:{}", channel_id.to_str());
+
+        match self.throttle.check(&key, quota)? {
+            None => Ok(true),
+            Some(wait) => {
+                msg.channel()
+                    .await?
+                    .send(
+                        format!(
+                            "this channel is running commands too quickly, try again in {} seconds",
+                            wait.as_secs().max(1)
+                        ),
+                        MessageSettings {
+                            reply: Some(msg.id()),
+                            ..Default::default()
+                        },
+                    )
+                    .await?;
+
+                Ok(false)
+            }
+        }
+    }
+
+    /// Http quotas of sandbox runs for the message, keyed by its author and channel
+    pub async fn http_quota(&self, msg: &BotMessage) -> Result<HttpQuota> {
+        self.http_quota_for(
+            msg.author().id(),
+            msg.channel().server().id(),
+            msg.channel().id(),
+        )
+        .await
+    }
+
+    async fn http_quota_for(
+        &self,
+        user_id: UserId,
+        server_id: ServerId,
+        channel_id: ChannelId,
+    ) -> Result<HttpQuota> {
+        let user_limit = self
+            .settings
+            .http_user_rate_limit
+            .value(server_id, channel_id)
+            .await?;
+        let channel_limit = self
+            .settings
+            .http_channel_rate_limit
+            .value(server_id, channel_id)
+            .await?;
+
+        let user_key = format!("http:user:{}", user_id.to_str());
+        let channel_key = format!("http:channel:{}", channel_id.to_str());
+
+        Ok(HttpQuota::new(vec![
+            (user_key, user_limit),
+            (channel_key, channel_limit),
+        ]))
+    }
+
     async fn restart_sandbox(&self) -> Result<()> {
-        *self.get_sandbox_state().await? = LuaState::create_state(&self.bot, true, None)?;
+        self.sandbox_pool.clear();
+
+        Ok(())
+    }
+
+    /// Swap a crashed bot state for a fresh one running the same scripts, which register their
+    /// commands and hooks again as they load
+    async fn recover_bot_state(&self, reason: &str) -> Result<()> {
+        println!("lua: the bot state crashed, rebuilding it: {}", reason);
+
+        let bot_state = LuaState::create_state(
+            &self.bot,
+            false,
+            Some((self.sandbox_pool.clone(), self.lua_sandbox_replies.clone())),
+            &self.lua_root_path.load(),
+            &self.clock,
+        )?;
+
+        {
+            // The old state can't be trusted to run its shutdown hooks
+            let mut old_bot_state = self.get_bot_state().await?;
+            *old_bot_state = bot_state;
+            old_bot_state.on_loaded()?;
+        }
+
+        let channel_id = match self
+            .bot
+            .config()
+            .lua
+            .as_ref()
+            .and_then(|lua| lua.operator_channel.as_ref())
+        {
+            Some(channel_id) => ChannelId::from_str(channel_id)?,
+            None => return Ok(()),
+        };
+
+        self.bot
+            .get_ctx()
+            .services()
+            .send_message(
+                channel_id,
+                escape_untrusted_text(
+                    channel_id.service_kind(),
+                    format!("The lua bot state crashed and was rebuilt: {}", reason),
+                ),
+                MessageSettings {
+                    priority: MessagePriority::High,
+                    ..Default::default()
+                },
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    /// Load the scripts at the path and swap both states over to them if they loaded cleanly
+    async fn reload_scripts(&self, lua_root_path: PathBuf) -> Result<()> {
+        // Runs get states of their own, this one only makes sure sandbox.lua loads
+        LuaState::create_state(&self.bot, true, None, &lua_root_path, &self.clock)?;
+        self.reload_bot_state(&lua_root_path).await?;
+
+        self.sandbox_pool.set_root_path(lua_root_path.clone());
+        self.lua_root_path.store(Arc::new(lua_root_path));
+
+        Ok(())
+    }
+
+    /// Swap the bot state for one running the scripts at the path. The scripts hand their state
+    /// over through bot.on_handoff and bot.on_resume, like they do across restarts
+    async fn reload_bot_state(&self, lua_root_path: &Path) -> Result<()> {
+        let bot_state = LuaState::create_state(
+            &self.bot,
+            false,
+            Some((self.sandbox_pool.clone(), self.lua_sandbox_replies.clone())),
+            lua_root_path,
+            &self.clock,
+        )?;
+
+        let handoff = self.call_lua("bot.on_handoff", &[]);
+        let handoff = match tokio::time::timeout(HANDOFF_TIMEOUT, handoff).await {
+            Ok(Ok(values)) => values.into_iter().next().unwrap_or(JsonValue::Null),
+            Ok(Err(err)) => {
+                println!("lua: error handing off the bot state: {}", err.to_string());
+                JsonValue::Null
+            }
+            Err(_) => {
+                println!("lua: handing off the bot state timed out");
+                JsonValue::Null
+            }
+        };
+
+        {
+            let mut old_bot_state = self.get_bot_state().await?;
+
+            // Give the shutdown hooks up to 5 seconds to finish
+            for _ in 0..100 {
+                if !old_bot_state.shutdown()? {
+                    break;
+                }
+
+                tokio::time::sleep(Duration::from_millis(50)).await;
+            }
+
+            let in_flight = old_bot_state.drain_async_threads();
+            if in_flight > 0 {
+                // Their results can't reach the new state, waiting threads get to handle the error
+                let rejected = old_bot_state.reject_in_flight_futures()?;
+                if let Err(err) = old_bot_state.think() {
+                    println!("lua: error in the replaced bot state: {}", err.to_string());
+                }
+
+                println!(
+                    "lua: rejected {} futures still in flight in the replaced bot state",
+                    rejected
+                );
+            }
+
+            *old_bot_state = bot_state;
+            old_bot_state.on_loaded()?;
+        }
+
+        if !handoff.is_null() {
+            if let Err(err) = self.call_lua("bot.on_resume", &[handoff]).await {
+                println!("lua: error resuming the bot state: {}", err.to_string());
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Rebuild the states running scripts that changed on disk
+    async fn reload_changed_scripts(&self, affected: AffectedStates) -> Result<()> {
+        if !affected.any() {
+            return Ok(());
+        }
+
+        let _lock = self.scripts_lock.lock().await;
+        let lua_root_path = self.lua_root_path.load_full();
+
+        if affected.sandbox {
+            LuaState::create_state(&self.bot, true, None, &lua_root_path, &self.clock)?;
+            self.sandbox_pool.clear();
+            println!("lua: reloaded the sandbox scripts");
+        }
+
+        if affected.bot {
+            self.reload_bot_state(&lua_root_path).await?;
+            println!("lua: reloaded the bot scripts");
+        }
 
         Ok(())
     }
 
+    /// Load the active scripts again from disk
+    async fn reload_current_scripts(&self, actor_uid: Option<Uid>) -> Result<()> {
+        let _lock = self.scripts_lock.lock().await;
+
+        self.reload_scripts(self.lua_root_path.load().as_ref().clone())
+            .await?;
+
+        self.bot
+            .db()
+            .audit(actor_uid, "scripts.reload", "global", None, None)
+            .await?;
+
+        Ok(())
+    }
+
+    /// Pull the scripts from the configured git repository and hot-swap them in as a new version
+    async fn sync_scripts(&self, actor_uid: Option<Uid>) -> Result<ScriptVersion> {
+        let config = self
+            .bot
+            .config()
+            .scripts
+            .as_ref()
+            .ok_or(scripts::ScriptsError::NotConfigured)?;
+
+        let _lock = self.scripts_lock.lock().await;
+
+        let (lua_root_path, commit) = scripts::sync_git(&self.bot, config).await?;
+        let version = self.script_versions.store(&lua_root_path, Some(commit))?;
+        let previous = self.script_versions.manifest()?.active;
+
+        self.reload_scripts(self.script_versions.version_path(&version.hash))
+            .await?;
+        self.script_versions.activate(&version.hash)?;
+
+        self.bot
+            .db()
+            .audit(
+                actor_uid,
+                "scripts.sync",
+                "global",
+                previous.as_deref(),
+                Some(&version.hash),
+            )
+            .await?;
+
+        Ok(version)
+    }
+
+    /// Revert to a stored version, defaulting to the one before the active version
+    async fn rollback_scripts(
+        &self,
+        hash: Option<String>,
+        actor_uid: Option<Uid>,
+    ) -> Result<ScriptVersion> {
+        let _lock = self.scripts_lock.lock().await;
+
+        let version = self.script_versions.rollback_target(hash.as_deref())?;
+        let previous = self.script_versions.manifest()?.active;
+
+        self.reload_scripts(self.script_versions.version_path(&version.hash))
+            .await?;
+        self.script_versions.activate(&version.hash)?;
+
+        self.bot
+            .db()
+            .audit(
+                actor_uid,
+                "scripts.rollback",
+                "global",
+                previous.as_deref(),
+                Some(&version.hash),
+            )
+            .await?;
+
+        Ok(version)
+    }
+
+    /// Call a function in the bot state and wait for the future it returns to resolve
+    pub async fn call_lua(&self, path: &str, args: &[JsonValue]) -> Result<Vec<JsonValue>> {
+        let handle = self.get_bot_state().await?.call_async(path, args)?;
+
+        handle.await
+    }
+
+    fn script_manifest(&self) -> Result<ScriptManifest> {
+        self.script_versions.manifest()
+    }
+
     async fn should_abort_sandbox(&self, cmd_msg_id: MessageId) -> bool {
         self.lua_sandbox_replies
             .lock()
@@ -310,42 +1090,81 @@ impl LuaModule {
     async fn add_to_sandbox_replies(
         &self,
         cmd_msg_id: MessageId,
-        reply: &Arc<dyn Message<impl Service>>,
-    ) -> Result<()> {
+        channel_id: ChannelId,
+        reply_id: MessageId,
+    ) {
         let mut replies = self.lua_sandbox_replies.lock().await;
 
         if let Some(replies) = replies.get_mut(&cmd_msg_id) {
-            replies.1.push((reply.channel().await?.id(), reply.id()));
+            replies.1.push((channel_id, reply_id));
         } else {
-            replies.put(
-                cmd_msg_id,
-                (false, vec![(reply.channel().await?.id(), reply.id())]),
-            );
+            replies.put(cmd_msg_id, (false, vec![(channel_id, reply_id)]));
         }
-
-        Ok(())
     }
 
     async fn eval_sandbox(
         &self,
         msg: Arc<dyn Message<impl Service>>,
+        owner: Uid,
         errors: bool,
         code: String,
     ) -> Result<()> {
         let code = trim_codeblocks(msg.service().kind(), code);
 
-        let lua_state = self.get_sandbox_state().await?;
+        let channel = msg.channel().await?;
+        let server_id = channel.server().await?.id();
+        let sanitize = self.sandbox_sanitize(server_id, channel.id()).await?;
+
+        let author_id = msg.author().id();
+        let filter = ContentFilter::load(&self.settings, server_id).await?;
+
+        if let Some(filter) = &filter {
+            if let Err(err) = filter
+                .check_code(&self.bot, channel.id(), author_id, &code)
+                .await
+            {
+                if errors {
+                    let reply = channel
+                        .send(
+                            format!("error: {}", err.to_string()),
+                            MessageSettings {
+                                priority: MessagePriority::High,
+                                ..Default::default()
+                            },
+                        )
+                        .await?;
+
+                    self.add_to_sandbox_replies(msg.id(), channel.id(), reply.id())
+                        .await;
+                }
+
+                return Ok(());
+            }
+        }
+
+        let lua_state = self.sandbox_pool.take_state().await?;
 
         let sender = lua_state.async_sender();
         let bot_msg = BotMessage::from_msg(self.bot.clone(), sender, &msg).await?;
-        let (sandbox_state, recv) = match lua_state.run_sandboxed(&code, bot_msg, None) {
+        let trace = TraceId::new();
+        let uid = owner;
+        let owner = SandboxOwner::User(owner);
+        let (sandbox_state, recv) = match self
+            .sandbox_pool
+            .run(lua_state, &code, bot_msg, None, owner, None, trace)
+            .await
+        {
             Ok(recv) => recv,
             Err(_err) => {
                 return Ok(());
             }
         };
 
-        drop(lua_state);
+        {
+            let mut sandbox_runs = self.sandbox_runs.lock().unwrap();
+            sandbox_runs.retain(|_, run| run.strong_count() > 0);
+            sandbox_runs.insert(uid, Arc::downgrade(&sandbox_state));
+        }
 
         let mut buffer: Vec<String> = Vec::new();
         let mut last_msg = Instant::now();
@@ -388,48 +1207,86 @@ impl LuaModule {
                                 .send(
                                     escape_untrusted_text(
                                         msg.service().kind(),
-                                        format!("error: {}", err),
+                                        format!("error: {}\ntrace: {}", err, trace),
                                     ),
-                                    MessageSettings::default(),
+                                    MessageSettings {
+                                        priority: MessagePriority::High,
+                                        sanitize,
+                                        ..Default::default()
+                                    },
                                 )
                                 .await?;
 
-                            self.add_to_sandbox_replies(msg.id(), &(reply as Arc<_>))
-                                .await?;
+                            self.add_to_sandbox_replies(msg.id(), channel.id(), reply.id())
+                                .await;
                         }
                     }
-                    SandboxMsg::Terminated(reason) => match reason {
-                        SandboxTerminationReason::Done => {}
-                        SandboxTerminationReason::ExecutionQuota => {
-                            let reply = msg
-                                .channel()
-                                .await?
-                                .send(
-                                    "Execution quota exceeded, terminated execution",
-                                    MessageSettings::default(),
-                                )
-                                .await?;
-                            self.add_to_sandbox_replies(msg.id(), &(reply as Arc<_>))
-                                .await?;
-
-                            break;
-                        }
-                        SandboxTerminationReason::TimeLimit => {
-                            let reply = msg
-                                .channel()
-                                .await?
-                                .send(
-                                    "Execution time limit reached, terminated execution",
-                                    MessageSettings::default(),
-                                )
-                                .await?;
-
-                            self.add_to_sandbox_replies(msg.id(), &(reply as Arc<_>))
-                                .await?;
-
-                            break;
+                    SandboxMsg::Terminated(reason) => {
+                        self.bot.metrics().count_sandbox_termination(reason.name());
+                        self.bot
+                            .get_ctx()
+                            .modules()
+                            .telemetry
+                            .module()
+                            .count_sandbox_termination(reason.name());
+
+                        match reason {
+                            SandboxTerminationReason::Done => {}
+                            SandboxTerminationReason::ExecutionQuota => {
+                                let reply = msg
+                                    .channel()
+                                    .await?
+                                    .send(
+                                        "Execution quota exceeded, terminated execution",
+                                        MessageSettings {
+                                            priority: MessagePriority::High,
+                                            ..Default::default()
+                                        },
+                                    )
+                                    .await?;
+                                self.add_to_sandbox_replies(msg.id(), channel.id(), reply.id())
+                                    .await;
+
+                                break;
+                            }
+                            SandboxTerminationReason::TimeLimit => {
+                                let reply = msg
+                                    .channel()
+                                    .await?
+                                    .send(
+                                        "Execution time limit reached, terminated execution",
+                                        MessageSettings {
+                                            priority: MessagePriority::High,
+                                            ..Default::default()
+                                        },
+                                    )
+                                    .await?;
+
+                                self.add_to_sandbox_replies(msg.id(), channel.id(), reply.id())
+                                    .await;
+
+                                break;
+                            }
+                            SandboxTerminationReason::MemoryLimit(peak) => {
+                                let reply = msg
+                                    .channel()
+                                    .await?
+                                    .send(
+                                        SandboxError::MemoryLimit(peak).to_string(),
+                                        MessageSettings {
+                                            priority: MessagePriority::High,
+                                            ..Default::default()
+                                        },
+                                    )
+                                    .await?;
+
+                                self.add_to_sandbox_replies(msg.id(), channel.id(), reply.id())
+                                    .await;
+
+                                break;
+                            }
                         }
-                    },
+                    }
                 },
                 Err(TryRecvError::Empty) => {
                     tokio::time::sleep(Duration::from_millis(50)).await;
@@ -480,14 +1337,54 @@ impl LuaModule {
 
                     sandbox_state.limits.set_characters_left(characters_left);
 
-                    let reply = msg
-                        .channel()
-                        .await?
-                        .send(out, MessageSettings::default())
-                        .await?;
-
-                    self.add_to_sandbox_replies(msg.id(), &(reply as Arc<_>))
-                        .await?;
+                    let out = match &filter {
+                        Some(filter) => match filter
+                            .check_output(&self.bot, channel.id(), author_id, out)
+                            .await
+                        {
+                            Ok(out) => out,
+                            Err(err) if err.downcast_ref::<FilterError>().is_some() => {
+                                aborting = Some(
+                                    "error: the output was blocked by the content filter, aborting",
+                                );
+                                String::new()
+                            }
+                            Err(err) => return Err(err),
+                        },
+                        None => out,
+                    };
+
+                    let out = match self.scan_sandbox_output(server_id, out).await {
+                        Ok(out) => out,
+                        Err(err) if err.downcast_ref::<UrlScanError>().is_some() => {
+                            aborting =
+                                Some("error: a link in the output was flagged as unsafe, aborting");
+                            String::new()
+                        }
+                        Err(err) => return Err(err),
+                    };
+
+                    if !out.is_empty() {
+                        let replies = self
+                            .bot
+                            .get_ctx()
+                            .services()
+                            .send_split(
+                                channel.id(),
+                                out,
+                                MessageSettings {
+                                    sanitize,
+                                    ..Default::default()
+                                },
+                                MAX_SANDBOX_OUTPUT_PARTS,
+                            )
+                            .await?;
+
+                        for reply in replies {
+                            self.add_to_sandbox_replies(msg.id(), channel.id(), reply.id())
+                                .await;
+                        }
+                    }
 
                     last_msg = Instant::now();
                     has_messaged = true;
@@ -499,25 +1396,345 @@ impl LuaModule {
             let reply = msg
                 .channel()
                 .await?
-                .send(aborting, MessageSettings::default())
+                .send(
+                    aborting,
+                    MessageSettings {
+                        priority: MessagePriority::High,
+                        ..Default::default()
+                    },
+                )
                 .await?;
 
-            self.add_to_sandbox_replies(msg.id(), &(reply as Arc<_>))
-                .await?;
+            self.add_to_sandbox_replies(msg.id(), channel.id(), reply.id())
+                .await;
         }
 
         Ok(())
     }
 
+    /// Parts of sandbox output that are neutralized in a channel
+    pub async fn sandbox_sanitize(
+        &self,
+        server_id: ServerId,
+        channel_id: ChannelId,
+    ) -> Result<Sanitize> {
+        let mut sanitize = Sanitize::EVERYONE;
+
+        if self
+            .settings
+            .sanitize_mentions
+            .value(server_id, channel_id)
+            .await?
+        {
+            sanitize |= Sanitize::MENTIONS;
+        }
+
+        if self
+            .settings
+            .sanitize_invites
+            .value(server_id, channel_id)
+            .await?
+        {
+            sanitize |= Sanitize::INVITES;
+        }
+
+        Ok(sanitize)
+    }
+
+    pub fn ipc(&self) -> &Arc<IpcBroker> {
+        &self.ipc
+    }
+
+    pub fn throttle(&self) -> &Arc<Throttle> {
+        &self.throttle
+    }
+
+    pub fn http_rate_limiter(&self) -> &Arc<HttpRateLimiter> {
+        &self.http_rate_limiter
+    }
+
+    pub fn url_scanner(&self) -> &Arc<UrlScanner> {
+        &self.url_scanner
+    }
+
+    /// What happens to unsafe links in sandbox runs of a server, ignored without providers
+    pub async fn url_scan_action(&self, server_id: ServerId) -> Result<UrlScanAction> {
+        if !self.url_scanner.is_enabled() {
+            return Ok(UrlScanAction::Ignore);
+        }
+
+        let action = self.settings.url_scan.server_value(server_id).await?;
+
+        UrlScanAction::from_str(&action).ok_or_else(|| UrlScanError::InvalidAction(action).into())
+    }
+
+    /// Flagged links of the output, scanning fails open so a provider outage can't stop the sandbox
+    async fn flagged_urls(&self, urls: &[String]) -> Vec<String> {
+        match self.url_scanner.scan(urls).await {
+            Ok(flagged) => flagged,
+            Err(err) => {
+                println!("error scanning urls: {}", err.to_string());
+                Vec::new()
+            }
+        }
+    }
+
+    /// Apply the url scan action of the server to sandbox output, blocked links fail the run
+    pub async fn scan_sandbox_output(&self, server_id: ServerId, output: String) -> Result<String> {
+        let action = self.url_scan_action(server_id).await?;
+
+        if action == UrlScanAction::Ignore {
+            return Ok(output);
+        }
+
+        let urls = url_scan::content_urls(server_id.service_kind(), &output);
+        let flagged = self.flagged_urls(&urls).await;
+
+        match (action, flagged.first()) {
+            (_, None) => Ok(output),
+            (UrlScanAction::Block, Some(url)) => {
+                Err(UrlScanError::UnsafeLink(url_scan::url_host(url)).into())
+            }
+            (_, Some(_)) => Ok(format!(
+                "{}\n⚠️ {} link(s) in this output were flagged as unsafe",
+                output,
+                flagged.len()
+            )),
+        }
+    }
+
+    /// Whether a url fetched by the sandbox was flagged, blocked urls are an error
+    pub async fn scan_sandbox_url(&self, server_id: ServerId, url: &str) -> Result<bool> {
+        let action = self.url_scan_action(server_id).await?;
+
+        if action == UrlScanAction::Ignore {
+            return Ok(false);
+        }
+
+        let flagged = !self.flagged_urls(&[url.to_string()]).await.is_empty();
+
+        match action {
+            UrlScanAction::Block if flagged => {
+                Err(UrlScanError::UnsafeLink(url_scan::url_host(url)).into())
+            }
+            _ => Ok(flagged),
+        }
+    }
+
+    pub async fn vm_stats(&self) -> Result<Vec<(&'static str, LuaVmStats)>> {
+        let bot_stats = self.get_bot_state().await?.vm_stats()?;
+        let sandbox_stats = self.sandbox_pool.vm_stats();
+
+        Ok(vec![("bot", bot_stats), ("sandbox", sandbox_stats)])
+    }
+
     pub async fn get_bot_state(&self) -> Result<MutexGuardArc<LuaState>> {
         Ok(self.bot_state.lock_arc().await)
     }
+}
 
-    pub async fn get_sandbox_state(&self) -> Result<MutexGuardArc<LuaState>> {
-        Ok(self.sandbox_state.lock_arc().await)
+fn metric_header(body: &mut String, name: &str, help: &str, kind: &str) {
+    body.push_str(&format!(
+        "# HELP {} {}\n# TYPE {} {}\n",
+        name, help, name, kind
+    ));
+}
+
+struct LuaMetricsHandler {
+    module: Arc<LuaModule>,
+    token: String,
+}
+
+#[async_trait]
+impl HttpHandler for LuaMetricsHandler {
+    async fn handle(&self, req: Request<Body>) -> Result<Response<Body>> {
+        if req.method() != hyper::Method::GET {
+            return Ok(status_response(StatusCode::METHOD_NOT_ALLOWED));
+        }
+
+        if !admin::authorized(&req, &self.token) {
+            return Ok(status_response(StatusCode::UNAUTHORIZED));
+        }
+
+        let stats = self.module.vm_stats().await?;
+        let gauges: [(&str, &str, fn(&LuaVmStats) -> usize); 4] = [
+            (
+                "kaito_lua_used_memory_bytes",
+                "Memory used by the lua state",
+                |stats| stats.used_memory,
+            ),
+            (
+                "kaito_lua_registry_size",
+                "Values in the lua registry",
+                |stats| stats.registry_size,
+            ),
+            (
+                "kaito_lua_pending_futures",
+                "Futures whose task hasn't been awaited by their thread yet",
+                |stats| stats.pending_futures,
+            ),
+            (
+                "kaito_lua_pending_callbacks",
+                "Threads woken by their futures waiting for the next think",
+                |stats| stats.pending_callbacks,
+            ),
+        ];
+
+        let mut body = String::new();
+
+        for (name, help, value) in gauges.iter() {
+            metric_header(&mut body, name, help, "gauge");
+
+            for (state, stats) in &stats {
+                body.push_str(&format!(
+                    "{}{{state=\"{}\"}} {}\n",
+                    name,
+                    state,
+                    value(stats)
+                ));
+            }
+        }
+
+        let workers = [
+            (
+                "kaito_sandbox_active_workers",
+                "Worker threads running sandboxed code",
+                self.module.sandbox_pool.active_workers(),
+            ),
+            (
+                "kaito_sandbox_abandoned_workers",
+                "Worker threads abandoned while stuck in a call that hasn't returned yet",
+                self.module.sandbox_pool.abandoned_workers(),
+            ),
+        ];
+
+        for (name, help, value) in workers.iter() {
+            metric_header(&mut body, name, help, "gauge");
+            body.push_str(&format!("{} {}\n", name, value));
+        }
+
+        let metrics = self.module.bot.metrics().snapshot();
+        let totals = [
+            (
+                "kaito_sandbox_runs_total",
+                "Sandbox runs that finished",
+                metrics.sandbox_runs,
+            ),
+            (
+                "kaito_sandbox_instructions_total",
+                "Instructions run by finished sandbox runs",
+                metrics.sandbox_instructions,
+            ),
+        ];
+
+        for (name, help, value) in totals.iter() {
+            metric_header(&mut body, name, help, "counter");
+            body.push_str(&format!("{} {}\n", name, value));
+        }
+
+        let messages_sent: BTreeMap<&str, u64> = self
+            .module
+            .bot
+            .get_ctx()
+            .services()
+            .status()
+            .await?
+            .into_iter()
+            .map(|(name, status)| (name, status.sent))
+            .collect();
+        let labelled = [
+            (
+                "kaito_sandbox_terminations_total",
+                "Sandbox runs terminated, by reason",
+                "reason",
+                &metrics.sandbox_terminations,
+            ),
+            (
+                "kaito_lua_async_callbacks_total",
+                "Future callbacks handled by think",
+                "state",
+                &metrics.async_callbacks,
+            ),
+            (
+                "kaito_sandbox_http_rejections_total",
+                "Sandbox http requests refused or cut off by a limit",
+                "reason",
+                &metrics.http_rejections,
+            ),
+            (
+                "kaito_messages_received_total",
+                "Messages received from a service",
+                "service",
+                &metrics.messages_received,
+            ),
+            (
+                "kaito_messages_sent_total",
+                "Messages sent to a service",
+                "service",
+                &messages_sent,
+            ),
+        ];
+
+        for (name, help, label, values) in labelled.iter() {
+            metric_header(&mut body, name, help, "counter");
+
+            for (key, value) in values.iter() {
+                body.push_str(&format!("{}{{{}=\"{}\"}} {}\n", name, label, key, value));
+            }
+        }
+
+        Ok(Response::builder()
+            .header("Content-Type", "text/plain; version=0.0.4")
+            .body(Body::from(body))?)
     }
 }
 
+// Pick the scripts to start with: a fresh sync if configured, then the active version, then the bundled scripts
+async fn initial_lua_root_path(bot: &Arc<Bot>, script_versions: &ScriptVersions) -> PathBuf {
+    if let Some(config) = bot.config().scripts.as_ref().filter(|c| c.sync_on_startup) {
+        let res = match scripts::sync_git(bot, config).await {
+            Ok((path, commit)) => script_versions.store(&path, Some(commit)),
+            Err(err) => Err(err),
+        };
+
+        match res {
+            Ok(version) => {
+                let path = script_versions.version_path(&version.hash);
+
+                match scripts::validate_scripts(bot, &path) {
+                    Ok(()) => match script_versions.activate(&version.hash) {
+                        Ok(()) => {
+                            println!("Loaded synced scripts version {}", version.hash);
+                            return path;
+                        }
+                        Err(err) => println!("error activating scripts: {}", err.to_string()),
+                    },
+                    Err(err) => println!("error validating synced scripts: {}", err.to_string()),
+                }
+            }
+            Err(err) => println!("error syncing scripts: {}", err.to_string()),
+        }
+    }
+
+    if let Ok(Some(active)) = script_versions.manifest().map(|m| m.active) {
+        let path = script_versions.version_path(&active);
+
+        match scripts::validate_scripts(bot, &path) {
+            Ok(()) => {
+                println!("Loaded scripts version {}", active);
+                return path;
+            }
+            Err(err) => println!(
+                "error loading scripts version {}: {}",
+                active,
+                err.to_string()
+            ),
+        }
+    }
+
+    bot.share_path().join("lua")
+}
+
 fn trim_codeblocks(service: ServiceKind, text: String) -> String {
     let trimmed = text.trim();
 
@@ -0,0 +1,219 @@
+use anyhow::{anyhow, Result};
+use async_mutex::Mutex;
+use criterion::{black_box, BatchSize, Criterion};
+use futures::FutureExt;
+use lru::LruCache;
+use std::{path::Path, sync::Arc, time::Duration};
+
+use super::{
+    clock::LuaClock,
+    lib::bot::BotMessage,
+    pool::SandboxPool,
+    state::{LuaFutureHandle, LuaState},
+    utils::TraceId,
+};
+use crate::{
+    bot::{permissions::Caller, Bot},
+    config::Config,
+};
+
+// Futures queued up before each think in the callback throughput benchmark
+const THINK_CALLBACKS: usize = 100;
+
+struct BenchOptions {
+    filter: Option<String>,
+    quick: bool,
+    save_baseline: Option<String>,
+    baseline: Option<String>,
+}
+
+impl BenchOptions {
+    fn parse(args: &[String]) -> Result<BenchOptions> {
+        let mut options = BenchOptions {
+            filter: None,
+            quick: false,
+            save_baseline: None,
+            baseline: None,
+        };
+
+        let mut args = args.iter();
+
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--quick" => options.quick = true,
+                "--save-baseline" => options.save_baseline = args.next().cloned(),
+                "--baseline" => options.baseline = args.next().cloned(),
+                _ if arg.starts_with("--") => {
+                    return Err(anyhow!("unknown bench option \"{}\"", arg));
+                }
+                _ => options.filter = Some(arg.clone()),
+            }
+        }
+
+        Ok(options)
+    }
+
+    fn criterion(self) -> Criterion {
+        let mut criterion = Criterion::default().without_plots();
+
+        // Fewer and shorter samples, enough to catch large regressions in CI
+        if self.quick {
+            criterion = criterion
+                .sample_size(10)
+                .warm_up_time(Duration::from_millis(500))
+                .measurement_time(Duration::from_secs(2));
+        }
+
+        if let Some(filter) = self.filter {
+            criterion = criterion.with_filter(filter);
+        }
+
+        if let Some(name) = self.save_baseline {
+            criterion = criterion.save_baseline(name);
+        }
+
+        if let Some(name) = self.baseline {
+            criterion = criterion.retain_baseline(name);
+        }
+
+        criterion
+    }
+}
+
+/// Wait for the future behind the handle, thinking until the thread awaiting it has settled it
+fn resolve(state: &LuaState, mut handle: LuaFutureHandle) -> Result<()> {
+    loop {
+        state.think()?;
+
+        if let Some(res) = (&mut handle).now_or_never() {
+            return res.map(|_| ());
+        }
+
+        std::thread::yield_now();
+    }
+}
+
+fn delay_futures(state: &LuaState, count: usize) -> Result<Vec<LuaFutureHandle>> {
+    let handles = (0..count)
+        .map(|_| state.call_async("async.delay", &[0.into()]))
+        .collect::<Result<Vec<_>>>()?;
+
+    // The threads awaiting the futures are woken to be resumed by the next think
+    while state.woken_threads() < count {
+        std::thread::yield_now();
+    }
+
+    Ok(handles)
+}
+
+fn run_benchmarks(
+    criterion: &mut Criterion,
+    bot_state: &LuaState,
+    sandbox_state: &LuaState,
+    msg: BotMessage,
+) -> Result<()> {
+    // Unknown commands that can't be tags return right after the lookup, leaving only the bridge
+    let args = vec!["bench-unknown".to_string()];
+    let caller = Caller::new(
+        msg.author().db_user().clone(),
+        msg.channel().server().id(),
+        msg.channel().id(),
+    );
+    bot_state.run_bot_command(
+        msg.clone(),
+        args.clone(),
+        false,
+        caller.clone(),
+        TraceId::new(),
+    )?;
+    resolve(bot_state, bot_state.call_async("async.delay", &[0.into()])?)?;
+
+    criterion.bench_function("lua/run_bot_command", |b| {
+        b.iter(|| {
+            black_box(bot_state.run_bot_command(
+                msg.clone(),
+                args.clone(),
+                false,
+                caller.clone(),
+                TraceId::new(),
+            ))
+        })
+    });
+
+    criterion.bench_function("lua/future_round_trip", |b| {
+        b.iter(|| {
+            let res = bot_state
+                .call_async("async.delay", &[0.into()])
+                .and_then(|handle| resolve(bot_state, handle));
+
+            black_box(res)
+        })
+    });
+
+    criterion.bench_function("lua/think_idle", |b| {
+        b.iter(|| black_box(bot_state.think()))
+    });
+
+    criterion.bench_function("lua/think_callbacks", |b| {
+        b.iter_batched(
+            || delay_futures(bot_state, THINK_CALLBACKS),
+            |handles| (black_box(bot_state.think()), handles),
+            BatchSize::PerIteration,
+        )
+    });
+
+    criterion.bench_function("lua/sandbox_think_idle", |b| {
+        b.iter(|| black_box(sandbox_state.think()))
+    });
+
+    Ok(())
+}
+
+/// `kaito bench [FILTER] [--quick] [--save-baseline NAME] [--baseline NAME]`
+pub async fn run_cli(
+    data_path: &Path,
+    share_path: &Path,
+    config: &Config,
+    args: &[String],
+) -> Result<()> {
+    let mut criterion = BenchOptions::parse(args)?.criterion();
+
+    // The benchmarks get a scratch database, so the bot data is never touched
+    let bench_path = data_path.join("bench");
+
+    if bench_path.is_dir() {
+        tokio::fs::remove_dir_all(&bench_path).await?;
+    }
+
+    tokio::fs::create_dir_all(&bench_path).await?;
+
+    let bot = Bot::init(bench_path.clone(), share_path.to_path_buf(), config).await?;
+    let lua_root_path = bot.share_path().join("lua");
+    let clock = LuaClock::from_config(&bot);
+
+    // Sandboxed runs of the bot state go through the pool, the benchmarks use a state of their own
+    let sandbox_state = LuaState::create_state(&bot, true, None, &lua_root_path, &clock)?;
+    let bot_state = LuaState::create_state(
+        &bot,
+        false,
+        Some((
+            SandboxPool::new(&bot, lua_root_path.clone(), &clock),
+            Arc::new(Mutex::new(LruCache::new(1))),
+        )),
+        &lua_root_path,
+        &clock,
+    )?;
+
+    let msg = BotMessage::synthetic(bot.clone(), bot_state.async_sender(), "!bench".into()).await?;
+
+    // Futures spawn onto the runtime while the benchmarks block this thread
+    tokio::task::block_in_place(|| {
+        run_benchmarks(&mut criterion, &bot_state, &sandbox_state, msg)
+    })?;
+
+    criterion.final_summary();
+
+    tokio::fs::remove_dir_all(&bench_path).await?;
+
+    Ok(())
+}
@@ -0,0 +1,105 @@
+use chrono::{DateTime, TimeZone, Utc};
+use governor::{
+    clock::{Clock, ReasonablyRealtime},
+    nanos::Nanos,
+};
+use std::{
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+use thiserror::Error;
+
+use crate::bot::Bot;
+
+struct FakeTime {
+    unix_start: i64,
+    random_seed: i64,
+    elapsed: Mutex<Duration>,
+}
+
+struct LuaClockInner {
+    start: Instant,
+    /// Set in the deterministic mode, time then only moves when the clock is advanced
+    fake: Option<FakeTime>,
+}
+
+/// Time and random seeds as seen by the lua states, also drives their rate limiters
+#[derive(Clone)]
+pub struct LuaClock(Arc<LuaClockInner>);
+
+impl LuaClock {
+    pub fn from_config(bot: &Bot) -> LuaClock {
+        let deterministic = bot
+            .config()
+            .lua
+            .as_ref()
+            .and_then(|lua| lua.deterministic.as_ref());
+
+        LuaClock(Arc::new(LuaClockInner {
+            start: Instant::now(),
+            fake: deterministic.map(|config| FakeTime {
+                unix_start: config.start_time.unwrap_or(0),
+                random_seed: config.seed.unwrap_or(0),
+                elapsed: Mutex::new(Duration::from_secs(0)),
+            }),
+        }))
+    }
+
+    pub fn is_fake(&self) -> bool {
+        self.0.fake.is_some()
+    }
+
+    /// Time since the clock was created
+    pub fn elapsed(&self) -> Duration {
+        match &self.0.fake {
+            Some(fake) => *fake.elapsed.lock().unwrap(),
+            None => self.0.start.elapsed(),
+        }
+    }
+
+    pub fn instant(&self) -> Instant {
+        self.0.start + self.elapsed()
+    }
+
+    pub fn utc_now(&self) -> DateTime<Utc> {
+        match &self.0.fake {
+            Some(fake) => {
+                Utc.timestamp(fake.unix_start, 0)
+                    + chrono::Duration::from_std(self.elapsed())
+                        .unwrap_or_else(|_| chrono::Duration::zero())
+            }
+            None => Utc::now(),
+        }
+    }
+
+    /// The fixed seed of the deterministic mode, states otherwise keep their random seeds
+    pub fn random_seed(&self) -> Option<i64> {
+        self.0.fake.as_ref().map(|fake| fake.random_seed)
+    }
+
+    pub fn advance(&self, duration: Duration) -> Result<(), ClockError> {
+        match &self.0.fake {
+            Some(fake) => {
+                *fake.elapsed.lock().unwrap() += duration;
+                Ok(())
+            }
+            None => Err(ClockError::NotFake),
+        }
+    }
+}
+
+impl Clock for LuaClock {
+    type Instant = Nanos;
+
+    fn now(&self) -> Nanos {
+        Nanos::from(self.elapsed())
+    }
+}
+
+impl ReasonablyRealtime for LuaClock {}
+
+#[derive(Debug, Error)]
+pub enum ClockError {
+    #[error("the clock can only be advanced in the deterministic mode")]
+    NotFake,
+}
@@ -0,0 +1,411 @@
+use anyhow::Error as AnyError;
+use mlua::{
+    prelude::{LuaError, LuaResult, LuaValue},
+    Function, Lua, Table,
+};
+use std::error::Error as StdError;
+
+use super::{
+    clock::ClockError,
+    filter::FilterError,
+    http::HttpError,
+    ipc::IpcError,
+    lib::{
+        blob::BlobError,
+        bot::{AttachmentError, FormatError},
+        chart::ChartError,
+        gif::GifError,
+        image::ImageError,
+        latex::LatexError,
+        proc::ProcError,
+        qr::QrError,
+        r#async::AsyncError,
+        schedule::ScheduleError,
+        server_scripts::ServerScriptError,
+        storage::StorageError,
+        translate::TranslateError,
+    },
+    replay::ReplayError,
+    scheduler::SchedulerError,
+    scripts::ScriptsError,
+    state::{current_trace, LuaStateError, SandboxError},
+    throttle::ThrottleError,
+    url_scan::UrlScanError,
+};
+use crate::{
+    bot::permissions::PermissionError,
+    modules::{
+        github::GithubError, moderation::ModerationError, polls::PollError,
+        reaction_roles::ReactionRoleError,
+    },
+    services::{discord::DiscordError, ServiceError},
+    vault::VaultError,
+};
+
+fn status_kind(status: u16) -> &'static str {
+    match status {
+        403 => "forbidden",
+        404 => "not_found",
+        429 => "rate_limited",
+        _ => "service",
+    }
+}
+
+fn classify(err: &(dyn StdError + 'static)) -> Option<&'static str> {
+    if let Some(err) = err.downcast_ref::<ServiceError>() {
+        return Some(match err {
+            ServiceError::NotFound
+            | ServiceError::UnknownUser(_)
+            | ServiceError::UnknownChannel(_)
+            | ServiceError::UnknownService(_) => "not_found",
+            ServiceError::Forbidden | ServiceError::MissingPermission(_) => "forbidden",
+            ServiceError::RateLimited | ServiceError::Duplicate => "rate_limited",
+            ServiceError::Other(_) | ServiceError::Restarting => "service",
+        });
+    }
+
+    if let Some(err) = err.downcast_ref::<SandboxError>() {
+        return Some(match err {
+            SandboxError::LimitReached(_)
+            | SandboxError::ExecutionQuota
+            | SandboxError::EnvTooLarge(_)
+            | SandboxError::DelayTooLong(_)
+            | SandboxError::MemoryLimit(_) => "limit",
+            SandboxError::OwnerQuotaExceeded => "rate_limited",
+            SandboxError::TimeLimit => "timeout",
+            SandboxError::Disabled => "forbidden",
+            SandboxError::Runtime(_) => "runtime",
+        });
+    }
+
+    if let Some(LuaStateError::Panicked(_)) = err.downcast_ref::<LuaStateError>() {
+        return Some("internal");
+    }
+
+    if let Some(err) = err.downcast_ref::<HttpError>() {
+        return Some(match err {
+            HttpError::HttpCallLimitReached => "limit",
+            HttpError::RateLimited(_) => "rate_limited",
+            HttpError::DisallowedAddress(_) => "forbidden",
+            HttpError::ErrorResolvingHosts(_) => "not_found",
+            HttpError::ErrorParsingUrl(_)
+            | HttpError::UnknownScheme(_)
+            | HttpError::ErrorBuildingRequest(_) => "invalid_argument",
+        });
+    }
+
+    if err.downcast_ref::<hyper::Error>().is_some() {
+        return Some("http");
+    }
+
+    if let Some(err) = err.downcast_ref::<TranslateError>() {
+        return Some(match err {
+            TranslateError::NoProviders => "unavailable",
+            TranslateError::TextTooLong(_) | TranslateError::UnknownLanguage => {
+                "invalid_argument"
+            }
+            TranslateError::EmptyResponse => "service",
+            TranslateError::ProviderError(status, _) => status_kind(*status),
+        });
+    }
+
+    if let Some(err) = err.downcast_ref::<GithubError>() {
+        return Some(match err {
+            GithubError::InvalidRepo(_) => "invalid_argument",
+            GithubError::NotFound => "not_found",
+            GithubError::ApiError(status) => status_kind(*status),
+        });
+    }
+
+    if let Some(err) = err.downcast_ref::<ScriptsError>() {
+        return Some(match err {
+            ScriptsError::NotConfigured => "unavailable",
+            ScriptsError::UnknownVersion(_) | ScriptsError::NoPreviousVersion => "not_found",
+            _ => "internal",
+        });
+    }
+
+    if let Some(err) = err.downcast_ref::<DiscordError>() {
+        return Some(match err {
+            DiscordError::CacheMiss => "not_found",
+            DiscordError::NoChannelGuild => "invalid_argument",
+            DiscordError::InteractionExpired
+            | DiscordError::InvalidTimestamp
+            | DiscordError::ForumChannel
+            | DiscordError::NotForumChannel
+            | DiscordError::UnknownForumTag(_) => "invalid_argument",
+            DiscordError::ModalTimeout => "timeout",
+        });
+    }
+
+    if let Some(err) = err.downcast_ref::<GifError>() {
+        return Some(match err {
+            GifError::Disabled => "forbidden",
+            GifError::NoProvider => "unavailable",
+            GifError::UnknownProvider(_) => "invalid_argument",
+            GifError::ProviderError(status, _) => status_kind(*status),
+        });
+    }
+
+    if let Some(err) = err.downcast_ref::<AttachmentError>() {
+        return Some(match err {
+            AttachmentError::TooLarge(_) => "limit",
+            AttachmentError::DisallowedType(_) => "invalid_argument",
+        });
+    }
+
+    if let Some(err) = err.downcast_ref::<ScheduleError>() {
+        return Some(match err {
+            ScheduleError::InPast | ScheduleError::EmptyContent => "invalid_argument",
+            ScheduleError::TooFarAhead(_)
+            | ScheduleError::TooLong(_)
+            | ScheduleError::TooManyPending(_) => "limit",
+        });
+    }
+
+    if let Some(err) = err.downcast_ref::<UrlScanError>() {
+        return Some(match err {
+            UrlScanError::UnsafeLink(_) => "forbidden",
+            UrlScanError::ProviderError(status) => status_kind(*status),
+            UrlScanError::InvalidAction(_) => "invalid_argument",
+        });
+    }
+
+    if let Some(err) = err.downcast_ref::<FilterError>() {
+        return Some(match err {
+            FilterError::Blocked(_) => "forbidden",
+            FilterError::InvalidPattern(_) | FilterError::InvalidAction(_) => "invalid_argument",
+        });
+    }
+
+    if let Some(err) = err.downcast_ref::<FormatError>() {
+        return Some(match err {
+            FormatError::UnknownStyle(_) | FormatError::UnknownDialect(_) => "invalid_argument",
+            FormatError::TooDeep(_) => "limit",
+        });
+    }
+
+    if err.downcast_ref::<ImageError>().is_some() || err.downcast_ref::<QrError>().is_some() {
+        return Some("invalid_argument");
+    }
+
+    if let Some(err) = err.downcast_ref::<ChartError>() {
+        return Some(match err {
+            ChartError::Draw(_) => "internal",
+            _ => "invalid_argument",
+        });
+    }
+
+    if let Some(err) = err.downcast_ref::<LatexError>() {
+        return Some(match err {
+            LatexError::NotConfigured => "unavailable",
+            LatexError::RateLimited => "rate_limited",
+            LatexError::ExpressionTooLong(_) => "invalid_argument",
+            LatexError::RenderFailed(status) => status_kind(*status),
+            LatexError::NotPng => "service",
+        });
+    }
+
+    if let Some(err) = err.downcast_ref::<ProcError>() {
+        return Some(match err {
+            ProcError::Disabled => "unavailable",
+            ProcError::Forbidden | ProcError::NotAllowed(_) => "forbidden",
+            ProcError::Timeout(_) => "timeout",
+        });
+    }
+
+    if err.downcast_ref::<ClockError>().is_some() {
+        return Some("unavailable");
+    }
+
+    if let Some(err) = err.downcast_ref::<IpcError>() {
+        return Some(match err {
+            IpcError::InvalidChannel(_) | IpcError::TooLarge(_) => "invalid_argument",
+            IpcError::RateLimited => "rate_limited",
+            IpcError::TooManySubscriptions(_) => "limit",
+        });
+    }
+
+    if let Some(err) = err.downcast_ref::<ThrottleError>() {
+        return Some(match err {
+            ThrottleError::InvalidQuota => "invalid_argument",
+            ThrottleError::TooManyQuotas(_) => "limit",
+        });
+    }
+
+    if let Some(err) = err.downcast_ref::<SchedulerError>() {
+        return Some(match err {
+            SchedulerError::InvalidCron(_)
+            | SchedulerError::NeverRuns
+            | SchedulerError::IntervalTooShort(_) => "invalid_argument",
+            SchedulerError::NamedInSandbox => "forbidden",
+        });
+    }
+
+    if let Some(err) = err.downcast_ref::<ReplayError>() {
+        return Some(match err {
+            ReplayError::UnknownTrace(_) => "not_found",
+            ReplayError::Diverged(_) => "internal",
+        });
+    }
+
+    if let Some(err) = err.downcast_ref::<BlobError>() {
+        return Some(match err {
+            BlobError::InvalidName(_) | BlobError::TooLarge(_) => "invalid_argument",
+            BlobError::QuotaExceeded(_) => "limit",
+        });
+    }
+
+    if let Some(err) = err.downcast_ref::<StorageError>() {
+        return Some(match err {
+            StorageError::InvalidKey(_)
+            | StorageError::TooLarge(_)
+            | StorageError::InvalidNamespace
+            | StorageError::NoUserNamespace => "invalid_argument",
+            StorageError::QuotaExceeded(_) => "limit",
+        });
+    }
+
+    if let Some(err) = err.downcast_ref::<ServerScriptError>() {
+        return Some(match err {
+            ServerScriptError::InvalidName(_) | ServerScriptError::TooLarge(_) => {
+                "invalid_argument"
+            }
+            ServerScriptError::TooMany(_) => "limit",
+            ServerScriptError::QuotaExceeded => "rate_limited",
+        });
+    }
+
+    if err.downcast_ref::<PermissionError>().is_some() {
+        return Some("forbidden");
+    }
+
+    if let Some(err) = err.downcast_ref::<ReactionRoleError>() {
+        return Some(match err {
+            ReactionRoleError::UnknownRole(_) => "not_found",
+            ReactionRoleError::EmojiTaken(_) | ReactionRoleError::RoleTaken(_) => {
+                "invalid_argument"
+            }
+        });
+    }
+
+    if let Some(err) = err.downcast_ref::<ModerationError>() {
+        return Some(match err {
+            ModerationError::Disabled => "unavailable",
+            ModerationError::TimeoutTooLong(_) | ModerationError::TooManyMessages(_) => {
+                "invalid_argument"
+            }
+            ModerationError::ProtectedUser => "forbidden",
+        });
+    }
+
+    if let Some(err) = err.downcast_ref::<PollError>() {
+        return Some(match err {
+            PollError::Disabled => "unavailable",
+            PollError::NotFound => "not_found",
+            PollError::AlreadyClosed
+            | PollError::OptionCount(_)
+            | PollError::InvalidOption(_)
+            | PollError::InvalidDuration(_) => "invalid_argument",
+        });
+    }
+
+    if let Some(err) = err.downcast_ref::<VaultError>() {
+        return Some(match err {
+            VaultError::NoKey => "unavailable",
+            VaultError::UnknownSecret(_) => "not_found",
+            _ => "internal",
+        });
+    }
+
+    if let Some(err) = err.downcast_ref::<AsyncError>() {
+        return Some(match err {
+            AsyncError::InvalidDuration => "invalid_argument",
+            AsyncError::TooManyPending(_) => "limit",
+            AsyncError::DeadlineExceeded => "timeout",
+            AsyncError::Reloaded => "unavailable",
+            AsyncError::SpawnInSandbox => "forbidden",
+            AsyncError::FutureError(_) | AsyncError::Panicked(_) => "internal",
+        });
+    }
+
+    if let Some(sqlx::Error::RowNotFound) = err.downcast_ref::<sqlx::Error>() {
+        return Some("not_found");
+    }
+
+    None
+}
+
+/// Classify an error returned from Rust into a kind scripts can branch on
+pub fn error_kind(err: &AnyError) -> &'static str {
+    err.chain().find_map(classify).unwrap_or("internal")
+}
+
+pub fn lua_error_kind(err: &LuaError) -> &'static str {
+    match err {
+        LuaError::ExternalError(err) => classify(err.as_ref()).unwrap_or("internal"),
+        LuaError::CallbackError { cause, .. } => lua_error_kind(cause),
+        LuaError::MemoryError(_) => "limit",
+        LuaError::RuntimeError(_) => "runtime",
+        _ => "internal",
+    }
+}
+
+fn error_metatable(state: &Lua) -> LuaResult<Table> {
+    if let Ok(meta) = state.named_registry_value::<_, Table>("__ERROR_META") {
+        return Ok(meta);
+    }
+
+    let meta = state.create_table()?;
+
+    meta.set(
+        "__tostring",
+        state.create_function(|_, err: Table| err.get::<_, String>("message"))?,
+    )?;
+
+    // Keep `"error: " .. err` working for scripts written against string errors
+    meta.set(
+        "__concat",
+        state.create_function(|state, (a, b): (LuaValue, LuaValue)| {
+            let tostring: Function = state.globals().get("tostring")?;
+            let a: String = tostring.call(a)?;
+            let b: String = tostring.call(b)?;
+
+            Ok(a + &b)
+        })?,
+    )?;
+
+    state.set_named_registry_value("__ERROR_META", meta.clone())?;
+
+    Ok(meta)
+}
+
+/// Convert an error into a `{ kind, message, trace, permission? }` table for rejecting futures
+pub fn create_error_value<'a>(state: &'a Lua, err: &AnyError) -> LuaResult<LuaValue<'a>> {
+    let tbl = state.create_table()?;
+
+    tbl.set("kind", error_kind(err))?;
+    tbl.set("message", err.to_string())?;
+    tbl.set("trace", current_trace(state).map(|trace| trace.to_string()))?;
+
+    if let Some(ServiceError::MissingPermission(permission)) = err
+        .chain()
+        .find_map(|err| err.downcast_ref::<ServiceError>())
+    {
+        tbl.set("permission", *permission)?;
+    }
+    tbl.set_metatable(Some(error_metatable(state)?));
+
+    Ok(LuaValue::Table(tbl))
+}
+
+/// Get the kind of any error value caught by a script
+pub fn value_error_kind(err: &LuaValue) -> LuaResult<String> {
+    Ok(match err {
+        LuaValue::Table(tbl) => tbl
+            .get::<_, Option<String>>("kind")?
+            .unwrap_or_else(|| "runtime".into()),
+        LuaValue::Error(err) => lua_error_kind(err).into(),
+        _ => "runtime".into(),
+    })
+}
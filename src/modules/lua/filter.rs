@@ -0,0 +1,282 @@
+use anyhow::Result;
+use regex::{Regex, RegexBuilder};
+use std::fmt;
+use thiserror::Error;
+
+use super::LuaModuleSettings;
+use crate::{
+    bot::Bot,
+    message::{MessageSettings, Sanitize},
+    services::{ChannelId, ContentSegment, ServerId, ServiceKind, UserId},
+};
+
+// Patterns come from server admins, keep them from compiling into something huge
+const MAX_PATTERN_SIZE: usize = 1 << 20;
+const REDACTED: &str = "***";
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum FilterAction {
+    /// Fail the run
+    Reject,
+    /// Replace matching words and patterns in the output, runs are still rejected for mentions
+    Redact,
+    /// Let the run through, only notifying the log channel
+    Log,
+}
+
+impl FilterAction {
+    fn from_str(s: &str) -> Option<FilterAction> {
+        match s {
+            "reject" => Some(FilterAction::Reject),
+            "redact" => Some(FilterAction::Redact),
+            "log" => Some(FilterAction::Log),
+            _ => None,
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            FilterAction::Reject => "rejected",
+            FilterAction::Redact => "redacted",
+            FilterAction::Log => "allowed",
+        }
+    }
+}
+
+enum Violation {
+    Word(String),
+    Pattern(String),
+    Mentions(usize),
+}
+
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Violation::Word(word) => write!(f, "contains the blocked word \"{}\"", word),
+            Violation::Pattern(text) => write!(f, "matches the blocked pattern with \"{}\"", text),
+            Violation::Mentions(count) => write!(f, "mentions {} users", count),
+        }
+    }
+}
+
+/// Rules sandbox code and output of a server are checked against
+pub struct ContentFilter {
+    words: Option<Regex>,
+    pattern: Option<Regex>,
+    max_mentions: usize,
+    action: FilterAction,
+    log_channel: Option<ChannelId>,
+    service: ServiceKind,
+}
+
+impl ContentFilter {
+    /// The filter of the server, `None` if it has no rules
+    pub async fn load(
+        settings: &LuaModuleSettings,
+        server_id: ServerId,
+    ) -> Result<Option<ContentFilter>> {
+        let words: Vec<String> = settings
+            .filter_words
+            .server_value(server_id)
+            .await?
+            .split(',')
+            .map(str::trim)
+            .filter(|word| !word.is_empty())
+            .map(regex::escape)
+            .collect();
+        let pattern = settings.filter_pattern.server_value(server_id).await?;
+        let max_mentions = settings.filter_max_mentions.server_value(server_id).await?;
+
+        if words.is_empty() && pattern.is_empty() && max_mentions == 0 {
+            return Ok(None);
+        }
+
+        let words = if words.is_empty() {
+            None
+        } else {
+            Some(Regex::new(&format!("(?i){}", words.join("|")))?)
+        };
+
+        let pattern = if pattern.is_empty() {
+            None
+        } else {
+            Some(
+                RegexBuilder::new(&pattern)
+                    .size_limit(MAX_PATTERN_SIZE)
+                    .build()
+                    .map_err(|err| FilterError::InvalidPattern(err.to_string()))?,
+            )
+        };
+
+        let action = settings.filter_action.server_value(server_id).await?;
+        let action = FilterAction::from_str(&action).ok_or(FilterError::InvalidAction(action))?;
+
+        let log_channel = settings.filter_log_channel.server_value(server_id).await?;
+        let log_channel = if log_channel.is_empty() {
+            None
+        } else {
+            Some(ChannelId::from_str(&log_channel)?)
+        };
+
+        Ok(Some(ContentFilter {
+            words,
+            pattern,
+            max_mentions: max_mentions as usize,
+            action,
+            log_channel,
+            service: server_id.service_kind(),
+        }))
+    }
+
+    fn count_mentions(&self, text: &str) -> usize {
+        let mut count = 0;
+
+        for segment in self.service.parse_content(text) {
+            match segment {
+                ContentSegment::UserMention(_) => count += 1,
+                ContentSegment::Text(text) => {
+                    count += text.matches("@everyone").count() + text.matches("@here").count()
+                }
+                _ => {}
+            }
+        }
+
+        count
+    }
+
+    fn find_violation(&self, text: &str, mentions: bool) -> Option<Violation> {
+        if let Some(found) = self.words.as_ref().and_then(|words| words.find(text)) {
+            return Some(Violation::Word(found.as_str().to_string()));
+        }
+
+        if let Some(found) = self.pattern.as_ref().and_then(|pattern| pattern.find(text)) {
+            return Some(Violation::Pattern(found.as_str().to_string()));
+        }
+
+        if mentions && self.max_mentions > 0 {
+            let count = self.count_mentions(text);
+
+            if count > self.max_mentions {
+                return Some(Violation::Mentions(count));
+            }
+        }
+
+        None
+    }
+
+    async fn notify(
+        &self,
+        bot: &Bot,
+        channel_id: ChannelId,
+        author_id: UserId,
+        kind: &str,
+        violation: &Violation,
+        action: FilterAction,
+    ) {
+        let log_channel = match self.log_channel {
+            Some(log_channel) => log_channel,
+            None => return,
+        };
+
+        let text = format!(
+            "Sandbox {} of {} in {} {}, the run was {}",
+            kind,
+            author_id.to_short_str(),
+            channel_id.to_short_str(),
+            violation,
+            action.name()
+        );
+        let settings = MessageSettings {
+            sanitize: Sanitize::all(),
+            ..Default::default()
+        };
+
+        if let Err(err) = bot
+            .get_ctx()
+            .services()
+            .send_message(log_channel, text, settings)
+            .await
+        {
+            println!("error sending filter notification: {}", err.to_string());
+        }
+    }
+
+    /// Check code before it runs, code breaking the filter never runs unless the action is to log
+    pub async fn check_code(
+        &self,
+        bot: &Bot,
+        channel_id: ChannelId,
+        author_id: UserId,
+        code: &str,
+    ) -> Result<()> {
+        let violation = match self.find_violation(code, false) {
+            Some(violation) => violation,
+            None => return Ok(()),
+        };
+
+        let action = match self.action {
+            FilterAction::Log => FilterAction::Log,
+            _ => FilterAction::Reject,
+        };
+
+        self.notify(bot, channel_id, author_id, "code", &violation, action)
+            .await;
+
+        match action {
+            FilterAction::Log => Ok(()),
+            _ => Err(FilterError::Blocked("code").into()),
+        }
+    }
+
+    /// Check the output of a run, returning what can be sent
+    pub async fn check_output(
+        &self,
+        bot: &Bot,
+        channel_id: ChannelId,
+        author_id: UserId,
+        output: String,
+    ) -> Result<String> {
+        let violation = match self.find_violation(&output, true) {
+            Some(violation) => violation,
+            None => return Ok(output),
+        };
+
+        let (action, output) = match self.action {
+            FilterAction::Redact => {
+                let mut output = output;
+
+                for regex in self.words.iter().chain(self.pattern.iter()) {
+                    output = regex.replace_all(&output, REDACTED).into_owned();
+                }
+
+                // Mentions can't be redacted, too many of them still reject the run
+                match self.find_violation(&output, true) {
+                    Some(_) => (FilterAction::Reject, output),
+                    None => (FilterAction::Redact, output),
+                }
+            }
+            action => (action, output),
+        };
+
+        self.notify(bot, channel_id, author_id, "output", &violation, action)
+            .await;
+
+        match action {
+            FilterAction::Reject => Err(FilterError::Blocked("output").into()),
+            _ => Ok(output),
+        }
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum FilterError {
+    #[error("the {} was blocked by the content filter of the server", _0)]
+    Blocked(&'static str),
+    #[error("the content filter pattern of the server is invalid: {}", _0)]
+    InvalidPattern(String),
+    #[error(
+        "unknown content filter action \"{}\", expected reject, redact or log",
+        _0
+    )]
+    InvalidAction(String),
+}
@@ -1,24 +1,172 @@
-use crossbeam::channel::Sender;
-use futures::{StreamExt, TryStreamExt};
-use hyper::{body::Bytes, Body, Client, Request, Response};
+use async_mutex::Mutex;
+use futures::{
+    stream::{SplitSink, SplitStream},
+    SinkExt, StreamExt, TryStreamExt,
+};
+use governor::{
+    state::{direct::NotKeyed, keyed::DefaultKeyedStateStore, InMemoryState},
+    Quota, RateLimiter,
+};
+use hyper::{
+    body::Bytes,
+    header::{HeaderMap, HeaderName, HeaderValue},
+    Body, Client, Request, Response, StatusCode,
+};
 use hyper_tls::HttpsConnector;
 use mlua::{
-    prelude::{LuaError, LuaMultiValue, LuaTable},
-    Lua, Table, Value,
+    prelude::{LuaError, LuaMultiValue, LuaString, LuaTable},
+    Lua, Table, UserData, UserDataMethods, Value,
 };
 use std::{
-    net::IpAddr,
+    collections::HashMap,
+    net::{IpAddr, SocketAddr},
+    num::NonZeroU32,
     sync::{atomic::Ordering, Arc},
+    time::Duration,
 };
 use thiserror::Error;
+use tokio::net::TcpStream;
+use tokio_tungstenite::{
+    tungstenite::{
+        protocol::{frame::coding::CloseCode, CloseFrame, WebSocketConfig},
+        Error as WsError, Message,
+    },
+    MaybeTlsStream, WebSocketStream,
+};
+
+use super::{
+    clock::LuaClock,
+    lib::r#async::AsyncSender,
+    replay::{RecordedEvent, ReplayError, SandboxTape},
+    state::{current_trace, SandboxError, SandboxState},
+    throttle::{Throttle, ThrottleError, ThrottleQuota},
+    utils::TraceId,
+};
+
+const GLOBAL_REQUESTS_PER_SECOND: u32 = 2;
+const HOST_REQUESTS_PER_MINUTE: u32 = 60;
+// Forget idle hosts once this many are tracked
+const MAX_TRACKED_HOSTS: usize = 1024;
+const MAX_WEBSOCKET_MESSAGE_SIZE: usize = 1024 * 1024 * 4;
+
+type WebSocket = WebSocketStream<MaybeTlsStream<TcpStream>>;
+
+/// Limits http calls overall and per destination host, so one busy api can't starve the others
+pub struct HttpRateLimiter {
+    global: RateLimiter<NotKeyed, InMemoryState, LuaClock>,
+    hosts: RateLimiter<String, DefaultKeyedStateStore<String>, LuaClock>,
+    /// Configured quotas, matching the domain and its subdomains
+    domains: HashMap<String, RateLimiter<NotKeyed, InMemoryState, LuaClock>>,
+}
+
+impl HttpRateLimiter {
+    pub fn new(domain_limits: Option<&HashMap<String, u32>>, clock: &LuaClock) -> HttpRateLimiter {
+        let domains = domain_limits
+            .into_iter()
+            .flatten()
+            .filter_map(|(domain, per_minute)| {
+                let quota = Quota::per_minute(NonZeroU32::new(*per_minute)?);
+                Some((
+                    domain.to_lowercase(),
+                    RateLimiter::direct_with_clock(quota, clock),
+                ))
+            })
+            .collect();
+
+        HttpRateLimiter {
+            global: RateLimiter::direct_with_clock(
+                Quota::per_second(NonZeroU32::new(GLOBAL_REQUESTS_PER_SECOND).unwrap()),
+                clock,
+            ),
+            hosts: RateLimiter::new(
+                Quota::per_minute(NonZeroU32::new(HOST_REQUESTS_PER_MINUTE).unwrap()),
+                DefaultKeyedStateStore::default(),
+                clock,
+            ),
+            domains,
+        }
+    }
 
-use super::state::{LuaAsyncCallback, SandboxState};
+    pub async fn until_ready(&self, host: &str) {
+        let host = host.to_lowercase();
+        let domain = self
+            .domains
+            .iter()
+            .find(|(domain, _)| host == **domain || host.ends_with(&format!(".{}", domain)));
+
+        match domain {
+            Some((_, limiter)) => limiter.until_ready().await,
+            None => {
+                if self.hosts.len() > MAX_TRACKED_HOSTS {
+                    self.hosts.retain_recent();
+                }
+
+                self.hosts.until_key_ready(&host).await
+            }
+        }
+
+        self.global.until_ready().await;
+    }
+}
+
+/// Per minute quotas a run's http calls count against, shared with other runs by the same user
+/// or in the same channel
+#[derive(Clone, Default)]
+pub struct HttpQuota {
+    keys: Vec<(String, ThrottleQuota)>,
+}
+
+impl HttpQuota {
+    /// Limits of 0 or less are left out
+    pub fn new(limits: Vec<(String, i64)>) -> HttpQuota {
+        let keys = limits
+            .into_iter()
+            .filter(|(_, per_minute)| *per_minute > 0)
+            .map(|(key, per_minute)| {
+                let quota = ThrottleQuota {
+                    count: per_minute.min(u32::MAX as i64) as u32,
+                    period: Duration::from_secs(60),
+                };
+
+                (key, quota)
+            })
+            .collect();
+
+        HttpQuota { keys }
+    }
+
+    /// Count a call against every quota, returns how long to wait if one of them is used up
+    pub fn check(&self, throttle: &Throttle) -> Result<Option<Duration>, ThrottleError> {
+        // A quota that's used up shouldn't cost the others a call
+        for (key, quota) in &self.keys {
+            if throttle.remaining(key, *quota) == 0 {
+                return throttle.check(key, *quota);
+            }
+        }
+
+        for (key, quota) in &self.keys {
+            if let Some(wait) = throttle.check(key, *quota)? {
+                return Ok(Some(wait));
+            }
+        }
+
+        Ok(None)
+    }
+
+    /// Calls left before one of the quotas is used up, None without any quotas
+    pub fn remaining(&self, throttle: &Throttle) -> Option<u32> {
+        self.keys
+            .iter()
+            .map(|(key, quota)| throttle.remaining(key, *quota))
+            .min()
+    }
+}
 
 pub fn http_fetch<'a>(
     state: &'a Lua,
     sandbox_state: &SandboxState,
     url: &str,
-    _options: LuaTable<'a>,
+    options: LuaTable<'a>,
 ) -> Result<LuaTable<'a>, LuaError> {
     // Check http call limit
     let calls_left = sandbox_state
@@ -28,6 +176,12 @@ pub fn http_fetch<'a>(
         .load(Ordering::Relaxed);
 
     if calls_left == 0 {
+        sandbox_state
+            .0
+            .bot
+            .metrics()
+            .count_http_rejection("call_limit");
+
         return Err(LuaError::ExternalError(Arc::new(
             HttpError::HttpCallLimitReached,
         )));
@@ -58,6 +212,36 @@ pub fn http_fetch<'a>(
         }
     }
 
+    // Replays get the recorded responses without touching the network
+    let replay = sandbox_state
+        .0
+        .tape
+        .as_ref()
+        .map_or(false, |tape| tape.is_replay());
+
+    if !replay {
+        let lua = sandbox_state.0.bot.get_ctx().modules().lua.module().clone();
+        let wait = sandbox_state
+            .0
+            .http_quota
+            .check(lua.throttle())
+            .map_err(|err| LuaError::ExternalError(Arc::new(err)))?;
+
+        if let Some(wait) = wait {
+            sandbox_state.0.bot.metrics().count_http_rejection("quota");
+
+            return Err(LuaError::ExternalError(Arc::new(HttpError::RateLimited(
+                wait.as_secs_f64().ceil() as u64,
+            ))));
+        }
+    }
+
+    let stream = options.get::<_, bool>("stream").unwrap_or(false);
+
+    if let Some(tape) = sandbox_state.0.tape.clone().filter(|tape| tape.is_replay()) {
+        return replay_http_fetch(state, sandbox_state, &tape, stream);
+    }
+
     let addrs = match url.socket_addrs(|| Some(if url.scheme() == "https" { 443 } else { 80 })) {
         Ok(addrs) => addrs,
         Err(err) => {
@@ -67,21 +251,7 @@ pub fn http_fetch<'a>(
         }
     };
 
-    let disallowed_addr = addrs.iter().find(|addr| {
-        let ip = addr.ip();
-        ip.is_loopback()
-            || ip.is_multicast()
-            || ip.is_unspecified()
-            || (match ip {
-                IpAddr::V4(ip) => match ip.octets() {
-                    [10, ..] => true,
-                    [172, b, ..] if b >= 16 && b <= 31 => true,
-                    [192, 168, ..] => true,
-                    _ => false,
-                },
-                IpAddr::V6(_) => false, // IPv6 should be disabled in networking
-            })
-    });
+    let disallowed_addr = disallowed_address(&addrs, false);
 
     if let Some(disallowed_addr) = disallowed_addr {
         return Err(LuaError::ExternalError(Arc::new(
@@ -106,19 +276,40 @@ pub fn http_fetch<'a>(
         }
     };
 
-    let http_rate_limiter = sandbox_state.0.http_rate_limiter.clone();
+    let bot = sandbox_state.0.bot.clone();
+    let server_id = sandbox_state.0.server_id;
+    let host = url.host_str().unwrap_or_default().to_string();
     let sender = sandbox_state.0.async_sender.clone();
+    let trace = sandbox_state.0.trace;
+    let tape = sandbox_state.0.tape.clone();
+    let log_url = url.clone();
+
+    // Recordings keep whole bodies, streamed responses are read in full while recording
+    let buffered = !stream || tape.is_some();
 
     let max_size = 1024 * 1024 * 4; // Max 4MB
     let fut = create_lua_future!(
         state,
         sender,
-        (url,),
+        (url, stream, sandbox_state.clone()),
         async move {
+            let lua = bot.get_ctx().modules().lua.module().clone();
+            let flagged = lua.scan_sandbox_url(server_id, &log_url).await?;
+
             // Rate limit how often http calls can be made
-            http_rate_limiter.until_ready().await;
+            lua.http_rate_limiter().until_ready(&host).await;
 
             match client.request(req).await {
+                Ok(res) if !buffered => {
+                    let (parts, body) = res.into_parts();
+
+                    Ok((
+                        parts.status,
+                        parts.headers,
+                        ResponseBody::Stream(body),
+                        flagged,
+                    ))
+                }
                 Ok(mut res) => {
                     let body = res
                         .body_mut()
@@ -135,32 +326,87 @@ pub fn http_fetch<'a>(
                         .await
                         .unwrap_or_default();
 
-                    Ok((res, body))
+                    if let Some(tape) = tape {
+                        tape.record_event(RecordedEvent::Http {
+                            url: log_url,
+                            status: res.status().as_u16(),
+                            headers: res
+                                .headers()
+                                .iter()
+                                .map(|(name, value)| {
+                                    (
+                                        name.to_string(),
+                                        String::from_utf8_lossy(value.as_bytes()).into_owned(),
+                                    )
+                                })
+                                .collect(),
+                            body: base64::encode(&body),
+                        });
+                    }
+
+                    Ok((
+                        res.status(),
+                        res.headers().clone(),
+                        ResponseBody::Full(body),
+                        flagged,
+                    ))
+                }
+                Err(err) => {
+                    println!("error fetching {} [trace {}]: {}", log_url, trace, err.to_string());
+
+                    if let Some(tape) = tape {
+                        tape.record_event(RecordedEvent::HttpFailed {
+                            url: log_url,
+                            error: err.to_string(),
+                        });
+                    }
+
+                    Err(anyhow::Error::from(err))
                 }
-                Err(err) => Err(err),
             }
         },
-        |state, data: (String,), res: Result<(Response<Body>, Vec<u8>), hyper::Error>| {
-            let (res, body) = res?;
-            let (url,) = data;
+        |state,
+         data: (String, bool, SandboxState),
+         res: anyhow::Result<(StatusCode, HeaderMap, ResponseBody, bool)>| {
+            let (status, headers, body, flagged) = res?;
+            let (url, stream, sandbox_state) = data;
+
+            let tbl = response_table(state, &url, status, &headers)?;
+            set_response_body(state, &tbl, sandbox_state, body, stream)?;
+
+            // The server warns about unsafe links instead of blocking them
+            if flagged {
+                tbl.set("unsafe", true)?;
+            }
 
-            let tbl: LuaTable = state.create_table()?;
-            let headers_tbl: LuaTable = state.create_table()?;
+            Ok(tbl)
+        }
+    );
 
-            for (header_name, header_value) in res.headers() {
-                headers_tbl.set(
-                    header_name.as_str(),
-                    state.create_string(&header_value.as_bytes())?,
-                )?;
-            }
+    Ok(fut)
+}
 
-            tbl.set("headers", headers_tbl)?;
-            tbl.set("ok", res.status().is_success())?;
-            tbl.set("redirected", res.status().is_redirection())?;
-            tbl.set("status", res.status().as_u16())?;
-            tbl.set("statusText", res.status().canonical_reason())?;
-            tbl.set("url", state.create_string(&url)?)?;
-            tbl.set("body", state.create_string(&body)?)?;
+fn replay_http_fetch<'a>(
+    state: &'a Lua,
+    sandbox_state: &SandboxState,
+    tape: &SandboxTape,
+    stream: bool,
+) -> Result<LuaTable<'a>, LuaError> {
+    let event = tape.next_event();
+
+    let fut = create_lua_future!(
+        state,
+        sandbox_state.0.async_sender,
+        (stream, sandbox_state.clone()),
+        recorded_response(event),
+        |state,
+         data: (bool, SandboxState),
+         res: anyhow::Result<(String, StatusCode, HeaderMap, Vec<u8>)>| {
+            let (url, status, headers, body) = res?;
+            let (stream, sandbox_state) = data;
+
+            let tbl = response_table(state, &url, status, &headers)?;
+            set_response_body(state, &tbl, sandbox_state, ResponseBody::Full(body), stream)?;
 
             Ok(tbl)
         }
@@ -169,8 +415,262 @@ pub fn http_fetch<'a>(
     Ok(fut)
 }
 
+async fn recorded_response(
+    event: Option<RecordedEvent>,
+) -> anyhow::Result<(String, StatusCode, HeaderMap, Vec<u8>)> {
+    match event {
+        Some(RecordedEvent::Http {
+            url,
+            status,
+            headers,
+            body,
+        }) => {
+            let mut header_map = HeaderMap::new();
+
+            for (name, value) in headers {
+                if let (Ok(name), Ok(value)) = (
+                    HeaderName::from_bytes(name.as_bytes()),
+                    HeaderValue::from_str(&value),
+                ) {
+                    header_map.append(name, value);
+                }
+            }
+
+            Ok((
+                url,
+                StatusCode::from_u16(status)?,
+                header_map,
+                base64::decode(body)?,
+            ))
+        }
+        Some(RecordedEvent::HttpFailed { error, .. }) => Err(anyhow::anyhow!(error)),
+        _ => Err(ReplayError::Diverged("http fetch").into()),
+    }
+}
+
+fn response_table<'a>(
+    state: &'a Lua,
+    url: &str,
+    status: StatusCode,
+    headers: &HeaderMap,
+) -> Result<LuaTable<'a>, LuaError> {
+    let tbl: LuaTable = state.create_table()?;
+    let headers_tbl: LuaTable = state.create_table()?;
+
+    for (header_name, header_value) in headers {
+        headers_tbl.set(
+            header_name.as_str(),
+            state.create_string(&header_value.as_bytes())?,
+        )?;
+    }
+
+    tbl.set("headers", headers_tbl)?;
+    tbl.set("ok", status.is_success())?;
+    tbl.set("redirected", status.is_redirection())?;
+    tbl.set("status", status.as_u16())?;
+    tbl.set("statusText", status.canonical_reason())?;
+    tbl.set("url", state.create_string(url)?)?;
+
+    Ok(tbl)
+}
+
+/// Body of a sandbox response, read in full or left to be streamed in chunks
+enum ResponseBody {
+    Full(Vec<u8>),
+    Stream(Body),
+}
+
+impl ResponseBody {
+    async fn next_chunk(&mut self) -> Option<Result<Vec<u8>, hyper::Error>> {
+        match self {
+            ResponseBody::Full(body) if body.is_empty() => None,
+            ResponseBody::Full(body) => Some(Ok(std::mem::take(body))),
+            ResponseBody::Stream(body) => body
+                .next()
+                .await
+                .map(|chunk| chunk.map(|chunk| chunk.to_vec())),
+        }
+    }
+}
+
+/// Set the body, or a next_body future for streamed responses. Every byte read counts against
+/// the http bytes limit of the run
+fn set_response_body<'a>(
+    state: &'a Lua,
+    tbl: &LuaTable<'a>,
+    sandbox_state: SandboxState,
+    body: ResponseBody,
+    stream: bool,
+) -> anyhow::Result<()> {
+    match body {
+        ResponseBody::Full(body) if !stream => {
+            if sandbox_state.limits().http_bytes_limit(body.len() as u64) {
+                sandbox_state.0.bot.metrics().count_http_rejection("bytes");
+                return Err(SandboxError::LimitReached("http bytes").into());
+            }
+
+            tbl.set("body", state.create_string(&body)?)?;
+        }
+        body => tbl.set("next_body", create_next_body(state, sandbox_state, body)?)?,
+    }
+
+    Ok(())
+}
+
+fn create_next_body(
+    state: &Lua,
+    sandbox_state: SandboxState,
+    body: ResponseBody,
+) -> anyhow::Result<LuaTable> {
+    Ok(create_lua_future!(
+        state,
+        sandbox_state.0.async_sender,
+        sandbox_state.clone(),
+        async move {
+            let mut body = body;
+            let chunk = body.next_chunk().await;
+
+            (body, chunk)
+        },
+        |state,
+         sandbox_state: SandboxState,
+         res: (ResponseBody, Option<Result<Vec<u8>, hyper::Error>>)| {
+            let (body, chunk) = res;
+
+            let chunk = match chunk {
+                Some(chunk) => chunk?,
+                None => return Ok(LuaMultiValue::default()),
+            };
+
+            if sandbox_state.limits().http_bytes_limit(chunk.len() as u64) {
+                sandbox_state.0.bot.metrics().count_http_rejection("bytes");
+                return Err(SandboxError::LimitReached("http bytes").into());
+            }
+
+            let tbl: LuaTable = state.create_table()?;
+
+            tbl.set("body", state.create_string(&chunk)?)?;
+            tbl.set("next_body", create_next_body(state, sandbox_state, body)?)?;
+
+            Ok(LuaMultiValue::from_vec(vec![Value::Table(tbl)]))
+        }
+    ))
+}
+
+/// Private network addresses, the bot state may still connect to loopback addresses
+fn disallowed_address(addrs: &[SocketAddr], allow_loopback: bool) -> Option<&SocketAddr> {
+    addrs.iter().find(|addr| {
+        let ip = addr.ip();
+        (!allow_loopback && ip.is_loopback())
+            || ip.is_multicast()
+            || ip.is_unspecified()
+            || (match ip {
+                IpAddr::V4(ip) => match ip.octets() {
+                    [10, ..] => true,
+                    [172, b, ..] if b >= 16 && b <= 31 => true,
+                    [192, 168, ..] => true,
+                    _ => false,
+                },
+                IpAddr::V6(_) => false, // IPv6 should be disabled in networking
+            })
+    })
+}
+
+/// An open websocket of the bot state, lib/websocket.lua wraps it for http.websocket
+struct LuaWebSocket {
+    sink: Arc<Mutex<SplitSink<WebSocket, Message>>>,
+    stream: Arc<Mutex<SplitStream<WebSocket>>>,
+    sender: AsyncSender,
+}
+
+impl UserData for LuaWebSocket {
+    fn add_methods<'a, M: UserDataMethods<'a, Self>>(methods: &mut M) {
+        // Valid utf-8 is sent as a text message, anything else as binary
+        methods.add_method("send", |state, this, data: LuaString| {
+            let sink = this.sink.clone();
+            let msg = match std::str::from_utf8(data.as_bytes()) {
+                Ok(text) => Message::Text(text.to_string()),
+                Err(_) => Message::Binary(data.as_bytes().to_vec()),
+            };
+
+            let fut = create_lua_future!(
+                state,
+                this.sender,
+                (),
+                async move { sink.lock().await.send(msg).await },
+                |_state, _data: (), res: Result<(), WsError>| { Ok(res?) }
+            );
+
+            Ok(fut)
+        });
+
+        // Resolves to the next message, or nil once the websocket is closed
+        methods.add_method("receive", |state, this, (): ()| {
+            let stream = this.stream.clone();
+
+            let fut = create_lua_future!(
+                state,
+                this.sender,
+                (),
+                async move {
+                    let mut stream = stream.lock().await;
+
+                    while let Some(msg) = stream.next().await {
+                        match msg {
+                            Ok(Message::Text(text)) => return Ok(Some(text.into_bytes())),
+                            Ok(Message::Binary(data)) => return Ok(Some(data)),
+                            Ok(Message::Close(_)) => break,
+                            Ok(_) => {}
+                            Err(WsError::ConnectionClosed) | Err(WsError::AlreadyClosed) => break,
+                            Err(err) => return Err(err),
+                        }
+                    }
+
+                    Ok(None)
+                },
+                |state, _data: (), res: Result<Option<Vec<u8>>, WsError>| {
+                    Ok(match res? {
+                        Some(data) => Some(state.create_string(&data)?),
+                        None => None,
+                    })
+                }
+            );
+
+            Ok(fut)
+        });
+
+        methods.add_method(
+            "close",
+            |state, this, (code, reason): (Option<u16>, Option<String>)| {
+                let sink = this.sink.clone();
+                let frame = code.map(|code| CloseFrame {
+                    code: CloseCode::from(code),
+                    reason: reason.unwrap_or_default().into(),
+                });
+
+                let fut = create_lua_future!(
+                    state,
+                    this.sender,
+                    (),
+                    async move { sink.lock().await.send(Message::Close(frame)).await },
+                    |_state, _data: (), res: Result<(), WsError>| {
+                        match res {
+                            Ok(())
+                            | Err(WsError::ConnectionClosed)
+                            | Err(WsError::AlreadyClosed) => Ok(()),
+                            Err(err) => Err(err.into()),
+                        }
+                    }
+                );
+
+                Ok(fut)
+            },
+        );
+    }
+}
+
 // bot state only
-pub fn lib_http(state: &Lua, sender: Sender<LuaAsyncCallback>) -> anyhow::Result<()> {
+pub fn lib_http(state: &Lua, sender: AsyncSender) -> anyhow::Result<()> {
     let http = state.create_table()?;
 
     // http.fetch
@@ -206,20 +706,7 @@ pub fn lib_http(state: &Lua, sender: Sender<LuaAsyncCallback>) -> anyhow::Result
             }
         };
 
-        let disallowed_addr = addrs.iter().find(|addr| {
-            let ip = addr.ip();
-            ip.is_multicast()
-                || ip.is_unspecified()
-                || (match ip {
-                    IpAddr::V4(ip) => match ip.octets() {
-                        [10, ..] => true,
-                        [172, b, ..] if b >= 16 && b <= 31 => true,
-                        [192, 168, ..] => true,
-                        _ => false,
-                    },
-                    IpAddr::V6(_) => false, // IPv6 should be disabled in networking
-                })
-        });
+        let disallowed_addr = disallowed_address(&addrs, true);
 
         if let Some(disallowed_addr) = disallowed_addr {
             return Err(LuaError::ExternalError(Arc::new(
@@ -246,6 +733,9 @@ pub fn lib_http(state: &Lua, sender: Sender<LuaAsyncCallback>) -> anyhow::Result
             }
         };
 
+        let trace = current_trace(state).unwrap_or_else(TraceId::new);
+        let log_url = url.clone();
+
         let max_size = 1024 * 1024 * 4; // Max 4MB
         let fut = if options.get::<&str, bool>("stream").unwrap_or(false) {
             create_lua_future!(
@@ -255,11 +745,14 @@ pub fn lib_http(state: &Lua, sender: Sender<LuaAsyncCallback>) -> anyhow::Result
                 async move {
                     match client.request(req).await {
                         Ok(res) => Ok(res),
-                        Err(err) => Err(err),
+                        Err(err) => {
+                            println!("error fetching {} [trace {}]: {}", log_url, trace, err.to_string());
+                            Err(err)
+                        }
                     }
                 },
                 |state,
-                 data: (usize, String, Sender<LuaAsyncCallback>),
+                 data: (usize, String, AsyncSender),
                  res: Result<Response<Body>, hyper::Error>| {
                     let res = res?;
                     let (max_size, url, sender) = data;
@@ -281,7 +774,7 @@ pub fn lib_http(state: &Lua, sender: Sender<LuaAsyncCallback>) -> anyhow::Result
                     tbl.set("statusText", res.status().canonical_reason())?;
                     tbl.set("url", state.create_string(&url)?)?;
 
-                    fn create_next_body(state: &Lua, sender: Sender<LuaAsyncCallback>, max_size: usize, bytes_received: usize, mut body: Body) -> anyhow::Result<LuaTable> {
+                    fn create_next_body(state: &Lua, sender: AsyncSender, max_size: usize, bytes_received: usize, mut body: Body) -> anyhow::Result<LuaTable> {
                         Ok(create_lua_future!(
                             state,
                             sender,
@@ -291,7 +784,7 @@ pub fn lib_http(state: &Lua, sender: Sender<LuaAsyncCallback>) -> anyhow::Result
 
                                 (body, bytes)
                             },
-                            |state, data: (usize, usize, Sender<LuaAsyncCallback>), res: (Body, Option<Result<Bytes, hyper::Error>>)| {
+                            |state, data: (usize, usize, AsyncSender), res: (Body, Option<Result<Bytes, hyper::Error>>)| {
                                 let (max_size, mut bytes_received, sender) = data;
                                 if let Some(data) = res.1 {
                                     let data = data?;
@@ -348,7 +841,10 @@ pub fn lib_http(state: &Lua, sender: Sender<LuaAsyncCallback>) -> anyhow::Result
 
                             Ok((res, body))
                         }
-                        Err(err) => Err(err),
+                        Err(err) => {
+                            println!("error fetching {} [trace {}]: {}", log_url, trace, err.to_string());
+                            Err(err)
+                        }
                     }
                 },
                 |state, data: (String,), res: Result<(Response<Body>, Vec<u8>), hyper::Error>| {
@@ -382,6 +878,88 @@ pub fn lib_http(state: &Lua, sender: Sender<LuaAsyncCallback>) -> anyhow::Result
     })?;
     http.set("fetch", http_fetch)?;
 
+    // http.__websocket
+    let sender2 = sender.clone();
+    let http_websocket = state.create_function(move |state, url: String| {
+        let sender = sender2.clone();
+
+        let url = match url::Url::parse(&url) {
+            Ok(url) => url,
+            Err(err) => {
+                return Err(LuaError::ExternalError(Arc::new(
+                    HttpError::ErrorParsingUrl(err.to_string()),
+                )));
+            }
+        };
+
+        match url.scheme() {
+            "ws" | "wss" => {}
+            _ => {
+                return Err(LuaError::ExternalError(Arc::new(HttpError::UnknownScheme(
+                    url.scheme().into(),
+                ))));
+            }
+        }
+
+        let addrs = match url.socket_addrs(|| Some(if url.scheme() == "wss" { 443 } else { 80 })) {
+            Ok(addrs) => addrs,
+            Err(err) => {
+                return Err(LuaError::ExternalError(Arc::new(
+                    HttpError::ErrorResolvingHosts(err.to_string()),
+                )));
+            }
+        };
+
+        if let Some(disallowed_addr) = disallowed_address(&addrs, true) {
+            return Err(LuaError::ExternalError(Arc::new(
+                HttpError::DisallowedAddress(disallowed_addr.to_string()),
+            )));
+        }
+
+        let trace = current_trace(state).unwrap_or_else(TraceId::new);
+        let url = url.to_string();
+
+        let fut = create_lua_future!(
+            state,
+            sender,
+            sender.clone(),
+            async move {
+                let config = WebSocketConfig {
+                    max_message_size: Some(MAX_WEBSOCKET_MESSAGE_SIZE),
+                    max_frame_size: Some(MAX_WEBSOCKET_MESSAGE_SIZE),
+                    ..Default::default()
+                };
+
+                let res = tokio_tungstenite::connect_async_with_config(url.clone(), Some(config));
+
+                match res.await {
+                    Ok((ws, _res)) => Ok(ws),
+                    Err(err) => {
+                        println!(
+                            "error connecting to {} [trace {}]: {}",
+                            url,
+                            trace,
+                            err.to_string()
+                        );
+                        Err(err)
+                    }
+                }
+            },
+            |_state, sender: AsyncSender, res: Result<WebSocket, WsError>| {
+                let (sink, stream) = res?.split();
+
+                Ok(LuaWebSocket {
+                    sink: Arc::new(Mutex::new(sink)),
+                    stream: Arc::new(Mutex::new(stream)),
+                    sender,
+                })
+            }
+        );
+
+        Ok(fut)
+    })?;
+    http.set("__websocket", http_websocket)?;
+
     state.globals().set("http", http)?;
 
     Ok(())
@@ -401,4 +979,6 @@ pub enum HttpError {
     DisallowedAddress(String),
     #[error("error building request: {}", _0)]
     ErrorBuildingRequest(String),
+    #[error("http rate limit reached, try again in {} seconds", _0)]
+    RateLimited(u64),
 }
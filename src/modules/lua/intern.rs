@@ -0,0 +1,49 @@
+use lru::LruCache;
+use std::{
+    hash::Hash,
+    sync::{Arc, Mutex},
+};
+
+use crate::services::{ChannelId, ServerId, UserId};
+
+const INTERNED_IDS: usize = 4096;
+
+/// Shared short strings of ids, busy channels and their users show up in message after message
+struct IdInterner<K: Hash + Eq>(Mutex<LruCache<K, Arc<str>>>);
+
+impl<K: Hash + Eq + Copy> IdInterner<K> {
+    fn new() -> IdInterner<K> {
+        IdInterner(Mutex::new(LruCache::new(INTERNED_IDS)))
+    }
+
+    fn get(&self, id: K, to_str: impl FnOnce(K) -> String) -> Arc<str> {
+        let mut ids = self.0.lock().unwrap();
+
+        if let Some(id_str) = ids.get(&id) {
+            return id_str.clone();
+        }
+
+        let id_str: Arc<str> = to_str(id).into();
+        ids.put(id, id_str.clone());
+
+        id_str
+    }
+}
+
+lazy_static::lazy_static! {
+    static ref USER_IDS: IdInterner<UserId> = IdInterner::new();
+    static ref CHANNEL_IDS: IdInterner<ChannelId> = IdInterner::new();
+    static ref SERVER_IDS: IdInterner<ServerId> = IdInterner::new();
+}
+
+pub fn user_id_str(id: UserId) -> Arc<str> {
+    USER_IDS.get(id, |id| id.to_short_str())
+}
+
+pub fn channel_id_str(id: ChannelId) -> Arc<str> {
+    CHANNEL_IDS.get(id, |id| id.to_short_str())
+}
+
+pub fn server_id_str(id: ServerId) -> Arc<str> {
+    SERVER_IDS.get(id, |id| id.to_short_str())
+}
@@ -0,0 +1,208 @@
+use anyhow::Result;
+use governor::{state::keyed::DefaultKeyedStateStore, Quota, RateLimiter};
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    num::NonZeroU32,
+    sync::Mutex,
+};
+use thiserror::Error;
+
+use super::{clock::LuaClock, state::SandboxOwner};
+use crate::{
+    bot::{
+        events::{BotEvent, IpcMessage},
+        Bot,
+    },
+    services::ServerId,
+};
+
+pub const MAX_IPC_MESSAGE_SIZE: usize = 4 * 1024;
+const MAX_CHANNEL_NAME_LEN: usize = 32;
+const MAX_SUBSCRIPTIONS: usize = 16;
+// Older messages are dropped once a mailbox is full
+const MAILBOX_SIZE: usize = 32;
+const PUBLISHES_PER_MINUTE: u32 = 30;
+
+type Endpoint = (ServerId, SandboxOwner);
+
+/// Channels sandbox runs on the same server publish data on, messages go through the event bus
+/// and wait in the mailboxes of the subscribers until their next run receives them
+pub struct IpcBroker {
+    subscriptions: Mutex<HashMap<(ServerId, String), HashSet<SandboxOwner>>>,
+    mailboxes: Mutex<HashMap<Endpoint, VecDeque<IpcMessage>>>,
+    limiter: RateLimiter<Endpoint, DefaultKeyedStateStore<Endpoint>, LuaClock>,
+}
+
+impl IpcBroker {
+    pub fn new(clock: &LuaClock) -> IpcBroker {
+        IpcBroker {
+            subscriptions: Mutex::new(HashMap::new()),
+            mailboxes: Mutex::new(HashMap::new()),
+            limiter: RateLimiter::new(
+                Quota::per_minute(NonZeroU32::new(PUBLISHES_PER_MINUTE).unwrap()),
+                DefaultKeyedStateStore::default(),
+                clock,
+            ),
+        }
+    }
+
+    pub fn publish(
+        &self,
+        bot: &Bot,
+        server_id: ServerId,
+        source: &SandboxOwner,
+        channel: &str,
+        data: String,
+    ) -> Result<(), IpcError> {
+        check_channel_name(channel)?;
+
+        if data.len() > MAX_IPC_MESSAGE_SIZE {
+            return Err(IpcError::TooLarge(MAX_IPC_MESSAGE_SIZE));
+        }
+
+        if self
+            .limiter
+            .check_key(&(server_id, source.clone()))
+            .is_err()
+        {
+            return Err(IpcError::RateLimited);
+        }
+
+        bot.events().publish(BotEvent::Ipc(IpcMessage {
+            server_id: server_id.to_short_str(),
+            channel: channel.into(),
+            source: source.to_string(),
+            data,
+            timestamp: chrono::Utc::now().timestamp(),
+        }));
+
+        Ok(())
+    }
+
+    /// Put a message from the event bus in the mailboxes of the channel subscribers
+    pub fn deliver(&self, msg: IpcMessage) -> Result<()> {
+        let server_id = ServerId::from_str(&msg.server_id)?;
+
+        let subscribers = match self
+            .subscriptions
+            .lock()
+            .unwrap()
+            .get(&(server_id, msg.channel.clone()))
+        {
+            Some(subscribers) => subscribers.clone(),
+            None => return Ok(()),
+        };
+
+        let mut mailboxes = self.mailboxes.lock().unwrap();
+
+        for subscriber in subscribers {
+            let mailbox = mailboxes.entry((server_id, subscriber)).or_default();
+
+            if mailbox.len() >= MAILBOX_SIZE {
+                mailbox.pop_front();
+            }
+
+            mailbox.push_back(msg.clone());
+        }
+
+        Ok(())
+    }
+
+    pub fn subscribe(
+        &self,
+        server_id: ServerId,
+        owner: &SandboxOwner,
+        channel: &str,
+    ) -> Result<(), IpcError> {
+        check_channel_name(channel)?;
+
+        let mut subscriptions = self.subscriptions.lock().unwrap();
+
+        let count = subscriptions
+            .iter()
+            .filter(|((id, _), subscribers)| *id == server_id && subscribers.contains(owner))
+            .count();
+
+        let subscribers = subscriptions
+            .entry((server_id, channel.into()))
+            .or_default();
+
+        if !subscribers.contains(owner) && count >= MAX_SUBSCRIPTIONS {
+            return Err(IpcError::TooManySubscriptions(MAX_SUBSCRIPTIONS));
+        }
+
+        subscribers.insert(owner.clone());
+
+        Ok(())
+    }
+
+    pub fn unsubscribe(&self, server_id: ServerId, owner: &SandboxOwner, channel: &str) {
+        let mut subscriptions = self.subscriptions.lock().unwrap();
+        let key = (server_id, channel.to_string());
+
+        if let Some(subscribers) = subscriptions.get_mut(&key) {
+            subscribers.remove(owner);
+
+            if subscribers.is_empty() {
+                subscriptions.remove(&key);
+            }
+        }
+    }
+
+    /// Drop the subscriptions and pending messages of an uninstalled script
+    pub fn remove(&self, server_id: ServerId, owner: &SandboxOwner) {
+        self.subscriptions
+            .lock()
+            .unwrap()
+            .retain(|(id, _), subscribers| {
+                if *id == server_id {
+                    subscribers.remove(owner);
+                }
+
+                !subscribers.is_empty()
+            });
+
+        self.mailboxes
+            .lock()
+            .unwrap()
+            .remove(&(server_id, owner.clone()));
+    }
+
+    /// Take the messages waiting for the owner, oldest first
+    pub fn receive(&self, server_id: ServerId, owner: &SandboxOwner) -> Vec<IpcMessage> {
+        self.mailboxes
+            .lock()
+            .unwrap()
+            .remove(&(server_id, owner.clone()))
+            .map(Vec::from)
+            .unwrap_or_default()
+    }
+}
+
+fn check_channel_name(channel: &str) -> Result<(), IpcError> {
+    if channel.is_empty()
+        || channel.len() > MAX_CHANNEL_NAME_LEN
+        || !channel
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-' || c == '.')
+    {
+        return Err(IpcError::InvalidChannel(MAX_CHANNEL_NAME_LEN));
+    }
+
+    Ok(())
+}
+
+#[derive(Debug, Error)]
+pub enum IpcError {
+    #[error(
+        "channel names must be at most {} letters, digits, dots, dashes or underscores",
+        _0
+    )]
+    InvalidChannel(usize),
+    #[error("messages can't be larger than {} bytes", _0)]
+    TooLarge(usize),
+    #[error("messages are being published too quickly, try again later")]
+    RateLimited,
+    #[error("at most {} channels can be subscribed to", _0)]
+    TooManySubscriptions(usize),
+}
@@ -10,10 +10,29 @@ use std::{
 
 #[macro_use]
 pub mod r#async;
+pub mod blob;
 pub mod bot;
+pub mod chart;
+pub mod emoji;
+pub mod fs;
+pub mod gif;
+pub mod github;
 pub mod image;
+pub mod image_meta;
+pub mod latex;
+pub mod moderation;
 pub mod os;
+pub mod polls;
+pub mod proc;
+pub mod qr;
+pub mod quotes;
+pub mod schedule;
+pub mod server_scripts;
+pub mod storage;
 pub mod tags;
+pub mod throttle;
+pub mod time;
+pub mod translate;
 pub mod voice;
 
 fn remove_upwards_components(path: &Path) -> PathBuf {
@@ -1,37 +1,426 @@
 use anyhow::Result;
-use crossbeam::channel::Sender;
+use crossbeam::channel::{bounded, unbounded, Receiver, Sender};
+use futures::task::{waker, ArcWake};
 use mlua::{
-    prelude::{LuaError, LuaMultiValue},
-    Function, Lua, RegistryKey, Table,
+    prelude::{LuaError, LuaMultiValue, LuaResult, LuaValue},
+    Function, Lua, Table, Thread, UserData, UserDataMethods,
 };
 use std::{
+    any::Any,
+    collections::HashMap,
+    panic::{catch_unwind, AssertUnwindSafe},
     sync::{
-        atomic::{AtomicU64, Ordering},
-        Arc,
+        atomic::{AtomicBool, AtomicU64, AtomicU8, AtomicUsize, Ordering},
+        Arc, Mutex as StdMutex,
     },
+    task::Waker,
     time::Duration,
 };
 use thiserror::Error;
+use tokio::{sync::Notify, task::JoinHandle};
 
-use super::super::state::LuaAsyncCallback;
+use super::super::{
+    error::{create_error_value, value_error_kind},
+    scheduler::{CronSchedule, Schedule, ScheduledJob, SchedulerError, MIN_INTERVAL},
+    state::{current_trace, get_sandbox_state, SandboxError},
+    utils::{panic_message, TraceId},
+};
+use crate::{bot::Bot, config::ConfigFutureOverflow};
+
+const DEFAULT_MAX_PENDING_FUTURES: usize = 4096;
+
+struct FutureLimiterInner {
+    pending: AtomicUsize,
+    limit: usize,
+    overflow: ConfigFutureOverflow,
+}
+
+/// Counts the futures of a state from their creation until their callback has been handled or dropped
+#[derive(Clone)]
+pub struct FutureLimiter(Arc<FutureLimiterInner>);
+
+impl FutureLimiter {
+    pub fn from_config(bot: &Bot) -> FutureLimiter {
+        let config = bot.config().lua.as_ref();
+
+        FutureLimiter(Arc::new(FutureLimiterInner {
+            pending: AtomicUsize::new(0),
+            limit: config
+                .and_then(|lua| lua.max_pending_futures)
+                .unwrap_or(DEFAULT_MAX_PENDING_FUTURES)
+                .max(1),
+            overflow: config
+                .and_then(|lua| lua.future_overflow)
+                .unwrap_or(ConfigFutureOverflow::Reject),
+        }))
+    }
+
+    pub fn limit(&self) -> usize {
+        self.0.limit
+    }
+
+    pub fn pending(&self) -> usize {
+        self.0.pending.load(Ordering::Relaxed)
+    }
+
+    /// Take a slot for a new future, or None if the future should be dropped
+    fn reserve(&self) -> Result<Option<FutureSlot>, AsyncError> {
+        let pending = self.0.pending.fetch_add(1, Ordering::Relaxed);
+        let slot = FutureSlot(self.clone());
+
+        if pending < self.0.limit {
+            return Ok(Some(slot));
+        }
+
+        match self.0.overflow {
+            ConfigFutureOverflow::Reject => Err(AsyncError::TooManyPending(self.0.limit)),
+            ConfigFutureOverflow::Drop => Ok(None),
+        }
+    }
+}
+
+impl UserData for FutureLimiter {}
+
+/// Held by a future until its callback is gone, freeing the slot again
+pub struct FutureSlot(FutureLimiter);
+
+impl Drop for FutureSlot {
+    fn drop(&mut self) {
+        (self.0).0.pending.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+pub fn reserve_future(state: &Lua) -> LuaResult<Option<FutureSlot>> {
+    let limiter: FutureLimiter = state.named_registry_value("__FUTURE_LIMITER")?;
+
+    limiter
+        .reserve()
+        .map_err(|err| LuaError::ExternalError(Arc::new(err)))
+}
+
+pub fn future_panicked(payload: Box<dyn Any + Send>, trace: Option<TraceId>) -> AsyncError {
+    let message = panic_message(&*payload);
+
+    match trace {
+        Some(trace) => println!("lua future panicked [trace {}]: {}", trace, message),
+        None => println!("lua future panicked: {}", message),
+    }
+
+    AsyncError::Panicked(message)
+}
+
+pub fn report_dropped_future(state: &Lua, trace: Option<TraceId>) {
+    let limit = state
+        .named_registry_value::<_, FutureLimiter>("__FUTURE_LIMITER")
+        .map(|limiter| limiter.limit())
+        .unwrap_or_default();
+
+    match trace {
+        Some(trace) => println!(
+            "dropped a future over the limit of {} pending [trace {}]",
+            limit, trace
+        ),
+        None => println!("dropped a future over the limit of {} pending", limit),
+    }
+}
+
+struct AsyncWakerInner {
+    notify: Notify,
+    sender: Sender<()>,
+    receiver: Receiver<()>,
+}
+
+/// Wakes the think loop of a state once a thread awaiting a future can be resumed, so it's resumed
+/// right away instead of on the next tick. The bot state waits for it on the runtime, sandbox workers
+/// block their thread on it.
+#[derive(Clone)]
+pub struct AsyncWaker(Arc<AsyncWakerInner>);
+
+impl Default for AsyncWaker {
+    fn default() -> AsyncWaker {
+        let (sender, receiver) = bounded(1);
+
+        AsyncWaker(Arc::new(AsyncWakerInner {
+            notify: Notify::new(),
+            sender,
+            receiver,
+        }))
+    }
+}
+
+impl AsyncWaker {
+    pub fn wake(&self) {
+        self.0.notify.notify_one();
+        self.0.sender.try_send(()).ok();
+    }
+
+    pub async fn woken(&self) {
+        self.0.notify.notified().await
+    }
+
+    /// Block the thread until woken, or the timeout has passed
+    pub fn wait_timeout(&self, timeout: Duration) {
+        self.0.receiver.recv_timeout(timeout).ok();
+    }
+}
+
+impl UserData for AsyncWaker {}
+
+const THREAD_RUNNING: u8 = 0;
+const THREAD_PARKED: u8 = 1;
+const THREAD_WOKEN: u8 = 2;
+
+/// Wakes a parked lua thread, so think resumes it once a future it awaits is ready
+struct ThreadWaker {
+    id: u64,
+    status: AtomicU8,
+    /// Set by `async.__park` when the thread yields waiting on a lua future
+    park_requested: AtomicBool,
+    woken: Sender<u64>,
+    waker: AsyncWaker,
+}
+
+impl ThreadWaker {
+    fn wake(&self) {
+        // Running threads are resumed every think anyway, or queued once their poll returns
+        if self.status.swap(THREAD_WOKEN, Ordering::AcqRel) == THREAD_PARKED {
+            self.woken.send(self.id).ok();
+            self.waker.wake();
+        }
+    }
+}
+
+impl ArcWake for ThreadWaker {
+    fn wake_by_ref(arc_self: &Arc<Self>) {
+        arc_self.wake();
+    }
+}
+
+struct AsyncSenderInner {
+    woken_sender: Sender<u64>,
+    woken_receiver: Receiver<u64>,
+    waker: AsyncWaker,
+    threads: StdMutex<HashMap<u64, Arc<ThreadWaker>>>,
+    next_thread_id: AtomicU64,
+}
+
+/// Drives the lua threads of a state. Rust futures are awaited natively by a lua thread of their
+/// own, which think resumes once the runtime wakes it.
+#[derive(Clone)]
+pub struct AsyncSender(Arc<AsyncSenderInner>);
+
+impl AsyncSender {
+    pub fn new(waker: AsyncWaker) -> AsyncSender {
+        let (woken_sender, woken_receiver) = unbounded();
+
+        AsyncSender(Arc::new(AsyncSenderInner {
+            woken_sender,
+            woken_receiver,
+            waker,
+            threads: StdMutex::new(HashMap::new()),
+            next_thread_id: AtomicU64::new(0),
+        }))
+    }
+
+    pub fn next_thread_id(&self) -> u64 {
+        self.0.next_thread_id.fetch_add(1, Ordering::Relaxed)
+    }
+
+    /// Have the next think resume the thread
+    pub fn wake_thread(&self, id: u64) {
+        match self.0.threads.lock().unwrap().get(&id) {
+            Some(thread) => thread.wake(),
+            None => {
+                self.0.woken_sender.send(id).ok();
+                self.0.waker.wake();
+            }
+        }
+    }
+
+    /// Waker of a thread that is about to be resumed, it stays running until parked
+    pub fn thread_waker(&self, id: u64) -> Waker {
+        let thread = self
+            .0
+            .threads
+            .lock()
+            .unwrap()
+            .entry(id)
+            .or_insert_with(|| {
+                Arc::new(ThreadWaker {
+                    id,
+                    status: AtomicU8::new(THREAD_RUNNING),
+                    park_requested: AtomicBool::new(false),
+                    woken: self.0.woken_sender.clone(),
+                    waker: self.0.waker.clone(),
+                })
+            })
+            .clone();
+
+        thread.status.store(THREAD_RUNNING, Ordering::Release);
+        thread.park_requested.store(false, Ordering::Relaxed);
+        waker(thread)
+    }
+
+    fn request_park(&self, id: u64) {
+        if let Some(thread) = self.0.threads.lock().unwrap().get(&id) {
+            thread.park_requested.store(true, Ordering::Relaxed);
+        }
+    }
+
+    /// Whether the thread yielded waiting on a lua future, rather than for the next tick
+    pub fn park_requested(&self, id: u64) -> bool {
+        match self.0.threads.lock().unwrap().get(&id) {
+            Some(thread) => thread.park_requested.load(Ordering::Relaxed),
+            None => false,
+        }
+    }
+
+    /// Park a thread after it was resumed. A thread woken while it was running is queued right away.
+    pub fn park_thread(&self, id: u64, force: bool) {
+        if let Some(thread) = self.0.threads.lock().unwrap().get(&id) {
+            // Yielding wakes the thread's own waker, which means nothing for a requested park
+            if force {
+                thread.status.store(THREAD_PARKED, Ordering::Release);
+                return;
+            }
+
+            let parked = thread.status.compare_exchange(
+                THREAD_RUNNING,
+                THREAD_PARKED,
+                Ordering::AcqRel,
+                Ordering::Acquire,
+            );
+
+            if parked.is_err() {
+                self.0.woken_sender.send(id).ok();
+                self.0.waker.wake();
+            }
+        }
+    }
+
+    /// Keep resuming the thread every think
+    pub fn run_thread(&self, id: u64) {
+        if let Some(thread) = self.0.threads.lock().unwrap().get(&id) {
+            thread.status.store(THREAD_RUNNING, Ordering::Release);
+        }
+    }
+
+    pub fn is_parked(&self, id: u64) -> bool {
+        match self.0.threads.lock().unwrap().get(&id) {
+            Some(thread) => thread.status.load(Ordering::Acquire) == THREAD_PARKED,
+            None => false,
+        }
+    }
+
+    /// The thread is gone, wakers still held by its futures do nothing anymore
+    pub fn remove_thread(&self, id: u64) {
+        self.0.threads.lock().unwrap().remove(&id);
+    }
 
-pub fn create_future(state: &Lua) -> Result<(RegistryKey, Table)> {
+    /// Ids of the threads woken since the last call
+    pub fn take_woken(&self) -> Vec<u64> {
+        self.0.woken_receiver.try_iter().collect()
+    }
+
+    pub fn woken_len(&self) -> usize {
+        self.0.woken_receiver.len()
+    }
+}
+
+/// Hand a thread to think, which resumes it until it's done. Returns the id of the thread.
+pub fn register_thread(
+    state: &Lua,
+    sender: &AsyncSender,
+    thread: Thread,
+    trace: Option<TraceId>,
+) -> LuaResult<u64> {
+    let threads: Table = state.named_registry_value("__ASYNC_THREADS")?;
+    let thread_traces: Table = state.named_registry_value("__ASYNC_THREADS_TRACES")?;
+    let thread_ids: Table = state.named_registry_value("__ASYNC_THREAD_IDS")?;
+
+    let id = sender.next_thread_id();
+    threads.set(id, thread.clone())?;
+    thread_traces.set(id, trace.map(|trace| trace.raw()))?;
+    thread_ids.set(thread, id)?;
+
+    sender.wake_thread(id);
+
+    Ok(id)
+}
+
+pub fn create_future(state: &Lua) -> Result<Table> {
     let async_tbl: Table = state.globals().get("async")?;
     let fut_fn: Function = async_tbl.get("__RustFuture")?;
 
-    let fut: Table = fut_fn.call(())?;
-    let fut_reg_key = state.create_registry_value(fut.clone())?;
+    Ok(fut_fn.call(())?)
+}
 
-    Ok((fut_reg_key, fut))
+pub type FutureCallback<T> =
+    Box<dyn for<'lua> FnOnce(&'lua Lua, T) -> Result<LuaMultiValue<'lua>> + Send>;
+
+/// Await a spawned task on a lua thread of its own. The thread returns whether the task succeeded
+/// and its results, think settles the lua future with them once it's done.
+pub fn await_on_thread<T: Send + 'static>(
+    state: &Lua,
+    sender: &AsyncSender,
+    fut: Table,
+    task: JoinHandle<Option<T>>,
+    slot: FutureSlot,
+    trace: Option<TraceId>,
+    callback: FutureCallback<T>,
+) -> LuaResult<()> {
+    let pending = StdMutex::new(Some((task, slot, callback)));
+
+    let await_fn = state.create_async_function(move |state, (): ()| {
+        let pending = pending.lock().unwrap().take();
+
+        async move {
+            // The slot is freed once the callback has run, or with the thread if it's dropped
+            let (task, _slot, callback) = match pending {
+                Some(pending) => pending,
+                None => return Ok(LuaMultiValue::new()),
+            };
+
+            let res = match task.await {
+                Ok(Some(res)) => catch_unwind(AssertUnwindSafe(move || callback(state, res)))
+                    .unwrap_or_else(|payload| Err(future_panicked(payload, trace).into())),
+                Ok(None) => Err(AsyncError::DeadlineExceeded.into()),
+                Err(err) if err.is_panic() => Err(future_panicked(err.into_panic(), trace).into()),
+                Err(err) => Err(AsyncError::FutureError(err.to_string()).into()),
+            };
+
+            let values = match res {
+                Ok(values) => [vec![LuaValue::Boolean(true)], values.into_vec()].concat(),
+                Err(err) => vec![LuaValue::Boolean(false), create_error_value(state, &err)?],
+            };
+
+            Ok(LuaMultiValue::from_vec(values))
+        }
+    })?;
+
+    let thread = state.create_thread(await_fn)?;
+    let id = register_thread(state, sender, thread, trace)?;
+
+    let thread_futures: Table = state.named_registry_value("__ASYNC_THREADS_FUTURES")?;
+    thread_futures.set(id, fut)?;
+
+    if let Some(sandbox_state) = get_sandbox_state(state) {
+        let thread_sandbox_states: Table =
+            state.named_registry_value("__ASYNC_THREADS_SANDBOX_STATES")?;
+        thread_sandbox_states.set(id, sandbox_state)?;
+    }
+
+    Ok(())
 }
 
 macro_rules! create_lua_future {
     ($state:expr, $sender:expr, $data:expr, $fut:expr, |$state_ident:ident, $data_ident:ident: $data_ty:ty, $res:ident: $res_ty:ty| $closure:block) => {{
         use mlua::ToLuaMulti;
 
-        let (future_reg_key, fut) = match $crate::modules::lua::lib::r#async::create_future($state)
-        {
-            Ok(a) => a,
+        let slot = $crate::modules::lua::lib::r#async::reserve_future($state)?;
+
+        let fut = match $crate::modules::lua::lib::r#async::create_future($state) {
+            Ok(fut) => fut,
             Err(err) => {
                 return Err(LuaError::ExternalError(Arc::new(
                     $crate::modules::lua::lib::r#async::AsyncError::FutureError(err.to_string()),
@@ -39,43 +428,118 @@ macro_rules! create_lua_future {
             }
         };
 
-        let sandbox_state = $state.named_registry_value("__SANDBOX_STATE").ok().clone();
+        let deadline = $crate::modules::lua::state::get_sandbox_state($state)
+            .map(|sandbox_state| sandbox_state.deadline());
+        let trace = $crate::modules::lua::state::current_trace($state);
+
+        match slot {
+            Some(slot) => {
+                let data = $data;
+                let task_fut = $fut;
 
-        let sender = $sender.clone();
-        let data = $data;
-        tokio::spawn(async move {
-            let fut_res = $fut.await;
+                // Sandbox futures still pending at the deadline are rejected, so upstreams that
+                // never respond don't keep their thread around
+                let task = tokio::spawn(async move {
+                    match deadline {
+                        Some(deadline) => {
+                            tokio::time::timeout_at(tokio::time::Instant::from_std(deadline), task_fut)
+                                .await
+                                .ok()
+                        }
+                        None => Some(task_fut.await),
+                    }
+                });
 
-            let callback: Box<dyn for<'c> FnOnce(&'c Lua) -> anyhow::Result<LuaMultiValue<'c>> + Send> = Box::new(move |state| {
-                fn lua_callback<'a>($state_ident: &'a Lua, $data_ident: $data_ty, $res: $res_ty) -> anyhow::Result<impl ToLuaMulti<'a>> $closure
+                let callback: $crate::modules::lua::lib::r#async::FutureCallback<$res_ty> = Box::new(move |state, res| {
+                    fn lua_callback<'a>($state_ident: &'a Lua, $data_ident: $data_ty, $res: $res_ty) -> anyhow::Result<impl ToLuaMulti<'a>> $closure
 
-                match lua_callback(state, data, fut_res) {
-                    Ok(data) => Ok(data.to_lua_multi(state)?),
-                    Err(err) => Err(err),
-                }
-            });
+                    Ok(lua_callback(state, data, res)?.to_lua_multi(state)?)
+                });
 
-            sender
-                .send((
-                    future_reg_key,
-                    sandbox_state,
+                $crate::modules::lua::lib::r#async::await_on_thread(
+                    $state,
+                    &$sender,
+                    fut.clone(),
+                    task,
+                    slot,
+                    trace,
                     callback,
-                ))
-                .unwrap();
-        });
+                )?;
+            }
+            None => $crate::modules::lua::lib::r#async::report_dropped_future($state, trace),
+        }
 
         fut
     }};
 }
 
-pub fn lib_async(
+/// A recurring job, async.lua calls its function every time the job's wait resolves to true
+struct LuaScheduledJob {
+    job: Arc<ScheduledJob>,
+    sender: AsyncSender,
+}
+
+impl UserData for LuaScheduledJob {
+    fn add_methods<'a, M: UserDataMethods<'a, Self>>(methods: &mut M) {
+        methods.add_method("wait", |state, this, (): ()| {
+            let job = this.job.clone();
+
+            let fut = create_lua_future!(
+                state,
+                this.sender,
+                (),
+                async move { job.wait().await },
+                |_state, _data: (), res: Result<bool>| { res }
+            );
+
+            Ok(fut)
+        });
+
+        methods.add_method("cancel", |_state, this, (): ()| {
+            this.job.cancel();
+            Ok(())
+        });
+
+        // Unix timestamp of the next run, nil until the job has been waited on
+        methods.add_method("next_run", |_state, this, (): ()| {
+            Ok(this.job.next_run().map(|next_run| next_run.timestamp()))
+        });
+    }
+}
+
+fn create_job(
     state: &Lua,
-    sender: Sender<LuaAsyncCallback>,
-    thread_id: Arc<AtomicU64>,
-) -> Result<()> {
+    bot: &Arc<Bot>,
+    sender: &AsyncSender,
+    schedule: Schedule,
+    name: Option<String>,
+) -> LuaResult<LuaScheduledJob> {
+    // Jobs of a sandbox run are rejected with the rest of its futures once it's past its deadline
+    if let Some(sandbox_state) = get_sandbox_state(state) {
+        if name.is_some() {
+            return Err(LuaError::ExternalError(Arc::new(
+                SchedulerError::NamedInSandbox,
+            )));
+        }
+
+        if sandbox_state.limits().scheduled_jobs_left_limit() {
+            return Err(LuaError::ExternalError(Arc::new(
+                SandboxError::LimitReached("scheduled job"),
+            )));
+        }
+    }
+
+    Ok(LuaScheduledJob {
+        job: ScheduledJob::new(schedule, name.map(|name| (bot.clone(), name))),
+        sender: sender.clone(),
+    })
+}
+
+pub fn lib_async(state: &Lua, bot: &Arc<Bot>, sender: AsyncSender) -> Result<()> {
     let async_tbl = state.create_table()?;
 
     // async.delay
+    let sender2 = sender.clone();
     let async_delay = state.create_function(move |state, duration: f64| {
         if duration.is_sign_negative() || !duration.is_finite() {
             return Err(LuaError::ExternalError(Arc::new(
@@ -87,7 +551,7 @@ pub fn lib_async(
 
         let fut = create_lua_future!(
             state,
-            sender,
+            sender2,
             (),
             tokio::time::sleep(duration),
             |_state, _data: (), _res: ()| { Ok(()) }
@@ -97,9 +561,81 @@ pub fn lib_async(
     })?;
     async_tbl.set("delay", async_delay)?;
 
-    let gen_thread_id_fn = state
-        .create_function(move |_state, (): ()| Ok(thread_id.fetch_add(1, Ordering::Relaxed)))?;
-    async_tbl.set("gen_thread_id", gen_thread_id_fn)?;
+    // async.__interval(seconds, name), async.interval runs a function with it
+    let bot2 = bot.clone();
+    let sender2 = sender.clone();
+    let interval_fn =
+        state.create_function(move |state, (seconds, name): (f64, Option<String>)| {
+            if !seconds.is_finite() || seconds < MIN_INTERVAL.as_secs_f64() {
+                return Err(LuaError::ExternalError(Arc::new(
+                    SchedulerError::IntervalTooShort(MIN_INTERVAL.as_secs()),
+                )));
+            }
+
+            let schedule = Schedule::Interval(Duration::from_secs_f64(seconds));
+
+            create_job(state, &bot2, &sender2, schedule, name)
+        })?;
+    async_tbl.set("__interval", interval_fn)?;
+
+    // async.__cron(expr, name), async.cron runs a function with it
+    let bot2 = bot.clone();
+    let sender2 = sender.clone();
+    let cron_fn = state.create_function(move |state, (expr, name): (String, Option<String>)| {
+        let schedule =
+            CronSchedule::parse(&expr).map_err(|err| LuaError::ExternalError(Arc::new(err)))?;
+
+        create_job(state, &bot2, &sender2, Schedule::Cron(schedule), name)
+    })?;
+    async_tbl.set("__cron", cron_fn)?;
+
+    // async.__spawn(thread), think resumes the thread until it's done
+    let sender2 = sender.clone();
+    let spawn_fn = state.create_function(move |state, thread: Thread| {
+        // Sandboxed coroutines are run by sandbox.lua, within the limits of their run
+        if get_sandbox_state(state).is_some() {
+            return Err(LuaError::ExternalError(Arc::new(
+                AsyncError::SpawnInSandbox,
+            )));
+        }
+
+        register_thread(state, &sender2, thread, current_trace(state))
+    })?;
+    async_tbl.set("__spawn", spawn_fn)?;
+
+    // async.__park(thread), the id to wake the thread with if it's the one think is resuming
+    let sender2 = sender.clone();
+    let park_fn = state.create_function(move |state, thread: Thread| {
+        let thread_ids: Table = state.named_registry_value("__ASYNC_THREAD_IDS")?;
+        let id: Option<u64> = thread_ids.get(thread)?;
+        let current: Option<u64> = state.named_registry_value("__CURRENT_THREAD")?;
+
+        match id {
+            Some(id) if current == Some(id) => {
+                sender2.request_park(id);
+                Ok(Some(id))
+            }
+            _ => Ok(None),
+        }
+    })?;
+    async_tbl.set("__park", park_fn)?;
+
+    // async.__wake(id)
+    let wake_fn = state.create_function(move |_state, id: u64| {
+        sender.wake_thread(id);
+        Ok(())
+    })?;
+    async_tbl.set("__wake", wake_fn)?;
+
+    // async.trace_id
+    let trace_id_fn = state.create_function(|state, (): ()| {
+        Ok(current_trace(state).map(|trace| trace.to_string()))
+    })?;
+    async_tbl.set("trace_id", trace_id_fn)?;
+
+    // async.error_kind
+    let error_kind_fn = state.create_function(|_state, err: LuaValue| value_error_kind(&err))?;
+    async_tbl.set("error_kind", error_kind_fn)?;
 
     state.globals().set("async", async_tbl)?;
 
@@ -112,4 +648,14 @@ pub enum AsyncError {
     InvalidDuration,
     #[error("{}", _0)]
     FutureError(String),
+    #[error("too many pending futures, at most {} can be awaited at once", _0)]
+    TooManyPending(usize),
+    #[error("internal error, the future panicked: {}", _0)]
+    Panicked(String),
+    #[error("timed out, the future was still pending when the time limit was reached")]
+    DeadlineExceeded,
+    #[error("the scripts were reloaded while the future was still pending")]
+    Reloaded,
+    #[error("threads can't be spawned in the sandbox")]
+    SpawnInSandbox,
 }
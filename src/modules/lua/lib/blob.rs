@@ -0,0 +1,366 @@
+use anyhow::Result;
+use async_mutex::Mutex;
+use hyper::{header::HeaderValue, Body, Request, Response, StatusCode};
+use mlua::{prelude::*, Lua};
+use sha2::{Digest, Sha256};
+use std::{collections::HashSet, io::ErrorKind, path::PathBuf, sync::Arc};
+use thiserror::Error;
+
+use super::{bot::BotServer, r#async::AsyncSender};
+use crate::{
+    bot::{
+        db::{Blob, Sid},
+        Bot,
+    },
+    server::{status_response, HttpHandler},
+};
+
+const MAX_BLOB_NAME_LEN: usize = 64;
+const MAX_BLOB_SIZE: usize = 8 * 1024 * 1024;
+// Total size of the blobs stored by a single server
+const SERVER_QUOTA: i64 = 64 * 1024 * 1024;
+
+pub const BLOBS_PATH: &str = "/blobs";
+
+lazy_static::lazy_static! {
+    // Writes and collection take turns, so a file can't be collected before its blob is stored
+    static ref BLOB_LOCK: Mutex<()> = Mutex::new(());
+}
+
+fn blobs_path(bot: &Bot) -> PathBuf {
+    bot.data_path().join("blobs")
+}
+
+fn is_hash(hash: &str) -> bool {
+    hash.len() == 64 && hash.chars().all(|c| c.is_ascii_hexdigit())
+}
+
+fn check_blob_name(name: &str) -> Result<()> {
+    if name.is_empty()
+        || name.chars().count() > MAX_BLOB_NAME_LEN
+        || !name
+            .chars()
+            .all(|c| c.is_alphanumeric() || matches!(c, '_' | '-' | '.'))
+    {
+        return Err(BlobError::InvalidName(MAX_BLOB_NAME_LEN).into());
+    }
+
+    Ok(())
+}
+
+fn blob_url(bot: &Bot, hash: &str) -> Option<String> {
+    let public_url = bot.config().http.as_ref()?.public_url.as_ref()?;
+
+    Some(format!(
+        "{}{}?key={}",
+        public_url.trim_end_matches('/'),
+        BLOBS_PATH,
+        hash
+    ))
+}
+
+fn blob_table<'a>(state: &'a Lua, blob: Blob) -> LuaResult<LuaTable<'a>> {
+    let tbl = state.create_table()?;
+
+    tbl.set("name", blob.name)?;
+    tbl.set("size", blob.size)?;
+    tbl.set("timestamp", blob.timestamp)?;
+
+    Ok(tbl)
+}
+
+async fn remove_if_unreferenced(bot: &Bot, hash: &str) -> Result<()> {
+    if bot.db().blob_referenced(hash).await? {
+        return Ok(());
+    }
+
+    match tokio::fs::remove_file(blobs_path(bot).join(hash)).await {
+        Ok(()) => Ok(()),
+        Err(err) if err.kind() == ErrorKind::NotFound => Ok(()),
+        Err(err) => Err(err.into()),
+    }
+}
+
+async fn put_blob(bot: &Bot, sid: Sid, name: &str, data: Vec<u8>) -> Result<()> {
+    check_blob_name(name)?;
+
+    if data.len() > MAX_BLOB_SIZE {
+        return Err(BlobError::TooLarge(MAX_BLOB_SIZE).into());
+    }
+
+    let _guard = BLOB_LOCK.lock().await;
+
+    if bot.db().blob_usage(sid, name).await? + data.len() as i64 > SERVER_QUOTA {
+        return Err(BlobError::QuotaExceeded(SERVER_QUOTA).into());
+    }
+
+    // Files are named by their content, blobs with the same data share one
+    let hash = hex::encode(Sha256::digest(&data));
+    let dir = blobs_path(bot);
+    let path = dir.join(&hash);
+
+    if !path.exists() {
+        tokio::fs::create_dir_all(&dir).await?;
+
+        let tmp_path = dir.join(format!("{}.tmp", hash));
+        tokio::fs::write(&tmp_path, &data).await?;
+        tokio::fs::rename(&tmp_path, &path).await?;
+    }
+
+    let old_hash = bot
+        .db()
+        .put_blob(sid, name, &hash, data.len() as i64)
+        .await?;
+
+    match old_hash {
+        Some(old_hash) if old_hash != hash => remove_if_unreferenced(bot, &old_hash).await,
+        _ => Ok(()),
+    }
+}
+
+async fn delete_blob(bot: &Bot, sid: Sid, name: &str) -> Result<bool> {
+    let _guard = BLOB_LOCK.lock().await;
+
+    match bot.db().delete_blob(sid, name).await? {
+        Some(hash) => {
+            remove_if_unreferenced(bot, &hash).await?;
+            Ok(true)
+        }
+        None => Ok(false),
+    }
+}
+
+/// Remove files no blob refers to anymore, like the ones left behind by a crash while writing
+pub async fn collect_blob_garbage(bot: &Bot) -> Result<usize> {
+    let _guard = BLOB_LOCK.lock().await;
+
+    let hashes: HashSet<String> = bot.db().blob_hashes().await?.into_iter().collect();
+
+    let mut entries = match tokio::fs::read_dir(blobs_path(bot)).await {
+        Ok(entries) => entries,
+        Err(err) if err.kind() == ErrorKind::NotFound => return Ok(0),
+        Err(err) => return Err(err.into()),
+    };
+
+    let mut removed = 0;
+
+    while let Some(entry) = entries.next_entry().await? {
+        let file_name = entry.file_name().to_string_lossy().to_string();
+
+        if !hashes.contains(&file_name) {
+            tokio::fs::remove_file(entry.path()).await?;
+            removed += 1;
+        }
+    }
+
+    Ok(removed)
+}
+
+// bot state only
+pub fn lib_blob(state: &Lua, bot: &Arc<Bot>, sender: AsyncSender) -> Result<()> {
+    let blob = state.create_table()?;
+
+    // blob.put, replaces the blob with the same name
+    let bot2 = bot.clone();
+    let sender2 = sender.clone();
+    let put_fn = state.create_function(
+        move |state, (server, name, data): (LuaAnyUserData, String, LuaString)| {
+            let bot = bot2.clone();
+            let server = server.borrow::<BotServer>()?.clone();
+            let data = data.as_bytes().to_vec();
+
+            let fut = create_lua_future!(
+                state,
+                sender2,
+                (),
+                async move {
+                    let sid = bot.db().get_sid(server.id()).await?;
+
+                    put_blob(&bot, sid, &name, data).await
+                },
+                |_state, _data: (), res: Result<()>| { res }
+            );
+
+            Ok(fut)
+        },
+    )?;
+    blob.set("put", put_fn)?;
+
+    // blob.get, resolves to the data or nil if there is no blob with the name
+    let bot2 = bot.clone();
+    let sender2 = sender.clone();
+    let get_fn =
+        state.create_function(move |state, (server, name): (LuaAnyUserData, String)| {
+            let bot = bot2.clone();
+            let server = server.borrow::<BotServer>()?.clone();
+
+            let fut = create_lua_future!(
+                state,
+                sender2,
+                (),
+                async move {
+                    let sid = bot.db().get_sid(server.id()).await?;
+
+                    match bot.db().blob(sid, &name).await? {
+                        Some(blob) => Ok(Some(
+                            tokio::fs::read(blobs_path(&bot).join(blob.hash)).await?,
+                        )),
+                        None => Ok(None),
+                    }
+                },
+                |state, _data: (), res: Result<Option<Vec<u8>>>| {
+                    Ok(match res? {
+                        Some(data) => LuaValue::String(state.create_string(&data)?),
+                        None => LuaValue::Nil,
+                    })
+                }
+            );
+
+            Ok(fut)
+        })?;
+    blob.set("get", get_fn)?;
+
+    // blob.url, resolves to a share link or nil if the blob doesn't exist or the http server
+    // has no public url
+    let bot2 = bot.clone();
+    let sender2 = sender.clone();
+    let url_fn =
+        state.create_function(move |state, (server, name): (LuaAnyUserData, String)| {
+            let bot = bot2.clone();
+            let server = server.borrow::<BotServer>()?.clone();
+
+            let fut = create_lua_future!(
+                state,
+                sender2,
+                (),
+                async move {
+                    let sid = bot.db().get_sid(server.id()).await?;
+
+                    Ok(bot
+                        .db()
+                        .blob(sid, &name)
+                        .await?
+                        .and_then(|blob| blob_url(&bot, &blob.hash)))
+                },
+                |_state, _data: (), res: Result<Option<String>>| { res }
+            );
+
+            Ok(fut)
+        })?;
+    blob.set("url", url_fn)?;
+
+    // blob.delete, resolves to false if there was no blob with the name
+    let bot2 = bot.clone();
+    let sender2 = sender.clone();
+    let delete_fn =
+        state.create_function(move |state, (server, name): (LuaAnyUserData, String)| {
+            let bot = bot2.clone();
+            let server = server.borrow::<BotServer>()?.clone();
+
+            let fut = create_lua_future!(
+                state,
+                sender2,
+                (),
+                async move {
+                    let sid = bot.db().get_sid(server.id()).await?;
+
+                    delete_blob(&bot, sid, &name).await
+                },
+                |_state, _data: (), res: Result<bool>| { res }
+            );
+
+            Ok(fut)
+        })?;
+    blob.set("delete", delete_fn)?;
+
+    // blob.list
+    let bot2 = bot.clone();
+    let list_fn = state.create_function(move |state, server: LuaAnyUserData| {
+        let bot = bot2.clone();
+        let server = server.borrow::<BotServer>()?.clone();
+
+        let fut = create_lua_future!(
+            state,
+            sender,
+            (),
+            async move {
+                let sid = bot.db().get_sid(server.id()).await?;
+
+                bot.db().blobs(sid).await
+            },
+            |state, _data: (), res: Result<Vec<Blob>>| {
+                let tbl = state.create_table()?;
+
+                for (i, blob) in res?.into_iter().enumerate() {
+                    tbl.set(i + 1, blob_table(state, blob)?)?;
+                }
+
+                Ok(tbl)
+            }
+        );
+
+        Ok(fut)
+    })?;
+    blob.set("list", list_fn)?;
+
+    state.globals().set("blob", blob)?;
+
+    Ok(())
+}
+
+/// Serves the share links handed out by `blob.url`
+pub struct BlobHandler(pub Arc<Bot>);
+
+#[async_trait]
+impl HttpHandler for BlobHandler {
+    async fn handle(&self, req: Request<Body>) -> Result<Response<Body>> {
+        if req.method() != hyper::Method::GET {
+            return Ok(status_response(StatusCode::METHOD_NOT_ALLOWED));
+        }
+
+        let hash = req.uri().query().and_then(|query| {
+            url::form_urlencoded::parse(query.as_bytes())
+                .find(|(key, _)| key == "key")
+                .map(|(_, value)| value.to_string())
+        });
+
+        // Only hashes are looked up, so the path can't lead outside of the blobs directory
+        let hash = match hash {
+            Some(hash) if is_hash(&hash) => hash,
+            _ => return Ok(status_response(StatusCode::NOT_FOUND)),
+        };
+
+        if !self.0.db().blob_referenced(&hash).await? {
+            return Ok(status_response(StatusCode::NOT_FOUND));
+        }
+
+        match tokio::fs::read(blobs_path(&self.0).join(&hash)).await {
+            Ok(data) => {
+                let mut res = Response::new(Body::from(data));
+                res.headers_mut().insert(
+                    hyper::header::CONTENT_TYPE,
+                    HeaderValue::from_static("application/octet-stream"),
+                );
+
+                Ok(res)
+            }
+            Err(err) if err.kind() == ErrorKind::NotFound => {
+                Ok(status_response(StatusCode::NOT_FOUND))
+            }
+            Err(err) => Err(err.into()),
+        }
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum BlobError {
+    #[error(
+        "blob names must be at most {} letters, digits, underscores, dashes or dots",
+        _0
+    )]
+    InvalidName(usize),
+    #[error("blobs can't be larger than {} bytes", _0)]
+    TooLarge(usize),
+    #[error("the server can't store more than {} bytes of blobs", _0)]
+    QuotaExceeded(i64),
+}
@@ -1,32 +1,88 @@
 use anyhow::Result;
-use async_mutex::Mutex;
 use chrono::{NaiveDateTime, Utc};
-use crossbeam::channel::{Sender, TryRecvError};
-use futures::TryFutureExt;
-use mlua::{prelude::*, Error as LuaError, Lua, MetaMethod, Table, UserData, UserDataMethods};
+use crossbeam::channel::TryRecvError;
+use futures::{TryFutureExt, TryStreamExt};
+use hyper::{header::CONTENT_TYPE, Body, Client, Request};
+use hyper_tls::HttpsConnector;
+use lru::LruCache;
+use mlua::{
+    prelude::*, Error as LuaError, Lua, LuaSerdeExt, MetaMethod, Table, UserData, UserDataMethods,
+};
+use governor::{state::keyed::DefaultKeyedStateStore, Quota, RateLimiter};
 use std::{
-    sync::Arc,
+    collections::HashMap,
+    num::NonZeroU32,
+    sync::{atomic::Ordering, Arc, Mutex as StdMutex},
     time::{Duration, Instant},
 };
+use thiserror::Error;
 
 use super::super::{
-    state::{get_sandbox_state, LuaAsyncCallback, LuaState, SandboxMsg, SandboxTerminationReason},
-    LuaSandboxReplies,
+    clock::LuaClock,
+    filter::ContentFilter,
+    intern::{channel_id_str, server_id_str, user_id_str},
+    pool::SandboxPool,
+    replay::{load_recording, save_recording, SandboxRecording, SandboxTape},
+    state::{
+        current_trace, get_sandbox_state, sandbox_env_path, LuaVmStats, SandboxError,
+        SandboxLimits, SandboxMsg, SandboxOwner, SandboxState, SandboxTerminationReason,
+        MAX_SAVED_ENV_SIZE,
+    },
+    trim_codeblocks,
+    utils::TraceId,
+    LuaSandboxReplies, SandboxQuota,
+};
+use super::{
+    emoji::resolve_shortcode,
+    gif::gifs_enabled,
+    image::{image_from_url, Image, ImageLimits},
+    r#async::AsyncSender,
+    schedule::{schedule_reply, BotScheduledReply},
+    time::parse_timezone,
 };
 use crate::{
     bot::{
-        db::{Uid, User as DbUser},
+        db::{AuditEntry, BlacklistEntry, ReactionRole, Uid, User as DbUser},
+        events::BotEvent,
+        permissions::{Caller, PermissionError},
         Bot, ROLES,
     },
-    message::{Attachment, MessageEmbed, MessageSettings},
+    message::{
+        format::{render, RichText, TextDialect},
+        Attachment, ButtonStyle, MessageComponent, MessageEmbed, MessageModal, MessagePriority,
+        MessageSettings, ModalField, Sanitize, SelectOption,
+    },
+    modules::Module,
     services::{
-        Channel, ChannelId, Message, MessageId, Server, ServerId, Service, ServiceFeatures,
-        ServiceKind, Services, User, UserId,
+        discord::DiscordService, ChannelAbstract, ChannelId, ContentSegment, ForumPost, ForumTag,
+        InteractionId, MessageAbstract, MessageId, ScheduledEvent, ScheduledEventSettings,
+        ServerAbstract, ServerId, ServerRole, Service, ServiceError, ServiceFeatures, ServiceKind,
+        ServiceStatus, Services, StickerSettings, UploadImage, UserAbstract, UserId,
+        UserPermissions,
     },
-    settings::SettingContext,
+    settings::{self, SettingContext},
     utils::escape_untrusted_text,
 };
 
+const SANDBOXED_LUA_RUNS_PER_MINUTE: u32 = 10;
+const MAX_COMPONENTS_PER_ROW: usize = 5;
+// Long replies are split into up to this many messages, sandboxed ones are never split
+const MAX_REPLY_PARTS: usize = 4;
+const EMOJI_LIMITS: ImageLimits = ImageLimits {
+    max_bytes: 256 * 1024,
+    dimensions: None,
+    formats: &["png", "gif", "jpeg"],
+};
+const STICKER_LIMITS: ImageLimits = ImageLimits {
+    max_bytes: 512 * 1024,
+    dimensions: Some((320, 320)),
+    formats: &["png"],
+};
+pub const MAX_ATTACHMENT_SIZE: u64 = 8 * 1024 * 1024;
+const MAX_SANDBOX_ATTACHMENT_SIZE: u64 = 1024 * 1024;
+// Content type prefixes of attachments that can be downloaded
+const ATTACHMENT_TYPES: &[&str] = &["image/", "text/", "application/json"];
+
 fn table_to_embed(tbl: LuaTable) -> Result<MessageEmbed> {
     let mut embed = MessageEmbed::default();
 
@@ -96,7 +152,11 @@ fn table_to_embed(tbl: LuaTable) -> Result<MessageEmbed> {
     Ok(embed)
 }
 
-fn message_settings_from_table(settings_tbl: LuaTable) -> Result<MessageSettings, LuaError> {
+fn message_settings_from_table(
+    settings_tbl: LuaTable,
+    sandbox_state: Option<&SandboxState>,
+) -> Result<MessageSettings, LuaError> {
+    let sandboxed = sandbox_state.is_some();
     let mut settings = MessageSettings::default();
 
     if let Ok(embed_tbl) = settings_tbl.get("embed") {
@@ -111,15 +171,358 @@ fn message_settings_from_table(settings_tbl: LuaTable) -> Result<MessageSettings
                     (field.get("filename"), field.get::<&str, LuaString>("data"))
                 {
                     let data = data.as_bytes().to_owned();
+
+                    if let Some(sandbox_state) = sandbox_state {
+                        if sandbox_state
+                            .limits()
+                            .attachment_upload_limit(data.len() as u64)
+                        {
+                            return Err(LuaError::ExternalError(Arc::new(
+                                SandboxError::LimitReached("attachment upload"),
+                            )));
+                        }
+                    }
+
                     settings.attachments.push((filename, data));
                 }
             }
         }
     }
 
+    if let Ok(gif) = settings_tbl.get("gif") {
+        settings.gif = Some(gif);
+    }
+
+    if let Ok(stickers) = settings_tbl.get::<&str, Vec<String>>("stickers") {
+        settings.stickers = stickers;
+    }
+
+    if let Some(priority) = settings_tbl.get::<_, Option<String>>("priority")? {
+        settings.priority = match priority.as_str() {
+            "low" => MessagePriority::Low,
+            "normal" => MessagePriority::Normal,
+            // Sandboxed code can't jump ahead of error notices
+            "high" if !sandboxed => MessagePriority::High,
+            "high" => MessagePriority::Normal,
+            priority => {
+                return Err(LuaError::RuntimeError(format!(
+                    "unknown message priority \"{}\"",
+                    priority
+                )))
+            }
+        };
+    }
+
+    if let Ok(components) = settings_tbl.get::<&str, LuaTable>("components") {
+        let mut row = Vec::new();
+
+        // Components listed directly share rows, nested tables are rows of their own
+        for entry in components.sequence_values::<LuaTable>() {
+            let entry = entry?;
+
+            if entry.get::<_, Option<String>>("id")?.is_some() {
+                row.push(table_to_component(entry)?);
+
+                if row.len() == MAX_COMPONENTS_PER_ROW {
+                    settings.components.push(std::mem::take(&mut row));
+                }
+            } else {
+                if !row.is_empty() {
+                    settings.components.push(std::mem::take(&mut row));
+                }
+
+                settings.components.push(
+                    entry
+                        .sequence_values::<LuaTable>()
+                        .map(|component| table_to_component(component?))
+                        .collect::<Result<Vec<_>, LuaError>>()?,
+                );
+            }
+        }
+
+        if !row.is_empty() {
+            settings.components.push(row);
+        }
+    }
+
+    Ok(settings)
+}
+
+fn table_to_component(tbl: LuaTable) -> Result<MessageComponent, LuaError> {
+    let id: String = tbl.get("id")?;
+    let kind: Option<String> = tbl.get("type")?;
+
+    match kind.as_deref().unwrap_or("button") {
+        "button" => {
+            let style: Option<String> = tbl.get("style")?;
+            let style = match style.as_deref().unwrap_or("primary") {
+                "primary" => ButtonStyle::Primary,
+                "secondary" => ButtonStyle::Secondary,
+                "success" => ButtonStyle::Success,
+                "danger" => ButtonStyle::Danger,
+                style => {
+                    return Err(LuaError::RuntimeError(format!(
+                        "unknown button style \"{}\"",
+                        style
+                    )))
+                }
+            };
+
+            Ok(MessageComponent::Button {
+                id,
+                label: tbl.get("label")?,
+                style,
+                disabled: tbl.get::<_, Option<bool>>("disabled")?.unwrap_or(false),
+            })
+        }
+        "select" => {
+            let mut options = Vec::new();
+
+            for option in tbl
+                .get::<_, LuaTable>("options")?
+                .sequence_values::<LuaTable>()
+            {
+                let option = option?;
+
+                options.push(SelectOption {
+                    label: option.get("label")?,
+                    value: option.get("value")?,
+                    description: option.get("description")?,
+                });
+            }
+
+            Ok(MessageComponent::Select {
+                id,
+                placeholder: tbl.get("placeholder")?,
+                options,
+                min_values: tbl.get::<_, Option<u64>>("min_values")?.unwrap_or(1),
+                max_values: tbl.get::<_, Option<u64>>("max_values")?.unwrap_or(1),
+            })
+        }
+        kind => Err(LuaError::RuntimeError(format!(
+            "unknown component type \"{}\"",
+            kind
+        ))),
+    }
+}
+
+fn table_to_modal(tbl: LuaTable) -> Result<MessageModal, LuaError> {
+    let mut fields = Vec::new();
+
+    for field in tbl
+        .get::<_, LuaTable>("fields")?
+        .sequence_values::<LuaTable>()
+    {
+        let field = field?;
+
+        fields.push(ModalField {
+            id: field.get("id")?,
+            label: field.get("label")?,
+            placeholder: field.get("placeholder")?,
+            value: field.get("value")?,
+            long: field.get::<_, Option<bool>>("long")?.unwrap_or(false),
+            required: field.get::<_, Option<bool>>("required")?.unwrap_or(true),
+            min_length: field.get("min_length")?,
+            max_length: field.get("max_length")?,
+        });
+    }
+
+    Ok(MessageModal {
+        title: tbl.get("title")?,
+        fields,
+    })
+}
+
+// Stickers and GIFs are dropped in channels that turned them off
+async fn filter_media(
+    bot: &Arc<Bot>,
+    server_id: ServerId,
+    channel_id: ChannelId,
+    mut settings: MessageSettings,
+) -> Result<MessageSettings> {
+    if settings.gif.is_some() || !settings.stickers.is_empty() {
+        if !gifs_enabled(bot, server_id, channel_id).await? {
+            settings.gif = None;
+            settings.stickers.clear();
+        }
+    }
+
     Ok(settings)
 }
 
+// Output of sandboxed code is sanitized according to the channel settings
+async fn sandbox_sanitize(
+    bot: &Arc<Bot>,
+    server_id: ServerId,
+    channel_id: ChannelId,
+    sandboxed: bool,
+) -> Result<Sanitize> {
+    if !sandboxed {
+        return Ok(Sanitize::empty());
+    }
+
+    bot.get_ctx()
+        .modules()
+        .lua
+        .module()
+        .sandbox_sanitize(server_id, channel_id)
+        .await
+}
+
+/// Services without components get a plain text version of them appended to the content instead
+fn component_fallback(
+    service: ServiceKind,
+    mut content: String,
+    mut settings: MessageSettings,
+) -> (String, MessageSettings) {
+    if settings.components.is_empty() || service.supports_feature(ServiceFeatures::COMPONENTS) {
+        return (content, settings);
+    }
+
+    for row in settings.components.drain(..) {
+        if !content.is_empty() {
+            content.push('\n');
+        }
+
+        let text = row
+            .iter()
+            .map(MessageComponent::fallback_text)
+            .collect::<Vec<_>>()
+            .join(" ");
+        content.push_str(&text);
+    }
+
+    (content, settings)
+}
+
+/// Images to upload are given as an image or an url to download
+enum UploadSource {
+    Image(Image),
+    Url(String),
+}
+
+impl UploadSource {
+    fn from_lua(value: LuaValue) -> LuaResult<UploadSource> {
+        match value {
+            LuaValue::UserData(image) => Ok(UploadSource::Image(image.borrow::<Image>()?.clone())),
+            LuaValue::String(url) => Ok(UploadSource::Url(url.to_str()?.to_string())),
+            _ => Err(LuaError::RuntimeError("expected an image or an url".into())),
+        }
+    }
+
+    async fn resolve(self, sender: AsyncSender, limits: &ImageLimits) -> Result<UploadImage> {
+        let image = match self {
+            UploadSource::Image(image) => image,
+            UploadSource::Url(url) => image_from_url(sender, &url).await?,
+        };
+
+        image.check_limits(limits)?;
+
+        Ok(UploadImage {
+            data: image.copy_data(),
+            format: image.info().format().to_string(),
+        })
+    }
+}
+
+fn check_upload_name(name: &str) -> LuaResult<()> {
+    if name.len() < 2
+        || name.len() > 32
+        || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+    {
+        return Err(LuaError::RuntimeError(
+            "names must be 2 to 32 alphanumeric characters or underscores".into(),
+        ));
+    }
+
+    Ok(())
+}
+
+fn scheduled_event_to_table(state: &Lua, event: ScheduledEvent) -> LuaResult<LuaTable> {
+    let tbl = state.create_table()?;
+
+    tbl.set("id", event.id)?;
+    tbl.set("name", event.name)?;
+    tbl.set("description", event.description)?;
+    tbl.set("start_time", event.start_time)?;
+    tbl.set("end_time", event.end_time)?;
+    tbl.set("location", event.location)?;
+    tbl.set("channel", event.channel_id.map(|id| id.to_short_str()))?;
+    tbl.set("user_count", event.user_count)?;
+
+    Ok(tbl)
+}
+
+// Nesting of styles in rich text given to bot.format
+const MAX_RICH_TEXT_DEPTH: usize = 16;
+
+/// Rich text from lua, a string or a table of children with an optional style, tables without a
+/// style are flattened into their children
+fn lua_to_rich_text(
+    state: &Lua,
+    value: LuaValue,
+    depth: usize,
+    out: &mut Vec<RichText>,
+) -> Result<()> {
+    let tbl = match value {
+        LuaValue::Table(tbl) => tbl,
+        value => {
+            out.push(RichText::Text(String::from_lua(value, state)?));
+            return Ok(());
+        }
+    };
+
+    if depth >= MAX_RICH_TEXT_DEPTH {
+        return Err(FormatError::TooDeep(MAX_RICH_TEXT_DEPTH).into());
+    }
+
+    let children = || -> Result<Vec<RichText>> {
+        let mut children = Vec::new();
+
+        for value in tbl.clone().sequence_values::<LuaValue>() {
+            lua_to_rich_text(state, value?, depth + 1, &mut children)?;
+        }
+
+        Ok(children)
+    };
+
+    // Code is kept as is, it is the concatenation of the string children
+    let code = || -> Result<String> {
+        Ok(tbl
+            .clone()
+            .sequence_values::<String>()
+            .collect::<LuaResult<Vec<_>>>()?
+            .concat())
+    };
+
+    let style: Option<String> = tbl.get("style")?;
+
+    let node = match style.as_deref() {
+        None => {
+            out.extend(children()?);
+            return Ok(());
+        }
+        Some("bold") => RichText::Bold(children()?),
+        Some("italic") => RichText::Italic(children()?),
+        Some("underline") => RichText::Underline(children()?),
+        Some("strike") => RichText::Strikethrough(children()?),
+        Some("code") => RichText::Code(code()?),
+        Some("code_block") => RichText::CodeBlock {
+            code: code()?,
+            language: tbl.get("language")?,
+        },
+        Some("link") => RichText::Link {
+            text: children()?,
+            url: tbl.get("url")?,
+        },
+        Some(style) => return Err(FormatError::UnknownStyle(style.to_string()).into()),
+    };
+
+    out.push(node);
+
+    Ok(())
+}
+
 pub fn bot_flags(state: &Lua, bot_tbl: &LuaTable) -> Result<()> {
     bot_tbl.set("ROLES", ROLES)?;
 
@@ -130,17 +533,79 @@ pub fn bot_flags(state: &Lua, bot_tbl: &LuaTable) -> Result<()> {
     features_tbl.set("React", ServiceFeatures::REACT.bits())?;
     features_tbl.set("Voice", ServiceFeatures::VOICE.bits())?;
     features_tbl.set("Markdown", ServiceFeatures::MARKDOWN.bits())?;
+    features_tbl.set("Components", ServiceFeatures::COMPONENTS.bits())?;
+    features_tbl.set("Modals", ServiceFeatures::MODALS.bits())?;
+    features_tbl.set("ScheduledEvents", ServiceFeatures::SCHEDULED_EVENTS.bits())?;
+    features_tbl.set("Forums", ServiceFeatures::FORUMS.bits())?;
 
     bot_tbl.set("FEATURES", features_tbl)?;
 
+    // bot.format, renders rich text for a channel or in a dialect by name, like "irc" for bridges
+    let format_fn = state.create_function(|state, (target, text): (LuaValue, LuaValue)| {
+        let dialect = match target {
+            LuaValue::UserData(ud) => ud.borrow::<BotChannel>()?.0.service.text_dialect(),
+            target => {
+                let name = String::from_lua(target, state)?;
+
+                TextDialect::from_str(&name)
+                    .ok_or_else(|| LuaError::external(FormatError::UnknownDialect(name)))?
+            }
+        };
+
+        let mut nodes = Vec::new();
+        lua_to_rich_text(state, text, 0, &mut nodes).map_err(LuaError::external)?;
+
+        Ok(render(&nodes, dialect))
+    })?;
+    bot_tbl.set("format", format_fn)?;
+
     Ok(())
 }
 
+// Names resolved to ids are remembered for a while, names can change or move to someone else
+const RESOLVE_CACHE_SIZE: usize = 256;
+const RESOLVE_CACHE_TTL: Duration = Duration::from_secs(5 * 60);
+
+/// Ids of users and channels that were looked up by name, scoped to the channel of the lookup
+struct ResolveCache<T> {
+    entries: StdMutex<LruCache<(Option<ChannelId>, String), (T, Instant)>>,
+}
+
+impl<T: Copy> ResolveCache<T> {
+    fn new() -> ResolveCache<T> {
+        ResolveCache {
+            entries: StdMutex::new(LruCache::new(RESOLVE_CACHE_SIZE)),
+        }
+    }
+
+    fn get(&self, channel_id: Option<ChannelId>, find: &str) -> Option<T> {
+        let mut entries = self.entries.lock().unwrap();
+        let key = (channel_id, find.trim().to_lowercase());
+
+        match entries.get(&key) {
+            Some((id, time)) if time.elapsed() < RESOLVE_CACHE_TTL => Some(*id),
+            Some(_) => {
+                entries.pop(&key);
+                None
+            }
+            None => None,
+        }
+    }
+
+    fn put(&self, channel_id: Option<ChannelId>, find: &str, id: T) {
+        self.entries.lock().unwrap().put(
+            (channel_id, find.trim().to_lowercase()),
+            (id, Instant::now()),
+        );
+    }
+}
+
 pub fn lib_bot(
     state: &Lua,
     bot: &Arc<Bot>,
-    sender: Sender<LuaAsyncCallback>,
-    (sandbox_state, lua_sandbox_replies): (Arc<Mutex<LuaState>>, Arc<LuaSandboxReplies>),
+    sender: AsyncSender,
+    (sandbox_pool, lua_sandbox_replies): (Arc<SandboxPool>, Arc<LuaSandboxReplies>),
+    clock: &LuaClock,
 ) -> Result<()> {
     let bot_tbl = state.create_table()?;
 
@@ -166,133 +631,454 @@ pub fn lib_bot(
     bot_tbl.set("restart_sandbox", bot_restart_sandbox_fn)?;
 
     let bot2 = bot.clone();
-    let sender2 = sender.clone();
-    let delete_lua_replies_fn = state.create_function(move |state, message_id: String| {
-        let ctx = bot2.get_ctx();
+    let bot_sync_scripts_fn = state.create_function(
+        move |_state, (channel, actor): (BotChannel, LuaAnyUserData)| {
+            let ctx = bot2.get_ctx();
+            let channel_id = channel.id();
+            let actor = actor.borrow::<BotUser>()?;
+            let actor_uid = actor.uid();
 
-        let message_id = MessageId::from_str(&message_id)
-            .map_err(|err| LuaError::RuntimeError(err.to_string()))?;
+            if !bot2.permissions().is_owner(actor.db_user()) {
+                return Err(LuaError::ExternalError(Arc::new(PermissionError::NotOwner)));
+            }
 
-        let sandbox_replies = lua_sandbox_replies.clone();
-        let fut = create_lua_future!(
-            state,
-            sender2,
-            (),
-            async move {
-                let mut exists = false;
+            // The bot state gets replaced on success, so report back through the channel directly
+            tokio::spawn(async move {
+                let content = match ctx
+                    .modules()
+                    .lua
+                    .module()
+                    .sync_scripts(Some(actor_uid))
+                    .await
+                {
+                    Ok(version) => format!(
+                        "synced scripts to version {} (commit {})",
+                        version.hash,
+                        version.commit.unwrap_or_default()
+                    ),
+                    Err(err) => format!("error syncing scripts: {}", err.to_string()),
+                };
 
-                // Abort
+                if let Err(err) = ctx
+                    .services()
+                    .send_message(channel_id, content, MessageSettings::default())
+                    .await
                 {
-                    if let Some((abort, _)) = sandbox_replies.lock().await.get_mut(&message_id) {
-                        *abort = true;
-                        exists = true;
-                    }
+                    println!("error sending script sync result: {}", err.to_string());
                 }
+            });
 
-                if exists {
-                    tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+            Ok(())
+        },
+    )?;
+    bot_tbl.set("sync_scripts", bot_sync_scripts_fn)?;
 
-                    let mut err = None;
-                    let mut deleted = false;
+    let bot2 = bot.clone();
+    let bot_reload_scripts_fn = state.create_function(
+        move |_state, (channel, actor): (BotChannel, LuaAnyUserData)| {
+            let ctx = bot2.get_ctx();
+            let channel_id = channel.id();
+            let actor = actor.borrow::<BotUser>()?;
+            let actor_uid = actor.uid();
 
-                    let mut replies = sandbox_replies.lock().await;
-                    if let Some((_, messages)) = replies.get_mut(&message_id) {
-                        for (channel_id, message_id) in messages.drain(..) {
-                            match ctx.services().delete_message(channel_id, message_id).await {
-                                Ok(_) => deleted = true,
-                                Err(e) => {
-                                    err = Some(e);
-                                    break;
-                                }
-                            };
-                        }
-                    }
+            if !bot2.permissions().is_owner(actor.db_user()) {
+                return Err(LuaError::ExternalError(Arc::new(PermissionError::NotOwner)));
+            }
 
-                    if let Some(err) = err {
-                        Err(err)
-                    } else {
-                        Ok(deleted)
-                    }
-                } else {
-                    Ok(false)
+            tokio::spawn(async move {
+                let content = match ctx
+                    .modules()
+                    .lua
+                    .module()
+                    .reload_current_scripts(Some(actor_uid))
+                    .await
+                {
+                    Ok(()) => "reloaded the scripts".to_string(),
+                    Err(err) => format!("error reloading scripts: {}", err.to_string()),
+                };
+
+                if let Err(err) = ctx
+                    .services()
+                    .send_message(channel_id, content, MessageSettings::default())
+                    .await
+                {
+                    println!("error sending script reload result: {}", err.to_string());
                 }
-            },
-            |_state, _data: (), res: Result<bool>| { Ok(res?) }
-        );
+            });
 
-        Ok(fut)
-    })?;
-    bot_tbl.set("delete_lua_replies", delete_lua_replies_fn)?;
+            Ok(())
+        },
+    )?;
+    bot_tbl.set("reload_scripts", bot_reload_scripts_fn)?;
 
     let bot2 = bot.clone();
-    let sender2 = sender.clone();
-    let channel_fn = state.create_function(move |state, channel_id: String| {
-        let bot = bot2.clone();
+    let bot_restart_fn = state.create_function(move |_state, actor: LuaAnyUserData| {
+        let actor = actor.borrow::<BotUser>()?.clone();
 
-        let channel_id = ChannelId::from_str(&channel_id)
-            .map_err(|err| LuaError::RuntimeError(err.to_string()))?;
-        let sender = sender2.clone();
+        if !bot2.permissions().is_owner(actor.db_user()) {
+            return Err(LuaError::ExternalError(Arc::new(PermissionError::NotOwner)));
+        }
 
-        let fut = create_lua_future!(
-            state,
-            sender2,
-            (),
-            bot.get_ctx()
-                .services()
-                .channel(channel_id)
-                .and_then(move |channel| {
-                    async move { BotChannel::from_channel(bot, sender, &channel).await }
-                }),
-            |_state, _data: (), res: Result<BotChannel>| { Ok(res?) }
-        );
+        bot2.events().publish(BotEvent::RestartRequested {
+            actor_uid: Some(actor.uid()),
+        });
 
-        Ok(fut)
+        Ok(())
     })?;
-    bot_tbl.set("channel", channel_fn)?;
+    bot_tbl.set("restart", bot_restart_fn)?;
 
     let bot2 = bot.clone();
     let sender2 = sender.clone();
-    let message_fn =
-        state.create_function(move |state, (channel_id, message_id): (String, String)| {
-            let bot = bot2.clone();
+    let bot_restart_service_fn =
+        state.create_function(move |state, (actor, service): (LuaAnyUserData, String)| {
+            let actor = actor.borrow::<BotUser>()?;
 
-            let channel_id = ChannelId::from_str(&channel_id)
-                .map_err(|err| LuaError::RuntimeError(err.to_string()))?;
-            let message_id = MessageId::from_str(&message_id)
-                .map_err(|err| LuaError::RuntimeError(err.to_string()))?;
-            let sender = sender2.clone();
+            if !bot2.permissions().is_owner(actor.db_user()) {
+                return Err(LuaError::ExternalError(Arc::new(PermissionError::NotOwner)));
+            }
+
+            let bot = bot2.clone();
+            let actor_uid = actor.uid();
 
+            // Resolves once the service is connected again
             let fut = create_lua_future!(
                 state,
                 sender2,
                 (),
-                bot.get_ctx()
-                    .services()
-                    .message(channel_id, message_id)
-                    .and_then(move |message| {
-                        async move { BotMessage::from_msg(bot, sender, &message).await }
-                    }),
-                |_state, _data: (), res: Result<BotMessage>| { Ok(res?) }
+                async move {
+                    bot.get_ctx().services().restart(&service).await?;
+                    bot.db()
+                        .audit(Some(actor_uid), "service.restart", &service, None, None)
+                        .await
+                },
+                |_state, _data: (), res: Result<()>| { Ok(res?) }
             );
 
             Ok(fut)
         })?;
-    bot_tbl.set("message", message_fn)?;
+    bot_tbl.set("restart_service", bot_restart_service_fn)?;
 
     let bot2 = bot.clone();
-    let sender2 = sender.clone();
-    let get_user_fn = state.create_function(move |state, user_id: i64| {
-        let bot = bot2.clone();
+    let bot_rollback_scripts_fn = state.create_function(
+        move |_state, (channel, actor, hash): (BotChannel, LuaAnyUserData, Option<String>)| {
+            let ctx = bot2.get_ctx();
+            let channel_id = channel.id();
+            let actor = actor.borrow::<BotUser>()?;
+            let actor_uid = actor.uid();
 
-        let fut = create_lua_future!(
-            state,
-            sender2,
-            (),
-            async move {
-                let user = bot.db().get_user_from_uid(user_id).await;
-                let ctx = bot.get_ctx();
+            if !bot2.permissions().is_owner(actor.db_user()) {
+                return Err(LuaError::ExternalError(Arc::new(PermissionError::NotOwner)));
+            }
 
-                match user {
+            tokio::spawn(async move {
+                let content = match ctx
+                    .modules()
+                    .lua
+                    .module()
+                    .rollback_scripts(hash, Some(actor_uid))
+                    .await
+                {
+                    Ok(version) => format!("rolled back scripts to version {}", version.hash),
+                    Err(err) => format!("error rolling back scripts: {}", err.to_string()),
+                };
+
+                if let Err(err) = ctx
+                    .services()
+                    .send_message(channel_id, content, MessageSettings::default())
+                    .await
+                {
+                    println!("error sending script rollback result: {}", err.to_string());
+                }
+            });
+
+            Ok(())
+        },
+    )?;
+    bot_tbl.set("rollback_scripts", bot_rollback_scripts_fn)?;
+
+    let bot2 = bot.clone();
+    let bot_script_versions_fn = state.create_function(move |state, (): ()| {
+        let manifest = bot2
+            .get_ctx()
+            .modules()
+            .lua
+            .module()
+            .script_manifest()
+            .map_err(|err| LuaError::RuntimeError(err.to_string()))?;
+
+        let tbl = state.create_table()?;
+
+        for (idx, version) in manifest.versions.into_iter().rev().enumerate() {
+            let version_tbl = state.create_table()?;
+
+            version_tbl.set("active", manifest.active.as_ref() == Some(&version.hash))?;
+            version_tbl.set("hash", version.hash)?;
+            version_tbl.set("commit", version.commit)?;
+            version_tbl.set("timestamp", version.timestamp)?;
+            version_tbl.set(
+                "date",
+                NaiveDateTime::from_timestamp(version.timestamp, 0)
+                    .format("%Y-%m-%d %H:%M")
+                    .to_string(),
+            )?;
+
+            tbl.raw_insert((idx + 1) as i64, version_tbl)?;
+        }
+
+        Ok(tbl)
+    })?;
+    bot_tbl.set("script_versions", bot_script_versions_fn)?;
+
+    let bot2 = bot.clone();
+    let sender2 = sender.clone();
+    let service_status_fn = state.create_function(move |state, (): ()| {
+        let ctx = bot2.get_ctx();
+
+        let fut = create_lua_future!(
+            state,
+            sender2,
+            (),
+            async move { ctx.services().status().await },
+            |state, _data: (), res: Result<Vec<(&'static str, ServiceStatus)>>| {
+                let tbl = state.create_table()?;
+
+                for (idx, (name, status)) in res?.into_iter().enumerate() {
+                    let status_tbl = state.create_table()?;
+
+                    status_tbl.set("name", name)?;
+                    status_tbl.set("uptime", status.uptime.map(|uptime| uptime.as_secs()))?;
+                    status_tbl.set("reconnects", status.reconnects)?;
+                    status_tbl.set("failed_attempts", status.failed_attempts)?;
+                    status_tbl.set("backlog", status.backlog)?;
+                    status_tbl.set("sent", status.sent)?;
+
+                    let shards_tbl = state.create_table()?;
+
+                    for (shard_idx, shard) in status.shards.into_iter().enumerate() {
+                        let shard_tbl = state.create_table()?;
+
+                        shard_tbl.set("id", shard.id)?;
+                        shard_tbl.set("stage", shard.stage)?;
+                        shard_tbl.set(
+                            "latency",
+                            shard.latency.map(|latency| latency.as_millis() as u64),
+                        )?;
+
+                        shards_tbl.raw_insert((shard_idx + 1) as i64, shard_tbl)?;
+                    }
+
+                    status_tbl.set("shards", shards_tbl)?;
+
+                    tbl.raw_insert((idx + 1) as i64, status_tbl)?;
+                }
+
+                Ok(tbl)
+            }
+        );
+
+        Ok(fut)
+    })?;
+    bot_tbl.set("service_status", service_status_fn)?;
+
+    let bot2 = bot.clone();
+    let sender2 = sender.clone();
+    let lua_stats_fn = state.create_function(move |state, (): ()| {
+        let ctx = bot2.get_ctx();
+
+        let fut = create_lua_future!(
+            state,
+            sender2,
+            (),
+            async move { ctx.modules().lua.module().vm_stats().await },
+            |state, _data: (), res: Result<Vec<(&'static str, LuaVmStats)>>| {
+                let tbl = state.create_table()?;
+
+                for (idx, (name, stats)) in res?.into_iter().enumerate() {
+                    let stats_tbl = state.create_table()?;
+
+                    stats_tbl.set("name", name)?;
+                    stats_tbl.set("used_memory", stats.used_memory)?;
+                    stats_tbl.set("registry_size", stats.registry_size)?;
+                    stats_tbl.set("pending_futures", stats.pending_futures)?;
+                    stats_tbl.set("pending_callbacks", stats.pending_callbacks)?;
+
+                    tbl.raw_insert((idx + 1) as i64, stats_tbl)?;
+                }
+
+                Ok(tbl)
+            }
+        );
+
+        Ok(fut)
+    })?;
+    bot_tbl.set("lua_stats", lua_stats_fn)?;
+
+    // bot.stats(actor), runtime counters since the bot started, owner only
+    let bot2 = bot.clone();
+    let sandbox_pool2 = sandbox_pool.clone();
+    let stats_fn = state.create_function(move |state, actor: LuaAnyUserData| {
+        let actor = actor.borrow::<BotUser>()?;
+
+        if !bot2.permissions().is_owner(actor.db_user()) {
+            return Err(LuaError::ExternalError(Arc::new(PermissionError::NotOwner)));
+        }
+
+        let tbl: Table = state.unpack(state.to_value(&bot2.metrics().snapshot())?)?;
+        tbl.set("sandbox_active_workers", sandbox_pool2.active_workers())?;
+        tbl.set("sandbox_abandoned_workers", sandbox_pool2.abandoned_workers())?;
+        tbl.set("uptime", bot2.uptime().as_secs())?;
+
+        Ok(tbl)
+    })?;
+    bot_tbl.set("stats", stats_fn)?;
+
+    // bot.telemetry, what the telemetry module reports and where to, for the disclosure command
+    let bot2 = bot.clone();
+    let telemetry_fn = state.create_function(move |state, (): ()| {
+        let ctx = bot2.get_ctx();
+        let telemetry = ctx.modules().telemetry.module();
+
+        let tbl = state.create_table()?;
+        tbl.set("enabled", telemetry.is_enabled())?;
+        tbl.set("endpoint", telemetry.endpoint())?;
+        tbl.set("interval", telemetry.interval().as_secs())?;
+        tbl.set(
+            "report",
+            serde_json::to_string_pretty(&telemetry.report()).unwrap_or_default(),
+        )?;
+
+        Ok(tbl)
+    })?;
+    bot_tbl.set("telemetry", telemetry_fn)?;
+
+    let bot2 = bot.clone();
+    let sender2 = sender.clone();
+    let delete_lua_replies_fn = state.create_function(move |state, message_id: String| {
+        let ctx = bot2.get_ctx();
+
+        let message_id = MessageId::from_str(&message_id)
+            .map_err(|err| LuaError::RuntimeError(err.to_string()))?;
+
+        let sandbox_replies = lua_sandbox_replies.clone();
+        let fut = create_lua_future!(
+            state,
+            sender2,
+            (),
+            async move {
+                let mut exists = false;
+
+                // Abort
+                {
+                    if let Some((abort, _)) = sandbox_replies.lock().await.get_mut(&message_id) {
+                        *abort = true;
+                        exists = true;
+                    }
+                }
+
+                if exists {
+                    tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+
+                    let mut err = None;
+                    let mut deleted = false;
+
+                    let mut replies = sandbox_replies.lock().await;
+                    if let Some((_, messages)) = replies.get_mut(&message_id) {
+                        for (channel_id, message_id) in messages.drain(..) {
+                            match ctx.services().delete_message(channel_id, message_id).await {
+                                Ok(_) => deleted = true,
+                                Err(e) => {
+                                    err = Some(e);
+                                    break;
+                                }
+                            };
+                        }
+                    }
+
+                    if let Some(err) = err {
+                        Err(err)
+                    } else {
+                        Ok(deleted)
+                    }
+                } else {
+                    Ok(false)
+                }
+            },
+            |_state, _data: (), res: Result<bool>| { Ok(res?) }
+        );
+
+        Ok(fut)
+    })?;
+    bot_tbl.set("delete_lua_replies", delete_lua_replies_fn)?;
+
+    let bot2 = bot.clone();
+    let sender2 = sender.clone();
+    let channel_fn = state.create_function(move |state, channel_id: String| {
+        let bot = bot2.clone();
+
+        let channel_id = ChannelId::from_str(&channel_id)
+            .map_err(|err| LuaError::RuntimeError(err.to_string()))?;
+        let sender = sender2.clone();
+
+        let fut = create_lua_future!(
+            state,
+            sender2,
+            (),
+            bot.get_ctx()
+                .services()
+                .channel(channel_id)
+                .and_then(move |channel| {
+                    async move { BotChannel::from_channel(bot, sender, &*channel).await }
+                }),
+            |_state, _data: (), res: Result<BotChannel>| { Ok(res?) }
+        );
+
+        Ok(fut)
+    })?;
+    bot_tbl.set("channel", channel_fn)?;
+
+    let bot2 = bot.clone();
+    let sender2 = sender.clone();
+    let message_fn =
+        state.create_function(move |state, (channel_id, message_id): (String, String)| {
+            let bot = bot2.clone();
+
+            let channel_id = ChannelId::from_str(&channel_id)
+                .map_err(|err| LuaError::RuntimeError(err.to_string()))?;
+            let message_id = MessageId::from_str(&message_id)
+                .map_err(|err| LuaError::RuntimeError(err.to_string()))?;
+            let sender = sender2.clone();
+
+            let fut = create_lua_future!(
+                state,
+                sender2,
+                (),
+                bot.get_ctx()
+                    .services()
+                    .message(channel_id, message_id)
+                    .and_then(move |message| {
+                        async move { BotMessage::from_msg(bot, sender, &*message).await }
+                    }),
+                |_state, _data: (), res: Result<BotMessage>| { Ok(res?) }
+            );
+
+            Ok(fut)
+        })?;
+    bot_tbl.set("message", message_fn)?;
+
+    let bot2 = bot.clone();
+    let sender2 = sender.clone();
+    let get_user_fn = state.create_function(move |state, user_id: i64| {
+        let bot = bot2.clone();
+
+        let fut = create_lua_future!(
+            state,
+            sender2,
+            (),
+            async move {
+                let user = bot.db().get_user_from_uid(user_id).await;
+                let ctx = bot.get_ctx();
+
+                match user {
                     Ok(user) => {
                         Ok((futures::join!(
                             ctx.services().user(user.service_user_id()),
@@ -304,9 +1090,9 @@ pub fn lib_bot(
             },
             |_state,
              _data: (),
-             res: Result<((Result<Arc<dyn User<impl Service>>>, Result<bool>), DbUser)>| {
+             res: Result<((Result<Arc<dyn UserAbstract>>, Result<bool>), DbUser)>| {
                 let (res, user) = res?;
-                let (service_user, restricted): (Arc<dyn User<_>>, _) = (res.0?, res.1?);
+                let (service_user, restricted) = (res.0?, res.1?);
 
                 Ok(BotUser(
                     Arc::new(BotUserInner {
@@ -356,8 +1142,8 @@ pub fn lib_bot(
                         Err(err) => Err(err),
                     }
                 },
-                |_state, _data: (), res: Result<(Arc<dyn User<impl Service>>, DbUser, bool)>| {
-                    let (service_user, user, restricted): (Arc<dyn User<_>>, _, _) = res?;
+                |_state, _data: (), res: Result<(Arc<dyn UserAbstract>, DbUser, bool)>| {
+                    let (service_user, user, restricted) = res?;
 
                     Ok(BotUser(
                         Arc::new(BotUserInner {
@@ -376,26 +1162,176 @@ pub fn lib_bot(
         })?;
     bot_tbl.set("find_user", find_user_fn)?;
 
+    // bot.resolve_user, takes a mention, an id or a name, names need the channel to search in
     let bot2 = bot.clone();
     let sender2 = sender.clone();
-    let set_role_fn =
-        state.create_function(move |state, (user, role): (LuaAnyUserData, String)| {
+    let user_cache = Arc::new(ResolveCache::<UserId>::new());
+    let resolve_user_fn = state.create_function(
+        move |state, (find, channel): (String, Option<LuaAnyUserData>)| {
+            let bot = bot2.clone();
+            let user_cache = user_cache.clone();
+            let channel_id = match channel {
+                Some(channel) => Some(channel.borrow::<BotChannel>()?.id()),
+                None => None,
+            };
+
+            let fut = create_lua_future!(
+                state,
+                sender2,
+                (),
+                async move {
+                    let ctx = bot.get_ctx();
+
+                    if let Some(user_id) = user_cache.get(channel_id, &find) {
+                        let user = ctx.services().user(user_id).await?;
+                        return BotUser::from_user(bot, &*user).await;
+                    }
+
+                    match channel_id {
+                        Some(channel_id) => {
+                            let user = ctx.services().find_user(channel_id, &find).await?;
+                            user_cache.put(Some(channel_id), &find, user.id());
+
+                            BotUser::from_user(bot, &*user).await
+                        }
+                        None => {
+                            let user_id = UserId::from_str(find.trim())
+                                .map_err(|_| ServiceError::UnknownUser(find.clone()))?;
+
+                            BotUser::from_user(bot, &*ctx.services().user(user_id).await?).await
+                        }
+                    }
+                },
+                |_state, _data: (), res: Result<BotUser>| { Ok(res?) }
+            );
+
+            Ok(fut)
+        },
+    )?;
+    bot_tbl.set("resolve_user", resolve_user_fn)?;
+
+    // bot.resolve_channel, like resolve_user with channel mentions and "#name"
+    let bot2 = bot.clone();
+    let sender2 = sender.clone();
+    let channel_cache = Arc::new(ResolveCache::<ChannelId>::new());
+    let resolve_channel_fn = state.create_function(
+        move |state, (find, channel): (String, Option<LuaAnyUserData>)| {
+            let bot = bot2.clone();
+            let channel_cache = channel_cache.clone();
+            let sender = sender2.clone();
+            let channel_id = match channel {
+                Some(channel) => Some(channel.borrow::<BotChannel>()?.id()),
+                None => None,
+            };
+
+            let fut = create_lua_future!(
+                state,
+                sender2,
+                (),
+                async move {
+                    let ctx = bot.get_ctx();
+
+                    if let Some(found_id) = channel_cache.get(channel_id, &find) {
+                        let channel = ctx.services().channel(found_id).await?;
+                        return BotChannel::from_channel(bot, sender, &*channel).await;
+                    }
+
+                    match channel_id {
+                        Some(channel_id) => {
+                            let channel = ctx.services().find_channel(channel_id, &find).await?;
+                            channel_cache.put(Some(channel_id), &find, channel.id());
+
+                            BotChannel::from_channel(bot, sender, &*channel).await
+                        }
+                        None => {
+                            let found_id = ChannelId::from_str(find.trim())
+                                .map_err(|_| ServiceError::UnknownChannel(find.clone()))?;
+                            let channel = ctx.services().channel(found_id).await?;
+
+                            BotChannel::from_channel(bot, sender, &*channel).await
+                        }
+                    }
+                },
+                |_state, _data: (), res: Result<BotChannel>| { Ok(res?) }
+            );
+
+            Ok(fut)
+        },
+    )?;
+    bot_tbl.set("resolve_channel", resolve_channel_fn)?;
+
+    let bot2 = bot.clone();
+    let sender2 = sender.clone();
+    let set_role_fn = state.create_function(
+        move |state, (user, role, actor): (LuaAnyUserData, String, LuaAnyUserData)| {
             let bot = bot2.clone();
 
             let user = user.borrow::<BotUser>()?.clone();
+            let actor = actor.borrow::<BotUser>()?.clone();
 
             let fut = create_lua_future!(
                 state,
                 sender2,
                 (),
-                bot.db().set_role_for_user(user.uid(), &role),
+                async move {
+                    bot.db().set_role_for_user(user.uid(), &role).await?;
+
+                    bot.db()
+                        .audit(
+                            Some(actor.uid()),
+                            "user.role",
+                            &format!("user:{}", user.uid()),
+                            Some(&user.1.role),
+                            Some(&role),
+                        )
+                        .await
+                },
                 |_state, _data: (), res: Result<()>| { res }
             );
 
             Ok(fut)
-        })?;
+        },
+    )?;
     bot_tbl.set("set_role", set_role_fn)?;
 
+    let bot2 = bot.clone();
+    let sender2 = sender.clone();
+    let set_timezone_fn = state.create_function(
+        move |state, (user, timezone): (LuaAnyUserData, Option<String>)| {
+            let bot = bot2.clone();
+
+            let user = user.borrow::<BotUser>()?.clone();
+
+            // Store the canonical name so lookups don't depend on the user's casing
+            let timezone = match timezone {
+                Some(name) => Some(
+                    parse_timezone(&name)
+                        .ok_or_else(|| {
+                            LuaError::RuntimeError(format!("unknown timezone \"{}\"", name))
+                        })?
+                        .name()
+                        .to_string(),
+                ),
+                None => None,
+            };
+
+            let fut = create_lua_future!(
+                state,
+                sender2,
+                (),
+                async move {
+                    bot.db()
+                        .set_timezone_for_user(user.uid(), timezone.as_deref())
+                        .await
+                },
+                |_state, _data: (), res: Result<()>| { res }
+            );
+
+            Ok(fut)
+        },
+    )?;
+    bot_tbl.set("set_timezone", set_timezone_fn)?;
+
     let bot2 = bot.clone();
     let sender2 = sender.clone();
     let restrict_user_fn = state.create_function(
@@ -409,7 +1345,19 @@ pub fn lib_bot(
                 state,
                 sender2,
                 (),
-                bot.db().restrict_user(user.uid(), restrictor.uid()),
+                async move {
+                    bot.db().restrict_user(user.uid(), restrictor.uid()).await?;
+
+                    bot.db()
+                        .audit(
+                            Some(restrictor.uid()),
+                            "user.restrict",
+                            &format!("user:{}", user.uid()),
+                            None,
+                            None,
+                        )
+                        .await
+                },
                 |_state, _data: (), res: Result<()>| { res }
             );
 
@@ -420,22 +1368,374 @@ pub fn lib_bot(
 
     let bot2 = bot.clone();
     let sender2 = sender.clone();
-    let unrestrict_user_fn = state.create_function(move |state, (user,): (LuaAnyUserData,)| {
+    let unrestrict_user_fn = state.create_function(
+        move |state, (user, actor): (LuaAnyUserData, LuaAnyUserData)| {
+            let bot = bot2.clone();
+
+            let user = user.borrow::<BotUser>()?.clone();
+            let actor = actor.borrow::<BotUser>()?.clone();
+
+            let fut = create_lua_future!(
+                state,
+                sender2,
+                (),
+                async move {
+                    bot.db().unrestrict_user(user.uid()).await?;
+
+                    bot.db()
+                        .audit(
+                            Some(actor.uid()),
+                            "user.unrestrict",
+                            &format!("user:{}", user.uid()),
+                            None,
+                            None,
+                        )
+                        .await
+                },
+                |_state, _data: (), res: Result<()>| { res }
+            );
+
+            Ok(fut)
+        },
+    )?;
+    bot_tbl.set("unrestrict_user", unrestrict_user_fn)?;
+
+    let bot2 = bot.clone();
+    let sender2 = sender.clone();
+    let blacklist_user_fn = state.create_function(
+        move |state,
+              (user, server, actor, reason, duration): (
+            LuaAnyUserData,
+            Option<LuaAnyUserData>,
+            LuaAnyUserData,
+            Option<String>,
+            Option<u64>,
+        )| {
+            let bot = bot2.clone();
+
+            let user = user.borrow::<BotUser>()?.clone();
+            let server_id = match server {
+                Some(server) => Some(server.borrow::<BotServer>()?.id()),
+                None => None,
+            };
+            let actor = actor.borrow::<BotUser>()?.clone();
+            let expire_time = duration.map(|secs| Utc::now().timestamp() + secs as i64);
+
+            let fut = create_lua_future!(
+                state,
+                sender2,
+                (),
+                async move {
+                    let sid = match server_id {
+                        Some(server_id) => Some(bot.db().get_sid(server_id).await?),
+                        None => None,
+                    };
+
+                    bot.db()
+                        .blacklist_user(user.uid(), sid, actor.uid(), reason.as_deref(), expire_time)
+                        .await
+                },
+                |_state, _data: (), res: Result<()>| { res }
+            );
+
+            Ok(fut)
+        },
+    )?;
+    bot_tbl.set("blacklist_user", blacklist_user_fn)?;
+
+    let bot2 = bot.clone();
+    let sender2 = sender.clone();
+    let unblacklist_user_fn = state.create_function(
+        move |state, (user, server, actor): (LuaAnyUserData, Option<LuaAnyUserData>, LuaAnyUserData)| {
+            let bot = bot2.clone();
+
+            let user = user.borrow::<BotUser>()?.clone();
+            let server_id = match server {
+                Some(server) => Some(server.borrow::<BotServer>()?.id()),
+                None => None,
+            };
+            let actor = actor.borrow::<BotUser>()?.clone();
+
+            let fut = create_lua_future!(
+                state,
+                sender2,
+                (),
+                async move {
+                    let sid = match server_id {
+                        Some(server_id) => Some(bot.db().get_sid(server_id).await?),
+                        None => None,
+                    };
+
+                    bot.db().unblacklist_user(user.uid(), sid, actor.uid()).await
+                },
+                |_state, _data: (), res: Result<bool>| { res }
+            );
+
+            Ok(fut)
+        },
+    )?;
+    bot_tbl.set("unblacklist_user", unblacklist_user_fn)?;
+
+    let bot2 = bot.clone();
+    let sender2 = sender.clone();
+    let list_blacklist_fn =
+        state.create_function(move |state, (server,): (Option<LuaAnyUserData>,)| {
+            let bot = bot2.clone();
+
+            let server_id = match server {
+                Some(server) => Some(server.borrow::<BotServer>()?.id()),
+                None => None,
+            };
+
+            let fut = create_lua_future!(
+                state,
+                sender2,
+                (),
+                async move {
+                    let sid = match server_id {
+                        Some(server_id) => Some(bot.db().get_sid(server_id).await?),
+                        None => None,
+                    };
+
+                    bot.db().list_blacklist(sid).await
+                },
+                |state, _data: (), res: Result<Vec<BlacklistEntry>>| {
+                    let entries_tbl = state.create_table()?;
+                    let now = Utc::now().timestamp();
+
+                    for (i, entry) in res?.into_iter().enumerate() {
+                        let entry_tbl = state.create_table()?;
+                        entry_tbl.set("uid", entry.uid)?;
+                        entry_tbl.set("added_by", entry.added_by)?;
+                        entry_tbl.set("reason", entry.reason)?;
+                        entry_tbl.set("expire_time", entry.expire_time)?;
+                        entry_tbl.set("expires_in", entry.expire_time.map(|time| time - now))?;
+                        entries_tbl.set(i + 1, entry_tbl)?;
+                    }
+
+                    Ok(entries_tbl)
+                }
+            );
+
+            Ok(fut)
+        })?;
+    bot_tbl.set("list_blacklist", list_blacklist_fn)?;
+
+    let bot2 = bot.clone();
+    let sender2 = sender.clone();
+    let scheduled_events_fn = state.create_function(move |state, server: LuaAnyUserData| {
+        let bot = bot2.clone();
+        let server_id = server.borrow::<BotServer>()?.id();
+
+        let fut = create_lua_future!(
+            state,
+            sender2,
+            (),
+            async move { bot.get_ctx().services().scheduled_events(server_id).await },
+            |state, _data: (), res: Result<Vec<ScheduledEvent>>| {
+                let events_tbl = state.create_table()?;
+
+                for (i, event) in res?.into_iter().enumerate() {
+                    events_tbl.set(i + 1, scheduled_event_to_table(state, event)?)?;
+                }
+
+                Ok(events_tbl)
+            }
+        );
+
+        Ok(fut)
+    })?;
+    bot_tbl.set("scheduled_events", scheduled_events_fn)?;
+
+    let bot2 = bot.clone();
+    let sender2 = sender.clone();
+    let create_scheduled_event_fn =
+        state.create_function(move |state, (server, tbl): (LuaAnyUserData, LuaTable)| {
+            let bot = bot2.clone();
+            let server_id = server.borrow::<BotServer>()?.id();
+
+            let channel_id = match tbl.get::<_, Option<String>>("channel")? {
+                Some(channel_id) => Some(
+                    ChannelId::from_str(&channel_id)
+                        .map_err(|err| LuaError::RuntimeError(err.to_string()))?,
+                ),
+                None => None,
+            };
+            let event = ScheduledEventSettings {
+                name: tbl.get("name")?,
+                description: tbl.get("description")?,
+                start_time: tbl.get("start_time")?,
+                end_time: tbl.get("end_time")?,
+                location: tbl.get("location")?,
+                channel_id,
+            };
+
+            if event.channel_id.is_none() && event.location.is_none() {
+                return Err(LuaError::RuntimeError(
+                    "events need either a channel or a location".into(),
+                ));
+            }
+
+            let fut = create_lua_future!(
+                state,
+                sender2,
+                (),
+                async move {
+                    bot.get_ctx()
+                        .services()
+                        .create_scheduled_event(server_id, event)
+                        .await
+                },
+                |state, _data: (), res: Result<ScheduledEvent>| {
+                    Ok(scheduled_event_to_table(state, res?)?)
+                }
+            );
+
+            Ok(fut)
+        })?;
+    bot_tbl.set("create_scheduled_event", create_scheduled_event_fn)?;
+
+    let bot2 = bot.clone();
+    let sender2 = sender.clone();
+    let upload_emoji_fn = state.create_function(
+        move |state, (server, name, image): (LuaAnyUserData, String, LuaValue)| {
+            if get_sandbox_state(state).is_some() {
+                return Err(LuaError::RuntimeError(
+                    "emoji can't be uploaded from the sandbox".into(),
+                ));
+            }
+
+            let bot = bot2.clone();
+            let sender = sender2.clone();
+            let server_id = server.borrow::<BotServer>()?.id();
+            let source = UploadSource::from_lua(image)?;
+            check_upload_name(&name)?;
+
+            let fut = create_lua_future!(
+                state,
+                sender2,
+                (),
+                async move {
+                    let image = source.resolve(sender, &EMOJI_LIMITS).await?;
+
+                    bot.get_ctx()
+                        .services()
+                        .create_emoji(server_id, name, image)
+                        .await
+                },
+                |_state, _data: (), res: Result<String>| { res }
+            );
+
+            Ok(fut)
+        },
+    )?;
+    bot_tbl.set("upload_emoji", upload_emoji_fn)?;
+
+    let bot2 = bot.clone();
+    let sender2 = sender.clone();
+    let upload_sticker_fn = state.create_function(
+        move |state,
+              (server, name, image, options): (
+            LuaAnyUserData,
+            String,
+            LuaValue,
+            Option<LuaTable>,
+        )| {
+            if get_sandbox_state(state).is_some() {
+                return Err(LuaError::RuntimeError(
+                    "stickers can't be uploaded from the sandbox".into(),
+                ));
+            }
+
+            let bot = bot2.clone();
+            let sender = sender2.clone();
+            let server_id = server.borrow::<BotServer>()?.id();
+            let source = UploadSource::from_lua(image)?;
+            check_upload_name(&name)?;
+
+            let (description, tags): (Option<String>, Option<String>) = match options {
+                Some(options) => (options.get("description")?, options.get("tags")?),
+                None => (None, None),
+            };
+
+            let fut = create_lua_future!(
+                state,
+                sender2,
+                (),
+                async move {
+                    let image = source.resolve(sender, &STICKER_LIMITS).await?;
+                    let sticker = StickerSettings {
+                        tags: tags.unwrap_or_else(|| name.clone()),
+                        description: description.unwrap_or_default(),
+                        name,
+                        image,
+                    };
+
+                    bot.get_ctx()
+                        .services()
+                        .create_sticker(server_id, sticker)
+                        .await
+                },
+                |_state, _data: (), res: Result<String>| { res }
+            );
+
+            Ok(fut)
+        },
+    )?;
+    bot_tbl.set("upload_sticker", upload_sticker_fn)?;
+
+    let bot2 = bot.clone();
+    let sender2 = sender.clone();
+    let audit_log_fn = state.create_function(move |state, filter: Option<LuaTable>| {
         let bot = bot2.clone();
 
-        let user = user.borrow::<BotUser>()?.clone();
+        let (action, actor, scope, limit) = match filter {
+            Some(filter) => (
+                filter.get::<_, Option<String>>("action")?,
+                filter.get::<_, Option<Uid>>("actor")?,
+                filter.get::<_, Option<String>>("scope")?,
+                filter.get::<_, Option<i64>>("limit")?.unwrap_or(10),
+            ),
+            None => (None, None, None, 10),
+        };
 
         let fut = create_lua_future!(
             state,
             sender2,
             (),
-            bot.db().unrestrict_user(user.uid()),
-            |_state, _data: (), res: Result<()>| { res }
+            async move {
+                bot.db()
+                    .list_audit_log(action.as_deref(), actor, scope.as_deref(), limit.clamp(1, 100))
+                    .await
+            },
+            |state, _data: (), res: Result<Vec<AuditEntry>>| {
+                let entries_tbl = state.create_table()?;
+
+                for (i, entry) in res?.into_iter().enumerate() {
+                    let entry_tbl = state.create_table()?;
+                    entry_tbl.set("id", entry.id)?;
+                    entry_tbl.set("actor", entry.actor_uid)?;
+                    entry_tbl.set("action", entry.action)?;
+                    entry_tbl.set("scope", entry.scope)?;
+                    entry_tbl.set("before", entry.before)?;
+                    entry_tbl.set("after", entry.after)?;
+                    entry_tbl.set("timestamp", entry.timestamp)?;
+                    entry_tbl.set(
+                        "date",
+                        NaiveDateTime::from_timestamp(entry.timestamp, 0)
+                            .format("%Y-%m-%d %H:%M")
+                            .to_string(),
+                    )?;
+                    entries_tbl.set(i + 1, entry_tbl)?;
+                }
+
+                Ok(entries_tbl)
+            }
         );
 
         Ok(fut)
     })?;
-    bot_tbl.set("unrestrict_user", unrestrict_user_fn)?;
+    bot_tbl.set("audit_log", audit_log_fn)?;
 
     let bot2 = bot.clone();
     let list_settings_fn = state.create_function(move |state, (module,): (String,)| {
@@ -453,6 +1753,10 @@ pub fn lib_bot(
 
             info_tbl.set("name", info.name)?;
             info_tbl.set("help", info.help)?;
+            info_tbl.set("kind", info.kind)?;
+            info_tbl.set("default", info.default)?;
+            info_tbl.set("constraints", info.constraints)?;
+            info_tbl.set("server_override", info.server_override)?;
 
             tbl.raw_insert((idx + 1) as i64, info_tbl)?;
         }
@@ -465,12 +1769,13 @@ pub fn lib_bot(
     let sender2 = sender.clone();
     let set_setting_fn = state.create_function(
         move |state,
-              (msg, server, module, setting, value): (
+              (msg, server, module, setting, value, duration): (
             LuaAnyUserData,
             bool,
             String,
             String,
             String,
+            Option<i64>,
         )| {
             let bot = bot2.clone();
 
@@ -483,21 +1788,59 @@ pub fn lib_bot(
                 }
             };
 
+            if matches!(duration, Some(duration) if duration <= 0) {
+                return Ok(LuaMultiValue::from_vec(vec![
+                    "the duration has to be above zero".to_lua(state)?,
+                ]));
+            }
+
             let msg = msg.borrow::<BotMessage>()?.clone();
 
             let fut = create_lua_future!(
                 state,
                 sender2,
                 (),
-                module_settings.set_setting(
-                    if server {
+                async move {
+                    let caller = Caller::new(
+                        msg.author().db_user().clone(),
+                        msg.channel().server().id(),
+                        msg.channel().id(),
+                    );
+
+                    let key = format!("{}/{}", module, setting);
+                    let ctx = if server {
                         SettingContext::Server(msg.channel().server().id())
                     } else {
                         SettingContext::Channel(msg.channel().id())
-                    },
-                    &setting,
-                    &value,
-                ),
+                    };
+                    let before = ctx.raw_value(&bot, &key).await?;
+
+                    // Secret settings are stored encrypted, so the audit log never sees the input
+                    let stored = module_settings
+                        .set_setting(ctx, &caller, &setting, &value)
+                        .await?;
+
+                    bot.db()
+                        .audit(
+                            Some(msg.author().uid()),
+                            &format!("setting.{}", key),
+                            &ctx.scope(),
+                            before.as_deref(),
+                            Some(&stored),
+                        )
+                        .await?;
+
+                    // Temporary values remember the value to revert to, permanent ones drop it
+                    settings::set_expiry(
+                        &bot,
+                        &ctx,
+                        &key,
+                        before.as_deref(),
+                        duration,
+                        msg.channel().id(),
+                    )
+                    .await
+                },
                 |_state, _data: (), res: Result<()>| { res }
             );
 
@@ -509,81 +1852,382 @@ pub fn lib_bot(
     )?;
     bot_tbl.set("set_setting", set_setting_fn)?;
 
+    let bot2 = bot.clone();
+    let is_owner_fn = state.create_function(move |_state, user: LuaAnyUserData| {
+        let user = user.borrow::<BotUser>()?;
+
+        Ok(bot2.permissions().is_owner(user.db_user()))
+    })?;
+    bot_tbl.set("is_owner", is_owner_fn)?;
+
+    let bot2 = bot.clone();
     let sender2 = sender.clone();
-    let run_sandboxed_lua_fn = state.create_function(
+    let is_admin_fn = state.create_function(
+        move |state, (user, channel): (LuaAnyUserData, Option<BotChannel>)| {
+            let bot = bot2.clone();
+            let user = user.borrow::<BotUser>()?.clone();
+
+            let fut = create_lua_future!(
+                state,
+                sender2,
+                (),
+                async move {
+                    let location = channel.map(|channel| (channel.server().id(), channel.id()));
+
+                    bot.permissions()
+                        .is_admin(&bot, user.db_user(), location)
+                        .await
+                },
+                |_state, _data: (), res: Result<bool>| { Ok(res?) }
+            );
+
+            Ok(fut)
+        },
+    )?;
+    bot_tbl.set("is_admin", is_admin_fn)?;
+
+    let bot2 = bot.clone();
+    let sender2 = sender.clone();
+    let has_permission_fn = state.create_function(
+        move |state, (user, node, channel): (LuaAnyUserData, String, Option<BotChannel>)| {
+            let bot = bot2.clone();
+            let user = user.borrow::<BotUser>()?.clone();
+
+            let fut = create_lua_future!(
+                state,
+                sender2,
+                (),
+                async move {
+                    let location = channel.map(|channel| (channel.server().id(), channel.id()));
+
+                    bot.permissions()
+                        .has_permission(&bot, user.db_user(), &node, location)
+                        .await
+                },
+                |_state, _data: (), res: Result<bool>| { Ok(res?) }
+            );
+
+            Ok(fut)
+        },
+    )?;
+    bot_tbl.set("has_permission", has_permission_fn)?;
+
+    let bot2 = bot.clone();
+    let sender2 = sender.clone();
+    let add_reaction_role_fn = state.create_function(
         move |state,
-              (user, msg, code, env): (
-            LuaAnyUserData,
+              (msg, message_id, emoji, role, exclusive): (
             LuaAnyUserData,
             String,
-            Table
+            String,
+            String,
+            Option<bool>,
         )| {
-            let sandbox_state = sandbox_state.clone();
+            let bot = bot2.clone();
+            let msg = msg.borrow::<BotMessage>()?.clone();
+
+            let message_id = MessageId::from_str(&message_id)
+                .map_err(|err| LuaError::RuntimeError(err.to_string()))?;
+            let emoji = resolve_shortcode(&emoji);
+
+            let fut = create_lua_future!(
+                state,
+                sender2,
+                (),
+                async move {
+                    let location = (msg.channel().server().id(), msg.channel().id());
+
+                    if !bot
+                        .permissions()
+                        .is_admin(&bot, msg.author().db_user(), Some(location))
+                        .await?
+                    {
+                        return Err(PermissionError::NotAdmin.into());
+                    }
+
+                    bot.get_ctx()
+                        .modules()
+                        .reaction_roles
+                        .module()
+                        .add(
+                            msg.channel().id(),
+                            message_id,
+                            &emoji,
+                            &role,
+                            exclusive.unwrap_or(false),
+                            msg.author().uid(),
+                        )
+                        .await
+                },
+                |_state, _data: (), res: Result<ServerRole>| { Ok(res?.name) }
+            );
+
+            Ok(fut)
+        },
+    )?;
+    bot_tbl.set("add_reaction_role", add_reaction_role_fn)?;
 
-            let _user = user.borrow::<BotUser>()?.clone();
+    let bot2 = bot.clone();
+    let sender2 = sender.clone();
+    let remove_reaction_role_fn = state.create_function(
+        move |state, (msg, message_id, emoji): (LuaAnyUserData, String, String)| {
+            let bot = bot2.clone();
             let msg = msg.borrow::<BotMessage>()?.clone();
 
-            let env_encoded: String = serde_json::to_string(&LuaValue::Table(env))
-                .map_err(|err| LuaError::ExternalError(Arc::new(err)))?;
+            let message_id = MessageId::from_str(&message_id)
+                .map_err(|err| LuaError::RuntimeError(err.to_string()))?;
+            let emoji = resolve_shortcode(&emoji);
 
             let fut = create_lua_future!(
                 state,
                 sender2,
                 (),
                 async move {
-                    let lua_state = sandbox_state.lock_arc().await;
+                    let location = (msg.channel().server().id(), msg.channel().id());
+
+                    if !bot
+                        .permissions()
+                        .is_admin(&bot, msg.author().db_user(), Some(location))
+                        .await?
+                    {
+                        return Err(PermissionError::NotAdmin.into());
+                    }
 
-                    let (_sandbox_state, recv) = match lua_state.run_sandboxed(&code, msg, Some(env_encoded)) {
-                        Ok(recv) => recv,
-                        Err(err) => {
-                            return Err(anyhow::anyhow!(err.to_string()));
-                        }
+                    bot.get_ctx()
+                        .modules()
+                        .reaction_roles
+                        .module()
+                        .remove(message_id, &emoji, msg.author().uid())
+                        .await
+                },
+                |_state, _data: (), res: Result<Option<ReactionRole>>| {
+                    Ok(res?.map(|role| role.role_id))
+                }
+            );
+
+            Ok(fut)
+        },
+    )?;
+    bot_tbl.set("remove_reaction_role", remove_reaction_role_fn)?;
+
+    let bot2 = bot.clone();
+    let sender2 = sender.clone();
+    let reaction_roles_fn = state.create_function(move |state, msg: LuaAnyUserData| {
+        let bot = bot2.clone();
+        let msg = msg.borrow::<BotMessage>()?.clone();
+
+        let fut = create_lua_future!(
+            state,
+            sender2,
+            (),
+            async move {
+                bot.get_ctx()
+                    .modules()
+                    .reaction_roles
+                    .module()
+                    .list(msg.channel().server().id())
+                    .await
+            },
+            |state, _data: (), res: Result<Vec<ReactionRole>>| {
+                let tbl = state.create_table()?;
+
+                for (idx, role) in res?.into_iter().enumerate() {
+                    let role_tbl = state.create_table()?;
+
+                    role_tbl.set("message_id", role.message_id)?;
+                    role_tbl.set("channel_id", role.channel_id)?;
+                    role_tbl.set("emoji", role.emoji)?;
+                    role_tbl.set("role_id", role.role_id)?;
+                    role_tbl.set("exclusive", role.exclusive)?;
+
+                    tbl.raw_insert((idx + 1) as i64, role_tbl)?;
+                }
+
+                Ok(tbl)
+            }
+        );
+
+        Ok(fut)
+    })?;
+    bot_tbl.set("reaction_roles", reaction_roles_fn)?;
+
+    // Record what a sandbox run gets from outside of the sandbox, so it can be replayed later
+    let bot2 = bot.clone();
+    let sender2 = sender.clone();
+    let sandbox_pool2 = sandbox_pool.clone();
+    let record_sandbox_fn =
+        state.create_function(move |state, (msg, code): (LuaAnyUserData, String)| {
+            let bot = bot2.clone();
+            let sandbox_pool = sandbox_pool2.clone();
+            let msg = msg.borrow::<BotMessage>()?.clone();
+            let code = trim_codeblocks(msg.0.service, code);
+
+            let fut = create_lua_future!(
+                state,
+                sender2,
+                (),
+                async move {
+                    let trace = TraceId::new();
+                    let tape = Arc::new(SandboxTape::record());
+                    let mut recording = SandboxRecording {
+                        trace: trace.to_string(),
+                        code,
+                        env: "{}".into(),
+                        random_seed: tape.random_seed,
+                        author_id: msg.author().id().to_short_str(),
+                        channel_id: msg.channel().id().to_short_str(),
+                        events: Vec::new(),
+                        output: String::new(),
+                        error: None,
+                        timestamp: Utc::now().timestamp(),
                     };
 
-                    drop(lua_state);
+                    let owner = SandboxOwner::User(msg.author().uid());
+                    let res = run_sandboxed_output(
+                        &bot,
+                        &sandbox_pool,
+                        &recording.code,
+                        msg,
+                        recording.env.clone(),
+                        owner,
+                        Some(tape.clone()),
+                        trace,
+                    )
+                    .await;
+
+                    recording.events = tape.events();
 
-                    let mut out_str = String::new();
-                    let start = Instant::now();
+                    match res {
+                        Ok(output) => recording.output = output,
+                        Err(err) => recording.error = Some(err.to_string()),
+                    }
 
-                    loop {
-                        if start.elapsed() > Duration::from_secs(2) {
-                            break;
-                        }
+                    save_recording(&bot, &recording).await?;
+
+                    Ok(recording)
+                },
+                |state, _data: (), res: Result<SandboxRecording>| {
+                    let recording = res?;
 
-                        match recv.try_recv() {
-                            Ok(out) => match out {
-                                SandboxMsg::Out(o) => {
-                                    if !out_str.is_empty() {
-                                        out_str.push('\n');
-                                    }
+                    let tbl = state.create_table()?;
+                    tbl.set("trace", recording.trace)?;
+                    tbl.set("output", recording.output)?;
+                    tbl.set("error", recording.error)?;
+                    tbl.set("events", recording.events.len())?;
 
-                                    out_str.push_str(&o);
-                                }
-                                SandboxMsg::Error(err) => {
-                                    return Err(anyhow::anyhow!(err));
-                                }
-                                SandboxMsg::Terminated(reason) => {
-                                    match reason {
-                                        SandboxTerminationReason::Done => {
-                                            break
-                                        },
-                                        SandboxTerminationReason::ExecutionQuota => {
-                                            return Err(anyhow::anyhow!("Execution quota exceeded, terminated execution"));
-                                        }
-                                        SandboxTerminationReason::TimeLimit => {
-                                            return Err(anyhow::anyhow!("Execution time limit reached, terminated execution"));
-                                        }
-                                    }
-                                }
-                            },
-                            Err(TryRecvError::Empty) => {
-                                tokio::time::sleep(Duration::from_millis(50)).await;
-                            }
-                            Err(TryRecvError::Disconnected) => break,
-                        }
+                    Ok(tbl)
+                }
+            );
+
+            Ok(fut)
+        })?;
+    bot_tbl.set("record_sandbox", record_sandbox_fn)?;
+
+    // Run a recording again against the recorded responses
+    let bot2 = bot.clone();
+    let sender2 = sender.clone();
+    let sandbox_pool2 = sandbox_pool.clone();
+    let replay_sandbox_fn =
+        state.create_function(move |state, (msg, trace): (LuaAnyUserData, String)| {
+            let bot = bot2.clone();
+            let sandbox_pool = sandbox_pool2.clone();
+            let msg = msg.borrow::<BotMessage>()?.clone();
+
+            let fut = create_lua_future!(
+                state,
+                sender2,
+                (),
+                async move {
+                    let recording = load_recording(&bot, &trace).await?;
+                    let tape = Arc::new(SandboxTape::replay(&recording));
+
+                    let owner = SandboxOwner::User(msg.author().uid());
+                    let res = run_sandboxed_output(
+                        &bot,
+                        &sandbox_pool,
+                        &recording.code,
+                        msg,
+                        recording.env.clone(),
+                        owner,
+                        Some(tape),
+                        TraceId::new(),
+                    )
+                    .await;
+
+                    Ok((recording, res.map_err(|err| err.to_string())))
+                },
+                |state, _data: (), res: Result<(SandboxRecording, Result<String, String>)>| {
+                    let (recording, res) = res?;
+                    let matches = match &res {
+                        Ok(output) => recording.error.is_none() && *output == recording.output,
+                        Err(err) => recording.error.as_ref() == Some(err),
+                    };
+
+                    let tbl = state.create_table()?;
+                    tbl.set("trace", recording.trace)?;
+                    tbl.set("code", recording.code)?;
+                    tbl.set("recorded_output", recording.output)?;
+                    tbl.set("recorded_error", recording.error)?;
+
+                    match res {
+                        Ok(output) => tbl.set("output", output)?,
+                        Err(err) => tbl.set("error", err)?,
                     }
 
-                    Ok(out_str)
+                    tbl.set("matches", matches)?;
+
+                    Ok(tbl)
+                }
+            );
+
+            Ok(fut)
+        })?;
+    bot_tbl.set("replay_sandbox", replay_sandbox_fn)?;
+
+    // Limit sandboxed runs per quota owner, so a popular lua tag is charged to whoever owns it
+    let sandboxed_lua_limiter: Arc<RateLimiter<Uid, DefaultKeyedStateStore<Uid>, LuaClock>> =
+        Arc::new(RateLimiter::new(
+            Quota::per_minute(NonZeroU32::new(SANDBOXED_LUA_RUNS_PER_MINUTE).unwrap()),
+            DefaultKeyedStateStore::default(),
+            clock,
+        ));
+
+    let bot2 = bot.clone();
+    let sender2 = sender.clone();
+    let run_sandboxed_lua_fn = state.create_function(
+        move |state, (user, msg, code, env): (LuaAnyUserData, LuaAnyUserData, String, Table)| {
+            let bot = bot2.clone();
+            let sandbox_pool = sandbox_pool.clone();
+
+            let user = user.borrow::<BotUser>()?.clone();
+            let msg = msg.borrow::<BotMessage>()?.clone();
+
+            if sandboxed_lua_limiter.check_key(&user.uid()).is_err() {
+                return Err(LuaError::ExternalError(Arc::new(
+                    SandboxError::OwnerQuotaExceeded,
+                )));
+            }
+
+            let env_encoded: String = serde_json::to_string(&LuaValue::Table(env))
+                .map_err(|err| LuaError::ExternalError(Arc::new(err)))?;
+            let trace = current_trace(state).unwrap_or_else(TraceId::new);
+
+            let fut = create_lua_future!(
+                state,
+                sender2,
+                (),
+                async move {
+                    run_sandboxed_output(
+                        &bot,
+                        &sandbox_pool,
+                        &code,
+                        msg,
+                        env_encoded,
+                        SandboxOwner::User(user.uid()),
+                        None,
+                        trace,
+                    )
+                    .await
                 },
                 |state, _data: (), res: Result<String>| {
                     match res {
@@ -598,6 +2242,93 @@ pub fn lib_bot(
     )?;
     bot_tbl.set("run_sandboxed_lua", run_sandboxed_lua_fn)?;
 
+    let bot2 = bot.clone();
+    let sender2 = sender.clone();
+    let sandbox_quota_fn = state.create_function(
+        move |state, (user, channel): (LuaAnyUserData, BotChannel)| {
+            let bot = bot2.clone();
+            let user = user.borrow::<BotUser>()?.clone();
+            let uid = user.uid();
+
+            let fut = create_lua_future!(
+                state,
+                sender2,
+                (),
+                async move {
+                    let quota = bot
+                        .get_ctx()
+                        .modules()
+                        .lua
+                        .module()
+                        .sandbox_quota(uid, user.id(), channel.server().id(), channel.id())
+                        .await?;
+
+                    let env_path = sandbox_env_path(&bot, uid);
+                    let storage_used = match tokio::fs::metadata(env_path).await {
+                        Ok(metadata) => metadata.len(),
+                        Err(err) if err.kind() == std::io::ErrorKind::NotFound => 0,
+                        Err(err) => return Err(err.into()),
+                    };
+
+                    Ok((quota, storage_used))
+                },
+                |state, _data: (), res: Result<(SandboxQuota, u64)>| {
+                    let (quota, storage_used) = res?;
+
+                    // A run in flight reports what's left of its budget, otherwise what a new run gets
+                    let default_limits = SandboxLimits::default();
+                    let (limits, http_calls) = match &quota.run {
+                        Some(run) => (&run.limits, run.http_calls_remaining()),
+                        None => {
+                            let calls_left = default_limits.http_calls_left.load(Ordering::Relaxed);
+                            let http_calls = match quota.http_quota_left {
+                                Some(quota_left) => calls_left.min(quota_left as u64),
+                                None => calls_left,
+                            };
+
+                            (&default_limits, http_calls)
+                        }
+                    };
+
+                    let runs = state.create_table()?;
+                    runs.set("left", quota.runs_left)?;
+                    runs.set("limit", quota.limit.count)?;
+                    runs.set("period", quota.limit.period.as_secs())?;
+                    runs.set("timeout", quota.timeout.map(|timeout| timeout.as_secs()))?;
+
+                    let per_run = state.create_table()?;
+                    per_run.set("running", quota.run.is_some())?;
+                    per_run.set("lines", limits.lines_left())?;
+                    per_run.set("characters", limits.characters_left())?;
+                    per_run.set("http_calls", http_calls)?;
+                    per_run.set("http_bytes", limits.http_bytes_left.load(Ordering::Relaxed))?;
+                    per_run.set("messages", limits.messages_left.load(Ordering::Relaxed))?;
+                    per_run.set("images", limits.images_left.load(Ordering::Relaxed))?;
+                    per_run.set("env_saves", limits.env_saves_left.load(Ordering::Relaxed))?;
+                    per_run.set(
+                        "attachment_downloads",
+                        limits.attachment_downloads_left.load(Ordering::Relaxed),
+                    )?;
+                    per_run.set("instructions", limits.instructions)?;
+
+                    let storage = state.create_table()?;
+                    storage.set("used", storage_used)?;
+                    storage.set("limit", MAX_SAVED_ENV_SIZE)?;
+
+                    let tbl = state.create_table()?;
+                    tbl.set("runs", runs)?;
+                    tbl.set("per_run", per_run)?;
+                    tbl.set("storage", storage)?;
+
+                    Ok(tbl)
+                }
+            );
+
+            Ok(fut)
+        },
+    )?;
+    bot_tbl.set("sandbox_quota", sandbox_quota_fn)?;
+
     let bot2 = bot.clone();
     let sender2 = sender.clone();
     let get_data_fn = state.create_function(move |state, (key,): (String,)| {
@@ -661,43 +2392,213 @@ pub fn lib_bot(
     Ok(())
 }
 
+fn segments_table<'lua>(
+    state: &'lua Lua,
+    segments: &[ContentSegment],
+) -> LuaResult<LuaTable<'lua>> {
+    let tbl = state.create_table()?;
+
+    for (i, segment) in segments.iter().enumerate() {
+        let segment_tbl = state.create_table()?;
+
+        match segment {
+            ContentSegment::Text(text) => {
+                segment_tbl.set("kind", "text")?;
+                segment_tbl.set("text", text.as_str())?;
+            }
+            ContentSegment::UserMention(id) => {
+                segment_tbl.set("kind", "user")?;
+                segment_tbl.set("id", id.to_short_str())?;
+            }
+            ContentSegment::ChannelMention(id) => {
+                segment_tbl.set("kind", "channel")?;
+                segment_tbl.set("id", id.to_short_str())?;
+            }
+            ContentSegment::Emoji { name, id, animated } => {
+                segment_tbl.set("kind", "emoji")?;
+                segment_tbl.set("name", name.as_str())?;
+                segment_tbl.set("id", id.as_str())?;
+                segment_tbl.set("animated", *animated)?;
+            }
+            ContentSegment::Link(url) => {
+                segment_tbl.set("kind", "link")?;
+                segment_tbl.set("url", url.as_str())?;
+            }
+            ContentSegment::Code {
+                code,
+                language,
+                block,
+            } => {
+                segment_tbl.set("kind", "code")?;
+                segment_tbl.set("code", code.as_str())?;
+                segment_tbl.set("language", language.as_deref())?;
+                segment_tbl.set("block", *block)?;
+            }
+        }
+
+        tbl.raw_insert((i + 1) as i64, segment_tbl)?;
+    }
+
+    Ok(tbl)
+}
+
 #[derive(Clone)]
 pub struct BotMessage(Arc<BotMessageInner>);
 
 pub struct BotMessageInner {
     bot: Arc<Bot>,
-    sender: Sender<LuaAsyncCallback>,
+    sender: AsyncSender,
     id: MessageId,
     author: BotUser,
     channel: BotChannel,
-    content: String,
+    content: Arc<str>,
+    segments: Vec<ContentSegment>,
     attachments: Vec<Arc<Attachment>>,
+    reply_to: Option<MessageId>,
+    link: Option<String>,
     service: ServiceKind,
 }
 
+// Messages get reused once nothing but the pool holds on to them anymore
+const MESSAGE_POOL_SIZE: usize = 32;
+
+lazy_static::lazy_static! {
+    static ref MESSAGE_POOL: StdMutex<Vec<Arc<BotMessageInner>>> = StdMutex::new(Vec::new());
+}
+
 impl BotMessage {
+    fn pooled(inner: BotMessageInner) -> BotMessage {
+        let mut pool = MESSAGE_POOL.lock().unwrap();
+
+        for pooled in pool.iter_mut() {
+            if let Some(free) = Arc::get_mut(pooled) {
+                *free = inner;
+                return BotMessage(pooled.clone());
+            }
+        }
+
+        let inner = Arc::new(inner);
+
+        if pool.len() < MESSAGE_POOL_SIZE {
+            pool.push(inner.clone());
+        }
+
+        BotMessage(inner)
+    }
+
     pub async fn from_msg(
         bot: Arc<Bot>,
-        sender: Sender<LuaAsyncCallback>,
-        msg: &Arc<dyn Message<impl Service>>,
+        sender: AsyncSender,
+        msg: &dyn MessageAbstract,
     ) -> Result<BotMessage> {
         let attachments = msg.attachments().to_vec();
-        let service_user = msg.author().clone() as Arc<dyn User<_>>;
-        let author = BotUser::from_user(bot.clone(), &service_user).await?;
-        let service_channel = msg.channel().await? as Arc<dyn Channel<_>>;
+        let author = BotUser::from_user(bot.clone(), &*msg.author()).await?;
+        let service_channel = msg.channel().await?;
         let channel =
-            BotChannel::from_channel(bot.clone(), sender.clone(), &service_channel).await?;
+            BotChannel::from_channel(bot.clone(), sender.clone(), &*service_channel).await?;
 
-        Ok(BotMessage(Arc::new(BotMessageInner {
+        Ok(BotMessage::pooled(BotMessageInner {
             bot,
             sender,
             id: msg.id(),
             author,
             channel,
-            content: msg.content().to_string(),
-            attachments,
-            service: msg.service().kind(),
-        })))
+            content: msg.content().into(),
+            segments: msg.kind().parse_content(msg.content()),
+            attachments,
+            reply_to: msg.reply_to(),
+            link: msg.link(),
+            service: msg.kind(),
+        }))
+    }
+
+    /// A message by a made up user in a made up channel, never sent to or through a service
+    pub async fn synthetic(
+        bot: Arc<Bot>,
+        sender: AsyncSender,
+        content: String,
+    ) -> Result<BotMessage> {
+        let user_id = UserId::Discord(1);
+        let user = bot.db().get_user_from_service_user_id(user_id).await?;
+
+        let author = BotUser(
+            Arc::new(BotUserInner {
+                name: "synthetic".into(),
+                nick: "synthetic".into(),
+                avatar: None,
+                id: user_id,
+                restricted: false,
+            }),
+            Arc::new(user),
+        );
+
+        let channel = BotChannel(Arc::new(BotChannelInner {
+            bot: bot.clone(),
+            sender: sender.clone(),
+            id: ChannelId::Discord(1),
+            server: BotServer(Arc::new(BotServerInner {
+                id: ServerId::Discord(1),
+            })),
+            service: ServiceKind::Discord,
+            forum_tags: None,
+        }));
+
+        Ok(BotMessage::pooled(BotMessageInner {
+            bot,
+            sender,
+            id: MessageId::Discord(1),
+            author,
+            channel,
+            segments: DiscordService::parse_content(&content),
+            content: content.into(),
+            attachments: Vec::new(),
+            reply_to: None,
+            link: None,
+            service: ServiceKind::Discord,
+        }))
+    }
+
+    /// The same message for another state, futures it creates resolve in that state
+    pub fn with_sender(&self, sender: AsyncSender) -> BotMessage {
+        let channel = &self.0.channel.0;
+        let channel = BotChannel(Arc::new(BotChannelInner {
+            bot: channel.bot.clone(),
+            sender: sender.clone(),
+            id: channel.id,
+            server: channel.server.clone(),
+            service: channel.service,
+            forum_tags: channel.forum_tags.clone(),
+        }));
+
+        BotMessage::pooled(BotMessageInner {
+            bot: self.0.bot.clone(),
+            sender,
+            id: self.0.id,
+            author: self.0.author.clone(),
+            channel,
+            content: self.0.content.clone(),
+            segments: self.0.segments.clone(),
+            attachments: self.0.attachments.clone(),
+            reply_to: self.0.reply_to,
+            link: self.0.link.clone(),
+            service: self.0.service,
+        })
+    }
+
+    pub fn id(&self) -> MessageId {
+        self.0.id
+    }
+
+    pub fn content(&self) -> &str {
+        &self.0.content
+    }
+
+    pub fn reply_to(&self) -> Option<MessageId> {
+        self.0.reply_to
+    }
+
+    pub fn link(&self) -> Option<&str> {
+        self.0.link.as_deref()
     }
 
     pub fn author(&self) -> &BotUser {
@@ -717,72 +2618,179 @@ impl BotMessage {
     }
 }
 
+fn reply_message<'a>(
+    state: &'a Lua,
+    msg: &BotMessage,
+    content: String,
+    settings: Option<LuaTable<'a>>,
+) -> LuaResult<LuaTable<'a>> {
+    let sandbox_state = get_sandbox_state(state);
+
+    if let Some(sandbox_state) = &sandbox_state {
+        if sandbox_state.limits().messages_left_limit() {
+            return Err(LuaError::ExternalError(Arc::new(
+                SandboxError::LimitReached("message sending"),
+            )));
+        }
+    }
+
+    let sandboxed = sandbox_state.is_some();
+    let message_settings = if let Some(settings) = settings {
+        message_settings_from_table(settings, sandbox_state.as_ref())?
+    } else {
+        MessageSettings::default()
+    };
+
+    let bot = msg.0.bot.clone();
+    let ctx = msg.0.bot.get_ctx();
+    let sender = msg.0.sender.clone();
+    let channel_id = msg.0.channel.id();
+    let server_id = msg.channel().server().id();
+    let (content, message_settings) =
+        component_fallback(channel_id.service_kind(), content, message_settings);
+    let author_id = msg.0.author.id();
+
+    let fut = create_lua_future!(
+        state,
+        msg.0.sender,
+        (),
+        async move {
+            let mut message_settings =
+                filter_media(&bot, server_id, channel_id, message_settings).await?;
+            message_settings.sanitize =
+                sandbox_sanitize(&bot, server_id, channel_id, sandboxed).await?;
+
+            let max_parts = if sandboxed { 1 } else { MAX_REPLY_PARTS };
+
+            match ctx
+                .services()
+                .clone()
+                .send_split(
+                    channel_id,
+                    content,
+                    MessageSettings {
+                        reply_user: Some(author_id),
+                        ..message_settings
+                    },
+                    max_parts,
+                )
+                .await
+            {
+                Ok(mut msgs) => {
+                    let msg = msgs.pop().expect("sent message");
+                    BotMessage::from_msg(bot, sender, &*msg).await
+                }
+                Err(err) => Err(err),
+            }
+        },
+        |_state, _data: (), res: Result<BotMessage>| { Ok(res?) }
+    );
+
+    Ok(fut)
+}
+
 impl UserData for BotMessage {
     fn add_methods<'a, M: UserDataMethods<'a, Self>>(methods: &mut M) {
         methods.add_method(
             "reply",
             |state, msg, (content, settings): (String, Option<LuaTable>)| {
-                if let Some(sandbox_state) = get_sandbox_state(state) {
-                    if sandbox_state.limits().messages_left_limit() {
-                        return Err(LuaError::RuntimeError(
-                            "sandbox message sending limit reached".into(),
-                        ));
-                    }
-                }
+                reply_message(state, msg, content, settings)
+            },
+        );
 
-                let message_settings = if let Some(settings) = settings {
-                    message_settings_from_table(settings)?
-                } else {
-                    MessageSettings::default()
+        // msg:reply_embed(embed, settings), a reply with only an embed
+        methods.add_method(
+            "reply_embed",
+            |state, msg, (embed, settings): (LuaTable, Option<LuaTable>)| {
+                let settings = match settings {
+                    Some(settings) => settings,
+                    None => state.create_table()?,
                 };
+                settings.set("embed", embed)?;
+
+                reply_message(state, msg, String::new(), Some(settings))
+            },
+        );
+
+        // msg:reply_at(timestamp, content), resolves to a handle that can cancel the reply
+        methods.add_method(
+            "reply_at",
+            |state, msg, (send_time, content): (i64, String)| {
+                if get_sandbox_state(state).is_some() {
+                    return Err(LuaError::RuntimeError(
+                        "replies can't be scheduled from the sandbox".into(),
+                    ));
+                }
 
                 let bot = msg.0.bot.clone();
-                let ctx = msg.0.bot.get_ctx();
                 let sender = msg.0.sender.clone();
-                let channel_id = msg.0.channel.id();
-                let author_id = msg.0.author.id();
+                let channel_id = msg.channel().id();
+                let msg_id = msg.0.id;
+                let created_by = msg.author().uid();
 
                 let fut = create_lua_future!(
                     state,
                     msg.0.sender,
                     (),
                     async move {
-                        match ctx
-                            .services()
-                            .clone()
-                            .send_message(
-                                channel_id,
-                                content,
-                                MessageSettings {
-                                    reply_user: Some(author_id),
-                                    ..message_settings
-                                },
-                            )
-                            .await
-                        {
-                            Ok(msg) => BotMessage::from_msg(bot, sender, &msg).await,
-                            Err(err) => Err(err),
-                        }
+                        let id = schedule_reply(
+                            &bot, channel_id, msg_id, created_by, send_time, &content,
+                        )
+                        .await?;
+
+                        Ok(BotScheduledReply {
+                            bot,
+                            sender,
+                            id,
+                            send_time,
+                        })
                     },
-                    |_state, _data: (), res: Result<BotMessage>| { Ok(res?) }
+                    |_state, _data: (), res: Result<BotScheduledReply>| { Ok(res?) }
                 );
 
                 Ok(fut)
             },
         );
 
+        // msg:author_permissions, resolves to a table of the normalized permissions like admin
+        methods.add_method("author_permissions", |state, msg, (): ()| {
+            let ctx = msg.0.bot.get_ctx();
+            let channel_id = msg.channel().id();
+            let user_id = msg.author().id();
+
+            let fut = create_lua_future!(
+                state,
+                msg.0.sender,
+                (),
+                ctx.services().user_permissions(channel_id, user_id),
+                |state, _data: (), res: Result<UserPermissions>| {
+                    let permissions = res?;
+                    let tbl = state.create_table()?;
+
+                    for (name, permission) in UserPermissions::NAMES {
+                        tbl.set(*name, permissions.contains(*permission))?;
+                    }
+
+                    Ok(tbl)
+                }
+            );
+
+            Ok(fut)
+        });
+
         methods.add_method("react", |state, msg, reaction: String| {
             if let Some(sandbox_state) = get_sandbox_state(state) {
                 if sandbox_state.limits().message_reacts_left_limit() {
-                    return Err(LuaError::RuntimeError(
-                        "sandbox message reacting limit reached".into(),
-                    ));
+                    return Err(LuaError::ExternalError(Arc::new(
+                        SandboxError::LimitReached("message reacting"),
+                    )));
                 }
             }
 
             let ctx = msg.0.bot.get_ctx();
             let channel_id = msg.channel().id();
             let msg_id = msg.0.id;
+            let reaction = resolve_shortcode(&reaction);
 
             let fut = create_lua_future!(
                 state,
@@ -800,28 +2808,48 @@ impl UserData for BotMessage {
             |state, msg, (content, settings): (String, Option<LuaTable>)| {
                 if let Some(sandbox_state) = get_sandbox_state(state) {
                     if sandbox_state.limits().message_edits_left_limit() {
-                        return Err(LuaError::RuntimeError(
-                            "sandbox message editing limit reached".into(),
-                        ));
+                        return Err(LuaError::ExternalError(Arc::new(
+                            SandboxError::LimitReached("message editing"),
+                        )));
                     }
                 }
 
-                let ctx = msg.0.bot.get_ctx();
+                let bot = msg.0.bot.clone();
                 let channel_id = msg.channel().id();
+                let server_id = msg.channel().server().id();
                 let msg_id = msg.0.id;
 
+                let sandbox_state = get_sandbox_state(state);
+                let sandboxed = sandbox_state.is_some();
                 let message_settings = if let Some(settings) = settings {
-                    message_settings_from_table(settings)?
+                    message_settings_from_table(settings, sandbox_state.as_ref())?
                 } else {
                     MessageSettings::default()
                 };
+                let (content, message_settings) =
+                    component_fallback(channel_id.service_kind(), content, message_settings);
 
                 let fut = create_lua_future!(
                     state,
                     msg.0.sender,
                     (),
-                    ctx.services()
-                        .edit_message(channel_id, msg_id, content, message_settings),
+                    async move {
+                        let sanitize =
+                            sandbox_sanitize(&bot, server_id, channel_id, sandboxed).await?;
+
+                        bot.get_ctx()
+                            .services()
+                            .edit_message(
+                                channel_id,
+                                msg_id,
+                                content,
+                                MessageSettings {
+                                    sanitize,
+                                    ..message_settings
+                                },
+                            )
+                            .await
+                    },
                     |_state, _data: (), res: Result<()>| { Ok(res?) }
                 );
 
@@ -829,12 +2857,28 @@ impl UserData for BotMessage {
             },
         );
 
+        methods.add_method(
+            "on_click",
+            |state, msg, (id, callback, timeout): (String, LuaFunction, Option<u64>)| {
+                if get_sandbox_state(state).is_some() {
+                    return Err(LuaError::RuntimeError(
+                        "components can't be handled from the sandbox".into(),
+                    ));
+                }
+
+                let bot_tbl: Table = state.globals().get("bot")?;
+                let add_component_hook: LuaFunction = bot_tbl.get("add_component_hook")?;
+
+                add_component_hook.call::<_, ()>((msg.clone(), id, callback, timeout))
+            },
+        );
+
         methods.add_method("delete", |state, msg, (): ()| {
             if let Some(sandbox_state) = get_sandbox_state(state) {
                 if sandbox_state.limits().message_deletions_left_limit() {
-                    return Err(LuaError::RuntimeError(
-                        "sandbox message deletion limit reached".into(),
-                    ));
+                    return Err(LuaError::ExternalError(Arc::new(
+                        SandboxError::LimitReached("message deletion"),
+                    )));
                 }
             }
 
@@ -864,7 +2908,10 @@ impl UserData for BotMessage {
                     for (i, attachment) in msg.0.attachments.iter().enumerate() {
                         attachments.raw_insert(
                             (i + 1) as i64,
-                            state.create_userdata(BotMessageAttachment(attachment.clone()))?,
+                            state.create_userdata(BotMessageAttachment(
+                                attachment.clone(),
+                                msg.0.sender.clone(),
+                            ))?,
                         )?;
                     }
 
@@ -876,6 +2923,15 @@ impl UserData for BotMessage {
                 "content" => Ok(mlua::Value::String(
                     state.create_string(msg.0.content.as_bytes())?,
                 )),
+                "segments" => Ok(mlua::Value::Table(segments_table(state, &msg.0.segments)?)),
+                "reply_to" => Ok(match msg.0.reply_to {
+                    Some(id) => mlua::Value::String(state.create_string(&id.to_short_str())?),
+                    None => mlua::Value::Nil,
+                }),
+                "link" => Ok(match &msg.0.link {
+                    Some(link) => mlua::Value::String(state.create_string(link)?),
+                    None => mlua::Value::Nil,
+                }),
                 "channel" => Ok(mlua::Value::UserData(
                     state.create_userdata(msg.channel().clone())?,
                 )),
@@ -896,11 +2952,194 @@ impl UserData for BotMessage {
     }
 }
 
+/// Run code in the sandbox state for a bot script, collecting its output for up to 2 seconds
+pub async fn run_sandboxed_output(
+    bot: &Arc<Bot>,
+    sandbox_pool: &Arc<SandboxPool>,
+    code: &str,
+    msg: BotMessage,
+    env_encoded: String,
+    owner: SandboxOwner,
+    tape: Option<Arc<SandboxTape>>,
+    trace: TraceId,
+) -> Result<String> {
+    let (server_id, channel_id) = (msg.channel().server().id(), msg.channel().id());
+    let settings = bot.get_ctx().modules().lua.module().settings().clone();
+
+    if !settings
+        .sandbox_enabled
+        .value(server_id, channel_id)
+        .await?
+    {
+        return Err(SandboxError::Disabled.into());
+    }
+
+    let filter = ContentFilter::load(&settings, server_id).await?;
+    let author_id = msg.author().id();
+
+    if let Some(filter) = &filter {
+        filter.check_code(bot, channel_id, author_id, code).await?;
+    }
+
+    let lua_state = sandbox_pool.take_state().await?;
+    let msg = msg.with_sender(lua_state.async_sender());
+
+    let (_sandbox_state, recv) = match sandbox_pool
+        .run(lua_state, code, msg, Some(env_encoded), owner, tape, trace)
+        .await
+    {
+        Ok(recv) => recv,
+        Err(err) => {
+            return Err(SandboxError::Runtime(err.to_string()).into());
+        }
+    };
+
+    let mut out_str = String::new();
+    let start = Instant::now();
+
+    loop {
+        if start.elapsed() > Duration::from_secs(2) {
+            break;
+        }
+
+        match recv.try_recv() {
+            Ok(out) => match out {
+                SandboxMsg::Out(o) => {
+                    if !out_str.is_empty() {
+                        out_str.push('\n');
+                    }
+
+                    out_str.push_str(&o);
+                }
+                SandboxMsg::Error(err) => {
+                    return Err(SandboxError::Runtime(err).into());
+                }
+                SandboxMsg::Terminated(reason) => {
+                    bot.metrics().count_sandbox_termination(reason.name());
+
+                    match reason {
+                        SandboxTerminationReason::Done => break,
+                        SandboxTerminationReason::ExecutionQuota => {
+                            return Err(SandboxError::ExecutionQuota.into());
+                        }
+                        SandboxTerminationReason::TimeLimit => {
+                            return Err(SandboxError::TimeLimit.into());
+                        }
+                        SandboxTerminationReason::MemoryLimit(peak) => {
+                            return Err(SandboxError::MemoryLimit(peak).into());
+                        }
+                    }
+                }
+            },
+            Err(TryRecvError::Empty) => {
+                tokio::time::sleep(Duration::from_millis(50)).await;
+            }
+            Err(TryRecvError::Disconnected) => break,
+        }
+    }
+
+    let out_str = match filter {
+        Some(filter) => {
+            filter
+                .check_output(bot, channel_id, author_id, out_str)
+                .await?
+        }
+        None => out_str,
+    };
+
+    bot.get_ctx()
+        .modules()
+        .lua
+        .module()
+        .scan_sandbox_output(server_id, out_str)
+        .await
+}
+
+fn check_attachment_type(content_type: &str) -> Result<()> {
+    if ATTACHMENT_TYPES
+        .iter()
+        .any(|prefix| content_type.starts_with(prefix))
+    {
+        Ok(())
+    } else {
+        Err(AttachmentError::DisallowedType(content_type.into()).into())
+    }
+}
+
+pub async fn download_attachment(attachment: Arc<Attachment>, max_size: u64) -> Result<Vec<u8>> {
+    if let Some(content_type) = &attachment.content_type {
+        check_attachment_type(content_type)?;
+    }
+
+    if attachment.size.unwrap_or(0) > max_size {
+        return Err(AttachmentError::TooLarge(max_size).into());
+    }
+
+    let client = Client::builder().build::<_, Body>(HttpsConnector::new());
+    let req = Request::builder()
+        .method("GET")
+        .uri(&attachment.url)
+        .body(Body::empty())?;
+    let mut res = client.request(req).await?;
+
+    if attachment.content_type.is_none() {
+        let content_type = res
+            .headers()
+            .get(CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok())
+            .unwrap_or("application/octet-stream");
+
+        check_attachment_type(content_type)?;
+    }
+
+    let body = res
+        .body_mut()
+        .map_err(|e: hyper::Error| e.into())
+        .try_fold(Vec::new(), |mut data, chunk| async move {
+            data.extend_from_slice(&chunk);
+
+            if data.len() as u64 > max_size {
+                return Err(AttachmentError::TooLarge(max_size).into());
+            }
+
+            Ok(data)
+        })
+        .await?;
+
+    Ok(body)
+}
+
 #[derive(Clone)]
-pub struct BotMessageAttachment(Arc<Attachment>);
+pub struct BotMessageAttachment(Arc<Attachment>, AsyncSender);
 
 impl UserData for BotMessageAttachment {
     fn add_methods<'a, M: UserDataMethods<'a, Self>>(methods: &mut M) {
+        methods.add_method("bytes", |state, a, (): ()| {
+            let max_size = if let Some(sandbox_state) = get_sandbox_state(state) {
+                if sandbox_state.limits().attachment_downloads_left_limit() {
+                    return Err(LuaError::ExternalError(Arc::new(
+                        SandboxError::LimitReached("attachment download"),
+                    )));
+                }
+
+                MAX_SANDBOX_ATTACHMENT_SIZE
+            } else {
+                MAX_ATTACHMENT_SIZE
+            };
+
+            let attachment = a.0.clone();
+
+            let fut = create_lua_future!(
+                state,
+                a.1,
+                (),
+                download_attachment(attachment, max_size),
+                |state, _data: (), res: Result<Vec<u8>>| { Ok(state.create_string(&res?)?) }
+            );
+
+            Ok(fut)
+        });
+
         methods.add_meta_method(MetaMethod::Index, |state, a, index: String| {
             match index.as_str() {
                 "filename" => Ok(mlua::Value::String(state.create_string(&a.0.filename)?)),
@@ -910,6 +3149,10 @@ impl UserData for BotMessageAttachment {
                 } else {
                     mlua::Value::Nil
                 }),
+                "content_type" => Ok(match &a.0.content_type {
+                    Some(content_type) => mlua::Value::String(state.create_string(content_type)?),
+                    None => mlua::Value::Nil,
+                }),
                 "dimensions" => Ok(if let Some((width, height)) = a.0.dimensions {
                     let tbl = state.create_table()?;
 
@@ -933,13 +3176,57 @@ impl UserData for BotMessageAttachment {
     }
 }
 
+/// A pending component interaction, which can be answered with a modal for a short while
+#[derive(Clone)]
+pub struct BotInteraction(Arc<BotInteractionInner>);
+
+pub struct BotInteractionInner {
+    bot: Arc<Bot>,
+    sender: AsyncSender,
+    id: InteractionId,
+}
+
+impl BotInteraction {
+    pub fn new(bot: Arc<Bot>, sender: AsyncSender, id: InteractionId) -> BotInteraction {
+        BotInteraction(Arc::new(BotInteractionInner { bot, sender, id }))
+    }
+}
+
+impl UserData for BotInteraction {
+    fn add_methods<'a, M: UserDataMethods<'a, Self>>(methods: &mut M) {
+        methods.add_method("modal", |state, interaction, modal: LuaTable| {
+            let modal = table_to_modal(modal)?;
+            let ctx = interaction.0.bot.get_ctx();
+            let interaction_id = interaction.0.id;
+
+            let fut = create_lua_future!(
+                state,
+                interaction.0.sender,
+                (),
+                async move { ctx.services().open_modal(interaction_id, modal).await },
+                |state, _data: (), res: Result<HashMap<String, String>>| {
+                    let values = state.create_table()?;
+
+                    for (id, value) in res? {
+                        values.set(id, value)?;
+                    }
+
+                    Ok(values)
+                }
+            );
+
+            Ok(fut)
+        });
+    }
+}
+
 #[derive(Clone)]
 pub struct BotUser(Arc<BotUserInner>, Arc<DbUser>);
 
 impl BotUser {
     pub async fn from_user(
         bot: Arc<Bot>,
-        service_user: &Arc<dyn User<impl Service>>,
+        service_user: &dyn UserAbstract,
     ) -> Result<BotUser> {
         let user = bot
             .db()
@@ -963,9 +3250,25 @@ impl BotUser {
         self.0.id
     }
 
+    pub fn name(&self) -> &str {
+        &self.0.name
+    }
+
     pub fn uid(&self) -> Uid {
         self.1.uid
     }
+
+    pub fn timezone(&self) -> Option<&str> {
+        self.1.timezone.as_deref()
+    }
+
+    pub fn role(&self) -> &str {
+        &self.1.role
+    }
+
+    pub fn db_user(&self) -> &DbUser {
+        &self.1
+    }
 }
 
 pub struct BotUserInner {
@@ -982,7 +3285,7 @@ impl UserData for BotUser {
             MetaMethod::Index,
             |state, user, index: String| match index.as_str() {
                 "id" => Ok(mlua::Value::String(
-                    state.create_string(user.0.id.to_short_str().as_bytes())?,
+                    state.create_string(user_id_str(user.0.id).as_bytes())?,
                 )),
                 "avatar" => Ok(if let Some(avatar) = user.0.avatar.as_ref() {
                     mlua::Value::String(state.create_string(avatar.as_bytes())?)
@@ -1000,6 +3303,10 @@ impl UserData for BotUser {
                     state.create_string(user.1.role.as_bytes())?,
                 )),
                 "restricted" => Ok(mlua::Value::Boolean(user.0.restricted)),
+                "timezone" => Ok(match &user.1.timezone {
+                    Some(timezone) => mlua::Value::String(state.create_string(timezone)?),
+                    None => mlua::Value::Nil,
+                }),
                 _ => Ok(mlua::Value::Nil),
             },
         );
@@ -1016,31 +3323,75 @@ impl UserData for BotUser {
 }
 
 #[derive(Clone)]
+/// The user running a command and where, commands get it as ctx.caller
+#[derive(Clone)]
+pub struct BotCaller {
+    bot: Arc<Bot>,
+    caller: Caller,
+    sender: AsyncSender,
+}
+
+impl BotCaller {
+    pub fn new(bot: Arc<Bot>, caller: Caller, sender: AsyncSender) -> BotCaller {
+        BotCaller {
+            bot,
+            caller,
+            sender,
+        }
+    }
+}
+
+impl UserData for BotCaller {
+    fn add_methods<'a, M: UserDataMethods<'a, Self>>(methods: &mut M) {
+        // caller:has_permission, resolves to whether the caller holds the node in the channel
+        methods.add_method("has_permission", |state, this, node: String| {
+            let bot = this.bot.clone();
+            let caller = this.caller.clone();
+
+            let fut = create_lua_future!(
+                state,
+                this.sender,
+                (),
+                async move {
+                    bot.permissions()
+                        .has_permission(&bot, &caller.user, &node, Some(caller.location()))
+                        .await
+                },
+                |_state, _data: (), res: Result<bool>| { Ok(res?) }
+            );
+
+            Ok(fut)
+        });
+    }
+}
+
 pub struct BotChannel(Arc<BotChannelInner>);
 
 pub struct BotChannelInner {
     bot: Arc<Bot>,
-    sender: Sender<LuaAsyncCallback>,
+    sender: AsyncSender,
     id: ChannelId,
     server: BotServer,
     service: ServiceKind,
+    forum_tags: Option<Vec<ForumTag>>,
 }
 
 impl BotChannel {
     pub async fn from_channel(
         bot: Arc<Bot>,
-        sender: Sender<LuaAsyncCallback>,
-        channel: &Arc<dyn Channel<impl Service>>,
+        sender: AsyncSender,
+        channel: &dyn ChannelAbstract,
     ) -> Result<BotChannel> {
-        let service_server = channel.server().await? as Arc<dyn Server<_>>;
-        let server = BotServer::from_server(&service_server).await?;
+        let service_server = channel.server().await?;
+        let server = BotServer::from_server(&*service_server).await?;
 
         Ok(BotChannel(Arc::new(BotChannelInner {
             bot,
             sender,
             id: channel.id(),
             server,
-            service: channel.service().kind(),
+            service: channel.kind(),
+            forum_tags: channel.forum_tags(),
         })))
     }
 
@@ -1071,9 +3422,9 @@ impl UserData for BotChannel {
             |state, chan, (content, settings): (String, Option<LuaTable>)| {
                 if let Some(sandbox_state) = get_sandbox_state(state) {
                     if sandbox_state.limits().messages_left_limit() {
-                        return Err(LuaError::RuntimeError(
-                            "sandbox message sending limit reached".into(),
-                        ));
+                        return Err(LuaError::ExternalError(Arc::new(
+                            SandboxError::LimitReached("message sending"),
+                        )));
                     }
                 }
 
@@ -1082,23 +3433,38 @@ impl UserData for BotChannel {
                 let ctx = chan.0.bot.get_ctx();
                 let channel_id = chan.id();
 
+                let sandbox_state = get_sandbox_state(state);
+                let sandboxed = sandbox_state.is_some();
                 let message_settings = if let Some(settings) = settings {
-                    message_settings_from_table(settings)?
+                    message_settings_from_table(settings, sandbox_state.as_ref())?
                 } else {
                     MessageSettings::default()
                 };
+                let server_id = chan.server().id();
+                let (content, message_settings) =
+                    component_fallback(chan.0.service, content, message_settings);
 
                 let fut = create_lua_future!(
                     state,
                     chan.0.sender,
                     (),
                     async move {
+                        let mut message_settings =
+                            filter_media(&bot, server_id, channel_id, message_settings).await?;
+                        message_settings.sanitize =
+                            sandbox_sanitize(&bot, server_id, channel_id, sandboxed).await?;
+
+                        let max_parts = if sandboxed { 1 } else { MAX_REPLY_PARTS };
+
                         match ctx
                             .services()
-                            .send_message(channel_id, content, message_settings)
+                            .send_split(channel_id, content, message_settings, max_parts)
                             .await
                         {
-                            Ok(msg) => BotMessage::from_msg(bot, sender, &msg).await,
+                            Ok(mut msgs) => {
+                                let msg = msgs.pop().expect("sent message");
+                                BotMessage::from_msg(bot, sender, &*msg).await
+                            }
                             Err(err) => Err(err),
                         }
                     },
@@ -1109,6 +3475,55 @@ impl UserData for BotChannel {
             },
         );
 
+        methods.add_method(
+            "create_post",
+            |state, chan, (name, content, options): (String, String, Option<LuaTable>)| {
+                if let Some(sandbox_state) = get_sandbox_state(state) {
+                    if sandbox_state.limits().messages_left_limit() {
+                        return Err(LuaError::ExternalError(Arc::new(
+                            SandboxError::LimitReached("message sending"),
+                        )));
+                    }
+                }
+
+                let bot = chan.0.bot.clone();
+                let sender = chan.0.sender.clone();
+                let channel_id = chan.id();
+                let server_id = chan.server().id();
+                let sandboxed = get_sandbox_state(state).is_some();
+
+                let tags = match options {
+                    Some(options) => options.get::<_, Option<Vec<String>>>("tags")?,
+                    None => None,
+                };
+                let mut post = ForumPost {
+                    name,
+                    content,
+                    tags: tags.unwrap_or_default(),
+                    sanitize: Sanitize::empty(),
+                };
+
+                let fut = create_lua_future!(
+                    state,
+                    chan.0.sender,
+                    (),
+                    async move {
+                        post.sanitize =
+                            sandbox_sanitize(&bot, server_id, channel_id, sandboxed).await?;
+
+                        let ctx = bot.get_ctx();
+                        let post_id = ctx.services().create_forum_post(channel_id, post).await?;
+                        let post = ctx.services().channel(post_id).await?;
+
+                        BotChannel::from_channel(bot, sender, &*post).await
+                    },
+                    |_state, _data: (), res: Result<BotChannel>| { Ok(res?) }
+                );
+
+                Ok(fut)
+            },
+        );
+
         methods.add_method("send_typing", |_state, chan, (): ()| {
             let ctx = chan.0.bot.get_ctx();
             let channel_id = chan.id();
@@ -1124,11 +3539,24 @@ impl UserData for BotChannel {
             MetaMethod::Index,
             |state, channel, index: String| match index.as_str() {
                 "id" => Ok(mlua::Value::String(
-                    state.create_string(&channel.0.id.to_short_str())?,
+                    state.create_string(channel_id_str(channel.0.id).as_bytes())?,
                 )),
                 "server" => Ok(mlua::Value::UserData(
                     state.create_userdata(channel.server().clone())?,
                 )),
+                "is_forum" => Ok(mlua::Value::Boolean(channel.0.forum_tags.is_some())),
+                "tags" => match &channel.0.forum_tags {
+                    Some(tags) => {
+                        let tags_tbl = state.create_table()?;
+
+                        for (i, tag) in tags.iter().enumerate() {
+                            tags_tbl.set(i + 1, tag.name.as_str())?;
+                        }
+
+                        Ok(mlua::Value::Table(tags_tbl))
+                    }
+                    None => Ok(mlua::Value::Nil),
+                },
                 _ => Ok(mlua::Value::Nil),
             },
         );
@@ -1147,7 +3575,7 @@ pub struct BotServerInner {
 }
 
 impl BotServer {
-    pub async fn from_server(server: &Arc<dyn Server<impl Service>>) -> Result<BotServer> {
+    pub async fn from_server(server: &dyn ServerAbstract) -> Result<BotServer> {
         Ok(BotServer(Arc::new(BotServerInner { id: server.id() })))
     }
 
@@ -1162,7 +3590,7 @@ impl UserData for BotServer {
             MetaMethod::Index,
             |state, server, index: String| match index.as_str() {
                 "id" => Ok(mlua::Value::String(
-                    state.create_string(&server.0.id.to_short_str())?,
+                    state.create_string(server_id_str(server.0.id).as_bytes())?,
                 )),
                 _ => Ok(mlua::Value::Nil),
             },
@@ -1173,3 +3601,21 @@ impl UserData for BotServer {
         });
     }
 }
+
+#[derive(Debug, Error)]
+pub enum AttachmentError {
+    #[error("attachments can't be larger than {} bytes", _0)]
+    TooLarge(u64),
+    #[error("attachments of type \"{}\" can't be downloaded", _0)]
+    DisallowedType(String),
+}
+
+#[derive(Debug, Error)]
+pub enum FormatError {
+    #[error("unknown text style \"{}\"", _0)]
+    UnknownStyle(String),
+    #[error("unknown text dialect \"{}\"", _0)]
+    UnknownDialect(String),
+    #[error("rich text can't be nested deeper than {} levels", _0)]
+    TooDeep(usize),
+}
@@ -0,0 +1,361 @@
+use anyhow::Result;
+use mlua::{prelude::*, Lua};
+use plotters::{
+    coord::Shift,
+    prelude::*,
+    style::{Palette, Palette99},
+};
+use std::sync::Arc;
+use thiserror::Error;
+
+use super::{
+    super::state::{get_sandbox_state, SandboxError},
+    r#async::AsyncSender,
+};
+
+const DEFAULT_WIDTH: u32 = 800;
+const DEFAULT_HEIGHT: u32 = 400;
+const MAX_WIDTH: u32 = 1600;
+const MAX_HEIGHT: u32 = 1000;
+const MAX_SERIES: usize = 10;
+const MAX_POINTS: usize = 500;
+const FONT: &str = "sans-serif";
+
+#[derive(Clone, Copy, PartialEq)]
+enum ChartKind {
+    Line,
+    Bar,
+    Pie,
+}
+
+struct Series {
+    name: String,
+    values: Vec<f64>,
+}
+
+struct ChartSpec {
+    kind: ChartKind,
+    title: Option<String>,
+    width: u32,
+    height: u32,
+    labels: Vec<String>,
+    series: Vec<Series>,
+}
+
+fn series_color(i: usize) -> RGBColor {
+    let (r, g, b) = Palette99::COLORS[i % Palette99::COLORS.len()];
+    RGBColor(r, g, b)
+}
+
+fn draw_err<E: std::fmt::Display>(err: E) -> anyhow::Error {
+    ChartError::Draw(err.to_string()).into()
+}
+
+fn table_to_series(tbl: LuaTable) -> LuaResult<Series> {
+    Ok(Series {
+        name: tbl.get::<_, Option<String>>("name")?.unwrap_or_default(),
+        values: tbl.get("values")?,
+    })
+}
+
+fn table_to_spec(tbl: LuaTable) -> Result<ChartSpec> {
+    let kind = match tbl.get::<_, Option<String>>("kind")?.as_deref() {
+        Some("line") | None => ChartKind::Line,
+        Some("bar") => ChartKind::Bar,
+        Some("pie") => ChartKind::Pie,
+        Some(kind) => return Err(ChartError::UnknownKind(kind.into()).into()),
+    };
+
+    let mut series = Vec::new();
+    if let Some(series_tbl) = tbl.get::<_, Option<LuaTable>>("series")? {
+        for entry in series_tbl.sequence_values::<LuaTable>() {
+            series.push(table_to_series(entry?)?);
+        }
+    } else if let Some(values) = tbl.get::<_, Option<Vec<f64>>>("values")? {
+        // Shorthand for a single series
+        series.push(Series {
+            name: String::new(),
+            values,
+        });
+    }
+
+    if series.is_empty() || series.iter().all(|series| series.values.is_empty()) {
+        return Err(ChartError::NoData.into());
+    }
+
+    if series.len() > MAX_SERIES {
+        return Err(ChartError::TooManySeries(MAX_SERIES).into());
+    }
+
+    let points: usize = series.iter().map(|series| series.values.len()).sum();
+    if points > MAX_POINTS {
+        return Err(ChartError::TooManyPoints(MAX_POINTS).into());
+    }
+
+    if series
+        .iter()
+        .any(|series| series.values.iter().any(|value| !value.is_finite()))
+    {
+        return Err(ChartError::InvalidValue.into());
+    }
+
+    Ok(ChartSpec {
+        kind,
+        title: tbl.get("title")?,
+        width: tbl
+            .get::<_, Option<u32>>("width")?
+            .unwrap_or(DEFAULT_WIDTH)
+            .clamp(100, MAX_WIDTH),
+        height: tbl
+            .get::<_, Option<u32>>("height")?
+            .unwrap_or(DEFAULT_HEIGHT)
+            .clamp(100, MAX_HEIGHT),
+        labels: tbl
+            .get::<_, Option<Vec<String>>>("labels")?
+            .unwrap_or_default(),
+        series,
+    })
+}
+
+fn value_range(spec: &ChartSpec) -> (f64, f64) {
+    let values = spec.series.iter().flat_map(|series| series.values.iter());
+    let min = values.clone().cloned().fold(0.0, f64::min);
+    let max = values.cloned().fold(0.0, f64::max);
+
+    if min == max {
+        (min, min + 1.0)
+    } else {
+        (min, max + (max - min) * 0.05)
+    }
+}
+
+fn point_count(spec: &ChartSpec) -> usize {
+    spec.series
+        .iter()
+        .map(|series| series.values.len())
+        .max()
+        .unwrap_or(0)
+}
+
+fn label(spec: &ChartSpec, index: usize) -> String {
+    spec.labels
+        .get(index)
+        .cloned()
+        .unwrap_or_else(|| (index + 1).to_string())
+}
+
+fn draw_legend<'a, DB: DrawingBackend + 'a, CT: CoordTranslate>(
+    spec: &ChartSpec,
+    chart: &mut ChartContext<'a, DB, CT>,
+) -> Result<()> {
+    if spec.series.len() > 1 {
+        chart
+            .configure_series_labels()
+            .background_style(&WHITE.mix(0.8))
+            .border_style(&BLACK)
+            .draw()
+            .map_err(draw_err)?;
+    }
+
+    Ok(())
+}
+
+fn draw_line(area: &DrawingArea<BitMapBackend, Shift>, spec: &ChartSpec) -> Result<()> {
+    let points = point_count(spec);
+    let (min, max) = value_range(spec);
+
+    let mut chart = ChartBuilder::on(area)
+        .margin(10)
+        .x_label_area_size(30)
+        .y_label_area_size(50)
+        .build_cartesian_2d(0..points.saturating_sub(1).max(1), min..max)
+        .map_err(draw_err)?;
+
+    chart
+        .configure_mesh()
+        .x_labels(points.min(12))
+        .x_label_formatter(&|index| label(spec, *index))
+        .draw()
+        .map_err(draw_err)?;
+
+    for (i, series) in spec.series.iter().enumerate() {
+        let color = series_color(i);
+
+        chart
+            .draw_series(LineSeries::new(
+                series.values.iter().cloned().enumerate(),
+                color.stroke_width(2),
+            ))
+            .map_err(draw_err)?
+            .label(&series.name)
+            .legend(move |(x, y)| PathElement::new(vec![(x, y), (x + 20, y)], color));
+    }
+
+    draw_legend(spec, &mut chart)
+}
+
+fn draw_bar(area: &DrawingArea<BitMapBackend, Shift>, spec: &ChartSpec) -> Result<()> {
+    let points = point_count(spec);
+    let (min, max) = value_range(spec);
+    // Bars of every series are grouped around their point
+    let bar_width = 0.8 / spec.series.len() as f64;
+
+    let mut chart = ChartBuilder::on(area)
+        .margin(10)
+        .x_label_area_size(30)
+        .y_label_area_size(50)
+        .build_cartesian_2d(-0.5..points as f64 - 0.5, min..max)
+        .map_err(draw_err)?;
+
+    chart
+        .configure_mesh()
+        .disable_x_mesh()
+        .x_labels(points.min(12))
+        .x_label_formatter(&|x| {
+            if (x - x.round()).abs() < f64::EPSILON && *x >= 0.0 {
+                label(spec, x.round() as usize)
+            } else {
+                String::new()
+            }
+        })
+        .draw()
+        .map_err(draw_err)?;
+
+    for (i, series) in spec.series.iter().enumerate() {
+        let color = series_color(i);
+        let offset = -0.4 + bar_width * i as f64;
+
+        chart
+            .draw_series(series.values.iter().enumerate().map(|(point, value)| {
+                let x = point as f64 + offset;
+                Rectangle::new([(x, 0.0), (x + bar_width, *value)], color.filled())
+            }))
+            .map_err(draw_err)?
+            .label(&series.name)
+            .legend(move |(x, y)| Rectangle::new([(x, y - 5), (x + 10, y + 5)], color.filled()));
+    }
+
+    draw_legend(spec, &mut chart)
+}
+
+fn draw_pie(area: &DrawingArea<BitMapBackend, Shift>, spec: &ChartSpec) -> Result<()> {
+    // Only the first series is shown in a pie chart
+    let values = &spec.series[0].values;
+
+    if values.iter().any(|value| *value < 0.0) {
+        return Err(ChartError::NegativeValue.into());
+    }
+
+    let (width, height) = area.dim_in_pixel();
+    let center = ((width / 2) as i32, (height / 2) as i32);
+    let radius = width.min(height) as f64 * 0.35;
+    let colors = (0..values.len()).map(series_color).collect::<Vec<_>>();
+    let labels = (0..values.len())
+        .map(|index| label(spec, index))
+        .collect::<Vec<_>>();
+
+    let mut pie = Pie::new(&center, &radius, values, &colors, &labels);
+    pie.label_style((FONT, 16).into_font());
+    pie.percentages((FONT, 14).into_font().color(&WHITE));
+
+    area.draw(&pie).map_err(draw_err)?;
+
+    Ok(())
+}
+
+fn render_chart(spec: ChartSpec) -> Result<Vec<u8>> {
+    let (width, height) = (spec.width, spec.height);
+    let mut buffer = vec![0u8; (width * height * 3) as usize];
+
+    {
+        let root = BitMapBackend::with_buffer(&mut buffer, (width, height)).into_drawing_area();
+        root.fill(&WHITE).map_err(draw_err)?;
+
+        let area = match &spec.title {
+            Some(title) => root.titled(title, (FONT, 24)).map_err(draw_err)?,
+            None => root.clone(),
+        };
+
+        match spec.kind {
+            ChartKind::Line => draw_line(&area, &spec)?,
+            ChartKind::Bar => draw_bar(&area, &spec)?,
+            ChartKind::Pie => draw_pie(&area, &spec)?,
+        }
+
+        root.present().map_err(draw_err)?;
+    }
+
+    let mut data = Vec::new();
+    {
+        let mut encoder = png::Encoder::new(&mut data, width, height);
+        encoder.set_color(png::ColorType::Rgb);
+        encoder.set_depth(png::BitDepth::Eight);
+
+        let mut writer = encoder.write_header()?;
+        writer.write_image_data(&buffer)?;
+    }
+
+    Ok(data)
+}
+
+pub fn lib_chart(state: &Lua, sender: AsyncSender) -> Result<()> {
+    let chart = state.create_table()?;
+
+    // chart.render({ kind, title, width, height, labels, series = { { name, values } } })
+    let render_fn = state.create_function(move |state, tbl: LuaTable| {
+        if let Some(sandbox_state) = get_sandbox_state(state) {
+            if sandbox_state.limits().images_left_limit() {
+                return Err(LuaError::ExternalError(Arc::new(
+                    SandboxError::LimitReached("image"),
+                )));
+            }
+        }
+
+        let spec = table_to_spec(tbl).map_err(|err| LuaError::RuntimeError(err.to_string()))?;
+
+        let fut = create_lua_future!(
+            state,
+            sender,
+            (),
+            async move {
+                match tokio::task::spawn_blocking(move || render_chart(spec)).await {
+                    Ok(res) => res,
+                    Err(err) => Err(err.into()),
+                }
+            },
+            |state, _data: (), res: Result<Vec<u8>>| {
+                // The same shape as message attachments so it can be sent as is
+                let attachment = state.create_table()?;
+                attachment.set("filename", "chart.png")?;
+                attachment.set("data", state.create_string(&res?)?)?;
+
+                Ok(attachment)
+            }
+        );
+
+        Ok(fut)
+    })?;
+    chart.set("render", render_fn)?;
+
+    state.globals().set("chart", chart)?;
+
+    Ok(())
+}
+
+#[derive(Debug, Error)]
+pub enum ChartError {
+    #[error("unknown chart kind \"{}\"", _0)]
+    UnknownKind(String),
+    #[error("the chart has no data")]
+    NoData,
+    #[error("charts can have at most {} series", _0)]
+    TooManySeries(usize),
+    #[error("charts can have at most {} points", _0)]
+    TooManyPoints(usize),
+    #[error("chart values must be finite numbers")]
+    InvalidValue,
+    #[error("pie charts can't have negative values")]
+    NegativeValue,
+    #[error("error drawing chart: {}", _0)]
+    Draw(String),
+}
@@ -0,0 +1,108 @@
+use anyhow::Result;
+use emojis::{Emoji, Group};
+use mlua::{prelude::*, Lua};
+use regex::{Captures, Regex};
+
+lazy_static::lazy_static! {
+    static ref SHORTCODE_RE: Regex = Regex::new(r":([a-z0-9_+\-]+):").unwrap();
+    static ref CUSTOM_EMOJI_RE: Regex = Regex::new(r"^<a?:\w+:\d+>$").unwrap();
+}
+
+/// Find an emoji by itself or by its shortcode, with or without the surrounding colons
+pub fn find_emoji(text: &str) -> Option<&'static Emoji> {
+    let text = text.trim();
+
+    emojis::get(text).or_else(|| emojis::get_by_shortcode(text.trim_matches(':')))
+}
+
+/// Turn a shortcode like ":thumbsup:" into its emoji, anything else is returned as is
+pub fn resolve_shortcode(text: &str) -> String {
+    match text
+        .trim()
+        .strip_prefix(':')
+        .and_then(|code| code.strip_suffix(':'))
+    {
+        Some(code) => emojis::get_by_shortcode(code)
+            .map(|emoji| emoji.as_str().to_string())
+            .unwrap_or_else(|| text.to_string()),
+        None => text.to_string(),
+    }
+}
+
+/// Whether the text is a single emoji, either unicode or a custom emoji of a service
+pub fn is_emoji(text: &str) -> bool {
+    emojis::get(text).is_some() || CUSTOM_EMOJI_RE.is_match(text)
+}
+
+fn category_name(group: Group) -> &'static str {
+    match group {
+        Group::SmileysAndEmotion => "smileys_and_emotion",
+        Group::PeopleAndBody => "people_and_body",
+        Group::AnimalsAndNature => "animals_and_nature",
+        Group::FoodAndDrink => "food_and_drink",
+        Group::TravelAndPlaces => "travel_and_places",
+        Group::Activities => "activities",
+        Group::Objects => "objects",
+        Group::Symbols => "symbols",
+        Group::Flags => "flags",
+        #[allow(unreachable_patterns)]
+        _ => "other",
+    }
+}
+
+fn emoji_to_table<'a>(state: &'a Lua, emoji: &Emoji) -> LuaResult<LuaTable<'a>> {
+    let tbl = state.create_table()?;
+
+    tbl.set("emoji", emoji.as_str())?;
+    tbl.set("name", emoji.name())?;
+    tbl.set("shortcode", emoji.shortcode())?;
+    tbl.set("category", category_name(emoji.group()))?;
+
+    Ok(tbl)
+}
+
+pub fn lib_emoji(state: &Lua) -> Result<()> {
+    let emoji = state.create_table()?;
+
+    // emoji.get(text)
+    let get_fn = state.create_function(|state, text: String| match find_emoji(&text) {
+        Some(emoji) => Ok(Some(emoji_to_table(state, emoji)?)),
+        None => Ok(None),
+    })?;
+    emoji.set("get", get_fn)?;
+
+    // emoji.from_shortcode(code)
+    let from_shortcode_fn = state.create_function(|_state, code: String| {
+        Ok(emojis::get_by_shortcode(code.trim().trim_matches(':')).map(|emoji| emoji.as_str()))
+    })?;
+    emoji.set("from_shortcode", from_shortcode_fn)?;
+
+    // emoji.to_shortcode(emoji)
+    let to_shortcode_fn = state.create_function(|_state, text: String| {
+        Ok(emojis::get(text.trim())
+            .and_then(|emoji| emoji.shortcode())
+            .map(|code| format!(":{}:", code)))
+    })?;
+    emoji.set("to_shortcode", to_shortcode_fn)?;
+
+    // emoji.is_emoji(text)
+    let is_emoji_fn = state.create_function(|_state, text: String| Ok(is_emoji(text.trim())))?;
+    emoji.set("is_emoji", is_emoji_fn)?;
+
+    // emoji.replace_shortcodes(text)
+    let replace_shortcodes_fn = state.create_function(|_state, text: String| {
+        Ok(SHORTCODE_RE
+            .replace_all(&text, |caps: &Captures| {
+                match emojis::get_by_shortcode(&caps[1]) {
+                    Some(emoji) => emoji.as_str().to_string(),
+                    None => caps[0].to_string(),
+                }
+            })
+            .into_owned())
+    })?;
+    emoji.set("replace_shortcodes", replace_shortcodes_fn)?;
+
+    state.globals().set("emoji", emoji)?;
+
+    Ok(())
+}
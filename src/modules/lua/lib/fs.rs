@@ -0,0 +1,329 @@
+use anyhow::Result;
+use mlua::{Error as LuaError, Lua, String as LuaString};
+use std::{
+    fs,
+    io::{ErrorKind, Write},
+    path::{Component, Path, PathBuf},
+    sync::Arc,
+};
+
+use crate::bot::Bot;
+
+// Total size of the files directly inside a directory
+const DIR_QUOTA: u64 = 16 * 1024 * 1024;
+// Files and directories directly inside a directory, so empty ones can't pile up either
+const MAX_DIR_ENTRIES: u64 = 1024;
+// Components a path may have
+const MAX_DEPTH: usize = 8;
+
+fn fs_error(message: impl Into<String>) -> LuaError {
+    LuaError::RuntimeError(message.into())
+}
+
+fn io_error(err: std::io::Error) -> LuaError {
+    LuaError::ExternalError(Arc::new(err))
+}
+
+/// Resolve a script supplied path inside the data directory, rejecting anything that could escape it
+fn resolve_path(root: &Path, path: &str) -> Result<PathBuf, LuaError> {
+    let mut resolved = root.to_path_buf();
+
+    for component in Path::new(path).components() {
+        match component {
+            Component::CurDir => {}
+            Component::Normal(c) => resolved.push(c),
+            _ => return Err(fs_error(format!("invalid path \"{}\"", path))),
+        }
+    }
+
+    if resolved
+        .strip_prefix(root)
+        .map_or(0, |rel| rel.components().count())
+        > MAX_DEPTH
+    {
+        return Err(fs_error(format!(
+            "paths can't be more than {} levels deep",
+            MAX_DEPTH
+        )));
+    }
+
+    // Symlinks placed in the directory by something else must not lead outside of it, dangling
+    // ones included, so none are followed
+    let mut existing = root.to_path_buf();
+    for component in resolved.strip_prefix(root).unwrap().components() {
+        existing.push(component);
+
+        match fs::symlink_metadata(&existing) {
+            Ok(metadata) if metadata.file_type().is_symlink() => {
+                return Err(fs_error(format!("invalid path \"{}\"", path)));
+            }
+            Ok(_) => {}
+            Err(err) if err.kind() == ErrorKind::NotFound => break,
+            Err(err) => return Err(io_error(err)),
+        }
+    }
+
+    Ok(resolved)
+}
+
+#[derive(Default)]
+struct DirUsage {
+    size: u64,
+    entries: u64,
+}
+
+// Only counts what's directly inside the directory, symlinks are counted as entries but not followed
+fn dir_usage(dir: &Path) -> std::io::Result<DirUsage> {
+    let mut usage = DirUsage::default();
+
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(err) if err.kind() == ErrorKind::NotFound => return Ok(usage),
+        Err(err) => return Err(err),
+    };
+
+    for entry in entries {
+        let metadata = fs::symlink_metadata(entry?.path())?;
+
+        usage.entries += 1;
+
+        if metadata.is_file() {
+            usage.size += metadata.len();
+        }
+    }
+
+    Ok(usage)
+}
+
+fn check_quota(root: &Path, path: &Path, new_len: u64, append: bool) -> Result<(), LuaError> {
+    // Missing parent directories are created along with the file, only the closest existing one
+    // gains an entry and the new ones start out empty
+    let mut dir = path.parent().unwrap_or(root);
+    let mut new_entry = !path.exists();
+    while dir != root && !dir.exists() {
+        dir = dir.parent().unwrap_or(root);
+        new_entry = true;
+    }
+
+    let usage = dir_usage(dir).map_err(io_error)?;
+
+    if new_entry && usage.entries + 1 > MAX_DIR_ENTRIES {
+        return Err(fs_error(format!(
+            "a directory can't hold more than {} files and directories",
+            MAX_DIR_ENTRIES
+        )));
+    }
+
+    // The file being overwritten no longer counts towards the quota, and a file created in new
+    // directories starts out alone
+    let used = if dir == path.parent().unwrap_or(root) {
+        match fs::metadata(path) {
+            Ok(metadata) if metadata.is_file() && !append => {
+                usage.size.saturating_sub(metadata.len())
+            }
+            _ => usage.size,
+        }
+    } else {
+        0
+    };
+
+    if used + new_len > DIR_QUOTA {
+        return Err(fs_error(format!(
+            "directory quota of {} bytes exceeded",
+            DIR_QUOTA
+        )));
+    }
+
+    Ok(())
+}
+
+pub fn lib_fs(state: &Lua, bot: &Arc<Bot>) -> Result<()> {
+    let root = bot.data_path().join("lua_data");
+    fs::create_dir_all(&root)?;
+
+    let fs_tbl = state.create_table()?;
+
+    // fs.read(path)
+    let fs_read = state.create_function({
+        let root = root.clone();
+        move |state, path: String| {
+            let path = resolve_path(&root, &path)?;
+
+            match fs::read(&path) {
+                Ok(data) => Ok(Some(state.create_string(&data)?)),
+                Err(err) if err.kind() == ErrorKind::NotFound => Ok(None),
+                Err(err) => Err(io_error(err)),
+            }
+        }
+    })?;
+    fs_tbl.set("read", fs_read)?;
+
+    // fs.write(path, data, append)
+    let fs_write = state.create_function({
+        let root = root.clone();
+        move |_, (path, data, append): (String, LuaString, Option<bool>)| {
+            let path = resolve_path(&root, &path)?;
+            let append = append.unwrap_or(false);
+
+            if path == root || path.is_dir() {
+                return Err(fs_error("cannot write to a directory"));
+            }
+
+            check_quota(&root, &path, data.as_bytes().len() as u64, append)?;
+
+            if let Some(parent) = path.parent() {
+                fs::create_dir_all(parent).map_err(io_error)?;
+            }
+
+            let mut file = fs::OpenOptions::new()
+                .create(true)
+                .write(true)
+                .append(append)
+                .truncate(!append)
+                .open(&path)
+                .map_err(io_error)?;
+
+            file.write_all(data.as_bytes()).map_err(io_error)?;
+
+            Ok(())
+        }
+    })?;
+    fs_tbl.set("write", fs_write)?;
+
+    // fs.list(path)
+    let fs_list = state.create_function({
+        let root = root.clone();
+        move |state, path: Option<String>| {
+            let path = resolve_path(&root, path.as_deref().unwrap_or("."))?;
+            let list = state.create_table()?;
+
+            let entries = match fs::read_dir(&path) {
+                Ok(entries) => entries,
+                Err(err) if err.kind() == ErrorKind::NotFound => return Ok(list),
+                Err(err) => return Err(io_error(err)),
+            };
+
+            for (i, entry) in entries.enumerate() {
+                let entry = entry.map_err(io_error)?;
+                let metadata = entry.metadata().map_err(io_error)?;
+
+                let item = state.create_table()?;
+                item.set("name", entry.file_name().to_string_lossy().to_string())?;
+                item.set("is_dir", metadata.is_dir())?;
+                item.set("size", metadata.len())?;
+
+                list.set(i + 1, item)?;
+            }
+
+            Ok(list)
+        }
+    })?;
+    fs_tbl.set("list", fs_list)?;
+
+    // fs.exists(path)
+    let fs_exists = state.create_function({
+        let root = root.clone();
+        move |_, path: String| Ok(resolve_path(&root, &path)?.exists())
+    })?;
+    fs_tbl.set("exists", fs_exists)?;
+
+    // fs.delete(path)
+    let fs_delete = state.create_function(move |_, path: String| {
+        let path = resolve_path(&root, &path)?;
+
+        if path == root {
+            return Err(fs_error("cannot delete the data directory"));
+        }
+
+        let result = if path.is_dir() {
+            fs::remove_dir_all(&path)
+        } else {
+            fs::remove_file(&path)
+        };
+
+        match result {
+            Ok(()) => Ok(true),
+            Err(err) if err.kind() == ErrorKind::NotFound => Ok(false),
+            Err(err) => Err(io_error(err)),
+        }
+    })?;
+    fs_tbl.set("delete", fs_delete)?;
+
+    state.globals().set("fs", fs_tbl)?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{check_quota, resolve_path, DIR_QUOTA};
+    use std::{fs, path::PathBuf};
+
+    fn test_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("kaito-fs-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join("root")).unwrap();
+        dir
+    }
+
+    #[test]
+    fn resolve_path_test() {
+        let dir = test_dir("resolve");
+        let root = dir.join("root");
+
+        assert_eq!(
+            resolve_path(&root, "a/b.txt").unwrap(),
+            root.join("a/b.txt")
+        );
+        assert_eq!(resolve_path(&root, "./a").unwrap(), root.join("a"));
+        assert!(resolve_path(&root, "../outside").is_err());
+        assert!(resolve_path(&root, "a/../../outside").is_err());
+        assert!(resolve_path(&root, "a/../b").is_err());
+        assert!(resolve_path(&root, "/etc/passwd").is_err());
+        assert!(resolve_path(&root, "a/b/c/d/e/f/g/h/i").is_err());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn resolve_path_symlink_test() {
+        let dir = test_dir("symlink");
+        let root = dir.join("root");
+
+        fs::create_dir_all(dir.join("outside")).unwrap();
+        std::os::unix::fs::symlink(dir.join("outside"), root.join("link")).unwrap();
+        fs::create_dir_all(root.join("inside")).unwrap();
+        std::os::unix::fs::symlink(root.join("inside"), root.join("inside_link")).unwrap();
+
+        std::os::unix::fs::symlink(dir.join("missing"), root.join("inside/dangling")).unwrap();
+
+        assert!(resolve_path(&root, "link").is_err());
+        assert!(resolve_path(&root, "link/file.txt").is_err());
+        assert!(resolve_path(&root, "inside_link/file.txt").is_err());
+        assert!(resolve_path(&root, "inside/dangling").is_err());
+        assert!(resolve_path(&root, "inside/dangling/file.txt").is_err());
+        assert!(resolve_path(&root, "inside/file.txt").is_ok());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn check_quota_test() {
+        let dir = test_dir("quota");
+        let root = dir.join("root");
+
+        fs::create_dir_all(root.join("a")).unwrap();
+        fs::write(root.join("a/full.bin"), vec![0; DIR_QUOTA as usize]).unwrap();
+
+        // Each directory has its own quota
+        assert!(check_quota(&root, &root.join("a/more.bin"), 1, false).is_err());
+        assert!(check_quota(&root, &root.join("a/full.bin"), 1, true).is_err());
+        assert!(check_quota(&root, &root.join("a/full.bin"), 1, false).is_ok());
+        assert!(check_quota(&root, &root.join("b.bin"), 1, false).is_ok());
+        assert!(check_quota(&root, &root.join("a/b/c.bin"), 1, false).is_ok());
+        assert!(check_quota(&root, &root.join("a/b/c.bin"), DIR_QUOTA + 1, false).is_err());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
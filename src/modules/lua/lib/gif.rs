@@ -0,0 +1,283 @@
+use anyhow::Result;
+use hyper::{Body, Client, Request};
+use hyper_tls::HttpsConnector;
+use mlua::{prelude::*, Lua};
+use std::{collections::HashMap, sync::Arc};
+use thiserror::Error;
+
+use super::{bot::BotChannel, r#async::AsyncSender};
+use crate::{
+    bot::Bot,
+    config::ConfigGif,
+    modules::Module,
+    services::{ChannelId, ServerId},
+};
+
+const TENOR_API_URL: &'static str = "https://tenor.googleapis.com";
+const GIPHY_API_URL: &'static str = "https://api.giphy.com";
+const DEFAULT_RESULTS: u32 = 10;
+const MAX_RESULTS: u32 = 25;
+
+#[derive(Clone)]
+pub struct Gif {
+    pub url: String,
+    pub preview: Option<String>,
+    pub title: String,
+}
+
+#[async_trait]
+pub trait GifProvider: Send + Sync {
+    fn id(&self) -> &'static str;
+
+    async fn search(&self, query: &str, limit: u32) -> Result<Vec<Gif>>;
+}
+
+async fn send_request(uri: String) -> Result<Vec<u8>> {
+    let https = HttpsConnector::new();
+    let client = Client::builder().build::<_, Body>(https);
+
+    let req = Request::builder()
+        .method("GET")
+        .uri(uri)
+        .body(Body::empty())?;
+
+    let res = client.request(req).await?;
+    let status = res.status();
+    let body = hyper::body::to_bytes(res.into_body()).await?;
+
+    if !status.is_success() {
+        return Err(GifError::ProviderError(
+            status.as_u16(),
+            String::from_utf8_lossy(&body).to_string(),
+        )
+        .into());
+    }
+
+    Ok(body.to_vec())
+}
+
+// Tenor
+
+pub struct TenorProvider {
+    config: ConfigGif,
+}
+
+#[derive(Deserialize)]
+struct TenorMedia {
+    url: String,
+}
+
+#[derive(Deserialize)]
+struct TenorResult {
+    content_description: String,
+    media_formats: HashMap<String, TenorMedia>,
+}
+
+#[derive(Deserialize)]
+struct TenorResponse {
+    results: Vec<TenorResult>,
+}
+
+#[async_trait]
+impl GifProvider for TenorProvider {
+    fn id(&self) -> &'static str {
+        "tenor"
+    }
+
+    async fn search(&self, query: &str, limit: u32) -> Result<Vec<Gif>> {
+        let params = url::form_urlencoded::Serializer::new(String::new())
+            .append_pair("q", query)
+            .append_pair("key", &self.config.api_key)
+            .append_pair("limit", &limit.to_string())
+            .append_pair("media_filter", "gif,tinygif")
+            .append_pair(
+                "contentfilter",
+                self.config.rating.as_deref().unwrap_or("medium"),
+            )
+            .finish();
+
+        let api_url = self.config.api_url.as_deref().unwrap_or(TENOR_API_URL);
+        let uri = format!("{}/v2/search?{}", api_url.trim_end_matches('/'), params);
+
+        let res: TenorResponse = serde_json::from_slice(&send_request(uri).await?)?;
+
+        Ok(res
+            .results
+            .into_iter()
+            .filter_map(|mut result| {
+                let url = result.media_formats.remove("gif")?.url;
+                let preview = result.media_formats.remove("tinygif").map(|m| m.url);
+
+                Some(Gif {
+                    url,
+                    preview,
+                    title: result.content_description,
+                })
+            })
+            .collect())
+    }
+}
+
+// Giphy
+
+pub struct GiphyProvider {
+    config: ConfigGif,
+}
+
+#[derive(Deserialize)]
+struct GiphyImage {
+    url: String,
+}
+
+#[derive(Deserialize)]
+struct GiphyImages {
+    original: GiphyImage,
+    fixed_width_small: Option<GiphyImage>,
+}
+
+#[derive(Deserialize)]
+struct GiphyGif {
+    title: String,
+    images: GiphyImages,
+}
+
+#[derive(Deserialize)]
+struct GiphyResponse {
+    data: Vec<GiphyGif>,
+}
+
+#[async_trait]
+impl GifProvider for GiphyProvider {
+    fn id(&self) -> &'static str {
+        "giphy"
+    }
+
+    async fn search(&self, query: &str, limit: u32) -> Result<Vec<Gif>> {
+        let params = url::form_urlencoded::Serializer::new(String::new())
+            .append_pair("q", query)
+            .append_pair("api_key", &self.config.api_key)
+            .append_pair("limit", &limit.to_string())
+            .append_pair("rating", self.config.rating.as_deref().unwrap_or("pg-13"))
+            .finish();
+
+        let api_url = self.config.api_url.as_deref().unwrap_or(GIPHY_API_URL);
+        let uri = format!(
+            "{}/v1/gifs/search?{}",
+            api_url.trim_end_matches('/'),
+            params
+        );
+
+        let res: GiphyResponse = serde_json::from_slice(&send_request(uri).await?)?;
+
+        Ok(res
+            .data
+            .into_iter()
+            .map(|gif| Gif {
+                url: gif.images.original.url,
+                preview: gif.images.fixed_width_small.map(|image| image.url),
+                title: gif.title,
+            })
+            .collect())
+    }
+}
+
+pub fn provider_from_config(config: Option<&ConfigGif>) -> Result<Option<Arc<dyn GifProvider>>> {
+    let config = match config {
+        Some(config) => config.clone(),
+        None => return Ok(None),
+    };
+
+    Ok(Some(match config.provider.as_str() {
+        "tenor" => Arc::new(TenorProvider { config }),
+        "giphy" => Arc::new(GiphyProvider { config }),
+        provider => return Err(GifError::UnknownProvider(provider.into()).into()),
+    }))
+}
+
+/// Whether stickers and GIFs may be sent in a channel
+pub async fn gifs_enabled(
+    bot: &Arc<Bot>,
+    server_id: ServerId,
+    channel_id: ChannelId,
+) -> Result<bool> {
+    let settings = bot.get_ctx().modules().lua.module().settings().clone();
+
+    settings.gifs_enabled.value(server_id, channel_id).await
+}
+
+// bot state only
+pub fn lib_gif(state: &Lua, bot: &Arc<Bot>, sender: AsyncSender) -> Result<()> {
+    let gif = state.create_table()?;
+    let provider = provider_from_config(bot.config().gif.as_ref())?;
+
+    // gif.available
+    let available = provider.is_some();
+    let available_fn = state.create_function(move |_state, (): ()| Ok(available))?;
+    gif.set("available", available_fn)?;
+
+    // gif.search(query, { limit, channel })
+    let bot = bot.clone();
+    let search_fn =
+        state.create_function(move |state, (query, options): (String, Option<LuaTable>)| {
+            let bot = bot.clone();
+            let provider = provider.clone();
+
+            let (limit, channel): (Option<u32>, Option<BotChannel>) = match options {
+                Some(options) => (options.get("limit")?, options.get("channel")?),
+                None => (None, None),
+            };
+            let limit = limit.unwrap_or(DEFAULT_RESULTS).clamp(1, MAX_RESULTS);
+
+            let fut = create_lua_future!(
+                state,
+                sender,
+                (),
+                async move {
+                    if let Some(channel) = channel {
+                        if !gifs_enabled(&bot, channel.server().id(), channel.id()).await? {
+                            return Err(GifError::Disabled.into());
+                        }
+                    }
+
+                    provider
+                        .ok_or(GifError::NoProvider)?
+                        .search(&query, limit)
+                        .await
+                },
+                |state, _data: (), res: Result<Vec<Gif>>| {
+                    let list = state.create_table()?;
+
+                    for (i, gif) in res?.into_iter().enumerate() {
+                        let tbl = state.create_table()?;
+
+                        tbl.set("url", gif.url)?;
+                        tbl.set("preview", gif.preview)?;
+                        tbl.set("title", gif.title)?;
+
+                        list.set(i + 1, tbl)?;
+                    }
+
+                    Ok(list)
+                }
+            );
+
+            Ok(fut)
+        })?;
+    gif.set("search", search_fn)?;
+
+    state.globals().set("gif", gif)?;
+
+    Ok(())
+}
+
+#[derive(Debug, Error)]
+pub enum GifError {
+    #[error("GIFs have been disabled in this channel")]
+    Disabled,
+    #[error("no GIF provider has been configured")]
+    NoProvider,
+    #[error("unknown GIF provider \"{}\"", _0)]
+    UnknownProvider(String),
+    #[error("GIF provider error ({}): {}", _0, _1)]
+    ProviderError(u16, String),
+}
@@ -0,0 +1,47 @@
+use anyhow::Result;
+use mlua::Lua;
+use std::sync::Arc;
+
+use super::r#async::AsyncSender;
+use crate::{bot::Bot, modules::github::GithubIssue};
+
+// bot state only
+pub fn lib_github(state: &Lua, bot: &Arc<Bot>, sender: AsyncSender) -> Result<()> {
+    let gh = state.create_table()?;
+
+    // gh.issue
+    let bot2 = bot.clone();
+    let sender2 = sender.clone();
+    let issue_fn = state.create_function(move |state, (repo, number): (String, u64)| {
+        let github = bot2.get_ctx().modules().github.module().clone();
+
+        let fut = create_lua_future!(
+            state,
+            sender2,
+            (),
+            async move { github.issue(&repo, number).await },
+            |state, _data: (), res: Result<GithubIssue>| {
+                let issue = res?;
+                let tbl = state.create_table()?;
+
+                tbl.set("number", issue.number)?;
+                tbl.set("title", issue.title)?;
+                tbl.set("state", issue.state)?;
+                tbl.set("url", issue.url)?;
+                tbl.set("author", issue.author)?;
+                tbl.set("body", issue.body)?;
+                tbl.set("comments", issue.comments)?;
+                tbl.set("is_pull_request", issue.is_pull_request)?;
+
+                Ok(tbl)
+            }
+        );
+
+        Ok(fut)
+    })?;
+    gh.set("issue", issue_fn)?;
+
+    state.globals().set("gh", gh)?;
+
+    Ok(())
+}
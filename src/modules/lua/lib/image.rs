@@ -1,5 +1,4 @@
 use anyhow::Result;
-use crossbeam::channel::Sender;
 use futures::TryStreamExt;
 use graphicsmagick::{
     types,
@@ -16,14 +15,16 @@ use std::{
     path::Path,
     sync::{Arc, Mutex},
 };
+use thiserror::Error;
 use tokio::task::JoinError;
 
+use super::image_meta::{read_image_meta, ExifValue};
 use crate::{
     bot::Bot,
     modules::lua::{
         http::HttpError,
-        lib::bot::BotMessage,
-        state::{get_sandbox_state, LuaAsyncCallback},
+        lib::{bot::BotMessage, r#async::AsyncSender},
+        state::{get_sandbox_state, SandboxError},
     },
     services::{Message, ServiceKind},
 };
@@ -317,7 +318,7 @@ fn check_url(url: &url::Url) -> Result<String> {
     Ok(url.to_string())
 }
 
-async fn download_image(url: &url::Url) -> Result<Vec<u8>> {
+pub async fn download_image(url: &url::Url) -> Result<Vec<u8>> {
     let client = Client::builder().build::<_, Body>(HttpsConnector::new());
     let req = Request::builder()
         .method("GET")
@@ -342,7 +343,7 @@ async fn download_image(url: &url::Url) -> Result<Vec<u8>> {
     Ok(body)
 }
 
-async fn create_image(sender: Sender<LuaAsyncCallback>, data: Vec<u8>, svg: bool) -> Result<Image> {
+async fn create_image(sender: AsyncSender, data: Vec<u8>, svg: bool) -> Result<Image> {
     match tokio::task::spawn_blocking(move || {
         // Ensure the image is valid
         let mut wand = MagickWand::new();
@@ -383,7 +384,15 @@ async fn create_image(sender: Sender<LuaAsyncCallback>, data: Vec<u8>, svg: bool
     }
 }
 
-pub fn lib_image(state: &Lua, bot: Arc<Bot>, sender: Sender<LuaAsyncCallback>) -> Result<()> {
+/// Download and validate an image
+pub async fn image_from_url(sender: AsyncSender, url: &str) -> Result<Image> {
+    let url = url::Url::parse(url)?;
+    let svg = url.path().ends_with(".svg");
+
+    create_image(sender, download_image(&url).await?, svg).await
+}
+
+pub fn lib_image(state: &Lua, bot: Arc<Bot>, sender: AsyncSender) -> Result<()> {
     let image = state.create_table()?;
 
     // image.create_draw_buffer
@@ -443,7 +452,9 @@ pub fn lib_image(state: &Lua, bot: Arc<Bot>, sender: Sender<LuaAsyncCallback>) -
     let from_data_fn = state.create_function(move |state, data: LuaString| {
         if let Some(sandbox_state) = get_sandbox_state(state) {
             if sandbox_state.limits().images_left_limit() {
-                return Err(LuaError::RuntimeError("image limit reached".into()));
+                return Err(LuaError::ExternalError(Arc::new(
+                    SandboxError::LimitReached("image"),
+                )));
             }
         }
 
@@ -475,7 +486,9 @@ pub fn lib_image(state: &Lua, bot: Arc<Bot>, sender: Sender<LuaAsyncCallback>) -
     let from_url_fn = state.create_function(move |state, url: String| {
         if let Some(sandbox_state) = get_sandbox_state(state) {
             if sandbox_state.limits().images_left_limit() {
-                return Err(LuaError::RuntimeError("image limit reached".into()));
+                return Err(LuaError::ExternalError(Arc::new(
+                    SandboxError::LimitReached("image"),
+                )));
             }
         }
 
@@ -544,13 +557,54 @@ pub fn lib_image(state: &Lua, bot: Arc<Bot>, sender: Sender<LuaAsyncCallback>) -
     })?;
     image.set("from_url", from_url_fn)?;
 
+    // image.info(data, options), reads only the headers, GPS data is left out unless options.gps
+    let info_fn =
+        state.create_function(|state, (data, options): (LuaString, Option<LuaTable>)| {
+            let data = data.as_bytes();
+
+            if data.len() > MAX_IMAGE_SIZE {
+                return Err(LuaError::ExternalError(Arc::new(ImageError::TooLarge(
+                    MAX_IMAGE_SIZE / 1024,
+                ))));
+            }
+
+            let gps = match options {
+                Some(options) => options.get::<_, Option<bool>>("gps")?.unwrap_or(false),
+                None => false,
+            };
+
+            let meta = read_image_meta(data, gps).ok_or_else(|| {
+                LuaError::ExternalError(Arc::new(ImageError::UnsupportedFormat("unknown".into())))
+            })?;
+
+            let exif = state.create_table()?;
+            for (name, value) in meta.exif {
+                match value {
+                    ExifValue::Text(text) => exif.set(name, text)?,
+                    ExifValue::Number(number) => exif.set(name, number)?,
+                    ExifValue::Numbers(numbers) => exif.set(name, numbers)?,
+                }
+            }
+
+            let tbl = state.create_table()?;
+            tbl.set("format", meta.format)?;
+            tbl.set("width", meta.width)?;
+            tbl.set("height", meta.height)?;
+            tbl.set("exif", exif)?;
+
+            Ok(tbl)
+        })?;
+    image.set("info", info_fn)?;
+
     // image.resolve
     let sender2 = sender.clone();
     let resolve_fn = state.create_function(
         move |state, (msg, text): (Option<BotMessage>, Option<String>)| {
             if let Some(sandbox_state) = get_sandbox_state(state) {
                 if sandbox_state.limits().images_left_limit() {
-                    return Err(LuaError::RuntimeError("image limit reached".into()));
+                    return Err(LuaError::ExternalError(Arc::new(
+                        SandboxError::LimitReached("image"),
+                    )));
                 }
             }
 
@@ -645,7 +699,7 @@ pub fn lib_image(state: &Lua, bot: Arc<Bot>, sender: Sender<LuaAsyncCallback>) -
 
                         let id = msg.channel().id();
                         let channel = bot.get_ctx().services().channel(id).await?;
-                        if let Ok(messages) = channel.messages(16, None).await {
+                        if let Ok(messages) = channel.messages(16).await {
                             for message in messages {
                                 for attachment in message.attachments() {
                                     if let Some(extension) =
@@ -717,14 +771,14 @@ pub fn lib_image(state: &Lua, bot: Arc<Bot>, sender: Sender<LuaAsyncCallback>) -
 }
 
 #[derive(Clone)]
-pub struct Image(Arc<ImageInner>, Sender<LuaAsyncCallback>);
+pub struct Image(Arc<ImageInner>, AsyncSender);
 
 impl Image {
     pub fn copy_data(&self) -> Vec<u8> {
         self.0.data.clone()
     }
 
-    pub fn async_sender(&self) -> Sender<LuaAsyncCallback> {
+    pub fn async_sender(&self) -> AsyncSender {
         self.1.clone()
     }
 
@@ -737,6 +791,33 @@ impl Image {
         wand.read_image_blob(&self.0.data)?;
         Ok(wand)
     }
+
+    pub fn check_limits(&self, limits: &ImageLimits) -> Result<()> {
+        let info = self.info();
+
+        if !limits.formats.contains(&info.format.as_str()) {
+            return Err(ImageError::UnsupportedFormat(info.format.clone()).into());
+        }
+
+        if let Some((width, height)) = limits.dimensions {
+            if info.width != width || info.height != height {
+                return Err(ImageError::WrongDimensions(width, height).into());
+            }
+        }
+
+        if self.0.data.len() > limits.max_bytes {
+            return Err(ImageError::TooLarge(limits.max_bytes / 1024).into());
+        }
+
+        Ok(())
+    }
+}
+
+/// Restrictions on uploaded images such as custom emoji
+pub struct ImageLimits {
+    pub max_bytes: usize,
+    pub dimensions: Option<(u64, u64)>,
+    pub formats: &'static [&'static str],
 }
 
 macro_rules! image_method {
@@ -744,9 +825,9 @@ macro_rules! image_method {
         $methods.add_method($name, |state, image, $args: $args_ty| {
             if let Some(sandbox_state) = get_sandbox_state(state) {
                 if sandbox_state.limits().image_operations_left_limit() {
-                    return Err(LuaError::RuntimeError(
-                        "image operation limit reached".into(),
-                    ));
+                    return Err(LuaError::ExternalError(Arc::new(
+                        SandboxError::LimitReached("image operation"),
+                    )));
                 }
             }
 
@@ -1080,6 +1161,12 @@ pub struct ImageInfo {
     format: String,
 }
 
+impl ImageInfo {
+    pub fn format(&self) -> &str {
+        &self.format
+    }
+}
+
 #[derive(Clone, Default)]
 struct DrawCommandBuffer {
     commands: Arc<Mutex<Vec<DrawCommand>>>,
@@ -1582,3 +1669,13 @@ enum PathCommand {
         y: f64,
     },
 }
+
+#[derive(Debug, Error)]
+pub enum ImageError {
+    #[error("unsupported image format \"{}\"", _0)]
+    UnsupportedFormat(String),
+    #[error("the image must be {}x{}", _0, _1)]
+    WrongDimensions(u64, u64),
+    #[error("the image must be smaller than {}KB", _0)]
+    TooLarge(usize),
+}
@@ -0,0 +1,367 @@
+use std::convert::TryInto;
+
+// Upper bounds for what is read out of the EXIF data, it comes straight from uploads
+const MAX_IFD_ENTRIES: usize = 256;
+const MAX_EXIF_TEXT_LEN: usize = 256;
+const MAX_EXIF_VALUES: usize = 16;
+
+const TAG_EXIF_IFD: u16 = 0x8769;
+const TAG_GPS_IFD: u16 = 0x8825;
+
+// Only these tags are given out, anything else like maker notes or thumbnails is dropped
+const IFD0_TAGS: &[(u16, &str)] = &[
+    (0x010f, "make"),
+    (0x0110, "model"),
+    (0x0112, "orientation"),
+    (0x011a, "x_resolution"),
+    (0x011b, "y_resolution"),
+    (0x0128, "resolution_unit"),
+    (0x0131, "software"),
+    (0x0132, "date_time"),
+];
+
+const EXIF_TAGS: &[(u16, &str)] = &[
+    (0x829a, "exposure_time"),
+    (0x829d, "f_number"),
+    (0x8827, "iso"),
+    (0x9003, "date_time_original"),
+    (0x9004, "date_time_digitized"),
+    (0x9209, "flash"),
+    (0x920a, "focal_length"),
+    (0xa002, "pixel_width"),
+    (0xa003, "pixel_height"),
+    (0xa433, "lens_make"),
+    (0xa434, "lens_model"),
+];
+
+const GPS_TAGS: &[(u16, &str)] = &[
+    (0x0001, "gps_latitude_ref"),
+    (0x0002, "gps_latitude"),
+    (0x0003, "gps_longitude_ref"),
+    (0x0004, "gps_longitude"),
+    (0x0005, "gps_altitude_ref"),
+    (0x0006, "gps_altitude"),
+    (0x001d, "gps_date"),
+];
+
+pub struct ImageMeta {
+    pub format: &'static str,
+    pub width: u32,
+    pub height: u32,
+    pub exif: Vec<(&'static str, ExifValue)>,
+}
+
+pub enum ExifValue {
+    Text(String),
+    Number(f64),
+    Numbers(Vec<f64>),
+}
+
+/// Read the format, dimensions and EXIF data from the headers of an image without decoding it
+pub fn read_image_meta(data: &[u8], gps: bool) -> Option<ImageMeta> {
+    let (format, width, height, tiff) = if data.starts_with(b"\x89PNG\r\n\x1a\n") {
+        read_png(data)?
+    } else if data.starts_with(b"GIF87a") || data.starts_with(b"GIF89a") {
+        let width = u16::from_le_bytes(data.get(6..8)?.try_into().ok()?);
+        let height = u16::from_le_bytes(data.get(8..10)?.try_into().ok()?);
+
+        ("gif", width as u32, height as u32, None)
+    } else if data.starts_with(&[0xff, 0xd8]) {
+        read_jpeg(data)?
+    } else if data.starts_with(b"RIFF") && data.get(8..12) == Some(&b"WEBP"[..]) {
+        read_webp(data)?
+    } else {
+        return None;
+    };
+
+    let exif = tiff
+        .and_then(|tiff| read_tiff(tiff, gps))
+        .unwrap_or_default();
+
+    Some(ImageMeta {
+        format,
+        width,
+        height,
+        exif,
+    })
+}
+
+fn read_png(data: &[u8]) -> Option<(&'static str, u32, u32, Option<&[u8]>)> {
+    if data.get(12..16)? != b"IHDR" {
+        return None;
+    }
+
+    let width = u32::from_be_bytes(data.get(16..20)?.try_into().ok()?);
+    let height = u32::from_be_bytes(data.get(20..24)?.try_into().ok()?);
+
+    let mut tiff = None;
+    let mut pos = 8;
+
+    while let Some(header) = data.get(pos..pos + 8) {
+        let len = u32::from_be_bytes(header[..4].try_into().ok()?) as usize;
+        let chunk = data.get(pos + 8..(pos + 8).checked_add(len)?)?;
+
+        match &header[4..] {
+            b"eXIf" => {
+                tiff = Some(chunk);
+                break;
+            }
+            b"IDAT" | b"IEND" => break,
+            _ => {}
+        }
+
+        // Chunk data is followed by a crc
+        pos += 12 + len;
+    }
+
+    Some(("png", width, height, tiff))
+}
+
+fn read_jpeg(data: &[u8]) -> Option<(&'static str, u32, u32, Option<&[u8]>)> {
+    let mut tiff = None;
+    let mut pos = 2;
+
+    loop {
+        if *data.get(pos)? != 0xff {
+            return None;
+        }
+
+        let marker = *data.get(pos + 1)?;
+
+        // Fill bytes and markers without a length
+        if marker == 0xff {
+            pos += 1;
+            continue;
+        }
+        if marker == 0x01 || (0xd0..=0xd7).contains(&marker) {
+            pos += 2;
+            continue;
+        }
+
+        let len = u16::from_be_bytes(data.get(pos + 2..pos + 4)?.try_into().ok()?) as usize;
+        let segment = data.get(pos + 4..(pos + 2).checked_add(len)?)?;
+
+        match marker {
+            0xe1 if tiff.is_none() && segment.starts_with(b"Exif\0\0") => {
+                tiff = Some(&segment[6..]);
+            }
+            // Start of frame, except for the huffman, arithmetic coding and lossless markers
+            0xc0..=0xcf if marker != 0xc4 && marker != 0xc8 && marker != 0xcc => {
+                let height = u16::from_be_bytes(segment.get(1..3)?.try_into().ok()?);
+                let width = u16::from_be_bytes(segment.get(3..5)?.try_into().ok()?);
+
+                return Some(("jpeg", width as u32, height as u32, tiff));
+            }
+            0xd9 | 0xda => return None,
+            _ => {}
+        }
+
+        pos += 2 + len;
+    }
+}
+
+fn u24_le(bytes: &[u8]) -> u32 {
+    bytes[0] as u32 | (bytes[1] as u32) << 8 | (bytes[2] as u32) << 16
+}
+
+fn read_webp(data: &[u8]) -> Option<(&'static str, u32, u32, Option<&[u8]>)> {
+    let mut size = None;
+    let mut tiff = None;
+    let mut pos = 12;
+
+    while let Some(header) = data.get(pos..pos + 8) {
+        let len = u32::from_le_bytes(header[4..].try_into().ok()?) as usize;
+        let chunk = data.get(pos + 8..(pos + 8).checked_add(len)?)?;
+
+        match &header[..4] {
+            b"VP8X" => {
+                size = Some((u24_le(chunk.get(4..7)?) + 1, u24_le(chunk.get(7..10)?) + 1));
+            }
+            b"VP8 " if size.is_none() => {
+                if chunk.get(3..6)? != [0x9d, 0x01, 0x2a] {
+                    return None;
+                }
+
+                let width = u16::from_le_bytes(chunk.get(6..8)?.try_into().ok()?) & 0x3fff;
+                let height = u16::from_le_bytes(chunk.get(8..10)?.try_into().ok()?) & 0x3fff;
+
+                size = Some((width as u32, height as u32));
+            }
+            b"VP8L" if size.is_none() => {
+                if *chunk.first()? != 0x2f {
+                    return None;
+                }
+
+                let bits = u32::from_le_bytes(chunk.get(1..5)?.try_into().ok()?);
+
+                size = Some(((bits & 0x3fff) + 1, ((bits >> 14) & 0x3fff) + 1));
+            }
+            b"EXIF" => {
+                tiff = Some(chunk.strip_prefix(b"Exif\0\0").unwrap_or(chunk));
+            }
+            _ => {}
+        }
+
+        // Chunks are padded to an even size
+        pos += 8 + len + (len & 1);
+    }
+
+    let (width, height) = size?;
+
+    Some(("webp", width, height, tiff))
+}
+
+struct Tiff<'a> {
+    data: &'a [u8],
+    little_endian: bool,
+}
+
+impl<'a> Tiff<'a> {
+    fn u16(&self, pos: usize) -> Option<u16> {
+        let bytes = self.data.get(pos..pos + 2)?.try_into().ok()?;
+
+        Some(if self.little_endian {
+            u16::from_le_bytes(bytes)
+        } else {
+            u16::from_be_bytes(bytes)
+        })
+    }
+
+    fn u32(&self, pos: usize) -> Option<u32> {
+        let bytes = self.data.get(pos..pos + 4)?.try_into().ok()?;
+
+        Some(if self.little_endian {
+            u32::from_le_bytes(bytes)
+        } else {
+            u32::from_be_bytes(bytes)
+        })
+    }
+
+    fn read_ifd(
+        &self,
+        offset: usize,
+        tags: &[(u16, &'static str)],
+        out: &mut Vec<(&'static str, ExifValue)>,
+    ) -> Option<Vec<(u16, u32)>> {
+        let count = (self.u16(offset)? as usize).min(MAX_IFD_ENTRIES);
+        let mut pointers = Vec::new();
+
+        for i in 0..count {
+            let entry = offset + 2 + i * 12;
+            let tag = self.u16(entry)?;
+
+            if tag == TAG_EXIF_IFD || tag == TAG_GPS_IFD {
+                pointers.push((tag, self.u32(entry + 8)?));
+                continue;
+            }
+
+            if let Some((_, name)) = tags.iter().find(|(id, _)| *id == tag) {
+                if let Some(value) = self.read_value(entry) {
+                    out.push((*name, value));
+                }
+            }
+        }
+
+        Some(pointers)
+    }
+
+    fn read_value(&self, entry: usize) -> Option<ExifValue> {
+        let kind = self.u16(entry + 2)?;
+        let count = self.u32(entry + 4)? as usize;
+
+        let size = match kind {
+            1 | 2 | 7 => 1,
+            3 => 2,
+            4 | 9 => 4,
+            5 | 10 => 8,
+            _ => return None,
+        };
+
+        // Values of up to 4 bytes are stored in the entry itself
+        let pos = if count.checked_mul(size)? <= 4 {
+            entry + 8
+        } else {
+            self.u32(entry + 8)? as usize
+        };
+
+        if kind == 2 {
+            let bytes = self.data.get(pos..pos.checked_add(count)?)?;
+            let text: String = String::from_utf8_lossy(bytes)
+                .chars()
+                .filter(|c| !c.is_control())
+                .take(MAX_EXIF_TEXT_LEN)
+                .collect();
+
+            return Some(ExifValue::Text(text.trim().to_string()));
+        }
+
+        let mut values = Vec::new();
+
+        for i in 0..count.min(MAX_EXIF_VALUES) {
+            let at = pos + i * size;
+
+            values.push(match kind {
+                1 | 7 => *self.data.get(at)? as f64,
+                3 => self.u16(at)? as f64,
+                4 => self.u32(at)? as f64,
+                9 => self.u32(at)? as i32 as f64,
+                5 | 10 => {
+                    let (num, den) = (self.u32(at)?, self.u32(at + 4)?);
+
+                    let (num, den) = if kind == 10 {
+                        (num as i32 as f64, den as i32 as f64)
+                    } else {
+                        (num as f64, den as f64)
+                    };
+
+                    if den == 0.0 {
+                        0.0
+                    } else {
+                        num / den
+                    }
+                }
+                _ => return None,
+            });
+        }
+
+        Some(match values.len() {
+            1 => ExifValue::Number(values[0]),
+            _ => ExifValue::Numbers(values),
+        })
+    }
+}
+
+fn read_tiff(data: &[u8], gps: bool) -> Option<Vec<(&'static str, ExifValue)>> {
+    let little_endian = match data.get(..2)? {
+        b"II" => true,
+        b"MM" => false,
+        _ => return None,
+    };
+
+    let tiff = Tiff {
+        data,
+        little_endian,
+    };
+
+    if tiff.u16(2)? != 42 {
+        return None;
+    }
+
+    let mut out = Vec::new();
+    let pointers = tiff.read_ifd(tiff.u32(4)? as usize, IFD0_TAGS, &mut out)?;
+
+    // Sub IFDs are only followed from IFD0, so offsets pointing back can't loop
+    for (tag, offset) in pointers {
+        match tag {
+            TAG_EXIF_IFD => {
+                tiff.read_ifd(offset as usize, EXIF_TAGS, &mut out);
+            }
+            TAG_GPS_IFD if gps => {
+                tiff.read_ifd(offset as usize, GPS_TAGS, &mut out);
+            }
+            _ => {}
+        }
+    }
+
+    Some(out)
+}
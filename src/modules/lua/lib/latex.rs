@@ -0,0 +1,140 @@
+use anyhow::Result;
+use futures::TryStreamExt;
+use governor::{
+    state::{direct::NotKeyed, InMemoryState},
+    Quota, RateLimiter,
+};
+use hyper::{Body, Client, Request};
+use hyper_tls::HttpsConnector;
+use mlua::{prelude::*, Lua};
+use std::{num::NonZeroU32, sync::Arc};
+use thiserror::Error;
+
+use super::super::{
+    clock::LuaClock,
+    state::{get_sandbox_state, SandboxError},
+};
+use super::r#async::AsyncSender;
+use crate::{bot::Bot, config::ConfigLatex};
+
+const MAX_EXPRESSION_LENGTH: usize = 1000;
+const MAX_RENDER_SIZE: usize = 1024 * 1024 * 2; // Max 2MB
+const SANDBOX_RENDERS_PER_MINUTE: u32 = 6;
+const PNG_SIGNATURE: &[u8] = b"\x89PNG\r\n\x1a\n";
+
+async fn render(config: &ConfigLatex, expr: &str) -> Result<Vec<u8>> {
+    let encoded: String = url::form_urlencoded::byte_serialize(expr.as_bytes()).collect();
+    // Spaces are encoded as "+" which is not understood in the path of most services
+    let uri = config.url.replace("{}", &encoded.replace('+', "%20"));
+
+    let client = Client::builder().build::<_, Body>(HttpsConnector::new());
+    let req = Request::builder()
+        .method("GET")
+        .uri(uri)
+        .body(Body::empty())?;
+    let mut res = client.request(req).await?;
+
+    if !res.status().is_success() {
+        return Err(LatexError::RenderFailed(res.status().as_u16()).into());
+    }
+
+    let data = res
+        .body_mut()
+        .map_err(|e: hyper::Error| e.into())
+        .try_fold(Vec::new(), |mut data, chunk| async move {
+            data.extend_from_slice(&chunk);
+
+            if data.len() > MAX_RENDER_SIZE {
+                return Err(anyhow::anyhow!("max body size limit reached")).into();
+            }
+
+            Ok(data)
+        })
+        .await?;
+
+    if !data.starts_with(PNG_SIGNATURE) {
+        return Err(LatexError::NotPng.into());
+    }
+
+    Ok(data)
+}
+
+pub fn lib_latex(state: &Lua, bot: &Arc<Bot>, sender: AsyncSender, clock: &LuaClock) -> Result<()> {
+    let latex = state.create_table()?;
+    let config = bot.config().latex.clone();
+
+    // latex.available
+    let available = config.is_some();
+    let available_fn = state.create_function(move |_state, (): ()| Ok(available))?;
+    latex.set("available", available_fn)?;
+
+    // Shared between every sandbox run, renders from the bot state are not limited
+    let sandbox_limiter: Arc<RateLimiter<NotKeyed, InMemoryState, LuaClock>> =
+        Arc::new(RateLimiter::direct_with_clock(
+            Quota::per_minute(NonZeroU32::new(SANDBOX_RENDERS_PER_MINUTE).unwrap()),
+            clock,
+        ));
+
+    // latex.render(expr)
+    let render_fn = state.create_function(move |state, expr: String| {
+        if let Some(sandbox_state) = get_sandbox_state(state) {
+            if sandbox_state.limits().latex_renders_left_limit() {
+                return Err(LuaError::ExternalError(Arc::new(
+                    SandboxError::LimitReached("latex render"),
+                )));
+            }
+
+            if sandbox_limiter.check().is_err() {
+                return Err(LuaError::ExternalError(Arc::new(LatexError::RateLimited)));
+            }
+        }
+
+        if expr.len() > MAX_EXPRESSION_LENGTH {
+            return Err(LuaError::ExternalError(Arc::new(
+                LatexError::ExpressionTooLong(MAX_EXPRESSION_LENGTH),
+            )));
+        }
+
+        let config = config.clone();
+
+        let fut = create_lua_future!(
+            state,
+            sender,
+            (),
+            async move {
+                let config = config.ok_or(LatexError::NotConfigured)?;
+
+                render(&config, &expr).await
+            },
+            |state, _data: (), res: Result<Vec<u8>>| {
+                // The same shape as message attachments so it can be sent as is
+                let attachment = state.create_table()?;
+                attachment.set("filename", "latex.png")?;
+                attachment.set("data", state.create_string(&res?)?)?;
+
+                Ok(attachment)
+            }
+        );
+
+        Ok(fut)
+    })?;
+    latex.set("render", render_fn)?;
+
+    state.globals().set("latex", latex)?;
+
+    Ok(())
+}
+
+#[derive(Debug, Error)]
+pub enum LatexError {
+    #[error("no latex renderer has been configured")]
+    NotConfigured,
+    #[error("latex renders are rate limited, try again later")]
+    RateLimited,
+    #[error("the expression is longer than {} characters", _0)]
+    ExpressionTooLong(usize),
+    #[error("the latex renderer failed ({})", _0)]
+    RenderFailed(u16),
+    #[error("the latex renderer did not return a png")]
+    NotPng,
+}
@@ -0,0 +1,225 @@
+use anyhow::Result;
+use mlua::{prelude::*, Lua};
+use std::sync::Arc;
+
+use super::{
+    bot::{BotChannel, BotMessage, BotUser},
+    r#async::AsyncSender,
+};
+use crate::{
+    bot::{permissions::PermissionError, Bot},
+    modules::moderation::ModerationError,
+    services::{ChannelId, ServerId},
+};
+
+// Only admins can moderate, and never other admins
+async fn check_moderator(
+    bot: &Arc<Bot>,
+    msg: &BotMessage,
+    location: (ServerId, ChannelId),
+    target: Option<&BotUser>,
+) -> Result<()> {
+    if !bot
+        .permissions()
+        .is_admin(bot, msg.author().db_user(), Some(location))
+        .await?
+    {
+        return Err(PermissionError::NotAdmin.into());
+    }
+
+    if let Some(target) = target {
+        if bot
+            .permissions()
+            .is_admin(bot, target.db_user(), Some(location))
+            .await?
+        {
+            return Err(ModerationError::ProtectedUser.into());
+        }
+    }
+
+    Ok(())
+}
+
+fn msg_location(msg: &BotMessage) -> (ServerId, ChannelId) {
+    (msg.channel().server().id(), msg.channel().id())
+}
+
+// bot state only
+pub fn lib_moderation(state: &Lua, bot: &Arc<Bot>, sender: AsyncSender) -> Result<()> {
+    let moderation = state.create_table()?;
+
+    // mod.kick
+    let bot2 = bot.clone();
+    let sender2 = sender.clone();
+    let kick_fn = state.create_function(
+        move |state, (msg, user, reason): (LuaAnyUserData, LuaAnyUserData, Option<String>)| {
+            let bot = bot2.clone();
+            let msg = msg.borrow::<BotMessage>()?.clone();
+            let user = user.borrow::<BotUser>()?.clone();
+
+            let fut = create_lua_future!(
+                state,
+                sender2,
+                (),
+                async move {
+                    let location = msg_location(&msg);
+                    check_moderator(&bot, &msg, location, Some(&user)).await?;
+
+                    bot.get_ctx()
+                        .modules()
+                        .moderation
+                        .module()
+                        .kick(location.0, user.id(), reason, msg.author().uid())
+                        .await
+                },
+                |_state, _data: (), res: Result<()>| { res }
+            );
+
+            Ok(fut)
+        },
+    )?;
+    moderation.set("kick", kick_fn)?;
+
+    // mod.ban
+    let bot2 = bot.clone();
+    let sender2 = sender.clone();
+    let ban_fn = state.create_function(
+        move |state,
+              (msg, user, reason, duration): (
+            LuaAnyUserData,
+            LuaAnyUserData,
+            Option<String>,
+            Option<u64>,
+        )| {
+            let bot = bot2.clone();
+            let msg = msg.borrow::<BotMessage>()?.clone();
+            let user = user.borrow::<BotUser>()?.clone();
+
+            let fut = create_lua_future!(
+                state,
+                sender2,
+                (),
+                async move {
+                    let location = msg_location(&msg);
+                    check_moderator(&bot, &msg, location, Some(&user)).await?;
+
+                    bot.get_ctx()
+                        .modules()
+                        .moderation
+                        .module()
+                        .ban(location.0, user.id(), reason, duration, msg.author().uid())
+                        .await
+                },
+                |_state, _data: (), res: Result<()>| { res }
+            );
+
+            Ok(fut)
+        },
+    )?;
+    moderation.set("ban", ban_fn)?;
+
+    // mod.unban
+    let bot2 = bot.clone();
+    let sender2 = sender.clone();
+    let unban_fn = state.create_function(
+        move |state, (msg, user): (LuaAnyUserData, LuaAnyUserData)| {
+            let bot = bot2.clone();
+            let msg = msg.borrow::<BotMessage>()?.clone();
+            let user = user.borrow::<BotUser>()?.clone();
+
+            let fut = create_lua_future!(
+                state,
+                sender2,
+                (),
+                async move {
+                    let location = msg_location(&msg);
+                    check_moderator(&bot, &msg, location, None).await?;
+
+                    bot.get_ctx()
+                        .modules()
+                        .moderation
+                        .module()
+                        .unban(location.0, user.id(), msg.author().uid())
+                        .await
+                },
+                |_state, _data: (), res: Result<()>| { res }
+            );
+
+            Ok(fut)
+        },
+    )?;
+    moderation.set("unban", unban_fn)?;
+
+    // mod.timeout
+    let bot2 = bot.clone();
+    let sender2 = sender.clone();
+    let timeout_fn = state.create_function(
+        move |state,
+              (msg, user, duration, reason): (
+            LuaAnyUserData,
+            LuaAnyUserData,
+            Option<u64>,
+            Option<String>,
+        )| {
+            let bot = bot2.clone();
+            let msg = msg.borrow::<BotMessage>()?.clone();
+            let user = user.borrow::<BotUser>()?.clone();
+
+            let fut = create_lua_future!(
+                state,
+                sender2,
+                (),
+                async move {
+                    let location = msg_location(&msg);
+                    check_moderator(&bot, &msg, location, Some(&user)).await?;
+
+                    bot.get_ctx()
+                        .modules()
+                        .moderation
+                        .module()
+                        .timeout(location.0, user.id(), duration, reason, msg.author().uid())
+                        .await
+                },
+                |_state, _data: (), res: Result<()>| { res }
+            );
+
+            Ok(fut)
+        },
+    )?;
+    moderation.set("timeout", timeout_fn)?;
+
+    // mod.purge
+    let bot2 = bot.clone();
+    let sender2 = sender.clone();
+    let purge_fn = state.create_function(
+        move |state, (msg, channel, count): (LuaAnyUserData, BotChannel, u64)| {
+            let bot = bot2.clone();
+            let msg = msg.borrow::<BotMessage>()?.clone();
+
+            let fut = create_lua_future!(
+                state,
+                sender2,
+                (),
+                async move {
+                    let location = (channel.server().id(), channel.id());
+                    check_moderator(&bot, &msg, location, None).await?;
+
+                    bot.get_ctx()
+                        .modules()
+                        .moderation
+                        .module()
+                        .purge(location.0, location.1, count, msg.author().uid())
+                        .await
+                },
+                |_state, _data: (), res: Result<usize>| { res }
+            );
+
+            Ok(fut)
+        },
+    )?;
+    moderation.set("purge", purge_fn)?;
+
+    state.globals().set("mod", moderation)?;
+
+    Ok(())
+}
@@ -1,25 +1,79 @@
 use anyhow::Result;
-use mlua::{Error, Lua};
-use std::{sync::Arc, time::SystemTime};
+use mlua::{Error, Lua, Table};
+use std::{sync::Arc, time::Duration};
 
-use super::super::utils::get_duration;
+use super::super::clock::LuaClock;
+use crate::bot::Bot;
 
-pub fn lib_os(state: &Lua) -> Result<()> {
-    let os = state.create_table()?;
+// Sandboxed scripts get coarser timers, fine enough to measure code but not to time the host
+const SANDBOX_TIMER_RESOLUTION: u128 = 1000;
+
+pub fn lib_os(state: &Lua, bot: &Arc<Bot>, clock: LuaClock, sandbox: bool) -> Result<()> {
+    // Extend the standard os library when the config loads it
+    let os = match state.globals().get::<_, Option<Table>>("os")? {
+        Some(os) => os,
+        None => state.create_table()?,
+    };
 
     // os.clock
-    let os_clock = state.create_function(|_, ()| Ok(get_duration()))?;
+    let clock2 = clock.clone();
+    let os_clock = state.create_function(move |_, ()| Ok(clock2.elapsed().as_secs_f64()))?;
     os.set("clock", os_clock)?;
 
     // os.time
-    let os_time = state.create_function(|_, ()| {
-        Ok(SystemTime::now()
-            .duration_since(SystemTime::UNIX_EPOCH)
-            .map_err(|err| Error::ExternalError(Arc::new(err)))?
-            .as_secs())
-    })?;
+    let clock2 = clock.clone();
+    let os_time = state.create_function(move |_, ()| Ok(clock2.utc_now().timestamp()))?;
     os.set("time", os_time)?;
 
+    // os.clock_monotonic, nanoseconds that never go backwards, only differences are meaningful
+    let clock2 = clock.clone();
+    let os_clock_monotonic = state.create_function(move |_, ()| {
+        let mut nanos = clock2.elapsed().as_nanos();
+
+        if sandbox {
+            nanos -= nanos % SANDBOX_TIMER_RESOLUTION;
+        }
+
+        Ok(nanos as i64)
+    })?;
+    os.set("clock_monotonic", os_clock_monotonic)?;
+
+    // os.uptime, seconds since the bot started, the fake clock stands in for it in tests
+    let bot2 = bot.clone();
+    let clock2 = clock.clone();
+    let os_uptime = state.create_function(move |_, ()| {
+        Ok(if clock2.is_fake() {
+            clock2.elapsed().as_secs_f64()
+        } else {
+            bot2.uptime().as_secs_f64()
+        })
+    })?;
+    os.set("uptime", os_uptime)?;
+
+    // os.version
+    let os_version = state.create_function(|state, ()| {
+        let version = state.create_table()?;
+        version.set("bot", env!("CARGO_PKG_VERSION"))?;
+        version.set("lua", state.globals().get::<_, String>("_VERSION")?)?;
+
+        Ok(version)
+    })?;
+    os.set("version", os_version)?;
+
+    // os.platform, like "linux" or "windows"
+    let os_platform = state.create_function(|_, ()| Ok(std::env::consts::OS))?;
+    os.set("platform", os_platform)?;
+
+    // os.advance_clock(seconds), lets tests move the fake clock of the deterministic mode
+    if clock.is_fake() && !sandbox {
+        let os_advance_clock = state.create_function(move |_, seconds: f64| {
+            clock
+                .advance(Duration::from_secs_f64(seconds.max(0.0)))
+                .map_err(|err| Error::ExternalError(Arc::new(err)))
+        })?;
+        os.set("advance_clock", os_advance_clock)?;
+    }
+
     state.globals().set("os", os)?;
 
     Ok(())
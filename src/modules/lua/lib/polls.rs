@@ -0,0 +1,140 @@
+use anyhow::Result;
+use mlua::{prelude::*, Lua};
+use std::sync::Arc;
+
+use super::{
+    bot::{BotChannel, BotUser},
+    r#async::AsyncSender,
+};
+use crate::{bot::Bot, modules::polls::PollResults, services::MessageId};
+
+fn results_table<'a>(state: &'a Lua, results: PollResults) -> LuaResult<LuaTable<'a>> {
+    let tbl = state.create_table()?;
+    let options = state.create_table()?;
+
+    for (i, (label, votes)) in results.options.iter().zip(results.votes.iter()).enumerate() {
+        let option = state.create_table()?;
+
+        option.set("label", label.as_str())?;
+        option.set("votes", *votes)?;
+
+        options.set(i + 1, option)?;
+    }
+
+    tbl.set("message_id", results.poll.message_id.as_str())?;
+    tbl.set("channel_id", results.poll.channel_id.as_str())?;
+    tbl.set("title", results.poll.title.as_str())?;
+    tbl.set("created_by", results.poll.created_by)?;
+    tbl.set("close_time", results.poll.close_time)?;
+    tbl.set("closed", results.poll.closed)?;
+    tbl.set("total", results.total())?;
+    tbl.set("winners", results.winners())?;
+    tbl.set("options", options)?;
+
+    Ok(tbl)
+}
+
+fn parse_message_id(message_id: &str) -> LuaResult<MessageId> {
+    MessageId::from_str(message_id).map_err(|err| LuaError::RuntimeError(err.to_string()))
+}
+
+// bot state only
+pub fn lib_polls(state: &Lua, bot: &Arc<Bot>, sender: AsyncSender) -> Result<()> {
+    let polls = state.create_table()?;
+
+    // polls.create
+    let bot2 = bot.clone();
+    let sender2 = sender.clone();
+    let create_fn = state.create_function(
+        move |state, (channel, author, options): (BotChannel, LuaAnyUserData, LuaTable)| {
+            let bot = bot2.clone();
+            let author = author.borrow::<BotUser>()?.clone();
+
+            let title: String = options.get("title")?;
+            let poll_options: Vec<String> = options.get("options")?;
+            let buttons: Option<bool> = options.get("buttons")?;
+            let duration: Option<u64> = options.get("duration")?;
+
+            let fut = create_lua_future!(
+                state,
+                sender2,
+                (),
+                async move {
+                    bot.get_ctx()
+                        .modules()
+                        .polls
+                        .module()
+                        .create(
+                            channel.id(),
+                            title,
+                            poll_options,
+                            buttons.unwrap_or(false),
+                            duration,
+                            author.uid(),
+                        )
+                        .await
+                },
+                |_state, _data: (), res: Result<MessageId>| { Ok(res?.to_short_str()) }
+            );
+
+            Ok(fut)
+        },
+    )?;
+    polls.set("create", create_fn)?;
+
+    // polls.results
+    let bot2 = bot.clone();
+    let sender2 = sender.clone();
+    let results_fn = state.create_function(move |state, message_id: String| {
+        let bot = bot2.clone();
+        let message_id = parse_message_id(&message_id)?;
+
+        let fut = create_lua_future!(
+            state,
+            sender2,
+            (),
+            async move {
+                bot.get_ctx()
+                    .modules()
+                    .polls
+                    .module()
+                    .results(message_id)
+                    .await
+            },
+            |state, _data: (), res: Result<PollResults>| { Ok(results_table(state, res?)?) }
+        );
+
+        Ok(fut)
+    })?;
+    polls.set("results", results_fn)?;
+
+    // polls.close
+    let bot2 = bot.clone();
+    let sender2 = sender.clone();
+    let close_fn = state.create_function(move |state, message_id: String| {
+        let bot = bot2.clone();
+        let message_id = parse_message_id(&message_id)?;
+
+        let fut = create_lua_future!(
+            state,
+            sender2,
+            (),
+            async move {
+                bot.get_ctx()
+                    .modules()
+                    .polls
+                    .module()
+                    .close(message_id)
+                    .await
+            },
+            |state, _data: (), res: Result<PollResults>| { Ok(results_table(state, res?)?) }
+        );
+
+        Ok(fut)
+    })?;
+    polls.set("close", close_fn)?;
+
+    state.globals().set("polls", polls)?;
+
+    Ok(())
+}
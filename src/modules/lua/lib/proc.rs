@@ -0,0 +1,185 @@
+use anyhow::Result;
+use mlua::{prelude::*, Lua};
+use std::{
+    path::{Path, PathBuf},
+    process::Stdio,
+    sync::Arc,
+    time::Duration,
+};
+use thiserror::Error;
+use tokio::{
+    io::{AsyncRead, AsyncReadExt, AsyncWriteExt},
+    process::Command,
+};
+
+use super::{super::state::caller_is_owner, r#async::AsyncSender};
+use crate::{bot::Bot, config::ConfigProc};
+
+const DEFAULT_TIMEOUT: u64 = 10;
+const DEFAULT_MAX_OUTPUT: usize = 64 * 1024;
+
+#[derive(Error, Debug)]
+pub enum ProcError {
+    #[error("running processes is disabled")]
+    Disabled,
+    #[error("only the bot owner can run processes")]
+    Forbidden,
+    #[error("\"{0}\" is not an allowed binary")]
+    NotAllowed(String),
+    #[error("process timed out after {0} seconds")]
+    Timeout(u64),
+}
+
+struct ProcOutput {
+    status: Option<i32>,
+    stdout: Vec<u8>,
+    stderr: Vec<u8>,
+    truncated: bool,
+}
+
+async fn read_capped(
+    reader: Option<impl AsyncRead + Unpin>,
+    max: usize,
+) -> Result<(Vec<u8>, bool)> {
+    let mut out = Vec::new();
+
+    if let Some(reader) = reader {
+        let mut reader = reader.take(max as u64 + 1);
+        reader.read_to_end(&mut out).await?;
+
+        // Drain the rest so the process doesn't block on a full pipe
+        tokio::io::copy(&mut reader.into_inner(), &mut tokio::io::sink()).await?;
+    }
+
+    let truncated = out.len() > max;
+    out.truncate(max);
+
+    Ok((out, truncated))
+}
+
+async fn run_process(
+    config: &ConfigProc,
+    dir: &Path,
+    cmd: &str,
+    args: Vec<String>,
+    stdin: Option<Vec<u8>>,
+    timeout: u64,
+) -> Result<ProcOutput> {
+    let max_output = config.max_output.unwrap_or(DEFAULT_MAX_OUTPUT);
+
+    let mut child = Command::new(cmd)
+        .args(args)
+        .current_dir(dir)
+        .stdin(if stdin.is_some() {
+            Stdio::piped()
+        } else {
+            Stdio::null()
+        })
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true)
+        .spawn()?;
+
+    let child_stdin = child.stdin.take();
+    let child_stdout = child.stdout.take();
+    let child_stderr = child.stderr.take();
+
+    let run = async {
+        let write_stdin = async {
+            if let (Some(mut child_stdin), Some(stdin)) = (child_stdin, stdin) {
+                // The process may exit without reading its input
+                let _ = child_stdin.write_all(&stdin).await;
+            }
+        };
+
+        let (_, stdout, stderr) = futures::join!(
+            write_stdin,
+            read_capped(child_stdout, max_output),
+            read_capped(child_stderr, max_output)
+        );
+        let ((stdout, stdout_truncated), (stderr, stderr_truncated)) = (stdout?, stderr?);
+
+        let status = child.wait().await?;
+
+        Ok::<_, anyhow::Error>(ProcOutput {
+            status: status.code(),
+            stdout,
+            stderr,
+            truncated: stdout_truncated || stderr_truncated,
+        })
+    };
+
+    match tokio::time::timeout(Duration::from_secs(timeout), run).await {
+        Ok(res) => res,
+        Err(_) => Err(ProcError::Timeout(timeout).into()),
+    }
+}
+
+pub fn lib_proc(state: &Lua, bot: &Arc<Bot>, sender: AsyncSender) -> Result<()> {
+    let proc = state.create_table()?;
+    let config = bot.config().proc.clone().filter(|config| config.enabled);
+    let dir: PathBuf = bot.data_path().join("lua_data");
+
+    // proc.enabled
+    let enabled = config.is_some();
+    let proc_enabled = state.create_function(move |_, ()| Ok(enabled))?;
+    proc.set("enabled", proc_enabled)?;
+
+    // proc.run(cmd, args, { stdin, timeout }), only from commands run by a bot owner
+    let proc_run = state.create_function(
+        move |state, (cmd, args, options): (String, Option<Vec<String>>, Option<LuaTable>)| {
+            let config = match &config {
+                Some(config) => config.clone(),
+                None => return Err(LuaError::ExternalError(Arc::new(ProcError::Disabled))),
+            };
+
+            if !caller_is_owner(state) {
+                return Err(LuaError::ExternalError(Arc::new(ProcError::Forbidden)));
+            }
+
+            // Only bare names from the allowlist, resolved through PATH
+            if cmd.contains('/') || !config.allowed.contains(&cmd) {
+                return Err(LuaError::ExternalError(Arc::new(ProcError::NotAllowed(
+                    cmd,
+                ))));
+            }
+
+            let (stdin, timeout): (Option<LuaString>, Option<u64>) = match options {
+                Some(options) => (options.get("stdin")?, options.get("timeout")?),
+                None => (None, None),
+            };
+            let stdin = stdin.map(|stdin| stdin.as_bytes().to_vec());
+
+            let max_timeout = config.timeout.unwrap_or(DEFAULT_TIMEOUT);
+            let timeout = timeout.map_or(max_timeout, |timeout| timeout.min(max_timeout));
+
+            let dir = dir.clone();
+            let args = args.unwrap_or_default();
+
+            let fut = create_lua_future!(
+                state,
+                sender,
+                (),
+                async move { run_process(&config, &dir, &cmd, args, stdin, timeout).await },
+                |state, _data: (), res: Result<ProcOutput>| {
+                    let output = res?;
+                    let tbl = state.create_table()?;
+
+                    tbl.set("status", output.status)?;
+                    tbl.set("stdout", state.create_string(&output.stdout)?)?;
+                    tbl.set("stderr", state.create_string(&output.stderr)?)?;
+                    tbl.set("truncated", output.truncated)?;
+
+                    Ok(tbl)
+                }
+            );
+
+            Ok(fut)
+        },
+    )?;
+    proc.set("run", proc_run)?;
+
+    state.globals().set("proc", proc)?;
+
+    Ok(())
+}
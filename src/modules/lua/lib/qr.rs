@@ -0,0 +1,172 @@
+use anyhow::Result;
+use mlua::{prelude::*, Lua};
+use qrcode::{Color, QrCode};
+use std::sync::Arc;
+use thiserror::Error;
+
+use super::{
+    super::state::{get_sandbox_state, SandboxError},
+    image::Image,
+    r#async::AsyncSender,
+};
+
+const MAX_TEXT_LENGTH: usize = 1024;
+const DEFAULT_SCALE: u32 = 8;
+const MAX_SCALE: u32 = 16;
+const QUIET_ZONE: u32 = 4;
+
+fn encode_png(text: &str, scale: u32) -> Result<Vec<u8>> {
+    let code = QrCode::new(text.as_bytes())?;
+    let modules = code.width() as u32;
+    let colors = code.to_colors();
+
+    let size = (modules + QUIET_ZONE * 2) * scale;
+    let mut pixels = vec![255u8; (size * size) as usize];
+
+    for (i, color) in colors.iter().enumerate() {
+        if *color != Color::Dark {
+            continue;
+        }
+
+        let x = (i as u32 % modules + QUIET_ZONE) * scale;
+        let y = (i as u32 / modules + QUIET_ZONE) * scale;
+
+        for py in y..y + scale {
+            let row = (py * size) as usize;
+            pixels[row + x as usize..row + (x + scale) as usize].fill(0);
+        }
+    }
+
+    let mut data = Vec::new();
+    {
+        let mut encoder = png::Encoder::new(&mut data, size, size);
+        encoder.set_color(png::ColorType::Grayscale);
+        encoder.set_depth(png::BitDepth::Eight);
+
+        let mut writer = encoder.write_header()?;
+        writer.write_image_data(&pixels)?;
+    }
+
+    Ok(data)
+}
+
+fn decode_image(image: Image) -> Result<Vec<String>> {
+    // Let the image library handle every input format and decode the png it produces
+    let png_data = {
+        let mut wand = image.get_wand()?;
+        wand.set_image_format("PNG")?;
+        wand.write_image_blob()
+            .ok_or_else(|| anyhow::anyhow!("unable to convert image"))?
+    };
+
+    let mut decoder = png::Decoder::new(&png_data[..]);
+    decoder.set_transformations(png::Transformations::EXPAND | png::Transformations::STRIP_16);
+
+    let mut reader = decoder.read_info()?;
+    let mut buffer = vec![0; reader.output_buffer_size()];
+    let info = reader.next_frame(&mut buffer)?;
+
+    let channels = match info.color_type {
+        png::ColorType::Grayscale => 1,
+        png::ColorType::GrayscaleAlpha => 2,
+        png::ColorType::Rgb | png::ColorType::Indexed => 3,
+        png::ColorType::Rgba => 4,
+    };
+
+    let (width, height) = (info.width as usize, info.height as usize);
+    let luma = |x: usize, y: usize| {
+        let offset = y * info.line_size + x * channels;
+
+        if channels < 3 {
+            buffer[offset]
+        } else {
+            let (r, g, b) = (buffer[offset], buffer[offset + 1], buffer[offset + 2]);
+            ((r as u32 * 299 + g as u32 * 587 + b as u32 * 114) / 1000) as u8
+        }
+    };
+
+    let mut prepared = rqrr::PreparedImage::prepare_from_greyscale(width, height, luma);
+
+    Ok(prepared
+        .detect_grids()
+        .into_iter()
+        .filter_map(|grid| grid.decode().ok())
+        .map(|(_meta, content)| content)
+        .collect())
+}
+
+pub fn lib_qr(state: &Lua, sender: AsyncSender) -> Result<()> {
+    let qr = state.create_table()?;
+
+    // qr.encode(text, { scale })
+    let encode_fn =
+        state.create_function(|state, (text, options): (String, Option<LuaTable>)| {
+            if let Some(sandbox_state) = get_sandbox_state(state) {
+                if sandbox_state.limits().images_left_limit() {
+                    return Err(LuaError::ExternalError(Arc::new(
+                        SandboxError::LimitReached("image"),
+                    )));
+                }
+            }
+
+            if text.len() > MAX_TEXT_LENGTH {
+                return Err(LuaError::ExternalError(Arc::new(QrError::TextTooLong(
+                    MAX_TEXT_LENGTH,
+                ))));
+            }
+
+            let scale = match options {
+                Some(options) => options.get::<_, Option<u32>>("scale")?,
+                None => None,
+            };
+            let scale = scale.unwrap_or(DEFAULT_SCALE).clamp(1, MAX_SCALE);
+
+            let data =
+                encode_png(&text, scale).map_err(|err| LuaError::RuntimeError(err.to_string()))?;
+
+            // The same shape as message attachments so it can be sent as is
+            let attachment = state.create_table()?;
+            attachment.set("filename", "qr.png")?;
+            attachment.set("data", state.create_string(&data)?)?;
+
+            Ok(attachment)
+        })?;
+    qr.set("encode", encode_fn)?;
+
+    // qr.decode(image)
+    let decode_fn = state.create_function(move |state, image: LuaAnyUserData| {
+        if get_sandbox_state(state).is_some() {
+            return Err(LuaError::RuntimeError(
+                "qr codes can't be decoded from the sandbox".into(),
+            ));
+        }
+
+        let image = image.borrow::<Image>()?.clone();
+
+        let fut = create_lua_future!(
+            state,
+            sender,
+            (),
+            async move {
+                match tokio::task::spawn_blocking(move || decode_image(image)).await {
+                    Ok(res) => res,
+                    Err(err) => Err(err.into()),
+                }
+            },
+            |_state, _data: (), res: Result<Vec<String>>| { res }
+        );
+
+        Ok(fut)
+    })?;
+    qr.set("decode", decode_fn)?;
+
+    state.globals().set("qr", qr)?;
+
+    Ok(())
+}
+
+#[derive(Debug, Error)]
+pub enum QrError {
+    #[error("the text is longer than {} bytes", _0)]
+    TextTooLong(usize),
+}
@@ -0,0 +1,182 @@
+use anyhow::Result;
+use mlua::{prelude::*, Lua};
+use std::sync::Arc;
+
+use super::{
+    bot::{BotMessage, BotServer, BotUser},
+    r#async::AsyncSender,
+};
+use crate::bot::{
+    db::{NewQuote, Quote},
+    Bot,
+};
+
+fn quote_table<'a>(state: &'a Lua, quote: Quote) -> LuaResult<LuaTable<'a>> {
+    let tbl = state.create_table()?;
+
+    tbl.set("number", quote.number)?;
+    tbl.set("author_id", quote.author_id)?;
+    tbl.set("author_name", quote.author_name)?;
+    tbl.set("content", quote.content)?;
+    tbl.set("link", quote.link)?;
+    tbl.set("added_by", quote.added_by)?;
+    tbl.set("timestamp", quote.timestamp)?;
+
+    Ok(tbl)
+}
+
+fn optional_quote_value<'a>(state: &'a Lua, quote: Option<Quote>) -> Result<LuaValue<'a>> {
+    Ok(match quote {
+        Some(quote) => LuaValue::Table(quote_table(state, quote)?),
+        None => LuaValue::Nil,
+    })
+}
+
+// bot state only
+pub fn lib_quotes(state: &Lua, bot: &Arc<Bot>, sender: AsyncSender) -> Result<()> {
+    let quotes = state.create_table()?;
+
+    // quotes.add, resolves to the quote number or nil if the message was quoted before
+    let bot2 = bot.clone();
+    let sender2 = sender.clone();
+    let add_fn = state.create_function(
+        move |state, (msg, actor): (LuaAnyUserData, LuaAnyUserData)| {
+            let bot = bot2.clone();
+            let msg = msg.borrow::<BotMessage>()?.clone();
+            let actor = actor.borrow::<BotUser>()?.clone();
+
+            let fut = create_lua_future!(
+                state,
+                sender2,
+                (),
+                async move {
+                    let sid = bot.db().get_sid(msg.channel().server().id()).await?;
+                    let quote = NewQuote {
+                        message_id: msg.id(),
+                        author_id: msg.author().id(),
+                        author_name: msg.author().name(),
+                        content: msg.content(),
+                        link: msg.link(),
+                    };
+
+                    bot.db().add_quote(sid, &quote, actor.uid()).await
+                },
+                |_state, _data: (), res: Result<Option<i64>>| { res }
+            );
+
+            Ok(fut)
+        },
+    )?;
+    quotes.set("add", add_fn)?;
+
+    // quotes.get
+    let bot2 = bot.clone();
+    let sender2 = sender.clone();
+    let get_fn = state.create_function(move |state, (server, number): (LuaAnyUserData, i64)| {
+        let bot = bot2.clone();
+        let server = server.borrow::<BotServer>()?.clone();
+
+        let fut = create_lua_future!(
+            state,
+            sender2,
+            (),
+            async move {
+                let sid = bot.db().get_sid(server.id()).await?;
+
+                bot.db().quote(sid, number).await
+            },
+            |state, _data: (), res: Result<Option<Quote>>| { optional_quote_value(state, res?) }
+        );
+
+        Ok(fut)
+    })?;
+    quotes.set("get", get_fn)?;
+
+    // quotes.random
+    let bot2 = bot.clone();
+    let sender2 = sender.clone();
+    let random_fn = state.create_function(move |state, server: LuaAnyUserData| {
+        let bot = bot2.clone();
+        let server = server.borrow::<BotServer>()?.clone();
+
+        let fut = create_lua_future!(
+            state,
+            sender2,
+            (),
+            async move {
+                let sid = bot.db().get_sid(server.id()).await?;
+
+                bot.db().random_quote(sid).await
+            },
+            |state, _data: (), res: Result<Option<Quote>>| { optional_quote_value(state, res?) }
+        );
+
+        Ok(fut)
+    })?;
+    quotes.set("random", random_fn)?;
+
+    // quotes.search
+    let bot2 = bot.clone();
+    let sender2 = sender.clone();
+    let search_fn = state.create_function(
+        move |state, (server, text): (LuaAnyUserData, Option<String>)| {
+            let bot = bot2.clone();
+            let server = server.borrow::<BotServer>()?.clone();
+
+            let fut = create_lua_future!(
+                state,
+                sender2,
+                (),
+                async move {
+                    let sid = bot.db().get_sid(server.id()).await?;
+
+                    bot.db()
+                        .search_quotes(sid, text.as_deref().unwrap_or(""))
+                        .await
+                },
+                |state, _data: (), res: Result<Vec<Quote>>| {
+                    let tbl = state.create_table()?;
+
+                    for (i, quote) in res?.into_iter().enumerate() {
+                        tbl.set(i + 1, quote_table(state, quote)?)?;
+                    }
+
+                    Ok(tbl)
+                }
+            );
+
+            Ok(fut)
+        },
+    )?;
+    quotes.set("search", search_fn)?;
+
+    // quotes.remove
+    let bot2 = bot.clone();
+    let sender2 = sender.clone();
+    let remove_fn = state.create_function(
+        move |state, (server, number, actor): (LuaAnyUserData, i64, LuaAnyUserData)| {
+            let bot = bot2.clone();
+            let server = server.borrow::<BotServer>()?.clone();
+            let actor = actor.borrow::<BotUser>()?.clone();
+
+            let fut = create_lua_future!(
+                state,
+                sender2,
+                (),
+                async move {
+                    let sid = bot.db().get_sid(server.id()).await?;
+
+                    bot.db().remove_quote(sid, number, actor.uid()).await
+                },
+                |_state, _data: (), res: Result<bool>| { res }
+            );
+
+            Ok(fut)
+        },
+    )?;
+    quotes.set("remove", remove_fn)?;
+
+    state.globals().set("quotes", quotes)?;
+
+    Ok(())
+}
@@ -0,0 +1,172 @@
+use anyhow::Result;
+use mlua::{prelude::*, MetaMethod, UserData, UserDataMethods};
+use std::{sync::Arc, time::Duration};
+use thiserror::Error;
+
+use super::r#async::AsyncSender;
+use crate::{
+    bot::{
+        db::{ScheduledReply, Uid},
+        Bot,
+    },
+    message::MessageSettings,
+    services::{ChannelId, MessageId},
+};
+
+const DELIVERY_INTERVAL: Duration = Duration::from_secs(15);
+// Furthest a reply can be scheduled ahead, in seconds
+const MAX_SCHEDULE_AHEAD: i64 = 90 * 24 * 60 * 60;
+const MAX_PENDING_REPLIES: i64 = 50;
+const MAX_SCHEDULED_REPLY_LEN: usize = 4000;
+const MAX_SCHEDULED_REPLY_PARTS: usize = 4;
+
+/// Check and persist a reply to send once the time has come
+pub async fn schedule_reply(
+    bot: &Bot,
+    channel_id: ChannelId,
+    message_id: MessageId,
+    created_by: Uid,
+    send_time: i64,
+    content: &str,
+) -> Result<i64> {
+    let now = chrono::Utc::now().timestamp();
+
+    if send_time <= now {
+        return Err(ScheduleError::InPast.into());
+    }
+
+    if send_time - now > MAX_SCHEDULE_AHEAD {
+        return Err(ScheduleError::TooFarAhead(MAX_SCHEDULE_AHEAD / (24 * 60 * 60)).into());
+    }
+
+    if content.trim().is_empty() {
+        return Err(ScheduleError::EmptyContent.into());
+    }
+
+    if content.chars().count() > MAX_SCHEDULED_REPLY_LEN {
+        return Err(ScheduleError::TooLong(MAX_SCHEDULED_REPLY_LEN).into());
+    }
+
+    if bot.db().scheduled_reply_count(created_by).await? >= MAX_PENDING_REPLIES {
+        return Err(ScheduleError::TooManyPending(MAX_PENDING_REPLIES).into());
+    }
+
+    bot.db()
+        .add_scheduled_reply(channel_id, message_id, content, created_by, send_time)
+        .await
+}
+
+async fn deliver_due_replies(bot: &Bot) -> Result<()> {
+    let due = bot
+        .db()
+        .due_scheduled_replies(chrono::Utc::now().timestamp())
+        .await?;
+
+    for ScheduledReply {
+        id,
+        channel_id,
+        message_id,
+        content,
+        ..
+    } in due
+    {
+        // Cancelled in the meantime
+        if !bot.db().remove_scheduled_reply(id).await? {
+            continue;
+        }
+
+        // Replies are sent at most once, a failed one is only logged
+        let res = async {
+            bot.get_ctx()
+                .services()
+                .send_split(
+                    ChannelId::from_str(&channel_id)?,
+                    content,
+                    MessageSettings {
+                        reply: Some(MessageId::from_str(&message_id)?),
+                        ..Default::default()
+                    },
+                    MAX_SCHEDULED_REPLY_PARTS,
+                )
+                .await
+                .map(|_| ())
+        }
+        .await;
+
+        if let Err(err) = res {
+            println!(
+                "error sending the scheduled reply {} in {}: {}",
+                id,
+                channel_id,
+                err.to_string()
+            );
+        }
+    }
+
+    Ok(())
+}
+
+/// Send scheduled replies once they are due
+pub fn spawn_delivery_task(bot: Arc<Bot>) {
+    tokio::spawn(async move {
+        loop {
+            tokio::time::sleep(DELIVERY_INTERVAL).await;
+
+            if let Err(err) = deliver_due_replies(&bot).await {
+                println!("error sending scheduled replies: {}", err.to_string());
+            }
+        }
+    });
+}
+
+/// Handle of a pending reply given out by `msg:reply_at`
+#[derive(Clone)]
+pub struct BotScheduledReply {
+    pub bot: Arc<Bot>,
+    pub sender: AsyncSender,
+    pub id: i64,
+    pub send_time: i64,
+}
+
+impl UserData for BotScheduledReply {
+    fn add_methods<'a, M: UserDataMethods<'a, Self>>(methods: &mut M) {
+        // handle:cancel, resolves to false if the reply was already sent or cancelled
+        methods.add_method("cancel", |state, reply, (): ()| {
+            let bot = reply.bot.clone();
+            let id = reply.id;
+
+            let fut = create_lua_future!(
+                state,
+                reply.sender,
+                (),
+                async move { bot.db().remove_scheduled_reply(id).await },
+                |_state, _data: (), res: Result<bool>| { Ok(res?) }
+            );
+
+            Ok(fut)
+        });
+
+        methods.add_meta_method(
+            MetaMethod::Index,
+            |_state, reply, index: String| match index.as_str() {
+                "id" => Ok(LuaValue::Integer(reply.id)),
+                "send_time" => Ok(LuaValue::Integer(reply.send_time)),
+                _ => Ok(LuaValue::Nil),
+            },
+        );
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum ScheduleError {
+    #[error("replies can only be scheduled in the future")]
+    InPast,
+    #[error("replies can't be scheduled more than {} days ahead", _0)]
+    TooFarAhead(i64),
+    #[error("scheduled replies can't be empty")]
+    EmptyContent,
+    #[error("scheduled replies can't be longer than {} characters", _0)]
+    TooLong(usize),
+    #[error("there can't be more than {} pending scheduled replies per user", _0)]
+    TooManyPending(i64),
+}
@@ -0,0 +1,282 @@
+use anyhow::Result;
+use governor::{state::keyed::DefaultKeyedStateStore, Quota, RateLimiter};
+use mlua::{prelude::*, Lua};
+use std::{num::NonZeroU32, sync::Arc};
+use thiserror::Error;
+
+use super::super::{
+    clock::LuaClock,
+    pool::SandboxPool,
+    state::{current_trace, SandboxOwner},
+    utils::TraceId,
+};
+use super::{
+    bot::{run_sandboxed_output, BotMessage, BotServer, BotUser},
+    r#async::AsyncSender,
+};
+use crate::bot::{
+    db::{ServerScript, Sid},
+    Bot,
+};
+
+const MAX_SCRIPT_NAME_LEN: usize = 20;
+const MAX_SCRIPT_SIZE: usize = 16 * 1024;
+const MAX_SERVER_SCRIPTS: usize = 25;
+const SCRIPT_RUNS_PER_MINUTE: u32 = 20;
+
+fn script_table<'a>(state: &'a Lua, script: ServerScript) -> LuaResult<LuaTable<'a>> {
+    let tbl = state.create_table()?;
+
+    tbl.set("name", script.name)?;
+    tbl.set("size", script.source.len())?;
+    tbl.set("installed_by", script.installed_by)?;
+    tbl.set("timestamp", script.timestamp)?;
+
+    Ok(tbl)
+}
+
+fn check_script_name(name: &str) -> Result<()> {
+    if name.is_empty()
+        || name.chars().count() > MAX_SCRIPT_NAME_LEN
+        || !name.chars().all(|c| c.is_alphanumeric() || c == '_')
+    {
+        return Err(ServerScriptError::InvalidName(MAX_SCRIPT_NAME_LEN).into());
+    }
+
+    Ok(())
+}
+
+// bot state only
+pub fn lib_server_scripts(
+    state: &Lua,
+    bot: &Arc<Bot>,
+    sender: AsyncSender,
+    sandbox_pool: Arc<SandboxPool>,
+    clock: LuaClock,
+) -> Result<()> {
+    let server_scripts = state.create_table()?;
+
+    // server_scripts.install, replaces the script with the same name but keeps its saved environment
+    let bot2 = bot.clone();
+    let sender2 = sender.clone();
+    let install_fn =
+        state.create_function(
+            move |state,
+                  (server, name, source, actor): (
+                LuaAnyUserData,
+                String,
+                String,
+                LuaAnyUserData,
+            )| {
+                let bot = bot2.clone();
+                let server = server.borrow::<BotServer>()?.clone();
+                let actor = actor.borrow::<BotUser>()?.clone();
+
+                let fut = create_lua_future!(
+                    state,
+                    sender2,
+                    (),
+                    async move {
+                        check_script_name(&name)?;
+
+                        if source.len() > MAX_SCRIPT_SIZE {
+                            return Err(ServerScriptError::TooLarge(MAX_SCRIPT_SIZE).into());
+                        }
+
+                        let sid = bot.db().get_sid(server.id()).await?;
+                        let scripts = bot.db().server_scripts(sid).await?;
+
+                        if scripts.len() >= MAX_SERVER_SCRIPTS
+                            && !scripts.iter().any(|script| script.name == name)
+                        {
+                            return Err(ServerScriptError::TooMany(MAX_SERVER_SCRIPTS).into());
+                        }
+
+                        bot.db()
+                            .install_server_script(sid, &name, &source, actor.uid())
+                            .await
+                    },
+                    |_state, _data: (), res: Result<()>| { res }
+                );
+
+                Ok(fut)
+            },
+        )?;
+    server_scripts.set("install", install_fn)?;
+
+    // server_scripts.uninstall, also removes the saved environment and subscriptions of the script
+    let bot2 = bot.clone();
+    let sender2 = sender.clone();
+    let uninstall_fn = state.create_function(
+        move |state, (server, name, actor): (LuaAnyUserData, String, LuaAnyUserData)| {
+            let bot = bot2.clone();
+            let server = server.borrow::<BotServer>()?.clone();
+            let actor = actor.borrow::<BotUser>()?.clone();
+
+            let fut = create_lua_future!(
+                state,
+                sender2,
+                (),
+                async move {
+                    let sid = bot.db().get_sid(server.id()).await?;
+
+                    if !bot
+                        .db()
+                        .uninstall_server_script(sid, &name, actor.uid())
+                        .await?
+                    {
+                        return Ok(false);
+                    }
+
+                    let owner = SandboxOwner::Script(sid, name);
+
+                    bot.get_ctx()
+                        .modules()
+                        .lua
+                        .module()
+                        .ipc()
+                        .remove(server.id(), &owner);
+
+                    match tokio::fs::remove_file(owner.env_path(&bot)).await {
+                        Ok(()) => {}
+                        Err(err) if err.kind() == std::io::ErrorKind::NotFound => {}
+                        Err(err) => return Err(err.into()),
+                    }
+
+                    Ok(true)
+                },
+                |_state, _data: (), res: Result<bool>| { res }
+            );
+
+            Ok(fut)
+        },
+    )?;
+    server_scripts.set("uninstall", uninstall_fn)?;
+
+    // server_scripts.list
+    let bot2 = bot.clone();
+    let sender2 = sender.clone();
+    let list_fn = state.create_function(move |state, server: LuaAnyUserData| {
+        let bot = bot2.clone();
+        let server = server.borrow::<BotServer>()?.clone();
+
+        let fut = create_lua_future!(
+            state,
+            sender2,
+            (),
+            async move {
+                let sid = bot.db().get_sid(server.id()).await?;
+
+                bot.db().server_scripts(sid).await
+            },
+            |state, _data: (), res: Result<Vec<ServerScript>>| {
+                let tbl = state.create_table()?;
+
+                for (i, script) in res?.into_iter().enumerate() {
+                    tbl.set(i + 1, script_table(state, script)?)?;
+                }
+
+                Ok(tbl)
+            }
+        );
+
+        Ok(fut)
+    })?;
+    server_scripts.set("list", list_fn)?;
+
+    // Every script has its own run budget, shared by everyone running it
+    let script_limiter: Arc<
+        RateLimiter<(Sid, String), DefaultKeyedStateStore<(Sid, String)>, LuaClock>,
+    > = Arc::new(RateLimiter::new(
+        Quota::per_minute(NonZeroU32::new(SCRIPT_RUNS_PER_MINUTE).unwrap()),
+        DefaultKeyedStateStore::default(),
+        &clock,
+    ));
+
+    // server_scripts.run, resolves to false if no script is installed with the name,
+    // otherwise to true followed by an error or the output
+    let bot2 = bot.clone();
+    let sender2 = sender.clone();
+    let run_fn = state.create_function(
+        move |state, (msg, name, args): (LuaAnyUserData, String, Vec<String>)| {
+            let bot = bot2.clone();
+            let sandbox_pool = sandbox_pool.clone();
+            let script_limiter = script_limiter.clone();
+            let msg = msg.borrow::<BotMessage>()?.clone();
+            let trace = current_trace(state).unwrap_or_else(TraceId::new);
+
+            let fut = create_lua_future!(
+                state,
+                sender2,
+                (),
+                async move {
+                    let sid = bot.db().get_sid(msg.channel().server().id()).await?;
+                    let script = match bot.db().server_script(sid, &name).await? {
+                        Some(script) => script,
+                        None => return Ok(None),
+                    };
+
+                    if script_limiter.check_key(&(sid, name.clone())).is_err() {
+                        return Ok(Some(Err(ServerScriptError::QuotaExceeded.into())));
+                    }
+
+                    let env_encoded = serde_json::to_string(&serde_json::json!({
+                        "args": args,
+                        "script": name,
+                        "user": {
+                            "name": msg.author().name(),
+                            "id": msg.author().id().to_short_str(),
+                        },
+                    }))?;
+
+                    Ok(Some(
+                        run_sandboxed_output(
+                            &bot,
+                            &sandbox_pool,
+                            &script.source,
+                            msg,
+                            env_encoded,
+                            SandboxOwner::Script(sid, name),
+                            None,
+                            trace,
+                        )
+                        .await,
+                    ))
+                },
+                |state, _data: (), res: Result<Option<Result<String>>>| {
+                    Ok(match res? {
+                        None => LuaMultiValue::from_vec(vec![LuaValue::Boolean(false)]),
+                        Some(Ok(out)) => LuaMultiValue::from_vec(vec![
+                            LuaValue::Boolean(true),
+                            LuaValue::Nil,
+                            LuaValue::String(state.create_string(&out)?),
+                        ]),
+                        Some(Err(err)) => LuaMultiValue::from_vec(vec![
+                            LuaValue::Boolean(true),
+                            LuaValue::String(state.create_string(&err.to_string())?),
+                        ]),
+                    })
+                }
+            );
+
+            Ok(fut)
+        },
+    )?;
+    server_scripts.set("run", run_fn)?;
+
+    state.globals().set("server_scripts", server_scripts)?;
+
+    Ok(())
+}
+
+#[derive(Debug, Error)]
+pub enum ServerScriptError {
+    #[error("script names must be at most {} letters, digits or underscores", _0)]
+    InvalidName(usize),
+    #[error("scripts can't be larger than {} bytes", _0)]
+    TooLarge(usize),
+    #[error("a server can have at most {} scripts installed", _0)]
+    TooMany(usize),
+    #[error("the script has been run too often, try again later")]
+    QuotaExceeded,
+}
@@ -0,0 +1,308 @@
+use anyhow::Result;
+use mlua::{prelude::*, Lua, LuaSerdeExt};
+use serde_json::Value as JsonValue;
+use std::sync::Arc;
+use thiserror::Error;
+
+use super::{
+    super::{
+        replay::{RecordedEvent, ReplayError, SandboxTape},
+        state::{get_sandbox_state, SandboxError, SandboxOwner, SandboxState},
+    },
+    bot::{BotServer, BotUser},
+    r#async::AsyncSender,
+};
+use crate::{
+    bot::{db::Uid, Bot},
+    services::ServerId,
+};
+
+const MAX_KEY_LEN: usize = 128;
+const MAX_VALUE_SIZE: usize = 64 * 1024;
+// Total size of the keys and values of a namespace written from the bot state
+const NAMESPACE_QUOTA: i64 = 4 * 1024 * 1024;
+const MAX_LISTED_KEYS: i64 = 100;
+
+enum Namespace {
+    Server(ServerId),
+    User(Uid),
+}
+
+impl Namespace {
+    async fn name(&self, bot: &Bot) -> Result<String> {
+        Ok(match self {
+            Namespace::Server(server_id) => {
+                format!("server:{}", bot.db().get_sid(*server_id).await?)
+            }
+            Namespace::User(uid) => format!("user:{}", uid),
+        })
+    }
+}
+
+fn check_key(key: &str) -> LuaResult<()> {
+    if key.is_empty() || key.chars().count() > MAX_KEY_LEN {
+        return Err(LuaError::ExternalError(Arc::new(StorageError::InvalidKey(
+            MAX_KEY_LEN,
+        ))));
+    }
+
+    Ok(())
+}
+
+// The bot state names a server or user, sandboxed code gets the server of the run and its owner
+fn resolve_namespace(state: &Lua, value: LuaValue) -> LuaResult<(Namespace, Option<SandboxState>)> {
+    let invalid = || LuaError::ExternalError(Arc::new(StorageError::InvalidNamespace));
+
+    if let Some(sandbox_state) = get_sandbox_state(state) {
+        let namespace = match value {
+            LuaValue::String(name) if name.as_bytes() == b"server" => {
+                Namespace::Server(sandbox_state.0.server_id)
+            }
+            LuaValue::String(name) if name.as_bytes() == b"user" => match &sandbox_state.0.owner {
+                SandboxOwner::User(uid) => Namespace::User(*uid),
+                SandboxOwner::Script(..) => {
+                    return Err(LuaError::ExternalError(Arc::new(
+                        StorageError::NoUserNamespace,
+                    )))
+                }
+            },
+            _ => return Err(invalid()),
+        };
+
+        return Ok((namespace, Some(sandbox_state)));
+    }
+
+    let namespace = match value {
+        LuaValue::UserData(data) => {
+            if let Ok(server) = data.borrow::<BotServer>() {
+                Namespace::Server(server.id())
+            } else if let Ok(user) = data.borrow::<BotUser>() {
+                Namespace::User(user.uid())
+            } else {
+                return Err(invalid());
+            }
+        }
+        _ => return Err(invalid()),
+    };
+
+    Ok((namespace, None))
+}
+
+fn replay_tape(sandbox_state: &Option<SandboxState>) -> Option<Arc<SandboxTape>> {
+    sandbox_state
+        .as_ref()
+        .and_then(|sandbox_state| sandbox_state.0.tape.clone())
+}
+
+pub fn lib_storage(state: &Lua, bot: &Arc<Bot>, sender: AsyncSender) -> Result<()> {
+    let storage = state.create_table()?;
+
+    // storage.get(namespace, key), resolves to the stored value or nil
+    let bot2 = bot.clone();
+    let sender2 = sender.clone();
+    let get_fn =
+        state.create_function(move |state, (namespace_value, key): (LuaValue, String)| {
+            check_key(&key)?;
+
+            let bot = bot2.clone();
+            let (namespace, sandbox_state) = resolve_namespace(state, namespace_value)?;
+            let tape = replay_tape(&sandbox_state);
+
+            let fut = create_lua_future!(
+                state,
+                sender2,
+                (),
+                async move {
+                    if let Some(tape) = tape.as_ref().filter(|tape| tape.is_replay()) {
+                        return match tape.next_event() {
+                            Some(RecordedEvent::StorageGet { value }) => Ok(value),
+                            _ => Err(ReplayError::Diverged("storage get").into()),
+                        };
+                    }
+
+                    let value = bot
+                        .db()
+                        .storage_value(&namespace.name(&bot).await?, &key)
+                        .await?;
+
+                    if let Some(tape) = tape {
+                        tape.record_event(RecordedEvent::StorageGet {
+                            value: value.clone(),
+                        });
+                    }
+
+                    Ok(value)
+                },
+                |state, _data: (), res: Result<Option<String>>| {
+                    Ok(match res? {
+                        Some(value) => {
+                            state.to_value(&serde_json::from_str::<JsonValue>(&value)?)?
+                        }
+                        None => LuaValue::Nil,
+                    })
+                }
+            );
+
+            Ok(fut)
+        })?;
+    storage.set("get", get_fn)?;
+
+    // storage.set(namespace, key, value), values are stored as json
+    let bot2 = bot.clone();
+    let sender2 = sender.clone();
+    let set_fn = state.create_function(
+        move |state, (namespace_value, key, value): (LuaValue, String, LuaValue)| {
+            check_key(&key)?;
+
+            let bot = bot2.clone();
+            let (namespace, sandbox_state) = resolve_namespace(state, namespace_value)?;
+            let value = serde_json::to_string(&state.from_value::<JsonValue>(value)?)
+                .map_err(|err| LuaError::ExternalError(Arc::new(err)))?;
+
+            if value.len() > MAX_VALUE_SIZE {
+                return Err(LuaError::ExternalError(Arc::new(StorageError::TooLarge(
+                    MAX_VALUE_SIZE,
+                ))));
+            }
+
+            let quota = match &sandbox_state {
+                Some(sandbox_state) => {
+                    if sandbox_state.limits().storage_writes_left_limit() {
+                        return Err(LuaError::ExternalError(Arc::new(
+                            SandboxError::LimitReached("storage write"),
+                        )));
+                    }
+
+                    sandbox_state.limits().storage_quota as i64
+                }
+                None => NAMESPACE_QUOTA,
+            };
+            let replay = replay_tape(&sandbox_state).map_or(false, |tape| tape.is_replay());
+
+            let fut = create_lua_future!(
+                state,
+                sender2,
+                (),
+                async move {
+                    if replay {
+                        return Ok(());
+                    }
+
+                    let stored = bot
+                        .db()
+                        .set_storage_value(&namespace.name(&bot).await?, &key, &value, quota)
+                        .await?;
+
+                    if !stored {
+                        return Err(StorageError::QuotaExceeded(quota).into());
+                    }
+
+                    Ok(())
+                },
+                |_state, _data: (), res: Result<()>| { Ok(res?) }
+            );
+
+            Ok(fut)
+        },
+    )?;
+    storage.set("set", set_fn)?;
+
+    // storage.delete(namespace, key), resolves to whether a value was removed
+    let bot2 = bot.clone();
+    let sender2 = sender.clone();
+    let delete_fn =
+        state.create_function(move |state, (namespace_value, key): (LuaValue, String)| {
+            check_key(&key)?;
+
+            let bot = bot2.clone();
+            let (namespace, sandbox_state) = resolve_namespace(state, namespace_value)?;
+
+            if let Some(sandbox_state) = &sandbox_state {
+                if sandbox_state.limits().storage_writes_left_limit() {
+                    return Err(LuaError::ExternalError(Arc::new(
+                        SandboxError::LimitReached("storage write"),
+                    )));
+                }
+            }
+
+            let replay = replay_tape(&sandbox_state).map_or(false, |tape| tape.is_replay());
+
+            let fut = create_lua_future!(
+                state,
+                sender2,
+                (),
+                async move {
+                    if replay {
+                        return Ok(false);
+                    }
+
+                    bot.db()
+                        .delete_storage_value(&namespace.name(&bot).await?, &key)
+                        .await
+                },
+                |_state, _data: (), res: Result<bool>| { res }
+            );
+
+            Ok(fut)
+        })?;
+    storage.set("delete", delete_fn)?;
+
+    // storage.list(namespace, prefix), resolves to the first 100 keys starting with the prefix
+    let bot2 = bot.clone();
+    let sender2 = sender.clone();
+    let list_fn = state.create_function(
+        move |state, (namespace_value, prefix): (LuaValue, Option<String>)| {
+            let bot = bot2.clone();
+            let (namespace, sandbox_state) = resolve_namespace(state, namespace_value)?;
+            let tape = replay_tape(&sandbox_state);
+            let prefix = prefix.unwrap_or_default();
+
+            let fut = create_lua_future!(
+                state,
+                sender2,
+                (),
+                async move {
+                    if let Some(tape) = tape.as_ref().filter(|tape| tape.is_replay()) {
+                        return match tape.next_event() {
+                            Some(RecordedEvent::StorageList { keys }) => Ok(keys),
+                            _ => Err(ReplayError::Diverged("storage list").into()),
+                        };
+                    }
+
+                    let keys = bot
+                        .db()
+                        .storage_keys(&namespace.name(&bot).await?, &prefix, MAX_LISTED_KEYS)
+                        .await?;
+
+                    if let Some(tape) = tape {
+                        tape.record_event(RecordedEvent::StorageList { keys: keys.clone() });
+                    }
+
+                    Ok(keys)
+                },
+                |_state, _data: (), res: Result<Vec<String>>| { res }
+            );
+
+            Ok(fut)
+        },
+    )?;
+    storage.set("list", list_fn)?;
+
+    state.globals().set("storage", storage)?;
+
+    Ok(())
+}
+
+#[derive(Debug, Error)]
+pub enum StorageError {
+    #[error("storage keys have to be 1 to {} characters long", _0)]
+    InvalidKey(usize),
+    #[error("stored values can't be larger than {} bytes", _0)]
+    TooLarge(usize),
+    #[error("the storage quota of {} bytes has been used up", _0)]
+    QuotaExceeded(i64),
+    #[error("storage namespaces are a server or user, or \"server\" or \"user\" in the sandbox")]
+    InvalidNamespace,
+    #[error("server scripts only have server storage")]
+    NoUserNamespace,
+}
@@ -1,11 +1,10 @@
 use anyhow::Result;
-use crossbeam::channel::Sender;
 use mlua::{prelude::*, Error as LuaError, Lua, MetaMethod, UserData, UserDataMethods};
 use std::sync::Arc;
 
 use super::{
-    super::state::LuaAsyncCallback,
     bot::{BotServer, BotUser},
+    r#async::AsyncSender,
 };
 use crate::bot::{db::Tag, Bot};
 
@@ -119,7 +118,28 @@ fn parse_tag(value: &str) -> Vec<TagPart> {
     out
 }
 
-pub fn lib_tags(state: &Lua, bot: &Arc<Bot>, sender: Sender<LuaAsyncCallback>) -> Result<()> {
+/// Fill in the `{var}` parts of a template, script tags and unknown variables are left as written
+pub fn render_template(value: &str, vars: &[(&str, &str)]) -> String {
+    let mut out = String::new();
+
+    for part in parse_tag(value) {
+        match part {
+            TagPart::Text(text) => out.push_str(&text),
+            TagPart::Var(name) => match vars.iter().find(|(var, _)| *var == name) {
+                Some((_, value)) => out.push_str(value),
+                None => out.push_str(&format!("{{{}}}", name)),
+            },
+            TagPart::Tag(name, value) => out.push_str(&format!("{{{}:{}}}", name, value)),
+            TagPart::Codeblock(lang, content) => {
+                out.push_str(&format!("```{}\n{}```", lang, content))
+            }
+        }
+    }
+
+    out
+}
+
+pub fn lib_tags(state: &Lua, bot: &Arc<Bot>, sender: AsyncSender) -> Result<()> {
     let tags_tbl = state.create_table()?;
 
     let bot2 = bot.clone();
@@ -134,7 +154,7 @@ pub fn lib_tags(state: &Lua, bot: &Arc<Bot>, sender: Sender<LuaAsyncCallback>) -
                 sender2.clone(),
                 (bot.clone(), sender2.clone()),
                 bot.db().find_tag(server.id(), &tag_key.to_lowercase()),
-                |state, data: (Arc<Bot>, Sender<LuaAsyncCallback>), res: Result<Option<Tag>>| {
+                |state, data: (Arc<Bot>, AsyncSender), res: Result<Option<Tag>>| {
                     match res? {
                         Some(tag) => Ok(LuaValue::UserData(
                             state.create_userdata(LuaTag::from_tag(data.0, data.1, tag)?)?,
@@ -264,12 +284,12 @@ pub fn lib_tags(state: &Lua, bot: &Arc<Bot>, sender: Sender<LuaAsyncCallback>) -
 }
 pub struct LuaTag {
     bot: Arc<Bot>,
-    sender: Sender<LuaAsyncCallback>,
+    sender: AsyncSender,
     inner: Tag,
 }
 
 impl LuaTag {
-    pub fn from_tag(bot: Arc<Bot>, sender: Sender<LuaAsyncCallback>, inner: Tag) -> Result<LuaTag> {
+    pub fn from_tag(bot: Arc<Bot>, sender: AsyncSender, inner: Tag) -> Result<LuaTag> {
         Ok(LuaTag { bot, sender, inner })
     }
 }
@@ -374,7 +394,7 @@ impl UserData for LuaTag {
 
 #[cfg(test)]
 mod tests {
-    use super::{parse_tag, TagPart};
+    use super::{parse_tag, render_template, TagPart};
 
     #[test]
     fn parse_tag_test() {
@@ -394,4 +414,14 @@ mod tests {
             ]
         )
     }
+
+    #[test]
+    fn render_template_test() {
+        let text = render_template(
+            "hi {name}, {unknown} {choose:a|b}",
+            &[("name", "kaito"), ("id", "1")],
+        );
+
+        assert_eq!(text, "hi kaito, {unknown} {choose:a|b}")
+    }
 }
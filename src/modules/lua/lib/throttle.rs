@@ -0,0 +1,41 @@
+use anyhow::Result;
+use mlua::{prelude::*, Lua};
+use std::{sync::Arc, time::Duration};
+
+use super::super::throttle::ThrottleQuota;
+use crate::bot::Bot;
+
+// bot state only
+pub fn lib_throttle(state: &Lua, bot: &Arc<Bot>) -> Result<()> {
+    let throttle = state.create_table()?;
+
+    // throttle.check, counts an action under the key and returns true, or false and the
+    // seconds to wait once the quota of `count` actions per `period` seconds is used up
+    let bot2 = bot.clone();
+    let check_fn = state.create_function(move |_state, (key, quota): (String, LuaTable)| {
+        let period: f64 = quota.get("period")?;
+        let quota = ThrottleQuota {
+            count: quota.get("count")?,
+            period: Duration::from_secs_f64(period.max(0.0)),
+        };
+
+        let wait = bot2
+            .get_ctx()
+            .modules()
+            .lua
+            .module()
+            .throttle()
+            .check(&key, quota)
+            .map_err(|err| LuaError::ExternalError(Arc::new(err)))?;
+
+        Ok(match wait {
+            Some(wait) => (false, Some(wait.as_secs_f64())),
+            None => (true, None),
+        })
+    })?;
+    throttle.set("check", check_fn)?;
+
+    state.globals().set("throttle", throttle)?;
+
+    Ok(())
+}
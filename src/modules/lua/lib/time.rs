@@ -0,0 +1,89 @@
+use anyhow::Result;
+use chrono::{
+    format::{Item, StrftimeItems},
+    DateTime, TimeZone, Utc,
+};
+use chrono_english::Dialect;
+use chrono_tz::Tz;
+use mlua::{Error as LuaError, Lua, Value};
+
+use super::{super::clock::LuaClock, bot::BotUser};
+
+const DEFAULT_FORMAT: &str = "%Y-%m-%d %H:%M %Z";
+
+pub fn parse_timezone(name: &str) -> Option<Tz> {
+    name.parse::<Tz>().ok()
+}
+
+/// Parse times like "next friday 5pm" or "in 2 hours" relative to a time in a timezone
+pub fn parse_human(text: &str, now: DateTime<Utc>, tz: Tz) -> Option<DateTime<Tz>> {
+    let now = now.with_timezone(&tz);
+    // "at" reads naturally but is not understood by the parser
+    let text = format!(" {} ", text.trim().to_lowercase()).replace(" at ", " ");
+
+    chrono_english::parse_date_string(text.trim(), now, Dialect::Uk).ok()
+}
+
+fn zone_from_value(zone: Value) -> Result<Tz, LuaError> {
+    match zone {
+        Value::Nil => Ok(Tz::UTC),
+        Value::String(name) => {
+            let name = name.to_str()?;
+
+            parse_timezone(name)
+                .ok_or_else(|| LuaError::RuntimeError(format!("unknown timezone \"{}\"", name)))
+        }
+        // Users without a timezone fall back to UTC
+        Value::UserData(user) => Ok(user
+            .borrow::<BotUser>()?
+            .timezone()
+            .and_then(parse_timezone)
+            .unwrap_or(Tz::UTC)),
+        _ => Err(LuaError::RuntimeError("expected a timezone name or a user".into())),
+    }
+}
+
+pub fn lib_time(state: &Lua, clock: LuaClock) -> Result<()> {
+    let time = state.create_table()?;
+
+    // time.format(timestamp, format, zone)
+    let time_format = state.create_function(
+        |_, (timestamp, format, zone): (i64, Option<String>, Value)| {
+            let tz = zone_from_value(zone)?;
+            let format = format.as_deref().unwrap_or(DEFAULT_FORMAT);
+            let items = StrftimeItems::new(format).collect::<Vec<_>>();
+
+            // chrono panics when displaying an invalid format
+            if items.iter().any(|item| matches!(item, Item::Error)) {
+                return Err(LuaError::RuntimeError(format!(
+                    "invalid time format \"{}\"",
+                    format
+                )));
+            }
+
+            Ok(Utc
+                .timestamp(timestamp, 0)
+                .with_timezone(&tz)
+                .format_with_items(items.into_iter())
+                .to_string())
+        },
+    )?;
+    time.set("format", time_format)?;
+
+    // time.is_timezone(name)
+    let time_is_timezone =
+        state.create_function(|_, name: String| Ok(parse_timezone(&name).is_some()))?;
+    time.set("is_timezone", time_is_timezone)?;
+
+    // time.parse_human(text, zone)
+    let time_parse_human = state.create_function(move |_, (text, zone): (String, Value)| {
+        let tz = zone_from_value(zone)?;
+
+        Ok(parse_human(&text, clock.utc_now(), tz).map(|time| time.timestamp()))
+    })?;
+    time.set("parse_human", time_parse_human)?;
+
+    state.globals().set("time", time)?;
+
+    Ok(())
+}
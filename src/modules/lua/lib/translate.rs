@@ -0,0 +1,428 @@
+use anyhow::Result;
+use async_mutex::Mutex;
+use hyper::{Body, Client, Request};
+use hyper_tls::HttpsConnector;
+use lru::LruCache;
+use mlua::{prelude::*, Lua};
+use std::sync::Arc;
+use thiserror::Error;
+
+use super::{bot::BotChannel, r#async::AsyncSender};
+use crate::{
+    bot::Bot,
+    config::{ConfigDeepL, ConfigLibreTranslate, ConfigTranslate},
+    modules::Module,
+};
+
+const DEFAULT_LANGUAGE: &'static str = "en";
+const DEEPL_API_URL: &'static str = "https://api-free.deepl.com";
+const MAX_TEXT_LEN: usize = 2000;
+
+#[derive(Clone)]
+pub struct Translation {
+    pub text: String,
+    pub source: Option<String>,
+    pub target: String,
+    pub provider: &'static str,
+}
+
+#[async_trait]
+pub trait TranslateProvider: Send + Sync {
+    fn id(&self) -> &'static str;
+
+    async fn translate(&self, text: &str, source: Option<&str>, target: &str)
+        -> Result<Translation>;
+    async fn detect(&self, text: &str) -> Result<String>;
+}
+
+async fn send_request(req: Request<Body>) -> Result<Vec<u8>> {
+    let https = HttpsConnector::new();
+    let client = Client::builder().build::<_, Body>(https);
+
+    let res = client.request(req).await?;
+    let status = res.status();
+    let body = hyper::body::to_bytes(res.into_body()).await?;
+
+    if !status.is_success() {
+        return Err(TranslateError::ProviderError(
+            status.as_u16(),
+            String::from_utf8_lossy(&body).to_string(),
+        )
+        .into());
+    }
+
+    Ok(body.to_vec())
+}
+
+// DeepL
+
+pub struct DeepLProvider {
+    config: ConfigDeepL,
+}
+
+#[derive(Deserialize)]
+struct DeepLTranslation {
+    detected_source_language: Option<String>,
+    text: String,
+}
+
+#[derive(Deserialize)]
+struct DeepLResponse {
+    translations: Vec<DeepLTranslation>,
+}
+
+#[async_trait]
+impl TranslateProvider for DeepLProvider {
+    fn id(&self) -> &'static str {
+        "deepl"
+    }
+
+    async fn translate(
+        &self,
+        text: &str,
+        source: Option<&str>,
+        target: &str,
+    ) -> Result<Translation> {
+        let mut form = url::form_urlencoded::Serializer::new(String::new());
+        form.append_pair("text", text);
+        form.append_pair("target_lang", &target.to_uppercase());
+
+        if let Some(source) = source {
+            form.append_pair("source_lang", &source.to_uppercase());
+        }
+
+        let api_url = self.config.api_url.as_deref().unwrap_or(DEEPL_API_URL);
+
+        let req = Request::builder()
+            .method("POST")
+            .uri(format!("{}/v2/translate", api_url.trim_end_matches('/')))
+            .header(
+                "Authorization",
+                format!("DeepL-Auth-Key {}", self.config.api_key),
+            )
+            .header("Content-Type", "application/x-www-form-urlencoded")
+            .body(Body::from(form.finish()))?;
+
+        let res: DeepLResponse = serde_json::from_slice(&send_request(req).await?)?;
+        let translation = res
+            .translations
+            .into_iter()
+            .next()
+            .ok_or(TranslateError::EmptyResponse)?;
+
+        Ok(Translation {
+            text: translation.text,
+            source: translation
+                .detected_source_language
+                .map(|lang| lang.to_lowercase())
+                .or_else(|| source.map(|s| s.to_lowercase())),
+            target: target.to_lowercase(),
+            provider: self.id(),
+        })
+    }
+
+    async fn detect(&self, text: &str) -> Result<String> {
+        // DeepL has no detection endpoint, but it reports the detected language on translations
+        self.translate(text, None, DEFAULT_LANGUAGE)
+            .await?
+            .source
+            .ok_or(TranslateError::UnknownLanguage.into())
+    }
+}
+
+// LibreTranslate
+
+pub struct LibreTranslateProvider {
+    config: ConfigLibreTranslate,
+}
+
+#[derive(Deserialize)]
+struct LibreTranslateDetected {
+    language: String,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct LibreTranslateResponse {
+    translated_text: String,
+    detected_language: Option<LibreTranslateDetected>,
+}
+
+impl LibreTranslateProvider {
+    fn request(&self, endpoint: &str, mut body: serde_json::Value) -> Result<Request<Body>> {
+        if let Some(api_key) = self.config.api_key.as_ref() {
+            body["api_key"] = serde_json::Value::String(api_key.clone());
+        }
+
+        Ok(Request::builder()
+            .method("POST")
+            .uri(format!(
+                "{}/{}",
+                self.config.url.trim_end_matches('/'),
+                endpoint
+            ))
+            .header("Content-Type", "application/json")
+            .body(Body::from(serde_json::to_vec(&body)?))?)
+    }
+}
+
+#[async_trait]
+impl TranslateProvider for LibreTranslateProvider {
+    fn id(&self) -> &'static str {
+        "libretranslate"
+    }
+
+    async fn translate(
+        &self,
+        text: &str,
+        source: Option<&str>,
+        target: &str,
+    ) -> Result<Translation> {
+        let req = self.request(
+            "translate",
+            serde_json::json!({
+                "q": text,
+                "source": source.unwrap_or("auto"),
+                "target": target,
+                "format": "text",
+            }),
+        )?;
+
+        let res: LibreTranslateResponse = serde_json::from_slice(&send_request(req).await?)?;
+
+        Ok(Translation {
+            text: res.translated_text,
+            source: res
+                .detected_language
+                .map(|detected| detected.language)
+                .or_else(|| source.map(|s| s.to_string())),
+            target: target.to_string(),
+            provider: self.id(),
+        })
+    }
+
+    async fn detect(&self, text: &str) -> Result<String> {
+        let req = self.request("detect", serde_json::json!({ "q": text }))?;
+
+        let res: Vec<LibreTranslateDetected> = serde_json::from_slice(&send_request(req).await?)?;
+
+        res.into_iter()
+            .next()
+            .map(|detected| detected.language)
+            .ok_or(TranslateError::UnknownLanguage.into())
+    }
+}
+
+type TranslationCacheKey = (&'static str, Option<String>, String, String);
+
+pub struct Translator {
+    providers: Vec<Arc<dyn TranslateProvider>>,
+    cache: Mutex<LruCache<TranslationCacheKey, Translation>>,
+}
+
+impl Translator {
+    pub fn from_config(config: Option<&ConfigTranslate>) -> Translator {
+        let mut providers: Vec<Arc<dyn TranslateProvider>> = Vec::new();
+
+        if let Some(config) = config {
+            if let Some(deepl) = config.deepl.clone() {
+                providers.push(Arc::new(DeepLProvider { config: deepl }));
+            }
+
+            if let Some(libretranslate) = config.libretranslate.clone() {
+                providers.push(Arc::new(LibreTranslateProvider {
+                    config: libretranslate,
+                }));
+            }
+        }
+
+        Translator {
+            providers,
+            cache: Mutex::new(LruCache::new(256)),
+        }
+    }
+
+    pub fn provider_ids(&self) -> Vec<&'static str> {
+        self.providers.iter().map(|p| p.id()).collect()
+    }
+
+    // Find the requested provider, falling back to the first configured one
+    fn provider(&self, id: Option<&str>) -> Result<Arc<dyn TranslateProvider>> {
+        if let Some(id) = id.filter(|id| !id.is_empty()) {
+            if let Some(provider) = self.providers.iter().find(|p| p.id() == id) {
+                return Ok(provider.clone());
+            }
+        }
+
+        self.providers
+            .first()
+            .cloned()
+            .ok_or(TranslateError::NoProviders.into())
+    }
+
+    pub async fn translate(
+        &self,
+        provider: Option<&str>,
+        text: &str,
+        source: Option<&str>,
+        target: &str,
+    ) -> Result<Translation> {
+        if text.len() > MAX_TEXT_LEN {
+            return Err(TranslateError::TextTooLong(MAX_TEXT_LEN).into());
+        }
+
+        let provider = self.provider(provider)?;
+        let key = (
+            provider.id(),
+            source.map(|s| s.to_lowercase()),
+            target.to_lowercase(),
+            text.to_string(),
+        );
+
+        if let Some(translation) = self.cache.lock().await.get(&key) {
+            return Ok(translation.clone());
+        }
+
+        let translation = provider.translate(text, source, target).await?;
+        self.cache.lock().await.put(key, translation.clone());
+
+        Ok(translation)
+    }
+
+    pub async fn detect(&self, provider: Option<&str>, text: &str) -> Result<String> {
+        if text.len() > MAX_TEXT_LEN {
+            return Err(TranslateError::TextTooLong(MAX_TEXT_LEN).into());
+        }
+
+        self.provider(provider)?.detect(text).await
+    }
+}
+
+// Resolve the provider and target language settings for a channel
+async fn channel_defaults(
+    bot: &Arc<Bot>,
+    channel: Option<BotChannel>,
+) -> Result<(Option<String>, String)> {
+    match channel {
+        Some(channel) => {
+            let settings = bot.get_ctx().modules().lua.module().settings().clone();
+            let (server_id, channel_id) = (channel.server().id(), channel.id());
+
+            Ok((
+                Some(
+                    settings
+                        .translate_provider
+                        .value(server_id, channel_id)
+                        .await?,
+                ),
+                settings
+                    .translate_language
+                    .value(server_id, channel_id)
+                    .await?,
+            ))
+        }
+        None => Ok((None, DEFAULT_LANGUAGE.into())),
+    }
+}
+
+// bot state only
+pub fn lib_translate(state: &Lua, bot: &Arc<Bot>, sender: AsyncSender) -> Result<()> {
+    let translate = state.create_table()?;
+    let translator = Arc::new(Translator::from_config(bot.config().translate.as_ref()));
+
+    // translate.translate
+    let bot2 = bot.clone();
+    let sender2 = sender.clone();
+    let translator2 = translator.clone();
+    let translate_fn =
+        state.create_function(move |state, (text, options): (String, Option<LuaTable>)| {
+            let bot = bot2.clone();
+            let translator = translator2.clone();
+
+            let (to, from, channel): (Option<String>, Option<String>, Option<BotChannel>) =
+                match options {
+                    Some(options) => (
+                        options.get("to")?,
+                        options.get("from")?,
+                        options.get("channel")?,
+                    ),
+                    None => (None, None, None),
+                };
+
+            let fut = create_lua_future!(
+                state,
+                sender2,
+                (),
+                async move {
+                    let (provider, default_target) = channel_defaults(&bot, channel).await?;
+                    let target = to.unwrap_or(default_target);
+
+                    translator
+                        .translate(provider.as_deref(), &text, from.as_deref(), &target)
+                        .await
+                },
+                |state, _data: (), res: Result<Translation>| {
+                    let translation = res?;
+                    let tbl = state.create_table()?;
+
+                    tbl.set("text", translation.text)?;
+                    tbl.set("source", translation.source)?;
+                    tbl.set("target", translation.target)?;
+                    tbl.set("provider", translation.provider)?;
+
+                    Ok(tbl)
+                }
+            );
+
+            Ok(fut)
+        })?;
+    translate.set("translate", translate_fn)?;
+
+    // translate.detect
+    let bot2 = bot.clone();
+    let sender2 = sender.clone();
+    let translator2 = translator.clone();
+    let detect_fn =
+        state.create_function(move |state, (text, channel): (String, Option<BotChannel>)| {
+            let bot = bot2.clone();
+            let translator = translator2.clone();
+
+            let fut = create_lua_future!(
+                state,
+                sender2,
+                (),
+                async move {
+                    let (provider, _) = channel_defaults(&bot, channel).await?;
+
+                    translator.detect(provider.as_deref(), &text).await
+                },
+                |_state, _data: (), res: Result<String>| { res }
+            );
+
+            Ok(fut)
+        })?;
+    translate.set("detect", detect_fn)?;
+
+    // translate.providers
+    let providers_fn =
+        state.create_function(move |_state, (): ()| Ok(translator.provider_ids()))?;
+    translate.set("providers", providers_fn)?;
+
+    state.globals().set("translate", translate)?;
+
+    Ok(())
+}
+
+#[derive(Debug, Error)]
+pub enum TranslateError {
+    #[error("no translation providers have been configured")]
+    NoProviders,
+    #[error("the text cannot be longer than {} bytes", _0)]
+    TextTooLong(usize),
+    #[error("translation provider returned an empty response")]
+    EmptyResponse,
+    #[error("unable to detect the language")]
+    UnknownLanguage,
+    #[error("translation provider error ({}): {}", _0, _1)]
+    ProviderError(u16, String),
+}
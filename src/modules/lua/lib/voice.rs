@@ -1,17 +1,16 @@
 use anyhow::Result;
-use crossbeam::channel::Sender;
 use mlua::{prelude::*, Lua, MetaMethod, UserData, UserDataMethods};
 use std::{process::Output, sync::Arc, time::Duration};
 
 use crate::{
     bot::Bot,
-    modules::lua::state::LuaAsyncCallback,
+    modules::lua::lib::r#async::AsyncSender,
     services::{ChannelId, ServerId, UserId, VoiceConnectionAbstract},
 };
 
 use super::bot::{BotServer, BotUser};
 
-pub fn lib_voice(state: &Lua, bot: &Arc<Bot>, sender: Sender<LuaAsyncCallback>) -> Result<()> {
+pub fn lib_voice(state: &Lua, bot: &Arc<Bot>, sender: AsyncSender) -> Result<()> {
     let voice = state.create_table()?;
 
     // voice.join
@@ -36,7 +35,7 @@ pub fn lib_voice(state: &Lua, bot: &Arc<Bot>, sender: Sender<LuaAsyncCallback>)
                 async move {
                     let connection = ctx.services().join_voice(server_id, channel_id).await?;
 
-                    Ok(LuaVoiceConnection(Arc::new(connection), bot3, sender3))
+                    Ok(LuaVoiceConnection(connection, bot3, sender3))
                 },
                 |_state, _data: (), res: Result<LuaVoiceConnection>| { Ok(res?) }
             );
@@ -99,11 +98,7 @@ pub fn lib_voice(state: &Lua, bot: &Arc<Bot>, sender: Sender<LuaAsyncCallback>)
 }
 
 #[derive(Clone)]
-struct LuaVoiceConnection(
-    Arc<dyn VoiceConnectionAbstract>,
-    Arc<Bot>,
-    Sender<LuaAsyncCallback>,
-);
+struct LuaVoiceConnection(Arc<dyn VoiceConnectionAbstract>, Arc<Bot>, AsyncSender);
 
 impl UserData for LuaVoiceConnection {
     fn add_methods<'a, M: UserDataMethods<'a, Self>>(methods: &mut M) {
@@ -0,0 +1,189 @@
+use lru::LruCache;
+use std::{
+    collections::VecDeque,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use super::clock::LuaClock;
+use crate::bot::db::Uid;
+
+const BASE_TIMEOUT: Duration = Duration::from_secs(10);
+const MAX_TIMEOUT: Duration = Duration::from_secs(60 * 60);
+// Offenses are forgotten after this long without being limited again
+const OFFENSE_DECAY: Duration = Duration::from_secs(10 * 60);
+
+#[derive(Clone, Copy, Debug)]
+pub struct RateLimit {
+    pub count: usize,
+    pub period: Duration,
+}
+
+pub enum RateLimitResult {
+    Allowed,
+    /// The user just got limited and should be told for how long
+    Limited(Duration),
+    /// The user is already timed out and should be ignored
+    TimedOut,
+}
+
+/// An active timeout, relative to when it was exported so it survives a restart
+#[derive(Deserialize, Serialize)]
+pub struct OffenderState {
+    pub uid: Uid,
+    pub offenses: u32,
+    pub timeout_left: f64,
+    pub since_last_offense: f64,
+}
+
+struct Offender {
+    offenses: u32,
+    timeout_until: Instant,
+    last_offense: Instant,
+}
+
+/// Sliding window limiter keyed by user and command, with escalating timeouts for repeat offenders
+pub struct CommandRateLimiter {
+    windows: Mutex<LruCache<(Uid, String), VecDeque<Instant>>>,
+    offenders: Mutex<LruCache<Uid, Offender>>,
+    clock: LuaClock,
+}
+
+impl CommandRateLimiter {
+    pub fn new(clock: LuaClock) -> CommandRateLimiter {
+        CommandRateLimiter {
+            windows: Mutex::new(LruCache::new(4096)),
+            offenders: Mutex::new(LruCache::new(1024)),
+            clock,
+        }
+    }
+
+    pub fn check(&self, uid: Uid, key: &str, limit: RateLimit) -> RateLimitResult {
+        let now = self.clock.instant();
+
+        let mut offenders = self.offenders.lock().unwrap();
+
+        if let Some(offender) = offenders.get(&uid) {
+            if offender.timeout_until > now {
+                return RateLimitResult::TimedOut;
+            }
+        }
+
+        if limit.count == 0 {
+            return RateLimitResult::Allowed;
+        }
+
+        let mut windows = self.windows.lock().unwrap();
+        let window_key = (uid, key.to_string());
+
+        if windows.get(&window_key).is_none() {
+            windows.put(window_key.clone(), VecDeque::new());
+        }
+
+        let window = windows.get_mut(&window_key).unwrap();
+
+        while let Some(time) = window.front() {
+            if now.duration_since(*time) >= limit.period {
+                window.pop_front();
+            } else {
+                break;
+            }
+        }
+
+        if window.len() < limit.count {
+            window.push_back(now);
+            return RateLimitResult::Allowed;
+        }
+
+        // Double the timeout for every recent offense
+        let offenses = match offenders.get(&uid) {
+            Some(offender) if now.duration_since(offender.last_offense) < OFFENSE_DECAY => {
+                offender.offenses + 1
+            }
+            _ => 1,
+        };
+
+        let timeout = BASE_TIMEOUT
+            .checked_mul(1 << (offenses - 1).min(16))
+            .unwrap_or(MAX_TIMEOUT)
+            .min(MAX_TIMEOUT);
+
+        offenders.put(
+            uid,
+            Offender {
+                offenses,
+                timeout_until: now + timeout,
+                last_offense: now,
+            },
+        );
+        window.clear();
+
+        RateLimitResult::Limited(timeout)
+    }
+
+    /// Uses left in the current window and the timeout left, without counting as a use
+    pub fn remaining(&self, uid: Uid, key: &str, limit: RateLimit) -> (usize, Option<Duration>) {
+        let now = self.clock.instant();
+
+        let timeout = self
+            .offenders
+            .lock()
+            .unwrap()
+            .peek(&uid)
+            .map(|offender| offender.timeout_until.saturating_duration_since(now))
+            .filter(|timeout| !timeout.is_zero());
+
+        let used = self
+            .windows
+            .lock()
+            .unwrap()
+            .peek(&(uid, key.to_string()))
+            .map(|window| {
+                window
+                    .iter()
+                    .filter(|time| now.duration_since(**time) < limit.period)
+                    .count()
+            })
+            .unwrap_or(0);
+
+        (limit.count.saturating_sub(used), timeout)
+    }
+
+    pub fn export(&self) -> Vec<OffenderState> {
+        let now = self.clock.instant();
+
+        self.offenders
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|(_, offender)| now.duration_since(offender.last_offense) < OFFENSE_DECAY)
+            .map(|(uid, offender)| OffenderState {
+                uid: *uid,
+                offenses: offender.offenses,
+                timeout_left: offender
+                    .timeout_until
+                    .saturating_duration_since(now)
+                    .as_secs_f64(),
+                since_last_offense: now.duration_since(offender.last_offense).as_secs_f64(),
+            })
+            .collect()
+    }
+
+    pub fn import(&self, states: Vec<OffenderState>) {
+        let now = self.clock.instant();
+        let mut offenders = self.offenders.lock().unwrap();
+
+        for state in states {
+            offenders.put(
+                state.uid,
+                Offender {
+                    offenses: state.offenses,
+                    timeout_until: now + Duration::from_secs_f64(state.timeout_left.max(0.0)),
+                    last_offense: now
+                        .checked_sub(Duration::from_secs_f64(state.since_last_offense.max(0.0)))
+                        .unwrap_or(now),
+                },
+            );
+        }
+    }
+}
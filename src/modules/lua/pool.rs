@@ -0,0 +1,374 @@
+use anyhow::Result;
+use arc_swap::ArcSwap;
+use crossbeam::channel::Receiver;
+use futures::channel::oneshot;
+use std::{
+    collections::HashMap,
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, AtomicU8, AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+use super::{
+    clock::LuaClock,
+    lib::bot::BotMessage,
+    replay::SandboxTape,
+    state::{
+        LuaState, LuaVmStats, SandboxError, SandboxMsg, SandboxOwner, SandboxState,
+        SandboxStateInner, SandboxTerminationReason, SANDBOX_TIME_LIMIT,
+    },
+    utils::TraceId,
+};
+use crate::bot::Bot;
+
+const THINK_INTERVAL: Duration = Duration::from_millis(50);
+// States kept loaded so a run doesn't wait on sandbox.lua
+const IDLE_STATES: usize = 2;
+const MAX_WORKERS: usize = 16;
+// Abandoned workers keep their thread and state until the call they are stuck in returns, past
+// this many no new runs are started so they can't pile up
+const MAX_ABANDONED_WORKERS: usize = 16;
+// Time a run gets past its time limit to wind down before its worker is abandoned
+const KILL_GRACE: Duration = Duration::from_secs(5);
+
+/// Runs every sandboxed call in its own lua state on a worker thread, so a run that never yields
+/// can be abandoned without holding up the bot state or other runs
+pub struct SandboxPool {
+    bot: Arc<Bot>,
+    clock: LuaClock,
+    lua_root_path: ArcSwap<PathBuf>,
+    // Idle states with the generation of the scripts they were loaded from
+    idle: Mutex<Vec<(u64, LuaState)>>,
+    refilling: AtomicBool,
+    generation: AtomicU64,
+    workers: Arc<WorkerCounts>,
+    next_worker: AtomicU64,
+    stats: Arc<Mutex<HashMap<u64, LuaVmStats>>>,
+}
+
+impl SandboxPool {
+    pub fn new(bot: &Arc<Bot>, lua_root_path: PathBuf, clock: &LuaClock) -> Arc<SandboxPool> {
+        Arc::new(SandboxPool {
+            bot: bot.clone(),
+            clock: clock.clone(),
+            lua_root_path: ArcSwap::from_pointee(lua_root_path),
+            idle: Mutex::new(Vec::new()),
+            refilling: AtomicBool::new(false),
+            generation: AtomicU64::new(0),
+            workers: Default::default(),
+            next_worker: AtomicU64::new(0),
+            stats: Default::default(),
+        })
+    }
+
+    /// Load new runs from the scripts at the path, dropping the idle states
+    pub fn set_root_path(&self, lua_root_path: PathBuf) {
+        self.lua_root_path.store(Arc::new(lua_root_path));
+        self.clear();
+    }
+
+    /// Drop the idle states, new runs get freshly loaded ones
+    pub fn clear(&self) {
+        self.generation.fetch_add(1, Ordering::AcqRel);
+        self.idle.lock().unwrap().clear();
+    }
+
+    fn create_state(&self) -> Result<LuaState> {
+        LuaState::create_state(
+            &self.bot,
+            true,
+            None,
+            &self.lua_root_path.load(),
+            &self.clock,
+        )
+    }
+
+    fn refill(self: &Arc<Self>) {
+        // One refill at a time, concurrent ones would load more states than are kept
+        if self.refilling.swap(true, Ordering::AcqRel) {
+            return;
+        }
+
+        let pool = self.clone();
+
+        tokio::task::spawn_blocking(move || {
+            while pool.idle.lock().unwrap().len() < IDLE_STATES {
+                let generation = pool.generation.load(Ordering::Acquire);
+
+                match pool.create_state() {
+                    Ok(state) => {
+                        let mut idle = pool.idle.lock().unwrap();
+
+                        // The scripts were swapped out while it loaded
+                        if generation == pool.generation.load(Ordering::Acquire)
+                            && idle.len() < IDLE_STATES
+                        {
+                            idle.push((generation, state));
+                        }
+                    }
+                    Err(err) => {
+                        println!("error creating a sandbox state: {}", err.to_string());
+                        break;
+                    }
+                }
+            }
+
+            pool.refilling.store(false, Ordering::Release);
+        });
+    }
+
+    /// A fresh state for a run, messages of the run have to be created with its async sender
+    pub async fn take_state(self: &Arc<Self>) -> Result<LuaState> {
+        let generation = self.generation.load(Ordering::Acquire);
+        let state = {
+            let mut idle = self.idle.lock().unwrap();
+            idle.retain(|(state_generation, _)| *state_generation == generation);
+            idle.pop()
+        };
+
+        self.refill();
+
+        match state {
+            Some((_, state)) => Ok(state),
+            None => {
+                let pool = self.clone();
+                tokio::task::spawn_blocking(move || pool.create_state()).await?
+            }
+        }
+    }
+
+    /// Start a run on a worker thread of its own, the state is dropped once the run has nothing
+    /// left to do or is past its time limit
+    pub async fn run(
+        self: &Arc<Self>,
+        state: LuaState,
+        source: &str,
+        msg: BotMessage,
+        env_encoded: Option<String>,
+        owner: SandboxOwner,
+        tape: Option<Arc<SandboxTape>>,
+        trace: TraceId,
+    ) -> Result<(Arc<SandboxStateInner>, Receiver<SandboxMsg>)> {
+        let lua = self.bot.get_ctx().modules().lua.module().clone();
+        let http_quota = lua.http_quota(&msg).await?;
+
+        let slot = WorkerSlot::acquire(
+            self.workers.clone(),
+            self.stats.clone(),
+            self.next_worker.fetch_add(1, Ordering::Relaxed),
+        )?;
+        let id = slot.id;
+        let abandon = slot.abandon_handle();
+        let killed = state.kill_handle();
+        let handle = tokio::runtime::Handle::current();
+        let source = source.to_string();
+        let (started_sender, started_receiver) = oneshot::channel();
+        let (done_sender, done_receiver) = oneshot::channel::<()>();
+
+        std::thread::Builder::new()
+            .name(format!("sandbox-{}", id))
+            .spawn(move || {
+                // Futures of the run are spawned onto the runtime of the bot
+                let _guard = handle.enter();
+
+                let res =
+                    state.run_sandboxed(&source, msg, env_encoded, owner, http_quota, tape, trace);
+                let sandbox_state = match res {
+                    Ok((sandbox_state, recv)) => {
+                        started_sender.send(Ok((sandbox_state.clone(), recv))).ok();
+                        sandbox_state
+                    }
+                    Err(err) => {
+                        started_sender.send(Err(err)).ok();
+                        return;
+                    }
+                };
+
+                slot.think_until_idle(&state, &sandbox_state);
+                done_sender.send(()).ok();
+            })?;
+
+        let limit = Duration::from_secs_f64(SANDBOX_TIME_LIMIT) + KILL_GRACE;
+
+        let (sandbox_state, recv) = match tokio::time::timeout(limit, started_receiver).await {
+            Ok(Ok(res)) => res?,
+            Ok(Err(_)) => {
+                return Err(SandboxError::Runtime("the sandbox worker stopped".into()).into())
+            }
+            Err(_) => {
+                killed.store(true, Ordering::Relaxed);
+                println!("sandbox worker {} didn't start in time, abandoning it", id);
+                abandon.abandon();
+                return Err(SandboxError::TimeLimit.into());
+            }
+        };
+
+        let deadline = SandboxState(sandbox_state.clone()).deadline() + KILL_GRACE;
+        let watched_state = sandbox_state.clone();
+
+        tokio::spawn(async move {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+
+            if tokio::time::timeout(remaining, done_receiver)
+                .await
+                .is_err()
+            {
+                killed.store(true, Ordering::Relaxed);
+                watched_state
+                    .sender
+                    .send(SandboxMsg::Terminated(SandboxTerminationReason::TimeLimit))
+                    .ok();
+
+                println!(
+                    "sandbox worker {} didn't stop in time, abandoning it [trace {}]",
+                    id, watched_state.trace
+                );
+                abandon.abandon();
+            }
+        });
+
+        Ok((sandbox_state, recv))
+    }
+
+    /// Worker threads running sandboxed code
+    pub fn active_workers(&self) -> usize {
+        self.workers.active.load(Ordering::Acquire)
+    }
+
+    /// Worker threads that were abandoned and are still stuck in a call
+    pub fn abandoned_workers(&self) -> usize {
+        self.workers.abandoned.load(Ordering::Acquire)
+    }
+
+    /// Stats of the states of every running worker, added up
+    pub fn vm_stats(&self) -> LuaVmStats {
+        self.stats
+            .lock()
+            .unwrap()
+            .values()
+            .fold(LuaVmStats::default(), |total, stats| LuaVmStats {
+                used_memory: total.used_memory + stats.used_memory,
+                registry_size: total.registry_size + stats.registry_size,
+                pending_futures: total.pending_futures + stats.pending_futures,
+                pending_callbacks: total.pending_callbacks + stats.pending_callbacks,
+            })
+    }
+}
+
+#[derive(Default)]
+struct WorkerCounts {
+    active: AtomicUsize,
+    abandoned: AtomicUsize,
+}
+
+const WORKER_RUNNING: u8 = 0;
+const WORKER_ABANDONED: u8 = 1;
+const WORKER_DONE: u8 = 2;
+
+// Counts a worker thread as active until it returns or is abandoned, abandoned ones are counted
+// separately until they return
+struct WorkerSlot {
+    workers: Arc<WorkerCounts>,
+    stats: Arc<Mutex<HashMap<u64, LuaVmStats>>>,
+    status: Arc<AtomicU8>,
+    id: u64,
+}
+
+impl WorkerSlot {
+    fn acquire(
+        workers: Arc<WorkerCounts>,
+        stats: Arc<Mutex<HashMap<u64, LuaVmStats>>>,
+        id: u64,
+    ) -> Result<WorkerSlot> {
+        let abandoned = workers.abandoned.load(Ordering::Acquire);
+        if abandoned >= MAX_ABANDONED_WORKERS {
+            println!(
+                "{} abandoned sandbox workers are still stuck, refusing new runs",
+                abandoned
+            );
+            return Err(SandboxError::LimitReached("abandoned worker").into());
+        }
+
+        if workers.active.fetch_add(1, Ordering::AcqRel) >= MAX_WORKERS {
+            workers.active.fetch_sub(1, Ordering::AcqRel);
+            return Err(SandboxError::LimitReached("concurrent run").into());
+        }
+
+        Ok(WorkerSlot {
+            workers,
+            stats,
+            status: Arc::new(AtomicU8::new(WORKER_RUNNING)),
+            id,
+        })
+    }
+
+    fn abandon_handle(&self) -> AbandonHandle {
+        AbandonHandle {
+            workers: self.workers.clone(),
+            status: self.status.clone(),
+        }
+    }
+    fn think_until_idle(&self, state: &LuaState, sandbox_state: &Arc<SandboxStateInner>) {
+        // Whatever the run still waits on past its time limit is dropped with the state
+        let deadline = SandboxState(sandbox_state.clone()).deadline();
+        let killed = state.kill_handle();
+        let waker = state.async_waker();
+
+        while !killed.load(Ordering::Relaxed) && Instant::now() < deadline {
+            if let Err(err) = state.think() {
+                println!("error: {}", err.to_string());
+            }
+
+            if let Ok(stats) = state.vm_stats() {
+                self.stats.lock().unwrap().insert(self.id, stats);
+            }
+
+            match state.sandbox_idle() {
+                Ok(false) => waker.wait_timeout(THINK_INTERVAL),
+                _ => break,
+            }
+        }
+    }
+}
+
+impl Drop for WorkerSlot {
+    fn drop(&mut self) {
+        self.stats.lock().unwrap().remove(&self.id);
+
+        match self.status.swap(WORKER_DONE, Ordering::AcqRel) {
+            WORKER_ABANDONED => {
+                self.workers.abandoned.fetch_sub(1, Ordering::AcqRel);
+                println!("abandoned sandbox worker {} returned", self.id);
+            }
+            _ => {
+                self.workers.active.fetch_sub(1, Ordering::AcqRel);
+            }
+        }
+    }
+}
+
+struct AbandonHandle {
+    workers: Arc<WorkerCounts>,
+    status: Arc<AtomicU8>,
+}
+
+impl AbandonHandle {
+    /// Move the worker from the active count to the abandoned one, unless it already returned
+    fn abandon(&self) {
+        let abandoned = self.status.compare_exchange(
+            WORKER_RUNNING,
+            WORKER_ABANDONED,
+            Ordering::AcqRel,
+            Ordering::Acquire,
+        );
+
+        if abandoned.is_ok() {
+            self.workers.active.fetch_sub(1, Ordering::AcqRel);
+            let count = self.workers.abandoned.fetch_add(1, Ordering::AcqRel) + 1;
+            println!("{} abandoned sandbox workers are stuck", count);
+        }
+    }
+}
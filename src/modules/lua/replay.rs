@@ -0,0 +1,138 @@
+use anyhow::Result;
+use std::{collections::VecDeque, path::PathBuf, sync::Mutex};
+use thiserror::Error;
+
+use crate::bot::{events::IpcMessage, Bot};
+
+/// Something a recorded sandbox run got from outside of the sandbox
+#[derive(Clone, Deserialize, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum RecordedEvent {
+    Http {
+        url: String,
+        status: u16,
+        headers: Vec<(String, String)>,
+        /// Base64 encoded
+        body: String,
+    },
+    HttpFailed {
+        url: String,
+        error: String,
+    },
+    LoadEnv {
+        data: Option<String>,
+    },
+    IpcReceive {
+        messages: Vec<IpcMessage>,
+    },
+    StorageGet {
+        value: Option<String>,
+    },
+    StorageList {
+        keys: Vec<String>,
+    },
+}
+
+/// A sandbox run with everything needed to run it again the same way
+#[derive(Clone, Deserialize, Serialize)]
+pub struct SandboxRecording {
+    pub trace: String,
+    pub code: String,
+    /// JSON encoded variables the run started with
+    pub env: String,
+    pub random_seed: i64,
+    /// Short service ids of the message that started the run
+    pub author_id: String,
+    pub channel_id: String,
+    pub events: Vec<RecordedEvent>,
+    pub output: String,
+    pub error: Option<String>,
+    pub timestamp: i64,
+}
+
+/// Records what a sandbox run takes from outside, or hands a recording back to a replay in the same order
+pub struct SandboxTape {
+    pub random_seed: i64,
+    replay: bool,
+    events: Mutex<VecDeque<RecordedEvent>>,
+}
+
+impl SandboxTape {
+    pub fn record() -> SandboxTape {
+        SandboxTape {
+            random_seed: rand::random::<u32>() as i64,
+            replay: false,
+            events: Mutex::new(VecDeque::new()),
+        }
+    }
+
+    pub fn replay(recording: &SandboxRecording) -> SandboxTape {
+        SandboxTape {
+            random_seed: recording.random_seed,
+            replay: true,
+            events: Mutex::new(recording.events.iter().cloned().collect()),
+        }
+    }
+
+    /// Replays don't touch the network or storage, and don't have side effects outside the run
+    pub fn is_replay(&self) -> bool {
+        self.replay
+    }
+
+    pub fn record_event(&self, event: RecordedEvent) {
+        if !self.replay {
+            self.events.lock().unwrap().push_back(event);
+        }
+    }
+
+    pub fn next_event(&self) -> Option<RecordedEvent> {
+        self.events.lock().unwrap().pop_front()
+    }
+
+    pub fn events(&self) -> Vec<RecordedEvent> {
+        self.events.lock().unwrap().iter().cloned().collect()
+    }
+}
+
+fn recording_path(bot: &Bot, trace: &str) -> PathBuf {
+    bot.data_path()
+        .join("sandbox_traces")
+        .join(format!("{}.json", trace))
+}
+
+pub async fn save_recording(bot: &Bot, recording: &SandboxRecording) -> Result<()> {
+    let path = recording_path(bot, &recording.trace);
+
+    if let Some(dir) = path.parent() {
+        tokio::fs::create_dir_all(dir).await?;
+    }
+
+    tokio::fs::write(&path, serde_json::to_string(recording)?).await?;
+
+    Ok(())
+}
+
+pub async fn load_recording(bot: &Bot, trace: &str) -> Result<SandboxRecording> {
+    // Trace ids are hex, anything else could escape the traces directory
+    if trace.len() != 8 || !trace.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Err(ReplayError::UnknownTrace(trace.into()).into());
+    }
+
+    let data = match tokio::fs::read_to_string(recording_path(bot, &trace.to_lowercase())).await {
+        Ok(data) => data,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => {
+            return Err(ReplayError::UnknownTrace(trace.into()).into());
+        }
+        Err(err) => return Err(err.into()),
+    };
+
+    Ok(serde_json::from_str(&data)?)
+}
+
+#[derive(Debug, Error)]
+pub enum ReplayError {
+    #[error("no recording was found for trace {}", _0)]
+    UnknownTrace(String),
+    #[error("the replay diverged from the recording at a {}", _0)]
+    Diverged(&'static str),
+}
@@ -0,0 +1,274 @@
+use anyhow::Result;
+use chrono::{DateTime, Datelike, Duration as ChronoDuration, TimeZone, Timelike, Utc};
+use futures::channel::oneshot;
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+use thiserror::Error;
+
+use crate::bot::Bot;
+
+pub const MIN_INTERVAL: Duration = Duration::from_secs(1);
+// Enough to find the next run of a schedule that only matches on leap days
+const MAX_CRON_STEPS: usize = 100_000;
+
+#[derive(Clone, Copy)]
+struct CronField(u64);
+
+impl CronField {
+    fn parse(text: &str, min: u32, max: u32) -> Result<CronField, SchedulerError> {
+        let invalid = || SchedulerError::InvalidCron(text.into());
+        let mut bits = 0u64;
+
+        for item in text.split(',') {
+            let (range, step) = match item.split_once('/') {
+                Some((range, step)) => (range, Some(step.parse::<u32>().map_err(|_| invalid())?)),
+                None => (item, None),
+            };
+
+            let (start, end) = match range.split_once('-') {
+                _ if range == "*" => (min, max),
+                Some((start, end)) => (
+                    start.parse().map_err(|_| invalid())?,
+                    end.parse().map_err(|_| invalid())?,
+                ),
+                // "5/15" runs from 5 to the end of the range
+                None if step.is_some() => (range.parse().map_err(|_| invalid())?, max),
+                None => {
+                    let value = range.parse().map_err(|_| invalid())?;
+                    (value, value)
+                }
+            };
+
+            let step = step.unwrap_or(1);
+
+            if step == 0 || start < min || end > max || start > end {
+                return Err(invalid());
+            }
+
+            for value in (start..=end).step_by(step as usize) {
+                bits |= 1 << value;
+            }
+        }
+
+        Ok(CronField(bits))
+    }
+
+    fn matches(self, value: u32) -> bool {
+        self.0 & (1 << value) != 0
+    }
+}
+
+/// A five field cron expression (minute, hour, day of month, month, day of week), in UTC
+#[derive(Clone, Copy)]
+pub struct CronSchedule {
+    minutes: CronField,
+    hours: CronField,
+    days: CronField,
+    months: CronField,
+    weekdays: CronField,
+    // Like cron, a day matches either restricted day field if both are restricted
+    any_day: bool,
+    any_weekday: bool,
+}
+
+impl CronSchedule {
+    pub fn parse(expr: &str) -> Result<CronSchedule, SchedulerError> {
+        let expr = match expr.trim() {
+            "@hourly" => "0 * * * *",
+            "@daily" | "@midnight" => "0 0 * * *",
+            "@weekly" => "0 0 * * 0",
+            "@monthly" => "0 0 1 * *",
+            "@yearly" | "@annually" => "0 0 1 1 *",
+            expr => expr,
+        };
+
+        let fields: Vec<&str> = expr.split_whitespace().collect();
+
+        if fields.len() != 5 {
+            return Err(SchedulerError::InvalidCron(expr.into()));
+        }
+
+        let mut weekdays = CronField::parse(fields[4], 0, 7)?;
+
+        // Sunday is both 0 and 7
+        if weekdays.matches(7) {
+            weekdays.0 |= 1;
+        }
+
+        Ok(CronSchedule {
+            minutes: CronField::parse(fields[0], 0, 59)?,
+            hours: CronField::parse(fields[1], 0, 23)?,
+            days: CronField::parse(fields[2], 1, 31)?,
+            months: CronField::parse(fields[3], 1, 12)?,
+            weekdays,
+            any_day: fields[2].starts_with('*'),
+            any_weekday: fields[4].starts_with('*'),
+        })
+    }
+
+    fn day_matches(&self, time: &DateTime<Utc>) -> bool {
+        let day = self.days.matches(time.day());
+        let weekday = self.weekdays.matches(time.weekday().num_days_from_sunday());
+
+        match (self.any_day, self.any_weekday) {
+            (true, true) => true,
+            (true, false) => weekday,
+            (false, true) => day,
+            (false, false) => day || weekday,
+        }
+    }
+
+    /// The first minute after the time the schedule matches
+    pub fn next_after(&self, after: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        let mut time = after.with_second(0)?.with_nanosecond(0)? + ChronoDuration::minutes(1);
+
+        for _ in 0..MAX_CRON_STEPS {
+            if !self.months.matches(time.month()) {
+                let (year, month) = match time.month() {
+                    12 => (time.year() + 1, 1),
+                    month => (time.year(), month + 1),
+                };
+
+                time = Utc.ymd(year, month, 1).and_hms(0, 0, 0);
+            } else if !self.day_matches(&time) {
+                time = (time + ChronoDuration::days(1)).date().and_hms(0, 0, 0);
+            } else if !self.hours.matches(time.hour()) {
+                time = time.with_minute(0)? + ChronoDuration::hours(1);
+            } else if !self.minutes.matches(time.minute()) {
+                time = time + ChronoDuration::minutes(1);
+            } else {
+                return Some(time);
+            }
+        }
+
+        None
+    }
+}
+
+#[derive(Clone, Copy)]
+pub enum Schedule {
+    Interval(Duration),
+    Cron(CronSchedule),
+}
+
+impl Schedule {
+    fn next_after(&self, after: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        match self {
+            Schedule::Interval(interval) => Some(after + ChronoDuration::from_std(*interval).ok()?),
+            Schedule::Cron(cron) => cron.next_after(after),
+        }
+    }
+}
+
+/// A recurring job of a state, the lua side waits for each run and calls its function
+pub struct ScheduledJob {
+    schedule: Schedule,
+    /// Named jobs of the bot state keep their next run across restarts
+    name: Option<(Arc<Bot>, String)>,
+    next_run: Mutex<Option<DateTime<Utc>>>,
+    cancelled: AtomicBool,
+    cancel_sender: Mutex<Option<oneshot::Sender<()>>>,
+}
+
+impl ScheduledJob {
+    pub fn new(schedule: Schedule, name: Option<(Arc<Bot>, String)>) -> Arc<ScheduledJob> {
+        Arc::new(ScheduledJob {
+            schedule,
+            name,
+            next_run: Mutex::new(None),
+            cancelled: AtomicBool::new(false),
+            cancel_sender: Mutex::new(None),
+        })
+    }
+
+    pub fn next_run(&self) -> Option<DateTime<Utc>> {
+        *self.next_run.lock().unwrap()
+    }
+
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::Release);
+
+        if let Some(sender) = self.cancel_sender.lock().unwrap().take() {
+            sender.send(()).ok();
+        }
+
+        // A cancelled job doesn't catch up on its run after a restart
+        if let Some((bot, name)) = self.name.clone() {
+            tokio::spawn(async move {
+                if let Err(err) = bot.db().remove_scheduled_job(&name).await {
+                    println!("error removing scheduled job {}: {}", name, err.to_string());
+                }
+            });
+        }
+    }
+
+    async fn first_run(&self, now: DateTime<Utc>) -> Result<Option<DateTime<Utc>>> {
+        // A run missed while the bot was down happens right away, once
+        if let Some((bot, name)) = &self.name {
+            if let Some(next_run) = bot.db().scheduled_job_next_run(name).await? {
+                return Ok(Some(Utc.timestamp(next_run, 0)));
+            }
+        }
+
+        Ok(self.schedule.next_after(now))
+    }
+
+    /// Wait for the next run, resolves to false once the job is cancelled
+    pub async fn wait(&self) -> Result<bool> {
+        let (sender, receiver) = oneshot::channel();
+        *self.cancel_sender.lock().unwrap() = Some(sender);
+
+        // Checked after the sender is in place, so a cancel can't slip in between
+        if self.cancelled.load(Ordering::Acquire) {
+            return Ok(false);
+        }
+
+        let now = Utc::now();
+        let next_run = match self.next_run() {
+            Some(next_run) => Some(next_run),
+            None => self.first_run(now).await?,
+        };
+        let next_run = next_run.ok_or(SchedulerError::NeverRuns)?;
+
+        *self.next_run.lock().unwrap() = Some(next_run);
+
+        let wait = (next_run - now).to_std().unwrap_or_default();
+
+        tokio::select! {
+            _ = tokio::time::sleep(wait) => {}
+            _ = receiver => return Ok(false),
+        }
+
+        let following = self
+            .schedule
+            .next_after(next_run.max(Utc::now()))
+            .ok_or(SchedulerError::NeverRuns)?;
+
+        *self.next_run.lock().unwrap() = Some(following);
+
+        if let Some((bot, name)) = &self.name {
+            bot.db()
+                .set_scheduled_job_next_run(name, following.timestamp())
+                .await?;
+        }
+
+        Ok(true)
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum SchedulerError {
+    #[error("invalid cron expression: \"{}\"", _0)]
+    InvalidCron(String),
+    #[error("the schedule never runs")]
+    NeverRuns,
+    #[error("intervals can't be shorter than {} seconds", _0)]
+    IntervalTooShort(u64),
+    #[error("only jobs of the bot state can be named")]
+    NamedInSandbox,
+}
@@ -0,0 +1,310 @@
+use anyhow::Result;
+use async_mutex::Mutex;
+use lru::LruCache;
+use sha2::{Digest, Sha256};
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+use thiserror::Error;
+use tokio::process::Command;
+
+use super::{clock::LuaClock, pool::SandboxPool, state::LuaState};
+use crate::{bot::Bot, config::ConfigScripts};
+
+// Rollbacks by hash need enough of it that a typo doesn't pick some other version
+const MIN_HASH_PREFIX: usize = 7;
+
+async fn git(dir: &Path, args: &[&str]) -> Result<String> {
+    let out = Command::new("git")
+        .arg("-C")
+        .arg(dir)
+        .args(args)
+        .output()
+        .await?;
+
+    if !out.status.success() {
+        return Err(ScriptsError::GitError(
+            args.first().map(|s| s.to_string()).unwrap_or_default(),
+            String::from_utf8_lossy(&out.stderr).trim().to_string(),
+        )
+        .into());
+    }
+
+    Ok(String::from_utf8_lossy(&out.stdout).trim().to_string())
+}
+
+/// Fetch the configured repository and check out the configured branch or commit,
+/// returning the lua root path inside the checkout and the commit hash
+pub async fn sync_git(bot: &Arc<Bot>, config: &ConfigScripts) -> Result<(PathBuf, String)> {
+    let repo_path = bot.data_path().join("scripts").join("repo");
+
+    if !repo_path.join(".git").is_dir() {
+        tokio::fs::create_dir_all(&repo_path).await?;
+        git(&repo_path, &["init", "--quiet"]).await?;
+        git(&repo_path, &["remote", "add", "origin", &config.git_url]).await?;
+    } else {
+        git(&repo_path, &["remote", "set-url", "origin", &config.git_url]).await?;
+    }
+
+    let branch = config.branch.as_deref().unwrap_or("master");
+    git(&repo_path, &["fetch", "--quiet", "origin", branch]).await?;
+
+    let target = match config.commit.as_deref() {
+        Some(commit) => commit.to_string(),
+        None => "FETCH_HEAD".to_string(),
+    };
+
+    git(&repo_path, &["checkout", "--quiet", "--force", "--detach", &target]).await?;
+    git(&repo_path, &["clean", "--quiet", "-fdx"]).await?;
+
+    let commit = git(&repo_path, &["rev-parse", "HEAD"]).await?;
+    let lua_root_path = repo_path.join(config.path.as_deref().unwrap_or("lua"));
+
+    if !lua_root_path.join("bot.lua").is_file() || !lua_root_path.join("sandbox.lua").is_file() {
+        return Err(ScriptsError::MissingEntryPoints(commit).into());
+    }
+
+    Ok((lua_root_path, commit))
+}
+
+/// Load the scripts into scratch states to make sure they load cleanly before swapping them in
+pub fn validate_scripts(bot: &Arc<Bot>, lua_root_path: &Path) -> Result<()> {
+    let clock = LuaClock::from_config(bot);
+    LuaState::create_state(bot, true, None, lua_root_path, &clock)
+        .map_err(|err| ScriptsError::ValidationFailed("sandbox.lua".into(), err.to_string()))?;
+
+    LuaState::create_state(
+        bot,
+        false,
+        Some((
+            SandboxPool::new(bot, lua_root_path.to_path_buf(), &clock),
+            Arc::new(Mutex::new(LruCache::new(1))),
+        )),
+        lua_root_path,
+        &clock,
+    )
+    .map_err(|err| ScriptsError::ValidationFailed("bot.lua".into(), err.to_string()))?;
+
+    Ok(())
+}
+
+#[derive(Clone, Deserialize, Serialize, Debug)]
+pub struct ScriptVersion {
+    pub hash: String,
+    pub commit: Option<String>,
+    pub timestamp: i64,
+}
+
+#[derive(Clone, Default, Deserialize, Serialize, Debug)]
+pub struct ScriptManifest {
+    pub active: Option<String>,
+    /// Stored versions, oldest first
+    pub versions: Vec<ScriptVersion>,
+}
+
+/// Immutable copies of deployed script bundles, stored under `data/scripts/versions`
+pub struct ScriptVersions {
+    path: PathBuf,
+    keep: usize,
+}
+
+impl ScriptVersions {
+    pub fn new(bot: &Arc<Bot>) -> ScriptVersions {
+        ScriptVersions {
+            path: bot.data_path().join("scripts").join("versions"),
+            keep: bot
+                .config()
+                .scripts
+                .as_ref()
+                .and_then(|c| c.keep_versions)
+                .unwrap_or(5)
+                .max(1),
+        }
+    }
+
+    fn manifest_path(&self) -> PathBuf {
+        self.path.join("manifest.json")
+    }
+
+    pub fn version_path(&self, hash: &str) -> PathBuf {
+        self.path.join(hash)
+    }
+
+    pub fn manifest(&self) -> Result<ScriptManifest> {
+        match fs::read(self.manifest_path()) {
+            Ok(data) => Ok(serde_json::from_slice(&data)?),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(ScriptManifest::default()),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    fn save_manifest(&self, manifest: &ScriptManifest) -> Result<()> {
+        // Write to a temporary file first so the manifest is replaced atomically
+        let tmp_path = self.path.join("manifest.json.tmp");
+        fs::write(&tmp_path, serde_json::to_vec_pretty(manifest)?)?;
+        fs::rename(tmp_path, self.manifest_path())?;
+
+        Ok(())
+    }
+
+    /// Copy the scripts into a new immutable version, returning the existing one if the contents are unchanged
+    pub fn store(&self, lua_root_path: &Path, commit: Option<String>) -> Result<ScriptVersion> {
+        fs::create_dir_all(&self.path)?;
+
+        let hash = hash_dir(lua_root_path)?;
+        let mut manifest = self.manifest()?;
+
+        if let Some(version) = manifest.versions.iter().find(|v| v.hash == hash) {
+            return Ok(version.clone());
+        }
+
+        let version_path = self.version_path(&hash);
+
+        if !version_path.is_dir() {
+            let tmp_path = self.path.join(format!(".tmp-{}", hash));
+            let _ = fs::remove_dir_all(&tmp_path);
+            copy_dir(lua_root_path, &tmp_path)?;
+            fs::rename(tmp_path, &version_path)?;
+        }
+
+        let version = ScriptVersion {
+            hash,
+            commit,
+            timestamp: chrono::Utc::now().timestamp(),
+        };
+
+        manifest.versions.push(version.clone());
+        self.save_manifest(&manifest)?;
+
+        Ok(version)
+    }
+
+    /// Mark the version as active and prune the oldest versions past the limit
+    pub fn activate(&self, hash: &str) -> Result<()> {
+        let mut manifest = self.manifest()?;
+        manifest.active = Some(hash.to_string());
+
+        while manifest.versions.len() > self.keep {
+            let idx = match manifest.versions.iter().position(|v| v.hash != hash) {
+                Some(idx) => idx,
+                None => break,
+            };
+
+            let version = manifest.versions.remove(idx);
+            let _ = fs::remove_dir_all(self.version_path(&version.hash));
+        }
+
+        self.save_manifest(&manifest)
+    }
+
+    /// Find the version to roll back to, either by hash prefix or the one before the active version
+    pub fn rollback_target(&self, hash: Option<&str>) -> Result<ScriptVersion> {
+        let manifest = self.manifest()?;
+
+        let version = match hash {
+            Some(hash) => {
+                if hash.len() < MIN_HASH_PREFIX {
+                    return Err(ScriptsError::HashTooShort(MIN_HASH_PREFIX).into());
+                }
+
+                let prefix = hash.to_lowercase();
+                let mut matches = manifest
+                    .versions
+                    .iter()
+                    .filter(|v| v.hash.starts_with(&prefix));
+
+                match (matches.next(), matches.next()) {
+                    (Some(version), None) => version,
+                    (Some(_), Some(_)) => {
+                        return Err(ScriptsError::AmbiguousVersion(hash.into()).into())
+                    }
+                    (None, _) => return Err(ScriptsError::UnknownVersion(hash.into()).into()),
+                }
+            }
+            None => {
+                let active_idx = manifest
+                    .active
+                    .as_ref()
+                    .and_then(|active| manifest.versions.iter().position(|v| &v.hash == active))
+                    .unwrap_or(manifest.versions.len());
+
+                active_idx
+                    .checked_sub(1)
+                    .and_then(|idx| manifest.versions.get(idx))
+                    .ok_or(ScriptsError::NoPreviousVersion)?
+            }
+        };
+
+        Ok(version.clone())
+    }
+}
+
+fn collect_files(root: &Path, dir: &Path, out: &mut Vec<PathBuf>) -> Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+
+        if path.is_dir() {
+            collect_files(root, &path, out)?;
+        } else {
+            out.push(path.strip_prefix(root)?.to_path_buf());
+        }
+    }
+
+    Ok(())
+}
+
+fn hash_dir(dir: &Path) -> Result<String> {
+    let mut files = Vec::new();
+    collect_files(dir, dir, &mut files)?;
+    files.sort();
+
+    let mut hasher = Sha256::new();
+
+    for file in files {
+        hasher.update(file.to_string_lossy().as_bytes());
+        hasher.update([0]);
+        hasher.update(fs::read(dir.join(&file))?);
+        hasher.update([0]);
+    }
+
+    Ok(hex::encode(&hasher.finalize()[..8]))
+}
+
+fn copy_dir(from: &Path, to: &Path) -> Result<()> {
+    let mut files = Vec::new();
+    collect_files(from, from, &mut files)?;
+
+    for file in files {
+        let dest = to.join(&file);
+
+        if let Some(parent) = dest.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        fs::copy(from.join(&file), dest)?;
+    }
+
+    Ok(())
+}
+
+#[derive(Debug, Error)]
+pub enum ScriptsError {
+    #[error("script syncing has not been configured")]
+    NotConfigured,
+    #[error("git {} failed: {}", _0, _1)]
+    GitError(String, String),
+    #[error("commit {} is missing bot.lua or sandbox.lua", _0)]
+    MissingEntryPoints(String),
+    #[error("error loading {}: {}", _0, _1)]
+    ValidationFailed(String, String),
+    #[error("unknown script version \"{}\"", _0)]
+    UnknownVersion(String),
+    #[error("\"{}\" matches several script versions", _0)]
+    AmbiguousVersion(String),
+    #[error("give at least {} characters of the version hash", _0)]
+    HashTooShort(usize),
+    #[error("there is no previous script version to roll back to")]
+    NoPreviousVersion,
+}
@@ -1,47 +1,121 @@
-use anyhow::Result;
-use async_mutex::Mutex;
+use anyhow::{anyhow, Result};
 use crossbeam::channel::{unbounded, Receiver, Sender};
-use governor::{
-    clock::QuantaClock,
-    state::{direct::NotKeyed, InMemoryState},
-    Quota, RateLimiter,
-};
+use futures::{channel::oneshot, StreamExt};
 use mlua::{
-    prelude::{LuaError, LuaMultiValue, LuaValue},
-    Function, Lua, LuaSerdeExt, RegistryKey, StdLib, Table, Thread, ThreadStatus, ToLua, UserData,
+    prelude::{LuaError, LuaMultiValue, LuaResult, LuaValue},
+    Function, HookTriggers, Lua, LuaSerdeExt, StdLib, Table, Thread, ThreadStatus, ToLua, UserData,
     UserDataMethods,
 };
 use paste::paste;
-use std::sync::{
-    atomic::{AtomicBool, AtomicU64, Ordering},
-    Arc,
+use serde_json::Value as JsonValue;
+use std::{
+    collections::{HashSet, VecDeque},
+    fmt,
+    future::Future,
+    panic::{self, AssertUnwindSafe},
+    path::{Path, PathBuf},
+    pin::Pin,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering},
+        Arc, Mutex as StdMutex,
+    },
+    task::{Context, Poll},
+    time::{Duration, Instant},
 };
+use thiserror::Error;
 
 use super::{
-    http,
+    clock::LuaClock,
+    error::{create_error_value, value_error_kind},
+    http::{self, HttpQuota},
     lib::{
-        bot::{bot_flags, lib_bot, BotMessage, BotUser},
+        blob::lib_blob,
+        bot::{bot_flags, lib_bot, BotCaller, BotInteraction, BotMessage, BotUser},
+        chart::lib_chart,
+        emoji::lib_emoji,
+        fs::lib_fs,
+        gif::lib_gif,
+        github::lib_github,
         image::lib_image,
-        include_lua, lib_include,
+        include_lua,
+        latex::lib_latex,
+        lib_include,
+        moderation::lib_moderation,
         os::lib_os,
-        r#async::lib_async,
+        polls::lib_polls,
+        proc::lib_proc,
+        qr::lib_qr,
+        quotes::lib_quotes,
+        r#async::{lib_async, register_thread, AsyncError, AsyncSender, AsyncWaker, FutureLimiter},
+        server_scripts::lib_server_scripts,
+        storage::lib_storage,
         tags::lib_tags,
+        throttle::lib_throttle,
+        time::lib_time,
+        translate::lib_translate,
         voice::lib_voice,
     },
+    limiter::RateLimit,
+    pool::SandboxPool,
+    replay::{RecordedEvent, ReplayError, SandboxTape},
+    utils::{panic_message, TraceId},
     LuaSandboxReplies,
 };
 use crate::{
-    bot::Bot,
-    message::MessageSettings,
+    bot::{
+        db::{Sid, Uid},
+        events::ServiceAudit,
+        permissions::Caller,
+        Bot,
+    },
+    message::{MessagePriority, MessageSettings},
     services::{ChannelId, MessageId, ServerId},
     utils::escape_untrusted_text,
 };
 
-pub type LuaAsyncCallback = (
-    RegistryKey,
-    Option<SandboxState>,
-    Box<dyn FnOnce(&Lua) -> Result<LuaMultiValue> + Send>,
-);
+/// Size limit of an environment saved with `sandbox.save()`
+pub const MAX_SAVED_ENV_SIZE: usize = 64 * 1024;
+/// Allocations over this fail, after Lua tried a full collection
+const MEMORY_LIMIT: usize = 256 * 1024 * 1024;
+const DEFAULT_GC_STEP_KBYTES: i32 = 64;
+const DEFAULT_SOFT_MEMORY_LIMIT: usize = 192;
+/// Over the soft limit, the heap has to grow by a quarter of what the last full collection left
+const FULL_COLLECT_GROWTH_DIVISOR: usize = 4;
+const HOOK_EVERY_INSTRUCTION: u32 = 32;
+/// Seconds a sandboxed coroutine may run for
+pub const SANDBOX_TIME_LIMIT: f64 = 30.0;
+/// Longest delay of output scheduled with `sandbox.later()`, in seconds
+const MAX_LATER_DELAY: u64 = 15;
+/// Woken threads resumed per think, the rest wait for the next one
+const ASYNC_THREADS_PER_TICK: usize = 256;
+/// Rounds of woken threads resumed before the state is replaced, settled futures wake more
+const DRAIN_ROUNDS: usize = 16;
+
+/// The trace of the command or sandbox run that is currently executing
+pub fn current_trace(state: &Lua) -> Option<TraceId> {
+    if let Ok(sandbox_state) = state.named_registry_value::<_, SandboxState>("__SANDBOX_STATE") {
+        return Some(sandbox_state.0.trace);
+    }
+
+    state
+        .named_registry_value::<_, Option<u32>>("__CURRENT_TRACE")
+        .ok()
+        .flatten()
+        .map(TraceId::from_raw)
+}
+
+/// Whether the current trace is a command run by a bot owner, which sandboxes never are
+pub fn caller_is_owner(state: &Lua) -> bool {
+    let trace = match current_trace(state) {
+        Some(trace) => trace,
+        None => return false,
+    };
+
+    state
+        .named_registry_value::<_, Table>("__OWNER_TRACES")
+        .and_then(|traces| traces.get::<_, LuaValue>(trace.raw()))
+        .map_or(false, |value| !matches!(value, LuaValue::Nil))
+}
 
 macro_rules! atomic_get_set {
     ($ident:ident, $ty:ty) => {
@@ -74,111 +148,429 @@ macro_rules! atomic_limit {
     };
 }
 
+fn parse_stdlib(name: &str) -> Option<StdLib> {
+    Some(match name {
+        "coroutine" => StdLib::COROUTINE,
+        "table" => StdLib::TABLE,
+        "io" => StdLib::IO,
+        "os" => StdLib::OS,
+        "string" => StdLib::STRING,
+        "utf8" => StdLib::UTF8,
+        "math" => StdLib::MATH,
+        "package" => StdLib::PACKAGE,
+        "debug" => StdLib::DEBUG,
+        _ => return None,
+    })
+}
+
+/// Standard libraries to load for a state kind, the ones the bundled scripts depend on are always included
+fn state_stdlib(bot: &Bot, sandbox: bool) -> Result<StdLib> {
+    // debug is needed by sandbox.lua for setfenv and by async.lua for the registry
+    let required = StdLib::COROUTINE | StdLib::TABLE | StdLib::STRING | StdLib::DEBUG;
+
+    let config = bot.config().lua.as_ref();
+    let names = if sandbox {
+        config.and_then(|c| c.sandbox_stdlib.as_ref())
+    } else {
+        config.and_then(|c| c.bot_stdlib.as_ref())
+    };
+
+    let names = match names {
+        Some(names) => names,
+        None => return Ok(required | StdLib::UTF8 | StdLib::MATH),
+    };
+
+    let mut libs = required;
+
+    for name in names {
+        let lib = parse_stdlib(name)
+            .ok_or_else(|| anyhow!("unknown lua standard library \"{}\"", name))?;
+
+        // Untrusted code must never reach the host
+        if sandbox && (lib == StdLib::IO || lib == StdLib::OS || lib == StdLib::PACKAGE) {
+            return Err(anyhow!("the \"{}\" library is not allowed in the sandbox", name));
+        }
+
+        libs |= lib;
+    }
+
+    Ok(libs)
+}
+
 pub fn is_sandboxed(state: &Lua) -> bool {
     state
         .named_registry_value::<str, SandboxState>("__SANDBOX_STATE")
         .is_ok()
 }
 
+fn seed_random(state: &Lua, seed: i64) -> Result<()> {
+    // math is only loaded when the config asks for it
+    if let Some(math) = state.globals().get::<_, Option<Table>>("math")? {
+        let randomseed_fn: Function = math.get("randomseed")?;
+        randomseed_fn.call::<_, ()>(seed)?;
+    }
+
+    Ok(())
+}
+
+/// Run a call into lua, turning a panic in it or in one of the bindings into an error
+fn catch_panic<R>(trace: Option<TraceId>, f: impl FnOnce() -> Result<R>) -> Result<R> {
+    match panic::catch_unwind(AssertUnwindSafe(f)) {
+        Ok(res) => res,
+        Err(payload) => {
+            let message = panic_message(&*payload);
+
+            match trace {
+                Some(trace) => println!("lua call panicked [trace {}]: {}", trace, message),
+                None => println!("lua call panicked: {}", message),
+            }
+
+            Err(LuaStateError::Panicked(message).into())
+        }
+    }
+}
+
+fn is_memory_error(err: &anyhow::Error) -> bool {
+    err.chain().any(|err| {
+        matches!(
+            err.downcast_ref::<LuaError>(),
+            Some(LuaError::MemoryError(_))
+        )
+    })
+}
+
+/// Lua reports allocations over the memory limit as "not enough memory", mlua as a memory error
+fn is_memory_error_message(message: &str) -> bool {
+    message.contains("not enough memory") || message.starts_with("memory error")
+}
+
 pub fn get_sandbox_state(state: &Lua) -> Option<SandboxState> {
     state.named_registry_value("__SANDBOX_STATE").ok().clone()
 }
 
+/// Counts the instructions run by sandboxed coroutines and stops them once they go over their limits
+fn set_sandbox_hook(state: &Lua, clock: LuaClock, killed: Arc<AtomicBool>) -> Result<()> {
+    let coroutine: Table = state.globals().get("coroutine")?;
+    let running_fn: Function = coroutine.get("running")?;
+    state.set_named_registry_value("__SANDBOX_COROUTINE_RUNNING", running_fn)?;
+
+    let deadlines = state.create_table()?;
+    let deadlines_mt = state.create_table()?;
+    deadlines_mt.set("__mode", "k")?;
+    deadlines.set_metatable(Some(deadlines_mt));
+    state.set_named_registry_value("__SANDBOX_DEADLINES", deadlines)?;
+
+    state.set_hook(
+        HookTriggers {
+            every_nth_instruction: Some(HOOK_EVERY_INSTRUCTION),
+            ..Default::default()
+        },
+        move |state, _debug| {
+            // Set by the sandbox pool once the run is past saving, it's abandoned either way
+            if killed.load(Ordering::Relaxed) {
+                return Err(LuaError::RuntimeError(
+                    "Execution time limit reached".into(),
+                ));
+            }
+
+            let sandbox_state = match get_sandbox_state(state) {
+                Some(sandbox_state) => sandbox_state,
+                None => return Ok(()),
+            };
+
+            // The main thread only runs sandbox.lua, sandboxed code always runs in a coroutine
+            let running_fn: Function = state.named_registry_value("__SANDBOX_COROUTINE_RUNNING")?;
+            let (thread, main): (Thread, bool) = running_fn.call(())?;
+            if main {
+                return Ok(());
+            }
+
+            // Threads awaiting the futures of sandboxed code only run rust callbacks
+            let thread_ids: Table = state.named_registry_value("__ASYNC_THREAD_IDS")?;
+            if thread_ids
+                .raw_get::<_, Option<u64>>(thread.clone())?
+                .is_some()
+            {
+                return Ok(());
+            }
+
+            let instructions_run = sandbox_state
+                .0
+                .instructions_run
+                .fetch_add(HOOK_EVERY_INSTRUCTION as u64, Ordering::Relaxed)
+                + HOOK_EVERY_INSTRUCTION as u64;
+
+            sandbox_state
+                .0
+                .peak_memory
+                .fetch_max(state.used_memory(), Ordering::Relaxed);
+
+            if instructions_run >= sandbox_state.0.limits.instructions {
+                sandbox_state
+                    .0
+                    .sender
+                    .send(SandboxMsg::Terminated(SandboxTerminationReason::ExecutionQuota))
+                    .ok();
+
+                return Err(LuaError::RuntimeError("Execution quota exceeded".into()));
+            }
+
+            let now = clock.elapsed().as_secs_f64();
+            let deadlines: Table = state.named_registry_value("__SANDBOX_DEADLINES")?;
+
+            match deadlines.raw_get::<_, Option<f64>>(thread.clone())? {
+                Some(deadline) if now > deadline => {
+                    sandbox_state
+                        .0
+                        .sender
+                        .send(SandboxMsg::Terminated(SandboxTerminationReason::TimeLimit))
+                        .ok();
+
+                    return Err(LuaError::RuntimeError(
+                        "Execution time limit reached".into(),
+                    ));
+                }
+                Some(_) => {}
+                None => deadlines.raw_set(thread, now + SANDBOX_TIME_LIMIT)?,
+            }
+
+            Ok(())
+        },
+    )?;
+
+    Ok(())
+}
+
+/// Where the environment saved by a sandbox owner is stored
+pub fn sandbox_env_path(bot: &Bot, owner: Uid) -> PathBuf {
+    bot.data_path()
+        .join("sandbox_env")
+        .join(format!("{}.json", owner))
+}
+
+/// Where the environment saved by an installed server script is stored, apart from any user
+pub fn script_env_path(bot: &Bot, sid: Sid, name: &str) -> PathBuf {
+    bot.data_path()
+        .join("sandbox_env")
+        .join("scripts")
+        .join(format!("{}_{}.json", sid, name))
+}
+
+/// Whose saved environment and messaging channels a sandbox run uses
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum SandboxOwner {
+    User(Uid),
+    /// A script installed on a server
+    Script(Sid, String),
+}
+
+impl SandboxOwner {
+    pub fn env_path(&self, bot: &Bot) -> PathBuf {
+        match self {
+            SandboxOwner::User(uid) => sandbox_env_path(bot, *uid),
+            SandboxOwner::Script(sid, name) => script_env_path(bot, *sid, name),
+        }
+    }
+}
+
+impl fmt::Display for SandboxOwner {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SandboxOwner::User(uid) => write!(f, "user:{}", uid),
+            SandboxOwner::Script(_, name) => write!(f, "script:{}", name),
+        }
+    }
+}
+
 pub struct LuaState {
     bot: Arc<Bot>,
     inner: Lua,
     sandbox: bool,
-    async_sender: Sender<LuaAsyncCallback>,
-    async_receiver: Receiver<LuaAsyncCallback>,
-    http_rate_limiter: Arc<RateLimiter<NotKeyed, InMemoryState, QuantaClock>>,
-    thread_id: Arc<AtomicU64>,
+    gc: GcSettings,
+    /// Heap size right after the last full collection
+    gc_collected_memory: AtomicUsize,
+    async_sender: AsyncSender,
+    woken_threads: StdMutex<WokenThreads>,
+    future_limiter: FutureLimiter,
+    async_waker: AsyncWaker,
+    clock: LuaClock,
     shutting_down: AtomicBool,
+    /// Set once the state ran into an error it can't recover from
+    crash_reason: StdMutex<Option<String>>,
+    /// Stops sandboxed code at the next instruction hook
+    killed: Arc<AtomicBool>,
 }
 
 impl LuaState {
     pub fn create_state(
         bot: &Arc<Bot>,
         sandbox: bool,
-        bot_state: Option<(Arc<Mutex<LuaState>>, Arc<LuaSandboxReplies>)>,
+        bot_state: Option<(Arc<SandboxPool>, Arc<LuaSandboxReplies>)>,
+        lua_root_path: &Path,
+        clock: &LuaClock,
     ) -> Result<LuaState> {
-        // Avoid loading os and io
-        let inner = unsafe {
-            Lua::unsafe_new_with(
-                StdLib::COROUTINE
-                    | StdLib::TABLE
-                    | StdLib::STRING
-                    | StdLib::UTF8
-                    | StdLib::MATH
-                    | StdLib::DEBUG,
-                Default::default(),
-            )
-        };
-
-        let (async_sender, async_receiver) = unbounded();
-
-        let thread_id = Arc::new(AtomicU64::new(0));
-
-        lib_async(&inner, async_sender.clone(), thread_id.clone())?;
-        lib_os(&inner)?;
+        // os and io are only loaded when the config asks for them
+        let inner = unsafe { Lua::unsafe_new_with(state_stdlib(bot, sandbox)?, Default::default()) };
+
+        let future_limiter = FutureLimiter::from_config(bot);
+        inner.set_named_registry_value("__FUTURE_LIMITER", future_limiter.clone())?;
+        let async_waker = AsyncWaker::default();
+        let async_sender = AsyncSender::new(async_waker.clone());
+
+        // Threads resumed by think, by id. Rust futures are awaited by threads of their own.
+        inner.set_named_registry_value("__ASYNC_THREADS", inner.create_table()?)?;
+        inner.set_named_registry_value("__ASYNC_THREADS_CHANNELS", inner.create_table()?)?;
+        inner.set_named_registry_value("__ASYNC_THREADS_TRACES", inner.create_table()?)?;
+        inner.set_named_registry_value("__ASYNC_THREADS_FUTURES", inner.create_table()?)?;
+        inner.set_named_registry_value("__ASYNC_THREADS_SANDBOX_STATES", inner.create_table()?)?;
+        inner.set_named_registry_value("__ASYNC_THREAD_IDS", inner.create_table()?)?;
+
+        let killed = Arc::new(AtomicBool::new(false));
+
+        lib_async(&inner, bot, async_sender.clone())?;
+        lib_os(&inner, bot, clock.clone(), sandbox)?;
+        lib_time(&inner, clock.clone())?;
+
+        if let Some(seed) = clock.random_seed() {
+            seed_random(&inner, seed)?;
+        }
 
-        let lua_root_path = bot.share_path().join("lua");
+        let lua_root_path = lua_root_path.to_path_buf();
 
         lib_include(lua_root_path.clone(), &inner)?;
         lib_image(&inner, bot.clone(), async_sender.clone())?;
+        lib_latex(&inner, bot, async_sender.clone(), clock)?;
+        lib_chart(&inner, async_sender.clone())?;
+        lib_qr(&inner, async_sender.clone())?;
+        lib_emoji(&inner)?;
+        lib_storage(&inner, bot, async_sender.clone())?;
 
         if sandbox {
             let bot_tbl = inner.create_table()?;
             bot_flags(&inner, &bot_tbl)?;
             inner.globals().set("bot", bot_tbl)?;
+            set_sandbox_hook(&inner, clock.clone(), killed.clone())?;
             include_lua(&inner, &lua_root_path, "sandbox.lua")?;
         } else {
+            let (sandbox_pool, lua_sandbox_replies) =
+                bot_state.expect("sandbox pool for bot state");
+
             lib_bot(
                 &inner,
                 bot,
                 async_sender.clone(),
-                bot_state.expect("sandbox state for bot state"),
+                (sandbox_pool.clone(), lua_sandbox_replies),
+                clock,
             )?;
             http::lib_http(&inner, async_sender.clone())?;
+            lib_fs(&inner, bot)?;
+            lib_proc(&inner, bot, async_sender.clone())?;
             lib_tags(&inner, bot, async_sender.clone())?;
+            lib_quotes(&inner, bot, async_sender.clone())?;
+            lib_blob(&inner, bot, async_sender.clone())?;
             lib_voice(&inner, bot, async_sender.clone())?;
-            inner.set_named_registry_value("__ASYNC_THREADS", inner.create_table()?)?;
-            inner.set_named_registry_value("__ASYNC_THREADS_CHANNELS", inner.create_table()?)?;
+            lib_translate(&inner, bot, async_sender.clone())?;
+            lib_moderation(&inner, bot, async_sender.clone())?;
+            lib_polls(&inner, bot, async_sender.clone())?;
+            lib_gif(&inner, bot, async_sender.clone())?;
+            lib_github(&inner, bot, async_sender.clone())?;
+            lib_throttle(&inner, bot)?;
+            lib_server_scripts(
+                &inner,
+                bot,
+                async_sender.clone(),
+                sandbox_pool,
+                clock.clone(),
+            )?;
+            inner.set_named_registry_value("__OWNER_TRACES", inner.create_table()?)?;
             include_lua(&inner, &lua_root_path, "bot.lua")?;
         }
 
-        // Limit memory to 256 MiB
-        inner.set_memory_limit(256 * 1024 * 1024)?;
-
-        let http_rate_limiter = Arc::new(RateLimiter::direct(Quota::per_second(
-            std::num::NonZeroU32::new(2).unwrap(),
-        )));
+        let gc = GcSettings::from_config(bot);
+        inner.gc_inc(gc.pause, gc.step_multiplier, 0);
+        inner.set_memory_limit(MEMORY_LIMIT)?;
 
         Ok(LuaState {
             bot: bot.clone(),
             inner,
             sandbox,
+            gc,
+            gc_collected_memory: AtomicUsize::new(0),
             async_sender,
-            async_receiver,
-            http_rate_limiter,
-            thread_id,
+            woken_threads: StdMutex::new(WokenThreads::default()),
+            future_limiter,
+            async_waker,
+            clock: clock.clone(),
             shutting_down: AtomicBool::new(false),
+            crash_reason: StdMutex::new(None),
+            killed,
         })
     }
 
-    fn create_async_thread(&self, thread: Thread, channel_id: Option<ChannelId>) -> Result<()> {
+    fn create_async_thread(
+        &self,
+        thread: Thread,
+        channel_id: Option<ChannelId>,
+        trace: TraceId,
+    ) -> Result<Option<u64>> {
         if thread.status() == ThreadStatus::Resumable {
-            let threads: Table = self.inner.named_registry_value("__ASYNC_THREADS")?;
             let thread_channels: Table = self
                 .inner
                 .named_registry_value("__ASYNC_THREADS_CHANNELS")?;
-            let id = self.thread_id.fetch_add(1, Ordering::AcqRel);
-            threads.set(id, thread)?;
+            let id = register_thread(&self.inner, &self.async_sender, thread, Some(trace))?;
             if let Some(channel_id) = channel_id {
                 thread_channels.set(id, channel_id.to_short_str())?;
             }
+
+            return Ok(Some(id));
         }
 
-        Ok(())
+        Ok(None)
+    }
+
+    // Run with the trace set as current, so futures created inside of it carry the trace
+    fn with_trace<R>(&self, trace: Option<TraceId>, f: impl FnOnce() -> Result<R>) -> Result<R> {
+        self.inner
+            .set_named_registry_value("__CURRENT_TRACE", trace.map(|trace| trace.raw()))?;
+        let res = catch_panic(trace, f);
+        self.inner
+            .set_named_registry_value("__CURRENT_TRACE", LuaValue::Nil)?;
+
+        if let Err(err) = &res {
+            self.check_crash(err);
+        }
+
+        res
+    }
+
+    /// Where a coroutine raised its error, the stack of a dead coroutine is kept for this
+    fn thread_traceback(&self, thread: &Thread) -> Result<String> {
+        let debug: Table = self.inner.globals().get("debug")?;
+        let traceback: Function = debug.get("traceback")?;
+
+        Ok(traceback.call(thread.clone())?)
+    }
+
+    /// Remember errors that leave the state unusable, like running out of memory
+    pub fn check_crash(&self, err: &anyhow::Error) {
+        let fatal = is_memory_error(err)
+            || err.chain().any(|err| {
+                matches!(
+                    err.downcast_ref::<LuaError>(),
+                    Some(LuaError::GarbageCollectorError(_))
+                )
+            });
+
+        if fatal {
+            self.crash_reason
+                .lock()
+                .unwrap()
+                .get_or_insert_with(|| err.to_string());
+        }
+    }
+
+    pub fn crash_reason(&self) -> Option<String> {
+        self.crash_reason.lock().unwrap().clone()
     }
 
     pub fn run_message_delete(
@@ -190,46 +582,106 @@ impl LuaState {
         let bot_tbl: Table = self.inner.globals().get("bot")?;
         let on_message_delete_fn: Function = bot_tbl.get("on_message_delete")?;
 
+        let trace = TraceId::new();
         let thread = self.inner.create_thread(on_message_delete_fn)?;
-        thread.resume(LuaMultiValue::from_vec(vec![
-            if let Some(server_id) = server_id {
-                LuaValue::String(self.inner.create_string(&server_id.to_short_str())?)
-            } else {
-                LuaValue::Nil
-            },
-            LuaValue::String(self.inner.create_string(&channel_id.to_short_str())?),
-            LuaValue::String(self.inner.create_string(&message_id.to_short_str())?),
-        ]))?;
+        self.with_trace(Some(trace), || {
+            Ok(thread.resume(LuaMultiValue::from_vec(vec![
+                if let Some(server_id) = server_id {
+                    LuaValue::String(self.inner.create_string(&server_id.to_short_str())?)
+                } else {
+                    LuaValue::Nil
+                },
+                LuaValue::String(self.inner.create_string(&channel_id.to_short_str())?),
+                LuaValue::String(self.inner.create_string(&message_id.to_short_str())?),
+            ]))?)
+        })?;
 
-        self.create_async_thread(thread, None)?;
+        self.create_async_thread(thread, None, trace)?;
 
         Ok(())
     }
 
-    pub fn run_bot_command(&self, msg: BotMessage, args: Vec<String>, edited: bool) -> Result<()> {
+    pub fn run_bot_command(
+        &self,
+        msg: BotMessage,
+        args: Vec<String>,
+        edited: bool,
+        caller: Caller,
+        trace: TraceId,
+    ) -> Result<()> {
         let bot_tbl: Table = self.inner.globals().get("bot")?;
         let on_command_fn: Function = bot_tbl.get("on_command")?;
 
+        // Owner only functions check the trace, so they can't be called on behalf of someone else
+        let owner_traces: Table = self.inner.named_registry_value("__OWNER_TRACES")?;
+        let owner = self.bot.permissions().is_owner(&caller.user);
+        if owner {
+            owner_traces.set(trace.raw(), true)?;
+        }
+
+        let caller = BotCaller::new(self.bot.clone(), caller, self.async_sender.clone());
+
         let thread = self.inner.create_thread(on_command_fn)?;
         let channel_id = msg.channel().id();
-        thread.resume((msg.clone(), args, edited))?;
+        let res = self.with_trace(Some(trace), || {
+            Ok(thread.resume((msg.clone(), args, edited, caller))?)
+        });
+
+        let id = if res.is_ok() {
+            self.create_async_thread(thread, Some(channel_id), trace)?
+        } else {
+            None
+        };
 
-        self.create_async_thread(thread, Some(channel_id))?;
+        // Until the command thread finishes, keyed to it so other threads on the trace don't end it early
+        if owner {
+            owner_traces.set(trace.raw(), id)?;
+        }
+
+        res?;
 
         self.run_bot_message(msg)?;
 
         Ok(())
     }
 
+    /// Look up a command by name or alias, returning its name and rate limit override
+    pub fn command_rate_limit(&self, name: &str) -> Result<Option<(String, Option<RateLimit>)>> {
+        let bot_tbl: Table = self.inner.globals().get("bot")?;
+        let cmds: Table = bot_tbl.get("cmds")?;
+        let aliases: Table = bot_tbl.get("aliases")?;
+
+        let cmd: Table = match cmds.get::<_, Option<Table>>(name)? {
+            Some(cmd) => cmd,
+            None => match aliases.get::<_, Option<Table>>(name)? {
+                Some(cmd) => cmd,
+                None => return Ok(None),
+            },
+        };
+
+        let limit = match cmd.get::<_, Option<Table>>("rate_limit")? {
+            Some(limit) => Some(RateLimit {
+                count: limit.get::<_, Option<usize>>("count")?.unwrap_or(0),
+                period: Duration::from_secs_f64(
+                    limit.get::<_, Option<f64>>("period")?.unwrap_or(1.0).max(0.0),
+                ),
+            }),
+            None => None,
+        };
+
+        Ok(Some((cmd.get("cmd")?, limit)))
+    }
+
     pub fn run_bot_message(&self, msg: BotMessage) -> Result<()> {
         let bot_tbl: Table = self.inner.globals().get("bot")?;
         let on_message_fn: Function = bot_tbl.get("on_message")?;
 
+        let trace = TraceId::new();
         let thread = self.inner.create_thread(on_message_fn)?;
         let channel_id = msg.channel().id();
-        thread.resume(msg)?;
+        self.with_trace(Some(trace), || Ok(thread.resume(msg)?))?;
 
-        self.create_async_thread(thread, Some(channel_id))?;
+        self.create_async_thread(thread, Some(channel_id), trace)?;
 
         Ok(())
     }
@@ -244,11 +696,69 @@ impl LuaState {
         let bot_tbl: Table = self.inner.globals().get("bot")?;
         let on_reaction_fn: Function = bot_tbl.get("on_reaction")?;
 
+        let trace = TraceId::new();
         let thread = self.inner.create_thread(on_reaction_fn)?;
         let channel_id = msg.channel().id();
-        thread.resume((msg, reactor, reaction, removed))?;
+        self.with_trace(Some(trace), || {
+            Ok(thread.resume((msg, reactor, reaction, removed))?)
+        })?;
+
+        self.create_async_thread(thread, Some(channel_id), trace)?;
+
+        Ok(())
+    }
+
+    pub fn run_bot_component(
+        &self,
+        msg: BotMessage,
+        user: BotUser,
+        id: String,
+        values: Vec<String>,
+        interaction: BotInteraction,
+    ) -> Result<()> {
+        let bot_tbl: Table = self.inner.globals().get("bot")?;
+        let on_component_fn: Function = bot_tbl.get("on_component")?;
+
+        let trace = TraceId::new();
+        let thread = self.inner.create_thread(on_component_fn)?;
+        let channel_id = msg.channel().id();
+        self.with_trace(Some(trace), || {
+            Ok(thread.resume((msg, user, id, values, interaction))?)
+        })?;
 
-        self.create_async_thread(thread, Some(channel_id))?;
+        self.create_async_thread(thread, Some(channel_id), trace)?;
+
+        Ok(())
+    }
+
+    pub fn run_bot_audit(&self, audit: ServiceAudit) -> Result<()> {
+        let bot_tbl: Table = self.inner.globals().get("bot")?;
+        let on_audit_fn: Function = bot_tbl.get("on_audit")?;
+
+        let event = self.inner.create_table()?;
+        event.set("service", audit.service)?;
+        event.set("server_id", audit.server_id)?;
+        event.set("action", audit.action)?;
+        event.set("actor_id", audit.actor_id)?;
+        event.set("target_id", audit.target_id)?;
+        event.set("reason", audit.reason)?;
+
+        let changes = self.inner.create_table()?;
+        for (i, (key, old, new)) in audit.changes.into_iter().enumerate() {
+            let change = self.inner.create_table()?;
+            change.set("key", key)?;
+            change.set("old", self.inner.to_value(&old)?)?;
+            change.set("new", self.inner.to_value(&new)?)?;
+
+            changes.set(i + 1, change)?;
+        }
+        event.set("changes", changes)?;
+
+        let trace = TraceId::new();
+        let thread = self.inner.create_thread(on_audit_fn)?;
+        self.with_trace(Some(trace), || Ok(thread.resume(event)?))?;
+
+        self.create_async_thread(thread, None, trace)?;
 
         Ok(())
     }
@@ -258,6 +768,10 @@ impl LuaState {
         source: &str,
         msg: BotMessage,
         env_encoded: Option<String>,
+        owner: SandboxOwner,
+        http_quota: HttpQuota,
+        tape: Option<Arc<SandboxTape>>,
+        trace: TraceId,
     ) -> Result<(Arc<SandboxStateInner>, Receiver<SandboxMsg>)> {
         let sandbox_tbl: Table = self.inner.globals().get("sandbox")?;
         let run_fn: Function = sandbox_tbl.get("run")?;
@@ -265,32 +779,52 @@ impl LuaState {
         let (sender, receiver) = unbounded();
 
         let sandbox_state = SandboxState(Arc::new(SandboxStateInner {
+            bot: self.bot.clone(),
             async_sender: self.async_sender.clone(),
             sender: sender.clone(),
             instructions_run: AtomicU64::new(0),
-            limits: SandboxLimits {
-                lines_left: AtomicU64::new(10),
-                characters_left: AtomicU64::new(2000),
-                http_calls_left: AtomicU64::new(2),
-                messages_left: AtomicU64::new(2),
-                message_edits_left: AtomicU64::new(5),
-                message_reacts_left: AtomicU64::new(10),
-                message_deletions_left: AtomicU64::new(2),
-                images_left: AtomicU64::new(4),
-                image_operations_left: AtomicU64::new(16),
-                instructions: 8388608,
-            },
-            http_rate_limiter: self.http_rate_limiter.clone(),
+            peak_memory: AtomicUsize::new(0),
+            limits: SandboxLimits::default(),
+            http_quota,
+            env_path: owner.env_path(&self.bot),
+            server_id: msg.channel().server().id(),
+            owner,
+            tape: tape.clone(),
+            trace,
+            started: Instant::now(),
         }));
 
+        // Recordings and their replays use the same seed, so math.random picks the same numbers
+        let seed = tape
+            .map(|tape| tape.random_seed)
+            .or_else(|| self.clock.random_seed());
+
+        if let Some(seed) = seed {
+            seed_random(&self.inner, seed)?;
+        }
+
         self.inner
             .set_named_registry_value("__SANDBOX_STATE", sandbox_state.clone())?;
 
-        if let Some(env_encoded) = env_encoded {
-            let env = self.inner.to_value(&env_encoded)?;
-            run_fn.call((sandbox_state.clone(), msg, source, env, true))?;
-        } else {
-            run_fn.call((sandbox_state.clone(), msg, source, LuaValue::Nil, true))?;
+        let res = catch_panic(Some(trace), || {
+            if let Some(env_encoded) = env_encoded {
+                let env = self.inner.to_value(&env_encoded)?;
+                run_fn.call((sandbox_state.clone(), msg, source, env, true))?;
+            } else {
+                run_fn.call((sandbox_state.clone(), msg, source, LuaValue::Nil, true))?;
+            }
+
+            Ok(())
+        });
+
+        match res {
+            Err(err) if is_memory_error(&err) => {
+                sandbox_state.terminate_memory_limit(&self.inner);
+
+                let reset_env_fn: Function = sandbox_tbl.get("reset_env")?;
+                reset_env_fn.call::<_, ()>(())?;
+            }
+            res => res?,
         }
 
         Ok((sandbox_state.0, receiver))
@@ -300,114 +834,407 @@ impl LuaState {
         if self.sandbox {
             let sandbox_tbl: Table = self.inner.globals().get("sandbox")?;
             let think_fn: Function = sandbox_tbl.get("think")?;
-            think_fn.call(())?;
+            catch_panic(None, || Ok(think_fn.call(())?))?;
         } else {
             let bot_tbl: Table = self.inner.globals().get("bot")?;
             let think_fn: Function = bot_tbl.get("think")?;
-            think_fn.call(())?;
+            catch_panic(None, || Ok(think_fn.call(())?))?;
+        }
 
-            let threads: Table = self.inner.named_registry_value("__ASYNC_THREADS")?;
-            let thread_channels: Table = self
-                .inner
-                .named_registry_value("__ASYNC_THREADS_CHANNELS")?;
+        self.think_threads()?;
+        self.step_gc()?;
 
-            for pair in threads.clone().pairs::<u64, Thread>() {
-                let (id, thread) = pair?;
-
-                if let Err(err) = thread.resume::<_, ()>(()) {
-                    if let Ok(channel_str) = thread_channels.get::<u64, String>(id) {
-                        let id = ChannelId::from_str(&channel_str)?;
-                        let bot = self.bot.clone();
-
-                        tokio::spawn(async move {
-                            bot.get_ctx()
-                                .services()
-                                .send_message(
-                                    id,
-                                    escape_untrusted_text(id.service_kind(), err.to_string()),
-                                    MessageSettings::default(),
-                                )
-                                .await
-                                .ok();
-                        });
-                    } else {
-                        println!("error during bot async think: {}", err.to_string());
-                    }
+        // Threads past the limit of a tick are resumed by the next think right away
+        if self.woken_threads() > 0 {
+            self.async_waker.wake();
+        }
+
+        Ok(())
+    }
+
+    /// Move the woken threads into the queue of the run they belong to
+    fn queue_woken_threads(&self) -> Result<()> {
+        let woken = self.async_sender.take_woken();
+
+        if woken.is_empty() {
+            return Ok(());
+        }
+
+        let thread_traces: Table = self.inner.named_registry_value("__ASYNC_THREADS_TRACES")?;
+        let mut guard = self.woken_threads.lock().unwrap();
+        let woken_threads = &mut *guard;
+
+        for id in woken {
+            if !woken_threads.queued.insert(id) {
+                continue;
+            }
+
+            // Sandbox runs and bot commands each get a queue, keyed by their trace
+            let key = thread_traces
+                .get::<u64, Option<u32>>(id)?
+                .map(TraceId::from_raw);
+
+            match woken_threads
+                .queues
+                .iter_mut()
+                .find(|(queue_key, _)| *queue_key == key)
+            {
+                Some((_, queue)) => queue.push_back(id),
+                None => woken_threads
+                    .queues
+                    .push_back((key, VecDeque::from(vec![id]))),
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Take a thread from the next queue in turn, so one busy run can't hold up the others
+    fn next_woken_thread(&self) -> Option<u64> {
+        let mut guard = self.woken_threads.lock().unwrap();
+        let woken_threads = &mut *guard;
+        let (key, mut queue) = woken_threads.queues.pop_front()?;
+        let id = queue.pop_front();
+
+        if !queue.is_empty() {
+            woken_threads.queues.push_back((key, queue));
+        }
+
+        if let Some(id) = id {
+            woken_threads.queued.remove(&id);
+        }
+
+        id
+    }
+
+    /// Collect a little garbage every think, so long running states don't creep up to the memory
+    /// limit and fail in the middle of a command
+    fn step_gc(&self) -> Result<()> {
+        // Live data above the soft limit would otherwise get a full collection every think
+        let collected = self.gc_collected_memory.load(Ordering::Relaxed);
+        let threshold = self
+            .gc
+            .soft_memory_limit
+            .max(collected.saturating_add(collected / FULL_COLLECT_GROWTH_DIVISOR));
+
+        if self.inner.used_memory() > threshold {
+            self.inner.gc_collect()?;
+            self.gc_collected_memory
+                .store(self.inner.used_memory(), Ordering::Relaxed);
+        } else {
+            self.inner.gc_step_kbytes(self.gc.step_kbytes)?;
+        }
+
+        Ok(())
+    }
+
+    /// Resume the threads woken by their futures, then the ones that yielded for the next tick
+    fn think_threads(&self) -> Result<()> {
+        let resumed = self.resume_woken_threads()?;
+
+        // Collected first, resuming a thread can register new ones
+        let threads: Table = self.inner.named_registry_value("__ASYNC_THREADS")?;
+        let running = threads
+            .pairs::<u64, Thread>()
+            .collect::<LuaResult<Vec<_>>>()?;
+
+        for (id, thread) in running {
+            if resumed.contains(&id)
+                || self.async_sender.is_parked(id)
+                || self.woken_threads.lock().unwrap().queued.contains(&id)
+            {
+                continue;
+            }
+
+            self.resume_thread(id, thread)?;
+        }
+
+        Ok(())
+    }
+
+    /// Resume the threads that were woken, up to the limit of a tick. Returns their ids.
+    fn resume_woken_threads(&self) -> Result<HashSet<u64>> {
+        let threads: Table = self.inner.named_registry_value("__ASYNC_THREADS")?;
+        let mut resumed = HashSet::new();
+
+        for _ in 0..ASYNC_THREADS_PER_TICK {
+            self.queue_woken_threads()?;
+
+            let id = match self.next_woken_thread() {
+                Some(id) => id,
+                None => break,
+            };
+
+            // Futures can still wake a thread that is already done
+            if let Some(thread) = threads.get::<u64, Option<Thread>>(id)? {
+                resumed.insert(id);
+                self.resume_thread(id, thread)?;
+            }
+        }
+
+        Ok(resumed)
+    }
+
+    /// Resume a thread with the waker of its id, so the futures it awaits can wake it again
+    fn resume_thread(&self, id: u64, thread: Thread) -> Result<()> {
+        let thread_traces: Table = self.inner.named_registry_value("__ASYNC_THREADS_TRACES")?;
+        let trace = thread_traces
+            .get::<u64, Option<u32>>(id)?
+            .map(TraceId::from_raw)
+            .unwrap_or_else(TraceId::new);
+
+        let waker = self.async_sender.thread_waker(id);
+        let mut async_thread = thread.clone().into_async::<_, LuaMultiValue>(());
+
+        self.inner
+            .set_named_registry_value("__CURRENT_THREAD", id)?;
+        let res = self.with_trace(Some(trace), || {
+            match async_thread.poll_next_unpin(&mut Context::from_waker(&waker)) {
+                Poll::Ready(Some(values)) => Ok(Some(values?)),
+                Poll::Ready(None) => Ok(Some(LuaMultiValue::new())),
+                Poll::Pending => Ok(None),
+            }
+        });
+        self.inner
+            .set_named_registry_value("__CURRENT_THREAD", LuaValue::Nil)?;
+
+        let res = match res {
+            Ok(None) => {
+                self.async_sender.park_thread(id, false);
+                return Ok(());
+            }
+            Ok(Some(_)) if thread.status() == ThreadStatus::Resumable => {
+                // A plain yield waits for the next tick, unless the thread awaits a lua future
+                if self.async_sender.park_requested(id) {
+                    self.async_sender.park_thread(id, true);
+                } else {
+                    self.async_sender.run_thread(id);
                 }
 
-                if thread.status() != ThreadStatus::Resumable {
-                    threads.set(id, LuaValue::Nil)?;
-                    thread_channels.set(id, LuaValue::Nil)?;
+                return Ok(());
+            }
+            Ok(Some(values)) => Ok(values),
+            Err(err) => Err(err),
+        };
+
+        self.finish_thread(id, &thread, trace, res)
+    }
+
+    /// Forget a thread that is done, and settle the future it awaited
+    fn finish_thread(
+        &self,
+        id: u64,
+        thread: &Thread,
+        trace: TraceId,
+        res: Result<LuaMultiValue>,
+    ) -> Result<()> {
+        let threads: Table = self.inner.named_registry_value("__ASYNC_THREADS")?;
+        let thread_channels: Table = self
+            .inner
+            .named_registry_value("__ASYNC_THREADS_CHANNELS")?;
+        let thread_traces: Table = self.inner.named_registry_value("__ASYNC_THREADS_TRACES")?;
+        let thread_futures: Table = self.inner.named_registry_value("__ASYNC_THREADS_FUTURES")?;
+        let thread_sandbox_states: Table = self
+            .inner
+            .named_registry_value("__ASYNC_THREADS_SANDBOX_STATES")?;
+        let thread_ids: Table = self.inner.named_registry_value("__ASYNC_THREAD_IDS")?;
+
+        let future: Option<Table> = thread_futures.get(id)?;
+        let sandbox_state: Option<SandboxState> = thread_sandbox_states.get(id)?;
+        let channel: Option<String> = thread_channels.get(id)?;
+
+        for table in [
+            &threads,
+            &thread_channels,
+            &thread_traces,
+            &thread_futures,
+            &thread_sandbox_states,
+        ]
+        .iter()
+        {
+            table.set(id, LuaValue::Nil)?;
+        }
+        thread_ids.set(thread.clone(), LuaValue::Nil)?;
+        self.async_sender.remove_thread(id);
+
+        if !self.sandbox {
+            let owner_traces: Table = self.inner.named_registry_value("__OWNER_TRACES")?;
+            let owner_thread: Option<u64> = owner_traces.get(trace.raw()).ok().flatten();
+            if owner_thread == Some(id) {
+                owner_traces.set(trace.raw(), LuaValue::Nil)?;
+            }
+        }
+
+        match (future, res) {
+            (Some(future), res) => {
+                // One broken future shouldn't hold up the other threads
+                if let Err(err) = self.settle_future(future, sandbox_state, trace, res) {
+                    println!("error settling a future [trace {}]: {}", trace, err.to_string());
                 }
+
+                Ok(())
             }
+            (None, Err(err)) => self.report_thread_error(thread, trace, channel, err),
+            (None, Ok(_)) => Ok(()),
         }
+    }
 
-        self.think_async_callbacks()?;
+    fn report_thread_error(
+        &self,
+        thread: &Thread,
+        trace: TraceId,
+        channel: Option<String>,
+        err: anyhow::Error,
+    ) -> Result<()> {
+        println!(
+            "error during bot async think [trace {}]: {}\n{}",
+            trace,
+            err.to_string(),
+            self.thread_traceback(thread).unwrap_or_default()
+        );
+
+        if let Some(channel_str) = channel {
+            let id = ChannelId::from_str(&channel_str)?;
+            let bot = self.bot.clone();
+
+            tokio::spawn(async move {
+                bot.get_ctx()
+                    .services()
+                    .send_message(
+                        id,
+                        escape_untrusted_text(
+                            id.service_kind(),
+                            format!("{}\ntrace: {}", err.to_string(), trace),
+                        ),
+                        MessageSettings {
+                            priority: MessagePriority::High,
+                            ..Default::default()
+                        },
+                    )
+                    .await
+                    .ok();
+            });
+        }
 
         Ok(())
     }
 
-    fn think_async_callbacks(&self) -> Result<()> {
-        loop {
-            // Check for async callbacks
-            match self.async_receiver.try_recv() {
-                Ok((fut_reg_key, sandbox_state, cb)) => {
-                    let (succ, value) = match cb(&self.inner) {
-                        Ok(vals) => (true, vals),
-                        Err(err) => (
-                            false,
-                            LuaMultiValue::from_vec(vec![LuaValue::String(
-                                self.inner.create_string(&err.to_string())?,
-                            )]),
-                        ),
-                    };
-                    let future: Table = self.inner.registry_value(&fut_reg_key)?;
-                    let resolve_fn: Function = if succ {
-                        future.get("__handle_resolve")?
-                    } else {
-                        future.get("__handle_reject")?
-                    };
+    /// Resolve or reject a future with what the thread awaiting its task returned
+    fn settle_future(
+        &self,
+        future: Table,
+        sandbox_state: Option<SandboxState>,
+        trace: TraceId,
+        res: Result<LuaMultiValue>,
+    ) -> Result<()> {
+        self.bot
+            .metrics()
+            .count_async_callback(if self.sandbox { "sandbox" } else { "bot" });
+
+        self.with_trace(Some(trace), || {
+            // The thread returns whether the task succeeded, followed by its results
+            let mut value = match res {
+                Ok(values) => values.into_vec(),
+                Err(err) => vec![
+                    LuaValue::Boolean(false),
+                    create_error_value(&self.inner, &err)?,
+                ],
+            };
+            let succ = matches!(value.first(), Some(LuaValue::Boolean(true)));
+            if !value.is_empty() {
+                value.remove(0);
+            }
 
-                    // Sandbox when resolving the future
-                    if self.sandbox {
-                        if let Some(sandbox_state) = sandbox_state {
-                            let sandbox_tbl: Table = self.inner.globals().get("sandbox")?;
-                            let run_fn: Function = sandbox_tbl.get("async_callback")?;
-
-                            let args = LuaMultiValue::from_vec(
-                                [
-                                    vec![
-                                        sandbox_state.to_lua(&self.inner)?,
-                                        LuaValue::Table(future.clone()),
-                                        LuaValue::Boolean(succ),
-                                    ],
-                                    value.into_vec(),
-                                ]
-                                .concat(),
-                            );
-
-                            run_fn.call::<_, ()>(args)?;
-                        }
-                    } else {
-                        let args = LuaMultiValue::from_vec(
-                            [
-                                vec![LuaValue::Table(future.clone()), LuaValue::Boolean(true)],
-                                value.into_vec(),
-                            ]
-                            .concat(),
-                        );
-
-                        resolve_fn.call::<_, ()>(args)?;
-                    }
+            let resolve_fn: Function = if succ {
+                future.get("__handle_resolve")?
+            } else {
+                future.get("__handle_reject")?
+            };
 
-                    // Clean up the async registry values
-                    self.inner.remove_registry_value(fut_reg_key)?;
+            // Sandbox when resolving the future
+            if self.sandbox {
+                if let Some(sandbox_state) = sandbox_state {
+                    let sandbox_tbl: Table = self.inner.globals().get("sandbox")?;
+                    let run_fn: Function = sandbox_tbl.get("async_callback")?;
+
+                    let args = LuaMultiValue::from_vec(
+                        [
+                            vec![
+                                sandbox_state.to_lua(&self.inner)?,
+                                LuaValue::Table(future),
+                                LuaValue::Boolean(succ),
+                            ],
+                            value,
+                        ]
+                        .concat(),
+                    );
+
+                    run_fn.call::<_, ()>(args)?;
                 }
-                _ => break,
+            } else {
+                let args = LuaMultiValue::from_vec(
+                    [
+                        vec![LuaValue::Table(future), LuaValue::Boolean(true)],
+                        value,
+                    ]
+                    .concat(),
+                );
+
+                resolve_fn.call::<_, ()>(args)?;
+            }
+
+            Ok(())
+        })
+    }
+
+    /// Resume the threads that were already woken, before the state is replaced. Returns how many
+    /// futures are still in flight, their threads are dropped along with the state
+    pub fn drain_async_threads(&self) -> usize {
+        // Settling a future wakes the threads awaiting it, which can settle more futures
+        for _ in 0..DRAIN_ROUNDS {
+            if self.woken_threads() == 0 {
+                break;
+            }
+
+            if let Err(err) = self.resume_woken_threads() {
+                println!("error draining async threads: {}", err.to_string());
             }
         }
 
-        Ok(())
+        self.future_limiter.pending()
+    }
+
+    /// Reject the futures still in flight, so whatever waits on them sees an error instead of
+    /// never resuming once the state is replaced. Returns how many were rejected.
+    pub fn reject_in_flight_futures(&self) -> Result<usize> {
+        let thread_futures: Table = self.inner.named_registry_value("__ASYNC_THREADS_FUTURES")?;
+        let thread_traces: Table = self.inner.named_registry_value("__ASYNC_THREADS_TRACES")?;
+        self.inner
+            .set_named_registry_value("__ASYNC_THREADS_FUTURES", self.inner.create_table()?)?;
+
+        let mut rejected = 0;
+
+        for pair in thread_futures.pairs::<u64, Table>() {
+            let (id, future) = pair?;
+            let trace = thread_traces
+                .get::<u64, Option<u32>>(id)?
+                .map(TraceId::from_raw);
+
+            let res = self.with_trace(trace, || {
+                let reject_fn: Function = future.get("__handle_reject")?;
+                let err = create_error_value(&self.inner, &AsyncError::Reloaded.into())?;
+
+                reject_fn.call::<_, ()>((future.clone(), true, err))?;
+                Ok(())
+            });
+
+            match res {
+                Ok(()) => rejected += 1,
+                Err(err) => println!("error rejecting a future: {}", err.to_string()),
+            }
+        }
+
+        Ok(rejected)
     }
 
     pub fn on_loaded(&self) -> Result<()> {
@@ -415,10 +1242,11 @@ impl LuaState {
             let bot_tbl: Table = self.inner.globals().get("bot")?;
             let on_loaded_fn: Function = bot_tbl.get("on_loaded")?;
 
+            let trace = TraceId::new();
             let thread = self.inner.create_thread(on_loaded_fn)?;
-            thread.resume(())?;
+            self.with_trace(Some(trace), || Ok(thread.resume(())?))?;
 
-            self.create_async_thread(thread, None)?;
+            self.create_async_thread(thread, None, trace)?;
         }
 
         Ok(())
@@ -427,7 +1255,7 @@ impl LuaState {
     pub fn shutdown(&self) -> Result<bool> {
         if !self.sandbox {
             if self.shutting_down.swap(true, Ordering::Relaxed) {
-                self.think_async_callbacks()?;
+                self.resume_woken_threads()?;
 
                 let thread: Thread = self.inner.named_registry_value("__ASYNC_SHUTDOWN_THREAD")?;
 
@@ -435,12 +1263,12 @@ impl LuaState {
                     return Ok(false);
                 }
 
-                thread.resume(())?;
+                catch_panic(None, || Ok(thread.resume(())?))?;
             } else {
                 let bot_tbl: Table = self.inner.globals().get("bot")?;
                 let shutdown_fn: Function = bot_tbl.get("shutdown")?;
                 let thread = self.inner.create_thread(shutdown_fn)?;
-                thread.resume(())?;
+                catch_panic(None, || Ok(thread.resume(())?))?;
                 self.inner
                     .set_named_registry_value("__ASYNC_SHUTDOWN_THREAD", thread)?;
             }
@@ -451,9 +1279,184 @@ impl LuaState {
         }
     }
 
-    pub fn async_sender(&self) -> Sender<LuaAsyncCallback> {
+    pub fn async_sender(&self) -> AsyncSender {
         self.async_sender.clone()
     }
+
+    /// Flag stopping sandboxed code in the state, it can be set from any thread
+    pub fn kill_handle(&self) -> Arc<AtomicBool> {
+        self.killed.clone()
+    }
+
+    /// Whether a sandbox state has no coroutines, futures or woken threads left to run
+    pub fn sandbox_idle(&self) -> Result<bool> {
+        let sandbox_tbl: Table = self.inner.globals().get("sandbox")?;
+        let tasks: Table = sandbox_tbl.get("tasks")?;
+
+        Ok(tasks.pairs::<LuaValue, LuaValue>().next().is_none()
+            && self.future_limiter.pending() == 0
+            && self.woken_threads() == 0)
+    }
+
+    /// Woken once a thread awaiting a future can be resumed by think
+    pub fn async_waker(&self) -> AsyncWaker {
+        self.async_waker.clone()
+    }
+
+    /// Threads woken by their futures, waiting for the next think
+    pub fn woken_threads(&self) -> usize {
+        self.async_sender.woken_len() + self.woken_threads.lock().unwrap().queued.len()
+    }
+
+    /// Gauges to catch leaking registry values and futures early
+    pub fn vm_stats(&self) -> Result<LuaVmStats> {
+        let registry_size = self
+            .inner
+            .load("local n = 0 for _ in pairs(debug.getregistry()) do n = n + 1 end return n")
+            .eval::<usize>()?;
+
+        Ok(LuaVmStats {
+            used_memory: self.inner.used_memory(),
+            registry_size,
+            pending_futures: self.future_limiter.pending(),
+            pending_callbacks: self.woken_threads(),
+        })
+    }
+
+    /// Call a global function by its dotted path, returning a handle that resolves with its results.
+    /// If the function returns a future, the handle resolves once that future does.
+    pub fn call_async(&self, path: &str, args: &[JsonValue]) -> Result<LuaFutureHandle> {
+        let mut func = LuaValue::Table(self.inner.globals());
+
+        for part in path.split('.') {
+            func = match func {
+                LuaValue::Table(tbl) => tbl.get(part)?,
+                _ => LuaValue::Nil,
+            };
+        }
+
+        let func = match func {
+            LuaValue::Function(func) => func,
+            _ => return Err(LuaFutureError::UnknownFunction(path.into()).into()),
+        };
+
+        let args = args
+            .iter()
+            .map(|arg| self.inner.to_value(arg))
+            .collect::<LuaResult<Vec<_>>>()?;
+
+        let (sender, receiver) = oneshot::channel();
+        let sender = Arc::new(StdMutex::new(Some(sender)));
+
+        let results = catch_panic(None, || {
+            Ok(func.call::<_, LuaMultiValue>(LuaMultiValue::from_vec(args))?)
+        })?
+        .into_vec();
+
+        let future = match results.first() {
+            Some(LuaValue::Table(tbl)) => match tbl.get::<_, Option<Function>>("__type")? {
+                Some(type_fn) if type_fn.call::<_, String>(tbl.clone())? == "future" => {
+                    Some(tbl.clone())
+                }
+                _ => None,
+            },
+            _ => None,
+        };
+
+        match future {
+            Some(future) => {
+                let resolve_sender = sender.clone();
+                let resolve_fn = self.inner.create_function(move |state, values: LuaMultiValue| {
+                    if let Some(sender) = resolve_sender.lock().unwrap().take() {
+                        sender.send(values_to_json(state, values.into_vec())).ok();
+                    }
+
+                    Ok(())
+                })?;
+
+                let reject_fn = self.inner.create_function(move |_state, err: LuaValue| {
+                    if let Some(sender) = sender.lock().unwrap().take() {
+                        let message = match &err {
+                            LuaValue::Table(tbl) => tbl.get::<_, Option<String>>("message")?,
+                            LuaValue::String(s) => Some(s.to_str()?.to_string()),
+                            LuaValue::Error(err) => Some(err.to_string()),
+                            _ => None,
+                        };
+
+                        sender
+                            .send(Err(LuaFutureError::Rejected {
+                                kind: value_error_kind(&err)?,
+                                message: message.unwrap_or_else(|| "unknown error".into()),
+                            }
+                            .into()))
+                            .ok();
+                    }
+
+                    Ok(())
+                })?;
+
+                let thence_fn: Function = future.get("thence")?;
+                thence_fn.call::<_, ()>((future.clone(), resolve_fn))?;
+                let catch_fn: Function = future.get("catch")?;
+                catch_fn.call::<_, ()>((future, reject_fn))?;
+            }
+            None => {
+                if let Some(sender) = sender.lock().unwrap().take() {
+                    sender.send(values_to_json(&self.inner, results)).ok();
+                }
+            }
+        }
+
+        Ok(LuaFutureHandle(receiver))
+    }
+}
+
+fn values_to_json(state: &Lua, values: Vec<LuaValue>) -> Result<Vec<JsonValue>> {
+    values
+        .into_iter()
+        .map(|value| Ok(state.from_value(value)?))
+        .collect()
+}
+
+/// Resolves with the results of a Lua future once the bot state has resolved it
+pub struct LuaFutureHandle(oneshot::Receiver<Result<Vec<JsonValue>>>);
+
+impl Future for LuaFutureHandle {
+    type Output = Result<Vec<JsonValue>>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        match Pin::new(&mut self.0).poll(cx) {
+            Poll::Ready(Ok(res)) => Poll::Ready(res),
+            Poll::Ready(Err(_)) => Poll::Ready(Err(LuaFutureError::Dropped.into())),
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum LuaFutureError {
+    #[error("unknown lua function \"{}\"", _0)]
+    UnknownFunction(String),
+    #[error("{}", message)]
+    Rejected { kind: String, message: String },
+    #[error("the lua future was dropped before resolving")]
+    Dropped,
+}
+
+#[derive(Clone, Default)]
+pub struct LuaVmStats {
+    pub used_memory: usize,
+    pub registry_size: usize,
+    /// Futures whose task hasn't been awaited by their thread yet
+    pub pending_futures: usize,
+    /// Threads woken by their futures, waiting for the next think
+    pub pending_callbacks: usize,
+}
+
+#[derive(Debug, Error)]
+pub enum LuaStateError {
+    #[error("internal error, the call panicked: {}", _0)]
+    Panicked(String),
 }
 
 pub enum SandboxMsg {
@@ -466,6 +1469,19 @@ pub enum SandboxTerminationReason {
     Done,
     ExecutionQuota,
     TimeLimit,
+    /// Peak memory use of the state in bytes
+    MemoryLimit(usize),
+}
+
+impl SandboxTerminationReason {
+    pub fn name(&self) -> &'static str {
+        match self {
+            SandboxTerminationReason::Done => "done",
+            SandboxTerminationReason::ExecutionQuota => "execution_quota",
+            SandboxTerminationReason::TimeLimit => "time_limit",
+            SandboxTerminationReason::MemoryLimit(_) => "memory_limit",
+        }
+    }
 }
 
 #[derive(Clone)]
@@ -475,14 +1491,94 @@ impl SandboxState {
     pub fn limits(&self) -> &SandboxLimits {
         &self.0.limits
     }
+
+    /// Futures still pending past the time limit of the run are rejected instead of awaited
+    pub fn deadline(&self) -> Instant {
+        self.0.started + Duration::from_secs_f64(SANDBOX_TIME_LIMIT)
+    }
+
+    fn terminate_memory_limit(&self, state: &Lua) {
+        let used = state.used_memory();
+        let peak = self
+            .0
+            .peak_memory
+            .fetch_max(used, Ordering::Relaxed)
+            .max(used);
+
+        self.0
+            .sender
+            .send(SandboxMsg::Terminated(
+                SandboxTerminationReason::MemoryLimit(peak),
+            ))
+            .ok();
+    }
 }
 
 pub struct SandboxStateInner {
-    pub async_sender: Sender<LuaAsyncCallback>,
+    pub bot: Arc<Bot>,
+    pub async_sender: AsyncSender,
     pub sender: Sender<SandboxMsg>,
     pub instructions_run: AtomicU64,
+    /// Highest memory use of the state seen while the run was executing
+    pub peak_memory: AtomicUsize,
     pub limits: SandboxLimits,
-    pub http_rate_limiter: Arc<RateLimiter<NotKeyed, InMemoryState, QuantaClock>>,
+    /// Quotas of the author and channel of the message the run is for
+    pub http_quota: HttpQuota,
+    pub env_path: PathBuf,
+    /// Server the run was started in, messaging channels are scoped to it
+    pub server_id: ServerId,
+    pub owner: SandboxOwner,
+    /// Set when the run is recorded or replayed
+    pub tape: Option<Arc<SandboxTape>>,
+    pub trace: TraceId,
+    /// Coroutines of the run count their time limit from here, including the time spent awaiting
+    pub started: Instant,
+}
+
+impl SandboxStateInner {
+    /// The http calls the run can still make, counting the quotas shared with other runs
+    pub fn http_calls_remaining(&self) -> u64 {
+        let calls_left = self.limits.http_calls_left.load(Ordering::Relaxed);
+        let quota_left = self
+            .http_quota
+            .remaining(self.bot.get_ctx().modules().lua.module().throttle());
+
+        match quota_left {
+            Some(quota_left) => calls_left.min(quota_left as u64),
+            None => calls_left,
+        }
+    }
+}
+
+impl Drop for SandboxStateInner {
+    fn drop(&mut self) {
+        // The last reference goes away once nothing of the run is left in the state
+        self.bot
+            .metrics()
+            .count_sandbox_run(self.instructions_run.load(Ordering::Relaxed));
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum SandboxError {
+    #[error("sandbox {} limit reached", _0)]
+    LimitReached(&'static str),
+    #[error("the sandbox quota of the owner has been exceeded, try again later")]
+    OwnerQuotaExceeded,
+    #[error("Execution quota exceeded, terminated execution")]
+    ExecutionQuota,
+    #[error("Execution time limit reached, terminated execution")]
+    TimeLimit,
+    #[error("Memory limit reached (peak usage {:.1} MiB), terminated execution", *_0 as f64 / (1024.0 * 1024.0))]
+    MemoryLimit(usize),
+    #[error("running lua code has been disabled here")]
+    Disabled,
+    #[error("the saved environment can't be larger than {} bytes", _0)]
+    EnvTooLarge(usize),
+    #[error("output can't be delayed for longer than {} seconds", _0)]
+    DelayTooLong(u64),
+    #[error("{}", _0)]
+    Runtime(String),
 }
 
 pub struct SandboxLimits {
@@ -495,9 +1591,50 @@ pub struct SandboxLimits {
     pub message_deletions_left: AtomicU64,
     pub images_left: AtomicU64,
     pub image_operations_left: AtomicU64,
+    pub latex_renders_left: AtomicU64,
+    pub env_saves_left: AtomicU64,
+    pub attachment_downloads_left: AtomicU64,
+    pub delayed_outputs_left: AtomicU64,
+    pub ipc_publishes_left: AtomicU64,
+    pub storage_writes_left: AtomicU64,
+    pub scheduled_jobs_left: AtomicU64,
+    pub attachment_uploads_left: AtomicU64,
+    pub attachment_bytes_left: AtomicU64,
+    /// Bytes of http response bodies the run can still read
+    pub http_bytes_left: AtomicU64,
+    /// Bytes the keys and values of a storage namespace can take up after a write from the run
+    pub storage_quota: u64,
     pub instructions: u64,
 }
 
+impl Default for SandboxLimits {
+    fn default() -> Self {
+        SandboxLimits {
+            lines_left: AtomicU64::new(10),
+            characters_left: AtomicU64::new(2000),
+            http_calls_left: AtomicU64::new(2),
+            messages_left: AtomicU64::new(2),
+            message_edits_left: AtomicU64::new(5),
+            message_reacts_left: AtomicU64::new(10),
+            message_deletions_left: AtomicU64::new(2),
+            images_left: AtomicU64::new(4),
+            image_operations_left: AtomicU64::new(16),
+            latex_renders_left: AtomicU64::new(1),
+            env_saves_left: AtomicU64::new(1),
+            attachment_downloads_left: AtomicU64::new(2),
+            delayed_outputs_left: AtomicU64::new(1),
+            ipc_publishes_left: AtomicU64::new(4),
+            storage_writes_left: AtomicU64::new(8),
+            scheduled_jobs_left: AtomicU64::new(2),
+            attachment_uploads_left: AtomicU64::new(4),
+            attachment_bytes_left: AtomicU64::new(8 * 1024 * 1024),
+            http_bytes_left: AtomicU64::new(8 * 1024 * 1024),
+            storage_quota: 256 * 1024,
+            instructions: 8388608,
+        }
+    }
+}
+
 impl SandboxLimits {
     atomic_get_set! {lines_left, u64}
     atomic_get_set! {characters_left, u64}
@@ -507,6 +1644,44 @@ impl SandboxLimits {
     atomic_limit! {message_deletions_left}
     atomic_limit! {images_left}
     atomic_limit! {image_operations_left}
+    atomic_limit! {latex_renders_left}
+    atomic_limit! {env_saves_left}
+    atomic_limit! {attachment_downloads_left}
+    atomic_limit! {delayed_outputs_left}
+    atomic_limit! {ipc_publishes_left}
+    atomic_limit! {storage_writes_left}
+    atomic_limit! {scheduled_jobs_left}
+    atomic_limit! {attachment_uploads_left}
+
+    /// Counts an uploaded attachment of the size, true if the run is over either limit
+    pub fn attachment_upload_limit(&self, size: u64) -> bool {
+        if self.attachment_uploads_left_limit() {
+            return true;
+        }
+
+        let left = self.attachment_bytes_left.load(Ordering::Relaxed);
+
+        if left >= size {
+            self.attachment_bytes_left
+                .store(left - size, Ordering::Relaxed);
+            false
+        } else {
+            true
+        }
+    }
+
+    /// Counts bytes of a response body read by the run, true if it's over the limit
+    pub fn http_bytes_limit(&self, size: u64) -> bool {
+        let left = self.http_bytes_left.load(Ordering::Relaxed);
+
+        if left >= size {
+            self.http_bytes_left.store(left - size, Ordering::Relaxed);
+            false
+        } else {
+            self.http_bytes_left.store(0, Ordering::Relaxed);
+            true
+        }
+    }
 }
 
 impl UserData for SandboxState {
@@ -516,36 +1691,234 @@ impl UserData for SandboxState {
             Ok(())
         });
 
-        methods.add_method("error", |_, this, value: String| {
+        // Returns true if the run ran out of memory, sandbox.lua then drops the env it left behind
+        methods.add_method("error", |state, this, value: String| {
+            if is_memory_error_message(&value) {
+                this.terminate_memory_limit(state);
+                return Ok(true);
+            }
+
             this.0.sender.send(SandboxMsg::Error(value)).ok(); // Ignore the error for now
-            Ok(())
+            Ok(false)
         });
 
-        methods.add_method("set_instructions_run", |_, this, value: u64| {
-            this.0.instructions_run.store(value, Ordering::Relaxed);
+        methods.add_method("set_state", |state, this, _: ()| {
+            state.set_named_registry_value("__SANDBOX_STATE", this.clone())?;
             Ok(())
         });
 
-        methods.add_method("get_instructions_run", |_, this, _: ()| {
-            Ok(this.0.instructions_run.load(Ordering::Relaxed))
+        methods.add_method(
+            "http_fetch",
+            |state, this, (url, options): (String, Table)| {
+                http::http_fetch(state, this, &url, options)
+            },
+        );
+
+        methods.add_method("http_quota_remaining", |_, this, _: ()| {
+            Ok(this.0.http_calls_remaining())
         });
 
-        methods.add_method("get_instruction_limit", |_, this, _: ()| {
-            Ok(this.0.limits.instructions)
+        methods.add_method("save_env", |state, this, data: String| {
+            if data.len() > MAX_SAVED_ENV_SIZE {
+                return Err(LuaError::ExternalError(Arc::new(
+                    SandboxError::EnvTooLarge(MAX_SAVED_ENV_SIZE),
+                )));
+            }
+
+            if this.limits().env_saves_left_limit() {
+                return Err(LuaError::ExternalError(Arc::new(
+                    SandboxError::LimitReached("environment save"),
+                )));
+            }
+
+            let path = this.0.env_path.clone();
+            let replay = this.0.tape.as_ref().map_or(false, |tape| tape.is_replay());
+
+            let fut = create_lua_future!(
+                state,
+                this.0.async_sender,
+                (),
+                async move {
+                    if replay {
+                        return Ok(());
+                    }
+
+                    if let Some(dir) = path.parent() {
+                        tokio::fs::create_dir_all(dir).await?;
+                    }
+
+                    tokio::fs::write(&path, data).await?;
+
+                    Ok(())
+                },
+                |_state, _data: (), res: Result<()>| { Ok(res?) }
+            );
+
+            Ok(fut)
         });
 
-        methods.add_method("set_state", |state, this, _: ()| {
-            state.set_named_registry_value("__SANDBOX_STATE", this.clone())?;
+        methods.add_method("load_env", |state, this, _: ()| {
+            let path = this.0.env_path.clone();
+            let tape = this.0.tape.clone();
+
+            let fut = create_lua_future!(
+                state,
+                this.0.async_sender,
+                (),
+                async move {
+                    if let Some(tape) = tape.as_ref().filter(|tape| tape.is_replay()) {
+                        return match tape.next_event() {
+                            Some(RecordedEvent::LoadEnv { data }) => Ok(data),
+                            _ => Err(ReplayError::Diverged("environment load").into()),
+                        };
+                    }
+
+                    let data = match tokio::fs::read_to_string(&path).await {
+                        Ok(data) => Some(data),
+                        Err(err) if err.kind() == std::io::ErrorKind::NotFound => None,
+                        Err(err) => return Err(err.into()),
+                    };
+
+                    if let Some(tape) = tape {
+                        tape.record_event(RecordedEvent::LoadEnv { data: data.clone() });
+                    }
+
+                    Ok(data)
+                },
+                |_state, _data: (), res: Result<Option<String>>| { Ok(res?) }
+            );
+
+            Ok(fut)
+        });
+
+        methods.add_method("later", |_, this, (seconds, text): (f64, String)| {
+            if !(seconds >= 0.0) || seconds > MAX_LATER_DELAY as f64 {
+                return Err(LuaError::ExternalError(Arc::new(
+                    SandboxError::DelayTooLong(MAX_LATER_DELAY),
+                )));
+            }
+
+            if this.limits().delayed_outputs_left_limit() {
+                return Err(LuaError::ExternalError(Arc::new(
+                    SandboxError::LimitReached("delayed output"),
+                )));
+            }
+
+            // Sent through the same channel as print, so it's counted against the output limits of the run
+            let sender = this.0.sender.clone();
+
+            tokio::spawn(async move {
+                tokio::time::sleep(Duration::from_secs_f64(seconds)).await;
+                sender.send(SandboxMsg::Out(text)).ok();
+            });
+
             Ok(())
         });
 
         methods.add_method(
-            "http_fetch",
-            |state, this, (url, options): (String, Table)| {
-                http::http_fetch(state, this, &url, options)
+            "ipc_publish",
+            |_, this, (channel, data): (String, String)| {
+                if this.limits().ipc_publishes_left_limit() {
+                    return Err(LuaError::ExternalError(Arc::new(
+                        SandboxError::LimitReached("ipc publish"),
+                    )));
+                }
+
+                if this.0.tape.as_ref().map_or(false, |tape| tape.is_replay()) {
+                    return Ok(());
+                }
+
+                let bot = &this.0.bot;
+
+                bot.get_ctx()
+                    .modules()
+                    .lua
+                    .module()
+                    .ipc()
+                    .publish(bot, this.0.server_id, &this.0.owner, &channel, data)
+                    .map_err(|err| LuaError::ExternalError(Arc::new(err)))
             },
         );
 
+        methods.add_method("ipc_subscribe", |_, this, channel: String| {
+            if this.0.tape.as_ref().map_or(false, |tape| tape.is_replay()) {
+                return Ok(());
+            }
+
+            this.0
+                .bot
+                .get_ctx()
+                .modules()
+                .lua
+                .module()
+                .ipc()
+                .subscribe(this.0.server_id, &this.0.owner, &channel)
+                .map_err(|err| LuaError::ExternalError(Arc::new(err)))
+        });
+
+        methods.add_method("ipc_unsubscribe", |_, this, channel: String| {
+            if this.0.tape.as_ref().map_or(false, |tape| tape.is_replay()) {
+                return Ok(());
+            }
+
+            this.0
+                .bot
+                .get_ctx()
+                .modules()
+                .lua
+                .module()
+                .ipc()
+                .unsubscribe(this.0.server_id, &this.0.owner, &channel);
+
+            Ok(())
+        });
+
+        methods.add_method("ipc_receive", |state, this, (): ()| {
+            let messages = match &this.0.tape {
+                Some(tape) if tape.is_replay() => match tape.next_event() {
+                    Some(RecordedEvent::IpcReceive { messages }) => messages,
+                    _ => {
+                        return Err(LuaError::ExternalError(Arc::new(ReplayError::Diverged(
+                            "ipc receive",
+                        ))))
+                    }
+                },
+                tape => {
+                    let messages = this
+                        .0
+                        .bot
+                        .get_ctx()
+                        .modules()
+                        .lua
+                        .module()
+                        .ipc()
+                        .receive(this.0.server_id, &this.0.owner);
+
+                    if let Some(tape) = tape {
+                        tape.record_event(RecordedEvent::IpcReceive {
+                            messages: messages.clone(),
+                        });
+                    }
+
+                    messages
+                }
+            };
+
+            let tbl = state.create_table()?;
+
+            for (i, msg) in messages.into_iter().enumerate() {
+                let msg_tbl = state.create_table()?;
+                msg_tbl.set("channel", msg.channel)?;
+                msg_tbl.set("source", msg.source)?;
+                msg_tbl.set("data", msg.data)?;
+                msg_tbl.set("timestamp", msg.timestamp)?;
+
+                tbl.set(i + 1, msg_tbl)?;
+            }
+
+            Ok(tbl)
+        });
+
         methods.add_method("terminate", |_, this, value: String| {
             let reason = match value.as_ref() {
                 "done" => SandboxTerminationReason::Done,
@@ -565,3 +1938,39 @@ impl UserData for SandboxState {
         });
     }
 }
+
+/// Threads woken since they were last resumed, queued by trace so one busy run can't hold up the
+/// others
+#[derive(Default)]
+struct WokenThreads {
+    queues: VecDeque<(Option<TraceId>, VecDeque<u64>)>,
+    queued: HashSet<u64>,
+}
+
+struct GcSettings {
+    /// Zero leaves the default of Lua in place
+    pause: i32,
+    step_multiplier: i32,
+    step_kbytes: i32,
+    soft_memory_limit: usize,
+}
+
+impl GcSettings {
+    fn from_config(bot: &Bot) -> GcSettings {
+        let config = bot.config().lua.as_ref().and_then(|lua| lua.gc.as_ref());
+
+        GcSettings {
+            pause: config.and_then(|gc| gc.pause).unwrap_or(0).max(0),
+            step_multiplier: config.and_then(|gc| gc.step_multiplier).unwrap_or(0).max(0),
+            step_kbytes: config
+                .and_then(|gc| gc.step_kbytes)
+                .unwrap_or(DEFAULT_GC_STEP_KBYTES)
+                .max(0),
+            soft_memory_limit: config
+                .and_then(|gc| gc.soft_memory_limit)
+                .unwrap_or(DEFAULT_SOFT_MEMORY_LIMIT)
+                .saturating_mul(1024 * 1024)
+                .min(MEMORY_LIMIT),
+        }
+    }
+}
@@ -0,0 +1,133 @@
+use governor::{clock::Clock, state::keyed::DefaultKeyedStateStore, Quota, RateLimiter};
+use lru::LruCache;
+use std::{
+    collections::HashMap,
+    num::NonZeroU32,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+use thiserror::Error;
+
+use super::clock::LuaClock;
+
+// Every distinct quota gets its own limiter, callers are expected to use a handful of them
+const MAX_QUOTAS: usize = 64;
+const MAX_TRACKED_KEYS: usize = 4096;
+
+type KeyedLimiter = RateLimiter<String, DefaultKeyedStateStore<String>, LuaClock>;
+
+#[derive(Clone, Copy, Debug, Hash, Eq, PartialEq)]
+pub struct ThrottleQuota {
+    /// Actions allowed back to back, they then come back one at a time over the period
+    pub count: u32,
+    pub period: Duration,
+}
+
+impl ThrottleQuota {
+    fn to_governor(self) -> Result<Quota, ThrottleError> {
+        let count = NonZeroU32::new(self.count).ok_or(ThrottleError::InvalidQuota)?;
+
+        Quota::with_period(self.period / self.count)
+            .map(|quota| quota.allow_burst(count))
+            .ok_or(ThrottleError::InvalidQuota)
+    }
+
+    fn interval(self) -> Duration {
+        self.period / self.count.max(1)
+    }
+}
+
+/// Keyed rate limiters for arbitrary actions, so features can enforce their own quotas,
+/// keys should be namespaced by the feature like "feeds:<server id>"
+pub struct Throttle {
+    limiters: Mutex<HashMap<ThrottleQuota, KeyedLimiter>>,
+    /// When each key has its whole quota back, mirroring the limiters since they can't be
+    /// queried without counting an action
+    usage: Mutex<LruCache<(ThrottleQuota, String), Instant>>,
+    clock: LuaClock,
+}
+
+impl Throttle {
+    pub fn new(clock: &LuaClock) -> Throttle {
+        Throttle {
+            limiters: Mutex::new(HashMap::new()),
+            usage: Mutex::new(LruCache::new(MAX_TRACKED_KEYS)),
+            clock: clock.clone(),
+        }
+    }
+
+    /// Count an action under the key, returns how long to wait if the quota is used up
+    pub fn check(
+        &self,
+        key: &str,
+        quota: ThrottleQuota,
+    ) -> Result<Option<Duration>, ThrottleError> {
+        let mut limiters = self.limiters.lock().unwrap();
+
+        if !limiters.contains_key(&quota) {
+            if limiters.len() >= MAX_QUOTAS {
+                return Err(ThrottleError::TooManyQuotas(MAX_QUOTAS));
+            }
+
+            let limiter = RateLimiter::new(
+                quota.to_governor()?,
+                DefaultKeyedStateStore::default(),
+                &self.clock,
+            );
+            limiters.insert(quota, limiter);
+        }
+
+        let limiter = &limiters[&quota];
+        let res = limiter.check_key(&key.to_string());
+
+        if limiter.len() > MAX_TRACKED_KEYS {
+            limiter.retain_recent();
+        }
+
+        if res.is_ok() {
+            let now = self.clock.instant();
+            let mut usage = self.usage.lock().unwrap();
+            let usage_key = (quota, key.to_string());
+            let full_at = usage
+                .get(&usage_key)
+                .copied()
+                .filter(|full_at| *full_at > now)
+                .unwrap_or(now);
+
+            usage.put(usage_key, full_at + quota.interval());
+        }
+
+        Ok(res
+            .err()
+            .map(|not_until| not_until.wait_time_from(self.clock.now())))
+    }
+
+    /// Actions the key can take right now before it runs into the quota, without counting one
+    pub fn remaining(&self, key: &str, quota: ThrottleQuota) -> u32 {
+        let now = self.clock.instant();
+        let full_at = self
+            .usage
+            .lock()
+            .unwrap()
+            .get(&(quota, key.to_string()))
+            .copied();
+
+        match full_at {
+            Some(full_at) if full_at > now => {
+                let interval = quota.interval().as_nanos().max(1);
+                let used = ((full_at - now).as_nanos() + interval - 1) / interval;
+
+                quota.count.saturating_sub(used as u32)
+            }
+            _ => quota.count,
+        }
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum ThrottleError {
+    #[error("quotas need a count and period above zero")]
+    InvalidQuota,
+    #[error("at most {} different quotas can be used", _0)]
+    TooManyQuotas(usize),
+}
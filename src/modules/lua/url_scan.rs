@@ -0,0 +1,229 @@
+use anyhow::Result;
+use hyper::{Body, Client, Request};
+use hyper_tls::HttpsConnector;
+use lru::LruCache;
+use serde_json::{json, Value as JsonValue};
+use std::{
+    collections::HashSet,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+use thiserror::Error;
+
+use crate::{
+    config::ConfigUrlScan,
+    services::{ContentSegment, ServiceKind},
+};
+
+const SAFE_BROWSING_URL: &str = "https://safebrowsing.googleapis.com/v4/threatMatches:find";
+const DEFAULT_CACHE_TTL: u64 = 30 * 60;
+const CACHE_SIZE: usize = 4096;
+// Links checked at once, the rest of the output is left alone
+const MAX_SCANNED_URLS: usize = 32;
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum UrlScanAction {
+    Ignore,
+    /// Let the link through with a warning
+    Warn,
+    Block,
+}
+
+impl UrlScanAction {
+    pub fn from_str(s: &str) -> Option<UrlScanAction> {
+        match s {
+            "ignore" => Some(UrlScanAction::Ignore),
+            "warn" => Some(UrlScanAction::Warn),
+            "block" => Some(UrlScanAction::Block),
+            _ => None,
+        }
+    }
+}
+
+/// Checks urls against the configured providers, remembering the results for a while
+pub struct UrlScanner {
+    safe_browsing_key: Option<String>,
+    blocked_domains: HashSet<String>,
+    cache_ttl: Duration,
+    cache: Mutex<LruCache<String, (bool, Instant)>>,
+}
+
+impl UrlScanner {
+    pub fn new(config: Option<&ConfigUrlScan>) -> Result<UrlScanner> {
+        let mut blocked_domains = HashSet::new();
+
+        for path in config.into_iter().flat_map(|config| &config.blocklists) {
+            let list = std::fs::read_to_string(path)?;
+
+            blocked_domains.extend(
+                list.lines()
+                    .map(|line| line.trim().trim_start_matches("*.").to_lowercase())
+                    .filter(|line| !line.is_empty() && !line.starts_with('#')),
+            );
+        }
+
+        Ok(UrlScanner {
+            safe_browsing_key: config.and_then(|config| config.safe_browsing_key.clone()),
+            blocked_domains,
+            cache_ttl: Duration::from_secs(
+                config
+                    .and_then(|config| config.cache_ttl)
+                    .unwrap_or(DEFAULT_CACHE_TTL),
+            ),
+            cache: Mutex::new(LruCache::new(CACHE_SIZE)),
+        })
+    }
+
+    /// Whether any provider is configured, nothing is scanned otherwise
+    pub fn is_enabled(&self) -> bool {
+        self.safe_browsing_key.is_some() || !self.blocked_domains.is_empty()
+    }
+
+    fn blocklisted(&self, url: &str) -> bool {
+        let host = match url::Url::parse(url)
+            .ok()
+            .and_then(|url| url.host_str().map(|host| host.to_lowercase()))
+        {
+            Some(host) => host,
+            None => return false,
+        };
+
+        // The host and every domain it is under, like a.b.com, b.com and com
+        let mut domain = host.as_str();
+        loop {
+            if self.blocked_domains.contains(domain) {
+                return true;
+            }
+
+            match domain.split_once('.') {
+                Some((_, parent)) => domain = parent,
+                None => return false,
+            }
+        }
+    }
+
+    async fn safe_browsing(&self, key: &str, urls: &[String]) -> Result<HashSet<String>> {
+        let body = json!({
+            "client": {
+                "clientId": "kaito",
+                "clientVersion": env!("CARGO_PKG_VERSION"),
+            },
+            "threatInfo": {
+                "threatTypes": [
+                    "MALWARE",
+                    "SOCIAL_ENGINEERING",
+                    "UNWANTED_SOFTWARE",
+                    "POTENTIALLY_HARMFUL_APPLICATION",
+                ],
+                "platformTypes": ["ANY_PLATFORM"],
+                "threatEntryTypes": ["URL"],
+                "threatEntries": urls.iter().map(|url| json!({ "url": url })).collect::<Vec<_>>(),
+            },
+        });
+
+        let client = Client::builder().build::<_, Body>(HttpsConnector::new());
+        let req = Request::builder()
+            .method("POST")
+            .uri(format!("{}?key={}", SAFE_BROWSING_URL, key))
+            .header("Content-Type", "application/json")
+            .body(Body::from(serde_json::to_vec(&body)?))?;
+
+        let res = client.request(req).await?;
+
+        if !res.status().is_success() {
+            return Err(UrlScanError::ProviderError(res.status().as_u16()).into());
+        }
+
+        let res: JsonValue = serde_json::from_slice(&hyper::body::to_bytes(res).await?)?;
+
+        // No matches is an empty object
+        Ok(res["matches"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .filter_map(|entry| entry["threat"]["url"].as_str())
+            .map(str::to_string)
+            .collect())
+    }
+
+    /// The urls flagged as unsafe by any provider
+    pub async fn scan(&self, urls: &[String]) -> Result<Vec<String>> {
+        if !self.is_enabled() {
+            return Ok(Vec::new());
+        }
+
+        let mut flagged = Vec::new();
+        let mut unknown = Vec::new();
+
+        {
+            let mut cache = self.cache.lock().unwrap();
+
+            for url in urls.iter().take(MAX_SCANNED_URLS) {
+                match cache.get(url) {
+                    Some((true, time)) if time.elapsed() < self.cache_ttl => {
+                        flagged.push(url.clone())
+                    }
+                    Some((false, time)) if time.elapsed() < self.cache_ttl => {}
+                    _ if self.blocklisted(url) => {
+                        cache.put(url.clone(), (true, Instant::now()));
+                        flagged.push(url.clone());
+                    }
+                    _ if !unknown.contains(url) => unknown.push(url.clone()),
+                    _ => {}
+                }
+            }
+        }
+
+        if unknown.is_empty() {
+            return Ok(flagged);
+        }
+
+        let unsafe_urls = match &self.safe_browsing_key {
+            Some(key) => self.safe_browsing(key, &unknown).await?,
+            None => HashSet::new(),
+        };
+
+        let mut cache = self.cache.lock().unwrap();
+
+        for url in unknown {
+            let flag = unsafe_urls.contains(&url);
+            cache.put(url.clone(), (flag, Instant::now()));
+
+            if flag {
+                flagged.push(url);
+            }
+        }
+
+        Ok(flagged)
+    }
+}
+
+/// Links in the content of a message
+pub fn content_urls(service: ServiceKind, content: &str) -> Vec<String> {
+    service
+        .parse_content(content)
+        .into_iter()
+        .filter_map(|segment| match segment {
+            ContentSegment::Link(url) => Some(url),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Host of a url for messages, so flagged links aren't posted again
+pub fn url_host(url: &str) -> String {
+    url::Url::parse(url)
+        .ok()
+        .and_then(|url| url.host_str().map(str::to_string))
+        .unwrap_or_default()
+}
+
+#[derive(Debug, Error)]
+pub enum UrlScanError {
+    #[error("a link to {} was flagged as unsafe", _0)]
+    UnsafeLink(String),
+    #[error("the url scanning provider responded with status {}", _0)]
+    ProviderError(u16),
+    #[error("unknown url scan action \"{}\", expected ignore, warn or block", _0)]
+    InvalidAction(String),
+}
@@ -1,10 +1,36 @@
-use once_cell::sync::OnceCell;
-use std::time::Instant;
+use std::{any::Any, fmt};
 
-static INSTANT: OnceCell<Instant> = OnceCell::new();
+/// Identifies a single command or sandbox run, carried through async callbacks so errors can be traced back to it
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TraceId(u32);
 
-pub fn get_duration() -> f64 {
-    let elapsed = INSTANT.get_or_init(|| Instant::now()).elapsed();
+impl TraceId {
+    pub fn new() -> TraceId {
+        TraceId(rand::random())
+    }
 
-    elapsed.as_secs() as f64 + elapsed.subsec_nanos() as f64 / 1_000_000_000.0
+    pub fn from_raw(raw: u32) -> TraceId {
+        TraceId(raw)
+    }
+
+    pub fn raw(&self) -> u32 {
+        self.0
+    }
+}
+
+impl fmt::Display for TraceId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:08x}", self.0)
+    }
+}
+
+/// The message a panic was started with, if it was given one
+pub fn panic_message(payload: &(dyn Any + Send)) -> String {
+    if let Some(message) = payload.downcast_ref::<&str>() {
+        message.to_string()
+    } else if let Some(message) = payload.downcast_ref::<String>() {
+        message.clone()
+    } else {
+        "unknown panic".into()
+    }
 }
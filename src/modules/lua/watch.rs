@@ -0,0 +1,90 @@
+use anyhow::Result;
+use futures::{channel::mpsc, StreamExt};
+use notify::{RecursiveMode, Watcher};
+use std::{path::Path, sync::Arc, time::Duration};
+
+use super::LuaModule;
+
+// Editors save in several steps, changes are collected for a moment before reloading
+const DEBOUNCE: Duration = Duration::from_millis(500);
+
+/// States that run the changed scripts
+#[derive(Clone, Copy, Debug, Default)]
+pub struct AffectedStates {
+    pub bot: bool,
+    pub sandbox: bool,
+}
+
+impl AffectedStates {
+    /// Sandbox scripts are sandbox.lua and sandbox/, lib/ is included by both states and the
+    /// rest belongs to the bot state
+    pub fn add(&mut self, relative_path: &Path) {
+        if relative_path.extension().map_or(true, |ext| ext != "lua") {
+            return;
+        }
+
+        match relative_path
+            .components()
+            .next()
+            .and_then(|component| component.as_os_str().to_str())
+        {
+            Some("sandbox.lua") | Some("sandbox") => self.sandbox = true,
+            Some("lib") => {
+                self.bot = true;
+                self.sandbox = true;
+            }
+            _ => self.bot = true,
+        }
+    }
+
+    pub fn any(&self) -> bool {
+        self.bot || self.sandbox
+    }
+}
+
+/// Reload the states whose scripts change under the lua root the module started with
+pub fn spawn_script_watcher(module: Arc<LuaModule>) -> Result<()> {
+    let watch_path = module.lua_root_path.load_full();
+    let (sender, mut receiver) = mpsc::unbounded();
+
+    let mut watcher =
+        notify::recommended_watcher(move |res: notify::Result<notify::Event>| match res {
+            Ok(event) => {
+                sender.unbounded_send(event.paths).ok();
+            }
+            Err(err) => println!("error watching lua scripts: {}", err.to_string()),
+        })?;
+    watcher.watch(&watch_path, RecursiveMode::Recursive)?;
+
+    println!("Watching {} for script changes", watch_path.display());
+
+    tokio::spawn(async move {
+        // Events stop once the watcher is dropped
+        let _watcher = watcher;
+
+        while let Some(mut paths) = receiver.next().await {
+            tokio::time::sleep(DEBOUNCE).await;
+
+            while let Ok(Some(more_paths)) = receiver.try_next() {
+                paths.extend(more_paths);
+            }
+
+            // After a sync or rollback the scripts run from a stored version, edits here no
+            // longer apply to them
+            let lua_root_path = module.lua_root_path.load_full();
+            let mut affected = AffectedStates::default();
+
+            for path in &paths {
+                if let Ok(relative_path) = path.strip_prefix(&*lua_root_path) {
+                    affected.add(relative_path);
+                }
+            }
+
+            if let Err(err) = module.reload_changed_scripts(affected).await {
+                println!("error reloading changed lua scripts: {}", err.to_string());
+            }
+        }
+    });
+
+    Ok(())
+}
@@ -0,0 +1,321 @@
+use anyhow::Result;
+use async_mutex::Mutex;
+use lru::LruCache;
+use std::sync::Arc;
+
+use super::{
+    lua::lib::bot::{download_attachment, MAX_ATTACHMENT_SIZE},
+    Module, ModuleKind,
+};
+use crate::{
+    bot::Bot,
+    message::{Attachment, MessageSettings, Sanitize},
+    services::{
+        Channel, ChannelId, InteractionId, Message, MessageId, Server, ServerId, Service, User,
+        UserId,
+    },
+    settings::prelude::*,
+};
+
+// Deleted messages can only be logged while they are still cached
+const MESSAGE_CACHE_SIZE: usize = 4096;
+const MAX_LOGGED_CONTENT_LEN: usize = 1700;
+
+#[derive(Clone)]
+struct LoggedMessage {
+    author_id: UserId,
+    author_name: String,
+    channel_name: String,
+    content: String,
+    attachments: Vec<Arc<Attachment>>,
+}
+
+pub struct MessageLogModule {
+    bot: Arc<Bot>,
+    settings: Arc<MessageLogModuleSettings>,
+    cache: Mutex<LruCache<MessageId, LoggedMessage>>,
+}
+
+settings! {
+    MessageLogModuleSettings,
+    MessageLogModule,
+    {
+        enable: bool => (false, SettingFlags::SERVER_OVERRIDE, "Log edited and deleted messages of the server", []),
+        channel: String => ("".into(), SettingFlags::SERVER_OVERRIDE, "Channel to post the message log in, as an id like discord:1234", [max_len => 64]),
+        excluded_channels: String => ("".into(), SettingFlags::SERVER_OVERRIDE, "Comma separated ids of channels to keep out of the log, like private channels", [max_len => 1000])
+    }
+}
+
+#[async_trait]
+impl Module for MessageLogModule {
+    const KIND: ModuleKind = ModuleKind::MessageLog;
+    const ID: &'static str = "message_log";
+    const NAME: &'static str = "Message log";
+
+    type ModuleConfig = ();
+    type ModuleSettings = MessageLogModuleSettings;
+
+    async fn load(bot: Arc<Bot>, _config: ()) -> Result<Arc<MessageLogModule>> {
+        Ok(Arc::new(MessageLogModule {
+            bot: bot.clone(),
+            settings: MessageLogModuleSettings::create(bot)?,
+            cache: Mutex::new(LruCache::new(MESSAGE_CACHE_SIZE)),
+        }))
+    }
+
+    async fn unload(&self) -> Result<()> {
+        Ok(())
+    }
+
+    async fn message(&self, msg: Arc<dyn Message<impl Service>>) -> Result<()> {
+        if msg.author().bot().unwrap_or(false) {
+            return Ok(());
+        }
+
+        let channel = msg.channel().await?;
+        let server_id = channel.server().await?.id();
+
+        if !self.logged(server_id, channel.id()).await? {
+            return Ok(());
+        }
+
+        self.cache.lock().await.put(
+            msg.id(),
+            LoggedMessage {
+                author_id: msg.author().id(),
+                author_name: msg.author().name().to_string(),
+                channel_name: channel.name(),
+                content: msg.content().to_string(),
+                attachments: msg.attachments().to_vec(),
+            },
+        );
+
+        Ok(())
+    }
+
+    async fn message_update(
+        &self,
+        msg: Arc<dyn Message<impl Service>>,
+        old_msg: Option<Arc<dyn Message<impl Service>>>,
+    ) -> Result<()> {
+        if msg.author().bot().unwrap_or(false) {
+            return Ok(());
+        }
+
+        let channel = msg.channel().await?;
+        let server_id = channel.server().await?.id();
+
+        if !self.logged(server_id, channel.id()).await? {
+            return Ok(());
+        }
+
+        let new = LoggedMessage {
+            author_id: msg.author().id(),
+            author_name: msg.author().name().to_string(),
+            channel_name: channel.name(),
+            content: msg.content().to_string(),
+            attachments: msg.attachments().to_vec(),
+        };
+
+        let cached = self.cache.lock().await.put(msg.id(), new.clone());
+        let old = match (cached, old_msg) {
+            (Some(cached), _) => cached,
+            (None, Some(old_msg)) => LoggedMessage {
+                content: old_msg.content().to_string(),
+                attachments: old_msg.attachments().to_vec(),
+                ..new.clone()
+            },
+            (None, None) => return Ok(()),
+        };
+
+        if old.content == new.content && old.attachments.len() == new.attachments.len() {
+            return Ok(());
+        }
+
+        let mut text = format!(
+            "**Message edited** in #{} by {} ({})\n",
+            new.channel_name,
+            new.author_name,
+            new.author_id.to_short_str()
+        );
+
+        if old.content != new.content {
+            text.push_str(&format!(
+                "```diff\n{}\n```",
+                truncate(
+                    &diff_lines(&old.content, &new.content),
+                    MAX_LOGGED_CONTENT_LEN
+                )
+            ));
+        }
+
+        // Attachments removed in the edit are gone from the message, so keep a copy in the log
+        let removed = old
+            .attachments
+            .into_iter()
+            .filter(|old| !new.attachments.iter().any(|new| new.url == old.url))
+            .collect();
+
+        self.post(server_id, text, removed).await
+    }
+
+    async fn message_delete(
+        &self,
+        server_id: Option<ServerId>,
+        channel_id: ChannelId,
+        message_id: MessageId,
+    ) -> Result<()> {
+        let server_id = match server_id {
+            Some(server_id) => server_id,
+            None => return Ok(()),
+        };
+
+        // Uncached messages were sent before the bot started or in excluded channels
+        let logged = match self.cache.lock().await.pop(&message_id) {
+            Some(logged) => logged,
+            None => return Ok(()),
+        };
+
+        if !self.logged(server_id, channel_id).await? {
+            return Ok(());
+        }
+
+        let mut text = format!(
+            "**Message deleted** in #{} by {} ({})\n",
+            logged.channel_name,
+            logged.author_name,
+            logged.author_id.to_short_str()
+        );
+
+        if !logged.content.is_empty() {
+            text.push_str(&format!(
+                "```\n{}\n```",
+                truncate(&escape_code_block(&logged.content), MAX_LOGGED_CONTENT_LEN)
+            ));
+        }
+
+        self.post(server_id, text, logged.attachments).await
+    }
+
+    async fn reaction(
+        &self,
+        _msg: Arc<dyn Message<impl Service>>,
+        _reactor: Arc<dyn User<impl Service>>,
+        _reaction: String,
+        _remove: bool,
+    ) -> Result<()> {
+        Ok(())
+    }
+
+    async fn component(
+        &self,
+        _msg: Arc<dyn Message<impl Service>>,
+        _user: Arc<dyn User<impl Service>>,
+        _id: String,
+        _values: Vec<String>,
+        _interaction_id: InteractionId,
+    ) -> Result<()> {
+        Ok(())
+    }
+
+    async fn enabled(&self, server_id: ServerId, channel_id: ChannelId) -> Result<bool> {
+        self.settings.enable.value(server_id, channel_id).await
+    }
+
+    fn settings(&self) -> &Arc<MessageLogModuleSettings> {
+        &self.settings
+    }
+}
+
+impl MessageLogModule {
+    /// Whether messages of the channel go into the log, the log channel itself never does
+    async fn logged(&self, server_id: ServerId, channel_id: ChannelId) -> Result<bool> {
+        if !self.enabled(server_id, channel_id).await? {
+            return Ok(false);
+        }
+
+        let log_channel = self.settings.channel.server_value(server_id).await?;
+
+        if log_channel.is_empty() || ChannelId::from_str(&log_channel)? == channel_id {
+            return Ok(false);
+        }
+
+        let excluded = self
+            .settings
+            .excluded_channels
+            .server_value(server_id)
+            .await?;
+
+        for id in excluded
+            .split(',')
+            .map(str::trim)
+            .filter(|id| !id.is_empty())
+        {
+            if ChannelId::from_str(id).ok() == Some(channel_id) {
+                return Ok(false);
+            }
+        }
+
+        Ok(true)
+    }
+
+    async fn post(
+        &self,
+        server_id: ServerId,
+        mut text: String,
+        attachments: Vec<Arc<Attachment>>,
+    ) -> Result<()> {
+        let log_channel =
+            ChannelId::from_str(&self.settings.channel.server_value(server_id).await?)?;
+        let mut settings = MessageSettings {
+            sanitize: Sanitize::all(),
+            ..Default::default()
+        };
+
+        // Re-upload what still can be downloaded, linking the rest
+        let mut total_size = 0;
+        for attachment in attachments {
+            match download_attachment(attachment.clone(), MAX_ATTACHMENT_SIZE - total_size).await {
+                Ok(data) => {
+                    total_size += data.len() as u64;
+                    settings
+                        .attachments
+                        .push((attachment.filename.clone(), data));
+                }
+                Err(_) => text.push_str(&format!("\n{}", attachment.url)),
+            }
+        }
+
+        self.bot
+            .get_ctx()
+            .services()
+            .send_message(log_channel, text, settings)
+            .await?;
+
+        Ok(())
+    }
+}
+
+fn diff_lines(old: &str, new: &str) -> String {
+    diff::lines(&escape_code_block(old), &escape_code_block(new))
+        .into_iter()
+        .map(|line| match line {
+            diff::Result::Left(line) => format!("- {}", line),
+            diff::Result::Both(line, _) => format!("  {}", line),
+            diff::Result::Right(line) => format!("+ {}", line),
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+// Keep logged content from closing the code block it is posted in
+fn escape_code_block(text: &str) -> String {
+    text.replace("```", "`\u{200B}``")
+}
+
+fn truncate(text: &str, max_len: usize) -> String {
+    match text.char_indices().nth(max_len) {
+        Some((i, _)) => format!("{}…", &text[..i]),
+        None => text.to_string(),
+    }
+}
@@ -0,0 +1,366 @@
+use anyhow::Result;
+use std::{sync::Arc, time::Duration};
+use thiserror::Error;
+
+use super::{Module, ModuleKind};
+use crate::{
+    bot::{db::Uid, Bot},
+    services::{
+        ChannelId, InteractionId, Message, MessageId, ModerationAction, ServerId, Service,
+        ServiceError, User, UserId,
+    },
+    settings::prelude::*,
+};
+
+const UNBAN_INTERVAL: Duration = Duration::from_secs(60);
+// Discord doesn't allow timeouts longer than 28 days
+const MAX_TIMEOUT_DAYS: u64 = 28;
+
+pub struct ModerationModule {
+    bot: Arc<Bot>,
+    settings: Arc<ModerationModuleSettings>,
+}
+
+settings! {
+    ModerationModuleSettings,
+    ModerationModule,
+    {
+        enable: bool => (true, SettingFlags::SERVER_OVERRIDE, "Allow kicking, banning, timing out and purging through the bot", []),
+        max_purge: i64 => (50, SettingFlags::empty(), "Set how many messages a purge can delete at once", [min => 1 max => 100])
+    }
+}
+
+#[async_trait]
+impl Module for ModerationModule {
+    const KIND: ModuleKind = ModuleKind::Moderation;
+    const ID: &'static str = "moderation";
+    const NAME: &'static str = "Moderation";
+
+    type ModuleConfig = ();
+    type ModuleSettings = ModerationModuleSettings;
+
+    async fn load(bot: Arc<Bot>, _config: ()) -> Result<Arc<ModerationModule>> {
+        let bot2 = bot.clone();
+        tokio::spawn(async move {
+            loop {
+                tokio::time::sleep(UNBAN_INTERVAL).await;
+
+                if let Err(err) = lift_expired_bans(&bot2).await {
+                    println!("error lifting temporary bans: {}", err.to_string());
+                }
+            }
+        });
+
+        Ok(Arc::new(ModerationModule {
+            bot: bot.clone(),
+            settings: ModerationModuleSettings::create(bot)?,
+        }))
+    }
+
+    async fn unload(&self) -> Result<()> {
+        Ok(())
+    }
+
+    async fn message(&self, _msg: Arc<dyn Message<impl Service>>) -> Result<()> {
+        Ok(())
+    }
+
+    async fn message_update(
+        &self,
+        _msg: Arc<dyn Message<impl Service>>,
+        _old_msg: Option<Arc<dyn Message<impl Service>>>,
+    ) -> Result<()> {
+        Ok(())
+    }
+
+    async fn message_delete(
+        &self,
+        _server_id: Option<ServerId>,
+        _channel_id: ChannelId,
+        _message_id: MessageId,
+    ) -> Result<()> {
+        Ok(())
+    }
+
+    async fn reaction(
+        &self,
+        _msg: Arc<dyn Message<impl Service>>,
+        _reactor: Arc<dyn User<impl Service>>,
+        _reaction: String,
+        _remove: bool,
+    ) -> Result<()> {
+        Ok(())
+    }
+
+    async fn component(
+        &self,
+        _msg: Arc<dyn Message<impl Service>>,
+        _user: Arc<dyn User<impl Service>>,
+        _id: String,
+        _values: Vec<String>,
+        _interaction_id: InteractionId,
+    ) -> Result<()> {
+        Ok(())
+    }
+
+    async fn enabled(&self, server_id: ServerId, channel_id: ChannelId) -> Result<bool> {
+        self.settings.enable.value(server_id, channel_id).await
+    }
+
+    fn settings(&self) -> &Arc<ModerationModuleSettings> {
+        &self.settings
+    }
+}
+
+impl ModerationModule {
+    pub async fn kick(
+        &self,
+        server_id: ServerId,
+        user_id: UserId,
+        reason: Option<String>,
+        actor_uid: Uid,
+    ) -> Result<()> {
+        self.check_enabled(server_id).await?;
+
+        self.bot
+            .get_ctx()
+            .services()
+            .moderate(
+                server_id,
+                user_id,
+                ModerationAction::Kick {
+                    reason: reason.clone(),
+                },
+            )
+            .await?;
+
+        audit(
+            &self.bot,
+            Some(actor_uid),
+            "kick",
+            server_id,
+            serde_json::json!({ "user_id": user_id.to_str(), "reason": reason }),
+        )
+        .await
+    }
+
+    /// Ban a user, lifting the ban again after `duration` seconds if set
+    pub async fn ban(
+        &self,
+        server_id: ServerId,
+        user_id: UserId,
+        reason: Option<String>,
+        duration: Option<u64>,
+        actor_uid: Uid,
+    ) -> Result<()> {
+        self.check_enabled(server_id).await?;
+
+        self.bot
+            .get_ctx()
+            .services()
+            .moderate(
+                server_id,
+                user_id,
+                ModerationAction::Ban {
+                    reason: reason.clone(),
+                },
+            )
+            .await?;
+
+        match duration {
+            Some(duration) => {
+                let expire_time = chrono::Utc::now().timestamp() + duration as i64;
+
+                self.bot
+                    .db()
+                    .add_temp_ban(
+                        server_id,
+                        user_id,
+                        actor_uid,
+                        reason.as_deref(),
+                        expire_time,
+                    )
+                    .await?;
+            }
+            // A permanent ban replaces an earlier temporary one
+            None => self.bot.db().remove_temp_ban(server_id, user_id).await?,
+        }
+
+        audit(
+            &self.bot,
+            Some(actor_uid),
+            "ban",
+            server_id,
+            serde_json::json!({ "user_id": user_id.to_str(), "reason": reason, "duration": duration }),
+        )
+        .await
+    }
+
+    pub async fn unban(&self, server_id: ServerId, user_id: UserId, actor_uid: Uid) -> Result<()> {
+        self.check_enabled(server_id).await?;
+
+        unban(&self.bot, server_id, user_id, Some(actor_uid)).await
+    }
+
+    /// Time out a member for `duration` seconds, `None` lifts the timeout
+    pub async fn timeout(
+        &self,
+        server_id: ServerId,
+        user_id: UserId,
+        duration: Option<u64>,
+        reason: Option<String>,
+        actor_uid: Uid,
+    ) -> Result<()> {
+        self.check_enabled(server_id).await?;
+
+        if let Some(duration) = duration {
+            if duration > MAX_TIMEOUT_DAYS * 24 * 60 * 60 {
+                return Err(ModerationError::TimeoutTooLong(MAX_TIMEOUT_DAYS).into());
+            }
+        }
+
+        let until = duration.map(|duration| chrono::Utc::now().timestamp() + duration as i64);
+
+        self.bot
+            .get_ctx()
+            .services()
+            .moderate(server_id, user_id, ModerationAction::Timeout { until })
+            .await?;
+
+        audit(
+            &self.bot,
+            Some(actor_uid),
+            "timeout",
+            server_id,
+            serde_json::json!({ "user_id": user_id.to_str(), "reason": reason, "duration": duration }),
+        )
+        .await
+    }
+
+    /// Delete the latest messages of a channel, returning how many were deleted
+    pub async fn purge(
+        &self,
+        server_id: ServerId,
+        channel_id: ChannelId,
+        count: u64,
+        actor_uid: Uid,
+    ) -> Result<usize> {
+        if !self.enabled(server_id, channel_id).await? {
+            return Err(ModerationError::Disabled.into());
+        }
+
+        let max_purge = self.settings.max_purge.value(server_id, channel_id).await?;
+        if count > max_purge as u64 {
+            return Err(ModerationError::TooManyMessages(max_purge).into());
+        }
+
+        let deleted = self
+            .bot
+            .get_ctx()
+            .services()
+            .purge(channel_id, count)
+            .await?;
+
+        audit(
+            &self.bot,
+            Some(actor_uid),
+            "purge",
+            server_id,
+            serde_json::json!({ "channel_id": channel_id.to_str(), "deleted": deleted }),
+        )
+        .await?;
+
+        Ok(deleted)
+    }
+
+    async fn check_enabled(&self, server_id: ServerId) -> Result<()> {
+        if !self.settings.enable.server_value(server_id).await? {
+            return Err(ModerationError::Disabled.into());
+        }
+
+        Ok(())
+    }
+}
+
+async fn unban(
+    bot: &Bot,
+    server_id: ServerId,
+    user_id: UserId,
+    actor_uid: Option<Uid>,
+) -> Result<()> {
+    bot.get_ctx()
+        .services()
+        .moderate(server_id, user_id, ModerationAction::Unban)
+        .await?;
+    bot.db().remove_temp_ban(server_id, user_id).await?;
+
+    audit(
+        bot,
+        actor_uid,
+        "unban",
+        server_id,
+        serde_json::json!({ "user_id": user_id.to_str() }),
+    )
+    .await
+}
+
+async fn lift_expired_bans(bot: &Bot) -> Result<()> {
+    let expired = bot
+        .db()
+        .expired_temp_bans(chrono::Utc::now().timestamp())
+        .await?;
+
+    for (server_id, user_id) in expired {
+        match unban(bot, server_id, user_id, None).await {
+            Ok(()) => {}
+            // The ban was already lifted by hand
+            Err(err)
+                if matches!(
+                    err.downcast_ref::<ServiceError>(),
+                    Some(ServiceError::NotFound)
+                ) =>
+            {
+                bot.db().remove_temp_ban(server_id, user_id).await?
+            }
+            // Failed entries are kept to retry on the next round
+            Err(err) => println!(
+                "error lifting the ban of {} in {}: {}",
+                user_id.to_str(),
+                server_id.to_str(),
+                err.to_string()
+            ),
+        }
+    }
+
+    Ok(())
+}
+
+async fn audit(
+    bot: &Bot,
+    actor_uid: Option<Uid>,
+    action: &str,
+    server_id: ServerId,
+    after: serde_json::Value,
+) -> Result<()> {
+    bot.db()
+        .audit(
+            actor_uid,
+            &format!("moderation.{}", action),
+            &format!("server:{}", server_id.to_str()),
+            None,
+            Some(&after.to_string()),
+        )
+        .await
+}
+
+#[derive(Debug, Error)]
+pub enum ModerationError {
+    #[error("moderation is disabled in this server")]
+    Disabled,
+    #[error("timeouts can last at most {} days", _0)]
+    TimeoutTooLong(u64),
+    #[error("at most {} messages can be purged at once", _0)]
+    TooManyMessages(i64),
+    #[error("admins can't be moderated through the bot")]
+    ProtectedUser,
+}
@@ -0,0 +1,435 @@
+use anyhow::Result;
+use std::{sync::Arc, time::Duration};
+use thiserror::Error;
+
+use super::{Module, ModuleKind};
+use crate::{
+    bot::{
+        db::{Poll, Uid},
+        Bot,
+    },
+    message::{ButtonStyle, MessageComponent, MessageSettings, Sanitize},
+    services::{
+        Channel, ChannelId, InteractionId, Message, MessageId, Server, ServerId, Service, User,
+    },
+    settings::prelude::*,
+};
+
+const POLL_EMOJIS: &[&str] = &["1️⃣", "2️⃣", "3️⃣", "4️⃣", "5️⃣", "6️⃣", "7️⃣", "8️⃣", "9️⃣", "🔟"];
+const BUTTONS_PER_ROW: usize = 5;
+const MAX_OPTION_LEN: usize = 80;
+const MAX_POLL_DURATION: u64 = 60 * 60 * 24 * 30;
+const CLOSE_INTERVAL: Duration = Duration::from_secs(15);
+const BUTTON_ID_PREFIX: &'static str = "poll:";
+
+/// Vote counts of a poll, in the order of its options
+pub struct PollResults {
+    pub poll: Poll,
+    pub options: Vec<String>,
+    pub votes: Vec<u64>,
+}
+
+impl PollResults {
+    pub fn total(&self) -> u64 {
+        self.votes.iter().sum()
+    }
+
+    /// Options with the most votes, empty if nobody voted
+    pub fn winners(&self) -> Vec<&str> {
+        let highest = self.votes.iter().copied().max().unwrap_or(0);
+
+        if highest == 0 {
+            return Vec::new();
+        }
+
+        self.options
+            .iter()
+            .zip(self.votes.iter())
+            .filter(|(_, votes)| **votes == highest)
+            .map(|(option, _)| option.as_str())
+            .collect()
+    }
+}
+
+pub struct PollsModule {
+    bot: Arc<Bot>,
+    settings: Arc<PollsModuleSettings>,
+}
+
+settings! {
+    PollsModuleSettings,
+    PollsModule,
+    {
+        enable: bool => (true, SettingFlags::empty(), "Allow creating polls", [])
+    }
+}
+
+#[async_trait]
+impl Module for PollsModule {
+    const KIND: ModuleKind = ModuleKind::Polls;
+    const ID: &'static str = "polls";
+    const NAME: &'static str = "Polls";
+
+    type ModuleConfig = ();
+    type ModuleSettings = PollsModuleSettings;
+
+    async fn load(bot: Arc<Bot>, _config: ()) -> Result<Arc<PollsModule>> {
+        let module = Arc::new(PollsModule {
+            bot: bot.clone(),
+            settings: PollsModuleSettings::create(bot)?,
+        });
+
+        // Close times are stored with the polls, so polls due while the bot was down close on start
+        let module2 = module.clone();
+        tokio::spawn(async move {
+            loop {
+                if let Err(err) = module2.close_due_polls().await {
+                    println!("error closing polls: {}", err.to_string());
+                }
+
+                tokio::time::sleep(CLOSE_INTERVAL).await;
+            }
+        });
+
+        Ok(module)
+    }
+
+    async fn unload(&self) -> Result<()> {
+        Ok(())
+    }
+
+    async fn message(&self, _msg: Arc<dyn Message<impl Service>>) -> Result<()> {
+        Ok(())
+    }
+
+    async fn message_update(
+        &self,
+        _msg: Arc<dyn Message<impl Service>>,
+        _old_msg: Option<Arc<dyn Message<impl Service>>>,
+    ) -> Result<()> {
+        Ok(())
+    }
+
+    async fn message_delete(
+        &self,
+        _server_id: Option<ServerId>,
+        _channel_id: ChannelId,
+        _message_id: MessageId,
+    ) -> Result<()> {
+        Ok(())
+    }
+
+    async fn reaction(
+        &self,
+        msg: Arc<dyn Message<impl Service>>,
+        reactor: Arc<dyn User<impl Service>>,
+        reaction: String,
+        remove: bool,
+    ) -> Result<()> {
+        if reactor.bot().unwrap_or(false) {
+            return Ok(());
+        }
+
+        let option = match POLL_EMOJIS.iter().position(|emoji| *emoji == reaction) {
+            Some(option) => option as i64,
+            None => return Ok(()),
+        };
+
+        let poll = match self.bot.db().poll(msg.id()).await? {
+            Some(poll) if !poll.closed && !poll.buttons => poll,
+            _ => return Ok(()),
+        };
+
+        if option as usize >= parse_options(&poll)?.len() {
+            return Ok(());
+        }
+
+        // Reacting with another option moves the vote, so the old reaction no longer counts
+        if remove {
+            self.bot
+                .db()
+                .remove_poll_vote(msg.id(), reactor.id(), option)
+                .await
+        } else {
+            self.bot
+                .db()
+                .set_poll_vote(msg.id(), reactor.id(), option)
+                .await
+        }
+    }
+
+    async fn component(
+        &self,
+        msg: Arc<dyn Message<impl Service>>,
+        user: Arc<dyn User<impl Service>>,
+        id: String,
+        _values: Vec<String>,
+        _interaction_id: InteractionId,
+    ) -> Result<()> {
+        let option = match id
+            .strip_prefix(BUTTON_ID_PREFIX)
+            .and_then(|option| option.parse::<i64>().ok())
+        {
+            Some(option) => option,
+            None => return Ok(()),
+        };
+
+        let poll = match self.bot.db().poll(msg.id()).await? {
+            Some(poll) if !poll.closed && poll.buttons => poll,
+            _ => return Ok(()),
+        };
+
+        if option < 0 || option as usize >= parse_options(&poll)?.len() {
+            return Ok(());
+        }
+
+        self.bot
+            .db()
+            .set_poll_vote(msg.id(), user.id(), option)
+            .await?;
+
+        // Button votes aren't visible on the message otherwise
+        let results = self.results(msg.id()).await?;
+        self.bot
+            .get_ctx()
+            .services()
+            .edit_message(
+                ChannelId::from_str(&poll.channel_id)?,
+                msg.id(),
+                render_poll(&results),
+                poll_settings(&results),
+            )
+            .await
+    }
+
+    async fn enabled(&self, server_id: ServerId, channel_id: ChannelId) -> Result<bool> {
+        self.settings.enable.value(server_id, channel_id).await
+    }
+
+    fn settings(&self) -> &Arc<PollsModuleSettings> {
+        &self.settings
+    }
+}
+
+impl PollsModule {
+    /// Post a poll in the channel, closing it after `duration` seconds if set
+    pub async fn create(
+        &self,
+        channel_id: ChannelId,
+        title: String,
+        options: Vec<String>,
+        buttons: bool,
+        duration: Option<u64>,
+        actor_uid: Uid,
+    ) -> Result<MessageId> {
+        let services = self.bot.get_ctx().services().clone();
+        let channel = services.channel(channel_id).await?;
+        let server_id = channel.server().await?.id();
+
+        if !self.enabled(server_id, channel_id).await? {
+            return Err(PollError::Disabled.into());
+        }
+
+        if options.len() < 2 || options.len() > POLL_EMOJIS.len() {
+            return Err(PollError::OptionCount(POLL_EMOJIS.len()).into());
+        }
+
+        if options
+            .iter()
+            .any(|option| option.trim().is_empty() || option.chars().count() > MAX_OPTION_LEN)
+        {
+            return Err(PollError::InvalidOption(MAX_OPTION_LEN).into());
+        }
+
+        if let Some(duration) = duration {
+            if duration == 0 || duration > MAX_POLL_DURATION {
+                return Err(PollError::InvalidDuration(MAX_POLL_DURATION / (60 * 60 * 24)).into());
+            }
+        }
+
+        let mut results = PollResults {
+            poll: Poll {
+                message_id: String::new(),
+                channel_id: channel_id.to_short_str(),
+                title,
+                options: serde_json::to_string(&options)?,
+                buttons,
+                created_by: actor_uid,
+                close_time: duration
+                    .map(|duration| chrono::Utc::now().timestamp() + duration as i64),
+                closed: false,
+            },
+            votes: vec![0; options.len()],
+            options,
+        };
+
+        let msg = services
+            .send_message(channel_id, render_poll(&results), poll_settings(&results))
+            .await?;
+
+        results.poll.message_id = msg.id().to_short_str();
+        self.bot.db().add_poll(&results.poll).await?;
+
+        if !buttons {
+            for emoji in POLL_EMOJIS.iter().take(results.options.len()) {
+                services
+                    .react(channel_id, msg.id(), emoji.to_string())
+                    .await?;
+            }
+        }
+
+        Ok(msg.id())
+    }
+
+    pub async fn results(&self, message_id: MessageId) -> Result<PollResults> {
+        let poll = self
+            .bot
+            .db()
+            .poll(message_id)
+            .await?
+            .ok_or(PollError::NotFound)?;
+        let options = parse_options(&poll)?;
+        let mut votes = vec![0; options.len()];
+
+        for (option, count) in self.bot.db().poll_tally(message_id).await? {
+            if let Some(votes) = votes.get_mut(option as usize) {
+                *votes = count as u64;
+            }
+        }
+
+        Ok(PollResults {
+            poll,
+            options,
+            votes,
+        })
+    }
+
+    /// Close the poll and show the results on its message
+    pub async fn close(&self, message_id: MessageId) -> Result<PollResults> {
+        if !self.bot.db().close_poll(message_id).await? {
+            return Err(PollError::AlreadyClosed.into());
+        }
+
+        let results = self.results(message_id).await?;
+
+        self.bot
+            .get_ctx()
+            .services()
+            .edit_message(
+                ChannelId::from_str(&results.poll.channel_id)?,
+                message_id,
+                render_poll(&results),
+                poll_settings(&results),
+            )
+            .await?;
+
+        Ok(results)
+    }
+
+    async fn close_due_polls(&self) -> Result<()> {
+        let due = self
+            .bot
+            .db()
+            .due_polls(chrono::Utc::now().timestamp())
+            .await?;
+
+        for poll in due {
+            let message_id = MessageId::from_str(&poll.message_id)?;
+
+            // A deleted message shouldn't keep the other polls from closing
+            if let Err(err) = self.close(message_id).await {
+                println!(
+                    "error closing poll {}: {}",
+                    poll.message_id,
+                    err.to_string()
+                );
+            }
+        }
+
+        Ok(())
+    }
+}
+
+fn parse_options(poll: &Poll) -> Result<Vec<String>> {
+    Ok(serde_json::from_str(&poll.options)?)
+}
+
+fn render_poll(results: &PollResults) -> String {
+    let poll = &results.poll;
+    let show_votes = poll.closed || poll.buttons;
+    let mut text = format!(
+        "**{}** {}\n\n",
+        if poll.closed {
+            "Poll results:"
+        } else {
+            "Poll:"
+        },
+        poll.title
+    );
+
+    for (i, option) in results.options.iter().enumerate() {
+        text.push_str(&format!("{} {}", POLL_EMOJIS[i], option));
+
+        if show_votes {
+            text.push_str(&format!(" ({}/{})", results.votes[i], results.total()));
+        }
+
+        text.push('\n');
+    }
+
+    if poll.closed {
+        let winners = results.winners();
+
+        if !winners.is_empty() {
+            text.push_str(&format!("\n**Winner:** {}", winners.join(", ")));
+        }
+    } else if let Some(close_time) = poll.close_time {
+        text.push_str(&format!("\nCloses <t:{}:R>", close_time));
+    }
+
+    text
+}
+
+fn poll_settings(results: &PollResults) -> MessageSettings {
+    let mut settings = MessageSettings {
+        sanitize: Sanitize::all(),
+        ..Default::default()
+    };
+
+    if results.poll.buttons {
+        let buttons: Vec<_> = results
+            .options
+            .iter()
+            .enumerate()
+            .map(|(i, option)| MessageComponent::Button {
+                id: format!("{}{}", BUTTON_ID_PREFIX, i),
+                label: option.clone(),
+                style: ButtonStyle::Secondary,
+                disabled: results.poll.closed,
+            })
+            .collect();
+
+        settings.components = buttons
+            .chunks(BUTTONS_PER_ROW)
+            .map(|row| row.to_vec())
+            .collect();
+    }
+
+    settings
+}
+
+#[derive(Debug, Error)]
+pub enum PollError {
+    #[error("polls are disabled in this channel")]
+    Disabled,
+    #[error("no poll was found for the message")]
+    NotFound,
+    #[error("the poll is already closed")]
+    AlreadyClosed,
+    #[error("polls need between 2 and {} options", _0)]
+    OptionCount(usize),
+    #[error("poll options can't be empty or longer than {} characters", _0)]
+    InvalidOption(usize),
+    #[error("polls can be open for at most {} days", _0)]
+    InvalidDuration(u64),
+}
@@ -0,0 +1,242 @@
+use anyhow::Result;
+use std::sync::Arc;
+use thiserror::Error;
+
+use super::{Module, ModuleKind};
+use crate::{
+    bot::{
+        db::{ReactionRole, Uid},
+        Bot,
+    },
+    services::{
+        Channel, ChannelId, InteractionId, Message, MessageId, Server, ServerId, ServerRole,
+        Service, User,
+    },
+    settings::prelude::*,
+};
+
+pub struct ReactionRolesModule {
+    bot: Arc<Bot>,
+    settings: Arc<ReactionRolesModuleSettings>,
+}
+
+settings! {
+    ReactionRolesModuleSettings,
+    ReactionRolesModule,
+    {
+        enable: bool => (true, SettingFlags::SERVER_OVERRIDE, "Give roles to members reacting to reaction role messages", [])
+    }
+}
+
+#[async_trait]
+impl Module for ReactionRolesModule {
+    const KIND: ModuleKind = ModuleKind::ReactionRoles;
+    const ID: &'static str = "reaction_roles";
+    const NAME: &'static str = "Reaction roles";
+
+    type ModuleConfig = ();
+    type ModuleSettings = ReactionRolesModuleSettings;
+
+    async fn load(bot: Arc<Bot>, _config: ()) -> Result<Arc<ReactionRolesModule>> {
+        Ok(Arc::new(ReactionRolesModule {
+            bot: bot.clone(),
+            settings: ReactionRolesModuleSettings::create(bot)?,
+        }))
+    }
+
+    async fn unload(&self) -> Result<()> {
+        Ok(())
+    }
+
+    async fn message(&self, _msg: Arc<dyn Message<impl Service>>) -> Result<()> {
+        Ok(())
+    }
+
+    async fn message_update(
+        &self,
+        _msg: Arc<dyn Message<impl Service>>,
+        _old_msg: Option<Arc<dyn Message<impl Service>>>,
+    ) -> Result<()> {
+        Ok(())
+    }
+
+    async fn message_delete(
+        &self,
+        _server_id: Option<ServerId>,
+        _channel_id: ChannelId,
+        _message_id: MessageId,
+    ) -> Result<()> {
+        Ok(())
+    }
+
+    async fn reaction(
+        &self,
+        msg: Arc<dyn Message<impl Service>>,
+        reactor: Arc<dyn User<impl Service>>,
+        reaction: String,
+        remove: bool,
+    ) -> Result<()> {
+        if reactor.bot().unwrap_or(false) {
+            return Ok(());
+        }
+
+        let roles = self.bot.db().message_reaction_roles(msg.id()).await?;
+        let role = match roles.iter().find(|role| role.emoji == reaction) {
+            Some(role) => role,
+            None => return Ok(()),
+        };
+
+        let channel = msg.channel().await?;
+        let server_id = channel.server().await?.id();
+
+        if !self.enabled(server_id, channel.id()).await? {
+            return Ok(());
+        }
+
+        let services = self.bot.get_ctx().services().clone();
+
+        // Taking away the other roles of an exclusive message leaves their reactions in place,
+        // removing those later is harmless as the member no longer has the role
+        if !remove && role.exclusive {
+            for other in roles.iter().filter(|other| other.role_id != role.role_id) {
+                services
+                    .set_member_role(server_id, reactor.id(), &other.role_id, false)
+                    .await?;
+            }
+        }
+
+        services
+            .set_member_role(server_id, reactor.id(), &role.role_id, !remove)
+            .await
+    }
+
+    async fn component(
+        &self,
+        _msg: Arc<dyn Message<impl Service>>,
+        _user: Arc<dyn User<impl Service>>,
+        _id: String,
+        _values: Vec<String>,
+        _interaction_id: InteractionId,
+    ) -> Result<()> {
+        Ok(())
+    }
+
+    async fn enabled(&self, server_id: ServerId, channel_id: ChannelId) -> Result<bool> {
+        self.settings.enable.value(server_id, channel_id).await
+    }
+
+    fn settings(&self) -> &Arc<ReactionRolesModuleSettings> {
+        &self.settings
+    }
+}
+
+impl ReactionRolesModule {
+    /// Map an emoji on a message to a role, reacting with the emoji so members can click it
+    pub async fn add(
+        &self,
+        channel_id: ChannelId,
+        message_id: MessageId,
+        emoji: &str,
+        role: &str,
+        exclusive: bool,
+        actor_uid: Uid,
+    ) -> Result<ServerRole> {
+        let services = self.bot.get_ctx().services().clone();
+        let emoji = normalize_emoji(emoji);
+
+        let msg = services.message(channel_id, message_id).await?;
+        let server_id = msg.channel().await?.server().await?.id();
+        let role = find_role(services.server_roles(server_id).await?, role)?;
+
+        let existing = self.bot.db().message_reaction_roles(message_id).await?;
+
+        if let Some(taken) = existing.iter().find(|existing| existing.emoji == emoji) {
+            return Err(ReactionRoleError::EmojiTaken(taken.role_id.clone()).into());
+        }
+
+        if let Some(taken) = existing.iter().find(|existing| existing.role_id == role.id) {
+            return Err(ReactionRoleError::RoleTaken(taken.emoji.clone()).into());
+        }
+
+        services
+            .react(channel_id, message_id, emoji.clone())
+            .await?;
+
+        let reaction_role = ReactionRole {
+            message_id: message_id.to_short_str(),
+            emoji,
+            sid: self.bot.db().get_sid(server_id).await?,
+            channel_id: channel_id.to_short_str(),
+            role_id: role.id.clone(),
+            exclusive,
+        };
+
+        self.bot
+            .db()
+            .add_reaction_role(&reaction_role, actor_uid)
+            .await?;
+
+        Ok(role)
+    }
+
+    pub async fn remove(
+        &self,
+        message_id: MessageId,
+        emoji: &str,
+        actor_uid: Uid,
+    ) -> Result<Option<ReactionRole>> {
+        self.bot
+            .db()
+            .remove_reaction_role(message_id, &normalize_emoji(emoji), actor_uid)
+            .await
+    }
+
+    pub async fn list(&self, server_id: ServerId) -> Result<Vec<ReactionRole>> {
+        let sid = self.bot.db().get_sid(server_id).await?;
+
+        self.bot.db().list_reaction_roles(sid).await
+    }
+}
+
+/// Custom emoji are written as `<:name:id>` in messages, but reactions only carry `name:id`
+fn normalize_emoji(emoji: &str) -> String {
+    let emoji = emoji.trim();
+
+    match emoji.strip_prefix('<').and_then(|e| e.strip_suffix('>')) {
+        Some(custom) => custom
+            .trim_start_matches('a')
+            .trim_start_matches(':')
+            .into(),
+        None => emoji.into(),
+    }
+}
+
+/// Find a role by id, mention or case insensitive name
+fn find_role(roles: Vec<ServerRole>, find: &str) -> Result<ServerRole> {
+    let find = find.trim();
+    let id = find
+        .strip_prefix("<@&")
+        .and_then(|id| id.strip_suffix('>'))
+        .unwrap_or(find);
+
+    roles
+        .iter()
+        .find(|role| role.id == id)
+        .or_else(|| {
+            roles
+                .iter()
+                .find(|role| role.name.eq_ignore_ascii_case(find))
+        })
+        .cloned()
+        .ok_or_else(|| ReactionRoleError::UnknownRole(find.into()).into())
+}
+
+#[derive(Debug, Error)]
+pub enum ReactionRoleError {
+    #[error("unknown role \"{}\"", _0)]
+    UnknownRole(String),
+    #[error("the emoji is already used for the role {} on this message", _0)]
+    EmojiTaken(String),
+    #[error("the role already has the emoji {} on this message", _0)]
+    RoleTaken(String),
+}
@@ -0,0 +1,269 @@
+use anyhow::Result;
+use hyper::{Body, Client, Request};
+use hyper_tls::HttpsConnector;
+use std::{
+    collections::BTreeMap,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
+use thiserror::Error;
+
+use super::{Module, ModuleKind};
+use crate::{
+    bot::Bot,
+    services::{ChannelId, InteractionId, Message, MessageId, ServerId, Service, User},
+};
+
+const DEFAULT_INTERVAL: u64 = 24 * 60 * 60;
+
+#[derive(Clone, Deserialize, Serialize, Debug)]
+pub struct TelemetryModuleConfig {
+    /// Nothing is counted or sent unless the owner enables it
+    #[serde(default)]
+    pub enabled: bool,
+    /// Url the reports are posted to as json
+    pub endpoint: String,
+    /// Seconds between reports, defaults to a day
+    pub interval: Option<u64>,
+}
+
+/// Anonymous counters, no ids, names or content of servers and users are ever part of it
+#[derive(Serialize)]
+pub struct TelemetryReport {
+    pub version: &'static str,
+    /// Seconds the counters were collected over
+    pub period: u64,
+    pub commands_run: u64,
+    pub sandbox_terminations: BTreeMap<&'static str, u64>,
+    pub services: Vec<&'static str>,
+}
+
+pub struct TelemetryModule {
+    bot: Arc<Bot>,
+    config: Option<TelemetryModuleConfig>,
+    settings: Arc<TelemetryModuleSettings>,
+    commands_run: AtomicU64,
+    sandbox_terminations: Mutex<BTreeMap<&'static str, u64>>,
+    period_start: Mutex<Instant>,
+}
+
+settings! {
+    TelemetryModuleSettings,
+    TelemetryModule,
+    {}
+}
+
+#[async_trait]
+impl Module for TelemetryModule {
+    const KIND: ModuleKind = ModuleKind::Telemetry;
+    const ID: &'static str = "telemetry";
+    const NAME: &'static str = "Telemetry";
+
+    type ModuleConfig = Option<TelemetryModuleConfig>;
+    type ModuleSettings = TelemetryModuleSettings;
+
+    async fn load(
+        bot: Arc<Bot>,
+        config: Option<TelemetryModuleConfig>,
+    ) -> Result<Arc<TelemetryModule>> {
+        let module = Arc::new(TelemetryModule {
+            bot: bot.clone(),
+            config,
+            settings: TelemetryModuleSettings::create(bot)?,
+            commands_run: AtomicU64::new(0),
+            sandbox_terminations: Mutex::new(BTreeMap::new()),
+            period_start: Mutex::new(Instant::now()),
+        });
+
+        if module.is_enabled() {
+            let module2 = module.clone();
+            tokio::spawn(async move {
+                let mut interval = tokio::time::interval(module2.interval());
+
+                // The first tick completes right away, reports are sent once a period has passed
+                interval.tick().await;
+
+                loop {
+                    interval.tick().await;
+
+                    if let Err(err) = module2.send_report().await {
+                        println!("error sending telemetry report: {}", err.to_string());
+                    }
+                }
+            });
+        }
+
+        Ok(module)
+    }
+
+    async fn unload(&self) -> Result<()> {
+        Ok(())
+    }
+
+    async fn message(&self, _msg: Arc<dyn Message<impl Service>>) -> Result<()> {
+        Ok(())
+    }
+
+    async fn message_update(
+        &self,
+        _msg: Arc<dyn Message<impl Service>>,
+        _old_msg: Option<Arc<dyn Message<impl Service>>>,
+    ) -> Result<()> {
+        Ok(())
+    }
+
+    async fn message_delete(
+        &self,
+        _server_id: Option<ServerId>,
+        _channel_id: ChannelId,
+        _message_id: MessageId,
+    ) -> Result<()> {
+        Ok(())
+    }
+
+    async fn reaction(
+        &self,
+        _msg: Arc<dyn Message<impl Service>>,
+        _reactor: Arc<dyn User<impl Service>>,
+        _reaction: String,
+        _remove: bool,
+    ) -> Result<()> {
+        Ok(())
+    }
+
+    async fn component(
+        &self,
+        _msg: Arc<dyn Message<impl Service>>,
+        _user: Arc<dyn User<impl Service>>,
+        _id: String,
+        _values: Vec<String>,
+        _interaction_id: InteractionId,
+    ) -> Result<()> {
+        Ok(())
+    }
+
+    async fn enabled(&self, _server_id: ServerId, _channel_id: ChannelId) -> Result<bool> {
+        Ok(self.is_enabled())
+    }
+
+    fn settings(&self) -> &Arc<TelemetryModuleSettings> {
+        &self.settings
+    }
+}
+
+impl TelemetryModule {
+    pub fn is_enabled(&self) -> bool {
+        self.config
+            .as_ref()
+            .map(|config| config.enabled)
+            .unwrap_or(false)
+    }
+
+    pub fn endpoint(&self) -> Option<&str> {
+        self.config.as_ref().map(|config| config.endpoint.as_str())
+    }
+
+    pub fn interval(&self) -> Duration {
+        Duration::from_secs(
+            self.config
+                .as_ref()
+                .and_then(|config| config.interval)
+                .unwrap_or(DEFAULT_INTERVAL)
+                .max(60),
+        )
+    }
+
+    pub fn count_command(&self) {
+        if self.is_enabled() {
+            self.commands_run.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    pub fn count_sandbox_termination(&self, reason: &'static str) {
+        if self.is_enabled() {
+            *self
+                .sandbox_terminations
+                .lock()
+                .unwrap()
+                .entry(reason)
+                .or_default() += 1;
+        }
+    }
+
+    /// The report as it would be sent now, shown to users asking what is collected
+    pub fn report(&self) -> TelemetryReport {
+        self.build_report(false)
+    }
+
+    fn build_report(&self, reset: bool) -> TelemetryReport {
+        let mut period_start = self.period_start.lock().unwrap();
+        let mut sandbox_terminations = self.sandbox_terminations.lock().unwrap();
+
+        let period = period_start.elapsed().as_secs();
+        let (commands_run, sandbox_terminations) = if reset {
+            *period_start = Instant::now();
+
+            (
+                self.commands_run.swap(0, Ordering::Relaxed),
+                std::mem::take(&mut *sandbox_terminations),
+            )
+        } else {
+            (
+                self.commands_run.load(Ordering::Relaxed),
+                sandbox_terminations.clone(),
+            )
+        };
+
+        let mut services = Vec::new();
+        if self.bot.config().services.discord.is_some() {
+            services.push("discord");
+        }
+        if self.bot.config().services.telegram.is_some() {
+            services.push("telegram");
+        }
+
+        TelemetryReport {
+            version: env!("CARGO_PKG_VERSION"),
+            period,
+            commands_run,
+            sandbox_terminations,
+            services,
+        }
+    }
+
+    async fn send_report(&self) -> Result<()> {
+        let endpoint = match self.endpoint() {
+            Some(endpoint) => endpoint,
+            None => return Ok(()),
+        };
+
+        let body = serde_json::to_vec(&self.build_report(true))?;
+
+        let https = HttpsConnector::new();
+        let client = Client::builder().build::<_, Body>(https);
+
+        let req = Request::builder()
+            .method("POST")
+            .uri(endpoint)
+            .header("Content-Type", "application/json")
+            .header("User-Agent", "kaito")
+            .body(Body::from(body))?;
+
+        let res = client.request(req).await?;
+
+        if !res.status().is_success() {
+            return Err(TelemetryError::EndpointError(res.status().as_u16()).into());
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum TelemetryError {
+    #[error("the telemetry endpoint responded with status {}", _0)]
+    EndpointError(u16),
+}
@@ -9,7 +9,9 @@ use super::{Module, ModuleKind};
 use crate::{
     bot::Bot,
     message::MessageSettings,
-    services::{Channel, ChannelId, Message, MessageId, Server, ServerId, Service, User},
+    services::{
+        Channel, ChannelId, InteractionId, Message, MessageId, Server, ServerId, Service, User,
+    },
     settings::prelude::*,
     utils::ci_regex,
 };
@@ -248,6 +250,17 @@ impl Module for UtilsModule {
         Ok(())
     }
 
+    async fn component(
+        &self,
+        _msg: Arc<dyn Message<impl Service>>,
+        _user: Arc<dyn User<impl Service>>,
+        _id: String,
+        _values: Vec<String>,
+        _interaction_id: InteractionId,
+    ) -> Result<()> {
+        Ok(())
+    }
+
     async fn enabled(&self, _server_id: ServerId, _channel_id: ChannelId) -> Result<bool> {
         Ok(true)
     }
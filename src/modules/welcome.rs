@@ -0,0 +1,241 @@
+use anyhow::{anyhow, Result};
+use graphicsmagick::{
+    types,
+    wand::{DrawingWand, MagickWand, PixelWand},
+};
+use std::sync::Arc;
+
+use super::{
+    lua::lib::{image::download_image, tags::render_template},
+    Module, ModuleKind,
+};
+use crate::{
+    bot::Bot,
+    message::MessageSettings,
+    services::{ChannelId, InteractionId, Message, MessageId, Server, ServerId, Service, User},
+    settings::prelude::*,
+};
+
+const CARD_WIDTH: u64 = 800;
+const CARD_HEIGHT: u64 = 250;
+const CARD_AVATAR_SIZE: u64 = 160;
+
+pub struct WelcomeModule {
+    bot: Arc<Bot>,
+    settings: Arc<WelcomeModuleSettings>,
+}
+
+settings! {
+    WelcomeModuleSettings,
+    WelcomeModule,
+    {
+        enable: bool => (false, SettingFlags::SERVER_OVERRIDE, "Greet members joining and leaving the server", []),
+        channel: String => ("".into(), SettingFlags::SERVER_OVERRIDE, "Channel to greet members in, as an id like discord:1234, empty to not post", [max_len => 64]),
+        join_message: String => ("Welcome {mention} to {server}!".into(), SettingFlags::SERVER_OVERRIDE, "Message for joining members, {name}, {nick}, {mention}, {id} and {server} are filled in", [max_len => 1000]),
+        leave_message: String => ("{nick} left {server}".into(), SettingFlags::SERVER_OVERRIDE, "Message for leaving members, empty to not post one", [max_len => 1000]),
+        dm: bool => (false, SettingFlags::SERVER_OVERRIDE, "Also send the welcome message to joining members directly", []),
+        card: bool => (false, SettingFlags::SERVER_OVERRIDE, "Attach an image card with the avatar of the member", [])
+    }
+}
+
+#[async_trait]
+impl Module for WelcomeModule {
+    const KIND: ModuleKind = ModuleKind::Welcome;
+    const ID: &'static str = "welcome";
+    const NAME: &'static str = "Welcome";
+
+    type ModuleConfig = ();
+    type ModuleSettings = WelcomeModuleSettings;
+
+    async fn load(bot: Arc<Bot>, _config: ()) -> Result<Arc<WelcomeModule>> {
+        Ok(Arc::new(WelcomeModule {
+            bot: bot.clone(),
+            settings: WelcomeModuleSettings::create(bot)?,
+        }))
+    }
+
+    async fn unload(&self) -> Result<()> {
+        Ok(())
+    }
+
+    async fn message(&self, _msg: Arc<dyn Message<impl Service>>) -> Result<()> {
+        Ok(())
+    }
+
+    async fn message_update(
+        &self,
+        _msg: Arc<dyn Message<impl Service>>,
+        _old_msg: Option<Arc<dyn Message<impl Service>>>,
+    ) -> Result<()> {
+        Ok(())
+    }
+
+    async fn message_delete(
+        &self,
+        _server_id: Option<ServerId>,
+        _channel_id: ChannelId,
+        _message_id: MessageId,
+    ) -> Result<()> {
+        Ok(())
+    }
+
+    async fn reaction(
+        &self,
+        _msg: Arc<dyn Message<impl Service>>,
+        _reactor: Arc<dyn User<impl Service>>,
+        _reaction: String,
+        _remove: bool,
+    ) -> Result<()> {
+        Ok(())
+    }
+
+    async fn component(
+        &self,
+        _msg: Arc<dyn Message<impl Service>>,
+        _user: Arc<dyn User<impl Service>>,
+        _id: String,
+        _values: Vec<String>,
+        _interaction_id: InteractionId,
+    ) -> Result<()> {
+        Ok(())
+    }
+
+    async fn member(
+        &self,
+        server_id: ServerId,
+        user: Arc<dyn User<impl Service>>,
+        joined: bool,
+    ) -> Result<()> {
+        if user.bot().unwrap_or(false) || !self.settings.enable.server_value(server_id).await? {
+            return Ok(());
+        }
+
+        let template = if joined {
+            self.settings.join_message.server_value(server_id).await?
+        } else {
+            self.settings.leave_message.server_value(server_id).await?
+        };
+
+        if template.is_empty() {
+            return Ok(());
+        }
+
+        let services = self.bot.get_ctx().services().clone();
+        let server = services.server(server_id).await?;
+
+        let content = render_template(
+            &template,
+            &[
+                ("name", user.name()),
+                ("nick", user.nick()),
+                ("mention", user.mention().as_str()),
+                ("id", user.id().to_str().as_str()),
+                ("server", server.name()),
+            ],
+        );
+
+        let mut settings = MessageSettings::default();
+
+        // Only ping members that are still around to see it
+        if joined {
+            settings.reply_user = Some(user.id());
+        }
+
+        if self.settings.card.server_value(server_id).await? {
+            let title = if joined {
+                format!("Welcome {}", user.nick())
+            } else {
+                format!("Goodbye {}", user.nick())
+            };
+
+            match create_card(user.avatar().clone(), title, server.name().to_string()).await {
+                Ok(card) => settings.attachments.push(("welcome.png".into(), card)),
+                Err(err) => println!("error creating welcome card: {}", err.to_string()),
+            }
+        }
+
+        let channel = self.settings.channel.server_value(server_id).await?;
+
+        if !channel.is_empty() {
+            services
+                .send_message(
+                    ChannelId::from_str(&channel)?,
+                    content.clone(),
+                    settings.clone(),
+                )
+                .await?;
+        }
+
+        // Members can have direct messages closed, which shouldn't be reported as an error
+        if joined && self.settings.dm.server_value(server_id).await? {
+            if let Err(err) = services.send_dm(user.id(), content, settings).await {
+                println!("unable to send welcome message: {}", err.to_string());
+            }
+        }
+
+        Ok(())
+    }
+
+    async fn enabled(&self, server_id: ServerId, channel_id: ChannelId) -> Result<bool> {
+        self.settings.enable.value(server_id, channel_id).await
+    }
+
+    fn settings(&self) -> &Arc<WelcomeModuleSettings> {
+        &self.settings
+    }
+}
+
+async fn create_card(avatar: Option<String>, title: String, subtitle: String) -> Result<Vec<u8>> {
+    // A missing avatar only leaves a gap on the card
+    let avatar = match avatar {
+        Some(avatar) => download_image(&url::Url::parse(avatar.trim())?).await.ok(),
+        None => None,
+    };
+
+    tokio::task::spawn_blocking(move || render_card(avatar, &title, &subtitle)).await?
+}
+
+fn render_card(avatar: Option<Vec<u8>>, title: &str, subtitle: &str) -> Result<Vec<u8>> {
+    let mut wand = MagickWand::new();
+
+    wand.set_size(CARD_WIDTH, CARD_HEIGHT)?;
+    wand.read_image("xc:#23272a")?;
+    wand.set_image_format("PNG")?;
+
+    let offset = ((CARD_HEIGHT - CARD_AVATAR_SIZE) / 2) as i64;
+
+    if let Some(avatar) = avatar {
+        let mut avatar_wand = MagickWand::new();
+
+        avatar_wand.read_image_blob(&avatar)?;
+        avatar_wand.resize_image(
+            CARD_AVATAR_SIZE,
+            CARD_AVATAR_SIZE,
+            types::FilterTypes::LanczosFilter,
+            1.0,
+        )?;
+        wand.composite_image(
+            &avatar_wand,
+            types::CompositeOperator::OverCompositeOp,
+            offset,
+            offset,
+        )?;
+    }
+
+    let text_x = (CARD_AVATAR_SIZE as i64 + offset * 2) as f64;
+    let mut draw = DrawingWand::new();
+
+    wand.draw_image(
+        &draw
+            .set_gravity(types::GravityType::WestGravity)
+            .set_fill_color(PixelWand::new().set_color("white"))
+            .set_font_size(44.0)
+            .annotation(text_x, -24.0, title)
+            .set_fill_color(PixelWand::new().set_color("#b9bbbe"))
+            .set_font_size(28.0)
+            .annotation(text_x, 30.0, subtitle),
+    )?;
+
+    wand.write_image_blob()
+        .ok_or_else(|| anyhow!("unable to write the welcome card"))
+}
@@ -0,0 +1,136 @@
+use anyhow::Result;
+use hyper::{
+    service::{make_service_fn, service_fn},
+    Body, Request, Response, StatusCode,
+};
+use std::{
+    collections::HashMap,
+    convert::Infallible,
+    env,
+    net::{SocketAddr, TcpListener},
+    os::unix::io::{AsRawFd, FromRawFd, RawFd},
+    sync::{Arc, Mutex, RwLock},
+};
+
+// Max 1MB for inbound request bodies
+pub const MAX_BODY_SIZE: usize = 1024 * 1024;
+
+#[async_trait]
+pub trait HttpHandler: Send + Sync {
+    async fn handle(&self, req: Request<Body>) -> Result<Response<Body>>;
+}
+
+pub struct HttpServer {
+    routes: RwLock<HashMap<String, Arc<dyn HttpHandler>>>,
+    listener_fd: Mutex<Option<RawFd>>,
+}
+
+impl HttpServer {
+    pub fn new() -> Arc<HttpServer> {
+        Arc::new(HttpServer {
+            routes: RwLock::new(HashMap::new()),
+            listener_fd: Mutex::new(None),
+        })
+    }
+
+    pub fn register(&self, path: &str, handler: Arc<dyn HttpHandler>) {
+        self.routes
+            .write()
+            .unwrap()
+            .insert(path.trim_end_matches('/').to_string(), handler);
+    }
+
+    pub fn unregister(&self, path: &str) {
+        self.routes
+            .write()
+            .unwrap()
+            .remove(path.trim_end_matches('/'));
+    }
+
+    async fn route(&self, req: Request<Body>) -> Response<Body> {
+        let handler = self
+            .routes
+            .read()
+            .unwrap()
+            .get(req.uri().path().trim_end_matches('/'))
+            .cloned();
+
+        match handler {
+            Some(handler) => match handler.handle(req).await {
+                Ok(res) => res,
+                Err(err) => {
+                    println!("error handling http request: {}", err.to_string());
+                    status_response(StatusCode::INTERNAL_SERVER_ERROR)
+                }
+            },
+            None => status_response(StatusCode::NOT_FOUND),
+        }
+    }
+
+    pub fn serve(self: Arc<Self>, addr: SocketAddr) -> Result<()> {
+        let server = self.clone();
+        let make_svc = make_service_fn(move |_conn| {
+            let server = server.clone();
+
+            async move {
+                Ok::<_, Infallible>(service_fn(move |req| {
+                    let server = server.clone();
+
+                    async move { Ok::<_, Infallible>(server.route(req).await) }
+                }))
+            }
+        });
+
+        // A restarted process picks up the listener of the old one, so no connections are refused
+        let listener = match env::var("KAITO_HTTP_FD").ok().and_then(|fd| fd.parse().ok()) {
+            Some(fd) => unsafe { TcpListener::from_raw_fd(fd) },
+            None => TcpListener::bind(addr)?,
+        };
+        env::remove_var("KAITO_HTTP_FD");
+
+        listener.set_nonblocking(true)?;
+        *self.listener_fd.lock().unwrap() = Some(listener.as_raw_fd());
+
+        let http_server = hyper::Server::from_tcp(listener)?.serve(make_svc);
+
+        tokio::spawn(async move {
+            if let Err(err) = http_server.await {
+                println!("http server error: {}", err.to_string());
+            }
+        });
+
+        println!("Listening for http requests on {}", addr);
+
+        Ok(())
+    }
+
+    pub fn listener_fd(&self) -> Option<RawFd> {
+        *self.listener_fd.lock().unwrap()
+    }
+}
+
+pub fn status_response(status: StatusCode) -> Response<Body> {
+    let mut res = Response::new(Body::from(status.canonical_reason().unwrap_or_default()));
+    *res.status_mut() = status;
+    res
+}
+
+pub async fn read_body(req: Request<Body>) -> Result<(hyper::http::request::Parts, Vec<u8>)> {
+    use futures::TryStreamExt;
+
+    let (parts, body) = req.into_parts();
+    let body = body
+        .map_err(|e: hyper::Error| e.into())
+        .try_fold(Vec::new(), |mut data, chunk| async move {
+            data.extend_from_slice(&chunk);
+
+            if data.len() > MAX_BODY_SIZE {
+                return Err(anyhow::anyhow!("max body size limit reached"));
+            }
+
+            Ok(data)
+        })
+        .await?;
+
+    Ok((parts, body))
+}
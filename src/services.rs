@@ -1,23 +1,29 @@
 use anyhow::{anyhow, Result};
 use serde::{Deserialize, Serialize};
-use std::{str::FromStr, sync::Arc, time::Duration};
+use std::{collections::HashMap, fmt, str::FromStr, sync::Arc, time::Duration};
+use thiserror::Error;
 
 pub mod discord;
+pub mod queue;
+pub mod supervisor;
+pub mod telegram;
 
 use crate::{
     bot::Bot,
     config::ConfigServices,
-    message::{Attachment, MessageSettings, ToMessageContent},
+    message::{
+        format::TextDialect, Attachment, MessageModal, MessageSettings, Sanitize, ToMessageContent,
+    },
+    utils::split_message,
 };
 
 macro_rules! service_id_functions {
     ($id:ident, $service_id:ident, $(($service_module_ident:ident, $service:ty)),+) => {
         #[allow(dead_code)]
         impl $id {
+            /// The stable encoding, like "discord:1234", ids of different services can't collide
             pub fn to_str(&self) -> String {
-                match self {
-                    $($id::$service_module_ident(id) => format!("{}:{}", <$service as Service>::ID, id)),+
-                }
+                self.to_string()
             }
 
             pub fn to_short_str(&self) -> String {
@@ -37,7 +43,7 @@ macro_rules! service_id_functions {
                                 let id = <$service as Service>::$service_id::from_str(after)?;
                                 return Ok($id::$service_module_ident(id));
                             },
-                        ),+
+                        )+
                         _ => return Err(anyhow!("unknown service \"{}\"", before))
                     }
                 }
@@ -51,6 +57,43 @@ macro_rules! service_id_functions {
                 }
             }
         }
+
+        impl fmt::Display for $id {
+            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                match self {
+                    $($id::$service_module_ident(id) => write!(f, "{}:{}", <$service as Service>::ID, id)),+
+                }
+            }
+        }
+
+        impl fmt::Debug for $id {
+            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                write!(f, "{}({})", stringify!($id), self)
+            }
+        }
+
+        // Parses both the full and the short service prefix
+        impl FromStr for $id {
+            type Err = anyhow::Error;
+
+            fn from_str(text: &str) -> Result<$id> {
+                $id::from_str(text)
+            }
+        }
+
+        impl Serialize for $id {
+            fn serialize<S: serde::Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+                serializer.collect_str(self)
+            }
+        }
+
+        impl<'de> Deserialize<'de> for $id {
+            fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> std::result::Result<$id, D::Error> {
+                let text = String::deserialize(deserializer)?;
+
+                $id::from_str(&text).map_err(serde::de::Error::custom)
+            }
+        }
     };
 }
 
@@ -74,7 +117,7 @@ macro_rules! services {
                 }))
             }
 
-            pub async fn send_message<'a, C>(&self, channel_id: ChannelId, content: C, settings: MessageSettings) -> Result<Arc<dyn Message<impl Service>>>
+            pub async fn send_message<'a, C>(&self, channel_id: ChannelId, content: C, settings: MessageSettings) -> Result<Arc<dyn MessageAbstract>>
             where
                 C: ToMessageContent<'a>
             {
@@ -90,12 +133,100 @@ macro_rules! services {
                                 .await?;
 
                             let msg: Arc<dyn Message<_>> = channel.send(content, settings).await?;
+                            let msg: Arc<dyn MessageAbstract> = Arc::new(msg);
                             Ok(msg)
                         }
                     ),+
                 }
             }
 
+            /// Send content as multiple messages if it doesn't fit in one, up to `max_parts` messages.
+            /// Content that needs more is sent as is, leaving it to the service to make it fit.
+            pub async fn send_split(&self, channel_id: ChannelId, content: String, settings: MessageSettings, max_parts: usize) -> Result<Vec<Arc<dyn MessageAbstract>>> {
+                match channel_id {
+                    $(
+                        ChannelId::$service_module_ident (id) => {
+                            let channel = self
+                                .$service_ident
+                                .as_ref()
+                                .ok_or(anyhow!("service {} has not been started", stringify!($service_module_ident)))?
+                                .service()
+                                .channel(id)
+                                .await?;
+
+                            let parts = split_message(
+                                &content,
+                                <$service as Service>::MAX_MESSAGE_LENGTH,
+                                <$service as Service>::MAX_MESSAGE_LINES,
+                                max_parts,
+                            )
+                            .unwrap_or_else(|| vec![content]);
+                            let last = parts.len() - 1;
+
+                            let mut messages = Vec::with_capacity(parts.len());
+                            for (i, part) in parts.into_iter().enumerate() {
+                                // Embeds, attachments and components go with the last part
+                                let part_settings = if i == last {
+                                    settings.clone()
+                                } else {
+                                    MessageSettings {
+                                        reply_user: settings.reply_user,
+                                        priority: settings.priority,
+                                        sanitize: settings.sanitize,
+                                        ..Default::default()
+                                    }
+                                };
+
+                                let msg: Arc<dyn Message<_>> = channel.send(part, part_settings).await?;
+                                messages.push(Arc::new(msg) as Arc<dyn MessageAbstract>);
+                            }
+
+                            Ok(messages)
+                        }
+                    ),+
+                }
+            }
+
+            /// Status of every started service, with its name
+            pub async fn status(&self) -> Result<Vec<(&'static str, ServiceStatus)>> {
+                let mut statuses = Vec::new();
+
+                $(
+                    if let Some(service) = self.$service_ident.as_ref() {
+                        statuses.push((<$service as Service>::NAME, service.service().status().await?));
+                    }
+                )+
+
+                Ok(statuses)
+            }
+
+            pub fn is_started(&self, id: &str) -> bool {
+                $(
+                    if id == <$service as Service>::ID {
+                        return self.$service_ident.is_some();
+                    }
+                )+
+
+                false
+            }
+
+            /// Restart the connection of a started service by id, like "discord"
+            pub async fn restart(&self, id: &str) -> Result<()> {
+                $(
+                    if id == <$service as Service>::ID {
+                        return self
+                            .$service_ident
+                            .as_ref()
+                            .ok_or(anyhow!("service {} has not been started", stringify!($service_module_ident)))?
+                            .service()
+                            .restart()
+                            .await;
+                    }
+                )+
+
+                Err(ServiceError::UnknownService(id.to_string()).into())
+            }
+
             pub async fn send_typing(&self, channel_id: ChannelId) -> Result<()> {
                 match channel_id {
                     $(
@@ -126,7 +257,7 @@ macro_rules! services {
                         ChannelId::$service_module_ident (id) => {
                             let message_id = match message_id {
                                 MessageId::$service_module_ident(msg_id) => msg_id,
-                                _ => unreachable!()
+                                _ => return Err(anyhow!("channel id and message id does not belong to the same service")),
                             };
 
                             self.$service_ident
@@ -146,7 +277,7 @@ macro_rules! services {
                         ChannelId::$service_module_ident (id) => {
                             let message_id = match message_id {
                                 MessageId::$service_module_ident(msg_id) => msg_id,
-                                _ => unreachable!()
+                                _ => return Err(anyhow!("channel id and message id does not belong to the same service")),
                             };
 
                             self.$service_ident
@@ -160,49 +291,70 @@ macro_rules! services {
             }
 
 
-            pub async fn user(&self, user_id: UserId) -> Result<Arc<dyn User<impl Service>>> {
+            pub async fn user(&self, user_id: UserId) -> Result<Arc<dyn UserAbstract>> {
                 match user_id {
                     $(
                         UserId::$service_module_ident(id) => {
-                            let user: Arc<<$service as Service>::User> = self.$service_ident.as_ref()
+                            let user: Arc<dyn User<$service>> = self.$service_ident.as_ref()
                             .ok_or(anyhow!("service {} has not been started", stringify!($service_module_ident)))?
                             .service()
                             .user(id)
                             .await?;
 
-                            Ok(user)
+                            Ok(Arc::new(user))
+                        }
+                    ),+
+                }
+            }
+
+            pub async fn send_dm<'a, C>(&self, user_id: UserId, content: C, settings: MessageSettings) -> Result<Arc<dyn MessageAbstract>>
+            where
+                C: ToMessageContent<'a>
+            {
+                match user_id {
+                    $(
+                        UserId::$service_module_ident(id) => {
+                            let channel = self.$service_ident.as_ref()
+                            .ok_or(anyhow!("service {} has not been started", stringify!($service_module_ident)))?
+                            .service()
+                            .dm_channel(id)
+                            .await?;
+
+                            let msg: Arc<dyn Message<_>> = channel.send(content, settings).await?;
+                            let msg: Arc<dyn MessageAbstract> = Arc::new(msg);
+                            Ok(msg)
                         }
                     ),+
                 }
             }
 
-            pub async fn channel(&self, channel_id: ChannelId) -> Result<Arc<dyn Channel<impl Service>>> {
+            pub async fn channel(&self, channel_id: ChannelId) -> Result<Arc<dyn ChannelAbstract>> {
                 match channel_id {
                     $(
                         ChannelId::$service_module_ident(id) => {
-                            let channel: Arc<<$service as Service>::Channel> = self.$service_ident.as_ref()
+                            let channel: Arc<dyn Channel<$service>> = self.$service_ident.as_ref()
                             .ok_or(anyhow!("service {} has not been started", stringify!($service_module_ident)))?
                             .service()
                             .channel(id)
                             .await?;
 
-                            Ok(channel)
+                            Ok(Arc::new(channel))
                         }
                     ),+
                 }
             }
 
-            pub async fn server(&self, server_id: ServerId) -> Result<Arc<dyn Server<impl Service>>> {
+            pub async fn server(&self, server_id: ServerId) -> Result<Arc<dyn ServerAbstract>> {
                 match server_id {
                     $(
                         ServerId::$service_module_ident(id) => {
-                            let server: Arc<<$service as Service>::Server> = self.$service_ident.as_ref()
+                            let server: Arc<dyn Server<$service>> = self.$service_ident.as_ref()
                             .ok_or(anyhow!("service {} has not been started", stringify!($service_module_ident)))?
                             .service()
                             .server(id)
                             .await?;
 
-                            Ok(server)
+                            Ok(Arc::new(server))
                         }
                     ),+
                 }
@@ -243,17 +395,17 @@ macro_rules! services {
             }
 
             #[allow(unreachable_patterns)]
-            pub async fn message(&self, channel_id: ChannelId, message_id: MessageId) -> Result<Arc<dyn Message<impl Service>>> {
+            pub async fn message(&self, channel_id: ChannelId, message_id: MessageId) -> Result<Arc<dyn MessageAbstract>> {
                 match (channel_id, message_id) {
                     $(
                         (ChannelId::$service_module_ident(chan_id), MessageId::$service_module_ident(msg_id)) => {
-                            let channel: Arc<<$service as Service>::Message> = self.$service_ident.as_ref()
+                            let message: Arc<dyn Message<$service>> = self.$service_ident.as_ref()
                             .ok_or(anyhow!("service {} has not been started", stringify!($service_module_ident)))?
                             .service()
                             .message(chan_id, msg_id)
                             .await?;
 
-                            Ok(channel)
+                            Ok(Arc::new(message))
                         },
                     )+
                     _ => Err(anyhow::anyhow!("channel id and message id does not belong to the same service"))
@@ -261,7 +413,7 @@ macro_rules! services {
             }
 
             #[allow(unreachable_patterns)]
-            pub async fn find_user(&self, channel_id: ChannelId, find: &str) -> Result<Arc<dyn User<impl Service>>> {
+            pub async fn find_user(&self, channel_id: ChannelId, find: &str) -> Result<Arc<dyn UserAbstract>> {
                 if let Some(sep) = find.find(':') {
                     let (before, after) = find.split_at(sep);
                     let after = &after[1..];
@@ -269,35 +421,104 @@ macro_rules! services {
                     match before {
                         $(
                             <$service as Service>::ID | <$service as Service>::ID_SHORT => {
-                                let user = self.$service_ident
+                                let channel_id = match channel_id {
+                                    ChannelId::$service_module_ident(id) => id,
+                                    _ => return Err(ServiceError::UnknownUser(find.to_string()).into()),
+                                };
+
+                                let user: Arc<dyn User<$service>> = self.$service_ident
                                 .as_ref()
                                 .ok_or(anyhow!("service {} has not been started", stringify!($service_module_ident)))?
                                 .service()
-                                    .find_user(match channel_id {
-                                          ChannelId::$service_module_ident(id) => id,
-                                        _ => panic!()
-                                    }, after).await?;
+                                .find_user(channel_id, after)
+                                .await?;
 
-                                return Ok(user)
+                                return Ok(Arc::new(user))
                             },
-                        ),+
+                        )+
                         _ => {}
                     }
                 }
 
-                Ok(
-                    match channel_id {
-                        $(
-                            ChannelId::$service_module_ident(id) => self
+                match channel_id {
+                    $(
+                        ChannelId::$service_module_ident(id) => {
+                            let user: Arc<dyn User<$service>> = self
                             .$service_ident
+                            .as_ref()
+                            .ok_or(anyhow!("service {} has not been started", stringify!($service_module_ident)))?
+                            .service()
+                            .find_user(id, find)
+                            .await?;
+
+                            Ok(Arc::new(user))
+                        },
+                    )+
+                }
+            }
+
+            pub async fn find_channel(&self, channel_id: ChannelId, find: &str) -> Result<Arc<dyn ChannelAbstract>> {
+                if let Some(sep) = find.find(':') {
+                    let (before, after) = find.split_at(sep);
+                    let after = &after[1..];
+
+                    match before {
+                        $(
+                            <$service as Service>::ID | <$service as Service>::ID_SHORT => {
+                                let channel_id = match channel_id {
+                                    ChannelId::$service_module_ident(id) => id,
+                                    _ => return Err(ServiceError::UnknownChannel(find.to_string()).into()),
+                                };
+
+                                let channel: Arc<dyn Channel<$service>> = self.$service_ident
                                 .as_ref()
                                 .ok_or(anyhow!("service {} has not been started", stringify!($service_module_ident)))?
                                 .service()
-                            .find_user(id, find)
-                            .await?,
-                        ),+
+                                .find_channel(channel_id, after)
+                                .await?;
+
+                                return Ok(Arc::new(channel))
+                            },
+                        )+
+                        _ => {}
                     }
-                )
+                }
+
+                match channel_id {
+                    $(
+                        ChannelId::$service_module_ident(id) => {
+                            let channel: Arc<dyn Channel<$service>> = self
+                            .$service_ident
+                            .as_ref()
+                            .ok_or(anyhow!("service {} has not been started", stringify!($service_module_ident)))?
+                            .service()
+                            .find_channel(id, find)
+                            .await?;
+
+                            Ok(Arc::new(channel))
+                        },
+                    )+
+                }
+            }
+
+            #[allow(unreachable_patterns)]
+            pub async fn user_permissions(&self, channel_id: ChannelId, user_id: UserId) -> Result<UserPermissions> {
+                match channel_id {
+                    $(
+                        ChannelId::$service_module_ident(channel_id) => {
+                            let user_id = match user_id {
+                                UserId::$service_module_ident(user_id) => user_id,
+                                _ => return Ok(UserPermissions::empty())
+                            };
+
+                            self.$service_ident
+                                .as_ref()
+                                .ok_or(anyhow!("service {} has not been started", stringify!($service_module_ident)))?
+                                .service()
+                                .user_permissions(channel_id, user_id).await
+                        }
+                    ),+
+                }
             }
 
             #[allow(unreachable_patterns)]
@@ -307,7 +528,7 @@ macro_rules! services {
                         ChannelId::$service_module_ident(channel_id) => {
                             let message_id = match message_id {
                                 MessageId::$service_module_ident(msg_id) => msg_id,
-                                _ => unreachable!()
+                                _ => return Err(anyhow!("channel id and message id does not belong to the same service")),
                             };
 
                             self.$service_ident
@@ -320,18 +541,195 @@ macro_rules! services {
                 }
             }
 
+            pub async fn scheduled_events(&self, server_id: ServerId) -> Result<Vec<ScheduledEvent>> {
+                match server_id {
+                    $(
+                        ServerId::$service_module_ident(id) => {
+                            self.$service_ident
+                                .as_ref()
+                                .ok_or(anyhow!("service {} has not been started", stringify!($service_module_ident)))?
+                                .service()
+                                .server(id)
+                                .await?
+                                .scheduled_events()
+                                .await
+                        }
+                    ),+
+                }
+            }
+
+            pub async fn create_scheduled_event(&self, server_id: ServerId, event: ScheduledEventSettings) -> Result<ScheduledEvent> {
+                match server_id {
+                    $(
+                        ServerId::$service_module_ident(id) => {
+                            self.$service_ident
+                                .as_ref()
+                                .ok_or(anyhow!("service {} has not been started", stringify!($service_module_ident)))?
+                                .service()
+                                .server(id)
+                                .await?
+                                .create_scheduled_event(event)
+                                .await
+                        }
+                    ),+
+                }
+            }
+
+            /// Upload a custom emoji, resolving to the emoji as it is written in messages
+            pub async fn create_emoji(&self, server_id: ServerId, name: String, image: UploadImage) -> Result<String> {
+                match server_id {
+                    $(
+                        ServerId::$service_module_ident(id) => {
+                            self.$service_ident
+                                .as_ref()
+                                .ok_or(anyhow!("service {} has not been started", stringify!($service_module_ident)))?
+                                .service()
+                                .server(id)
+                                .await?
+                                .create_emoji(name, image)
+                                .await
+                        }
+                    ),+
+                }
+            }
+
+            /// Upload a sticker, resolving to the sticker id
+            pub async fn create_sticker(&self, server_id: ServerId, sticker: StickerSettings) -> Result<String> {
+                match server_id {
+                    $(
+                        ServerId::$service_module_ident(id) => {
+                            self.$service_ident
+                                .as_ref()
+                                .ok_or(anyhow!("service {} has not been started", stringify!($service_module_ident)))?
+                                .service()
+                                .server(id)
+                                .await?
+                                .create_sticker(sticker)
+                                .await
+                        }
+                    ),+
+                }
+            }
+
+            pub async fn server_roles(&self, server_id: ServerId) -> Result<Vec<ServerRole>> {
+                match server_id {
+                    $(
+                        ServerId::$service_module_ident(id) => {
+                            self.$service_ident
+                                .as_ref()
+                                .ok_or(anyhow!("service {} has not been started", stringify!($service_module_ident)))?
+                                .service()
+                                .server(id)
+                                .await?
+                                .roles()
+                                .await
+                        }
+                    ),+
+                }
+            }
+
+            /// Give or take away a role of a server member
+            pub async fn set_member_role(&self, server_id: ServerId, user_id: UserId, role_id: &str, has_role: bool) -> Result<()> {
+                match (server_id, user_id) {
+                    $(
+                        (ServerId::$service_module_ident(server_id), UserId::$service_module_ident(user_id)) => {
+                            let server: Arc<<$service as Service>::Server> = self.$service_ident.as_ref()
+                                .ok_or(anyhow!("service {} has not been started", stringify!($service_module_ident)))?
+                                .service()
+                                .server(server_id)
+                                .await?;
+
+                            server.set_member_role(user_id, role_id, has_role).await
+                        },
+                    )+
+                    _ => Err(anyhow::anyhow!("server id and user id does not belong to the same service"))
+                }
+            }
+
+            pub async fn moderate(&self, server_id: ServerId, user_id: UserId, action: ModerationAction) -> Result<()> {
+                match (server_id, user_id) {
+                    $(
+                        (ServerId::$service_module_ident(server_id), UserId::$service_module_ident(user_id)) => {
+                            let server: Arc<<$service as Service>::Server> = self.$service_ident.as_ref()
+                                .ok_or(anyhow!("service {} has not been started", stringify!($service_module_ident)))?
+                                .service()
+                                .server(server_id)
+                                .await?;
+
+                            server.moderate(user_id, action).await
+                        },
+                    )+
+                    _ => Err(anyhow::anyhow!("server id and user id does not belong to the same service"))
+                }
+            }
+
+            /// Delete up to `count` of the latest messages in a channel, returning how many were deleted
+            pub async fn purge(&self, channel_id: ChannelId, count: u64) -> Result<usize> {
+                match channel_id {
+                    $(
+                        ChannelId::$service_module_ident(id) => {
+                            self.$service_ident
+                                .as_ref()
+                                .ok_or(anyhow!("service {} has not been started", stringify!($service_module_ident)))?
+                                .service()
+                                .channel(id)
+                                .await?
+                                .purge(count)
+                                .await
+                        }
+                    ),+
+                }
+            }
+
+            /// Create a post in a forum channel, the post is a channel of its own
+            pub async fn create_forum_post(&self, channel_id: ChannelId, post: ForumPost) -> Result<ChannelId> {
+                match channel_id {
+                    $(
+                        ChannelId::$service_module_ident(id) => {
+                            let post = self
+                                .$service_ident
+                                .as_ref()
+                                .ok_or(anyhow!("service {} has not been started", stringify!($service_module_ident)))?
+                                .service()
+                                .channel(id)
+                                .await?
+                                .create_post(post)
+                                .await?;
+
+                            Ok(post.id())
+                        }
+                    ),+
+                }
+            }
+
+            /// Show a modal in response to an interaction, resolving to the submitted values by field id
+            pub async fn open_modal(&self, interaction_id: InteractionId, modal: MessageModal) -> Result<HashMap<String, String>> {
+                match interaction_id {
+                    $(
+                        InteractionId::$service_module_ident(id) => {
+                            self.$service_ident
+                                .as_ref()
+                                .ok_or(anyhow!("service {} has not been started", stringify!($service_module_ident)))?
+                                .service()
+                                .open_modal(id, modal)
+                                .await
+                        }
+                    ),+
+                }
+            }
+
             #[allow(unreachable_patterns)]
-            pub async fn join_voice(&self, server_id: ServerId, channel_id: ChannelId) -> Result<Arc<dyn VoiceConnection<impl Service>>> {
+            pub async fn join_voice(&self, server_id: ServerId, channel_id: ChannelId) -> Result<Arc<dyn VoiceConnectionAbstract>> {
                 match (server_id, channel_id) {
                     $(
                         (ServerId::$service_module_ident(server_id), ChannelId::$service_module_ident(channel_id)) => {
-                            let voice_connection: Arc<<$service as Service>::VoiceConnection> = self.$service_ident.as_ref()
+                            let voice_connection: Arc<dyn VoiceConnection<$service>> = self.$service_ident.as_ref()
                             .ok_or(anyhow!("service {} has not been started", stringify!($service_module_ident)))?
                             .service()
                             .join_voice(server_id, channel_id)
                             .await?;
 
-                            Ok(voice_connection)
+                            Ok(Arc::new(voice_connection))
                         },
                     )+
                     _ => Err(anyhow::anyhow!("server id and channel id does not belong to the same service"))
@@ -374,6 +772,13 @@ macro_rules! services {
 
         service_id_functions!{UserId, UserId, $(($service_module_ident, $service)),+}
 
+        #[derive(Copy, Clone, Hash, Eq, PartialEq)]
+        pub enum InteractionId {
+            $($service_module_ident (<$service as Service>::InteractionId)),+
+        }
+
+        service_id_functions!{InteractionId, InteractionId, $(($service_module_ident, $service)),+}
+
         #[derive(Copy, Clone, Hash, PartialEq)]
         pub enum ServiceKind {
             $($service_module_ident),+
@@ -395,6 +800,22 @@ macro_rules! services {
                     ),+
                 }
             }
+
+            pub fn text_dialect(&self) -> TextDialect {
+                match self {
+                    $(
+                        ServiceKind::$service_module_ident => <$service as Service>::TEXT_DIALECT
+                    ),+
+                }
+            }
+
+            pub fn parse_content(&self, content: &str) -> Vec<ContentSegment> {
+                match self {
+                    $(
+                        ServiceKind::$service_module_ident => <$service as Service>::parse_content(content)
+                    ),+
+                }
+            }
         }
 
         $(
@@ -420,6 +841,11 @@ pub trait Service: 'static + Sized + Send + Sync {
     const ID_SHORT: &'static str;
     const NAME: &'static str;
     const FEATURES: ServiceFeatures;
+    /// Longest message content that can be sent, in characters and lines
+    const MAX_MESSAGE_LENGTH: usize;
+    const MAX_MESSAGE_LINES: usize;
+    /// How rich text is written in messages sent to the service
+    const TEXT_DIALECT: TextDialect;
 
     type ServiceConfig: Clone + Deserialize<'static> + Serialize + std::fmt::Debug;
     type Message: Message<Self>;
@@ -432,6 +858,7 @@ pub trait Service: 'static + Sized + Send + Sync {
     type ChannelId: Send + Sync;
     type ServerId: Send + Sync;
     type UserId: Send + Sync;
+    type InteractionId: Send + Sync;
 
     async fn init(bot: Arc<Bot>, config: Self::ServiceConfig) -> Result<Arc<Self>>;
     async fn unload(&self) -> Result<()>;
@@ -445,25 +872,50 @@ pub trait Service: 'static + Sized + Send + Sync {
     async fn server(self: &Arc<Self>, id: Self::ServerId) -> Result<Arc<Self::Server>>;
     async fn channel(self: &Arc<Self>, id: Self::ChannelId) -> Result<Arc<Self::Channel>>;
     async fn user(self: &Arc<Self>, get_user: Self::UserId) -> Result<Arc<Self::User>>;
+    async fn dm_channel(self: &Arc<Self>, user: Self::UserId) -> Result<Arc<Self::Channel>>;
     async fn find_user(
         self: &Arc<Self>,
         channel_id: Self::ChannelId,
         find: &str,
     ) -> Result<Arc<Self::User>>;
+    /// Channel by mention, id or name, names are looked up in the server of `channel_id`
+    async fn find_channel(
+        self: &Arc<Self>,
+        channel_id: Self::ChannelId,
+        find: &str,
+    ) -> Result<Arc<Self::Channel>>;
+
+    /// What the user is allowed to do in the channel
+    async fn user_permissions(
+        self: &Arc<Self>,
+        channel_id: Self::ChannelId,
+        user_id: Self::UserId,
+    ) -> Result<UserPermissions>;
 
     async fn react(
         self: &Arc<Self>,
-        channel_id: Self::MessageId,
+        channel_id: Self::ChannelId,
         msg_id: Self::MessageId,
         reaction: String,
     ) -> Result<()>;
 
+    async fn open_modal(
+        self: &Arc<Self>,
+        interaction_id: Self::InteractionId,
+        modal: MessageModal,
+    ) -> Result<HashMap<String, String>>;
+
     async fn join_voice(
         &self,
-        server_id: Self::ChannelId,
+        server_id: Self::ServerId,
         channel_id: Self::ChannelId,
     ) -> Result<Arc<Self::VoiceConnection>>;
 
+    async fn status(self: &Arc<Self>) -> Result<ServiceStatus>;
+
+    /// Reconnect to the service without unloading it, queued outgoing messages are kept
+    async fn restart(self: &Arc<Self>) -> Result<()>;
+
     fn kind(&self) -> ServiceKind {
         Self::KIND
     }
@@ -471,6 +923,32 @@ pub trait Service: 'static + Sized + Send + Sync {
     fn supports_feature(feature: ServiceFeatures) -> bool {
         Self::FEATURES.contains(feature)
     }
+
+    /// Split message content into plain text and the markup of the service
+    fn parse_content(content: &str) -> Vec<ContentSegment> {
+        vec![ContentSegment::Text(content.into())]
+    }
+}
+
+/// Part of the content of a message
+#[derive(Clone, Debug, PartialEq)]
+pub enum ContentSegment {
+    Text(String),
+    UserMention(UserId),
+    ChannelMention(ChannelId),
+    /// Custom emoji of the service, unicode emoji stay in the text
+    Emoji {
+        name: String,
+        id: String,
+        animated: bool,
+    },
+    Link(String),
+    Code {
+        code: String,
+        language: Option<String>,
+        /// Multi line code blocks, as opposed to inline code
+        block: bool,
+    },
 }
 
 bitflags! {
@@ -480,9 +958,42 @@ bitflags! {
         const REACT = 1 << 2;
         const VOICE = 1 << 3;
         const MARKDOWN = 1 << 4;
+        const COMPONENTS = 1 << 5;
+        const MODALS = 1 << 6;
+        const SCHEDULED_EVENTS = 1 << 7;
+        const FORUMS = 1 << 8;
     }
 }
 
+bitflags! {
+    /// Permissions of a user in a channel, normalized over the services
+    pub struct UserPermissions: u32 {
+        const ADMIN = 1;
+        const MANAGE_MESSAGES = 1 << 1;
+        const MANAGE_CHANNELS = 1 << 2;
+        const MANAGE_SERVER = 1 << 3;
+        const MANAGE_ROLES = 1 << 4;
+        const KICK_MEMBERS = 1 << 5;
+        const BAN_MEMBERS = 1 << 6;
+        const TIMEOUT_MEMBERS = 1 << 7;
+        const MENTION_EVERYONE = 1 << 8;
+    }
+}
+
+impl UserPermissions {
+    pub const NAMES: &'static [(&'static str, UserPermissions)] = &[
+        ("admin", UserPermissions::ADMIN),
+        ("manage_messages", UserPermissions::MANAGE_MESSAGES),
+        ("manage_channels", UserPermissions::MANAGE_CHANNELS),
+        ("manage_server", UserPermissions::MANAGE_SERVER),
+        ("manage_roles", UserPermissions::MANAGE_ROLES),
+        ("kick_members", UserPermissions::KICK_MEMBERS),
+        ("ban_members", UserPermissions::BAN_MEMBERS),
+        ("timeout_members", UserPermissions::TIMEOUT_MEMBERS),
+        ("mention_everyone", UserPermissions::MENTION_EVERYONE),
+    ];
+}
+
 #[async_trait]
 pub trait Message<S: Service>: Send + Sync {
     fn author(&self) -> &Arc<S::User>;
@@ -494,6 +1005,10 @@ pub trait Message<S: Service>: Send + Sync {
     async fn delete(&self) -> Result<()>;
     fn content(&self) -> &str;
     fn attachments(&self) -> &[Arc<Attachment>];
+    /// Message this one is a reply to
+    fn reply_to(&self) -> Option<MessageId>;
+    /// Link that jumps to the message in the client
+    fn link(&self) -> Option<String>;
     fn service(&self) -> &Arc<S>;
     fn id(&self) -> MessageId;
 }
@@ -502,6 +1017,7 @@ pub trait User<S: Service>: Send + Sync {
     fn id(&self) -> UserId;
     fn name(&self) -> &str;
     fn nick(&self) -> &str;
+    fn mention(&self) -> String;
     fn avatar(&self) -> &Option<String> {
         &None
     }
@@ -526,9 +1042,28 @@ pub trait Channel<S: Service>: Send + Sync {
         C: ToMessageContent<'a>;
     async fn server(&self) -> Result<Arc<S::Server>>;
     async fn send_typing(&self) -> Result<()>;
+    /// Tags available for posts, `None` if the channel is not a forum
+    fn forum_tags(&self) -> Option<Vec<ForumTag>>;
+    async fn create_post(&self, post: ForumPost) -> Result<Arc<S::Channel>>;
+    async fn purge(&self, count: u64) -> Result<usize>;
     fn service(&self) -> &Arc<S>;
 }
 
+#[derive(Clone)]
+pub struct ForumTag {
+    pub id: String,
+    pub name: String,
+}
+
+/// A new post in a forum channel, tags are matched by name
+#[derive(Clone, Default)]
+pub struct ForumPost {
+    pub name: String,
+    pub content: String,
+    pub tags: Vec<String>,
+    pub sanitize: Sanitize,
+}
+
 #[async_trait]
 pub trait Server<S: Service>: Send + Sync {
     fn id(&self) -> ServerId;
@@ -536,6 +1071,72 @@ pub trait Server<S: Service>: Send + Sync {
     fn service(&self) -> &Arc<S>;
     async fn voice_user_channel(&self, user: S::UserId) -> Result<Option<ChannelId>>;
     async fn voice_channel_users(&self, channel: S::ChannelId) -> Result<Vec<UserId>>;
+    async fn scheduled_events(&self) -> Result<Vec<ScheduledEvent>>;
+    async fn create_scheduled_event(&self, event: ScheduledEventSettings)
+        -> Result<ScheduledEvent>;
+    async fn create_emoji(&self, name: String, image: UploadImage) -> Result<String>;
+    async fn create_sticker(&self, sticker: StickerSettings) -> Result<String>;
+    async fn roles(&self) -> Result<Vec<ServerRole>>;
+    async fn set_member_role(&self, user: S::UserId, role_id: &str, has_role: bool) -> Result<()>;
+    async fn moderate(&self, user: S::UserId, action: ModerationAction) -> Result<()>;
+}
+
+#[derive(Clone, Debug)]
+pub enum ModerationAction {
+    Kick {
+        reason: Option<String>,
+    },
+    Ban {
+        reason: Option<String>,
+    },
+    Unban,
+    /// Stop the member from talking until the unix timestamp, `None` lifts the timeout
+    Timeout { until: Option<i64> },
+}
+
+#[derive(Clone)]
+pub struct ServerRole {
+    pub id: String,
+    pub name: String,
+}
+
+/// Validated image data for custom emoji and stickers
+#[derive(Clone)]
+pub struct UploadImage {
+    pub data: Vec<u8>,
+    pub format: String,
+}
+
+#[derive(Clone)]
+pub struct StickerSettings {
+    pub name: String,
+    pub description: String,
+    pub tags: String,
+    pub image: UploadImage,
+}
+
+/// An event planned on a server, times are unix timestamps
+#[derive(Clone)]
+pub struct ScheduledEvent {
+    pub id: String,
+    pub name: String,
+    pub description: Option<String>,
+    pub start_time: i64,
+    pub end_time: Option<i64>,
+    pub location: Option<String>,
+    pub channel_id: Option<ChannelId>,
+    pub user_count: Option<u64>,
+}
+
+/// Events take place either in a voice channel or at an external location
+#[derive(Clone)]
+pub struct ScheduledEventSettings {
+    pub name: String,
+    pub description: Option<String>,
+    pub start_time: i64,
+    pub end_time: Option<i64>,
+    pub location: Option<String>,
+    pub channel_id: Option<ChannelId>,
 }
 
 #[async_trait]
@@ -621,6 +1222,216 @@ where
     }
 }
 
+/// A message of any service, returned by the `Services` router
+#[async_trait]
+pub trait MessageAbstract: Send + Sync {
+    fn id(&self) -> MessageId;
+    fn author(&self) -> Arc<dyn UserAbstract>;
+    async fn channel(&self) -> Result<Arc<dyn ChannelAbstract>>;
+    fn content(&self) -> &str;
+    fn attachments(&self) -> &[Arc<Attachment>];
+    fn reply_to(&self) -> Option<MessageId>;
+    fn link(&self) -> Option<String>;
+    fn kind(&self) -> ServiceKind;
+}
+
+#[async_trait]
+impl<S> MessageAbstract for Arc<dyn Message<S>>
+where
+    S: Service,
+{
+    fn id(&self) -> MessageId {
+        let inner: &dyn Message<S> = self.as_ref();
+        inner.id()
+    }
+
+    fn author(&self) -> Arc<dyn UserAbstract> {
+        let inner: &dyn Message<S> = self.as_ref();
+        let author: Arc<dyn User<S>> = inner.author().clone();
+        Arc::new(author)
+    }
+
+    async fn channel(&self) -> Result<Arc<dyn ChannelAbstract>> {
+        let inner: &dyn Message<S> = self.as_ref();
+        let channel: Arc<dyn Channel<S>> = inner.channel().await?;
+        Ok(Arc::new(channel))
+    }
+
+    fn content(&self) -> &str {
+        let inner: &dyn Message<S> = self.as_ref();
+        inner.content()
+    }
+
+    fn attachments(&self) -> &[Arc<Attachment>] {
+        let inner: &dyn Message<S> = self.as_ref();
+        inner.attachments()
+    }
+
+    fn reply_to(&self) -> Option<MessageId> {
+        let inner: &dyn Message<S> = self.as_ref();
+        inner.reply_to()
+    }
+
+    fn link(&self) -> Option<String> {
+        let inner: &dyn Message<S> = self.as_ref();
+        inner.link()
+    }
+
+    fn kind(&self) -> ServiceKind {
+        S::KIND
+    }
+}
+
+pub trait UserAbstract: Send + Sync {
+    fn id(&self) -> UserId;
+    fn name(&self) -> &str;
+    fn nick(&self) -> &str;
+    fn mention(&self) -> String;
+    fn avatar(&self) -> &Option<String>;
+    fn bot(&self) -> Option<bool>;
+    fn kind(&self) -> ServiceKind;
+}
+
+impl<S> UserAbstract for Arc<dyn User<S>>
+where
+    S: Service,
+{
+    fn id(&self) -> UserId {
+        let inner: &dyn User<S> = self.as_ref();
+        inner.id()
+    }
+
+    fn name(&self) -> &str {
+        let inner: &dyn User<S> = self.as_ref();
+        inner.name()
+    }
+
+    fn nick(&self) -> &str {
+        let inner: &dyn User<S> = self.as_ref();
+        inner.nick()
+    }
+
+    fn mention(&self) -> String {
+        let inner: &dyn User<S> = self.as_ref();
+        inner.mention()
+    }
+
+    fn avatar(&self) -> &Option<String> {
+        let inner: &dyn User<S> = self.as_ref();
+        inner.avatar()
+    }
+
+    fn bot(&self) -> Option<bool> {
+        let inner: &dyn User<S> = self.as_ref();
+        inner.bot()
+    }
+
+    fn kind(&self) -> ServiceKind {
+        S::KIND
+    }
+}
+
+#[async_trait]
+pub trait ChannelAbstract: Send + Sync {
+    fn id(&self) -> ChannelId;
+    fn name(&self) -> String;
+    /// The latest messages of the channel
+    async fn messages(&self, limit: u64) -> Result<Vec<Arc<dyn MessageAbstract>>>;
+    async fn server(&self) -> Result<Arc<dyn ServerAbstract>>;
+    fn forum_tags(&self) -> Option<Vec<ForumTag>>;
+    fn kind(&self) -> ServiceKind;
+}
+
+#[async_trait]
+impl<S> ChannelAbstract for Arc<dyn Channel<S>>
+where
+    S: Service,
+{
+    fn id(&self) -> ChannelId {
+        let inner: &dyn Channel<S> = self.as_ref();
+        inner.id()
+    }
+
+    fn name(&self) -> String {
+        let inner: &dyn Channel<S> = self.as_ref();
+        inner.name()
+    }
+
+    async fn messages(&self, limit: u64) -> Result<Vec<Arc<dyn MessageAbstract>>> {
+        let inner: &dyn Channel<S> = self.as_ref();
+        let messages = inner.messages(limit, None).await?;
+
+        Ok(messages
+            .into_iter()
+            .map(|msg| {
+                let msg: Arc<dyn Message<S>> = msg;
+                Arc::new(msg) as Arc<dyn MessageAbstract>
+            })
+            .collect())
+    }
+
+    async fn server(&self) -> Result<Arc<dyn ServerAbstract>> {
+        let inner: &dyn Channel<S> = self.as_ref();
+        let server: Arc<dyn Server<S>> = inner.server().await?;
+        Ok(Arc::new(server))
+    }
+
+    fn forum_tags(&self) -> Option<Vec<ForumTag>> {
+        let inner: &dyn Channel<S> = self.as_ref();
+        inner.forum_tags()
+    }
+
+    fn kind(&self) -> ServiceKind {
+        S::KIND
+    }
+}
+
+pub trait ServerAbstract: Send + Sync {
+    fn id(&self) -> ServerId;
+    fn name(&self) -> &str;
+    fn kind(&self) -> ServiceKind;
+}
+
+impl<S> ServerAbstract for Arc<dyn Server<S>>
+where
+    S: Service,
+{
+    fn id(&self) -> ServerId {
+        let inner: &dyn Server<S> = self.as_ref();
+        inner.id()
+    }
+
+    fn name(&self) -> &str {
+        let inner: &dyn Server<S> = self.as_ref();
+        inner.name()
+    }
+
+    fn kind(&self) -> ServiceKind {
+        S::KIND
+    }
+}
+
+/// Health of the connection to a service
+pub struct ServiceStatus {
+    /// Time since the connection was established, none while disconnected
+    pub uptime: Option<Duration>,
+    pub reconnects: u32,
+    /// Consecutive failed connection attempts
+    pub failed_attempts: u32,
+    /// Messages waiting to be sent
+    pub backlog: usize,
+    /// Messages sent since the service started
+    pub sent: u64,
+    pub shards: Vec<ShardStatus>,
+}
+
+pub struct ShardStatus {
+    pub id: u64,
+    pub stage: String,
+    /// Time between the last heartbeat and its acknowledgement
+    pub latency: Option<Duration>,
+}
+
 pub struct ServiceWrapper<S: Service> {
     service: Arc<S>,
 }
@@ -638,5 +1449,31 @@ impl<S: Service> ServiceWrapper<S> {
 
 services! {
     Services,
-    discord => (Discord, discord::DiscordService)
+    discord => (Discord, discord::DiscordService),
+    telegram => (Telegram, telegram::TelegramService)
+}
+
+/// Errors returned by services, classified so callers can tell them apart
+#[derive(Debug, Error)]
+pub enum ServiceError {
+    #[error("not found")]
+    NotFound,
+    #[error("unable to find user \"{}\"", _0)]
+    UnknownUser(String),
+    #[error("unable to find channel \"{}\"", _0)]
+    UnknownChannel(String),
+    #[error("missing permissions")]
+    Forbidden,
+    #[error("the bot is missing the permission to {} in this channel", _0)]
+    MissingPermission(&'static str),
+    #[error("rate limited by the service")]
+    RateLimited,
+    #[error("an identical message was just sent to this channel")]
+    Duplicate,
+    #[error("unknown service \"{}\"", _0)]
+    UnknownService(String),
+    #[error("the connection of the service is already restarting")]
+    Restarting,
+    #[error("{}", _0)]
+    Other(String),
 }
@@ -1,24 +1,43 @@
-use anyhow::{anyhow, Result};
+use anyhow::Result;
 use arc_swap::ArcSwapOption;
 use async_mutex::Mutex as AsyncMutex;
-use futures::future::{AbortHandle, Abortable};
+use futures::{
+    channel::oneshot,
+    future::{AbortHandle, Abortable},
+};
 use lru::LruCache;
 use serenity::{
-    client::Context,
+    client::{
+        bridge::gateway::{ShardId, ShardManager},
+        Context,
+    },
+    gateway::ConnectionStage,
     http::CacheHttp,
     model::{
-        channel::{Message, Reaction, ReactionType},
-        event::MessageUpdateEvent,
+        application::{
+            component::ActionRowComponent,
+            interaction::{
+                message_component::MessageComponentInteraction, modal::ModalSubmitInteraction,
+                Interaction, InteractionResponseType,
+            },
+        },
+        channel::{self as serenity_channel, GuildChannel, Message, Reaction, ReactionType},
+        event::{MessageUpdateEvent, ResumedEvent, ShardStageUpdateEvent},
         gateway::{GatewayIntents, Ready},
-        id::{ChannelId, GuildId, MessageId},
+        guild::{Member, Role},
+        id::{ChannelId, GuildId, MessageId, RoleId},
+        permissions::Permissions,
+        user::User,
     },
     prelude::*,
     CacheAndHttp,
 };
 use songbird::SerenityInit;
 use std::{
+    collections::HashMap,
     str::FromStr,
     sync::{Arc, Mutex},
+    time::Duration,
     u64,
 };
 use thiserror::Error;
@@ -31,20 +50,89 @@ mod voice;
 
 use self::{user::DiscordUser, voice::DiscordVoiceConnection};
 
-use super::{Channel, Service, ServiceFeatures, ServiceKind};
-use crate::bot::Bot;
+use super::{
+    queue::{SendLimits, SendQueue},
+    supervisor::{Backoff, ConnectionSupervisor},
+    Channel, ContentSegment, ServerId, Service, ServiceError, ServiceFeatures, ServiceKind,
+    ServiceStatus, ShardStatus, UserPermissions,
+};
+use crate::{
+    bot::{
+        events::{BotEvent, ServiceAudit},
+        Bot,
+    },
+    message::{format::TextDialect, MessageModal},
+};
+
+// Discord drops interactions that aren't acknowledged within 3 seconds
+const INTERACTION_ACK_DELAY: Duration = Duration::from_secs(2);
+const MODAL_TIMEOUT: Duration = Duration::from_secs(15 * 60);
+
+// Discord permissions behind the normalized ones, administrators get all of them
+const PERMISSIONS: &[(Permissions, UserPermissions)] = &[
+    (
+        Permissions::MANAGE_MESSAGES,
+        UserPermissions::MANAGE_MESSAGES,
+    ),
+    (
+        Permissions::MANAGE_CHANNELS,
+        UserPermissions::MANAGE_CHANNELS,
+    ),
+    (Permissions::MANAGE_GUILD, UserPermissions::MANAGE_SERVER),
+    (Permissions::MANAGE_ROLES, UserPermissions::MANAGE_ROLES),
+    (Permissions::KICK_MEMBERS, UserPermissions::KICK_MEMBERS),
+    (Permissions::BAN_MEMBERS, UserPermissions::BAN_MEMBERS),
+    (
+        Permissions::MODERATE_MEMBERS,
+        UserPermissions::TIMEOUT_MEMBERS,
+    ),
+    (
+        Permissions::MENTION_EVERYONE,
+        UserPermissions::MENTION_EVERYONE,
+    ),
+];
+
+// Discord allows 5 messages per 5 seconds in a channel and 50 requests per second overall
+const SEND_LIMITS: SendLimits = SendLimits {
+    channel_burst: 3,
+    channel_period: Duration::from_secs(2),
+    service_per_second: 40,
+};
+
+// Audit log entries show up shortly after the gateway event they belong to
+const AUDIT_LOG_DELAY: Duration = Duration::from_secs(1);
+const AUDIT_LOG_MAX_AGE_MS: u64 = 30_000;
+const DISCORD_EPOCH_MS: u64 = 1_420_070_400_000;
+
+// Audit log action types
+const AUDIT_CHANNEL_CREATE: u8 = 10;
+const AUDIT_CHANNEL_DELETE: u8 = 12;
+const AUDIT_MEMBER_KICK: u8 = 20;
+const AUDIT_MEMBER_BAN_ADD: u8 = 22;
+const AUDIT_MEMBER_BAN_REMOVE: u8 = 23;
+const AUDIT_ROLE_CREATE: u8 = 30;
+const AUDIT_ROLE_UPDATE: u8 = 31;
+const AUDIT_ROLE_DELETE: u8 = 32;
 
 pub struct DiscordService {
     bot: Arc<Bot>,
     cache_and_http: ArcSwapOption<CacheAndHttp>,
+    shard_manager: ArcSwapOption<serenity::prelude::Mutex<ShardManager>>,
     context: ArcSwapOption<Context>,
     ready_abort: Mutex<Option<AbortHandle>>,
     user_cache: AsyncMutex<LruCache<u64, Arc<DiscordUser>>>,
+    supervisor: ConnectionSupervisor,
+    send_queue: SendQueue<u64>,
+    // Component interactions that can still be responded to with a modal
+    pending_interactions: Mutex<HashMap<u64, MessageComponentInteraction>>,
+    modal_waiters: Mutex<HashMap<String, oneshot::Sender<HashMap<String, String>>>>,
 }
 
 #[derive(Clone, Deserialize, Serialize, Debug)]
 pub struct DiscordServiceConfig {
     pub token: String,
+    /// Seconds identical messages to a channel are suppressed for, off unless set
+    pub dedup_window: Option<u64>,
 }
 
 struct SerenityHandler {
@@ -81,6 +169,159 @@ impl SerenityHandler {
             .reaction(msg, reactor, reaction.emoji.as_data(), remove)
             .await
     }
+
+    async fn member(&self, guild_id: GuildId, user_id: u64, joined: bool) {
+        let user = match self.service.user(user_id).await {
+            Ok(user) => user,
+            Err(_) => return,
+        };
+
+        self.service
+            .bot
+            .member(ServerId::Discord(guild_id.0), user, joined)
+            .await
+    }
+
+    /// Publish a change made to a server, with the actor and reason taken from its audit log entry
+    async fn audit(
+        &self,
+        ctx: &Context,
+        guild_id: GuildId,
+        action: &'static str,
+        action_type: Option<u8>,
+        target_id: u64,
+        require_entry: bool,
+    ) {
+        tokio::time::sleep(AUDIT_LOG_DELAY).await;
+
+        let now = chrono::Utc::now().timestamp_millis() as u64;
+        let entry = match guild_id
+            .audit_logs(&ctx.http, action_type, None, None, Some(10))
+            .await
+        {
+            Ok(logs) => logs.entries.into_iter().find(|entry| {
+                let created = (entry.id.0 >> 22) + DISCORD_EPOCH_MS;
+
+                entry.target_id == Some(target_id)
+                    && now.saturating_sub(created) < AUDIT_LOG_MAX_AGE_MS
+            }),
+            // Without access to the audit log only the actor and reason are lost
+            Err(_) => None,
+        };
+
+        if entry.is_none() && require_entry {
+            return;
+        }
+
+        let to_id = |id: u64| format!("{}:{}", DiscordService::ID_SHORT, id);
+
+        self.service
+            .bot
+            .events()
+            .publish(BotEvent::ServiceAudit(ServiceAudit {
+                service: DiscordService::ID,
+                server_id: to_id(guild_id.0),
+                action,
+                actor_id: entry.as_ref().map(|entry| to_id(entry.user_id.0)),
+                target_id: Some(to_id(target_id)),
+                reason: entry.as_ref().and_then(|entry| entry.reason.clone()),
+                changes: entry
+                    .and_then(|entry| entry.changes)
+                    .unwrap_or_default()
+                    .into_iter()
+                    .map(|change| (change.name, change.old, change.new))
+                    .collect(),
+            }));
+    }
+
+    async fn component(&self, ctx: Context, interaction: MessageComponentInteraction) {
+        let interaction_id = *interaction.id.as_u64();
+
+        self.service
+            .pending_interactions
+            .lock()
+            .unwrap()
+            .insert(interaction_id, interaction.clone());
+
+        // Acknowledge the interaction ourselves unless a handler opened a modal in time
+        let service = self.service.clone();
+        tokio::spawn(async move {
+            tokio::time::sleep(INTERACTION_ACK_DELAY).await;
+
+            let pending = service
+                .pending_interactions
+                .lock()
+                .unwrap()
+                .remove(&interaction_id);
+
+            if let Some(interaction) = pending {
+                if let Err(err) = interaction
+                    .create_interaction_response(&ctx.http, |r| {
+                        r.kind(InteractionResponseType::DeferredUpdateMessage)
+                    })
+                    .await
+                {
+                    println!("error acknowledging interaction: {}", err.to_string());
+                }
+            }
+        });
+
+        let user = match self.service.user(*interaction.user.id.as_u64()).await {
+            Ok(user) => user,
+            Err(_) => return,
+        };
+
+        let msg = Arc::new(message::DiscordMessage::new(
+            interaction.message.clone(),
+            self.service.clone(),
+        ));
+
+        self.service
+            .bot
+            .component(
+                msg,
+                user,
+                interaction.data.custom_id.clone(),
+                interaction.data.values.clone(),
+                super::InteractionId::Discord(interaction_id),
+            )
+            .await
+    }
+
+    async fn modal_submit(&self, ctx: Context, interaction: ModalSubmitInteraction) {
+        if let Err(err) = interaction
+            .create_interaction_response(&ctx.http, |r| {
+                r.kind(InteractionResponseType::DeferredUpdateMessage)
+            })
+            .await
+        {
+            println!("error acknowledging modal: {}", err.to_string());
+        }
+
+        let waiter = self
+            .service
+            .modal_waiters
+            .lock()
+            .unwrap()
+            .remove(&interaction.data.custom_id);
+
+        if let Some(waiter) = waiter {
+            let values = interaction
+                .data
+                .components
+                .iter()
+                .flat_map(|row| row.components.iter())
+                .filter_map(|component| match component {
+                    ActionRowComponent::InputText(input) => {
+                        Some((input.custom_id.clone(), input.value.clone()))
+                    }
+                    _ => None,
+                })
+                .collect();
+
+            waiter.send(values).ok();
+        }
+    }
 }
 
 #[async_trait]
@@ -91,6 +332,7 @@ impl EventHandler for SerenityHandler {
         }
 
         self.service.context.store(Some(Arc::new(context)));
+        self.service.supervisor.connected();
 
         println!(
             "{}#{:04} is connected!",
@@ -98,6 +340,16 @@ impl EventHandler for SerenityHandler {
         );
     }
 
+    async fn resume(&self, _ctx: Context, _resumed: ResumedEvent) {
+        self.service.supervisor.resumed();
+    }
+
+    async fn shard_stage_update(&self, _ctx: Context, update: ShardStageUpdateEvent) {
+        if update.new != ConnectionStage::Connected {
+            self.service.supervisor.disconnected();
+        }
+    }
+
     async fn message(&self, _ctx: Context, msg: Message) {
         let msg = message::DiscordMessage::new(msg, self.service.clone());
         self.service.bot.message(Arc::new(msg)).await;
@@ -145,6 +397,150 @@ impl EventHandler for SerenityHandler {
     async fn reaction_remove(&self, _ctx: Context, reaction: Reaction) {
         self.reaction(reaction, true).await;
     }
+
+    async fn guild_ban_addition(&self, ctx: Context, guild_id: GuildId, banned_user: User) {
+        self.audit(
+            &ctx,
+            guild_id,
+            "member_ban_add",
+            Some(AUDIT_MEMBER_BAN_ADD),
+            banned_user.id.0,
+            false,
+        )
+        .await;
+    }
+
+    async fn guild_ban_removal(&self, ctx: Context, guild_id: GuildId, unbanned_user: User) {
+        self.audit(
+            &ctx,
+            guild_id,
+            "member_ban_remove",
+            Some(AUDIT_MEMBER_BAN_REMOVE),
+            unbanned_user.id.0,
+            false,
+        )
+        .await;
+    }
+
+    async fn guild_member_addition(&self, _ctx: Context, new_member: Member) {
+        self.member(new_member.guild_id, new_member.user.id.0, true)
+            .await;
+    }
+
+    async fn guild_member_removal(
+        &self,
+        ctx: Context,
+        guild_id: GuildId,
+        user: User,
+        _member: Option<Member>,
+    ) {
+        self.member(guild_id, user.id.0, false).await;
+
+        // Members leaving on their own have no audit log entry
+        self.audit(
+            &ctx,
+            guild_id,
+            "member_kick",
+            Some(AUDIT_MEMBER_KICK),
+            user.id.0,
+            true,
+        )
+        .await;
+    }
+
+    async fn channel_create(&self, ctx: Context, channel: &GuildChannel) {
+        self.audit(
+            &ctx,
+            channel.guild_id,
+            "channel_create",
+            Some(AUDIT_CHANNEL_CREATE),
+            channel.id.0,
+            false,
+        )
+        .await;
+    }
+
+    async fn channel_update(
+        &self,
+        ctx: Context,
+        _old: Option<serenity_channel::Channel>,
+        new: serenity_channel::Channel,
+    ) {
+        if let Some(channel) = new.guild() {
+            // Any action type, permission overwrite changes are logged separately
+            self.audit(
+                &ctx,
+                channel.guild_id,
+                "channel_update",
+                None,
+                channel.id.0,
+                false,
+            )
+            .await;
+        }
+    }
+
+    async fn channel_delete(&self, ctx: Context, channel: &GuildChannel) {
+        self.audit(
+            &ctx,
+            channel.guild_id,
+            "channel_delete",
+            Some(AUDIT_CHANNEL_DELETE),
+            channel.id.0,
+            false,
+        )
+        .await;
+    }
+
+    async fn guild_role_create(&self, ctx: Context, new: Role) {
+        self.audit(
+            &ctx,
+            new.guild_id,
+            "role_create",
+            Some(AUDIT_ROLE_CREATE),
+            new.id.0,
+            false,
+        )
+        .await;
+    }
+
+    async fn guild_role_update(&self, ctx: Context, _old: Option<Role>, new: Role) {
+        self.audit(
+            &ctx,
+            new.guild_id,
+            "role_update",
+            Some(AUDIT_ROLE_UPDATE),
+            new.id.0,
+            false,
+        )
+        .await;
+    }
+
+    async fn guild_role_delete(
+        &self,
+        ctx: Context,
+        guild_id: GuildId,
+        removed_role_id: RoleId,
+        _removed_role: Option<Role>,
+    ) {
+        self.audit(
+            &ctx,
+            guild_id,
+            "role_delete",
+            Some(AUDIT_ROLE_DELETE),
+            removed_role_id.0,
+            false,
+        )
+        .await;
+    }
+
+    async fn interaction_create(&self, ctx: Context, interaction: Interaction) {
+        match interaction {
+            Interaction::MessageComponent(interaction) => self.component(ctx, interaction).await,
+            Interaction::ModalSubmit(interaction) => self.modal_submit(ctx, interaction).await,
+            _ => {}
+        }
+    }
 }
 
 #[async_trait]
@@ -158,8 +554,15 @@ impl Service for DiscordService {
             | ServiceFeatures::EMBED.bits()
             | ServiceFeatures::REACT.bits()
             | ServiceFeatures::VOICE.bits()
-            | ServiceFeatures::MARKDOWN.bits(),
+            | ServiceFeatures::MARKDOWN.bits()
+            | ServiceFeatures::COMPONENTS.bits()
+            | ServiceFeatures::MODALS.bits()
+            | ServiceFeatures::SCHEDULED_EVENTS.bits()
+            | ServiceFeatures::FORUMS.bits(),
     );
+    const MAX_MESSAGE_LENGTH: usize = 2000;
+    const MAX_MESSAGE_LINES: usize = 20;
+    const TEXT_DIALECT: TextDialect = TextDialect::Markdown;
 
     type ServiceConfig = DiscordServiceConfig;
     type Message = message::DiscordMessage;
@@ -172,18 +575,24 @@ impl Service for DiscordService {
     type ChannelId = u64;
     type ServerId = u64;
     type UserId = u64;
+    type InteractionId = u64;
 
     async fn init(bot: Arc<Bot>, config: Self::ServiceConfig) -> Result<Arc<Self>> {
         let service = Arc::new(DiscordService {
-            bot,
+            bot: bot.clone(),
             cache_and_http: ArcSwapOption::new(None),
+            shard_manager: ArcSwapOption::new(None),
             context: ArcSwapOption::new(None),
             ready_abort: Default::default(),
             user_cache: AsyncMutex::new(LruCache::new(64)),
+            supervisor: ConnectionSupervisor::new(bot, Self::ID, Backoff::default()),
+            send_queue: SendQueue::new(SEND_LIMITS)
+                .with_dedup(config.dedup_window.map(Duration::from_secs)),
+            pending_interactions: Default::default(),
+            modal_waiters: Default::default(),
         });
 
         let client;
-        let mut retry_count = 1;
 
         loop {
             match Client::builder(&config.token, GatewayIntents::all())
@@ -193,15 +602,10 @@ impl Service for DiscordService {
             {
                 Ok(c) => break client = c,
                 Err(err) => {
-                    let time = 2 ^ retry_count;
-                    retry_count += 1;
-                    println!(
-                        "Error creating discord client: {}, retrying in {} seconds",
-                        err.to_string(),
-                        time
-                    );
-
-                    tokio::time::sleep(std::time::Duration::from_secs(time)).await;
+                    service
+                        .supervisor
+                        .failed("creating discord client", err)
+                        .await
                 }
             }
         }
@@ -216,23 +620,19 @@ impl Service for DiscordService {
         service
             .cache_and_http
             .store(Some(client.cache_and_http.clone()));
+        service
+            .shard_manager
+            .store(Some(client.shard_manager.clone()));
 
-        async fn wrap_client(mut client: Client) -> Result<()> {
-            let mut retry_count = 1;
-
+        async fn wrap_client(service: Arc<DiscordService>, mut client: Client) -> Result<()> {
             loop {
                 match client.start().await {
                     Ok(_) => break,
                     Err(err) => {
-                        let time = 2 ^ retry_count;
-                        retry_count += 1;
-                        println!(
-                            "Error connecting to discord: {}, retrying in {} seconds",
-                            err.to_string(),
-                            time
-                        );
-
-                        tokio::time::sleep(std::time::Duration::from_secs(time)).await;
+                        service
+                            .supervisor
+                            .failed("connecting to discord", err)
+                            .await
                     }
                 }
             }
@@ -240,7 +640,7 @@ impl Service for DiscordService {
             Ok(())
         }
 
-        let join_task = tokio::spawn(wrap_client(client));
+        let join_task = tokio::spawn(wrap_client(service.clone(), client));
 
         // Block on the client task until it is ready or it has errored and yielded
         let (abort_handle, abort_registration) = AbortHandle::new_pair();
@@ -275,7 +675,8 @@ impl Service for DiscordService {
                 self.cache_and_http()
                     .http
                     .get_message(channel_id, id)
-                    .await?
+                    .await
+                    .map_err(ServiceError::from)?
             }
         };
 
@@ -288,7 +689,7 @@ impl Service for DiscordService {
     async fn server(self: &Arc<Self>, id: Self::ServerId) -> Result<Arc<Self::Server>> {
         let server = match self.cache_and_http().cache.guild(id) {
             Some(server) => server,
-            None => return Err(anyhow::anyhow!("error getting server")),
+            None => return Err(ServiceError::NotFound.into()),
         };
 
         Ok(Arc::new(server::DiscordServer::new(server, self.clone())))
@@ -297,7 +698,12 @@ impl Service for DiscordService {
     async fn channel(self: &Arc<Self>, id: Self::ChannelId) -> Result<Arc<Self::Channel>> {
         let channel = match self.cache_and_http().cache.channel(id) {
             Some(channel) => channel,
-            None => self.cache_and_http().http.get_channel(id).await?,
+            None => self
+                .cache_and_http()
+                .http
+                .get_channel(id)
+                .await
+                .map_err(ServiceError::from)?,
         };
 
         Ok(Arc::new(channel::DiscordChannel::new(
@@ -315,7 +721,12 @@ impl Service for DiscordService {
 
         let user = match self.cache_and_http().cache.user(id) {
             Some(user) => user,
-            None => self.cache_and_http().http.get_user(id).await?,
+            None => self
+                .cache_and_http()
+                .http
+                .get_user(id)
+                .await
+                .map_err(ServiceError::from)?,
         };
 
         let user = Arc::new(user::DiscordUser::new(user, self.clone()));
@@ -325,18 +736,37 @@ impl Service for DiscordService {
         Ok(user)
     }
 
+    async fn dm_channel(self: &Arc<Self>, id: u64) -> Result<Arc<Self::Channel>> {
+        let cache_and_http = self.cache_and_http();
+        let channel = serenity::model::id::UserId(id)
+            .create_dm_channel(&*cache_and_http)
+            .await
+            .map_err(ServiceError::from)?;
+
+        Ok(Arc::new(channel::DiscordChannel::new(
+            serenity_channel::Channel::Private(channel),
+            self.clone(),
+        )))
+    }
+
     async fn find_user(self: &Arc<Self>, channel_id: u64, find: &str) -> Result<Arc<Self::User>> {
         let find = find.trim();
 
         if let Some(id) = serenity::utils::parse_username(find).or(u64::from_str(find).ok()) {
             let user = match self.cache_and_http().cache.user(id) {
                 Some(channel) => channel,
-                None => self.cache_and_http().http.get_user(id).await?,
+                None => self
+                    .cache_and_http()
+                    .http
+                    .get_user(id)
+                    .await
+                    .map_err(ServiceError::from)?,
             };
 
             Ok(Arc::new(user::DiscordUser::new(user, self.clone())))
         } else {
-            let channel = self.find_channel(channel_id).await?;
+            let channel = self.channel(channel_id).await?;
+            let find = find.trim_start_matches('@');
 
             // Search for the member manually
             if let Some((member, _)) = channel
@@ -354,10 +784,76 @@ impl Service for DiscordService {
             }
 
             // TODO: Look in caches for name matches?
-            return Err(anyhow!("unable to parse \"{}\" as a discord user", find));
+            return Err(ServiceError::UnknownUser(find.to_string()).into());
+        }
+    }
+
+    async fn find_channel(
+        self: &Arc<Self>,
+        channel_id: u64,
+        find: &str,
+    ) -> Result<Arc<Self::Channel>> {
+        let find = find.trim();
+
+        if let Some(id) = serenity::utils::parse_channel(find).or(u64::from_str(find).ok()) {
+            return self.channel(id).await;
+        }
+
+        let name = find.trim_start_matches('#');
+        let server = self.channel(channel_id).await?.server().await?;
+
+        let channel = server
+            .guild()
+            .channels
+            .values()
+            .find(|channel| match channel {
+                serenity_channel::Channel::Guild(channel) => {
+                    channel.name.eq_ignore_ascii_case(name)
+                }
+                _ => false,
+            })
+            .cloned();
+
+        match channel {
+            Some(channel) => Ok(Arc::new(channel::DiscordChannel::new(
+                channel,
+                self.clone(),
+            ))),
+            None => Err(ServiceError::UnknownChannel(find.to_string()).into()),
         }
     }
 
+    async fn user_permissions(
+        self: &Arc<Self>,
+        channel_id: u64,
+        user_id: u64,
+    ) -> Result<UserPermissions> {
+        let channel = match self.channel(channel_id).await?.inner() {
+            serenity_channel::Channel::Guild(channel) => channel.clone(),
+            // Nobody moderates direct messages
+            _ => return Ok(UserPermissions::empty()),
+        };
+
+        let cache_and_http = self.cache_and_http();
+        let permissions = channel
+            .permissions_for_user(&cache_and_http.cache, user_id)
+            .map_err(|_| DiscordError::CacheMiss)?;
+
+        if permissions.administrator() {
+            return Ok(UserPermissions::all());
+        }
+
+        let mut normalized = UserPermissions::empty();
+
+        for (permission, user_permission) in PERMISSIONS {
+            if permissions.contains(*permission) {
+                normalized |= *user_permission;
+            }
+        }
+
+        Ok(normalized)
+    }
+
     async fn react(
         self: &Arc<Self>,
         channel_id: u64,
@@ -367,11 +863,56 @@ impl Service for DiscordService {
         self.cache_and_http()
             .http
             .create_reaction(channel_id, message_id, &ReactionType::Unicode(reaction))
-            .await?;
+            .await
+            .map_err(ServiceError::from)?;
 
         Ok(())
     }
 
+    async fn open_modal(
+        self: &Arc<Self>,
+        interaction_id: u64,
+        modal: MessageModal,
+    ) -> Result<HashMap<String, String>> {
+        let interaction = self
+            .pending_interactions
+            .lock()
+            .unwrap()
+            .remove(&interaction_id)
+            .ok_or(DiscordError::InteractionExpired)?;
+
+        let custom_id = format!("modal:{}", interaction_id);
+        let (sender, receiver) = oneshot::channel();
+        self.modal_waiters
+            .lock()
+            .unwrap()
+            .insert(custom_id.clone(), sender);
+
+        let res = interaction
+            .create_interaction_response(&self.cache_and_http().http, |r| {
+                r.kind(InteractionResponseType::Modal)
+                    .interaction_response_data(|d| {
+                        d.custom_id(&custom_id)
+                            .title(modal.title)
+                            .components(|c| message::create_discord_modal(modal.fields, c))
+                    })
+            })
+            .await;
+
+        if let Err(err) = res {
+            self.modal_waiters.lock().unwrap().remove(&custom_id);
+            return Err(ServiceError::from(err).into());
+        }
+
+        match tokio::time::timeout(MODAL_TIMEOUT, receiver).await {
+            Ok(Ok(values)) => Ok(values),
+            _ => {
+                self.modal_waiters.lock().unwrap().remove(&custom_id);
+                Err(DiscordError::ModalTimeout.into())
+            }
+        }
+    }
+
     async fn join_voice(
         &self,
         server_id: u64,
@@ -388,6 +929,64 @@ impl Service for DiscordService {
             server_id, channel_id, call,
         )))
     }
+
+    async fn status(self: &Arc<Self>) -> Result<ServiceStatus> {
+        let mut shards = Vec::new();
+
+        if let Some(shard_manager) = self.shard_manager.load_full() {
+            let shard_manager = shard_manager.lock().await;
+
+            for (id, runner) in shard_manager.runners.lock().await.iter() {
+                shards.push(ShardStatus {
+                    id: id.0,
+                    stage: runner.stage.to_string(),
+                    latency: runner.latency,
+                });
+            }
+        }
+
+        shards.sort_by_key(|shard| shard.id);
+
+        Ok(ServiceStatus {
+            uptime: self.supervisor.uptime(),
+            reconnects: self.supervisor.reconnects(),
+            failed_attempts: self.supervisor.failed_attempts(),
+            backlog: self.send_queue.backlog(),
+            sent: self.send_queue.sent(),
+            shards,
+        })
+    }
+
+    async fn restart(self: &Arc<Self>) -> Result<()> {
+        let shard_manager = self
+            .shard_manager
+            .load_full()
+            .ok_or_else(|| anyhow::anyhow!("discord has not connected yet"))?;
+        let shard_manager = &shard_manager;
+
+        // Shards reboot through the shard queuer, the http client and the send queue stay up
+        self.supervisor
+            .restart(|| async move {
+                let mut shard_manager = shard_manager.lock().await;
+                let mut ids: Vec<ShardId> =
+                    shard_manager.runners.lock().await.keys().copied().collect();
+
+                if ids.is_empty() {
+                    ids.push(ShardId(0));
+                }
+
+                for id in ids {
+                    shard_manager.restart(id).await;
+                }
+
+                Ok(())
+            })
+            .await
+    }
+
+    fn parse_content(content: &str) -> Vec<ContentSegment> {
+        message::parse_content(content)
+    }
 }
 
 impl DiscordService {
@@ -395,18 +994,6 @@ impl DiscordService {
         self.cache_and_http.load_full().unwrap()
     }
 
-    async fn find_channel(self: &Arc<Self>, id: u64) -> Result<Arc<channel::DiscordChannel>> {
-        let channel = match self.cache_and_http().cache.channel(id) {
-            Some(channel) => channel,
-            None => self.cache_and_http().http.get_channel(id).await?,
-        };
-
-        Ok(Arc::new(channel::DiscordChannel::new(
-            channel,
-            self.clone(),
-        )))
-    }
-
     fn get_ctx(&self) -> Result<Arc<Context>> {
         if let Some(ctx) = self.context.load().as_ref() {
             Ok(ctx.clone())
@@ -422,4 +1009,39 @@ pub enum DiscordError {
     NoChannelGuild,
     #[error("cache miss")]
     CacheMiss,
+    #[error("the interaction has expired or was already responded to")]
+    InteractionExpired,
+    #[error("the modal was not submitted in time")]
+    ModalTimeout,
+    #[error("invalid timestamp")]
+    InvalidTimestamp,
+    #[error("messages can only be sent to the posts of a forum channel")]
+    ForumChannel,
+    #[error("the channel is not a forum channel")]
+    NotForumChannel,
+    #[error("unknown forum tag \"{}\"", _0)]
+    UnknownForumTag(String),
+}
+
+impl From<SerenityError> for ServiceError {
+    fn from(err: SerenityError) -> ServiceError {
+        match &err {
+            SerenityError::Http(http_err) => {
+                if let serenity::http::error::Error::UnsuccessfulRequest(res) = http_err.as_ref() {
+                    match res.status_code.as_u16() {
+                        403 => return ServiceError::Forbidden,
+                        404 => return ServiceError::NotFound,
+                        429 => return ServiceError::RateLimited,
+                        _ => {}
+                    }
+                }
+            }
+            SerenityError::Model(serenity::model::error::Error::InvalidPermissions(_)) => {
+                return ServiceError::Forbidden
+            }
+            _ => {}
+        }
+
+        ServiceError::Other(err.to_string())
+    }
 }
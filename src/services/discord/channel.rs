@@ -1,17 +1,23 @@
 use anyhow::Result;
-use serenity::model::channel::{self, AttachmentType};
+use serenity::model::{
+    channel::{self, AttachmentType, ChannelType},
+    permissions::Permissions,
+};
 use std::{convert::TryInto, sync::Arc};
 
 use super::{
-    message::{create_discord_embed, DiscordMessage},
+    message::{create_discord_components, create_discord_embed, sanitize_content, DiscordMessage},
     server::DiscordServer,
     DiscordError, DiscordService,
 };
 use crate::{
     message::{MessageContent, MessageSettings, ToMessageContent},
-    services::{Channel, ChannelId},
+    services::{queue::Repeat, Channel, ChannelId, ForumPost, ForumTag, ServiceError},
 };
 
+const MAX_PURGE: u64 = 100;
+const BULK_DELETE_MAX_AGE: i64 = 14 * 24 * 60 * 60;
+
 pub struct DiscordChannel {
     channel: channel::Channel,
     service: Arc<DiscordService>,
@@ -25,6 +31,72 @@ impl DiscordChannel {
     pub fn inner(&self) -> &channel::Channel {
         &self.channel
     }
+
+    fn forum(&self) -> Option<&channel::GuildChannel> {
+        match &self.channel {
+            channel::Channel::Guild(c) if c.kind == ChannelType::Forum => Some(c),
+            _ => None,
+        }
+    }
+
+    /// Check the bot can send what the message needs, instead of letting the API request fail
+    fn check_send_permissions(&self, settings: &MessageSettings, as_file: bool) -> Result<()> {
+        let channel = match &self.channel {
+            channel::Channel::Guild(c) => c,
+            _ => return Ok(()),
+        };
+
+        let cache = &self.service.cache_and_http().cache;
+        let permissions = match channel.permissions_for_user(cache, cache.current_user_id()) {
+            Ok(permissions) => permissions,
+            // Leave it to the API when the guild isn't cached
+            Err(_) => return Ok(()),
+        };
+
+        let send = if channel.thread_metadata.is_some() {
+            Permissions::SEND_MESSAGES_IN_THREADS
+        } else {
+            Permissions::SEND_MESSAGES
+        };
+
+        let mut required = vec![(send, "send messages")];
+
+        if settings.embed.is_some() || settings.gif.is_some() {
+            required.push((Permissions::EMBED_LINKS, "embed links"));
+        }
+
+        if as_file || !settings.attachments.is_empty() {
+            required.push((Permissions::ATTACH_FILES, "attach files"));
+        }
+
+        for (permission, name) in required {
+            if !permissions.contains(permission) {
+                return Err(ServiceError::MissingPermission(name).into());
+            }
+        }
+
+        Ok(())
+    }
+
+    // Note on the copies of the previous message that were held back
+    async fn send_repeat_summary(&self, count: usize, settings: &MessageSettings) {
+        self.service
+            .send_queue
+            .wait(self.channel.id().0, settings.priority)
+            .await;
+
+        if let Err(err) = self
+            .channel
+            .id()
+            .say(
+                &self.service.cache_and_http().http,
+                format!("(repeated x{})", count),
+            )
+            .await
+        {
+            println!("error sending repeat summary: {}", err.to_string());
+        }
+    }
 }
 
 #[async_trait]
@@ -53,7 +125,8 @@ impl Channel<DiscordService> for DiscordChannel {
 
                 ret.limit(limit)
             })
-            .await?;
+            .await
+            .map_err(ServiceError::from)?;
 
         Ok(messages
             .into_iter()
@@ -69,10 +142,36 @@ impl Channel<DiscordService> for DiscordChannel {
     where
         C: ToMessageContent<'a>,
     {
+        if self.forum().is_some() {
+            return Err(DiscordError::ForumChannel.into());
+        }
+
         let content = match content.to_message_content() {
             MessageContent::String(text) => text,
             MessageContent::Str(text) => text.to_string(),
         };
+        let content = sanitize_content(content, settings.sanitize);
+        let as_file = content.chars().count() > 2000
+            || content.as_bytes().iter().filter(|&&c| c == b'\n').count() > 20;
+
+        self.check_send_permissions(&settings, as_file)?;
+
+        if !content.is_empty() {
+            match self
+                .service
+                .send_queue
+                .check_repeat(self.channel.id().0, &content)
+            {
+                Repeat::Send => {}
+                Repeat::Suppress => return Err(ServiceError::Duplicate.into()),
+                Repeat::SendAfter(count) => self.send_repeat_summary(count, &settings).await,
+            }
+        }
+
+        self.service
+            .send_queue
+            .wait(self.channel.id().0, settings.priority)
+            .await;
 
         let msg = self
             .channel
@@ -92,7 +191,7 @@ impl Channel<DiscordService> for DiscordChannel {
                 });
 
                 if !content.is_empty() {
-                    if content.chars().count() > 2000 || content.as_bytes().iter().filter(|&&c| c == b'\n').count() > 20 {
+                    if as_file {
                         m = m.add_file(AttachmentType::Bytes {
                             data: std::borrow::Cow::from(content.as_bytes().to_owned()),
                             filename: "message.txt".into(),
@@ -102,10 +201,28 @@ impl Channel<DiscordService> for DiscordChannel {
                     }
                 }
 
-                if let Some(embed) = settings.embed {
+                let mut embed = settings.embed;
+                if let Some(gif) = settings.gif {
+                    embed
+                        .get_or_insert_with(Default::default)
+                        .image
+                        .get_or_insert(gif);
+                }
+
+                if let Some(embed) = embed {
                     m = m.embed(|e| create_discord_embed(embed, e));
                 }
 
+                for sticker in settings.stickers {
+                    if let Ok(id) = sticker.parse::<u64>() {
+                        m = m.add_sticker_id(id);
+                    }
+                }
+
+                if !settings.components.is_empty() {
+                    m = m.components(|c| create_discord_components(settings.components, c));
+                }
+
                 for (filename, data) in settings.attachments {
                     m = m.add_file(AttachmentType::Bytes {
                         data: data.into(),
@@ -115,7 +232,8 @@ impl Channel<DiscordService> for DiscordChannel {
 
                 m
             })
-            .await?;
+            .await
+            .map_err(ServiceError::from)?;
 
         Ok(Arc::new(DiscordMessage::new(msg, self.service.clone())))
     }
@@ -140,11 +258,100 @@ impl Channel<DiscordService> for DiscordChannel {
             .cache_and_http()
             .http
             .broadcast_typing(*self.channel.id().as_u64())
-            .await?;
+            .await
+            .map_err(ServiceError::from)?;
 
         Ok(())
     }
 
+    fn forum_tags(&self) -> Option<Vec<ForumTag>> {
+        self.forum().map(|forum| {
+            forum
+                .available_tags
+                .iter()
+                .map(|tag| ForumTag {
+                    id: tag.id.0.to_string(),
+                    name: tag.name.clone(),
+                })
+                .collect()
+        })
+    }
+
+    async fn create_post(&self, post: ForumPost) -> Result<Arc<DiscordChannel>> {
+        let forum = self.forum().ok_or(DiscordError::NotForumChannel)?;
+
+        let mut tags = Vec::with_capacity(post.tags.len());
+        for name in &post.tags {
+            let tag = forum
+                .available_tags
+                .iter()
+                .find(|tag| tag.name.eq_ignore_ascii_case(name))
+                .ok_or_else(|| DiscordError::UnknownForumTag(name.clone()))?;
+
+            tags.push(tag.id);
+        }
+
+        let thread = forum
+            .id
+            .create_forum_post(&self.service.cache_and_http().http, |p| {
+                p.name(post.name).message(|m| {
+                    m.content(sanitize_content(post.content, post.sanitize))
+                        .allowed_mentions(|am| am.empty_parse())
+                });
+
+                for tag in tags {
+                    p.add_applied_tag(tag);
+                }
+
+                p
+            })
+            .await
+            .map_err(ServiceError::from)?;
+
+        Ok(Arc::new(DiscordChannel::new(
+            channel::Channel::Guild(thread),
+            self.service.clone(),
+        )))
+    }
+
+    async fn purge(&self, count: u64) -> Result<usize> {
+        let cache_and_http = self.service.cache_and_http();
+        let http = &cache_and_http.http;
+
+        let messages = self
+            .channel
+            .id()
+            .messages(http, |ret| ret.limit(count.min(MAX_PURGE)))
+            .await
+            .map_err(ServiceError::from)?;
+
+        // Discord refuses to bulk delete messages older than two weeks
+        let cutoff = chrono::Utc::now().timestamp() - BULK_DELETE_MAX_AGE;
+        let ids: Vec<_> = messages
+            .iter()
+            .filter(|msg| msg.timestamp.unix_timestamp() > cutoff)
+            .map(|msg| msg.id)
+            .collect();
+
+        match ids.as_slice() {
+            [] => {}
+            [id] => self
+                .channel
+                .id()
+                .delete_message(http, *id)
+                .await
+                .map_err(ServiceError::from)?,
+            ids => self
+                .channel
+                .id()
+                .delete_messages(http, ids)
+                .await
+                .map_err(ServiceError::from)?,
+        }
+
+        Ok(ids.len())
+    }
+
     fn service(&self) -> &Arc<DiscordService> {
         &self.service
     }
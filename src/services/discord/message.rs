@@ -1,13 +1,129 @@
 use anyhow::Result;
-use serenity::{builder::CreateEmbed, model::channel};
+use regex::Regex;
+use serenity::{
+    builder::{CreateComponents, CreateEmbed},
+    model::{application::component, channel},
+};
 use std::sync::Arc;
 
 use super::{channel::DiscordChannel, user::DiscordUser, DiscordService};
 use crate::{
-    message::{Attachment, MessageContent, MessageEmbed, MessageSettings, ToMessageContent},
-    services::{Message, MessageId},
+    message::{
+        Attachment, ButtonStyle, MessageComponent, MessageContent, MessageEmbed, MessageSettings,
+        ModalField, Sanitize, ToMessageContent,
+    },
+    services::{ChannelId, ContentSegment, Message, MessageId, ServiceError, UserId},
+    utils::ci_regex,
 };
 
+/// Neutralize mentions and invites with zero width spaces, so they're shown as plain text
+pub fn sanitize_content(content: String, sanitize: Sanitize) -> String {
+    lazy_static::lazy_static! {
+        static ref MENTION_RE: Regex = Regex::new(r"<@([!&]?\d+)>").unwrap();
+        static ref INVITE_RE: Regex = ci_regex!(r"(discord(?:app)?\.com/invite|discord\.gg)/").unwrap();
+    }
+
+    let mut content = content;
+
+    if sanitize.contains(Sanitize::EVERYONE) {
+        content = content
+            .replace("@everyone", "@\u{200B}everyone")
+            .replace("@here", "@\u{200B}here");
+    }
+
+    if sanitize.contains(Sanitize::MENTIONS) {
+        content = MENTION_RE
+            .replace_all(&content, "<@\u{200B}$1>")
+            .into_owned();
+    }
+
+    if sanitize.contains(Sanitize::INVITES) {
+        content = INVITE_RE.replace_all(&content, "$1\u{200B}/").into_owned();
+    }
+
+    content
+}
+
+fn push_text(segments: &mut Vec<ContentSegment>, text: &str) {
+    if text.is_empty() {
+        return;
+    }
+
+    match segments.last_mut() {
+        Some(ContentSegment::Text(last)) => last.push_str(text),
+        _ => segments.push(ContentSegment::Text(text.into())),
+    }
+}
+
+/// Split content into text, mentions, custom emoji, links and code, mentions in code stay code
+pub fn parse_content(content: &str) -> Vec<ContentSegment> {
+    lazy_static::lazy_static! {
+        static ref SEGMENT_RE: Regex = Regex::new(concat!(
+            r"(?s)```(?:([\w+#-]+)\n)?(.*?)```",
+            r"|`([^`]+)`",
+            r"|<@!?(\d+)>",
+            r"|<#(\d+)>",
+            r"|<(a?):(\w+):(\d+)>",
+            r"|<(https?://[^\s>]+)>",
+            r"|(https?://[^\s<>]+)",
+        ))
+        .unwrap();
+    }
+
+    let mut segments = Vec::new();
+    let mut last = 0;
+
+    for caps in SEGMENT_RE.captures_iter(content) {
+        let whole = caps.get(0).unwrap();
+        push_text(&mut segments, &content[last..whole.start()]);
+        last = whole.end();
+
+        let segment = if let Some(code) = caps.get(2) {
+            Some(ContentSegment::Code {
+                code: code.as_str().into(),
+                language: caps.get(1).map(|language| language.as_str().into()),
+                block: true,
+            })
+        } else if let Some(code) = caps.get(3) {
+            Some(ContentSegment::Code {
+                code: code.as_str().into(),
+                language: None,
+                block: false,
+            })
+        } else if let Some(id) = caps.get(4) {
+            id.as_str()
+                .parse()
+                .ok()
+                .map(|id| ContentSegment::UserMention(UserId::Discord(id)))
+        } else if let Some(id) = caps.get(5) {
+            id.as_str()
+                .parse()
+                .ok()
+                .map(|id| ContentSegment::ChannelMention(ChannelId::Discord(id)))
+        } else if let (Some(name), Some(id)) = (caps.get(7), caps.get(8)) {
+            Some(ContentSegment::Emoji {
+                name: name.as_str().into(),
+                id: id.as_str().into(),
+                animated: caps.get(6).map_or(false, |a| !a.as_str().is_empty()),
+            })
+        } else {
+            caps.get(9)
+                .or_else(|| caps.get(10))
+                .map(|url| ContentSegment::Link(url.as_str().into()))
+        };
+
+        match segment {
+            Some(segment) => segments.push(segment),
+            // Ids too large to be real are left as they were written
+            None => push_text(&mut segments, whole.as_str()),
+        }
+    }
+
+    push_text(&mut segments, &content[last..]);
+
+    segments
+}
+
 pub fn create_discord_embed(embed: MessageEmbed, mut e: &mut CreateEmbed) -> &mut CreateEmbed {
     // Set up the author
     if let Some(author_name) = embed.author_name {
@@ -73,6 +189,115 @@ pub fn create_discord_embed(embed: MessageEmbed, mut e: &mut CreateEmbed) -> &mu
     e
 }
 
+pub fn create_discord_components(
+    rows: Vec<Vec<MessageComponent>>,
+    c: &mut CreateComponents,
+) -> &mut CreateComponents {
+    for row in rows {
+        c.create_action_row(move |r| {
+            for component in row {
+                match component {
+                    MessageComponent::Button {
+                        id,
+                        label,
+                        style,
+                        disabled,
+                    } => {
+                        r.create_button(move |b| {
+                            b.custom_id(id)
+                                .label(label)
+                                .disabled(disabled)
+                                .style(match style {
+                                    ButtonStyle::Primary => component::ButtonStyle::Primary,
+                                    ButtonStyle::Secondary => component::ButtonStyle::Secondary,
+                                    ButtonStyle::Success => component::ButtonStyle::Success,
+                                    ButtonStyle::Danger => component::ButtonStyle::Danger,
+                                })
+                        });
+                    }
+                    MessageComponent::Select {
+                        id,
+                        placeholder,
+                        options,
+                        min_values,
+                        max_values,
+                    } => {
+                        r.create_select_menu(move |s| {
+                            s.custom_id(id)
+                                .min_values(min_values)
+                                .max_values(max_values);
+
+                            if let Some(placeholder) = placeholder {
+                                s.placeholder(placeholder);
+                            }
+
+                            s.options(move |o| {
+                                for option in options {
+                                    o.create_option(move |so| {
+                                        so.label(option.label).value(option.value);
+
+                                        if let Some(description) = option.description {
+                                            so.description(description);
+                                        }
+
+                                        so
+                                    });
+                                }
+
+                                o
+                            })
+                        });
+                    }
+                }
+            }
+
+            r
+        });
+    }
+
+    c
+}
+
+pub fn create_discord_modal(
+    fields: Vec<ModalField>,
+    c: &mut CreateComponents,
+) -> &mut CreateComponents {
+    for field in fields {
+        c.create_action_row(move |r| {
+            r.create_input_text(move |t| {
+                t.custom_id(field.id)
+                    .label(field.label)
+                    .required(field.required)
+                    .style(if field.long {
+                        component::InputTextStyle::Paragraph
+                    } else {
+                        component::InputTextStyle::Short
+                    });
+
+                if let Some(placeholder) = field.placeholder {
+                    t.placeholder(placeholder);
+                }
+
+                if let Some(value) = field.value {
+                    t.value(value);
+                }
+
+                if let Some(min_length) = field.min_length {
+                    t.min_length(min_length);
+                }
+
+                if let Some(max_length) = field.max_length {
+                    t.max_length(max_length);
+                }
+
+                t
+            })
+        });
+    }
+
+    c
+}
+
 pub struct DiscordMessage {
     author: Arc<DiscordUser>,
     msg: channel::Message,
@@ -91,6 +316,7 @@ impl DiscordMessage {
                     url: a.proxy_url.to_string(),
                     size: Some(a.size),
                     dimensions: a.dimensions(),
+                    content_type: a.content_type.clone(),
                 })
             })
             .collect();
@@ -118,7 +344,11 @@ impl Message<DiscordService> for DiscordMessage {
     async fn channel(&self) -> Result<Arc<DiscordChannel>> {
         let cache_and_http = self.service().cache_and_http();
 
-        let channel = self.msg.channel(&cache_and_http).await?;
+        let channel = self
+            .msg
+            .channel(&cache_and_http)
+            .await
+            .map_err(ServiceError::from)?;
 
         Ok(Arc::new(DiscordChannel::new(channel, self.service.clone())))
     }
@@ -131,6 +361,7 @@ impl Message<DiscordService> for DiscordMessage {
             MessageContent::String(text) => text,
             MessageContent::Str(text) => text.to_string(),
         };
+        let content = sanitize_content(content, settings.sanitize);
 
         self.channel()
             .await?
@@ -145,15 +376,23 @@ impl Message<DiscordService> for DiscordMessage {
                     m = m.embed(|e| create_discord_embed(embed, e));
                 }
 
+                if !settings.components.is_empty() {
+                    m = m.components(|c| create_discord_components(settings.components, c));
+                }
+
                 m
             })
-            .await?;
+            .await
+            .map_err(ServiceError::from)?;
 
         Ok(())
     }
 
     async fn delete(&self) -> Result<()> {
-        self.msg.delete(&self.service.cache_and_http()).await?;
+        self.msg
+            .delete(&self.service.cache_and_http())
+            .await
+            .map_err(ServiceError::from)?;
 
         Ok(())
     }
@@ -162,6 +401,18 @@ impl Message<DiscordService> for DiscordMessage {
         &self.attachments
     }
 
+    fn reply_to(&self) -> Option<MessageId> {
+        self.msg
+            .message_reference
+            .as_ref()
+            .and_then(|reference| reference.message_id)
+            .map(|id| MessageId::Discord(*id.as_u64()))
+    }
+
+    fn link(&self) -> Option<String> {
+        Some(self.msg.link())
+    }
+
     fn service(&self) -> &Arc<DiscordService> {
         &self.service
     }
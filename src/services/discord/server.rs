@@ -1,9 +1,32 @@
 use anyhow::Result;
-use serenity::model::guild;
+use serenity::model::{channel::AttachmentType, guild, Timestamp};
 use std::sync::Arc;
 
-use super::DiscordService;
-use crate::services::{ChannelId, Server, ServerId, Service, User, UserId};
+use super::{DiscordError, DiscordService};
+use crate::services::{
+    ChannelId, ModerationAction, ScheduledEvent, ScheduledEventSettings, Server, ServerId,
+    ServerRole, Service, ServiceError, StickerSettings, UploadImage, User, UserId,
+};
+
+// External events need an end time, default to an hour after the start
+const DEFAULT_EVENT_LENGTH: i64 = 60 * 60;
+
+fn timestamp(time: i64) -> Result<Timestamp> {
+    Ok(Timestamp::from_unix_timestamp(time).map_err(|_| DiscordError::InvalidTimestamp)?)
+}
+
+fn to_scheduled_event(event: guild::ScheduledEvent) -> ScheduledEvent {
+    ScheduledEvent {
+        id: event.id.0.to_string(),
+        name: event.name,
+        description: event.description,
+        start_time: event.start_time.unix_timestamp(),
+        end_time: event.end_time.map(|time| time.unix_timestamp()),
+        location: event.metadata.map(|metadata| metadata.location),
+        channel_id: event.channel_id.map(|id| ChannelId::Discord(id.0)),
+        user_count: event.user_count,
+    }
+}
 
 pub struct DiscordServer {
     guild: guild::Guild,
@@ -63,6 +86,170 @@ impl Server<DiscordService> for DiscordServer {
 
         Ok(ids)
     }
+
+    async fn scheduled_events(&self) -> Result<Vec<ScheduledEvent>> {
+        let events = self
+            .guild
+            .id
+            .scheduled_events(&self.service.cache_and_http().http, true)
+            .await
+            .map_err(ServiceError::from)?;
+
+        Ok(events.into_iter().map(to_scheduled_event).collect())
+    }
+
+    async fn create_scheduled_event(
+        &self,
+        event: ScheduledEventSettings,
+    ) -> Result<ScheduledEvent> {
+        let channel_id = match event.channel_id {
+            Some(ChannelId::Discord(id)) => Some(id),
+            Some(channel_id) => {
+                return Err(ServiceError::UnknownChannel(channel_id.to_string()).into())
+            }
+            None => None,
+        };
+
+        let start_time = timestamp(event.start_time)?;
+        let end_time = match (event.end_time, channel_id) {
+            (Some(end_time), _) => Some(timestamp(end_time)?),
+            (None, None) => Some(timestamp(event.start_time + DEFAULT_EVENT_LENGTH)?),
+            (None, Some(_)) => None,
+        };
+
+        let created = self
+            .guild
+            .id
+            .create_scheduled_event(&self.service.cache_and_http().http, |e| {
+                e.name(event.name).start_time(start_time);
+
+                if let Some(description) = event.description {
+                    e.description(description);
+                }
+
+                if let Some(end_time) = end_time {
+                    e.end_time(end_time);
+                }
+
+                match channel_id {
+                    Some(channel_id) => {
+                        e.kind(guild::ScheduledEventType::Voice)
+                            .channel_id(channel_id);
+                    }
+                    None => {
+                        e.kind(guild::ScheduledEventType::External)
+                            .location(event.location.unwrap_or_default());
+                    }
+                }
+
+                e
+            })
+            .await
+            .map_err(ServiceError::from)?;
+
+        Ok(to_scheduled_event(created))
+    }
+
+    async fn create_emoji(&self, name: String, image: UploadImage) -> Result<String> {
+        let data = format!(
+            "data:image/{};base64,{}",
+            image.format,
+            base64::encode(&image.data)
+        );
+
+        let emoji = self
+            .guild
+            .id
+            .create_emoji(&self.service.cache_and_http().http, &name, &data)
+            .await
+            .map_err(ServiceError::from)?;
+
+        Ok(emoji.to_string())
+    }
+
+    async fn create_sticker(&self, sticker: StickerSettings) -> Result<String> {
+        let filename = format!("sticker.{}", sticker.image.format);
+
+        let created = self
+            .guild
+            .id
+            .create_sticker(&self.service.cache_and_http().http, |s| {
+                s.name(sticker.name)
+                    .description(sticker.description)
+                    .tags(sticker.tags)
+                    .file(AttachmentType::Bytes {
+                        data: sticker.image.data.into(),
+                        filename,
+                    })
+            })
+            .await
+            .map_err(ServiceError::from)?;
+
+        Ok(created.id.0.to_string())
+    }
+
+    async fn roles(&self) -> Result<Vec<ServerRole>> {
+        let mut roles: Vec<_> = self.guild.roles.values().collect();
+        roles.sort_by_key(|role| std::cmp::Reverse(role.position));
+
+        Ok(roles
+            .into_iter()
+            .map(|role| ServerRole {
+                id: role.id.0.to_string(),
+                name: role.name.clone(),
+            })
+            .collect())
+    }
+
+    async fn set_member_role(&self, user: u64, role_id: &str, has_role: bool) -> Result<()> {
+        let role_id: u64 = role_id.parse().map_err(|_| ServiceError::NotFound)?;
+        let cache_and_http = self.service.cache_and_http();
+        let http = &cache_and_http.http;
+
+        if has_role {
+            http.add_member_role(self.guild.id.0, user, role_id, None)
+                .await
+                .map_err(ServiceError::from)?;
+        } else {
+            http.remove_member_role(self.guild.id.0, user, role_id, None)
+                .await
+                .map_err(ServiceError::from)?;
+        }
+
+        Ok(())
+    }
+
+    async fn moderate(&self, user: u64, action: ModerationAction) -> Result<()> {
+        let cache_and_http = self.service.cache_and_http();
+        let http = &cache_and_http.http;
+
+        match action {
+            ModerationAction::Kick {
+                reason: Some(reason),
+            } => self.guild.id.kick_with_reason(http, user, &reason).await,
+            ModerationAction::Kick { reason: None } => self.guild.id.kick(http, user).await,
+            ModerationAction::Ban {
+                reason: Some(reason),
+            } => self.guild.id.ban_with_reason(http, user, 0, reason).await,
+            ModerationAction::Ban { reason: None } => self.guild.id.ban(http, user, 0).await,
+            ModerationAction::Unban => self.guild.id.unban(http, user).await,
+            ModerationAction::Timeout { until } => {
+                let until = until.map(timestamp).transpose()?;
+
+                self.guild
+                    .id
+                    .edit_member(http, user, |m| match until {
+                        Some(until) => m.disable_communication_until_datetime(until),
+                        None => m.enable_communication(),
+                    })
+                    .await
+                    .map(|_| ())
+            }
+        }
+        .map_err(ServiceError::from)?;
+
+        Ok(())
+    }
 }
 
 impl DiscordServer {
@@ -37,6 +37,10 @@ impl User<DiscordService> for DiscordUser {
         &self.nick
     }
 
+    fn mention(&self) -> String {
+        format!("<@{}>", self.user.id.0)
+    }
+
     fn avatar(&self) -> &Option<String> {
         &self.avatar
     }
@@ -0,0 +1,218 @@
+use governor::{
+    clock::DefaultClock,
+    state::{keyed::DefaultKeyedStateStore, InMemoryState, NotKeyed},
+    Quota, RateLimiter,
+};
+use lru::LruCache;
+use std::{
+    collections::{hash_map::DefaultHasher, HashMap},
+    hash::{Hash, Hasher},
+    num::NonZeroU32,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+use crate::message::MessagePriority;
+
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+const MAX_TRACKED_CHANNELS: usize = 1024;
+const MAX_DEDUP_CHANNELS: usize = 1024;
+
+#[derive(Clone, Copy, Debug)]
+pub struct SendLimits {
+    /// Messages that can be sent to a channel back to back
+    pub channel_burst: u32,
+    /// Time between messages to a channel once the burst is used up
+    pub channel_period: Duration,
+    pub service_per_second: u32,
+}
+
+/// What to do with an outgoing message, see [`SendQueue::check_repeat`]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Repeat {
+    Send,
+    /// Identical to the last message sent to the channel, within the window
+    Suppress,
+    /// Send it after summarizing how many copies of the previous message were suppressed
+    SendAfter(usize),
+}
+
+struct LastMessage {
+    hash: u64,
+    sent: Instant,
+    suppressed: usize,
+}
+
+struct Dedup<K: Hash + Eq> {
+    window: Duration,
+    last: Mutex<LruCache<K, LastMessage>>,
+}
+
+/// Holds back outgoing messages so they stay within the rate limits of a service, spreading out
+/// bursts. Messages waiting on a channel are sent in order of priority.
+pub struct SendQueue<K: Hash + Eq + Clone> {
+    channels: RateLimiter<K, DefaultKeyedStateStore<K>, DefaultClock>,
+    service: RateLimiter<NotKeyed, InMemoryState, DefaultClock>,
+    // Senders waiting on each channel, by priority
+    waiting: Mutex<HashMap<K, [usize; 3]>>,
+    dedup: Option<Dedup<K>>,
+    sent: AtomicU64,
+}
+
+impl<K: Hash + Eq + Clone> SendQueue<K> {
+    /// Amount of messages waiting to be sent on any channel
+    pub fn backlog(&self) -> usize {
+        self.waiting
+            .lock()
+            .unwrap()
+            .values()
+            .map(|counts| counts.iter().sum::<usize>())
+            .sum()
+    }
+
+    /// Amount of messages let through since the queue was created
+    pub fn sent(&self) -> u64 {
+        self.sent.load(Ordering::Relaxed)
+    }
+
+    pub fn new(limits: SendLimits) -> SendQueue<K> {
+        let channel_quota = Quota::with_period(limits.channel_period)
+            .expect("channel send period")
+            .allow_burst(NonZeroU32::new(limits.channel_burst).expect("channel send burst"));
+
+        SendQueue {
+            channels: RateLimiter::keyed(channel_quota),
+            service: RateLimiter::direct(Quota::per_second(
+                NonZeroU32::new(limits.service_per_second).expect("service send rate"),
+            )),
+            waiting: Default::default(),
+            dedup: None,
+            sent: AtomicU64::new(0),
+        }
+    }
+
+    /// Suppress messages identical to the last one sent to a channel within the window
+    pub fn with_dedup(mut self, window: Option<Duration>) -> SendQueue<K> {
+        self.dedup = window.map(|window| Dedup {
+            window,
+            last: Mutex::new(LruCache::new(MAX_DEDUP_CHANNELS)),
+        });
+        self
+    }
+
+    /// Check a message against the last one sent to the channel. Suppressed copies are only
+    /// summarized once a different message is sent, or the same one after the window.
+    pub fn check_repeat(&self, channel: K, content: &str) -> Repeat {
+        let dedup = match &self.dedup {
+            Some(dedup) => dedup,
+            None => return Repeat::Send,
+        };
+
+        let mut hasher = DefaultHasher::new();
+        content.hash(&mut hasher);
+        let hash = hasher.finish();
+
+        let mut last = dedup.last.lock().unwrap();
+
+        if let Some(last) = last.get_mut(&channel) {
+            if last.hash == hash && last.sent.elapsed() < dedup.window {
+                last.suppressed += 1;
+                return Repeat::Suppress;
+            }
+        }
+
+        let suppressed = last
+            .put(
+                channel,
+                LastMessage {
+                    hash,
+                    sent: Instant::now(),
+                    suppressed: 0,
+                },
+            )
+            .map(|last| last.suppressed)
+            .unwrap_or(0);
+
+        if suppressed > 0 {
+            Repeat::SendAfter(suppressed)
+        } else {
+            Repeat::Send
+        }
+    }
+
+    /// Wait until a message can be sent to the channel
+    pub async fn wait(&self, channel: K, priority: MessagePriority) {
+        let waiting = Waiting::new(self, channel, priority);
+
+        loop {
+            if !waiting.behind_higher_priority()
+                && waiting.queue.channels.check_key(&waiting.channel).is_ok()
+            {
+                break;
+            }
+
+            tokio::time::sleep(POLL_INTERVAL).await;
+        }
+
+        drop(waiting);
+
+        if self.channels.len() > MAX_TRACKED_CHANNELS {
+            self.channels.retain_recent();
+        }
+
+        self.service.until_ready().await;
+        self.sent.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+// Counts a sender as waiting on a channel, until it is dropped
+struct Waiting<'a, K: Hash + Eq + Clone> {
+    queue: &'a SendQueue<K>,
+    channel: K,
+    priority: usize,
+}
+
+impl<'a, K: Hash + Eq + Clone> Waiting<'a, K> {
+    fn new(queue: &'a SendQueue<K>, channel: K, priority: MessagePriority) -> Waiting<'a, K> {
+        let priority = priority as usize;
+
+        queue
+            .waiting
+            .lock()
+            .unwrap()
+            .entry(channel.clone())
+            .or_default()[priority] += 1;
+
+        Waiting {
+            queue,
+            channel,
+            priority,
+        }
+    }
+
+    fn behind_higher_priority(&self) -> bool {
+        let waiting = self.queue.waiting.lock().unwrap();
+
+        waiting
+            .get(&self.channel)
+            .map(|counts| counts[self.priority + 1..].iter().any(|&count| count > 0))
+            .unwrap_or(false)
+    }
+}
+
+impl<'a, K: Hash + Eq + Clone> Drop for Waiting<'a, K> {
+    fn drop(&mut self) {
+        let mut waiting = self.queue.waiting.lock().unwrap();
+
+        if let Some(counts) = waiting.get_mut(&self.channel) {
+            counts[self.priority] -= 1;
+
+            if counts.iter().all(|&count| count == 0) {
+                waiting.remove(&self.channel);
+            }
+        }
+    }
+}
@@ -0,0 +1,185 @@
+use anyhow::Result;
+use rand::Rng;
+use std::{
+    fmt::Display,
+    future::Future,
+    sync::{
+        atomic::{AtomicBool, AtomicU32, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+use crate::{
+    bot::{
+        events::{BotEvent, ConnectionState},
+        Bot,
+    },
+    services::ServiceError,
+};
+
+// How long a restarted connection gets to come back before it counts as a failed attempt
+const RESTART_TIMEOUT: Duration = Duration::from_secs(60);
+const RESTART_POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+#[derive(Clone, Copy, Debug)]
+pub struct Backoff {
+    pub initial: Duration,
+    pub max: Duration,
+    /// Consecutive failures before the connection is reported as unhealthy
+    pub alert_after: u32,
+}
+
+impl Default for Backoff {
+    fn default() -> Self {
+        Backoff {
+            initial: Duration::from_secs(1),
+            max: Duration::from_secs(300),
+            alert_after: 8,
+        }
+    }
+}
+
+impl Backoff {
+    /// Exponential delay for an attempt, with jitter so reconnecting services don't stampede
+    pub fn delay(&self, attempt: u32) -> Duration {
+        let exponent = attempt.saturating_sub(1).min(16);
+        let delay = self.initial.saturating_mul(1 << exponent).min(self.max);
+
+        delay.mul_f64(rand::thread_rng().gen_range(0.5..=1.0))
+    }
+}
+
+/// Tracks the connection of a service backend, backing off between failed attempts
+pub struct ConnectionSupervisor {
+    bot: Arc<Bot>,
+    service: &'static str,
+    backoff: Backoff,
+    attempt: AtomicU32,
+    connected_at: Mutex<Option<Instant>>,
+    has_connected: AtomicBool,
+    reconnects: AtomicU32,
+    restarting: AtomicBool,
+}
+
+impl ConnectionSupervisor {
+    pub fn new(bot: Arc<Bot>, service: &'static str, backoff: Backoff) -> ConnectionSupervisor {
+        ConnectionSupervisor {
+            bot,
+            service,
+            backoff,
+            attempt: AtomicU32::new(0),
+            connected_at: Mutex::new(None),
+            has_connected: AtomicBool::new(false),
+            reconnects: AtomicU32::new(0),
+            restarting: AtomicBool::new(false),
+        }
+    }
+
+    fn publish(&self, state: ConnectionState) {
+        self.bot.events().publish(BotEvent::Connection {
+            service: self.service,
+            state,
+        });
+    }
+
+    pub fn connected(&self) {
+        self.attempt.store(0, Ordering::Relaxed);
+        *self.connected_at.lock().unwrap() = Some(Instant::now());
+
+        if self.has_connected.swap(true, Ordering::Relaxed) {
+            self.reconnects.fetch_add(1, Ordering::Relaxed);
+        }
+
+        self.publish(ConnectionState::Connected);
+    }
+
+    /// The connection came back without going through a full reconnect
+    pub fn resumed(&self) {
+        *self.connected_at.lock().unwrap() = Some(Instant::now());
+        self.reconnects.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn disconnected(&self) {
+        *self.connected_at.lock().unwrap() = None;
+    }
+
+    /// How long the connection has been up, none while disconnected
+    pub fn uptime(&self) -> Option<Duration> {
+        self.connected_at
+            .lock()
+            .unwrap()
+            .map(|connected_at| connected_at.elapsed())
+    }
+
+    pub fn reconnects(&self) -> u32 {
+        self.reconnects.load(Ordering::Relaxed)
+    }
+
+    /// Consecutive failed attempts since the last successful connection
+    pub fn failed_attempts(&self) -> u32 {
+        self.attempt.load(Ordering::Relaxed)
+    }
+
+    /// Report a failed attempt and wait out the backoff before the caller retries
+    pub async fn failed(&self, action: &str, err: impl Display) {
+        let attempt = self.attempt.fetch_add(1, Ordering::Relaxed) + 1;
+        let delay = self.backoff.delay(attempt);
+
+        println!(
+            "Error {} ({}): {}, retrying in {:.1} seconds",
+            action,
+            self.service,
+            err,
+            delay.as_secs_f64()
+        );
+
+        if attempt == self.backoff.alert_after {
+            println!(
+                "{} has failed to connect {} times in a row",
+                self.service, attempt
+            );
+            self.publish(ConnectionState::Unhealthy { attempt });
+        }
+
+        self.publish(ConnectionState::Reconnecting { attempt, delay });
+
+        tokio::time::sleep(delay).await;
+    }
+
+    /// Tear down the connection with `restart` and wait for it to come back, restarting again
+    /// with backoff until it does
+    pub async fn restart<F, Fut>(&self, restart: F) -> Result<()>
+    where
+        F: Fn() -> Fut,
+        Fut: Future<Output = Result<()>>,
+    {
+        if self.restarting.swap(true, Ordering::Relaxed) {
+            return Err(ServiceError::Restarting.into());
+        }
+
+        println!("Restarting the {} connection", self.service);
+
+        let res = async {
+            loop {
+                self.disconnected();
+                restart().await?;
+
+                let started = Instant::now();
+                while self.uptime().is_none() && started.elapsed() < RESTART_TIMEOUT {
+                    tokio::time::sleep(RESTART_POLL_INTERVAL).await;
+                }
+
+                if self.uptime().is_some() {
+                    return Ok(());
+                }
+
+                self.failed("restarting the connection", "timed out").await;
+            }
+        }
+        .await;
+
+        self.restarting.store(false, Ordering::Relaxed);
+        res
+    }
+}
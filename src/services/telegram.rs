@@ -0,0 +1,634 @@
+use anyhow::Result;
+use arc_swap::ArcSwapOption;
+use futures::future::{AbortHandle, Abortable};
+use lru::LruCache;
+use regex::Regex;
+use serde_json::json;
+use std::{
+    collections::HashMap,
+    fmt,
+    str::FromStr,
+    sync::{
+        atomic::{AtomicI64, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+use thiserror::Error;
+
+mod api;
+mod channel;
+mod message;
+mod server;
+mod user;
+mod voice;
+
+use self::{
+    channel::TelegramChannel, message::TelegramMessage, server::TelegramServer, user::TelegramUser,
+    voice::TelegramVoiceConnection,
+};
+
+use super::{
+    queue::{SendLimits, SendQueue},
+    supervisor::{Backoff, ConnectionSupervisor},
+    ContentSegment, ServerId, Service, ServiceError, ServiceFeatures, ServiceKind, ServiceStatus,
+    UserPermissions,
+};
+use crate::{
+    bot::Bot,
+    message::{format::TextDialect, MessageModal},
+};
+
+// Telegram allows about 20 messages per minute in a group and 30 messages per second overall
+const SEND_LIMITS: SendLimits = SendLimits {
+    channel_burst: 3,
+    channel_period: Duration::from_secs(3),
+    service_per_second: 25,
+};
+
+// Long polling holds getUpdates open until an update arrives or the timeout passes
+const POLL_TIMEOUT: u64 = 30;
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(POLL_TIMEOUT + 10);
+
+// The bot api can't fetch past messages or look up users by name, so both are remembered
+const USER_CACHE_SIZE: usize = 256;
+const CHAT_CACHE_SIZE: usize = 256;
+const MESSAGE_CACHE_SIZE: usize = 1024;
+
+pub struct TelegramService {
+    bot: Arc<Bot>,
+    api: api::TelegramApi,
+    me: ArcSwapOption<api::User>,
+    poll_abort: Mutex<Option<AbortHandle>>,
+    // Id of the next update to fetch, kept across restarts so no updates are lost
+    offset: AtomicI64,
+    user_cache: Mutex<LruCache<i64, api::User>>,
+    chat_cache: Mutex<LruCache<i64, api::Chat>>,
+    message_cache: Mutex<LruCache<TelegramMessageId, api::Message>>,
+    supervisor: ConnectionSupervisor,
+    send_queue: SendQueue<i64>,
+}
+
+#[derive(Clone, Deserialize, Serialize, Debug)]
+pub struct TelegramServiceConfig {
+    /// Token of the bot, as given by @BotFather
+    pub token: String,
+    /// Seconds identical messages to a chat are suppressed for, off unless set
+    pub dedup_window: Option<u64>,
+}
+
+/// Message ids are only unique within their chat
+#[derive(Clone, Copy, Debug, Hash, PartialEq, Eq)]
+pub struct TelegramMessageId {
+    pub chat_id: i64,
+    pub message_id: i64,
+}
+
+impl fmt::Display for TelegramMessageId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.chat_id, self.message_id)
+    }
+}
+
+impl FromStr for TelegramMessageId {
+    type Err = anyhow::Error;
+
+    fn from_str(text: &str) -> Result<TelegramMessageId> {
+        let invalid = || TelegramError::InvalidMessageId(text.to_string());
+        let (chat_id, message_id) = text.split_once('/').ok_or_else(invalid)?;
+
+        Ok(TelegramMessageId {
+            chat_id: chat_id.parse().map_err(|_| invalid())?,
+            message_id: message_id.parse().map_err(|_| invalid())?,
+        })
+    }
+}
+
+impl TelegramService {
+    fn me(&self) -> api::User {
+        (*self.me.load_full().unwrap()).clone()
+    }
+
+    fn start_polling(self: &Arc<Self>) {
+        let (abort_handle, abort_registration) = AbortHandle::new_pair();
+
+        if let Some(old) = self.poll_abort.lock().unwrap().replace(abort_handle) {
+            old.abort();
+        }
+
+        tokio::spawn(Abortable::new(self.clone().poll(), abort_registration));
+    }
+
+    async fn poll(self: Arc<Self>) {
+        loop {
+            let res = tokio::time::timeout(
+                REQUEST_TIMEOUT,
+                self.api.call::<Vec<serde_json::Value>>(
+                    "getUpdates",
+                    json!({
+                        "offset": self.offset.load(Ordering::Relaxed),
+                        "timeout": POLL_TIMEOUT,
+                        "allowed_updates": ["message", "edited_message", "message_reaction"],
+                    }),
+                ),
+            )
+            .await
+            .unwrap_or_else(|_| Err(TelegramError::Timeout.into()));
+
+            let updates = match res {
+                Ok(updates) => updates,
+                Err(err) => {
+                    self.supervisor.disconnected();
+                    self.supervisor.failed("polling telegram", err).await;
+                    continue;
+                }
+            };
+
+            if self.supervisor.uptime().is_none() {
+                self.supervisor.connected();
+            }
+
+            for update in updates {
+                // Updates that fail to parse are skipped instead of being fetched again forever
+                if let Some(id) = update.get("update_id").and_then(|id| id.as_i64()) {
+                    self.offset.store(id + 1, Ordering::Relaxed);
+                }
+
+                match serde_json::from_value::<api::Update>(update) {
+                    Ok(update) => {
+                        let service = self.clone();
+                        tokio::spawn(async move { service.handle_update(update).await });
+                    }
+                    Err(err) => println!("error parsing telegram update: {}", err.to_string()),
+                }
+            }
+        }
+    }
+
+    async fn handle_update(self: &Arc<Self>, update: api::Update) {
+        if let Some(msg) = update.message {
+            self.handle_message(msg).await;
+        }
+
+        if let Some(msg) = update.edited_message {
+            self.handle_edit(msg).await;
+        }
+
+        if let Some(reaction) = update.message_reaction {
+            self.handle_reaction(reaction).await;
+        }
+    }
+
+    async fn handle_message(self: &Arc<Self>, msg: api::Message) {
+        let server_id = ServerId::Telegram(msg.chat.id);
+
+        for user in &msg.new_chat_members {
+            let user = Arc::new(TelegramUser::new(user.clone(), self.clone()));
+            self.bot.member(server_id, user, true).await;
+        }
+
+        if let Some(user) = &msg.left_chat_member {
+            let user = Arc::new(TelegramUser::new(user.clone(), self.clone()));
+            self.bot.member(server_id, user, false).await;
+        }
+
+        self.cache_message(&msg);
+
+        if msg.from.is_none() || (msg.text.is_none() && msg.caption.is_none()) {
+            return;
+        }
+
+        self.bot.message(self.wrap_message(msg)).await;
+    }
+
+    async fn handle_edit(self: &Arc<Self>, msg: api::Message) {
+        let old = self.cache_message(&msg);
+
+        if msg.from.is_none() {
+            return;
+        }
+
+        let old_msg = old.map(|old| self.wrap_message(old) as Arc<_>);
+        self.bot
+            .message_update(self.wrap_message(msg), old_msg)
+            .await;
+    }
+
+    async fn handle_reaction(self: &Arc<Self>, reaction: api::MessageReactionUpdated) {
+        // Anonymous admins react on behalf of the chat, without a user
+        let user = match reaction.user {
+            Some(user) => user,
+            None => return,
+        };
+
+        let id = TelegramMessageId {
+            chat_id: reaction.chat.id,
+            message_id: reaction.message_id,
+        };
+
+        // Only the id of the message comes with the update
+        let msg = match self.cached_message(id) {
+            Some(msg) => self.wrap_message(msg),
+            None => return,
+        };
+
+        let emoji = |reactions: &[api::ReactionType]| -> Vec<String> {
+            reactions
+                .iter()
+                .filter_map(|reaction| match reaction {
+                    api::ReactionType::Emoji { emoji } => Some(emoji.clone()),
+                    _ => None,
+                })
+                .collect()
+        };
+
+        let old = emoji(&reaction.old_reaction);
+        let new = emoji(&reaction.new_reaction);
+        let reactor = Arc::new(TelegramUser::new(user, self.clone()));
+
+        for emoji in new.iter().filter(|emoji| !old.contains(emoji)) {
+            self.bot
+                .reaction(msg.clone(), reactor.clone(), emoji.clone(), false)
+                .await;
+        }
+
+        for emoji in old.iter().filter(|emoji| !new.contains(emoji)) {
+            self.bot
+                .reaction(msg.clone(), reactor.clone(), emoji.clone(), true)
+                .await;
+        }
+    }
+
+    fn wrap_message(self: &Arc<Self>, msg: api::Message) -> Arc<TelegramMessage> {
+        let author = msg.from.clone().unwrap_or_else(|| self.me());
+
+        Arc::new(TelegramMessage::new(msg, author, self.clone()))
+    }
+
+    /// Remember a message along with its chat and author, returns the previous version
+    fn cache_message(&self, msg: &api::Message) -> Option<api::Message> {
+        if let Some(user) = &msg.from {
+            self.user_cache.lock().unwrap().put(user.id, user.clone());
+        }
+
+        self.chat_cache
+            .lock()
+            .unwrap()
+            .put(msg.chat.id, msg.chat.clone());
+
+        let id = TelegramMessageId {
+            chat_id: msg.chat.id,
+            message_id: msg.message_id,
+        };
+
+        self.message_cache.lock().unwrap().put(id, msg.clone())
+    }
+
+    fn cached_message(&self, id: TelegramMessageId) -> Option<api::Message> {
+        self.message_cache.lock().unwrap().get(&id).cloned()
+    }
+
+    /// Cached messages of a chat, newest first
+    fn cached_messages(&self, chat_id: i64) -> Vec<api::Message> {
+        let mut messages: Vec<api::Message> = self
+            .message_cache
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|(id, _)| id.chat_id == chat_id)
+            .map(|(_, msg)| msg.clone())
+            .collect();
+
+        messages.sort_by(|a, b| b.message_id.cmp(&a.message_id));
+        messages
+    }
+
+    fn forget_message(&self, id: TelegramMessageId) {
+        self.message_cache.lock().unwrap().pop(&id);
+    }
+
+    async fn chat(&self, id: i64) -> Result<api::Chat> {
+        if let Some(chat) = self.chat_cache.lock().unwrap().get(&id) {
+            return Ok(chat.clone());
+        }
+
+        let chat: api::Chat = self.api.call("getChat", json!({ "chat_id": id })).await?;
+        self.chat_cache.lock().unwrap().put(id, chat.clone());
+
+        Ok(chat)
+    }
+}
+
+#[async_trait]
+impl Service for TelegramService {
+    const KIND: ServiceKind = ServiceKind::Telegram;
+    const ID: &'static str = "telegram";
+    const ID_SHORT: &'static str = "tg";
+    const NAME: &'static str = "Telegram";
+    const FEATURES: ServiceFeatures = ServiceFeatures::from_bits_truncate(
+        ServiceFeatures::EDIT.bits() | ServiceFeatures::REACT.bits(),
+    );
+    const MAX_MESSAGE_LENGTH: usize = 4096;
+    const MAX_MESSAGE_LINES: usize = 40;
+    const TEXT_DIALECT: TextDialect = TextDialect::TelegramHtml;
+
+    type ServiceConfig = TelegramServiceConfig;
+    type Message = TelegramMessage;
+    type User = TelegramUser;
+    type Channel = TelegramChannel;
+    type Server = TelegramServer;
+    type VoiceConnection = TelegramVoiceConnection;
+
+    type MessageId = TelegramMessageId;
+    type ChannelId = i64;
+    type ServerId = i64;
+    type UserId = i64;
+    type InteractionId = u64;
+
+    async fn init(bot: Arc<Bot>, config: Self::ServiceConfig) -> Result<Arc<Self>> {
+        let service = Arc::new(TelegramService {
+            bot: bot.clone(),
+            api: api::TelegramApi::new(config.token),
+            me: ArcSwapOption::new(None),
+            poll_abort: Default::default(),
+            offset: AtomicI64::new(0),
+            user_cache: Mutex::new(LruCache::new(USER_CACHE_SIZE)),
+            chat_cache: Mutex::new(LruCache::new(CHAT_CACHE_SIZE)),
+            message_cache: Mutex::new(LruCache::new(MESSAGE_CACHE_SIZE)),
+            supervisor: ConnectionSupervisor::new(bot, Self::ID, Backoff::default()),
+            send_queue: SendQueue::new(SEND_LIMITS)
+                .with_dedup(config.dedup_window.map(Duration::from_secs)),
+        });
+
+        loop {
+            match service.api.call::<api::User>("getMe", json!({})).await {
+                Ok(me) => {
+                    println!("{} is connected!", me.full_name());
+                    service.me.store(Some(Arc::new(me)));
+                    break;
+                }
+                Err(err) => {
+                    service
+                        .supervisor
+                        .failed("connecting to telegram", err)
+                        .await
+                }
+            }
+        }
+
+        service.start_polling();
+
+        Ok(service)
+    }
+
+    async fn unload(&self) -> Result<()> {
+        if let Some(abort_handle) = self.poll_abort.lock().unwrap().take() {
+            abort_handle.abort();
+        }
+
+        Ok(())
+    }
+
+    async fn current_user(self: &Arc<Self>) -> Result<Arc<TelegramUser>> {
+        Ok(Arc::new(TelegramUser::new(self.me(), self.clone())))
+    }
+
+    async fn message(
+        self: &Arc<Self>,
+        channel_id: i64,
+        id: TelegramMessageId,
+    ) -> Result<Arc<TelegramMessage>> {
+        match self.cached_message(id) {
+            Some(msg) if msg.chat.id == channel_id => Ok(self.wrap_message(msg)),
+            _ => Err(ServiceError::NotFound.into()),
+        }
+    }
+
+    async fn server(self: &Arc<Self>, id: i64) -> Result<Arc<TelegramServer>> {
+        Ok(Arc::new(TelegramServer::new(
+            self.chat(id).await?,
+            self.clone(),
+        )))
+    }
+
+    async fn channel(self: &Arc<Self>, id: i64) -> Result<Arc<TelegramChannel>> {
+        Ok(Arc::new(TelegramChannel::new(
+            self.chat(id).await?,
+            self.clone(),
+        )))
+    }
+
+    async fn user(self: &Arc<Self>, id: i64) -> Result<Arc<TelegramUser>> {
+        let user = self.user_cache.lock().unwrap().get(&id).cloned();
+
+        match user {
+            Some(user) => Ok(Arc::new(TelegramUser::new(user, self.clone()))),
+            None => Ok(Arc::new(TelegramUser::from_chat(
+                self.chat(id).await?,
+                self.clone(),
+            ))),
+        }
+    }
+
+    // Private chats share the id of the user, they only work after the user has started the bot
+    async fn dm_channel(self: &Arc<Self>, id: i64) -> Result<Arc<TelegramChannel>> {
+        self.channel(id).await
+    }
+
+    async fn find_user(self: &Arc<Self>, channel_id: i64, find: &str) -> Result<Arc<TelegramUser>> {
+        lazy_static::lazy_static! {
+            static ref USER_LINK_RE: Regex = Regex::new(r"^tg://user\?id=(\d+)$").unwrap();
+        }
+
+        let find = find.trim();
+        let id = USER_LINK_RE
+            .captures(find)
+            .and_then(|captures| captures[1].parse().ok())
+            .or_else(|| i64::from_str(find).ok());
+
+        if let Some(id) = id {
+            let member: api::ChatMember = self
+                .api
+                .call(
+                    "getChatMember",
+                    json!({
+                        "chat_id": channel_id,
+                        "user_id": id,
+                    }),
+                )
+                .await?;
+
+            return Ok(Arc::new(TelegramUser::new(member.user, self.clone())));
+        }
+
+        // Usernames can't be looked up through the bot api, only users seen before are found
+        let name = find.trim_start_matches('@');
+        let user = self
+            .user_cache
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(_, user)| user)
+            .find(|user| match &user.username {
+                Some(username) => username.eq_ignore_ascii_case(name),
+                None => false,
+            })
+            .cloned();
+
+        match user {
+            Some(user) => Ok(Arc::new(TelegramUser::new(user, self.clone()))),
+            None => Err(ServiceError::UnknownUser(find.to_string()).into()),
+        }
+    }
+
+    // Chats can only be found by id or by the username of public chats
+    async fn find_channel(
+        self: &Arc<Self>,
+        _channel_id: i64,
+        find: &str,
+    ) -> Result<Arc<TelegramChannel>> {
+        let find = find.trim();
+
+        let chat_id = match i64::from_str(find) {
+            Ok(id) => json!(id),
+            Err(_) if find.starts_with('@') => json!(find),
+            Err(_) => return Err(ServiceError::UnknownChannel(find.to_string()).into()),
+        };
+
+        let chat: api::Chat = self
+            .api
+            .call("getChat", json!({ "chat_id": chat_id }))
+            .await?;
+        self.chat_cache.lock().unwrap().put(chat.id, chat.clone());
+
+        Ok(Arc::new(TelegramChannel::new(chat, self.clone())))
+    }
+
+    async fn user_permissions(
+        self: &Arc<Self>,
+        channel_id: i64,
+        user_id: i64,
+    ) -> Result<UserPermissions> {
+        // Nobody moderates private chats
+        if self.chat(channel_id).await?.is_private() {
+            return Ok(UserPermissions::empty());
+        }
+
+        let member: api::ChatMember = self
+            .api
+            .call(
+                "getChatMember",
+                json!({
+                    "chat_id": channel_id,
+                    "user_id": user_id,
+                }),
+            )
+            .await?;
+
+        match member.status.as_str() {
+            "creator" => return Ok(UserPermissions::all()),
+            "administrator" => {}
+            _ => return Ok(UserPermissions::empty()),
+        }
+
+        let permissions = &[
+            (member.can_delete_messages, UserPermissions::MANAGE_MESSAGES),
+            (
+                member.can_change_info,
+                UserPermissions::MANAGE_SERVER | UserPermissions::MANAGE_CHANNELS,
+            ),
+            (member.can_promote_members, UserPermissions::MANAGE_ROLES),
+            (
+                member.can_restrict_members,
+                UserPermissions::KICK_MEMBERS
+                    | UserPermissions::BAN_MEMBERS
+                    | UserPermissions::TIMEOUT_MEMBERS,
+            ),
+        ];
+
+        let mut normalized = UserPermissions::empty();
+
+        for (allowed, permission) in permissions {
+            if *allowed {
+                normalized |= *permission;
+            }
+        }
+
+        Ok(normalized)
+    }
+
+    async fn react(
+        self: &Arc<Self>,
+        channel_id: i64,
+        msg_id: TelegramMessageId,
+        reaction: String,
+    ) -> Result<()> {
+        self.api
+            .call::<bool>(
+                "setMessageReaction",
+                json!({
+                    "chat_id": channel_id,
+                    "message_id": msg_id.message_id,
+                    "reaction": [{ "type": "emoji", "emoji": reaction }],
+                }),
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    async fn open_modal(
+        self: &Arc<Self>,
+        _interaction_id: u64,
+        _modal: MessageModal,
+    ) -> Result<HashMap<String, String>> {
+        Err(TelegramError::Unsupported("modals").into())
+    }
+
+    async fn join_voice(
+        &self,
+        _server_id: i64,
+        _channel_id: i64,
+    ) -> Result<Arc<TelegramVoiceConnection>> {
+        Err(TelegramError::Unsupported("voice").into())
+    }
+
+    async fn status(self: &Arc<Self>) -> Result<ServiceStatus> {
+        Ok(ServiceStatus {
+            uptime: self.supervisor.uptime(),
+            reconnects: self.supervisor.reconnects(),
+            failed_attempts: self.supervisor.failed_attempts(),
+            backlog: self.send_queue.backlog(),
+            sent: self.send_queue.sent(),
+            shards: vec![],
+        })
+    }
+
+    async fn restart(self: &Arc<Self>) -> Result<()> {
+        // Polling starts over from the stored offset, the send queue stays up
+        self.supervisor
+            .restart(|| async move {
+                self.start_polling();
+                Ok(())
+            })
+            .await
+    }
+
+    fn parse_content(content: &str) -> Vec<ContentSegment> {
+        message::parse_content(content)
+    }
+}
+
+#[derive(Error, Debug)]
+pub enum TelegramError {
+    #[error("telegram does not support {}", _0)]
+    Unsupported(&'static str),
+    #[error("{}", _0)]
+    Api(String),
+    #[error("invalid telegram message id \"{}\"", _0)]
+    InvalidMessageId(String),
+    #[error("the message has no content")]
+    EmptyMessage,
+    #[error("timed out waiting for telegram")]
+    Timeout,
+}
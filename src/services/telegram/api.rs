@@ -0,0 +1,242 @@
+use anyhow::Result;
+use hyper::{client::HttpConnector, header::CONTENT_TYPE, Body, Client, Request};
+use hyper_tls::HttpsConnector;
+use serde::de::DeserializeOwned;
+
+use super::TelegramError;
+use crate::services::ServiceError;
+
+const API_URL: &str = "https://api.telegram.org";
+
+/// Client for the Telegram Bot API, every method is a POST with JSON or form parameters
+pub struct TelegramApi {
+    token: String,
+    client: Client<HttpsConnector<HttpConnector>>,
+}
+
+#[derive(Deserialize)]
+struct ApiResponse<T> {
+    ok: bool,
+    result: Option<T>,
+    description: Option<String>,
+    error_code: Option<u16>,
+}
+
+impl TelegramApi {
+    pub fn new(token: String) -> TelegramApi {
+        TelegramApi {
+            token,
+            client: Client::builder().build::<_, Body>(HttpsConnector::new()),
+        }
+    }
+
+    pub async fn call<T: DeserializeOwned>(
+        &self,
+        method: &str,
+        params: serde_json::Value,
+    ) -> Result<T> {
+        let req = Request::builder()
+            .method("POST")
+            .uri(self.url(method))
+            .header(CONTENT_TYPE, "application/json")
+            .body(Body::from(serde_json::to_vec(&params)?))?;
+
+        self.send(req).await
+    }
+
+    /// Call a method with a file, the other parameters are sent as form fields
+    pub async fn upload<T: DeserializeOwned>(
+        &self,
+        method: &str,
+        params: serde_json::Value,
+        field: &str,
+        filename: &str,
+        data: Vec<u8>,
+    ) -> Result<T> {
+        let boundary = format!("kaito{:016x}", rand::random::<u64>());
+        let filename: String = filename
+            .chars()
+            .filter(|c| !matches!(c, '"' | '\r' | '\n'))
+            .collect();
+
+        let mut body = Vec::with_capacity(data.len() + 512);
+
+        if let serde_json::Value::Object(params) = params {
+            for (name, value) in params {
+                let value = match value {
+                    serde_json::Value::String(value) => value,
+                    value => value.to_string(),
+                };
+
+                body.extend_from_slice(
+                    format!(
+                        "--{}\r\nContent-Disposition: form-data; name=\"{}\"\r\n\r\n{}\r\n",
+                        boundary, name, value
+                    )
+                    .as_bytes(),
+                );
+            }
+        }
+
+        body.extend_from_slice(
+            format!(
+                "--{}\r\nContent-Disposition: form-data; name=\"{}\"; filename=\"{}\"\r\n\
+                 Content-Type: application/octet-stream\r\n\r\n",
+                boundary, field, filename
+            )
+            .as_bytes(),
+        );
+        body.extend_from_slice(&data);
+        body.extend_from_slice(format!("\r\n--{}--\r\n", boundary).as_bytes());
+
+        let req = Request::builder()
+            .method("POST")
+            .uri(self.url(method))
+            .header(
+                CONTENT_TYPE,
+                format!("multipart/form-data; boundary={}", boundary),
+            )
+            .body(Body::from(body))?;
+
+        self.send(req).await
+    }
+
+    fn url(&self, method: &str) -> String {
+        format!("{}/bot{}/{}", API_URL, self.token, method)
+    }
+
+    async fn send<T: DeserializeOwned>(&self, req: Request<Body>) -> Result<T> {
+        let res = self.client.request(req).await?;
+        let body = hyper::body::to_bytes(res.into_body()).await?;
+        let res: ApiResponse<T> = serde_json::from_slice(&body)?;
+
+        match res.result {
+            Some(result) if res.ok => Ok(result),
+            _ => Err(api_error(
+                res.error_code,
+                res.description.unwrap_or_default(),
+            )),
+        }
+    }
+}
+
+fn api_error(code: Option<u16>, description: String) -> anyhow::Error {
+    match code {
+        Some(403) => ServiceError::Forbidden.into(),
+        Some(429) => ServiceError::RateLimited.into(),
+        // Missing chats, users and messages are all reported as bad requests
+        _ if description.contains("not found") => ServiceError::NotFound.into(),
+        _ => TelegramError::Api(description).into(),
+    }
+}
+
+/// Content that isn't valid HTML is rejected instead of being sent as it was written
+pub fn is_parse_error(err: &anyhow::Error) -> bool {
+    matches!(
+        err.downcast_ref::<TelegramError>(),
+        Some(TelegramError::Api(description)) if description.contains("can't parse entities")
+    )
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct Update {
+    pub update_id: i64,
+    pub message: Option<Message>,
+    pub edited_message: Option<Message>,
+    pub message_reaction: Option<MessageReactionUpdated>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct User {
+    pub id: i64,
+    #[serde(default)]
+    pub is_bot: bool,
+    pub first_name: String,
+    pub last_name: Option<String>,
+    pub username: Option<String>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct Chat {
+    pub id: i64,
+    /// private, group, supergroup or channel
+    #[serde(rename = "type")]
+    pub kind: String,
+    pub title: Option<String>,
+    pub username: Option<String>,
+    pub first_name: Option<String>,
+    pub last_name: Option<String>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct Message {
+    pub message_id: i64,
+    pub from: Option<User>,
+    pub chat: Chat,
+    pub date: i64,
+    pub text: Option<String>,
+    pub caption: Option<String>,
+    pub reply_to_message: Option<Box<Message>>,
+    #[serde(default)]
+    pub new_chat_members: Vec<User>,
+    pub left_chat_member: Option<User>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct MessageReactionUpdated {
+    pub chat: Chat,
+    pub message_id: i64,
+    pub user: Option<User>,
+    pub old_reaction: Vec<ReactionType>,
+    pub new_reaction: Vec<ReactionType>,
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ReactionType {
+    Emoji { emoji: String },
+    CustomEmoji { custom_emoji_id: String },
+    Paid,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct ChatMember {
+    /// creator, administrator, member, restricted, left or kicked
+    pub status: String,
+    pub user: User,
+    #[serde(default)]
+    pub can_delete_messages: bool,
+    #[serde(default)]
+    pub can_restrict_members: bool,
+    #[serde(default)]
+    pub can_change_info: bool,
+    #[serde(default)]
+    pub can_promote_members: bool,
+}
+
+impl Chat {
+    pub fn name(&self) -> String {
+        if let Some(title) = &self.title {
+            return title.clone();
+        }
+
+        match (&self.first_name, &self.last_name) {
+            (Some(first), Some(last)) => format!("{} {}", first, last),
+            (Some(first), None) => first.clone(),
+            _ => self.username.clone().unwrap_or_default(),
+        }
+    }
+
+    pub fn is_private(&self) -> bool {
+        self.kind == "private"
+    }
+}
+
+impl User {
+    pub fn full_name(&self) -> String {
+        match &self.last_name {
+            Some(last) => format!("{} {}", self.first_name, last),
+            None => self.first_name.clone(),
+        }
+    }
+}
@@ -0,0 +1,304 @@
+use anyhow::Result;
+use serde_json::json;
+use std::sync::Arc;
+
+use super::{
+    api,
+    message::{render_embed, sanitize_content, TelegramMessage},
+    server::TelegramServer,
+    TelegramError, TelegramMessageId, TelegramService,
+};
+use crate::{
+    message::{MessageContent, MessagePriority, MessageSettings, ToMessageContent},
+    services::{
+        queue::Repeat, Channel, ChannelId, ForumPost, ForumTag, MessageId, Service, ServiceError,
+    },
+};
+
+// Telegram only deletes messages of the last 48 hours, up to 100 at a time
+const MAX_PURGE: u64 = 100;
+const DELETE_MAX_AGE: i64 = 48 * 60 * 60;
+
+pub struct TelegramChannel {
+    chat: api::Chat,
+    service: Arc<TelegramService>,
+}
+
+impl TelegramChannel {
+    pub fn new(chat: api::Chat, service: Arc<TelegramService>) -> TelegramChannel {
+        TelegramChannel { chat, service }
+    }
+
+    pub fn inner(&self) -> &api::Chat {
+        &self.chat
+    }
+
+    // Note on the copies of the previous message that were held back
+    async fn send_repeat_summary(&self, count: usize, priority: MessagePriority) {
+        self.service.send_queue.wait(self.chat.id, priority).await;
+
+        if let Err(err) = self
+            .service
+            .api
+            .call::<api::Message>(
+                "sendMessage",
+                json!({
+                    "chat_id": self.chat.id,
+                    "text": format!("(repeated x{})", count),
+                }),
+            )
+            .await
+        {
+            println!("error sending repeat summary: {}", err.to_string());
+        }
+    }
+
+    /// Send text as HTML, content that isn't valid HTML is sent again as plain text
+    async fn send_text(&self, text: String, reply_to: Option<i64>) -> Result<api::Message> {
+        let mut params = json!({
+            "chat_id": self.chat.id,
+            "text": text,
+            "parse_mode": "HTML",
+        });
+
+        if let Some(reply_to) = reply_to {
+            params["reply_parameters"] = reply_parameters(reply_to);
+        }
+
+        match self.service.api.call("sendMessage", params.clone()).await {
+            Err(err) if api::is_parse_error(&err) => {
+                params.as_object_mut().unwrap().remove("parse_mode");
+                self.service.api.call("sendMessage", params).await
+            }
+            res => res,
+        }
+    }
+
+    async fn send_document(
+        &self,
+        filename: &str,
+        data: Vec<u8>,
+        reply_to: Option<i64>,
+    ) -> Result<api::Message> {
+        let mut params = json!({ "chat_id": self.chat.id });
+
+        if let Some(reply_to) = reply_to {
+            params["reply_parameters"] = reply_parameters(reply_to);
+        }
+
+        self.service
+            .api
+            .upload("sendDocument", params, "document", filename, data)
+            .await
+    }
+}
+
+fn reply_parameters(message_id: i64) -> serde_json::Value {
+    json!({
+        "message_id": message_id,
+        "allow_sending_without_reply": true,
+    })
+}
+
+#[async_trait]
+impl Channel<TelegramService> for TelegramChannel {
+    fn id(&self) -> ChannelId {
+        ChannelId::Telegram(self.chat.id)
+    }
+
+    fn name(&self) -> String {
+        self.chat.name()
+    }
+
+    // There is no way to fetch past messages, only those seen since the bot started are known
+    async fn messages(
+        &self,
+        limit: u64,
+        before: Option<TelegramMessageId>,
+    ) -> Result<Vec<Arc<TelegramMessage>>> {
+        Ok(self
+            .service
+            .cached_messages(self.chat.id)
+            .into_iter()
+            .filter(|msg| match before {
+                Some(before) => msg.message_id < before.message_id,
+                None => true,
+            })
+            .take(limit as usize)
+            .map(|msg| self.service.wrap_message(msg))
+            .collect())
+    }
+
+    async fn send<'a, C>(
+        &self,
+        content: C,
+        settings: MessageSettings,
+    ) -> Result<Arc<TelegramMessage>>
+    where
+        C: ToMessageContent<'a>,
+    {
+        let content = match content.to_message_content() {
+            MessageContent::String(text) => text,
+            MessageContent::Str(text) => text.to_string(),
+        };
+        let mut content = sanitize_content(content, settings.sanitize);
+
+        let mut embed = settings.embed;
+        if let Some(gif) = settings.gif {
+            embed
+                .get_or_insert_with(Default::default)
+                .image
+                .get_or_insert(gif);
+        }
+
+        if let Some(embed) = embed {
+            if !content.is_empty() {
+                content.push_str("\n\n");
+            }
+
+            content.push_str(&render_embed(embed));
+        }
+
+        let as_file = content.chars().count() > TelegramService::MAX_MESSAGE_LENGTH;
+
+        if !content.is_empty() {
+            match self.service.send_queue.check_repeat(self.chat.id, &content) {
+                Repeat::Send => {}
+                Repeat::Suppress => return Err(ServiceError::Duplicate.into()),
+                Repeat::SendAfter(count) => {
+                    self.send_repeat_summary(count, settings.priority).await
+                }
+            }
+        }
+
+        let reply_to = match settings.reply {
+            Some(MessageId::Telegram(id)) if id.chat_id == self.chat.id => Some(id.message_id),
+            _ => None,
+        };
+
+        // Telegram has no messages with both text and files, each part is a message of its own
+        let mut sent = Vec::new();
+
+        if !content.is_empty() {
+            self.service
+                .send_queue
+                .wait(self.chat.id, settings.priority)
+                .await;
+
+            sent.push(if as_file {
+                self.send_document("message.txt", content.into_bytes(), reply_to)
+                    .await?
+            } else {
+                self.send_text(content, reply_to).await?
+            });
+        }
+
+        for sticker in settings.stickers {
+            self.service
+                .send_queue
+                .wait(self.chat.id, settings.priority)
+                .await;
+
+            sent.push(
+                self.service
+                    .api
+                    .call(
+                        "sendSticker",
+                        json!({
+                            "chat_id": self.chat.id,
+                            "sticker": sticker,
+                        }),
+                    )
+                    .await?,
+            );
+        }
+
+        for (filename, data) in settings.attachments {
+            self.service
+                .send_queue
+                .wait(self.chat.id, settings.priority)
+                .await;
+
+            sent.push(self.send_document(&filename, data, reply_to).await?);
+        }
+
+        for msg in &sent {
+            self.service.cache_message(msg);
+        }
+
+        // The first part stands in for the rest, like the text does for its attachments
+        let msg = sent.into_iter().next().ok_or(TelegramError::EmptyMessage)?;
+
+        Ok(self.service.wrap_message(msg))
+    }
+
+    async fn server(&self) -> Result<Arc<TelegramServer>> {
+        Ok(Arc::new(TelegramServer::new(
+            self.chat.clone(),
+            self.service.clone(),
+        )))
+    }
+
+    async fn send_typing(&self) -> Result<()> {
+        self.service
+            .api
+            .call::<bool>(
+                "sendChatAction",
+                json!({
+                    "chat_id": self.chat.id,
+                    "action": "typing",
+                }),
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    fn forum_tags(&self) -> Option<Vec<ForumTag>> {
+        None
+    }
+
+    async fn create_post(&self, _post: ForumPost) -> Result<Arc<TelegramChannel>> {
+        Err(TelegramError::Unsupported("forum posts").into())
+    }
+
+    async fn purge(&self, count: u64) -> Result<usize> {
+        let cutoff = chrono::Utc::now().timestamp() - DELETE_MAX_AGE;
+        let ids: Vec<i64> = self
+            .service
+            .cached_messages(self.chat.id)
+            .into_iter()
+            .filter(|msg| msg.date > cutoff)
+            .take(count.min(MAX_PURGE) as usize)
+            .map(|msg| msg.message_id)
+            .collect();
+
+        if ids.is_empty() {
+            return Ok(0);
+        }
+
+        self.service
+            .api
+            .call::<bool>(
+                "deleteMessages",
+                json!({
+                    "chat_id": self.chat.id,
+                    "message_ids": ids,
+                }),
+            )
+            .await?;
+
+        for id in &ids {
+            self.service.forget_message(TelegramMessageId {
+                chat_id: self.chat.id,
+                message_id: *id,
+            });
+        }
+
+        Ok(ids.len())
+    }
+
+    fn service(&self) -> &Arc<TelegramService> {
+        &self.service
+    }
+}
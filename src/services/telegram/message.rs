@@ -0,0 +1,288 @@
+use anyhow::Result;
+use regex::Regex;
+use serde_json::json;
+use std::sync::Arc;
+
+use super::{
+    api, channel::TelegramChannel, user::TelegramUser, TelegramMessageId, TelegramService,
+};
+use crate::{
+    message::{
+        format::{render, RichText, TextDialect},
+        Attachment, MessageContent, MessageEmbed, MessageSettings, Sanitize, ToMessageContent,
+    },
+    services::{ContentSegment, Message, MessageId},
+    utils::ci_regex,
+};
+
+// File urls contain the bot token, so attachments of received messages aren't passed on
+const NO_ATTACHMENTS: &[Arc<Attachment>] = &[];
+
+pub fn escape_html(text: &str) -> String {
+    render(&[RichText::Text(text.into())], TextDialect::TelegramHtml)
+}
+
+/// Neutralize mentions and invites with zero width spaces, so they're shown as plain text
+pub fn sanitize_content(content: String, sanitize: Sanitize) -> String {
+    lazy_static::lazy_static! {
+        static ref MENTION_RE: Regex = Regex::new(r"@(\w)").unwrap();
+        static ref USER_LINK_RE: Regex = ci_regex!(r"tg://user").unwrap();
+        static ref INVITE_RE: Regex = ci_regex!(r"(t(?:elegram)?\.me)/(\+|joinchat/)").unwrap();
+    }
+
+    let mut content = content;
+
+    // Telegram has no mentions of everyone, they're only sanitized with the other mentions
+    if sanitize.contains(Sanitize::MENTIONS) {
+        content = MENTION_RE.replace_all(&content, "@\u{200B}$1").into_owned();
+        content = USER_LINK_RE
+            .replace_all(&content, "tg:\u{200B}//user")
+            .into_owned();
+    }
+
+    if sanitize.contains(Sanitize::INVITES) {
+        content = INVITE_RE
+            .replace_all(&content, "$1/\u{200B}$2")
+            .into_owned();
+    }
+
+    content
+}
+
+/// Split content into text and links, Telegram sends all other markup as separate entities
+pub fn parse_content(content: &str) -> Vec<ContentSegment> {
+    lazy_static::lazy_static! {
+        static ref LINK_RE: Regex = Regex::new(r"https?://[^\s<>]+").unwrap();
+    }
+
+    let mut segments = Vec::new();
+    let mut last = 0;
+
+    for link in LINK_RE.find_iter(content) {
+        if link.start() > last {
+            segments.push(ContentSegment::Text(content[last..link.start()].into()));
+        }
+
+        segments.push(ContentSegment::Link(link.as_str().into()));
+        last = link.end();
+    }
+
+    if last < content.len() {
+        segments.push(ContentSegment::Text(content[last..].into()));
+    }
+
+    segments
+}
+
+fn push_line(text: &mut Vec<RichText>, node: RichText) {
+    if !text.is_empty() {
+        text.push(RichText::Text("\n".into()));
+    }
+
+    text.push(node);
+}
+
+/// Embeds are written out as formatted text, with the image as a link below it
+pub fn render_embed(embed: MessageEmbed) -> String {
+    let mut text = Vec::new();
+
+    if let Some(author_name) = embed.author_name {
+        let author = RichText::Italic(vec![RichText::Text(author_name)]);
+
+        match embed.author_url {
+            Some(url) => push_line(
+                &mut text,
+                RichText::Link {
+                    text: vec![author],
+                    url,
+                },
+            ),
+            None => push_line(&mut text, author),
+        }
+    }
+
+    if let Some(title) = embed.title {
+        push_line(&mut text, RichText::Bold(vec![RichText::Text(title)]));
+    }
+
+    if let Some(description) = embed.description {
+        push_line(&mut text, RichText::Text(description));
+    }
+
+    for (name, value, _) in embed.fields {
+        push_line(&mut text, RichText::Bold(vec![RichText::Text(name)]));
+        push_line(&mut text, RichText::Text(value));
+    }
+
+    if let Some(footer) = embed.footer_text {
+        push_line(&mut text, RichText::Italic(vec![RichText::Text(footer)]));
+    }
+
+    if let Some(url) = embed.image.or(embed.thumbnail) {
+        push_line(
+            &mut text,
+            RichText::Link {
+                text: vec![RichText::Text(url.clone())],
+                url,
+            },
+        );
+    }
+
+    render(&text, TextDialect::TelegramHtml)
+}
+
+pub struct TelegramMessage {
+    author: Arc<TelegramUser>,
+    msg: api::Message,
+    service: Arc<TelegramService>,
+}
+
+impl TelegramMessage {
+    pub fn new(
+        msg: api::Message,
+        author: api::User,
+        service: Arc<TelegramService>,
+    ) -> TelegramMessage {
+        TelegramMessage {
+            author: Arc::new(TelegramUser::new(author, service.clone())),
+            msg,
+            service,
+        }
+    }
+
+    pub fn inner(&self) -> &api::Message {
+        &self.msg
+    }
+
+    fn message_id(&self) -> TelegramMessageId {
+        TelegramMessageId {
+            chat_id: self.msg.chat.id,
+            message_id: self.msg.message_id,
+        }
+    }
+}
+
+#[async_trait]
+impl Message<TelegramService> for TelegramMessage {
+    fn author(&self) -> &Arc<TelegramUser> {
+        &self.author
+    }
+
+    fn content(&self) -> &str {
+        self.msg
+            .text
+            .as_deref()
+            .or_else(|| self.msg.caption.as_deref())
+            .unwrap_or_default()
+    }
+
+    async fn channel(&self) -> Result<Arc<TelegramChannel>> {
+        Ok(Arc::new(TelegramChannel::new(
+            self.msg.chat.clone(),
+            self.service.clone(),
+        )))
+    }
+
+    async fn edit<'a, C>(&self, content: C, settings: MessageSettings) -> Result<()>
+    where
+        C: ToMessageContent<'a>,
+    {
+        let mut content = match content.to_message_content() {
+            MessageContent::String(text) => text,
+            MessageContent::Str(text) => text.to_string(),
+        };
+        content = sanitize_content(content, settings.sanitize);
+
+        if let Some(embed) = settings.embed {
+            if !content.is_empty() {
+                content.push_str("\n\n");
+            }
+
+            content.push_str(&render_embed(embed));
+        }
+
+        let mut params = json!({
+            "chat_id": self.msg.chat.id,
+            "message_id": self.msg.message_id,
+            "text": content,
+            "parse_mode": "HTML",
+        });
+
+        let res = self
+            .service
+            .api
+            .call::<serde_json::Value>("editMessageText", params.clone())
+            .await;
+
+        match res {
+            Err(err) if api::is_parse_error(&err) => {
+                params.as_object_mut().unwrap().remove("parse_mode");
+                self.service
+                    .api
+                    .call::<serde_json::Value>("editMessageText", params)
+                    .await?;
+            }
+            res => {
+                res?;
+            }
+        }
+
+        Ok(())
+    }
+
+    async fn delete(&self) -> Result<()> {
+        self.service
+            .api
+            .call::<bool>(
+                "deleteMessage",
+                json!({
+                    "chat_id": self.msg.chat.id,
+                    "message_id": self.msg.message_id,
+                }),
+            )
+            .await?;
+
+        self.service.forget_message(self.message_id());
+
+        Ok(())
+    }
+
+    fn attachments(&self) -> &[Arc<Attachment>] {
+        NO_ATTACHMENTS
+    }
+
+    fn reply_to(&self) -> Option<MessageId> {
+        self.msg.reply_to_message.as_ref().map(|reply| {
+            MessageId::Telegram(TelegramMessageId {
+                chat_id: reply.chat.id,
+                message_id: reply.message_id,
+            })
+        })
+    }
+
+    fn link(&self) -> Option<String> {
+        let chat = &self.msg.chat;
+
+        if chat.is_private() {
+            return None;
+        }
+
+        if let Some(username) = &chat.username {
+            return Some(format!("https://t.me/{}/{}", username, self.msg.message_id));
+        }
+
+        // Links to private supergroups use the chat id without its -100 prefix
+        chat.id
+            .to_string()
+            .strip_prefix("-100")
+            .map(|id| format!("https://t.me/c/{}/{}", id, self.msg.message_id))
+    }
+
+    fn service(&self) -> &Arc<TelegramService> {
+        &self.service
+    }
+
+    fn id(&self) -> MessageId {
+        MessageId::Telegram(self.message_id())
+    }
+}
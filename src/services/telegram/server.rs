@@ -0,0 +1,143 @@
+use anyhow::Result;
+use serde_json::json;
+use std::sync::Arc;
+
+use super::{api, TelegramError, TelegramService};
+use crate::services::{
+    ChannelId, ModerationAction, ScheduledEvent, ScheduledEventSettings, Server, ServerId,
+    ServerRole, StickerSettings, UploadImage, UserId,
+};
+
+/// Group chats are both the server and its only channel
+pub struct TelegramServer {
+    chat: api::Chat,
+    name: String,
+    service: Arc<TelegramService>,
+}
+
+impl TelegramServer {
+    pub fn new(chat: api::Chat, service: Arc<TelegramService>) -> TelegramServer {
+        TelegramServer {
+            name: chat.name(),
+            chat,
+            service,
+        }
+    }
+
+    async fn restrict(&self, user: i64, allowed: bool, until: Option<i64>) -> Result<()> {
+        let mut params = json!({
+            "chat_id": self.chat.id,
+            "user_id": user,
+            "permissions": {
+                "can_send_messages": allowed,
+                "can_send_audios": allowed,
+                "can_send_documents": allowed,
+                "can_send_photos": allowed,
+                "can_send_videos": allowed,
+                "can_send_video_notes": allowed,
+                "can_send_voice_notes": allowed,
+                "can_send_polls": allowed,
+                "can_send_other_messages": allowed,
+                "can_add_web_page_previews": allowed,
+            },
+        });
+
+        if let Some(until) = until {
+            params["until_date"] = json!(until);
+        }
+
+        self.service
+            .api
+            .call::<bool>("restrictChatMember", params)
+            .await?;
+
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl Server<TelegramService> for TelegramServer {
+    fn id(&self) -> ServerId {
+        ServerId::Telegram(self.chat.id)
+    }
+
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn service(&self) -> &Arc<TelegramService> {
+        &self.service
+    }
+
+    async fn voice_user_channel(&self, _user: i64) -> Result<Option<ChannelId>> {
+        Ok(None)
+    }
+
+    async fn voice_channel_users(&self, _channel_id: i64) -> Result<Vec<UserId>> {
+        Ok(vec![])
+    }
+
+    async fn scheduled_events(&self) -> Result<Vec<ScheduledEvent>> {
+        Err(TelegramError::Unsupported("scheduled events").into())
+    }
+
+    async fn create_scheduled_event(
+        &self,
+        _event: ScheduledEventSettings,
+    ) -> Result<ScheduledEvent> {
+        Err(TelegramError::Unsupported("scheduled events").into())
+    }
+
+    async fn create_emoji(&self, _name: String, _image: UploadImage) -> Result<String> {
+        Err(TelegramError::Unsupported("custom emoji").into())
+    }
+
+    async fn create_sticker(&self, _sticker: StickerSettings) -> Result<String> {
+        Err(TelegramError::Unsupported("server stickers").into())
+    }
+
+    async fn roles(&self) -> Result<Vec<ServerRole>> {
+        Err(TelegramError::Unsupported("roles").into())
+    }
+
+    async fn set_member_role(&self, _user: i64, _role_id: &str, _has_role: bool) -> Result<()> {
+        Err(TelegramError::Unsupported("roles").into())
+    }
+
+    async fn moderate(&self, user: i64, action: ModerationAction) -> Result<()> {
+        if self.chat.is_private() {
+            return Err(TelegramError::Unsupported("moderation in private chats").into());
+        }
+
+        let api = &self.service.api;
+        let member = json!({
+            "chat_id": self.chat.id,
+            "user_id": user,
+        });
+
+        // Reasons aren't recorded anywhere on Telegram
+        match action {
+            ModerationAction::Kick { .. } => {
+                api.call::<bool>("banChatMember", member.clone()).await?;
+                // Unbanning right away leaves the user free to join again
+                let mut params = member;
+                params["only_if_banned"] = json!(true);
+                api.call::<bool>("unbanChatMember", params).await?;
+            }
+            ModerationAction::Ban { .. } => {
+                api.call::<bool>("banChatMember", member).await?;
+            }
+            ModerationAction::Unban => {
+                let mut params = member;
+                params["only_if_banned"] = json!(true);
+                api.call::<bool>("unbanChatMember", params).await?;
+            }
+            ModerationAction::Timeout { until: Some(until) } => {
+                self.restrict(user, false, Some(until)).await?
+            }
+            ModerationAction::Timeout { until: None } => self.restrict(user, true, None).await?,
+        }
+
+        Ok(())
+    }
+}
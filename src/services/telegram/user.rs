@@ -0,0 +1,73 @@
+use std::sync::Arc;
+
+use super::{api, message::escape_html, TelegramService};
+use crate::services::{User, UserId};
+
+pub struct TelegramUser {
+    user: api::User,
+    service: Arc<TelegramService>,
+    name: String,
+    nick: String,
+    // Users looked up by chat only are missing whether they are a bot
+    bot: Option<bool>,
+}
+
+impl TelegramUser {
+    pub fn new(user: api::User, service: Arc<TelegramService>) -> TelegramUser {
+        TelegramUser {
+            name: match &user.username {
+                Some(username) => format!("@{}", username),
+                None => user.full_name(),
+            },
+            nick: user.full_name(),
+            bot: Some(user.is_bot),
+            user,
+            service,
+        }
+    }
+
+    pub fn from_chat(chat: api::Chat, service: Arc<TelegramService>) -> TelegramUser {
+        let user = api::User {
+            id: chat.id,
+            is_bot: false,
+            first_name: chat.first_name.unwrap_or_default(),
+            last_name: chat.last_name,
+            username: chat.username,
+        };
+
+        TelegramUser {
+            bot: None,
+            ..TelegramUser::new(user, service)
+        }
+    }
+}
+
+impl User<TelegramService> for TelegramUser {
+    fn id(&self) -> UserId {
+        UserId::Telegram(self.user.id)
+    }
+
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn nick(&self) -> &str {
+        &self.nick
+    }
+
+    fn mention(&self) -> String {
+        format!(
+            "<a href=\"tg://user?id={}\">{}</a>",
+            self.user.id,
+            escape_html(&self.nick)
+        )
+    }
+
+    fn bot(&self) -> Option<bool> {
+        self.bot
+    }
+
+    fn service(&self) -> &Arc<TelegramService> {
+        &self.service
+    }
+}
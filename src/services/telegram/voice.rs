@@ -0,0 +1,51 @@
+use anyhow::Result;
+use std::time::Duration;
+
+use super::TelegramService;
+use crate::services::{ChannelId, ServerId, VoiceConnection};
+
+/// Bots can't join voice chats on Telegram, there are no connections to hold
+pub enum TelegramVoiceConnection {}
+
+#[async_trait]
+impl VoiceConnection<TelegramService> for TelegramVoiceConnection {
+    fn channel_id(&self) -> ChannelId {
+        match *self {}
+    }
+
+    fn server_id(&self) -> ServerId {
+        match *self {}
+    }
+
+    async fn position(&self) -> Option<Duration> {
+        match *self {}
+    }
+
+    async fn length(&self) -> Option<Duration> {
+        match *self {}
+    }
+
+    async fn playing(&self) -> bool {
+        match *self {}
+    }
+
+    async fn connected(&self) -> bool {
+        match *self {}
+    }
+
+    async fn disconnect(&self) -> Result<()> {
+        match *self {}
+    }
+
+    async fn set_volume(&self, _volume: f32) {
+        match *self {}
+    }
+
+    async fn play(&self, _url: &str, _seek: Option<Duration>) -> Result<()> {
+        match *self {}
+    }
+
+    async fn stop(&self) -> Result<()> {
+        match *self {}
+    }
+}
@@ -1,14 +1,24 @@
 use anyhow::Result;
 use serde::{Deserialize, Serialize};
-use std::{marker::PhantomData, str::FromStr, sync::Arc};
+use std::{fmt::Write, marker::PhantomData, path::Path, str::FromStr, sync::Arc, time::Duration};
 use thiserror::Error;
 
 use crate::{
-    bot::Bot,
-    modules::Module,
-    services::{ChannelId, ServerId},
+    bot::{
+        db::{SettingExpiry, User as DbUser},
+        permissions::{has_role_or_higher, Caller},
+        Bot, ROLES,
+    },
+    config::Config,
+    message::{MessagePriority, MessageSettings},
+    modules::{Module, Modules},
+    services::{ChannelId, ServerId, UserId},
+    utils::escape_untrusted_text,
+    vault::Vault,
 };
 
+const EXPIRY_INTERVAL: Duration = Duration::from_secs(60);
+
 macro_rules! settings {
     ($sname:ident, $module:ident, { $($name:ident: $type:ty => ($default:expr, $flags:expr, $help:expr, [ $($setting_ident:ident => $setting_value:expr)* ])),* }) => {
         pub struct $sname {
@@ -18,6 +28,7 @@ macro_rules! settings {
         }
 
         impl $sname {
+            #[allow(unused_variables)]
             pub fn create(bot: Arc<Bot>) -> Result<Arc<$sname>> {
                 $(
                     #[allow(unused, non_camel_case_types)]
@@ -45,10 +56,11 @@ macro_rules! settings {
                 ]
             }
 
-            async fn set_setting(&self, ctx: $crate::settings::SettingContext, setting: &str, value: &str) -> Result<()> {
+            #[allow(unused_variables)]
+            async fn set_setting(&self, ctx: $crate::settings::SettingContext, caller: &$crate::bot::permissions::Caller, setting: &str, value: &str) -> Result<String> {
                 match setting {
                     $(
-                        stringify!($name) => self.$name.set_value(ctx, value).await,
+                        stringify!($name) => self.$name.set_value(ctx, caller, value).await,
                     )*
                     _ => Err(anyhow::anyhow!("unknown setting"))
                 }
@@ -109,6 +121,10 @@ where
         SettingInfo {
             name: self.name.clone(),
             help: self.help.clone(),
+            kind: T::KIND,
+            default: T::describe(&self.default),
+            constraints: T::describe_parameters(&self.parameters),
+            server_override: self.flags.contains(SettingFlags::SERVER_OVERRIDE),
         }
     }
 
@@ -136,6 +152,14 @@ where
         }
     }
 
+    /// Value for events that don't happen in a channel, like members joining the server
+    pub async fn server_value(&self, server_id: ServerId) -> Result<T> {
+        Ok(self
+            .get_server_value(server_id)
+            .await?
+            .unwrap_or_else(|| self.default.clone()))
+    }
+
     async fn get_channel_value(&self, channel_id: ChannelId) -> Result<Option<T>> {
         let raw_value = match self
             .bot
@@ -166,26 +190,45 @@ where
         Ok(T::set_value(&raw_value, &self.parameters).ok())
     }
 
-    pub async fn set_value(&self, ctx: SettingContext, input: &str) -> Result<()> {
+    /// Permission node of the setting, like "setting.lua.prefix"
+    pub fn permission_node(&self) -> String {
+        format!("setting.{}.{}", M::ID, self.name)
+    }
+
+    /// Store a new value if the caller may change the setting, returning the raw value as it was
+    /// saved
+    pub async fn set_value(
+        &self,
+        ctx: SettingContext,
+        caller: &Caller,
+        input: &str,
+    ) -> Result<String> {
+        self.bot
+            .permissions()
+            .check_permission(&self.bot, caller, &self.permission_node())
+            .await?;
+
+        let input = T::encode(input, &self.parameters, &self.bot)?;
+
         // Ensure the value is valid
-        let _value = T::set_value(input, &self.parameters)?;
+        let _value = T::set_value(&input, &self.parameters)?;
 
         match ctx {
             SettingContext::Channel(channel_id) => {
                 self.bot
                     .db()
-                    .save_channel_setting(channel_id, &format!("{}/{}", M::ID, self.name), input)
+                    .save_channel_setting(channel_id, &format!("{}/{}", M::ID, self.name), &input)
                     .await?;
             }
             SettingContext::Server(server_id) => {
                 self.bot
                     .db()
-                    .save_server_setting(server_id, &format!("{}/{}", M::ID, self.name), input)
+                    .save_server_setting(server_id, &format!("{}/{}", M::ID, self.name), &input)
                     .await?;
             }
         };
 
-        Ok(())
+        Ok(input)
     }
 }
 
@@ -193,21 +236,109 @@ where
 pub struct SettingInfo {
     pub name: String,
     pub help: String,
+    pub kind: &'static str,
+    pub default: String,
+    pub constraints: Option<String>,
+    /// Server values take precedence over channel values
+    pub server_override: bool,
+}
+
+/// Markdown reference of the settings of every module
+pub fn render_reference(modules: &[(&str, Vec<SettingInfo>)]) -> String {
+    let mut out = String::from("# Settings\n\n");
+    out.push_str("Set with `settings set MODULE SETTING VALUE --server` or `--channel`. ");
+    out.push_str("Channel values take precedence over server values, unless noted otherwise.\n");
+
+    for (module, settings) in modules {
+        if settings.is_empty() {
+            continue;
+        }
+
+        write!(out, "\n## {}\n\n", module).unwrap();
+        out.push_str("| Setting | Type | Default | Description |\n");
+        out.push_str("| --- | --- | --- | --- |\n");
+
+        for info in settings {
+            let mut description = info.help.clone();
+
+            if let Some(constraints) = &info.constraints {
+                write!(description, ", {}", constraints).unwrap();
+            }
+
+            if info.server_override {
+                description.push_str(", server values take precedence");
+            }
+
+            writeln!(
+                out,
+                "| `{}` | {} | `{}` | {} |",
+                info.name,
+                info.kind,
+                info.default,
+                description.replace('|', "\\|")
+            )
+            .unwrap();
+        }
+    }
+
+    out
+}
+
+/// `kaito settings-docs [OUTPUT]`, writes the reference to OUTPUT or prints it
+pub async fn run_cli(
+    data_path: &Path,
+    share_path: &Path,
+    config: &Config,
+    args: &[String],
+) -> Result<()> {
+    let bot = Bot::init(data_path.to_path_buf(), share_path.to_path_buf(), config).await?;
+    let reference = render_reference(&Modules::settings_reference(bot)?);
+
+    match args.first() {
+        Some(path) => {
+            tokio::fs::write(path, reference).await?;
+            println!("Wrote the settings reference to {}", path);
+        }
+        None => print!("{}", reference),
+    }
+
+    Ok(())
 }
 
 #[async_trait]
 pub trait Settings: Send + Sync {
     fn enumerate(&self) -> Vec<SettingInfo>;
-    async fn set_setting(&self, ctx: SettingContext, setting: &str, value: &str) -> Result<()>;
+    async fn set_setting(
+        &self,
+        ctx: SettingContext,
+        caller: &Caller,
+        setting: &str,
+        value: &str,
+    ) -> Result<String>;
 }
 
 pub trait SettingValue: Clone + Sized + Deserialize<'static> + Serialize {
     type Parameters;
 
+    /// Type name shown in the settings reference
+    const KIND: &'static str;
+
     // Let the value type check that the default value is valid based on the paramters
     fn is_valid(value: &Self, parameters: &Self::Parameters) -> Result<()>;
     // Set
     fn set_value(input: &str, parameters: &Self::Parameters) -> Result<Self>;
+    // Turn user input into the raw value stored in the database
+    fn encode(input: &str, _parameters: &Self::Parameters, _bot: &Bot) -> Result<String> {
+        Ok(input.into())
+    }
+    // Show a value in the settings reference
+    fn describe(value: &Self) -> String {
+        serde_json::to_string(value).unwrap_or_default()
+    }
+    // Explain the limits set by the parameters, if any
+    fn describe_parameters(_parameters: &Self::Parameters) -> Option<String> {
+        None
+    }
 }
 
 // Setting value - bool
@@ -215,6 +346,8 @@ pub trait SettingValue: Clone + Sized + Deserialize<'static> + Serialize {
 impl SettingValue for bool {
     type Parameters = SettingBoolParameters;
 
+    const KIND: &'static str = "bool";
+
     fn is_valid(_value: &bool, _parameters: &SettingBoolParameters) -> Result<()> {
         Ok(())
     }
@@ -248,6 +381,8 @@ pub struct SettingBoolParameters {}
 impl SettingValue for String {
     type Parameters = SettingStringParameters;
 
+    const KIND: &'static str = "string";
+
     fn is_valid(value: &String, parameters: &SettingStringParameters) -> Result<()> {
         if let Some(max_len) = parameters.max_len {
             let len = value.len();
@@ -268,6 +403,12 @@ impl SettingValue for String {
 
         Ok(input.into())
     }
+
+    fn describe_parameters(parameters: &SettingStringParameters) -> Option<String> {
+        parameters
+            .max_len
+            .map(|max_len| format!("at most {} bytes", max_len))
+    }
 }
 
 #[derive(Default)]
@@ -275,14 +416,834 @@ pub struct SettingStringParameters {
     pub max_len: Option<usize>,
 }
 
+// Setting value - i64
+
+impl SettingValue for i64 {
+    type Parameters = SettingIntegerParameters;
+
+    const KIND: &'static str = "integer";
+
+    fn is_valid(value: &i64, parameters: &SettingIntegerParameters) -> Result<()> {
+        let min = parameters.min.unwrap_or(i64::MIN);
+        let max = parameters.max.unwrap_or(i64::MAX);
+
+        if *value < min || *value > max {
+            return Err(SettingError::OutOfRange {
+                min,
+                max,
+                value: *value,
+            }
+            .into());
+        }
+
+        Ok(())
+    }
+
+    fn set_value(input: &str, parameters: &SettingIntegerParameters) -> Result<i64> {
+        let value = i64::from_str(input.trim()).map_err(|_| SettingError::UnexpectedInput {
+            expected: SettingType::Integer,
+            input: input.into(),
+        })?;
+
+        <i64 as SettingValue>::is_valid(&value, parameters)?;
+
+        Ok(value)
+    }
+
+    fn describe_parameters(parameters: &SettingIntegerParameters) -> Option<String> {
+        match (parameters.min, parameters.max) {
+            (Some(min), Some(max)) => Some(format!("between {} and {}", min, max)),
+            (Some(min), None) => Some(format!("at least {}", min)),
+            (None, Some(max)) => Some(format!("at most {}", max)),
+            (None, None) => None,
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct SettingIntegerParameters {
+    pub min: Option<i64>,
+    pub max: Option<i64>,
+}
+
+// Setting value - f64
+
+impl SettingValue for f64 {
+    type Parameters = SettingFloatParameters;
+
+    const KIND: &'static str = "float";
+
+    fn is_valid(value: &f64, parameters: &SettingFloatParameters) -> Result<()> {
+        let min = parameters.min.unwrap_or(f64::MIN);
+        let max = parameters.max.unwrap_or(f64::MAX);
+
+        if value.is_nan() || *value < min || *value > max {
+            return Err(SettingError::FloatOutOfRange {
+                min,
+                max,
+                value: *value,
+            }
+            .into());
+        }
+
+        Ok(())
+    }
+
+    fn set_value(input: &str, parameters: &SettingFloatParameters) -> Result<f64> {
+        let value = f64::from_str(input.trim())
+            .ok()
+            .filter(|value| value.is_finite())
+            .ok_or_else(|| SettingError::UnexpectedInput {
+                expected: SettingType::Float,
+                input: input.into(),
+            })?;
+
+        <f64 as SettingValue>::is_valid(&value, parameters)?;
+
+        Ok(value)
+    }
+
+    fn describe_parameters(parameters: &SettingFloatParameters) -> Option<String> {
+        match (parameters.min, parameters.max) {
+            (Some(min), Some(max)) => Some(format!("between {} and {}", min, max)),
+            (Some(min), None) => Some(format!("at least {}", min)),
+            (None, Some(max)) => Some(format!("at most {}", max)),
+            (None, None) => None,
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct SettingFloatParameters {
+    pub min: Option<f64>,
+    pub max: Option<f64>,
+}
+
+// Setting value - Choice
+
+/// One of a fixed set of names, like "low" or "high"
+#[derive(Clone, Default, Deserialize, Serialize, PartialEq)]
+pub struct Choice(String);
+
+impl Choice {
+    pub fn new(choice: &str) -> Choice {
+        Choice(choice.into())
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl SettingValue for Choice {
+    type Parameters = SettingChoiceParameters;
+
+    const KIND: &'static str = "choice";
+
+    fn is_valid(value: &Choice, parameters: &SettingChoiceParameters) -> Result<()> {
+        match parameters.choices {
+            Some(choices) if !choices.contains(&value.as_str()) => {
+                Err(SettingError::InvalidChoice {
+                    input: value.0.clone(),
+                    choices: choices.join(", "),
+                }
+                .into())
+            }
+            _ => Ok(()),
+        }
+    }
+
+    fn set_value(input: &str, parameters: &SettingChoiceParameters) -> Result<Choice> {
+        let input = input.trim();
+
+        // Stored as written in the choices, whatever the case of the input
+        let value = match parameters.choices {
+            Some(choices) => choices
+                .iter()
+                .find(|choice| choice.eq_ignore_ascii_case(input))
+                .map(|choice| Choice::new(choice))
+                .unwrap_or_else(|| Choice::new(input)),
+            None => Choice::new(input),
+        };
+
+        <Choice as SettingValue>::is_valid(&value, parameters)?;
+
+        Ok(value)
+    }
+
+    fn encode(input: &str, parameters: &SettingChoiceParameters, _bot: &Bot) -> Result<String> {
+        Ok(<Choice as SettingValue>::set_value(input, parameters)?.0)
+    }
+
+    fn describe(value: &Choice) -> String {
+        value.0.clone()
+    }
+
+    fn describe_parameters(parameters: &SettingChoiceParameters) -> Option<String> {
+        parameters
+            .choices
+            .map(|choices| format!("one of {}", choices.join(", ")))
+    }
+}
+
+#[derive(Default)]
+pub struct SettingChoiceParameters {
+    pub choices: Option<&'static [&'static str]>,
+}
+
+// Setting value - Duration
+
+const DURATION_UNITS: &[(char, u64)] = &[
+    ('w', 7 * 24 * 60 * 60),
+    ('d', 24 * 60 * 60),
+    ('h', 60 * 60),
+    ('m', 60),
+    ('s', 1),
+];
+
+/// Parse a duration like "90", "45s" or "1h30m", plain numbers are seconds
+fn parse_duration(input: &str) -> Option<Duration> {
+    let input = input.trim();
+
+    if input.is_empty() {
+        return None;
+    }
+
+    if let Ok(secs) = u64::from_str(input) {
+        return Some(Duration::from_secs(secs));
+    }
+
+    let mut total: u64 = 0;
+    let mut number = String::new();
+
+    for c in input.chars() {
+        if c.is_ascii_digit() {
+            number.push(c);
+            continue;
+        }
+
+        if c.is_whitespace() && number.is_empty() {
+            continue;
+        }
+
+        let (_, unit) = DURATION_UNITS.iter().find(|(name, _)| *name == c)?;
+        let value = u64::from_str(&number).ok()?;
+        total = total.checked_add(value.checked_mul(*unit)?)?;
+        number.clear();
+    }
+
+    if !number.is_empty() {
+        return None;
+    }
+
+    Some(Duration::from_secs(total))
+}
+
+fn format_duration(duration: Duration) -> String {
+    let mut secs = duration.as_secs();
+
+    if secs == 0 {
+        return "0s".into();
+    }
+
+    let mut out = String::new();
+
+    for (name, unit) in DURATION_UNITS {
+        if secs >= *unit {
+            write!(out, "{}{}", secs / unit, name).unwrap();
+            secs %= unit;
+        }
+    }
+
+    out
+}
+
+impl SettingValue for Duration {
+    type Parameters = SettingDurationParameters;
+
+    const KIND: &'static str = "duration";
+
+    fn is_valid(value: &Duration, parameters: &SettingDurationParameters) -> Result<()> {
+        let too_short = parameters.min.map_or(false, |min| *value < min);
+        let too_long = parameters.max.map_or(false, |max| *value > max);
+
+        if too_short || too_long {
+            return Err(SettingError::DurationOutOfRange {
+                value: format_duration(*value),
+                bounds: <Duration as SettingValue>::describe_parameters(parameters)
+                    .unwrap_or_default(),
+            }
+            .into());
+        }
+
+        Ok(())
+    }
+
+    fn set_value(input: &str, parameters: &SettingDurationParameters) -> Result<Duration> {
+        let value = parse_duration(input).ok_or_else(|| SettingError::UnexpectedInput {
+            expected: SettingType::Duration,
+            input: input.into(),
+        })?;
+
+        <Duration as SettingValue>::is_valid(&value, parameters)?;
+
+        Ok(value)
+    }
+
+    fn encode(input: &str, parameters: &SettingDurationParameters, _bot: &Bot) -> Result<String> {
+        Ok(format_duration(<Duration as SettingValue>::set_value(
+            input, parameters,
+        )?))
+    }
+
+    fn describe(value: &Duration) -> String {
+        format_duration(*value)
+    }
+
+    fn describe_parameters(parameters: &SettingDurationParameters) -> Option<String> {
+        match (parameters.min, parameters.max) {
+            (Some(min), Some(max)) => Some(format!(
+                "between {} and {}",
+                format_duration(min),
+                format_duration(max)
+            )),
+            (Some(min), None) => Some(format!("at least {}", format_duration(min))),
+            (None, Some(max)) => Some(format!("at most {}", format_duration(max))),
+            (None, None) => None,
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct SettingDurationParameters {
+    pub min: Option<Duration>,
+    pub max: Option<Duration>,
+}
+
+// Setting value - Vec
+
+/// Comma separated values, each checked against the item parameters
+impl<T> SettingValue for Vec<T>
+where
+    T: SettingValue,
+    T::Parameters: Default,
+{
+    type Parameters = SettingListParameters<T::Parameters>;
+
+    const KIND: &'static str = "list";
+
+    fn is_valid(value: &Vec<T>, parameters: &SettingListParameters<T::Parameters>) -> Result<()> {
+        if let Some(max_len) = parameters.max_len {
+            let len = value.len();
+            if len > max_len {
+                return Err(SettingError::ExceededMaxLength {
+                    max: max_len,
+                    length: len,
+                }
+                .into());
+            }
+        }
+
+        let default_item = T::Parameters::default();
+        let item = parameters.item.as_ref().unwrap_or(&default_item);
+
+        for value in value {
+            T::is_valid(value, item)?;
+        }
+
+        Ok(())
+    }
+
+    fn set_value(input: &str, parameters: &SettingListParameters<T::Parameters>) -> Result<Vec<T>> {
+        let default_item = T::Parameters::default();
+        let item = parameters.item.as_ref().unwrap_or(&default_item);
+
+        let value = input
+            .split(',')
+            .map(|value| value.trim())
+            .filter(|value| !value.is_empty())
+            .map(|value| T::set_value(value, item))
+            .collect::<Result<Vec<_>>>()?;
+
+        <Vec<T> as SettingValue>::is_valid(&value, parameters)?;
+
+        Ok(value)
+    }
+
+    fn encode(
+        input: &str,
+        parameters: &SettingListParameters<T::Parameters>,
+        bot: &Bot,
+    ) -> Result<String> {
+        let default_item = T::Parameters::default();
+        let item = parameters.item.as_ref().unwrap_or(&default_item);
+
+        let encoded = input
+            .split(',')
+            .map(|value| value.trim())
+            .filter(|value| !value.is_empty())
+            .map(|value| T::encode(value, item, bot))
+            .collect::<Result<Vec<_>>>()?
+            .join(",");
+
+        // Ensure the whole list is valid, like its length
+        <Vec<T> as SettingValue>::set_value(&encoded, parameters)?;
+
+        Ok(encoded)
+    }
+
+    fn describe(value: &Vec<T>) -> String {
+        value.iter().map(T::describe).collect::<Vec<_>>().join(",")
+    }
+
+    fn describe_parameters(parameters: &SettingListParameters<T::Parameters>) -> Option<String> {
+        let item = parameters
+            .item
+            .as_ref()
+            .and_then(T::describe_parameters)
+            .map(|item| format!("each {}", item));
+        let max_len = parameters
+            .max_len
+            .map(|max_len| format!("at most {} items", max_len));
+
+        match (max_len, item) {
+            (Some(max_len), Some(item)) => Some(format!("{}, {}", max_len, item)),
+            (max_len, item) => max_len.or(item),
+        }
+    }
+}
+
+pub struct SettingListParameters<P> {
+    pub max_len: Option<usize>,
+    /// Parameters every item is checked against
+    pub item: Option<P>,
+}
+
+// Not derived, that would require the item parameters to be Default
+impl<P> Default for SettingListParameters<P> {
+    fn default() -> Self {
+        SettingListParameters {
+            max_len: None,
+            item: None,
+        }
+    }
+}
+
+// Setting value - Secret
+
+/// A value encrypted by the vault, only decrypted when revealed
+#[derive(Clone, Default, Deserialize, Serialize)]
+pub struct Secret(String);
+
+impl Secret {
+    pub fn reveal(&self, bot: &Bot) -> Result<Option<String>> {
+        if self.0.is_empty() {
+            return Ok(None);
+        }
+
+        Ok(Some(bot.vault().decrypt(&self.0)?))
+    }
+}
+
+impl SettingValue for Secret {
+    type Parameters = SettingSecretParameters;
+
+    const KIND: &'static str = "secret";
+
+    fn is_valid(value: &Secret, _parameters: &SettingSecretParameters) -> Result<()> {
+        if !value.0.is_empty() && !Vault::is_encrypted(&value.0) {
+            return Err(SettingError::UnexpectedInput {
+                expected: SettingType::Secret,
+                input: "<redacted>".into(),
+            }
+            .into());
+        }
+
+        Ok(())
+    }
+
+    fn set_value(input: &str, parameters: &SettingSecretParameters) -> Result<Secret> {
+        let value = Secret(input.into());
+        <Secret as SettingValue>::is_valid(&value, parameters)?;
+
+        Ok(value)
+    }
+
+    fn encode(input: &str, _parameters: &SettingSecretParameters, bot: &Bot) -> Result<String> {
+        bot.vault().encrypt(input)
+    }
+
+    // Secrets have no default worth showing, and set values never leave the vault
+    fn describe(_value: &Secret) -> String {
+        "unset".into()
+    }
+}
+
+#[derive(Default)]
+pub struct SettingSecretParameters {}
+
+// Setting value - UserList
+
+/// A list of service users, stored as comma separated ids like "discord:1234"
+#[derive(Clone, Default, Deserialize, Serialize)]
+pub struct UserList(Vec<String>);
+
+impl UserList {
+    pub fn parse<'a>(ids: impl IntoIterator<Item = &'a str>) -> Result<UserList> {
+        let users = ids
+            .into_iter()
+            .map(|id| {
+                UserId::from_str(id.trim())
+                    .map(|id| id.to_str())
+                    .map_err(|_| SettingError::UnexpectedInput {
+                        expected: SettingType::UserList,
+                        input: id.into(),
+                    })
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(UserList(users))
+    }
+
+    pub fn contains(&self, user_id: UserId) -> bool {
+        let id = user_id.to_str();
+        self.0.iter().any(|entry| *entry == id)
+    }
+}
+
+impl SettingValue for UserList {
+    type Parameters = SettingUserListParameters;
+
+    const KIND: &'static str = "user list";
+
+    fn is_valid(value: &UserList, parameters: &SettingUserListParameters) -> Result<()> {
+        if let Some(max_len) = parameters.max_len {
+            let len = value.0.len();
+            if len > max_len {
+                return Err(SettingError::ExceededMaxLength {
+                    max: max_len,
+                    length: len,
+                }
+                .into());
+            }
+        }
+
+        Ok(())
+    }
+
+    fn set_value(input: &str, parameters: &SettingUserListParameters) -> Result<UserList> {
+        let value = UserList::parse(
+            input
+                .split(|c: char| c == ',' || c.is_whitespace())
+                .filter(|id| !id.is_empty()),
+        )?;
+        <UserList as SettingValue>::is_valid(&value, parameters)?;
+
+        Ok(value)
+    }
+
+    fn encode(input: &str, parameters: &SettingUserListParameters, _bot: &Bot) -> Result<String> {
+        Ok(<UserList as SettingValue>::set_value(input, parameters)?
+            .0
+            .join(","))
+    }
+
+    fn describe(value: &UserList) -> String {
+        value.0.join(",")
+    }
+
+    fn describe_parameters(parameters: &SettingUserListParameters) -> Option<String> {
+        parameters
+            .max_len
+            .map(|max_len| format!("at most {} users", max_len))
+    }
+}
+
+#[derive(Default)]
+pub struct SettingUserListParameters {
+    pub max_len: Option<usize>,
+}
+
+// Setting value - PermissionGrant
+
+/// A permission node granted to a user or to a bot role and the roles above it, like
+/// "command.tags.delete=discord:1234" or "setting.lua.*=role:trusted"
+#[derive(Clone, Deserialize, Serialize)]
+pub struct PermissionGrant {
+    node: String,
+    subject: String,
+}
+
+impl PermissionGrant {
+    /// Nodes ending in ".*" match every node below them, "*" matches every node
+    pub fn matches(&self, node: &str) -> bool {
+        match self.node.strip_suffix('*') {
+            Some(prefix) => {
+                (prefix.is_empty() || prefix.ends_with('.')) && node.starts_with(prefix)
+            }
+            None => self.node == node,
+        }
+    }
+
+    pub fn applies_to(&self, user: &DbUser) -> bool {
+        match self.subject.strip_prefix("role:") {
+            Some(role) => has_role_or_higher(&user.role, role),
+            None => self.subject == user.service_user_id().to_str(),
+        }
+    }
+}
+
+impl SettingValue for PermissionGrant {
+    type Parameters = SettingPermissionGrantParameters;
+
+    const KIND: &'static str = "permission grant";
+
+    fn is_valid(
+        _value: &PermissionGrant,
+        _parameters: &SettingPermissionGrantParameters,
+    ) -> Result<()> {
+        Ok(())
+    }
+
+    fn set_value(
+        input: &str,
+        _parameters: &SettingPermissionGrantParameters,
+    ) -> Result<PermissionGrant> {
+        let invalid = || SettingError::UnexpectedInput {
+            expected: SettingType::PermissionGrant,
+            input: input.into(),
+        };
+
+        let (node, subject) = input.split_once('=').ok_or_else(invalid)?;
+        let (node, subject) = (node.trim(), subject.trim());
+
+        let valid_node = !node.is_empty()
+            && node
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '_' | '-' | '*'));
+
+        if !valid_node {
+            return Err(invalid().into());
+        }
+
+        let subject = match subject.strip_prefix("role:") {
+            Some(role) if ROLES.contains(&role) => subject.to_string(),
+            Some(_) => return Err(invalid().into()),
+            None => UserId::from_str(subject).map_err(|_| invalid())?.to_str(),
+        };
+
+        Ok(PermissionGrant {
+            node: node.into(),
+            subject,
+        })
+    }
+
+    fn encode(
+        input: &str,
+        parameters: &SettingPermissionGrantParameters,
+        _bot: &Bot,
+    ) -> Result<String> {
+        Ok(<PermissionGrant as SettingValue>::describe(
+            &<PermissionGrant as SettingValue>::set_value(input, parameters)?,
+        ))
+    }
+
+    fn describe(value: &PermissionGrant) -> String {
+        format!("{}={}", value.node, value.subject)
+    }
+}
+
+#[derive(Default)]
+pub struct SettingPermissionGrantParameters {}
+
+#[derive(Clone, Copy)]
 pub enum SettingContext {
     Channel(ChannelId),
     Server(ServerId),
 }
 
+impl SettingContext {
+    fn kind(&self) -> &'static str {
+        match self {
+            SettingContext::Channel(_) => "channel",
+            SettingContext::Server(_) => "server",
+        }
+    }
+
+    fn location(&self) -> String {
+        match self {
+            SettingContext::Channel(channel_id) => channel_id.to_short_str(),
+            SettingContext::Server(server_id) => server_id.to_short_str(),
+        }
+    }
+
+    fn parse(kind: &str, location: &str) -> Result<SettingContext> {
+        match kind {
+            "channel" => Ok(SettingContext::Channel(ChannelId::from_str(location)?)),
+            "server" => Ok(SettingContext::Server(ServerId::from_str(location)?)),
+            _ => Err(anyhow::anyhow!("unknown setting context \"{}\"", kind)),
+        }
+    }
+
+    /// Audit log scope of settings changed in the context
+    pub fn scope(&self) -> String {
+        match self {
+            SettingContext::Channel(channel_id) => format!("channel:{}", channel_id.to_str()),
+            SettingContext::Server(server_id) => format!("server:{}", server_id.to_str()),
+        }
+    }
+
+    pub async fn raw_value(&self, bot: &Bot, key: &str) -> Result<Option<String>> {
+        match self {
+            SettingContext::Channel(channel_id) => {
+                bot.db().get_channel_setting(*channel_id, key).await
+            }
+            SettingContext::Server(server_id) => bot.db().get_server_setting(*server_id, key).await,
+        }
+    }
+
+    async fn restore_raw_value(&self, bot: &Bot, key: &str, value: Option<&str>) -> Result<()> {
+        match (self, value) {
+            (SettingContext::Channel(channel_id), Some(value)) => {
+                bot.db().save_channel_setting(*channel_id, key, value).await
+            }
+            (SettingContext::Channel(channel_id), None) => {
+                bot.db().remove_channel_setting(*channel_id, key).await
+            }
+            (SettingContext::Server(server_id), Some(value)) => {
+                bot.db().save_server_setting(*server_id, key, value).await
+            }
+            (SettingContext::Server(server_id), None) => {
+                bot.db().remove_server_setting(*server_id, key).await
+            }
+        }
+    }
+}
+
+/// Revert a setting that was just changed after `duration` seconds, or make the change permanent
+/// when `duration` is `None`. `previous` is the raw value from before the change.
+pub async fn set_expiry(
+    bot: &Bot,
+    ctx: &SettingContext,
+    key: &str,
+    previous: Option<&str>,
+    duration: Option<i64>,
+    announce_channel: ChannelId,
+) -> Result<()> {
+    let duration = match duration {
+        Some(duration) => duration,
+        None => {
+            return bot
+                .db()
+                .remove_setting_expiry(ctx.kind(), &ctx.location(), key)
+                .await
+        }
+    };
+
+    bot.db()
+        .add_setting_expiry(&SettingExpiry {
+            kind: ctx.kind().into(),
+            location: ctx.location(),
+            key: key.into(),
+            previous: previous.map(|previous| previous.into()),
+            announce_channel: Some(announce_channel.to_short_str()),
+            expire_time: chrono::Utc::now().timestamp() + duration,
+        })
+        .await
+}
+
+async fn revert_expired(bot: &Bot) -> Result<()> {
+    let expired = bot
+        .db()
+        .expired_settings(chrono::Utc::now().timestamp())
+        .await?;
+
+    for expiry in expired {
+        let ctx = SettingContext::parse(&expiry.kind, &expiry.location)?;
+        let current = ctx.raw_value(bot, &expiry.key).await?;
+
+        ctx.restore_raw_value(bot, &expiry.key, expiry.previous.as_deref())
+            .await?;
+        bot.db()
+            .remove_setting_expiry(&expiry.kind, &expiry.location, &expiry.key)
+            .await?;
+        bot.db()
+            .audit(
+                None,
+                &format!("setting.{}", expiry.key),
+                &ctx.scope(),
+                current.as_deref(),
+                expiry.previous.as_deref(),
+            )
+            .await?;
+
+        let channel_id = match expiry
+            .announce_channel
+            .as_deref()
+            .and_then(|id| ChannelId::from_str(id).ok())
+        {
+            Some(channel_id) => channel_id,
+            None => continue,
+        };
+
+        let reverted_to = if expiry.previous.is_some() {
+            "its previous value"
+        } else {
+            "the default"
+        };
+
+        // The setting is reverted either way, a failed announcement is only logged
+        if let Err(err) = bot
+            .get_ctx()
+            .services()
+            .send_message(
+                channel_id,
+                escape_untrusted_text(
+                    channel_id.service_kind(),
+                    format!(
+                        "The temporary value of \"{}\" expired and was reverted to {}",
+                        expiry.key, reverted_to
+                    ),
+                ),
+                MessageSettings {
+                    priority: MessagePriority::High,
+                    ..Default::default()
+                },
+            )
+            .await
+        {
+            println!(
+                "error announcing the reverted setting {}: {}",
+                expiry.key, err
+            );
+        }
+    }
+
+    Ok(())
+}
+
+/// Revert temporary settings once they expire
+pub fn spawn_expiry_task(bot: Arc<Bot>) {
+    tokio::spawn(async move {
+        loop {
+            tokio::time::sleep(EXPIRY_INTERVAL).await;
+
+            if let Err(err) = revert_expired(&bot).await {
+                println!("error reverting temporary settings: {}", err.to_string());
+            }
+        }
+    });
+}
+
 #[derive(Debug, Copy, Clone)]
 pub enum SettingType {
     Bool,
+    Integer,
+    Float,
+    Duration,
+    Secret,
+    UserList,
+    PermissionGrant,
 }
 
 #[derive(Debug, Error)]
@@ -294,8 +1255,101 @@ pub enum SettingError {
     },
     #[error("len {} exceeded max length {}", length, max)]
     ExceededMaxLength { max: usize, length: usize },
+    #[error("{} is not within the range {}..={}", value, min, max)]
+    OutOfRange { min: i64, max: i64, value: i64 },
+    #[error("{} is not within the range {}..={}", value, min, max)]
+    FloatOutOfRange { min: f64, max: f64, value: f64 },
+    #[error("{} is not {}", value, bounds)]
+    DurationOutOfRange { bounds: String, value: String },
+    #[error("\"{}\" is not one of {}", input, choices)]
+    InvalidChoice { input: String, choices: String },
 }
 
 pub mod prelude {
-    pub use super::{Setting, SettingBoolParameters, SettingFlags, SettingValue};
+    pub use super::{
+        Choice, PermissionGrant, Secret, Setting, SettingBoolParameters, SettingChoiceParameters,
+        SettingDurationParameters, SettingFlags, SettingFloatParameters, SettingIntegerParameters,
+        SettingListParameters, SettingPermissionGrantParameters, SettingSecretParameters,
+        SettingUserListParameters, SettingValue, UserList,
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{PermissionGrant, SettingPermissionGrantParameters, SettingValue};
+    use crate::bot::db::User as DbUser;
+
+    fn grant(input: &str) -> PermissionGrant {
+        <PermissionGrant as SettingValue>::set_value(
+            input,
+            &SettingPermissionGrantParameters::default(),
+        )
+        .unwrap()
+    }
+
+    fn user(role: &str, discord_id: u64) -> DbUser {
+        DbUser {
+            uid: 1,
+            role: role.into(),
+            discord_id: Some(discord_id),
+            telegram_id: None,
+            timezone: None,
+        }
+    }
+
+    #[test]
+    fn permission_grant_matches_test() {
+        let all = grant("*=role:trusted");
+        assert!(all.matches("command.purge"));
+        assert!(all.matches("setting.lua.prefix"));
+
+        let settings = grant("setting.lua.*=role:trusted");
+        assert!(settings.matches("setting.lua.prefix"));
+        assert!(settings.matches("setting.lua.a.b"));
+        assert!(!settings.matches("setting.luax.prefix"));
+        assert!(!settings.matches("setting.lua"));
+        assert!(!settings.matches("command.purge"));
+
+        let exact = grant("command.purge=role:trusted");
+        assert!(exact.matches("command.purge"));
+        assert!(!exact.matches("command.purge.all"));
+        assert!(!exact.matches("command.purg"));
+
+        // Only whole components are wildcards
+        let partial = grant("command.pur*=role:trusted");
+        assert!(!partial.matches("command.purge"));
+    }
+
+    #[test]
+    fn permission_grant_applies_to_test() {
+        let trusted = grant("command.purge=role:trusted");
+        assert!(!trusted.applies_to(&user("guest", 1234)));
+        assert!(trusted.applies_to(&user("trusted", 1234)));
+        assert!(trusted.applies_to(&user("admin", 1234)));
+
+        let single = grant("command.purge = discord:1234");
+        assert!(single.applies_to(&user("guest", 1234)));
+        assert!(!single.applies_to(&user("guest", 4321)));
+
+        // Short service ids are stored in their long form
+        let short = grant("command.purge=d:1234");
+        assert!(short.applies_to(&user("guest", 1234)));
+    }
+
+    #[test]
+    fn permission_grant_set_value_test() {
+        let parameters = SettingPermissionGrantParameters::default();
+        let set_value =
+            |input: &str| <PermissionGrant as SettingValue>::set_value(input, &parameters).is_ok();
+
+        assert!(set_value("*=role:guest"));
+        assert!(set_value("a.*=role:root"));
+        assert!(set_value("a.b=discord:1234"));
+        assert!(!set_value("a.b"));
+        assert!(!set_value("=role:trusted"));
+        assert!(!set_value("a b=role:trusted"));
+        assert!(!set_value("a.b=role:nobody"));
+        assert!(!set_value("a.b=1234"));
+        assert!(!set_value("a.b=unknown:1234"));
+    }
 }
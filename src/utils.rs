@@ -13,6 +13,101 @@ pub fn escape_untrusted_text(service: ServiceKind, text: String) -> String {
     }
 }
 
+/// Split text into parts that fit in a message, breaking at line boundaries. Code blocks that
+/// span multiple parts are closed and reopened. None if more than `max_parts` parts are needed.
+pub fn split_message(
+    text: &str,
+    max_len: usize,
+    max_lines: usize,
+    max_parts: usize,
+) -> Option<Vec<String>> {
+    if text.chars().count() <= max_len && text.split('\n').count() <= max_lines {
+        return Some(vec![text.to_string()]);
+    }
+
+    let mut parts = Vec::new();
+    let mut part = String::new();
+    let (mut part_len, mut part_lines) = (0, 0);
+    // Opening line of the code block the current line is in
+    let mut fence: Option<String> = None;
+
+    for line in text.split('\n') {
+        // Room for reopening and closing the code block
+        let reserved = fence.as_ref().map_or(0, |fence| fence.chars().count() + 5);
+        let piece_len = max_len.saturating_sub(reserved).max(1);
+
+        for piece in split_chars(line, piece_len) {
+            let len = piece.chars().count();
+            let closing = if fence.is_some() { 4 } else { 0 };
+
+            if part_lines > 0
+                && (part_len + 1 + len + closing > max_len
+                    || part_lines + 1 + (closing > 0) as usize > max_lines)
+            {
+                if fence.is_some() {
+                    part.push_str("\n```");
+                }
+
+                parts.push(std::mem::take(&mut part));
+                if parts.len() >= max_parts {
+                    return None;
+                }
+
+                part_len = 0;
+                part_lines = 0;
+
+                if let Some(fence) = &fence {
+                    part.push_str(fence);
+                    part_len = fence.chars().count();
+                    part_lines = 1;
+                }
+            }
+
+            if part_lines > 0 {
+                part.push('\n');
+                part_len += 1;
+            }
+
+            part.push_str(piece);
+            part_len += len;
+            part_lines += 1;
+        }
+
+        if line.matches("```").count() % 2 == 1 {
+            fence = match fence {
+                Some(_) => None,
+                None => {
+                    let trimmed = line.trim();
+                    Some(if trimmed.starts_with("```") {
+                        trimmed.chars().take(16).collect()
+                    } else {
+                        "```".into()
+                    })
+                }
+            };
+        }
+    }
+
+    parts.push(part);
+
+    Some(parts)
+}
+
+// Split a line into pieces of at most max characters
+fn split_chars(line: &str, max: usize) -> Vec<&str> {
+    let mut pieces = Vec::new();
+    let mut rest = line;
+
+    while let Some((i, _)) = rest.char_indices().nth(max) {
+        pieces.push(&rest[..i]);
+        rest = &rest[i..];
+    }
+
+    pieces.push(rest);
+
+    pieces
+}
+
 /// Case-insensitive Regex
 macro_rules! ci_regex {
     ($regex:literal) => {
@@ -0,0 +1,334 @@
+use anyhow::Result;
+use chacha20poly1305::{
+    aead::{Aead, NewAead},
+    ChaCha20Poly1305, Key, Nonce,
+};
+use std::{
+    env, fs,
+    io::Write,
+    os::unix::fs::OpenOptionsExt,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+use thiserror::Error;
+
+use crate::{
+    bot::db::{BotDb, EncryptedValue, SecretEntry},
+    config::Config,
+};
+
+const KEY_LEN: usize = 32;
+const NONCE_LEN: usize = 12;
+// Prefix of values encrypted with the vault key
+const ENCRYPTED_PREFIX: &'static str = "enc:";
+// Prefix of config values that refer to a secret stored in the vault
+const REFERENCE_PREFIX: &'static str = "vault:";
+
+#[derive(Debug, Error)]
+pub enum VaultError {
+    #[error("no vault key found, set KAITO_VAULT_KEY or run `kaito vault init`")]
+    NoKey,
+    #[error("vault keys must be {} hex encoded bytes", KEY_LEN)]
+    InvalidKey,
+    #[error("unknown secret \"{0}\"")]
+    UnknownSecret(String),
+    #[error("secret \"{0}\" already exists")]
+    SecretExists(String),
+    #[error("malformed encrypted value")]
+    Malformed,
+    #[error("unable to decrypt value, the vault key might have changed")]
+    Decrypt,
+}
+
+pub fn key_path(data_path: &Path) -> PathBuf {
+    env::var("KAITO_VAULT_KEY_FILE")
+        .map(|p| PathBuf::from(p))
+        .unwrap_or_else(|_| data_path.join("vault.key"))
+}
+
+pub fn parse_key(key: &str) -> Result<ChaCha20Poly1305> {
+    let key = hex::decode(key.trim()).map_err(|_| VaultError::InvalidKey)?;
+
+    if key.len() != KEY_LEN {
+        return Err(VaultError::InvalidKey.into());
+    }
+
+    Ok(ChaCha20Poly1305::new(Key::from_slice(&key)))
+}
+
+pub fn generate_key() -> String {
+    hex::encode(rand::random::<[u8; KEY_LEN]>())
+}
+
+// The key is read from KAITO_VAULT_KEY first, then from the key file
+fn load_key(data_path: &Path) -> Result<Option<ChaCha20Poly1305>> {
+    if let Ok(key) = env::var("KAITO_VAULT_KEY") {
+        return Ok(Some(parse_key(&key)?));
+    }
+
+    let path = key_path(data_path);
+    if !path.is_file() {
+        return Ok(None);
+    }
+
+    Ok(Some(parse_key(&fs::read_to_string(path)?)?))
+}
+
+fn encrypt_with(cipher: &ChaCha20Poly1305, plaintext: &str) -> Result<String> {
+    let nonce = rand::random::<[u8; NONCE_LEN]>();
+    let ciphertext = cipher
+        .encrypt(Nonce::from_slice(&nonce), plaintext.as_bytes())
+        .map_err(|_| VaultError::Malformed)?;
+
+    Ok(format!(
+        "{}{}:{}",
+        ENCRYPTED_PREFIX,
+        hex::encode(nonce),
+        hex::encode(ciphertext)
+    ))
+}
+
+fn decrypt_with(cipher: &ChaCha20Poly1305, value: &str) -> Result<String> {
+    let (nonce, ciphertext) = value
+        .strip_prefix(ENCRYPTED_PREFIX)
+        .and_then(|value| value.split_once(':'))
+        .ok_or(VaultError::Malformed)?;
+
+    let nonce = hex::decode(nonce).map_err(|_| VaultError::Malformed)?;
+    let ciphertext = hex::decode(ciphertext).map_err(|_| VaultError::Malformed)?;
+
+    if nonce.len() != NONCE_LEN {
+        return Err(VaultError::Malformed.into());
+    }
+
+    let plaintext = cipher
+        .decrypt(Nonce::from_slice(&nonce), ciphertext.as_ref())
+        .map_err(|_| VaultError::Decrypt)?;
+
+    Ok(String::from_utf8(plaintext).map_err(|_| VaultError::Malformed)?)
+}
+
+/// Secrets encrypted at rest in the database, keyed by a key kept outside of it
+pub struct Vault {
+    db: Arc<BotDb>,
+    cipher: Option<ChaCha20Poly1305>,
+}
+
+impl Vault {
+    pub fn new(db: Arc<BotDb>, data_path: &Path) -> Result<Arc<Vault>> {
+        Ok(Arc::new(Vault {
+            db,
+            cipher: load_key(data_path)?,
+        }))
+    }
+
+    fn cipher(&self) -> Result<&ChaCha20Poly1305> {
+        Ok(self.cipher.as_ref().ok_or(VaultError::NoKey)?)
+    }
+
+    pub fn is_encrypted(value: &str) -> bool {
+        value.starts_with(ENCRYPTED_PREFIX)
+    }
+
+    pub fn encrypt(&self, plaintext: &str) -> Result<String> {
+        encrypt_with(self.cipher()?, plaintext)
+    }
+
+    pub fn decrypt(&self, value: &str) -> Result<String> {
+        decrypt_with(self.cipher()?, value)
+    }
+
+    pub async fn get(&self, name: &str) -> Result<String> {
+        match self.db.get_secret(name).await? {
+            Some(value) => self.decrypt(&value),
+            None => Err(VaultError::UnknownSecret(name.into()).into()),
+        }
+    }
+
+    /// Store a new secret, returning its version
+    pub async fn add(&self, name: &str, value: &str) -> Result<i64> {
+        if self.db.get_secret(name).await?.is_some() {
+            return Err(VaultError::SecretExists(name.into()).into());
+        }
+
+        self.db.save_secret(name, &self.encrypt(value)?).await
+    }
+
+    /// Replace the value of an existing secret, returning its new version
+    pub async fn rotate(&self, name: &str, value: &str) -> Result<i64> {
+        if self.db.get_secret(name).await?.is_none() {
+            return Err(VaultError::UnknownSecret(name.into()).into());
+        }
+
+        self.db.save_secret(name, &self.encrypt(value)?).await
+    }
+
+    pub async fn remove(&self, name: &str) -> Result<bool> {
+        self.db.delete_secret(name).await
+    }
+
+    pub async fn list(&self) -> Result<Vec<SecretEntry>> {
+        self.db.list_secrets().await
+    }
+
+    /// Re-encrypt every secret and encrypted setting with a new key, returning the amount of values
+    pub async fn rekey(&self, new_cipher: &ChaCha20Poly1305) -> Result<usize> {
+        let cipher = self.cipher()?;
+
+        let values = self
+            .db
+            .list_encrypted_values()
+            .await?
+            .into_iter()
+            .map(|value| {
+                Ok(EncryptedValue {
+                    value: encrypt_with(new_cipher, &decrypt_with(cipher, &value.value)?)?,
+                    ..value
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        self.db.replace_encrypted_values(&values).await?;
+
+        Ok(values.len())
+    }
+
+    /// Resolve a config value of the form "vault:<name>" to the secret it refers to
+    pub async fn resolve(&self, value: &str) -> Result<String> {
+        match value.strip_prefix(REFERENCE_PREFIX) {
+            Some(name) => self.get(name).await,
+            None => Ok(value.into()),
+        }
+    }
+
+    async fn resolve_in_place(&self, value: &mut String) -> Result<()> {
+        *value = self.resolve(value).await?;
+
+        Ok(())
+    }
+
+    /// Replace every secret reference in the service tokens and API keys of the config
+    pub async fn resolve_config(&self, config: &mut Config) -> Result<()> {
+        if let Some(discord) = config.services.discord.as_mut() {
+            self.resolve_in_place(&mut discord.token).await?;
+        }
+
+        if let Some(telegram) = config.services.telegram.as_mut() {
+            self.resolve_in_place(&mut telegram.token).await?;
+        }
+
+        if let Some(github) = config.github.as_mut() {
+            self.resolve_in_place(&mut github.webhook_secret).await?;
+
+            if let Some(token) = github.token.as_mut() {
+                self.resolve_in_place(token).await?;
+            }
+        }
+
+        if let Some(translate) = config.translate.as_mut() {
+            if let Some(deepl) = translate.deepl.as_mut() {
+                self.resolve_in_place(&mut deepl.api_key).await?;
+            }
+
+            if let Some(api_key) = translate
+                .libretranslate
+                .as_mut()
+                .and_then(|libretranslate| libretranslate.api_key.as_mut())
+            {
+                self.resolve_in_place(api_key).await?;
+            }
+        }
+
+        if let Some(token) = config
+            .http
+            .as_mut()
+            .and_then(|http| http.admin_token.as_mut())
+        {
+            self.resolve_in_place(token).await?;
+        }
+
+        if let Some(gif) = config.gif.as_mut() {
+            self.resolve_in_place(&mut gif.api_key).await?;
+        }
+
+        Ok(())
+    }
+}
+
+fn read_secret_value() -> Result<String> {
+    let mut value = String::new();
+    std::io::stdin().read_line(&mut value)?;
+
+    Ok(value.trim_end_matches(&['\r', '\n'][..]).into())
+}
+
+/// `kaito vault <command>`, manages secrets without starting the bot
+pub async fn run_cli(
+    data_path: &Path,
+    share_path: &Path,
+    config: &Config,
+    args: &[String],
+) -> Result<()> {
+    let command = args.first().map(String::as_str).unwrap_or_default();
+    let name = args.get(1).map(String::as_str);
+
+    if command == "init" {
+        let path = key_path(data_path);
+
+        let mut file = fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .mode(0o600)
+            .open(&path)
+            .map_err(|err| anyhow::anyhow!("unable to create {}: {}", path.display(), err))?;
+        writeln!(file, "{}", generate_key())?;
+
+        println!("Created vault key at {}", path.display());
+        return Ok(());
+    }
+
+    let db = BotDb::new(data_path, share_path, config).await?;
+    let vault = Vault::new(db, data_path)?;
+
+    match (command, name) {
+        ("add", Some(name)) => {
+            println!("Enter the value of \"{}\":", name);
+            let version = vault.add(name, &read_secret_value()?).await?;
+            println!("Added \"{}\" (version {})", name, version);
+        }
+        ("rotate", Some(name)) => {
+            println!("Enter the new value of \"{}\":", name);
+            let version = vault.rotate(name, &read_secret_value()?).await?;
+            println!("Rotated \"{}\" (version {})", name, version);
+        }
+        ("remove", Some(name)) => {
+            if vault.remove(name).await? {
+                println!("Removed \"{}\"", name);
+            } else {
+                return Err(VaultError::UnknownSecret(name.into()).into());
+            }
+        }
+        ("list", None) => {
+            for secret in vault.list().await? {
+                println!(
+                    "{} (version {}, updated {})",
+                    secret.name,
+                    secret.version,
+                    chrono::NaiveDateTime::from_timestamp(secret.update_timestamp, 0)
+                );
+            }
+        }
+        ("rekey", Some(new_key_path)) => {
+            let new_cipher = parse_key(&fs::read_to_string(new_key_path)?)?;
+            let count = vault.rekey(&new_cipher).await?;
+
+            println!(
+                "Re-encrypted {} values, replace the vault key with {} before starting the bot",
+                count, new_key_path
+            );
+        }
+        _ => println!("usage: kaito vault <init | add <name> | rotate <name> | remove <name> | list | rekey <key file>>"),
+    }
+
+    Ok(())
+}